@@ -0,0 +1,70 @@
+//! Per-request vhost resolution: precompiled `CompiledConfig` versus
+//! re-deriving vhost state from the raw configuration on every request.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use veloserve::config::{Config, VirtualHostConfig};
+use veloserve::server::{CompiledConfig, PathMatcher};
+
+fn config_with_vhosts(count: usize) -> Config {
+    let mut toml = String::new();
+    for i in 0..count {
+        toml.push_str(&format!(
+            "[[virtualhost]]\ndomain = \"site{i}.example\"\nroot = \"/srv/site{i}\"\n\n[virtualhost.cache]\nexclude = [\"/wp-admin\", \"/wp-login.php\", \"/cart\", \"/checkout\", \"/my-account\", \"/api/*\"]\n\n"
+        ));
+    }
+    Config::from_str(&toml).expect("valid benchmark config")
+}
+
+/// The uncompiled path: linear domain scan plus rule parsing per request
+fn find_uncompiled<'a>(config: &'a Config, host: &str) -> Option<&'a VirtualHostConfig> {
+    config
+        .virtualhost
+        .iter()
+        .find(|v| v.domain == host || v.domain == "*")
+}
+
+fn excluded_uncompiled(path: &str, rules: &[String]) -> bool {
+    rules.iter().any(|rule| {
+        if let Some(prefix) = rule.strip_suffix('*') {
+            path.starts_with(prefix)
+        } else {
+            path == rule || path.starts_with(&format!("{}/", rule.trim_end_matches('/')))
+        }
+    })
+}
+
+fn bench_vhost_lookup(c: &mut Criterion) {
+    let config = Arc::new(config_with_vhosts(200));
+    let compiled = CompiledConfig::compile(config.clone());
+    let host = "site150.example";
+    let path = "/blog/2024/hello-world";
+
+    c.bench_function("vhost_uncompiled", |b| {
+        b.iter(|| {
+            let vhost = find_uncompiled(&config, black_box(host)).unwrap();
+            let rules = &vhost.cache.as_ref().unwrap().exclude;
+            black_box(excluded_uncompiled(black_box(path), rules))
+        })
+    });
+
+    c.bench_function("vhost_compiled", |b| {
+        b.iter(|| {
+            let vhost = compiled.find(black_box(host)).unwrap();
+            black_box(vhost.cache_exclude.excludes(black_box(path), None))
+        })
+    });
+
+    let rules: Vec<String> = ["/wp-admin", "/wp-login.php", "/cart", "/api/*"]
+        .iter()
+        .map(|rule| rule.to_string())
+        .collect();
+    let matcher = PathMatcher::new(&rules);
+    c.bench_function("exclude_match_compiled", |b| {
+        b.iter(|| black_box(matcher.matches(black_box(path))))
+    });
+}
+
+criterion_group!(benches, bench_vhost_lookup);
+criterion_main!(benches);
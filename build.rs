@@ -52,8 +52,7 @@ fn setup_php_embed() {
     // Get additional libraries PHP depends on
     if let Some(libs) = get_php_config("--libs") {
         for lib in libs.split_whitespace() {
-            if lib.starts_with("-l") {
-                let lib_name = &lib[2..];
+            if let Some(lib_name) = lib.strip_prefix("-l") {
                 println!("cargo:rustc-link-lib={}", lib_name);
             }
         }
@@ -62,8 +61,7 @@ fn setup_php_embed() {
     // Get PHP include paths (for potential bindgen use)
     if let Some(includes) = get_php_config("--includes") {
         for inc in includes.split_whitespace() {
-            if inc.starts_with("-I") {
-                let path = &inc[2..];
+            if let Some(path) = inc.strip_prefix("-I") {
                 println!("cargo:include={}", path);
             }
         }
@@ -117,7 +115,7 @@ fn generate_php_bindings() {
     )
     .expect("Failed to write php_bindings.h");
 
-    let mut builder = bindgen::Builder::default()
+    let builder = bindgen::Builder::default()
         .header(header_path.to_string_lossy())
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
         .clang_args(includes.iter().map(|inc| format!("-I{}", inc)))
@@ -2,15 +2,40 @@
 //!
 //! Converts parsed Apache configuration to VeloServe TOML format.
 
-use std::collections::HashMap;
-
-use crate::apache_compat::{ApacheConfig, ApacheVirtualHost};
-use crate::config::{Config, VirtualHostConfig};
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::apache_compat::{ApacheConfig, ApacheDirective, ApacheVirtualHost};
+use crate::config::{Config, Listen, SymlinkPolicy, VHostCacheConfig, VirtualHostConfig};
+
+/// Header of a converted configuration file
+const TOML_HEADER: &str = "# VeloServe Configuration\n# Converted from Apache httpd.conf\n\n";
+
+/// VirtualHost directives the conversion carries over
+const CONVERTED_DIRECTIVES: &[&str] = &[
+    "ServerName",
+    "ServerAlias",
+    "DocumentRoot",
+    "DirectoryIndex",
+    "SSLEngine",
+    "SSLCertificateFile",
+    "SSLCertificateKeyFile",
+];
+
+/// VirtualHost directives with nothing to convert: VeloServe keeps its own
+/// logs
+const IGNORED_DIRECTIVES: &[&str] = &["ServerAdmin", "ErrorLog", "CustomLog", "LogLevel"];
+
+/// `[[virtualhost]]` tables without the rest of the configuration
+#[derive(Serialize)]
+struct VhostsOnly<'a> {
+    virtualhost: &'a [VirtualHostConfig],
+}
 
 /// Converts Apache configuration to VeloServe configuration
 pub struct ApacheToVeloServeConverter {
-    /// Default values for missing directives
-    defaults: HashMap<String, String>,
     /// Enable strict mode (fail on unsupported directives)
     strict: bool,
 }
@@ -18,15 +43,7 @@ pub struct ApacheToVeloServeConverter {
 impl ApacheToVeloServeConverter {
     /// Create a new converter
     pub fn new() -> Self {
-        let mut defaults = HashMap::new();
-        defaults.insert("port".to_string(), "80".to_string());
-        defaults.insert("php_memory_limit".to_string(), "256M".to_string());
-        defaults.insert("php_max_execution_time".to_string(), "30".to_string());
-
-        Self {
-            defaults,
-            strict: false,
-        }
+        Self { strict: false }
     }
 
     /// Enable strict mode
@@ -35,28 +52,58 @@ impl ApacheToVeloServeConverter {
         self
     }
 
-    /// Convert Apache configuration to VeloServe Config
+    /// Convert Apache configuration to VeloServe Config, logging what
+    /// couldn't be converted
     pub fn convert(&self, apache: &ApacheConfig) -> Config {
+        let (config, warnings) = self.convert_with_warnings(apache);
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        config
+    }
+
+    /// Convert Apache configuration to VeloServe Config, along with a
+    /// message for each VirtualHost or directive that was left out
+    pub fn convert_with_warnings(&self, apache: &ApacheConfig) -> (Config, Vec<String>) {
         let mut config = Config::default();
+        let mut warnings = Vec::new();
+        config.server.listen = Listen::One("0.0.0.0:80".to_string());
 
         for apache_vhost in &apache.virtual_hosts {
-            if let Ok(veloserve_vhost) = self.convert_vhost(apache_vhost) {
-                config.virtualhost.push(veloserve_vhost);
+            match self.convert_vhost(apache_vhost, &mut warnings) {
+                Ok(veloserve_vhost) => config.virtualhost.push(veloserve_vhost),
+                Err(e) => warnings.push(format!(
+                    "Skipping VirtualHost {}: {}",
+                    apache_vhost.server_names.join(" "),
+                    e
+                )),
             }
         }
 
         self.apply_global_php_settings(&mut config, apache);
 
-        config
+        (config, warnings)
     }
 
     /// Convert single Apache VirtualHost to VeloServe VirtualHostConfig
     fn convert_vhost(
         &self,
         apache: &ApacheVirtualHost,
+        warnings: &mut Vec<String>,
     ) -> Result<VirtualHostConfig, ConversionError> {
         let domain = apache.server_names.first().cloned().unwrap_or_default();
 
+        for directive in &apache.directives {
+            let Some(name) = unsupported_directive(directive) else {
+                continue;
+            };
+            let e = ConversionError::UnsupportedDirective(format!("{} in {}", name, domain));
+            if self.strict {
+                return Err(e);
+            }
+            warnings.push(format!("Not converted: {}", e));
+        }
+
         let root = apache
             .document_root
             .as_ref()
@@ -73,7 +120,14 @@ impl ApacheToVeloServeConverter {
             return Err(ConversionError::MissingDocumentRoot);
         }
 
-        let platform = self.detect_platform(&root);
+        let platform = detect_platform(&root);
+        let cache = platform_cache(&platform);
+
+        let index = if apache.directory_index.is_empty() {
+            vec!["index.php".to_string(), "index.html".to_string()]
+        } else {
+            apache.directory_index.clone()
+        };
 
         let ssl_certificate = apache
             .ssl
@@ -93,114 +147,143 @@ impl ApacheToVeloServeConverter {
             platform: Some(platform),
             ssl_certificate,
             ssl_certificate_key,
-            cache: None,
-            index: vec!["index.php".to_string(), "index.html".to_string()],
+            cache,
+            index,
             error_pages: std::collections::HashMap::new(),
+            request_timeout: None,
+            locations: Vec::new(),
+            rewrites: Vec::new(),
+            precompressed: None,
+            autoindex: false,
+            follow_symlinks: SymlinkPolicy::Always,
+            deny_dotfiles: true,
+            deny_files: Vec::new(),
+            try_files: Vec::new(),
+            sendfile_root: None,
+            static_cache_control: std::collections::HashMap::new(),
+            force_download: Vec::new(),
+            spa_fallback: None,
+            spa_fallback_extensions: false,
+            redirect_to_https: None,
         })
     }
 
-    /// Detect CMS/platform from document root path
-    fn detect_platform(&self, docroot: &str) -> String {
-        let path = std::path::Path::new(docroot);
-
-        if path.join("wp-config.php").exists() {
-            return "wordpress".to_string();
-        }
-        if path.join("app/etc/env.php").exists() {
-            return "magento2".to_string();
-        }
-        if path.join("artisan").exists() {
-            return "laravel".to_string();
-        }
-
-        "generic".to_string()
-    }
-
-    /// Apply global PHP settings from Apache config
-    fn apply_global_php_settings(&self, _config: &mut Config, apache: &ApacheConfig) {
-        for directive in &apache.global_directives {
-            if let crate::apache_compat::ApacheDirective::Simple { name, value } = directive {
-                if name == "php_admin_value" || name == "php_value" {
-                    // Parse "php_admin_value memory_limit 512M" format
-                    let parts: Vec<&str> = value.splitn(2, char::is_whitespace).collect();
-                    if parts.len() == 2 {
-                        match parts[0] {
-                            "memory_limit" => {
-                                // config.php.memory_limit = parts[1].to_string();
-                            }
-                            "max_execution_time" => {
-                                // config.php.max_execution_time = parts[1].parse().unwrap_or(30);
-                            }
-                            _ => {}
-                        }
+    /// Apply the global PHP settings from Apache config. VeloServe has a
+    /// single PHP pool, so those of a VirtualHost are left out, with a
+    /// warning, rather than applied to every site.
+    fn apply_global_php_settings(&self, config: &mut Config, apache: &ApacheConfig) {
+        let global = apache.global_directives.iter().filter_map(|directive| {
+            let ApacheDirective::Simple { name, value } = directive else {
+                return None;
+            };
+            if name != "php_admin_value" && name != "php_value" {
+                return None;
+            }
+            // "php_admin_value memory_limit 512M"
+            let (key, value) = value.split_once(char::is_whitespace)?;
+            Some((key, value.trim()))
+        });
+
+        for (key, value) in global {
+            match key {
+                "memory_limit" => {
+                    config.php.memory_limit = value.to_string();
+                }
+                "max_execution_time" => {
+                    // 0 (unlimited) has no equivalent; keep the default
+                    if let Some(secs) = value.parse::<u64>().ok().filter(|&secs| secs > 0) {
+                        config.php.max_execution_time = Duration::from_secs(secs);
                     }
                 }
+                _ => {}
             }
         }
     }
 
     /// Generate VeloServe TOML string from Apache config
-    pub fn to_toml(&self, apache: &ApacheConfig) -> String {
-        let config = self.convert(apache);
-
-        // In full implementation, this would serialize Config to TOML
-        // For now, return a template
-        let mut output = String::from(
-            "# VeloServe Configuration\n\
-             # Converted from Apache httpd.conf\n\
-             \n\
-             [server]\n\
-             listen = \"0.0.0.0:80\"\n\
-             workers = \"auto\"\n\
-             \n",
-        );
-
-        output.push_str(&self.vhosts_toml_fragment(&config.virtualhost));
-        output
+    pub fn to_toml(&self, apache: &ApacheConfig) -> Result<String, ConversionError> {
+        self.render(&self.convert(apache), false)
     }
 
     /// Output only [[virtualhost]] blocks for appending to an existing base config.
-    pub fn to_toml_vhosts_only(&self, apache: &ApacheConfig) -> String {
-        let config = self.convert(apache);
-        self.vhosts_toml_fragment(&config.virtualhost)
-    }
-
-    fn vhosts_toml_fragment(&self, vhosts: &[VirtualHostConfig]) -> String {
-        let mut output = String::new();
-        for vhost in vhosts {
-            output.push_str(&format!(
-                "[[virtualhost]]\n\
-                 domain = \"{}\"\n\
-                 root = \"{}\"\n\
-                 platform = \"{}\"\n",
-                vhost.domain,
-                vhost.root,
-                vhost.platform.as_deref().unwrap_or("generic"),
-            ));
-
-            if let Some(ref cert) = vhost.ssl_certificate {
-                output.push_str(&format!("ssl_certificate = \"{}\"\n", cert));
-            }
-            if let Some(ref key) = vhost.ssl_certificate_key {
-                output.push_str(&format!("ssl_certificate_key = \"{}\"\n", key));
-            }
+    pub fn to_toml_vhosts_only(&self, apache: &ApacheConfig) -> Result<String, ConversionError> {
+        self.render(&self.convert(apache), true)
+    }
+
+    /// Serialize a converted configuration, or only its [[virtualhost]] blocks
+    pub fn render(&self, config: &Config, vhosts_only: bool) -> Result<String, ConversionError> {
+        render_toml(config, TOML_HEADER, vhosts_only)
+            .map_err(|e| ConversionError::Serialization(e.to_string()))
+    }
+}
 
-            output.push('\n');
+/// Serialize a converted configuration under `header`, or only its
+/// [[virtualhost]] blocks; shared by the Apache and Nginx converters
+pub(crate) fn render_toml(
+    config: &Config,
+    header: &str,
+    vhosts_only: bool,
+) -> Result<String, toml::ser::Error> {
+    if vhosts_only {
+        return toml::to_string(&VhostsOnly {
+            virtualhost: &config.virtualhost,
+        });
+    }
+    Ok(format!("{}{}", header, toml::to_string(config)?))
+}
 
-            let platform = vhost.platform.as_deref().unwrap_or("");
-            if platform == "wordpress" || platform == "magento2" {
-                output.push_str(
-                    "[virtualhost.cache]\n\
-                     enable = true\n\
-                     ttl = 3600\n\
-                     exclude = [\"/wp-admin/*\", \"/wp-login.php\"]\n\n",
-                );
-            }
+/// Name of a VirtualHost directive the conversion leaves out, e.g.
+/// `RewriteRule` or `<Directory /var/www>`
+fn unsupported_directive(directive: &ApacheDirective) -> Option<String> {
+    match directive {
+        // [php] applies to every site, so it can't take a VirtualHost's
+        ApacheDirective::Simple { name, value } if name.starts_with("php_") => Some(format!(
+            "{} {} (PHP settings are server-wide)",
+            name,
+            value.split_whitespace().next().unwrap_or_default()
+        )),
+        ApacheDirective::Simple { name, .. } => {
+            let known = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+            (!known(CONVERTED_DIRECTIVES) && !known(IGNORED_DIRECTIVES)).then(|| name.clone())
         }
-        output
+        ApacheDirective::Directory { path, .. } => Some(format!("<Directory {}>", path)),
+        ApacheDirective::Files { pattern, .. } => Some(format!("<Files {}>", pattern)),
+        ApacheDirective::IfModule { module, .. } => Some(format!("<IfModule {}>", module)),
+        ApacheDirective::VirtualHost { .. } | ApacheDirective::Comment(_) => None,
     }
 }
 
+/// Detect CMS/platform from document root path
+pub(crate) fn detect_platform(docroot: &str) -> String {
+    let path = std::path::Path::new(docroot);
+
+    if path.join("wp-config.php").exists() {
+        return "wordpress".to_string();
+    }
+    if path.join("app/etc/env.php").exists() {
+        return "magento2".to_string();
+    }
+    if path.join("artisan").exists() {
+        return "laravel".to_string();
+    }
+
+    "generic".to_string()
+}
+
+/// Page cache settings for a detected platform
+pub(crate) fn platform_cache(platform: &str) -> Option<VHostCacheConfig> {
+    matches!(platform, "wordpress" | "magento2").then(|| VHostCacheConfig {
+        enable: true,
+        ttl: 3600,
+        stale_grace: None,
+        bypass_cookies: None,
+        vary: Vec::new(),
+        exclude: vec!["/wp-admin/*".to_string(), "/wp-login.php".to_string()],
+        exclude_types: Vec::new(),
+        query_allow: Vec::new(),
+    })
+}
+
 impl Default for ApacheToVeloServeConverter {
     fn default() -> Self {
         Self::new()
@@ -214,6 +297,7 @@ pub enum ConversionError {
     MissingServerName,
     InvalidSslConfiguration,
     UnsupportedDirective(String),
+    Serialization(String),
 }
 
 impl std::fmt::Display for ConversionError {
@@ -231,6 +315,9 @@ impl std::fmt::Display for ConversionError {
             ConversionError::UnsupportedDirective(d) => {
                 write!(f, "Unsupported directive: {}", d)
             }
+            ConversionError::Serialization(e) => {
+                write!(f, "Failed to write TOML: {}", e)
+            }
         }
     }
 }
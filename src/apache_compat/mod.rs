@@ -105,6 +105,7 @@ impl ApacheConfig {
     }
 
     /// Parse Apache configuration from string
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(content: &str) -> ParseResult<Self> {
         let parser = ApacheConfigParser::new();
         parser.parse(content)
@@ -176,4 +177,197 @@ mod tests {
             Some(PathBuf::from("/etc/ssl/certs/example.crt"))
         );
     }
+
+    #[test]
+    fn test_parse_nested_blocks() {
+        let config = r#"
+<IfModule mod_ssl.c>
+    <VirtualHost *:443>
+        ServerName shop.example.com
+        DocumentRoot /var/www/shop
+        <Directory /var/www/shop>
+            AllowOverride All
+            <Files "wp-config.php">
+                Require all denied
+            </Files>
+        </Directory>
+        <Proxy *>
+            Require all granted
+        </Proxy>
+        SSLEngine on
+        SSLCertificateFile /etc/ssl/certs/shop.crt
+    </VirtualHost>
+</IfModule>
+"#;
+
+        let apache_config = ApacheConfig::from_str(config).unwrap();
+        assert_eq!(apache_config.virtual_hosts.len(), 1);
+
+        let vhost = &apache_config.virtual_hosts[0];
+        assert_eq!(vhost.server_names, vec!["shop.example.com".to_string()]);
+        assert_eq!(vhost.document_root, Some(PathBuf::from("/var/www/shop")));
+        assert_eq!(
+            vhost.ssl.as_ref().unwrap().certificate_file,
+            Some(PathBuf::from("/etc/ssl/certs/shop.crt"))
+        );
+
+        let ApacheDirective::IfModule { module, content } = &apache_config.global_directives[0]
+        else {
+            panic!("expected <IfModule>");
+        };
+        assert_eq!(module, "mod_ssl.c");
+        let ApacheDirective::VirtualHost { content, .. } = &content[0] else {
+            panic!("expected <VirtualHost>");
+        };
+        // The unknown <Proxy> block is skipped along with its content
+        assert_eq!(content.len(), 5);
+        let ApacheDirective::Directory { path, content } = &content[2] else {
+            panic!("expected <Directory>");
+        };
+        assert_eq!(path, "/var/www/shop");
+        assert!(matches!(
+            &content[1],
+            ApacheDirective::Files { pattern, content }
+                if pattern == "\"wp-config.php\"" && content.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_parse_unclosed_block() {
+        let config = "<VirtualHost *:80>\n    ServerName example.com\n";
+        assert!(matches!(
+            ApacheConfig::from_str(config),
+            Err(ApacheParseError::UnclosedBlock)
+        ));
+    }
+
+    #[test]
+    fn test_parse_file_expands_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("sites-enabled")).unwrap();
+        std::fs::write(
+            root.join("httpd.conf"),
+            "LoadModule ssl_module modules/mod_ssl.so\n\
+             Include sites-enabled/*.conf\n\
+             IncludeOptional conf.d/*.conf\n\
+             IncludeOptional missing.conf\n",
+        )
+        .unwrap();
+        for (file, name) in [("b.conf", "b.example.com"), ("a.conf", "a.example.com")] {
+            std::fs::write(
+                root.join("sites-enabled").join(file),
+                format!(
+                    "<VirtualHost *:80>\n    ServerName {}\n    Include \"ssl.inc\"\n</VirtualHost>\n",
+                    name
+                ),
+            )
+            .unwrap();
+        }
+        std::fs::write(root.join("sites-enabled/README"), "ServerName ignored\n").unwrap();
+        std::fs::write(
+            root.join("ssl.inc"),
+            "SSLCertificateFile /etc/ssl/site.crt\n",
+        )
+        .unwrap();
+
+        let config = ApacheConfig::from_file(root.join("httpd.conf")).unwrap();
+        let names: Vec<&str> = config
+            .virtual_hosts
+            .iter()
+            .map(|vhost| vhost.server_names[0].as_str())
+            .collect();
+        assert_eq!(names, ["a.example.com", "b.example.com"]);
+        assert!(config.virtual_hosts.iter().all(|vhost| {
+            vhost.ssl.as_ref().unwrap().certificate_file == Some(PathBuf::from("/etc/ssl/site.crt"))
+        }));
+        assert_eq!(config.modules.len(), 1);
+        assert_eq!(config.includes.len(), 3);
+        assert!(config
+            .global_directives
+            .iter()
+            .any(|directive| matches!(directive, ApacheDirective::VirtualHost { .. })));
+
+        std::fs::write(root.join("httpd.conf"), "Include missing.conf\n").unwrap();
+        assert!(matches!(
+            ApacheConfig::from_file(root.join("httpd.conf")),
+            Err(ApacheParseError::IoError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_circular_include() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.conf"), "Include b.conf\n").unwrap();
+        std::fs::write(dir.path().join("b.conf"), "Include a.conf\n").unwrap();
+
+        assert!(matches!(
+            ApacheConfig::from_file(dir.path().join("a.conf")),
+            Err(ApacheParseError::CircularInclude { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_include_directory_with_symlink_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let sites = dir.path().join("sites");
+        std::fs::create_dir(&sites).unwrap();
+        std::fs::write(
+            sites.join("site.conf"),
+            "<VirtualHost *:80>\n    ServerName example.com\n</VirtualHost>\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(&sites, sites.join("loop")).unwrap();
+        std::fs::write(dir.path().join("httpd.conf"), "Include sites\n").unwrap();
+
+        // The directory is read once, not again through the link
+        let config = ApacheConfig::from_file(dir.path().join("httpd.conf")).unwrap();
+        assert_eq!(config.virtual_hosts.len(), 1);
+    }
+
+    #[test]
+    fn test_converted_toml_round_trips() {
+        let config = r#"
+php_admin_value max_execution_time 120
+<VirtualHost *:80>
+    ServerName shop.example.com
+    DocumentRoot /var/www/shop
+    php_admin_value memory_limit 512M
+</VirtualHost>
+"#;
+
+        let apache_config = ApacheConfig::from_str(config).unwrap();
+        let toml = ApacheToVeloServeConverter::new()
+            .to_toml(&apache_config)
+            .unwrap();
+        let converted = crate::config::Config::from_str(&toml).unwrap();
+
+        // The VirtualHost's memory_limit would apply to every site
+        assert_eq!(
+            converted.php.memory_limit,
+            crate::config::Config::default().php.memory_limit
+        );
+        let (_, warnings) = ApacheToVeloServeConverter::new().convert_with_warnings(&apache_config);
+        assert_eq!(
+            warnings,
+            [
+                "Not converted: Unsupported directive: php_admin_value memory_limit \
+              (PHP settings are server-wide) in shop.example.com"
+            ]
+        );
+        assert_eq!(
+            converted.php.max_execution_time,
+            std::time::Duration::from_secs(120)
+        );
+        assert_eq!(converted.virtualhost.len(), 1);
+        assert_eq!(converted.virtualhost[0].domain, "shop.example.com");
+        assert_eq!(converted.virtualhost[0].root, "/var/www/shop");
+
+        let vhosts = ApacheToVeloServeConverter::new()
+            .to_toml_vhosts_only(&apache_config)
+            .unwrap();
+        assert!(vhosts.starts_with("[[virtualhost]]"), "{}", vhosts);
+        assert!(!vhosts.contains("[server]"));
+    }
 }
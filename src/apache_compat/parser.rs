@@ -2,13 +2,19 @@
 //!
 //! Parses Apache httpd.conf and vhost files into structured data.
 
+use std::collections::HashSet;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 use crate::apache_compat::{
     errors::{ApacheParseError, ParseResult},
     ApacheConfig, ApacheDirective, ApacheSslConfig, ApacheVirtualHost,
 };
+use crate::server::glob_matches;
+
+/// Numbered lines of a configuration file
+type Lines<'a> = std::iter::Enumerate<std::str::Lines<'a>>;
 
 /// Parser for Apache configuration files
 pub struct ApacheConfigParser {
@@ -16,6 +22,16 @@ pub struct ApacheConfigParser {
     verbose: bool,
     /// Expand includes (Include, IncludeOptional directives)
     expand_includes: bool,
+    /// Directory relative include paths resolve against, unless the file
+    /// sets `ServerRoot` (defaults to the directory of the parsed file)
+    server_root: Option<PathBuf>,
+}
+
+/// State for reading included files
+struct IncludeContext {
+    server_root: PathBuf,
+    /// Canonical paths of the files currently being read
+    visited: HashSet<PathBuf>,
 }
 
 impl ApacheConfigParser {
@@ -24,6 +40,7 @@ impl ApacheConfigParser {
         Self {
             verbose: false,
             expand_includes: true,
+            server_root: None,
         }
     }
 
@@ -39,26 +56,91 @@ impl ApacheConfigParser {
         self
     }
 
-    /// Parse configuration from a file
+    /// Set the directory relative include paths resolve against
+    pub fn server_root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.server_root = Some(root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Parse configuration from a file, reading the files it includes
     pub fn parse_file<P: AsRef<Path>>(&self, path: P) -> ParseResult<ApacheConfig> {
-        let content = fs::read_to_string(&path).map_err(|e| ApacheParseError::IoError {
-            path: path.as_ref().to_path_buf(),
-            source: e,
-        })?;
+        let path = path.as_ref();
+        let content = read_file(path)?;
 
-        self.parse(&content)
+        if !self.expand_includes {
+            return self.parse_config(&content, None);
+        }
+        let server_root = self
+            .server_root
+            .clone()
+            .unwrap_or_else(|| path.parent().map(Path::to_path_buf).unwrap_or_default());
+        let mut includes = IncludeContext {
+            server_root,
+            visited: HashSet::new(),
+        };
+        includes.visited.insert(canonical(path)?);
+        self.parse_config(&content, Some(&mut includes))
     }
 
-    /// Parse configuration from string content
+    /// Parse configuration from string content. Include paths are recorded
+    /// but not read; use `parse_file` to expand them.
     pub fn parse(&self, content: &str) -> ParseResult<ApacheConfig> {
+        self.parse_config(content, None)
+    }
+
+    fn parse_config(
+        &self,
+        content: &str,
+        includes: Option<&mut IncludeContext>,
+    ) -> ParseResult<ApacheConfig> {
         let mut config = ApacheConfig::default();
-        let mut lines = content.lines().peekable();
-        let mut line_number = 0;
+        let directives = self.parse_content(
+            &mut content.lines().enumerate(),
+            None,
+            &mut config,
+            includes,
+        )?;
 
-        while let Some(line) = lines.next() {
-            line_number += 1;
+        // Handle global directives
+        for directive in &directives {
+            if let ApacheDirective::Simple { name, value } = directive {
+                match name.as_str() {
+                    "Include" | "IncludeOptional" if self.expand_includes => {
+                        config.includes.push(PathBuf::from(value));
+                    }
+                    "LoadModule" => {
+                        let parts: Vec<&str> = value.split_whitespace().collect();
+                        if parts.len() >= 2 {
+                            config
+                                .modules
+                                .push((parts[0].to_string(), PathBuf::from(parts[1])));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        config.global_directives = directives;
+
+        Ok(config)
+    }
+
+    /// Parse lines up to the closing tag of `block` (or the end of input at
+    /// the top level), recursing into nested blocks. Every VirtualHost found
+    /// on the way is added to `config.virtual_hosts`. With `includes`, the
+    /// directives of included files follow their `Include` line.
+    fn parse_content(
+        &self,
+        lines: &mut Lines<'_>,
+        block: Option<&str>,
+        config: &mut ApacheConfig,
+        mut includes: Option<&mut IncludeContext>,
+    ) -> ParseResult<Vec<ApacheDirective>> {
+        let mut content = Vec::new();
+
+        while let Some((index, line)) = lines.next() {
+            let line_number = index + 1;
 
-            // Skip empty lines and comments (but keep them for context)
             let trimmed = line.trim();
             if trimmed.is_empty() {
                 continue;
@@ -66,61 +148,150 @@ impl ApacheConfigParser {
 
             // Handle comments
             if trimmed.starts_with('#') {
-                config
-                    .global_directives
-                    .push(ApacheDirective::Comment(trimmed.to_string()));
+                content.push(ApacheDirective::Comment(trimmed.to_string()));
                 continue;
             }
 
-            // Parse directive
-            match self.parse_line(trimmed) {
+            if let Some(closing) = trimmed.strip_prefix("</") {
+                let name = block_name(closing);
+                if block.is_some_and(|open| open.eq_ignore_ascii_case(name)) {
+                    return Ok(content);
+                }
+                if self.verbose {
+                    eprintln!("Warning at line {}: unexpected </{}>", line_number, name);
+                }
+                continue;
+            }
+
+            if !trimmed.starts_with('<') {
+                match self.parse_line(trimmed) {
+                    Ok(directive) => {
+                        let include = match (&directive, includes.as_deref_mut()) {
+                            (ApacheDirective::Simple { name, value }, Some(context)) => {
+                                match name.as_str() {
+                                    "ServerRoot" => {
+                                        context.server_root = PathBuf::from(unquote(value));
+                                        None
+                                    }
+                                    "Include" => Some((unquote(value).to_string(), false)),
+                                    "IncludeOptional" => Some((unquote(value).to_string(), true)),
+                                    _ => None,
+                                }
+                            }
+                            _ => None,
+                        };
+                        content.push(directive);
+
+                        if let (Some((pattern, optional)), Some(context)) =
+                            (include, includes.as_deref_mut())
+                        {
+                            content
+                                .extend(self.parse_include(&pattern, optional, context, config)?);
+                        }
+                    }
+                    Err(e) => {
+                        if self.verbose {
+                            eprintln!("Warning at line {}: {:?}", line_number, e);
+                        }
+                        // Continue parsing even if one line fails
+                    }
+                }
+                continue;
+            }
+
+            let name = block_name(&trimmed[1..]);
+            if name.is_empty() || !trimmed.contains('>') {
+                if self.verbose {
+                    eprintln!(
+                        "Warning at line {}: malformed block {}",
+                        line_number, trimmed
+                    );
+                }
+                continue;
+            }
+
+            // Unknown blocks are still read to their closing tag, then dropped
+            let inner = self.parse_content(lines, Some(name), config, includes.as_deref_mut())?;
+            match self.parse_block_start(trimmed, inner) {
                 Ok(directive) => {
-                    // Extract virtual hosts
                     if let ApacheDirective::VirtualHost { addresses, content } = &directive {
                         if let Ok(vhost) = self.parse_virtual_host(addresses, content) {
                             config.virtual_hosts.push(vhost);
                         }
-                    } else if let ApacheDirective::Simple { name, value } = &directive {
-                        // Handle global directives
-                        match name.as_str() {
-                            "Include" | "IncludeOptional" => {
-                                if self.expand_includes {
-                                    config.includes.push(PathBuf::from(value));
-                                }
-                            }
-                            "LoadModule" => {
-                                let parts: Vec<&str> = value.split_whitespace().collect();
-                                if parts.len() >= 2 {
-                                    config
-                                        .modules
-                                        .push((parts[0].to_string(), PathBuf::from(parts[1])));
-                                }
-                            }
-                            _ => {}
-                        }
                     }
-
-                    config.global_directives.push(directive);
+                    content.push(directive);
                 }
                 Err(e) => {
                     if self.verbose {
                         eprintln!("Warning at line {}: {:?}", line_number, e);
                     }
-                    // Continue parsing even if one line fails
                 }
             }
         }
 
-        Ok(config)
+        match block {
+            Some(_) => Err(ApacheParseError::UnclosedBlock),
+            None => Ok(content),
+        }
     }
 
-    /// Parse a single line into a directive
-    fn parse_line(&self, line: &str) -> ParseResult<ApacheDirective> {
-        // Handle block directives (<VirtualHost>, <Directory>, etc.)
-        if line.starts_with('<') {
-            return self.parse_block_start(line);
+    /// Read and parse the files an `Include` or `IncludeOptional` names
+    fn parse_include(
+        &self,
+        pattern: &str,
+        optional: bool,
+        includes: &mut IncludeContext,
+        config: &mut ApacheConfig,
+    ) -> ParseResult<Vec<ApacheDirective>> {
+        let pattern = includes.server_root.join(pattern);
+        let files = match expand_include(&pattern) {
+            Ok(files) if !files.is_empty() => files,
+            Ok(_) if optional => return Ok(Vec::new()),
+            Ok(_) => {
+                return Err(ApacheParseError::IoError {
+                    path: pattern,
+                    source: io::Error::new(io::ErrorKind::NotFound, "no matching files"),
+                })
+            }
+            Err(e) if optional && e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(ApacheParseError::IoError {
+                    path: pattern,
+                    source: e,
+                })
+            }
+        };
+
+        let mut directives = Vec::new();
+        for file in files {
+            let content = match fs::read_to_string(&file) {
+                Ok(content) => content,
+                Err(e) if optional && e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(ApacheParseError::IoError {
+                        path: file,
+                        source: e,
+                    })
+                }
+            };
+            let path = canonical(&file)?;
+            if !includes.visited.insert(path.clone()) {
+                return Err(ApacheParseError::CircularInclude { path });
+            }
+            directives.extend(self.parse_content(
+                &mut content.lines().enumerate(),
+                None,
+                config,
+                Some(&mut *includes),
+            )?);
+            includes.visited.remove(&path);
         }
 
+        Ok(directives)
+    }
+
+    /// Parse a single line into a directive
+    fn parse_line(&self, line: &str) -> ParseResult<ApacheDirective> {
         // Simple directive: Name value
         let parts: Vec<&str> = line.splitn(2, char::is_whitespace).collect();
         if parts.is_empty() {
@@ -133,8 +304,12 @@ impl ApacheConfigParser {
         Ok(ApacheDirective::Simple { name, value })
     }
 
-    /// Parse block directive start
-    fn parse_block_start(&self, line: &str) -> ParseResult<ApacheDirective> {
+    /// Build a block directive from its opening line and parsed content
+    fn parse_block_start(
+        &self,
+        line: &str,
+        content: Vec<ApacheDirective>,
+    ) -> ParseResult<ApacheDirective> {
         // Extract block type and arguments
         let end_pos = line.find('>').ok_or(ApacheParseError::UnclosedBlock)?;
         let inner = &line[1..end_pos];
@@ -146,36 +321,22 @@ impl ApacheConfigParser {
 
         let block_type = parts[0].to_lowercase();
 
-        // For now, return a simplified version
-        // In full implementation, we'd parse the entire block content
         match block_type.as_str() {
             "virtualhost" => {
                 let addresses = parts[1..].iter().map(|s| s.to_string()).collect();
-                Ok(ApacheDirective::VirtualHost {
-                    addresses,
-                    content: vec![], // Would be filled by parsing block content
-                })
+                Ok(ApacheDirective::VirtualHost { addresses, content })
             }
             "directory" => {
                 let path = parts.get(1).unwrap_or(&"/").to_string();
-                Ok(ApacheDirective::Directory {
-                    path,
-                    content: vec![],
-                })
+                Ok(ApacheDirective::Directory { path, content })
             }
             "ifmodule" => {
                 let module = parts.get(1).unwrap_or(&"").to_string();
-                Ok(ApacheDirective::IfModule {
-                    module,
-                    content: vec![],
-                })
+                Ok(ApacheDirective::IfModule { module, content })
             }
             "files" => {
                 let pattern = parts.get(1).unwrap_or(&"").to_string();
-                Ok(ApacheDirective::Files {
-                    pattern,
-                    content: vec![],
-                })
+                Ok(ApacheDirective::Files { pattern, content })
             }
             _ => Err(ApacheParseError::UnknownBlock(block_type)),
         }
@@ -185,9 +346,12 @@ impl ApacheConfigParser {
     fn parse_virtual_host(
         &self,
         addresses: &[String],
-        _content: &[ApacheDirective],
+        content: &[ApacheDirective],
     ) -> ParseResult<ApacheVirtualHost> {
-        let mut vhost = ApacheVirtualHost::default();
+        let mut vhost = ApacheVirtualHost {
+            directives: content.to_vec(),
+            ..Default::default()
+        };
 
         // Extract port from address (e.g., "*:80" or "127.0.0.1:443")
         for addr in addresses {
@@ -200,70 +364,69 @@ impl ApacheConfigParser {
         }
 
         // Parse content directives
-        for directive in _content {
-            match directive {
-                ApacheDirective::Simple { name, value } => {
-                    match name.as_str() {
-                        "ServerName" => {
-                            if vhost.server_names.is_empty() {
-                                vhost.server_names.push(value.clone());
-                            }
-                        }
-                        "ServerAlias" => {
-                            for alias in value.split_whitespace() {
-                                vhost.server_names.push(alias.to_string());
-                            }
-                        }
-                        "DocumentRoot" => {
-                            vhost.document_root = Some(PathBuf::from(value));
-                        }
-                        "SSLEngine" => {
-                            let enabled = value.eq_ignore_ascii_case("on");
-                            if vhost.ssl.is_none() {
-                                vhost.ssl = Some(ApacheSslConfig {
-                                    enabled,
-                                    ..Default::default()
-                                });
-                            } else if let Some(ref mut ssl) = vhost.ssl {
-                                ssl.enabled = enabled;
-                            }
+        for directive in content {
+            if let ApacheDirective::Simple { name, value } = directive {
+                match name.as_str() {
+                    "ServerName" if vhost.server_names.is_empty() => {
+                        vhost.server_names.push(value.clone());
+                    }
+                    "ServerAlias" => {
+                        for alias in value.split_whitespace() {
+                            vhost.server_names.push(alias.to_string());
                         }
-                        "SSLCertificateFile" => {
-                            if vhost.ssl.is_none() {
-                                vhost.ssl = Some(ApacheSslConfig::default());
-                            }
-                            if let Some(ref mut ssl) = vhost.ssl {
-                                ssl.certificate_file = Some(PathBuf::from(value));
-                            }
+                    }
+                    "DocumentRoot" => {
+                        vhost.document_root = Some(PathBuf::from(value));
+                    }
+                    "SSLEngine" => {
+                        let enabled = value.eq_ignore_ascii_case("on");
+                        if vhost.ssl.is_none() {
+                            vhost.ssl = Some(ApacheSslConfig {
+                                enabled,
+                                ..Default::default()
+                            });
+                        } else if let Some(ref mut ssl) = vhost.ssl {
+                            ssl.enabled = enabled;
                         }
-                        "SSLCertificateKeyFile" => {
-                            if vhost.ssl.is_none() {
-                                vhost.ssl = Some(ApacheSslConfig::default());
-                            }
-                            if let Some(ref mut ssl) = vhost.ssl {
-                                ssl.certificate_key_file = Some(PathBuf::from(value));
-                            }
+                    }
+                    "SSLCertificateFile" => {
+                        if vhost.ssl.is_none() {
+                            vhost.ssl = Some(ApacheSslConfig::default());
                         }
-                        "DirectoryIndex" => {
-                            vhost.directory_index =
-                                value.split_whitespace().map(|s| s.to_string()).collect();
+                        if let Some(ref mut ssl) = vhost.ssl {
+                            ssl.certificate_file = Some(PathBuf::from(value));
                         }
-                        "ErrorLog" => {
-                            vhost.error_log = Some(PathBuf::from(value));
+                    }
+                    "SSLCertificateKeyFile" => {
+                        if vhost.ssl.is_none() {
+                            vhost.ssl = Some(ApacheSslConfig::default());
                         }
-                        "CustomLog" => {
-                            // CustomLog has format: path format [env]
-                            let path = value.split_whitespace().next().map(|s| PathBuf::from(s));
-                            vhost.custom_log = path;
+                        if let Some(ref mut ssl) = vhost.ssl {
+                            ssl.certificate_key_file = Some(PathBuf::from(value));
                         }
-                        name if name.starts_with("php_admin_") => {
-                            let key = name.strip_prefix("php_admin_").unwrap_or(name);
-                            vhost.php_settings.insert(key.to_string(), value.clone());
+                    }
+                    "DirectoryIndex" => {
+                        vhost.directory_index =
+                            value.split_whitespace().map(|s| s.to_string()).collect();
+                    }
+                    "ErrorLog" => {
+                        vhost.error_log = Some(PathBuf::from(value));
+                    }
+                    "CustomLog" => {
+                        // CustomLog has format: path format [env]
+                        let path = value.split_whitespace().next().map(PathBuf::from);
+                        vhost.custom_log = path;
+                    }
+                    "php_admin_value" | "php_admin_flag" | "php_value" | "php_flag" => {
+                        // "php_admin_value memory_limit 512M"
+                        if let Some((key, setting)) = value.split_once(char::is_whitespace) {
+                            vhost
+                                .php_settings
+                                .insert(key.to_string(), setting.trim().to_string());
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
-                _ => {}
             }
         }
 
@@ -271,6 +434,97 @@ impl ApacheConfigParser {
     }
 }
 
+/// Name of a block tag, e.g. `VirtualHost` for `VirtualHost *:80>`
+fn block_name(tag: &str) -> &str {
+    tag.split(|c: char| c.is_whitespace() || c == '>')
+        .next()
+        .unwrap_or("")
+}
+
+/// Strip the quotes around a directive value
+fn unquote(value: &str) -> &str {
+    value.trim_matches('"')
+}
+
+fn read_file(path: &Path) -> ParseResult<String> {
+    fs::read_to_string(path).map_err(|e| ApacheParseError::IoError {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+fn canonical(path: &Path) -> ParseResult<PathBuf> {
+    fs::canonicalize(path).map_err(|e| ApacheParseError::IoError {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+/// Files an include path names, in the order Apache reads them: `*` and `?`
+/// match within one path component, matches are sorted, and a directory
+/// stands for every file below it. Hidden files are skipped.
+pub(crate) fn expand_include(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut matches = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !part.contains(['*', '?']) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for dir in &matches {
+            next.extend(
+                dir_entries(dir, &part)?
+                    .into_iter()
+                    .map(|name| dir.join(name)),
+            );
+        }
+        matches = next;
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = HashSet::new();
+    for path in matches {
+        push_files(path, &mut dirs, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Add `path` to `files`, or every file below it if it is a directory.
+/// `dirs` holds the canonical directories already read, so a symlink back
+/// up the tree is not followed round forever.
+fn push_files(
+    path: PathBuf,
+    dirs: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path);
+        return Ok(());
+    }
+    if !dirs.insert(fs::canonicalize(&path)?) {
+        return Ok(());
+    }
+    for name in dir_entries(&path, "*")? {
+        push_files(path.join(name), dirs, files)?;
+    }
+    Ok(())
+}
+
+/// Sorted names in `dir` matching `pattern`, hidden ones left out
+fn dir_entries(dir: &Path, pattern: &str) -> io::Result<Vec<String>> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.') && glob_matches(pattern, name))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
 impl Default for ApacheConfigParser {
     fn default() -> Self {
         Self::new()
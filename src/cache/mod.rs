@@ -3,6 +3,7 @@
 //! Multi-layer caching system for VeloServe.
 
 use crate::config::{CacheConfig, CacheStorage};
+use bytes::Bytes;
 use dashmap::DashMap;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
@@ -12,78 +13,302 @@ use parking_lot::Mutex;
 use redis::{Client, Commands, Connection};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::io::{Read, Write};
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
-#[derive(Clone)]
-struct CacheEntry {
-    data: Vec<u8>,
-    content_type: String,
-    tags: Vec<String>,
-    created_at_epoch_secs: u64,
-    ttl: Duration,
-    stale_after: Duration,
+/// Validators stored alongside a cached response
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheValidators {
+    /// `ETag` of the stored response
+    pub etag: Option<String>,
+    /// `Last-Modified` of the stored response
+    pub last_modified: Option<String>,
 }
 
-impl CacheEntry {
-    fn new(
+/// A complete cached HTTP response
+///
+/// Entries keep the status line, the replayable response headers and the raw
+/// body bytes, so a hit reproduces the original response instead of only its
+/// body and content type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// Response headers in original order (names as received)
+    pub headers: Vec<(String, String)>,
+    /// Response body
+    pub body: Bytes,
+    /// Validators extracted from the headers
+    pub validators: CacheValidators,
+    /// Request headers named by the response `Vary` header (lowercased)
+    pub vary: Vec<String>,
+    /// Purge tags
+    pub tags: Vec<String>,
+    /// Unix timestamp (seconds) the entry was stored at
+    pub stored_at: u64,
+    /// Time after which the entry is expired
+    pub ttl: Duration,
+    /// Time after which the entry is stale
+    pub stale_after: Duration,
+    /// URL of the request the entry answered, for re-warming it elsewhere
+    pub url: Option<String>,
+}
+
+impl CachedResponse {
+    /// Build an entry from a response; validators and vary are taken from `headers`.
+    pub fn new(status: u16, headers: Vec<(String, String)>, body: impl Into<Bytes>) -> Self {
+        let mut response = Self {
+            status,
+            headers,
+            body: body.into(),
+            validators: CacheValidators::default(),
+            vary: Vec::new(),
+            tags: Vec::new(),
+            stored_at: now_epoch_secs(),
+            ttl: Duration::ZERO,
+            stale_after: Duration::ZERO,
+            url: None,
+        };
+        response.validators = CacheValidators {
+            etag: response.header("etag").map(str::to_string),
+            last_modified: response.header("last-modified").map(str::to_string),
+        };
+        response.vary = response
+            .header("vary")
+            .map(|vary| {
+                vary.split(',')
+                    .map(|name| name.trim().to_ascii_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        response
+    }
+
+    /// A `200 OK` entry with only a content type, as stored by the byte-oriented API
+    pub fn from_bytes(data: impl Into<Bytes>, content_type: &str) -> Self {
+        Self::new(
+            200,
+            vec![("Content-Type".to_string(), content_type.to_string())],
+            data,
+        )
+    }
+
+    /// Attach purge tags
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Record the URL of the request the entry answers
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// First header value named `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(current, _)| current.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Stored `Content-Type`, or an empty string
+    pub fn content_type(&self) -> &str {
+        self.header("content-type").unwrap_or("")
+    }
+
+    /// Bytes accounted against the memory limit: body, headers and metadata
+    pub fn size_bytes(&self) -> u64 {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        let strings: usize = self
+            .tags
+            .iter()
+            .chain(self.vary.iter())
+            .chain(self.validators.etag.iter())
+            .chain(self.validators.last_modified.iter())
+            .chain(self.url.iter())
+            .map(String::len)
+            .sum();
+        (self.body.len() + headers + strings) as u64
+    }
+
+    /// Seconds since the entry was stored
+    pub fn age_seconds(&self) -> u64 {
+        now_epoch_secs().saturating_sub(self.stored_at)
+    }
+
+    /// True once the entry outlived its ttl
+    pub fn is_expired(&self) -> bool {
+        self.age_seconds() > self.ttl.as_secs()
+    }
+
+    /// True once the entry outlived its stale threshold
+    pub fn is_stale(&self) -> bool {
+        self.age_seconds() > self.stale_after.as_secs()
+    }
+
+    /// Serialize for a persistent layer, tagged with the current format version
+    pub(crate) fn encode(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        let (compressed, body) = match (self.body.len() >= ENTRY_COMPRESSION_THRESHOLD_BYTES)
+            .then(|| gzip(&self.body))
+            .flatten()
+        {
+            Some(compressed) if compressed.len() < self.body.len() => (true, compressed),
+            _ => (false, self.body.to_vec()),
+        };
+
+        let persisted = PersistedResponse {
+            key: key.to_string(),
+            status: self.status,
+            headers: self.headers.clone(),
+            validators: self.validators.clone(),
+            vary: self.vary.clone(),
+            tags: self.tags.clone(),
+            stored_at: self.stored_at,
+            ttl_seconds: self.ttl.as_secs(),
+            stale_after_seconds: self.stale_after.as_secs(),
+            url: self.url.clone(),
+            compressed,
+            body,
+        };
+
+        let mut out = Vec::with_capacity(persisted.body.len() + 256);
+        out.extend_from_slice(ENTRY_MAGIC);
+        out.push(ENTRY_FORMAT_VERSION);
+        bincode::serialize_into(&mut out, &persisted)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(out)
+    }
+
+    /// Deserialize a versioned entry, returning its key; `None` for legacy or unknown formats
+    pub(crate) fn decode(raw: &[u8]) -> Option<(String, Self)> {
+        let payload = raw.strip_prefix(ENTRY_MAGIC.as_slice())?;
+        let (&version, payload) = payload.split_first()?;
+        if version != ENTRY_FORMAT_VERSION {
+            return None;
+        }
+
+        let persisted: PersistedResponse = bincode::deserialize(payload).ok()?;
+        let body = if persisted.compressed {
+            gunzip(&persisted.body)?
+        } else {
+            persisted.body
+        };
+
+        Some((
+            persisted.key,
+            Self {
+                status: persisted.status,
+                headers: persisted.headers,
+                body: Bytes::from(body),
+                validators: persisted.validators,
+                vary: persisted.vary,
+                tags: persisted.tags,
+                stored_at: persisted.stored_at,
+                ttl: Duration::from_secs(persisted.ttl_seconds),
+                stale_after: Duration::from_secs(persisted.stale_after_seconds),
+                url: persisted.url,
+            },
+        ))
+    }
+
+    fn from_legacy(
         data: Vec<u8>,
-        content_type: String,
+        content_type: &str,
         tags: Vec<String>,
-        ttl: Duration,
-        stale_after: Duration,
+        stored_at: u64,
+        ttl_seconds: u64,
+        stale_after_seconds: u64,
     ) -> Self {
-        Self {
-            data,
-            content_type,
-            tags,
-            created_at_epoch_secs: now_epoch_secs(),
-            ttl,
-            stale_after,
+        let mut response = Self::from_bytes(data, content_type).with_tags(tags);
+        response.stored_at = stored_at;
+        response.ttl = Duration::from_secs(ttl_seconds);
+        response.stale_after = Duration::from_secs(stale_after_seconds);
+        response
+    }
+}
+
+/// An L1 entry, its body gzipped when `cache.compress_memory` shrank it
+struct L1Entry {
+    response: CachedResponse,
+    /// Length of the original body while `response.body` holds it gzipped
+    original_len: Option<usize>,
+}
+
+impl L1Entry {
+    /// Wrap `response`, gzipping a body of at least `min_size` bytes if that
+    /// makes it smaller
+    fn compressed(mut response: CachedResponse, min_size: usize) -> Self {
+        let original_len = response.body.len();
+        if original_len < min_size.max(1) {
+            return Self::plain(response);
+        }
+        match gzip(&response.body) {
+            Some(compressed) if compressed.len() < original_len => {
+                response.body = Bytes::from(compressed);
+                Self {
+                    response,
+                    original_len: Some(original_len),
+                }
+            }
+            _ => Self::plain(response),
         }
     }
 
-    fn from_persisted(persisted: PersistedEntry) -> Self {
+    fn plain(response: CachedResponse) -> Self {
         Self {
-            data: persisted.data,
-            content_type: persisted.content_type,
-            tags: persisted.tags,
-            created_at_epoch_secs: persisted.created_at_epoch_secs,
-            ttl: Duration::from_secs(persisted.ttl_seconds),
-            stale_after: Duration::from_secs(persisted.stale_after_seconds),
+            response,
+            original_len: None,
         }
     }
 
-    fn to_persisted(&self) -> PersistedEntry {
-        PersistedEntry {
-            key: String::new(),
-            data: self.data.clone(),
-            content_type: self.content_type.clone(),
-            tags: self.tags.clone(),
-            created_at_epoch_secs: self.created_at_epoch_secs,
-            ttl_seconds: self.ttl.as_secs(),
-            stale_after_seconds: self.stale_after.as_secs(),
+    /// The response as stored, with its body decompressed; `None` if the
+    /// gzipped body is corrupt
+    fn to_response(&self) -> Option<CachedResponse> {
+        let mut response = self.response.clone();
+        if self.original_len.is_some() {
+            response.body = Bytes::from(gunzip(&self.response.body)?);
         }
+        Some(response)
     }
 
-    fn age_seconds(&self) -> u64 {
-        now_epoch_secs().saturating_sub(self.created_at_epoch_secs)
+    /// Bytes accounted against the memory limit, with the body as stored
+    fn size_bytes(&self) -> u64 {
+        self.response.size_bytes()
     }
+}
 
-    fn is_expired(&self) -> bool {
-        self.age_seconds() > self.ttl.as_secs()
-    }
+impl std::ops::Deref for L1Entry {
+    type Target = CachedResponse;
 
-    fn is_stale(&self) -> bool {
-        self.age_seconds() > self.stale_after.as_secs()
+    fn deref(&self) -> &CachedResponse {
+        &self.response
     }
 }
 
+/// What [`CacheManager::set_response`] did with an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStore {
+    Stored,
+    /// Caching is turned off
+    Disabled,
+    /// The body is bigger than `cache.max_entry_size`
+    TooLarge,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct CacheLifetime {
     pub ttl: Duration,
@@ -102,6 +327,15 @@ impl CacheLifetime {
             stale_after: ttl,
         }
     }
+
+    /// Fresh for `ttl`, then kept `grace` longer to be served stale while
+    /// it is refreshed
+    pub fn with_grace(ttl: Duration, grace: Duration) -> Self {
+        Self {
+            ttl: ttl.saturating_add(grace),
+            stale_after: ttl,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -117,20 +351,99 @@ struct LayerStats {
     op_latency_micros: AtomicU64,
 }
 
+/// Page cache lookups of one host across both layers, and its L1 entries
+#[derive(Default)]
+struct HostStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    entries: AtomicU64,
+    size_bytes: AtomicU64,
+}
+
 #[derive(Default)]
 struct CacheStats {
     l1: LayerStats,
     l2: LayerStats,
+    /// Lookups and entries by host, for at most [`MAX_STATS_HOSTS`] hosts
+    hosts: DashMap<String, HostStats>,
     size_bytes: AtomicU64,
+    /// Entries refused for being bigger than `cache.max_entry_size`
+    skipped_too_large: AtomicU64,
+    /// L1 entries held gzipped, and their body sizes before and after
+    compressed_entries: AtomicU64,
+    compressed_original_bytes: AtomicU64,
+    compressed_stored_bytes: AtomicU64,
 }
 
-const REDIS_ENTRY_VERSION: u8 = 1;
-const REDIS_COMPRESSION_THRESHOLD_BYTES: usize = 1024;
+/// Version of the persisted entry format written by both L2 backends
+pub const ENTRY_FORMAT_VERSION: u8 = 3;
+/// Prefix of every versioned entry; legacy entries predate it
+const ENTRY_MAGIC: &[u8; 3] = b"VSC";
+const ENTRY_COMPRESSION_THRESHOLD_BYTES: usize = 1024;
 const REDIS_RETRY_ATTEMPTS: u32 = 2;
 const REDIS_TAG_INDEX_TTL_GRACE_SECS: u64 = 300;
+/// How long Redis is bypassed after a failed call
+const REDIS_BACKOFF: Duration = Duration::from_secs(5);
+/// How often the disk cache is checked against `cache.disk_limit`
+const DISK_EVICTION_INTERVAL: Duration = Duration::from_secs(30);
+/// Hosts with their own lookup counters; later ones share [`OTHER_HOSTS`]
+/// so stray `Host` headers can't grow the table without bound
+const MAX_STATS_HOSTS: usize = 1024;
+const OTHER_HOSTS: &str = "(other)";
+/// How long a miss waits for another request filling the same key before
+/// generating the page itself
+const FILL_WAIT: Duration = Duration::from_secs(10);
+
+/// Persisted form of a [`CachedResponse`], shared by the disk and Redis layers
+#[derive(Serialize, Deserialize)]
+struct PersistedResponse {
+    key: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    validators: CacheValidators,
+    vary: Vec<String>,
+    tags: Vec<String>,
+    stored_at: u64,
+    ttl_seconds: u64,
+    stale_after_seconds: u64,
+    url: Option<String>,
+    compressed: bool,
+    body: Vec<u8>,
+}
+
+/// Unversioned disk entry written before format version 2 (body + content type only)
+#[derive(Serialize, Deserialize)]
+struct LegacyDiskEntry {
+    #[serde(default)]
+    key: String,
+    data: Vec<u8>,
+    content_type: String,
+    tags: Vec<String>,
+    created_at_epoch_secs: u64,
+    ttl_seconds: u64,
+    stale_after_seconds: u64,
+}
 
+impl LegacyDiskEntry {
+    fn decode(raw: &[u8]) -> Option<(String, CachedResponse)> {
+        let legacy: Self = bincode::deserialize(raw).ok()?;
+        Some((
+            legacy.key,
+            CachedResponse::from_legacy(
+                legacy.data,
+                &legacy.content_type,
+                legacy.tags,
+                legacy.created_at_epoch_secs,
+                legacy.ttl_seconds,
+                legacy.stale_after_seconds,
+            ),
+        ))
+    }
+}
+
+/// Redis entry written before format version 2 (body + content type only)
 #[derive(Serialize, Deserialize)]
-struct RedisPersistedEntry {
+struct LegacyRedisEntry {
     version: u8,
     content_type: String,
     tags: Vec<String>,
@@ -141,117 +454,386 @@ struct RedisPersistedEntry {
     data: Vec<u8>,
 }
 
+impl LegacyRedisEntry {
+    const VERSION: u8 = 1;
+
+    fn decode(raw: &[u8]) -> Option<CachedResponse> {
+        let legacy: Self = bincode::deserialize(raw).ok()?;
+        if legacy.version != Self::VERSION {
+            return None;
+        }
+        let data = if legacy.compressed {
+            gunzip(&legacy.data)?
+        } else {
+            legacy.data
+        };
+        Some(CachedResponse::from_legacy(
+            data,
+            &legacy.content_type,
+            legacy.tags,
+            legacy.created_at_epoch_secs,
+            legacy.ttl_seconds,
+            legacy.stale_after_seconds,
+        ))
+    }
+}
+
+fn gzip(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = GzDecoder::new(data);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out).ok()?;
+    Some(out)
+}
+
 trait PersistentCacheLayer: Send + Sync {
-    fn get(&self, key: &str) -> Option<CacheEntry>;
-    fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()>;
+    fn get(&self, key: &str) -> Option<CachedResponse>;
+    fn set(&self, key: &str, entry: &CachedResponse) -> std::io::Result<()>;
     fn remove(&self, key: &str) -> std::io::Result<()>;
     fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize>;
     fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize>;
     fn purge_all(&self) -> std::io::Result<usize>;
+
+    /// False while the layer is known to be unreachable
+    fn available(&self) -> bool {
+        true
+    }
+
+    /// Bytes the stored entries take up, where the layer keeps count
+    fn usage_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    /// Remove least recently used entries until at most `target` bytes
+    /// remain; returns how many were removed
+    fn evict_lru(&self, _target: u64) -> std::io::Result<usize> {
+        Ok(0)
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct PersistedEntry {
-    #[serde(default)]
+/// Sidecar metadata written next to each disk entry
+///
+/// Small enough to scan on startup, so the tag index can be rebuilt without
+/// decoding every stored body.
+#[derive(Debug, Serialize, Deserialize)]
+struct DiskEntryMeta {
     key: String,
-    data: Vec<u8>,
     content_type: String,
     tags: Vec<String>,
-    created_at_epoch_secs: u64,
+    created_at: u64,
     ttl_seconds: u64,
-    stale_after_seconds: u64,
 }
 
+impl DiskEntryMeta {
+    fn new(key: &str, entry: &CachedResponse) -> Self {
+        Self {
+            key: key.to_string(),
+            content_type: entry.content_type().to_string(),
+            tags: entry.tags.clone(),
+            created_at: entry.stored_at,
+            ttl_seconds: entry.ttl.as_secs(),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        now_epoch_secs().saturating_sub(self.created_at) > self.ttl_seconds
+    }
+}
+
+/// L2 cache on the local filesystem
+///
+/// Entries live at `<root>/ab/cd/abcd….bin` (the encoded response) with a
+/// `.meta` JSON sidecar, sharded by a stable hash of the cache key. Only the
+/// two-level hex shard directories belong to the cache; anything else under
+/// the root (such as converted images) is left alone.
 struct DiskCacheLayer {
     root: PathBuf,
-    io_lock: Mutex<()>,
+    /// What is on disk; the lock also serializes file I/O
+    index: Mutex<DiskIndex>,
+}
+
+/// In-memory view of the disk cache, rebuilt from the sidecars at startup
+#[derive(Default)]
+struct DiskIndex {
+    /// Tag -> keys of the entries on disk
+    tags: HashMap<String, HashSet<String>>,
+    /// Key -> size of its files and when it was last used
+    entries: HashMap<String, DiskUsage>,
+    /// Total size of the entries' files
+    bytes: u64,
+    /// Ticks on every use, ordering entries from least to most recent
+    clock: u64,
+}
+
+struct DiskUsage {
+    bytes: u64,
+    last_used: u64,
+}
+
+impl DiskIndex {
+    /// Record an entry just written
+    fn insert(&mut self, key: &str, tags: &[String], bytes: u64) {
+        for tag in tags {
+            self.tags
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.to_string());
+        }
+        self.clock += 1;
+        let usage = DiskUsage {
+            bytes,
+            last_used: self.clock,
+        };
+        if let Some(previous) = self.entries.insert(key.to_string(), usage) {
+            self.bytes -= previous.bytes;
+        }
+        self.bytes += bytes;
+    }
+
+    /// Forget an entry and its tags
+    fn remove(&mut self, key: &str, tags: &[String]) {
+        for tag in tags {
+            if let Some(keys) = self.tags.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tags.remove(tag);
+                }
+            }
+        }
+        if let Some(usage) = self.entries.remove(key) {
+            self.bytes -= usage.bytes;
+        }
+    }
+
+    /// Mark an entry as just read
+    fn touch(&mut self, key: &str) {
+        if let Some(usage) = self.entries.get_mut(key) {
+            self.clock += 1;
+            usage.last_used = self.clock;
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
 }
 
 impl DiskCacheLayer {
     fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
         let root = path.as_ref().to_path_buf();
         fs::create_dir_all(&root)?;
-        Ok(Self {
+        let layer = Self {
             root,
-            io_lock: Mutex::new(()),
-        })
+            index: Mutex::new(DiskIndex::default()),
+        };
+        layer.migrate_flat_entries()?;
+        layer.rebuild_index()?;
+        Ok(layer)
     }
 
-    fn key_path(&self, key: &str) -> PathBuf {
-        self.root
-            .join(format!("{}.bin", filesystem_safe_key(key.as_bytes())))
+    /// Path of an entry without extension
+    fn entry_stem(&self, key: &str) -> PathBuf {
+        let hash = format!("{:016x}", stable_key_hash(key));
+        self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash)
     }
 
-    fn entry_paths(&self) -> std::io::Result<Vec<PathBuf>> {
+    /// Files with `extension` in the shard directories
+    fn shard_files(&self, extension: &str) -> std::io::Result<Vec<PathBuf>> {
         let mut files = Vec::new();
-        for entry in fs::read_dir(&self.root)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                files.push(path);
+        for first in shard_dirs(&self.root)? {
+            for second in shard_dirs(&first)? {
+                for entry in fs::read_dir(&second)? {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|ext| ext == extension) {
+                        files.push(path);
+                    }
+                }
             }
         }
         Ok(files)
     }
 
-    fn read_entry(&self, path: &Path) -> Option<PersistedEntry> {
+    fn read_meta(path: &Path) -> Option<DiskEntryMeta> {
+        serde_json::from_slice(&fs::read(path).ok()?).ok()
+    }
+
+    fn read_entry(path: &Path) -> Option<(String, CachedResponse)> {
         let bytes = fs::read(path).ok()?;
-        bincode::deserialize::<PersistedEntry>(&bytes).ok()
+        CachedResponse::decode(&bytes).or_else(|| LegacyDiskEntry::decode(&bytes))
     }
 
-    fn write_entry(&self, path: &Path, entry: &PersistedEntry) -> std::io::Result<()> {
-        let bytes = bincode::serialize(entry)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
-        fs::write(path, bytes)
+    /// Write the entry and its sidecar, each through a rename so readers
+    /// never see a partial file; returns the bytes written
+    fn write_entry(&self, key: &str, entry: &CachedResponse) -> std::io::Result<u64> {
+        let stem = self.entry_stem(key);
+        if let Some(dir) = stem.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let meta = serde_json::to_vec(&DiskEntryMeta::new(key, entry)).map_err(to_io_error)?;
+        let encoded = entry.encode(key)?;
+        write_atomic(&stem.with_extension("bin"), &encoded)?;
+        write_atomic(&stem.with_extension("meta"), &meta)?;
+        Ok((encoded.len() + meta.len()) as u64)
     }
-}
 
-impl PersistentCacheLayer for DiskCacheLayer {
-    fn get(&self, key: &str) -> Option<CacheEntry> {
-        let _guard = self.io_lock.lock();
-        let path = self.key_path(key);
-        let persisted = self.read_entry(&path)?;
-        Some(CacheEntry::from_persisted(persisted))
+    /// Size of an entry's files as found on disk
+    fn entry_bytes(stem: &Path) -> u64 {
+        ["bin", "meta"]
+            .iter()
+            .filter_map(|ext| fs::metadata(stem.with_extension(ext)).ok())
+            .map(|meta| meta.len())
+            .sum()
     }
 
-    fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
-        let _guard = self.io_lock.lock();
-        let path = self.key_path(key);
-        let mut persisted = entry.to_persisted();
-        persisted.key = key.to_string();
-        self.write_entry(&path, &persisted)
+    /// Delete an entry's files; true if it existed
+    fn delete_entry(&self, stem: &Path) -> std::io::Result<bool> {
+        let existed = match fs::remove_file(stem.with_extension("bin")) {
+            Ok(()) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        match fs::remove_file(stem.with_extension("meta")) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(existed)
     }
 
-    fn remove(&self, key: &str) -> std::io::Result<()> {
-        let _guard = self.io_lock.lock();
-        let path = self.key_path(key);
-        if path.exists() {
-            fs::remove_file(path)?;
+    /// Remove an entry and its index entries; true if it existed
+    fn remove_locked(&self, index: &mut DiskIndex, key: &str) -> std::io::Result<bool> {
+        let stem = self.entry_stem(key);
+        if let Some(meta) = Self::read_meta(&stem.with_extension("meta")) {
+            index.remove(&meta.key, &meta.tags);
+        }
+        self.delete_entry(&stem)
+    }
+
+    /// Move entries from the flat `<root>/<key>.bin` layout into shards
+    fn migrate_flat_entries(&self) -> std::io::Result<()> {
+        let mut migrated = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "bin") {
+                continue;
+            }
+            if let Some((key, entry)) = Self::read_entry(&path) {
+                if !key.is_empty() && !entry.is_expired() {
+                    self.write_entry(&key, &entry)?;
+                    migrated += 1;
+                }
+            }
+            fs::remove_file(&path)?;
+        }
+        if migrated > 0 {
+            info!(
+                "Migrated {} disk cache entries to the sharded layout",
+                migrated
+            );
         }
         Ok(())
     }
 
+    /// Rebuild the index from the sidecars, dropping expired and
+    /// half-written entries on the way
+    ///
+    /// Entries count as used when they were stored, oldest first.
+    fn rebuild_index(&self) -> std::io::Result<()> {
+        let mut index = self.index.lock();
+        index.clear();
+
+        let mut found = Vec::new();
+        for meta_path in self.shard_files("meta")? {
+            let stem = meta_path.with_extension("");
+            match Self::read_meta(&meta_path) {
+                Some(meta) if stem.with_extension("bin").is_file() && !meta.is_expired() => {
+                    let bytes = Self::entry_bytes(&stem);
+                    found.push((meta.created_at, meta.key, meta.tags, bytes));
+                }
+                _ => {
+                    self.delete_entry(&stem)?;
+                }
+            }
+        }
+
+        // Entries whose sidecar never made it to disk get one now
+        for bin_path in self.shard_files("bin")? {
+            if bin_path.with_extension("meta").is_file() {
+                continue;
+            }
+            match Self::read_entry(&bin_path) {
+                Some((key, entry)) if !entry.is_expired() => {
+                    let bytes = self.write_entry(&key, &entry)?;
+                    found.push((entry.stored_at, key, entry.tags, bytes));
+                }
+                _ => fs::remove_file(&bin_path)?,
+            }
+        }
+
+        found.sort_by_key(|(created_at, ..)| *created_at);
+        for (_, key, tags, bytes) in found {
+            index.insert(&key, &tags, bytes);
+        }
+
+        debug!(
+            "Disk cache at {} has {} entries ({} bytes) and {} tags indexed",
+            self.root.display(),
+            index.entries.len(),
+            index.bytes,
+            index.tags.len()
+        );
+        Ok(())
+    }
+}
+
+impl PersistentCacheLayer for DiskCacheLayer {
+    fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut index = self.index.lock();
+        let path = self.entry_stem(key).with_extension("bin");
+        // A different key with the same hash is a miss
+        let entry = Self::read_entry(&path)
+            .filter(|(stored_key, _)| stored_key == key)
+            .map(|(_, entry)| entry)?;
+        index.touch(key);
+        Some(entry)
+    }
+
+    fn set(&self, key: &str, entry: &CachedResponse) -> std::io::Result<()> {
+        let mut index = self.index.lock();
+        self.remove_locked(&mut index, key)?;
+        let bytes = self.write_entry(key, entry)?;
+        index.insert(key, &entry.tags, bytes);
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> std::io::Result<()> {
+        let mut index = self.index.lock();
+        self.remove_locked(&mut index, key).map(|_| ())
+    }
+
     fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize> {
-        let _guard = self.io_lock.lock();
+        let mut index = self.index.lock();
+        let keys = index.tags.remove(tag).unwrap_or_default();
         let mut removed = 0;
-        for path in self.entry_paths()? {
-            if let Some(entry) = self.read_entry(&path) {
-                if entry.tags.iter().any(|current| current == tag) {
-                    fs::remove_file(path)?;
-                    removed += 1;
-                }
+        for key in keys {
+            if self.remove_locked(&mut index, &key)? {
+                removed += 1;
             }
         }
         Ok(removed)
     }
 
     fn purge_by_prefix(&self, prefix: &str) -> std::io::Result<usize> {
-        let _guard = self.io_lock.lock();
+        let mut index = self.index.lock();
         let mut removed = 0;
-        for path in self.entry_paths()? {
-            if let Some(entry) = self.read_entry(&path) {
-                if entry.key.starts_with(prefix) {
-                    fs::remove_file(path)?;
+        for meta_path in self.shard_files("meta")? {
+            if let Some(meta) = Self::read_meta(&meta_path) {
+                if meta.key.starts_with(prefix) && self.remove_locked(&mut index, &meta.key)? {
                     removed += 1;
                 }
             }
@@ -260,25 +842,97 @@ impl PersistentCacheLayer for DiskCacheLayer {
     }
 
     fn purge_all(&self) -> std::io::Result<usize> {
-        let _guard = self.io_lock.lock();
+        let mut index = self.index.lock();
         let mut removed = 0;
-        for path in self.entry_paths()? {
+        for path in self.shard_files("bin")? {
             fs::remove_file(path)?;
             removed += 1;
         }
+        for path in self.shard_files("meta")? {
+            fs::remove_file(path)?;
+        }
+        index.clear();
         Ok(removed)
     }
+
+    fn usage_bytes(&self) -> Option<u64> {
+        Some(self.index.lock().bytes)
+    }
+
+    fn evict_lru(&self, target: u64) -> std::io::Result<usize> {
+        let mut index = self.index.lock();
+        if index.bytes <= target {
+            return Ok(0);
+        }
+        let mut by_age: Vec<(u64, String)> = index
+            .entries
+            .iter()
+            .map(|(key, usage)| (usage.last_used, key.clone()))
+            .collect();
+        by_age.sort_unstable();
+
+        let mut evicted = 0;
+        for (_, key) in by_age {
+            if index.bytes <= target {
+                break;
+            }
+            if self.remove_locked(&mut index, &key)? {
+                evicted += 1;
+            }
+            // Gone from disk behind our back: stop counting it regardless
+            index.remove(&key, &[]);
+        }
+        Ok(evicted)
+    }
+}
+
+/// Two-hex-digit shard directories directly under `dir`
+fn shard_dirs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_shard = name.len() == 2
+            && name
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_hexdigit()));
+        if is_shard && entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
 }
 
+/// FNV-1a: stable across releases and platforms, unlike `DefaultHasher`
+fn stable_key_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Redis-backed L2
+///
+/// Entries expire through Redis itself (`SET ... EX`); each tag is a set of
+/// the keys carrying it. Every call runs under `cache.redis_timeout_ms`, and
+/// after a failure Redis is bypassed for [`REDIS_BACKOFF`], during which the
+/// manager behaves as if no L2 were configured.
 struct RedisCacheLayer {
     client: Client,
     pool: Mutex<Vec<Connection>>,
     max_pool_size: usize,
     namespace: String,
+    timeout: Duration,
+    down_until: Mutex<Option<Instant>>,
 }
 
 impl RedisCacheLayer {
-    fn new(redis_url: &str) -> std::io::Result<Self> {
+    fn new(redis_url: &str, timeout: Duration) -> std::io::Result<Self> {
         let client = Client::open(redis_url).map_err(to_io_error)?;
 
         Ok(Self {
@@ -286,9 +940,39 @@ impl RedisCacheLayer {
             pool: Mutex::new(Vec::new()),
             max_pool_size: 8,
             namespace: "veloserve:v1".to_string(),
+            timeout,
+            down_until: Mutex::new(None),
         })
     }
 
+    /// Open the first pooled connection so an unreachable Redis shows up at startup
+    fn connect(&self) -> std::io::Result<()> {
+        self.with_conn(|conn| redis::cmd("PING").query::<()>(conn))
+    }
+
+    fn backing_off(&self) -> bool {
+        matches!(*self.down_until.lock(), Some(until) if Instant::now() < until)
+    }
+
+    fn back_off(&self, err: &std::io::Error) {
+        let mut down_until = self.down_until.lock();
+        if down_until.is_none() {
+            warn!(
+                "Redis cache unavailable, bypassing it for {}s: {}",
+                REDIS_BACKOFF.as_secs(),
+                err
+            );
+        }
+        *down_until = Some(Instant::now() + REDIS_BACKOFF);
+    }
+
+    fn recovered(&self) {
+        let mut down_until = self.down_until.lock();
+        if down_until.take().is_some() {
+            info!("Redis cache reachable again");
+        }
+    }
+
     fn entry_key(&self, key: &str) -> String {
         format!("{}:entry:{}", self.namespace, key)
     }
@@ -305,9 +989,13 @@ impl RedisCacheLayer {
         if let Some(conn) = self.pool.lock().pop() {
             return Ok(conn);
         }
-        self.client
-            .get_connection()
-            .map_err(|err| to_io_error(format!("redis connection failed: {}", err)))
+        let connect = || -> redis::RedisResult<Connection> {
+            let conn = self.client.get_connection_with_timeout(self.timeout)?;
+            conn.set_read_timeout(Some(self.timeout))?;
+            conn.set_write_timeout(Some(self.timeout))?;
+            Ok(conn)
+        };
+        connect().map_err(|err| to_io_error(format!("redis connection failed: {}", err)))
     }
 
     fn release_connection(&self, conn: Connection) {
@@ -321,17 +1009,33 @@ impl RedisCacheLayer {
     where
         F: FnMut(&mut Connection) -> redis::RedisResult<T>,
     {
+        if self.backing_off() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "redis unavailable, backing off",
+            ));
+        }
+
         for attempt in 0..=REDIS_RETRY_ATTEMPTS {
-            let mut conn = self.acquire_connection()?;
+            let mut conn = match self.acquire_connection() {
+                Ok(conn) => conn,
+                Err(err) => {
+                    self.back_off(&err);
+                    return Err(err);
+                }
+            };
             match op(&mut conn) {
                 Ok(value) => {
                     self.release_connection(conn);
+                    self.recovered();
                     return Ok(value);
                 }
                 Err(err) => {
                     drop(conn);
                     if attempt == REDIS_RETRY_ATTEMPTS {
-                        return Err(to_io_error(err));
+                        let err = to_io_error(err);
+                        self.back_off(&err);
+                        return Err(err);
                     }
                 }
             }
@@ -343,58 +1047,10 @@ impl RedisCacheLayer {
         ))
     }
 
-    fn serialize_entry(entry: &CacheEntry) -> std::io::Result<Vec<u8>> {
-        let (compressed, data) = if entry.data.len() >= REDIS_COMPRESSION_THRESHOLD_BYTES {
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
-            encoder.write_all(&entry.data)?;
-            let compressed = encoder.finish()?;
-            if compressed.len() < entry.data.len() {
-                (true, compressed)
-            } else {
-                (false, entry.data.clone())
-            }
-        } else {
-            (false, entry.data.clone())
-        };
-
-        let persisted = RedisPersistedEntry {
-            version: REDIS_ENTRY_VERSION,
-            content_type: entry.content_type.clone(),
-            tags: entry.tags.clone(),
-            created_at_epoch_secs: entry.created_at_epoch_secs,
-            ttl_seconds: entry.ttl.as_secs(),
-            stale_after_seconds: entry.stale_after.as_secs(),
-            compressed,
-            data,
-        };
-
-        bincode::serialize(&persisted)
-            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
-    }
-
-    fn deserialize_entry(raw: &[u8]) -> Option<CacheEntry> {
-        let persisted: RedisPersistedEntry = bincode::deserialize(raw).ok()?;
-        if persisted.version != REDIS_ENTRY_VERSION {
-            return None;
-        }
-
-        let data = if persisted.compressed {
-            let mut decoder = GzDecoder::new(persisted.data.as_slice());
-            let mut out = Vec::new();
-            decoder.read_to_end(&mut out).ok()?;
-            out
-        } else {
-            persisted.data
-        };
-
-        Some(CacheEntry {
-            data,
-            content_type: persisted.content_type,
-            tags: persisted.tags,
-            created_at_epoch_secs: persisted.created_at_epoch_secs,
-            ttl: Duration::from_secs(persisted.ttl_seconds),
-            stale_after: Duration::from_secs(persisted.stale_after_seconds),
-        })
+    fn deserialize_entry(raw: &[u8]) -> Option<CachedResponse> {
+        CachedResponse::decode(raw)
+            .map(|(_, entry)| entry)
+            .or_else(|| LegacyRedisEntry::decode(raw))
     }
 
     fn remove_internal(&self, conn: &mut Connection, key: &str) -> redis::RedisResult<bool> {
@@ -420,7 +1076,7 @@ impl RedisCacheLayer {
 }
 
 impl PersistentCacheLayer for RedisCacheLayer {
-    fn get(&self, key: &str) -> Option<CacheEntry> {
+    fn get(&self, key: &str) -> Option<CachedResponse> {
         let entry_key = self.entry_key(key);
         let raw = self
             .with_conn(|conn| conn.get::<_, Option<Vec<u8>>>(&entry_key))
@@ -428,10 +1084,10 @@ impl PersistentCacheLayer for RedisCacheLayer {
         raw.and_then(|bytes| Self::deserialize_entry(&bytes))
     }
 
-    fn set(&self, key: &str, entry: &CacheEntry) -> std::io::Result<()> {
+    fn set(&self, key: &str, entry: &CachedResponse) -> std::io::Result<()> {
         let entry_key = self.entry_key(key);
         let key_index_key = self.key_index_key();
-        let payload = Self::serialize_entry(entry)?;
+        let payload = entry.encode(key)?;
         let ttl_secs = entry.ttl.as_secs().max(1);
         let tag_ttl = ttl_secs.saturating_add(REDIS_TAG_INDEX_TTL_GRACE_SECS);
 
@@ -451,18 +1107,28 @@ impl PersistentCacheLayer for RedisCacheLayer {
         self.with_conn(|conn| self.remove_internal(conn, key).map(|_| ()))
     }
 
+    /// One SMEMBERS, then the entries and the tag set go in a single DEL;
+    /// other tag sets still listing the keys are left to expire
     fn purge_by_tag(&self, tag: &str) -> std::io::Result<usize> {
         let tag_key = self.tag_key(tag);
+        let key_index_key = self.key_index_key();
         self.with_conn(|conn| {
             let keys: Vec<String> = conn.smembers(&tag_key)?;
-            let mut removed = 0usize;
-            for key in &keys {
-                if self.remove_internal(conn, key)? {
-                    removed += 1;
-                }
+            if keys.is_empty() {
+                return Ok(0);
             }
-            let _: usize = conn.del(&tag_key)?;
-            Ok(removed)
+
+            let entry_keys: Vec<String> = keys.iter().map(|key| self.entry_key(key)).collect();
+            let (deleted,): (usize,) = redis::pipe()
+                .atomic()
+                .cmd("DEL")
+                .arg(&entry_keys)
+                .arg(&tag_key)
+                .srem(&key_index_key, &keys)
+                .ignore()
+                .query(conn)?;
+            // The tag set itself was one of the deleted keys
+            Ok(deleted.saturating_sub(1))
         })
     }
 
@@ -494,31 +1160,87 @@ impl PersistentCacheLayer for RedisCacheLayer {
             Ok(removed)
         })
     }
+
+    fn available(&self) -> bool {
+        !self.backing_off()
+    }
 }
 
 /// Cache manager
 pub struct CacheManager {
-    l1_cache: DashMap<String, CacheEntry>,
-    l1_lru: Mutex<LruCache<String, ()>>,
+    l1_cache: DashMap<String, L1Entry>,
+    /// L1 keys in order; keys lead with host and path, so everything under a
+    /// path prefix is one range
+    l1_keys: Mutex<BTreeSet<String>>,
+    /// Keys in LRU order, with when each was written to L1
+    l1_lru: Mutex<LruCache<String, Instant>>,
     tag_index: DashMap<String, Vec<String>>,
     config: CacheConfig,
     stats: CacheStats,
     max_memory: u64,
+    /// `cache.disk_limit` in bytes
+    disk_limit: Option<u64>,
+    /// `cache.max_entry_size` in bytes
+    max_entry_size: Option<u64>,
     l2_cache: Option<Box<dyn PersistentCacheLayer>>,
+    /// How long an L1 copy is trusted before going back to a shared L2
+    l1_refresh_after: Option<Duration>,
+    /// Keys of stale entries being refreshed right now
+    revalidating: Arc<Mutex<HashSet<String>>>,
+    /// Keys of missing entries being generated right now, each with the
+    /// lock other misses for it wait on
+    filling: Arc<Mutex<HashMap<String, Arc<tokio::sync::RwLock<()>>>>>,
+}
+
+/// The claim on refreshing one stale entry, released when dropped
+#[derive(Debug)]
+pub struct Revalidation {
+    key: String,
+    revalidating: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for Revalidation {
+    fn drop(&mut self) {
+        self.revalidating.lock().remove(&self.key);
+    }
+}
+
+/// The claim on generating one missing entry; misses for the same key wait
+/// until it is dropped
+#[derive(Debug)]
+pub struct Fill {
+    key: String,
+    filling: Arc<Mutex<HashMap<String, Arc<tokio::sync::RwLock<()>>>>>,
+    _guard: tokio::sync::OwnedRwLockWriteGuard<()>,
+}
+
+impl Drop for Fill {
+    fn drop(&mut self) {
+        // Unlisted before the guard goes, so no new miss can find a released lock
+        self.filling.lock().remove(&self.key);
+    }
 }
 
 impl CacheManager {
     /// Create a new cache manager
     pub fn new(config: &CacheConfig) -> Self {
-        let max_memory = parse_size(&config.memory_limit);
+        // Validated configs always parse; fall back for hand-built ones
+        let max_memory = parse_size(&config.memory_limit).unwrap_or(512 * 1024 * 1024);
         let max_entries = NonZeroUsize::new(10_000).expect("non-zero LRU size");
 
         let l2_cache = if config.l2_enabled {
             match config.storage {
                 CacheStorage::Redis => {
                     if let Some(redis_url) = config.redis_url.as_deref() {
-                        match RedisCacheLayer::new(redis_url) {
-                            Ok(layer) => Some(Box::new(layer) as Box<dyn PersistentCacheLayer>),
+                        let timeout = Duration::from_millis(config.redis_timeout_ms);
+                        match RedisCacheLayer::new(redis_url, timeout) {
+                            Ok(layer) => {
+                                // Unreachable is not fatal: the layer is bypassed until Redis answers
+                                if layer.connect().is_ok() {
+                                    info!("Connected to Redis cache at {}", redis_url);
+                                }
+                                Some(Box::new(layer) as Box<dyn PersistentCacheLayer>)
+                            }
                             Err(err) => {
                                 warn!("Failed to initialize Redis cache layer: {}", err);
                                 None
@@ -546,6 +1268,10 @@ impl CacheManager {
             None
         };
 
+        // Other instances purge Redis, not our L1, so L1 copies are short-lived
+        let l1_refresh_after = (l2_cache.is_some() && config.storage == CacheStorage::Redis)
+            .then(|| Duration::from_secs(config.redis_l1_ttl));
+
         info!(
             "Initializing cache: l1_enabled={}, l2_enabled={}, storage={:?}, max_memory={}",
             config.l1_enabled,
@@ -556,12 +1282,99 @@ impl CacheManager {
 
         Self {
             l1_cache: DashMap::new(),
+            l1_keys: Mutex::new(BTreeSet::new()),
             l1_lru: Mutex::new(LruCache::new(max_entries)),
             tag_index: DashMap::new(),
             config: config.clone(),
             stats: CacheStats::default(),
             max_memory,
+            disk_limit: config
+                .disk_limit
+                .as_deref()
+                .and_then(|size| parse_size(size).ok()),
+            max_entry_size: config
+                .max_entry_size
+                .as_deref()
+                .and_then(|size| parse_size(size).ok()),
             l2_cache,
+            l1_refresh_after,
+            revalidating: Arc::default(),
+            filling: Arc::default(),
+        }
+    }
+
+    /// Start evicting from the disk cache in the background once it grows
+    /// past `cache.disk_limit`
+    pub fn start(self: &Arc<Self>) {
+        let Some(limit) = self.disk_limit else {
+            return;
+        };
+        if self
+            .l2_cache
+            .as_ref()
+            .and_then(|l2| l2.usage_bytes())
+            .is_none()
+        {
+            return;
+        }
+        info!(
+            "Disk cache limited to {} bytes, checked every {}s",
+            limit,
+            DISK_EVICTION_INTERVAL.as_secs()
+        );
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DISK_EVICTION_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let cache = cache.clone();
+                if let Err(e) =
+                    tokio::task::spawn_blocking(move || cache.enforce_disk_limit()).await
+                {
+                    warn!("Disk cache eviction failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Evict least recently used disk entries if the disk cache is over
+    /// `cache.disk_limit`, down to 80% of it; returns how many were evicted
+    pub fn enforce_disk_limit(&self) -> usize {
+        let (Some(limit), Some(l2)) = (self.disk_limit, self.l2_cache.as_deref()) else {
+            return 0;
+        };
+        if l2.usage_bytes().is_none_or(|used| used <= limit) {
+            return 0;
+        }
+
+        let started = Instant::now();
+        match l2.evict_lru(limit * 8 / 10) {
+            Ok(evicted) => {
+                self.record_l2_op(started, true);
+                self.stats
+                    .l2
+                    .evictions
+                    .fetch_add(evicted as u64, Ordering::Relaxed);
+                debug!("Evicted {} disk cache entries over the size limit", evicted);
+                evicted
+            }
+            Err(err) => {
+                self.record_l2_op(started, false);
+                warn!("Failed to evict disk cache entries: {}", err);
+                0
+            }
+        }
+    }
+
+    /// The L2 layer, unless it is currently unreachable
+    fn l2(&self) -> Option<&dyn PersistentCacheLayer> {
+        let l2 = self.l2_cache.as_deref()?;
+        if l2.available() {
+            Some(l2)
+        } else {
+            self.stats.l2.fallbacks.fetch_add(1, Ordering::Relaxed);
+            None
         }
     }
 
@@ -572,11 +1385,109 @@ impl CacheManager {
 
     /// Get an entry and its content-type from cache
     pub async fn get_with_metadata(&self, key: &str) -> Option<(Vec<u8>, String)> {
+        self.get_response(key)
+            .await
+            .map(|entry| (entry.body.to_vec(), entry.content_type().to_string()))
+    }
+
+    /// Get a complete cached response
+    pub async fn get_response(&self, key: &str) -> Option<CachedResponse> {
+        self.lookup(key, false).await
+    }
+
+    /// Like [`Self::get_response`], but stale entries inside their grace
+    /// window are returned as well, for the caller to serve while it
+    /// refreshes them (see [`CachedResponse::is_stale`])
+    pub async fn get_allow_stale(&self, key: &str) -> Option<CachedResponse> {
+        self.lookup(key, true).await
+    }
+
+    /// Claim the refresh of a stale entry; `None` while another request
+    /// already refreshes it
+    pub fn begin_revalidation(&self, key: &str) -> Option<Revalidation> {
+        let key = normalize_cache_key(key);
+        if !self.revalidating.lock().insert(key.clone()) {
+            return None;
+        }
+        Some(Revalidation {
+            key,
+            revalidating: self.revalidating.clone(),
+        })
+    }
+
+    /// Claim the generation of a missing entry, so concurrent misses for one
+    /// key run the backend once.
+    ///
+    /// `Some` makes the caller the request that fills the key. `None` means
+    /// another request was filling it and has finished (or [`FILL_WAIT`] ran
+    /// out): the caller looks again, and if the result wasn't stored,
+    /// generates the page itself without holding anyone up.
+    pub async fn begin_fill(&self, key: &str) -> Option<Fill> {
+        let key = normalize_cache_key(key);
+        let lock = {
+            let mut filling = self.filling.lock();
+            match filling.get(&key) {
+                Some(lock) => lock.clone(),
+                None => {
+                    let lock = Arc::new(tokio::sync::RwLock::new(()));
+                    let guard = lock.clone().try_write_owned().expect("a new lock is free");
+                    filling.insert(key.clone(), lock);
+                    return Some(Fill {
+                        key,
+                        filling: self.filling.clone(),
+                        _guard: guard,
+                    });
+                }
+            }
+        };
+        if tokio::time::timeout(FILL_WAIT, lock.read()).await.is_err() {
+            debug!("Gave up waiting for {} to be filled", key);
+        }
+        None
+    }
+
+    async fn lookup(&self, key: &str, allow_stale: bool) -> Option<CachedResponse> {
         if !self.config.enable {
             return None;
         }
 
         let key = normalize_cache_key(key);
+        let found = self.lookup_layers(&key, allow_stale).await;
+        if let Some(host) = key_host(&key) {
+            self.record_host_lookup(host, found.is_some());
+        }
+        found
+    }
+
+    /// The counters of `host`, or of [`OTHER_HOSTS`] once the table is full.
+    /// Hosts are never dropped from it, so a host always gets the same ones.
+    fn host_stats(&self, host: &str) -> dashmap::mapref::one::Ref<'_, String, HostStats> {
+        let hosts = &self.stats.hosts;
+        match hosts.get(host) {
+            Some(counters) => counters,
+            None => {
+                let host = if hosts.len() < MAX_STATS_HOSTS {
+                    host
+                } else {
+                    OTHER_HOSTS
+                };
+                hosts.entry(host.to_string()).or_default().downgrade()
+            }
+        }
+    }
+
+    fn record_host_lookup(&self, host: &str, hit: bool) {
+        let counters = self.host_stats(host);
+        let counter = if hit {
+            &counters.hits
+        } else {
+            &counters.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn lookup_layers(&self, key: &str, allow_stale: bool) -> Option<CachedResponse> {
+        let key = key.to_string();
 
         if self.config.l1_enabled {
             if let Some(entry) = self.l1_cache.get(&key) {
@@ -584,26 +1495,35 @@ impl CacheManager {
                     drop(entry);
                     self.remove_l1(&key).await;
                     self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
-                } else if entry.is_stale() {
+                } else if entry.is_stale() && !allow_stale {
                     drop(entry);
                     self.remove_l1(&key).await;
                     self.stats.l1.stale.fetch_add(1, Ordering::Relaxed);
                     self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
+                } else if self.l1_refresh_due(&key) {
+                    drop(entry);
+                    self.remove_l1(&key).await;
+                    self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
                 } else {
-                    {
-                        let mut lru = self.l1_lru.lock();
-                        lru.get(&key);
+                    if entry.is_stale() {
+                        self.stats.l1.stale.fetch_add(1, Ordering::Relaxed);
                     }
-                    self.stats.l1.hits.fetch_add(1, Ordering::Relaxed);
-                    debug!("L1 cache hit: {}", key);
-                    return Some((entry.data.clone(), entry.content_type.clone()));
+                    if let Some(response) = entry.to_response() {
+                        self.stats.l1.hits.fetch_add(1, Ordering::Relaxed);
+                        debug!("L1 cache hit: {}", key);
+                        return Some(response);
+                    }
+                    drop(entry);
+                    warn!("Dropping corrupt compressed L1 entry {}", key);
+                    self.remove_l1(&key).await;
+                    self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
                 }
             } else {
                 self.stats.l1.misses.fetch_add(1, Ordering::Relaxed);
             }
         }
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
             if let Some(entry) = l2.get(&key) {
                 self.record_l2_op(started, true);
@@ -614,10 +1534,12 @@ impl CacheManager {
                 }
 
                 if entry.is_stale() {
-                    let _ = l2.remove(&key);
                     self.stats.l2.stale.fetch_add(1, Ordering::Relaxed);
-                    self.stats.l2.misses.fetch_add(1, Ordering::Relaxed);
-                    return None;
+                    if !allow_stale {
+                        let _ = l2.remove(&key);
+                        self.stats.l2.misses.fetch_add(1, Ordering::Relaxed);
+                        return None;
+                    }
                 }
 
                 self.stats.l2.hits.fetch_add(1, Ordering::Relaxed);
@@ -625,9 +1547,10 @@ impl CacheManager {
 
                 if self.config.l1_enabled {
                     self.write_l1(&key, entry.clone()).await;
+                    self.index_tags(&key, &entry.tags);
                 }
 
-                return Some((entry.data, entry.content_type));
+                return Some(entry);
             }
             self.record_l2_op(started, true);
             self.stats.l2.misses.fetch_add(1, Ordering::Relaxed);
@@ -636,6 +1559,16 @@ impl CacheManager {
         None
     }
 
+    /// Look up an entry for inspection without touching hit/miss stats,
+    /// LRU order or expiry; expired and stale entries are returned as-is.
+    pub fn inspect(&self, key: &str) -> Option<CachedResponse> {
+        let key = normalize_cache_key(key);
+        if let Some(entry) = self.l1_cache.get(&key) {
+            return entry.to_response();
+        }
+        self.l2().and_then(|l2| l2.get(&key))
+    }
+
     /// Store an entry in cache using default layer policy.
     pub async fn set(&self, key: &str, data: Vec<u8>, content_type: &str, tags: Vec<String>) {
         if !self.config.enable {
@@ -668,26 +1601,84 @@ impl CacheManager {
         tags: Vec<String>,
         lifetime: CacheLifetime,
     ) {
+        self.set_response(
+            key,
+            CachedResponse::from_bytes(data, content_type).with_tags(tags),
+            lifetime,
+        )
+        .await;
+    }
+
+    /// Store a complete response with explicit ttl/stale policy.
+    pub async fn set_response(
+        &self,
+        key: &str,
+        mut entry: CachedResponse,
+        lifetime: CacheLifetime,
+    ) -> CacheStore {
         if !self.config.enable {
-            return;
+            return CacheStore::Disabled;
+        }
+        if self.too_large(&entry) {
+            debug!(
+                "Not caching {}: {} byte body is over cache.max_entry_size",
+                key,
+                entry.body.len()
+            );
+            return CacheStore::TooLarge;
         }
 
-        let key = normalize_cache_key(key);
-        let entry = CacheEntry::new(
-            data,
-            content_type.to_string(),
-            tags.clone(),
-            lifetime.ttl,
-            lifetime.stale_after,
-        );
+        entry.stored_at = now_epoch_secs();
+        entry.ttl = lifetime.ttl;
+        entry.stale_after = lifetime.stale_after;
+        self.store(&normalize_cache_key(key), entry).await;
+        CacheStore::Stored
+    }
+
+    /// Store an entry exported by another instance, keeping its original
+    /// timestamps. Returns false if caching is disabled, the entry expired
+    /// or is over `cache.max_entry_size`.
+    pub async fn restore_response(&self, key: &str, entry: CachedResponse) -> bool {
+        if !self.config.enable || entry.is_expired() || self.too_large(&entry) {
+            return false;
+        }
+
+        self.store(&normalize_cache_key(key), entry).await;
+        true
+    }
+
+    /// `cache.memory_limit` in bytes
+    pub fn memory_limit(&self) -> u64 {
+        self.max_memory
+    }
+
+    /// Unexpired L1 entries, most recently used first
+    pub fn snapshot_entries(&self) -> Vec<(String, CachedResponse)> {
+        let keys: Vec<String> = {
+            let lru = self.l1_lru.lock();
+            lru.iter().map(|(key, _)| key.clone()).collect()
+        };
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let entry = self.l1_cache.get(&key)?;
+                if entry.is_expired() {
+                    return None;
+                }
+                let entry = entry.to_response()?;
+                Some((key, entry))
+            })
+            .collect()
+    }
 
+    async fn store(&self, key: &str, entry: CachedResponse) {
         if self.config.l1_enabled {
-            self.write_l1(&key, entry.clone()).await;
+            self.write_l1(key, entry.clone()).await;
         }
 
-        if let Some(l2) = &self.l2_cache {
+        if let Some(l2) = self.l2() {
             let started = Instant::now();
-            if let Err(err) = l2.set(&key, &entry) {
+            if let Err(err) = l2.set(key, &entry) {
                 self.record_l2_op(started, false);
                 warn!("Failed to write L2 cache key {}: {}", key, err);
             } else {
@@ -696,11 +1687,12 @@ impl CacheManager {
             }
         }
 
-        self.index_tags(&key, &tags);
+        self.index_tags(key, &entry.tags);
         debug!(
-            "Cache set: {} ({} bytes, ttl={:?}, stale_after={:?})",
+            "Cache set: {} ({} bytes, status={}, ttl={:?}, stale_after={:?})",
             key,
-            entry.data.len(),
+            entry.size_bytes(),
+            entry.status,
             entry.ttl,
             entry.stale_after
         );
@@ -741,6 +1733,30 @@ impl CacheManager {
         let _ = self.purge_by_tag_count(tag).await;
     }
 
+    /// Entries carrying `tag`. Once there are `limit` of them, expired L1
+    /// entries are dropped instead of counted, so short-lived entries nobody
+    /// asks for again don't hold the count up.
+    pub async fn live_tagged(&self, tag: &str, limit: usize) -> usize {
+        let keys = match self.tag_index.get(tag) {
+            Some(keys) if keys.len() >= limit => keys.clone(),
+            Some(keys) => return keys.len(),
+            None => return 0,
+        };
+        let mut live = 0;
+        for key in keys {
+            let expired = self
+                .l1_cache
+                .get(&key)
+                .is_some_and(|entry| entry.is_expired());
+            if expired {
+                self.remove_l1(&key).await;
+            } else {
+                live += 1;
+            }
+        }
+        live
+    }
+
     /// Purge all entries with a specific tag and return affected entry count.
     pub async fn purge_by_tag_count(&self, tag: &str) -> usize {
         info!("Purging cache entries with tag: {}", tag);
@@ -781,10 +1797,11 @@ impl CacheManager {
         let prefix = normalize_cache_key(prefix);
         let mut affected = 0usize;
         let keys: Vec<String> = self
-            .l1_cache
-            .iter()
-            .filter(|entry| entry.key().starts_with(&prefix))
-            .map(|entry| entry.key().clone())
+            .l1_keys
+            .lock()
+            .range(prefix.clone()..)
+            .take_while(|key| key.starts_with(&prefix))
+            .cloned()
             .collect();
 
         for key in keys {
@@ -810,12 +1827,50 @@ impl CacheManager {
         affected
     }
 
+    /// Purge the page cached for one URL, in every site/store/variant, and
+    /// return affected entry count. Other query strings of the path are kept.
+    pub async fn purge_url(&self, host: &str, path_and_query: &str) -> usize {
+        let base_key = build_page_cache_key(host, path_and_query);
+        self.purge_by_prefix_count(&format!("{}:", base_key)).await
+            + self.remove_with_count(&base_key).await
+    }
+
+    /// Purge every page of `host` whose path (and query) starts with
+    /// `path_prefix` and return affected entry count. A prefix ending in `/`
+    /// takes the page it names along with everything below it.
+    pub async fn purge_url_prefix(&self, host: &str, path_prefix: &str) -> usize {
+        let mut affected = self
+            .purge_by_prefix_count(&build_page_cache_prefix(host, path_prefix))
+            .await;
+        if path_prefix.len() > 1 && path_prefix.ends_with('/') {
+            affected += self.purge_url(host, path_prefix).await;
+            let key = build_page_cache_key(host, path_prefix);
+            affected += self.purge_by_prefix_count(&format!("{}?", key)).await;
+        }
+        affected
+    }
+
+    /// Purge every page of `domain` and return affected entry count. Pages
+    /// are found by their `page:<host>:` keys, and by their `domain:` tag
+    /// for entries stored under other keys.
+    pub async fn purge_by_domain(&self, domain: &str) -> usize {
+        let host = normalize_host(domain);
+        info!("Purging cache entries for domain: {}", host);
+        self.purge_by_prefix_count(&format!("page:{}:", host)).await
+            + self.purge_by_tag_count(&format!("domain:{}", host)).await
+    }
+
     /// Purge all cache entries.
     pub async fn purge_all(&self) {
         info!("Purging all cache entries");
 
         self.l1_cache.clear();
+        self.l1_keys.lock().clear();
         self.tag_index.clear();
+        for counters in self.stats.hosts.iter() {
+            counters.entries.store(0, Ordering::Relaxed);
+            counters.size_bytes.store(0, Ordering::Relaxed);
+        }
 
         {
             let mut lru = self.l1_lru.lock();
@@ -823,6 +1878,13 @@ impl CacheManager {
         }
 
         self.stats.size_bytes.store(0, Ordering::Relaxed);
+        self.stats.compressed_entries.store(0, Ordering::Relaxed);
+        self.stats
+            .compressed_original_bytes
+            .store(0, Ordering::Relaxed);
+        self.stats
+            .compressed_stored_bytes
+            .store(0, Ordering::Relaxed);
         self.stats.l1.evictions.fetch_add(1, Ordering::Relaxed);
 
         if let Some(l2) = &self.l2_cache {
@@ -851,22 +1913,34 @@ impl CacheManager {
         let l1_misses = self.stats.l1.misses.load(Ordering::Relaxed);
         let l2_hits = self.stats.l2.hits.load(Ordering::Relaxed);
         let l2_misses = self.stats.l2.misses.load(Ordering::Relaxed);
+        let original_bytes = self.stats.compressed_original_bytes.load(Ordering::Relaxed);
+        let stored_bytes = self.stats.compressed_stored_bytes.load(Ordering::Relaxed);
 
         json!({
             "enabled": self.config.enable,
             "entries": self.l1_cache.len(),
             "size_bytes": self.stats.size_bytes.load(Ordering::Relaxed),
             "max_memory": self.max_memory,
+            "max_entry_size": self.max_entry_size,
+            "skipped_too_large": self.stats.skipped_too_large.load(Ordering::Relaxed),
             "l1": {
                 "enabled": self.config.l1_enabled,
                 "hits": l1_hits,
                 "misses": l1_misses,
                 "writes": self.stats.l1.writes.load(Ordering::Relaxed),
                 "evictions": self.stats.l1.evictions.load(Ordering::Relaxed),
-                "stale": self.stats.l1.stale.load(Ordering::Relaxed)
+                "stale": self.stats.l1.stale.load(Ordering::Relaxed),
+                "compression": {
+                    "enabled": self.config.compress_memory,
+                    "entries": self.stats.compressed_entries.load(Ordering::Relaxed),
+                    "original_bytes": original_bytes,
+                    "stored_bytes": stored_bytes,
+                    "ratio": compression_ratio(original_bytes, stored_bytes)
+                }
             },
             "l2": {
                 "enabled": self.l2_cache.is_some(),
+                "available": self.l2_cache.as_ref().is_some_and(|l2| l2.available()),
                 "hits": l2_hits,
                 "misses": l2_misses,
                 "writes": self.stats.l2.writes.load(Ordering::Relaxed),
@@ -874,6 +1948,8 @@ impl CacheManager {
                 "stale": self.stats.l2.stale.load(Ordering::Relaxed),
                 "errors": self.stats.l2.errors.load(Ordering::Relaxed),
                 "fallbacks": self.stats.l2.fallbacks.load(Ordering::Relaxed),
+                "disk_bytes": self.l2_cache.as_ref().and_then(|l2| l2.usage_bytes()),
+                "disk_limit": self.disk_limit,
                 "ops": self.stats.l2.ops.load(Ordering::Relaxed),
                 "latency_ms_avg": avg_latency_ms(
                     self.stats.l2.op_latency_micros.load(Ordering::Relaxed),
@@ -884,6 +1960,63 @@ impl CacheManager {
         })
     }
 
+    /// [`Self::stats`] with the per-host and per-tag breakdown, of every
+    /// host or of `host` alone
+    pub fn detailed_stats(&self, host: Option<&str>) -> serde_json::Value {
+        let mut stats = self.stats();
+        stats["hosts"] = self.host_breakdown(host);
+        stats["tags"] = self.tag_counts(host);
+        stats
+    }
+
+    /// Entries, memory and lookups per host (`page:<host>:` keys), or for
+    /// `host` alone
+    fn host_breakdown(&self, host: Option<&str>) -> serde_json::Value {
+        let host = host.map(normalize_host);
+        let hosts: BTreeMap<String, serde_json::Value> = self
+            .stats
+            .hosts
+            .iter()
+            .filter(|counters| host.as_deref().is_none_or(|host| host == counters.key()))
+            .map(|counters| {
+                let hits = counters.hits.load(Ordering::Relaxed);
+                let misses = counters.misses.load(Ordering::Relaxed);
+                let stats = json!({
+                    "entries": counters.entries.load(Ordering::Relaxed),
+                    "size_bytes": counters.size_bytes.load(Ordering::Relaxed),
+                    "hits": hits,
+                    "misses": misses,
+                    "hit_rate": hit_rate(hits, misses),
+                });
+                (counters.key().clone(), stats)
+            })
+            .collect();
+        json!(hosts)
+    }
+
+    /// Entries per tag, skipping the `path:` tag every page carries; only
+    /// pages of `host` when given
+    fn tag_counts(&self, host: Option<&str>) -> serde_json::Value {
+        let host = host.map(normalize_host);
+        let tags: BTreeMap<String, usize> = self
+            .tag_index
+            .iter()
+            .filter(|keys| !keys.key().starts_with("path:"))
+            .map(|keys| {
+                let count = match &host {
+                    Some(host) => keys
+                        .iter()
+                        .filter(|key| key_host(key) == Some(host.as_str()))
+                        .count(),
+                    None => keys.len(),
+                };
+                (keys.key().clone(), count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        json!(tags)
+    }
+
     fn record_l2_op(&self, started: Instant, ok: bool) {
         self.stats.l2.ops.fetch_add(1, Ordering::Relaxed);
         self.stats
@@ -896,19 +2029,21 @@ impl CacheManager {
         }
     }
 
+    /// Touch `key` in the LRU; true once its L1 copy should be refetched from L2
+    fn l1_refresh_due(&self, key: &str) -> bool {
+        let written_at = self.l1_lru.lock().get(key).copied();
+        match (self.l1_refresh_after, written_at) {
+            (Some(refresh_after), Some(written_at)) => written_at.elapsed() >= refresh_after,
+            _ => false,
+        }
+    }
+
     async fn remove_l1(&self, key: &str) -> bool {
         let mut removed = false;
         if let Some((_, entry)) = self.l1_cache.remove(key) {
             removed = true;
-            self.stats
-                .size_bytes
-                .fetch_sub(entry.data.len() as u64, Ordering::Relaxed);
-
-            for tag in &entry.tags {
-                if let Some(mut keys) = self.tag_index.get_mut(tag) {
-                    keys.retain(|current| current != key);
-                }
-            }
+            self.l1_keys.lock().remove(key);
+            self.forget_l1(key, &entry, &[]);
         }
 
         {
@@ -919,36 +2054,110 @@ impl CacheManager {
         removed
     }
 
-    async fn write_l1(&self, key: &str, entry: CacheEntry) {
-        let entry_size = entry.data.len() as u64;
-        if let Some(previous) = self.l1_cache.get(key) {
-            self.stats
-                .size_bytes
-                .fetch_sub(previous.data.len() as u64, Ordering::Relaxed);
-        }
+    async fn write_l1(&self, key: &str, entry: CachedResponse) {
+        let entry = if self.config.compress_memory {
+            L1Entry::compressed(entry, self.config.compress_min_size)
+        } else {
+            L1Entry::plain(entry)
+        };
+        let entry_size = entry.size_bytes();
         if self.stats.size_bytes.load(Ordering::Relaxed) + entry_size > self.max_memory {
             self.evict_lru().await;
         }
 
-        self.l1_cache.insert(key.to_string(), entry);
+        self.record_compression(&entry, true);
+        self.record_host_entry(key, &entry, true);
+        self.stats
+            .size_bytes
+            .fetch_add(entry_size, Ordering::Relaxed);
+        let tags = entry.tags.clone();
+        // Whatever the key held before goes out of the counts and off the
+        // tags the new entry doesn't carry
+        if let Some(previous) = self.l1_cache.insert(key.to_string(), entry) {
+            self.forget_l1(key, &previous, &tags);
+        }
+        self.l1_keys.lock().insert(key.to_string());
 
         {
             let mut lru = self.l1_lru.lock();
-            lru.put(key.to_string(), ());
+            lru.put(key.to_string(), Instant::now());
         }
 
+        self.stats.l1.writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take an entry that left L1 out of the stats, and `key` off its tags
+    /// except `kept_tags`; tags left without keys are dropped
+    fn forget_l1(&self, key: &str, entry: &L1Entry, kept_tags: &[String]) {
         self.stats
             .size_bytes
-            .fetch_add(entry_size, Ordering::Relaxed);
-        self.stats.l1.writes.fetch_add(1, Ordering::Relaxed);
+            .fetch_sub(entry.size_bytes(), Ordering::Relaxed);
+        self.record_compression(entry, false);
+        self.record_host_entry(key, entry, false);
+
+        for tag in entry.tags.iter().filter(|tag| !kept_tags.contains(tag)) {
+            if let Some(mut keys) = self.tag_index.get_mut(tag) {
+                keys.retain(|current| current != key);
+            }
+            self.tag_index.remove_if(tag, |_, keys| keys.is_empty());
+        }
+    }
+
+    /// True, and counted, if the body is over `cache.max_entry_size`
+    fn too_large(&self, entry: &CachedResponse) -> bool {
+        let too_large = self
+            .max_entry_size
+            .is_some_and(|max| entry.body.len() as u64 > max);
+        if too_large {
+            self.stats.skipped_too_large.fetch_add(1, Ordering::Relaxed);
+        }
+        too_large
+    }
+
+    /// Count a gzipped L1 entry in or out of the compression stats
+    fn record_compression(&self, entry: &L1Entry, added: bool) {
+        let Some(original_len) = entry.original_len else {
+            return;
+        };
+        let counters = [
+            (&self.stats.compressed_entries, 1),
+            (&self.stats.compressed_original_bytes, original_len as u64),
+            (&self.stats.compressed_stored_bytes, entry.body.len() as u64),
+        ];
+        for (counter, value) in counters {
+            if added {
+                counter.fetch_add(value, Ordering::Relaxed);
+            } else {
+                counter.fetch_sub(value, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Count an L1 entry in or out of its host's stats
+    fn record_host_entry(&self, key: &str, entry: &L1Entry, added: bool) {
+        let Some(host) = key_host(key) else {
+            return;
+        };
+        let counters = self.host_stats(host);
+        if added {
+            counters.entries.fetch_add(1, Ordering::Relaxed);
+            counters
+                .size_bytes
+                .fetch_add(entry.size_bytes(), Ordering::Relaxed);
+        } else {
+            counters.entries.fetch_sub(1, Ordering::Relaxed);
+            counters
+                .size_bytes
+                .fetch_sub(entry.size_bytes(), Ordering::Relaxed);
+        }
     }
 
     fn index_tags(&self, key: &str, tags: &[String]) {
         for tag in tags {
-            self.tag_index
-                .entry(tag.clone())
-                .or_insert_with(Vec::new)
-                .push(key.to_string());
+            let mut keys = self.tag_index.entry(tag.clone()).or_default();
+            if !keys.iter().any(|current| current == key) {
+                keys.push(key.to_string());
+            }
         }
     }
 
@@ -986,7 +2195,9 @@ pub fn normalize_cache_key(raw: &str) -> String {
     let mut key = String::with_capacity(raw.len());
 
     for ch in raw.chars() {
-        if ch.is_ascii_alphanumeric() || matches!(ch, ':' | '/' | '_' | '-' | '.') {
+        if ch.is_ascii_alphanumeric()
+            || matches!(ch, ':' | '/' | '_' | '-' | '.' | '?' | '%' | '=' | '&')
+        {
             key.push(ch);
         } else {
             key.push('_');
@@ -996,34 +2207,63 @@ pub fn normalize_cache_key(raw: &str) -> String {
     key
 }
 
-fn filesystem_safe_key(bytes: &[u8]) -> String {
-    let mut out = String::with_capacity(bytes.len() * 2);
-    for byte in bytes {
-        let ch = *byte as char;
-        if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.') {
-            out.push(ch);
+/// Build deterministic cache key for page responses.
+///
+/// The decoded path and the raw query string (after the key's only `?`) are
+/// both run through [`encode_key_part`], so distinct URLs never share a key:
+/// `/blog?page=2`, `/blog_page_2` and `/blog%3Fpage=2` are three pages.
+pub fn build_page_cache_key(host: &str, path_and_query: &str) -> String {
+    let normalized_host = normalize_cache_key(&normalize_host(host)).replace('?', "_");
+    let (path, query) = path_and_query
+        .split_once('?')
+        .unwrap_or((path_and_query, ""));
+
+    let path = percent_encoding::percent_decode_str(path)
+        .decode_utf8_lossy()
+        .to_string();
+    let path = normalize_path(&path);
+
+    let mut key = format!("page:{}:{}", normalized_host, encode_key_part(&path));
+    if !query.is_empty() {
+        key.push('?');
+        key.push_str(&encode_key_part(query));
+    }
+    key
+}
+
+/// Percent-encode all but ASCII letters, digits and `/-._=&`, leaving no
+/// `?` or `:` to be mistaken for the key's own separators
+fn encode_key_part(raw: &str) -> String {
+    let mut encoded = String::with_capacity(raw.len());
+    for byte in raw.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._=&".contains(&byte) {
+            encoded.push(char::from(byte));
         } else {
-            out.push_str(&format!("{:02x}", byte));
+            encoded.push_str(&format!("%{:02X}", byte));
         }
     }
-    out
+    encoded
 }
 
-/// Build deterministic cache key for page responses.
-pub fn build_page_cache_key(host: &str, path_and_query: &str) -> String {
-    let normalized_host = host
-        .trim()
+/// Host as it appears in page keys and `domain:` tags: lowercase, no port
+pub fn normalize_host(host: &str) -> String {
+    host.trim()
         .split(':')
         .next()
         .unwrap_or("localhost")
-        .to_ascii_lowercase();
-
-    let path = percent_encoding::percent_decode_str(path_and_query)
-        .decode_utf8_lossy()
-        .to_string();
-    let path = normalize_path(&path);
+        .to_ascii_lowercase()
+}
 
-    normalize_cache_key(&format!("page:{}:{}", normalized_host, path))
+/// Key prefix shared by every page of `host` under `path_prefix`. Unlike
+/// [`build_page_cache_key`], a trailing `/` is kept, so `/category/` doesn't
+/// reach `/category-archive`.
+pub fn build_page_cache_prefix(host: &str, path_prefix: &str) -> String {
+    let key = build_page_cache_key(host, path_prefix);
+    if path_prefix.len() > 1 && path_prefix.ends_with('/') {
+        format!("{}/", key)
+    } else {
+        key
+    }
 }
 
 /// Build scoped cache key that avoids collisions across app/site/store/variant dimensions.
@@ -1089,7 +2329,13 @@ fn now_epoch_secs() -> u64 {
 }
 
 fn to_io_error(err: impl std::fmt::Display) -> std::io::Error {
-    std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+    std::io::Error::other(err.to_string())
+}
+
+/// Host of a page cache key (`page:<host>:<path>...`)
+fn key_host(key: &str) -> Option<&str> {
+    let (host, _) = key.strip_prefix("page:")?.split_once(':')?;
+    Some(host)
 }
 
 fn hit_rate(hits: u64, misses: u64) -> f64 {
@@ -1101,6 +2347,15 @@ fn hit_rate(hits: u64, misses: u64) -> f64 {
     }
 }
 
+/// How many times smaller compressed bodies are than the originals
+fn compression_ratio(original_bytes: u64, stored_bytes: u64) -> f64 {
+    if stored_bytes == 0 {
+        1.0
+    } else {
+        original_bytes as f64 / stored_bytes as f64
+    }
+}
+
 fn avg_latency_ms(total_micros: u64, ops: u64) -> f64 {
     if ops == 0 {
         0.0
@@ -1109,32 +2364,42 @@ fn avg_latency_ms(total_micros: u64, ops: u64) -> f64 {
     }
 }
 
-/// Parse size string (e.g., "512M", "2G") to bytes
-fn parse_size(s: &str) -> u64 {
-    let s = s.trim().to_uppercase();
-
-    if let Some(num) = s.strip_suffix('G') {
-        num.parse::<u64>().unwrap_or(1) * 1024 * 1024 * 1024
-    } else if let Some(num) = s.strip_suffix('M') {
-        num.parse::<u64>().unwrap_or(512) * 1024 * 1024
-    } else if let Some(num) = s.strip_suffix('K') {
-        num.parse::<u64>().unwrap_or(1) * 1024
-    } else {
-        s.parse::<u64>().unwrap_or(512 * 1024 * 1024)
-    }
+/// Parse size string (e.g., "512M", "2G", "4KB") to bytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, unit) = match digits.as_bytes().last() {
+        Some(b'G') => (&digits[..digits.len() - 1], 1024 * 1024 * 1024),
+        Some(b'M') => (&digits[..digits.len() - 1], 1024 * 1024),
+        Some(b'K') => (&digits[..digits.len() - 1], 1024),
+        _ => (digits, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("'{}' is not a size like \"512M\" or \"4KB\"", s))
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
     use tempfile::tempdir;
 
     #[test]
     fn test_parse_size() {
-        assert_eq!(parse_size("512M"), 512 * 1024 * 1024);
-        assert_eq!(parse_size("2G"), 2 * 1024 * 1024 * 1024);
-        assert_eq!(parse_size("1024K"), 1024 * 1024);
-        assert_eq!(parse_size("1048576"), 1_048_576);
+        assert_eq!(parse_size("512M"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1024K"), Ok(1024 * 1024));
+        assert_eq!(parse_size("1048576"), Ok(1_048_576));
+        assert_eq!(parse_size("4KB"), Ok(4096));
+        assert_eq!(parse_size("8 mb"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_size("100B"), Ok(100));
+        for bad in ["", "M", "lots", "1.5G", "-1K", "4TB", "99999999999G"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
     }
 
     #[test]
@@ -1145,6 +2410,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_page_cache_key_keeps_queries_apart_from_paths() {
+        assert_eq!(
+            build_page_cache_key("example.com", "/blog?page=2&s=a_b:c"),
+            "page:example.com:/blog?page=2&s=a_b%3Ac"
+        );
+        assert_eq!(
+            build_page_cache_key("example.com", "/caf%C3%A9 menu"),
+            "page:example.com:/caf%C3%A9%20menu"
+        );
+        let keys: HashSet<String> = [
+            "/blog?page=2",
+            "/blog?page_2",
+            "/blog_page_2",
+            "/blog%3Fpage=2",
+            "/blog?page%3D2",
+            "/blog?page=2:site:x",
+            "/blog%253Fpage=2",
+        ]
+        .iter()
+        .map(|url| build_page_cache_key("example.com", url))
+        .collect();
+        assert_eq!(keys.len(), 7, "{:?}", keys);
+        // Keys that went through normalization already stay the same
+        for key in &keys {
+            assert_eq!(&normalize_cache_key(key), key);
+        }
+        assert_eq!(
+            build_page_cache_key("example.com", "/blog?"),
+            "page:example.com:/blog"
+        );
+    }
+
     #[test]
     fn test_build_page_cache_key_scoped() {
         assert_eq!(
@@ -1159,24 +2457,317 @@ mod tests {
         );
     }
 
+    fn sample_response(body: Vec<u8>) -> CachedResponse {
+        let mut entry = CachedResponse::new(
+            203,
+            vec![
+                ("Content-Type".to_string(), "text/html".to_string()),
+                ("Content-Language".to_string(), "de".to_string()),
+                ("ETag".to_string(), "\"v1\"".to_string()),
+                ("Vary".to_string(), "Accept-Encoding, Cookie".to_string()),
+                ("X-Custom".to_string(), "kept".to_string()),
+            ],
+            body,
+        )
+        .with_tags(vec!["domain:example.test".to_string()]);
+        entry.ttl = Duration::from_secs(300);
+        entry.stale_after = Duration::from_secs(120);
+        entry
+    }
+
+    #[test]
+    fn test_cached_response_roundtrip_with_compression() {
+        let entry = sample_response(vec![b'x'; 4096]);
+
+        let encoded = entry.encode("page:example.test:/").unwrap();
+        assert!(encoded.len() < 4096, "large bodies are compressed");
+        let (key, decoded) = CachedResponse::decode(&encoded).unwrap();
+
+        assert_eq!(key, "page:example.test:/");
+        assert_eq!(decoded, entry);
+        assert_eq!(decoded.validators.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(decoded.vary, vec!["accept-encoding", "cookie"]);
+        assert_eq!(RedisCacheLayer::deserialize_entry(&encoded).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_cached_response_roundtrip_binary_body() {
+        let body: Vec<u8> = (0..=255u8).cycle().take(700).collect();
+        let entry = sample_response(body.clone());
+
+        let (_, decoded) = CachedResponse::decode(&entry.encode("k").unwrap()).unwrap();
+        assert_eq!(decoded.body.as_ref(), body.as_slice());
+        assert_eq!(decoded.status, 203);
+        assert_eq!(decoded.header("content-language"), Some("de"));
+    }
+
+    #[test]
+    fn test_unknown_format_version_is_rejected() {
+        let mut encoded = sample_response(b"body".to_vec()).encode("k").unwrap();
+        encoded[ENTRY_MAGIC.len()] = ENTRY_FORMAT_VERSION + 1;
+        assert!(CachedResponse::decode(&encoded).is_none());
+    }
+
     #[test]
-    fn test_redis_payload_roundtrip_with_compression() {
-        let entry = CacheEntry::new(
-            vec![b'x'; 4096],
-            "text/html".to_string(),
-            vec!["domain:example.test".to_string()],
-            Duration::from_secs(300),
-            Duration::from_secs(120),
+    fn test_legacy_entries_are_readable() {
+        let disk = bincode::serialize(&LegacyDiskEntry {
+            key: "page:example.test:/old".to_string(),
+            data: b"old disk".to_vec(),
+            content_type: "text/html".to_string(),
+            tags: vec!["domain:example.test".to_string()],
+            created_at_epoch_secs: 42,
+            ttl_seconds: 60,
+            stale_after_seconds: 30,
+        })
+        .unwrap();
+        let (key, entry) = LegacyDiskEntry::decode(&disk).unwrap();
+        assert_eq!(key, "page:example.test:/old");
+        assert_eq!(entry.status, 200);
+        assert_eq!(entry.body.as_ref(), b"old disk");
+        assert_eq!(entry.content_type(), "text/html");
+        assert_eq!(entry.stored_at, 42);
+        assert_eq!(entry.stale_after, Duration::from_secs(30));
+
+        let redis = bincode::serialize(&LegacyRedisEntry {
+            version: LegacyRedisEntry::VERSION,
+            content_type: "text/plain".to_string(),
+            tags: vec![],
+            created_at_epoch_secs: 7,
+            ttl_seconds: 60,
+            stale_after_seconds: 60,
+            compressed: false,
+            data: b"old redis".to_vec(),
+        })
+        .unwrap();
+        let entry = RedisCacheLayer::deserialize_entry(&redis).unwrap();
+        assert_eq!(entry.body.as_ref(), b"old redis");
+        assert_eq!(entry.content_type(), "text/plain");
+    }
+
+    #[test]
+    fn test_size_accounts_for_headers() {
+        let bare = CachedResponse::from_bytes(b"body".to_vec(), "");
+        let full = sample_response(b"body".to_vec());
+        assert_eq!(bare.size_bytes(), (4 + "Content-Type".len()) as u64);
+        assert!(full.size_bytes() > bare.size_bytes() + 40);
+    }
+
+    #[tokio::test]
+    async fn test_full_response_survives_l2() {
+        let dir = tempdir().unwrap();
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.path().to_string_lossy().to_string();
+        config.l1_enabled = true;
+        config.l2_enabled = true;
+
+        let writer = CacheManager::new(&config);
+        let entry = sample_response(b"<p>hallo</p>".to_vec());
+        writer
+            .set_response(
+                "page:example.test:/de",
+                entry.clone(),
+                CacheLifetime::from_ttl(Duration::from_secs(60)),
+            )
+            .await;
+        assert_eq!(
+            writer.stats()["size_bytes"].as_u64(),
+            Some(entry.size_bytes())
+        );
+
+        let reader = CacheManager::new(&config);
+        let cached = reader.get_response("page:example.test:/de").await.unwrap();
+        assert_eq!(cached.status, 203);
+        assert_eq!(cached.headers, entry.headers);
+        assert_eq!(cached.tags, entry.tags);
+        assert_eq!(cached.ttl, Duration::from_secs(60));
+
+        // The byte-oriented API still sees body and content type
+        assert_eq!(
+            reader.get_with_metadata("page:example.test:/de").await,
+            Some((b"<p>hallo</p>".to_vec(), "text/html".to_string()))
+        );
+
+        reader.purge_by_tag("domain:example.test").await;
+        assert!(reader.inspect("page:example.test:/de").is_none());
+    }
+
+    fn disk_only_config(dir: &Path) -> CacheConfig {
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.to_string_lossy().to_string();
+        config.storage = CacheStorage::Disk;
+        config.l1_enabled = false;
+        config.l2_enabled = true;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_disk_entries_and_tags_survive_restart() {
+        let dir = tempdir().unwrap();
+        let config = disk_only_config(dir.path());
+        std::fs::create_dir(dir.path().join("images")).unwrap();
+
+        let writer = CacheManager::new(&config);
+        for (path, tags) in [
+            ("/shoes/1", vec!["category:shoes", "domain:example.com"]),
+            ("/shoes/2", vec!["category:shoes"]),
+            ("/hats/1", vec!["domain:example.com"]),
+        ] {
+            writer
+                .set(
+                    &format!("page:example.com:{}", path),
+                    path.as_bytes().to_vec(),
+                    "text/html",
+                    tags.into_iter().map(str::to_string).collect(),
+                )
+                .await;
+        }
+        drop(writer);
+
+        // Sharded by key hash, with a JSON sidecar next to each entry
+        let stem = DiskCacheLayer::new(dir.path())
+            .unwrap()
+            .entry_stem("page:example.com:/shoes/1");
+        assert_eq!(
+            stem.parent().unwrap().parent().unwrap().parent(),
+            Some(dir.path())
         );
+        let meta = DiskCacheLayer::read_meta(&stem.with_extension("meta")).unwrap();
+        assert_eq!(meta.key, "page:example.com:/shoes/1");
+        assert_eq!(meta.content_type, "text/html");
+        assert_eq!(meta.tags, vec!["category:shoes", "domain:example.com"]);
+        assert_eq!(meta.ttl_seconds, config.default_ttl);
+        assert!(meta.created_at > 0);
 
-        let encoded = RedisCacheLayer::serialize_entry(&entry).unwrap();
-        let decoded = RedisCacheLayer::deserialize_entry(&encoded).unwrap();
+        let reader = CacheManager::new(&config);
+        assert_eq!(
+            reader.get("page:example.com:/hats/1").await,
+            Some(b"/hats/1".to_vec())
+        );
+
+        // Nothing is in L1, so only the rebuilt disk index can find these
+        assert_eq!(reader.purge_by_tag_count("category:shoes").await, 2);
+        assert!(reader.get("page:example.com:/shoes/1").await.is_none());
+        assert!(reader.get("page:example.com:/shoes/2").await.is_none());
+        assert!(reader.get("page:example.com:/hats/1").await.is_some());
+
+        // /shoes/1 left the domain tag as well
+        drop(reader);
+        let reader = CacheManager::new(&config);
+        assert_eq!(reader.purge_by_tag_count("domain:example.com").await, 1);
+
+        reader.purge_all().await;
+        assert!(dir.path().join("images").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_disk_limit_evicts_least_recently_used() {
+        let dir = tempdir().unwrap();
+        let mut cache = CacheManager::new(&disk_only_config(dir.path()));
+        for n in 1..=4 {
+            cache
+                .set(
+                    &format!("page:example.com:/{}", n),
+                    vec![b'x'; 4096],
+                    "text/html",
+                    vec!["domain:example.com".to_string()],
+                )
+                .await;
+        }
+        // /1 was written first but read last
+        assert!(cache.get("page:example.com:/1").await.is_some());
+
+        let used = cache.stats()["l2"]["disk_bytes"].as_u64().unwrap();
+        assert!(used > 0);
+        assert_eq!(cache.enforce_disk_limit(), 0, "no limit set");
+        cache.disk_limit = Some(used);
+        assert_eq!(cache.enforce_disk_limit(), 0, "at the limit");
+
+        // Over the limit: down to 80% of it, oldest use first
+        cache.disk_limit = Some(used - 1);
+        assert_eq!(cache.enforce_disk_limit(), 1);
+        assert!(cache.get("page:example.com:/2").await.is_none());
+        for n in [1, 3, 4] {
+            assert!(cache
+                .get(&format!("page:example.com:/{}", n))
+                .await
+                .is_some());
+        }
+        let stats = cache.stats();
+        assert_eq!(stats["l2"]["evictions"], 1);
+        let remaining = stats["l2"]["disk_bytes"].as_u64().unwrap();
+        assert!(
+            remaining <= (used - 1) * 8 / 10,
+            "{} of {}",
+            remaining,
+            used
+        );
+
+        // The evicted entry left the tag index too
+        assert_eq!(cache.purge_by_tag_count("domain:example.com").await, 3);
+        assert_eq!(cache.stats()["l2"]["disk_bytes"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_disk_usage_survives_restart() {
+        let dir = tempdir().unwrap();
+        let config = disk_only_config(dir.path());
+        let writer = CacheManager::new(&config);
+        writer
+            .set("page:example.com:/", b"home".to_vec(), "text/html", vec![])
+            .await;
+        let used = writer.stats()["l2"]["disk_bytes"].as_u64().unwrap();
+        drop(writer);
+
+        let reader = CacheManager::new(&config);
+        assert_eq!(reader.stats()["l2"]["disk_bytes"], used);
+    }
+
+    #[tokio::test]
+    async fn test_expired_disk_entry_is_removed_on_get() {
+        let dir = tempdir().unwrap();
+        let cache = CacheManager::new(&disk_only_config(dir.path()));
 
-        assert_eq!(decoded.data, entry.data);
-        assert_eq!(decoded.content_type, entry.content_type);
-        assert_eq!(decoded.tags, entry.tags);
-        assert_eq!(decoded.ttl, entry.ttl);
-        assert_eq!(decoded.stale_after, entry.stale_after);
+        // Expired while the manager is running
+        let layer = DiskCacheLayer::new(dir.path()).unwrap();
+        let mut entry = sample_response(b"old".to_vec());
+        entry.stored_at = now_epoch_secs() - 600;
+        layer.set("page:example.test:/old", &entry).unwrap();
+        let stem = layer.entry_stem("page:example.test:/old");
+        assert!(stem.with_extension("bin").is_file());
+
+        assert!(cache.get("page:example.test:/old").await.is_none());
+        assert!(!stem.with_extension("bin").exists());
+        assert!(!stem.with_extension("meta").exists());
+
+        // Startup drops whatever expired while the server was down
+        layer.set("page:example.test:/old", &entry).unwrap();
+        drop(DiskCacheLayer::new(dir.path()).unwrap());
+        assert!(!stem.with_extension("bin").exists());
+    }
+
+    #[tokio::test]
+    async fn test_flat_disk_entries_are_migrated() {
+        let dir = tempdir().unwrap();
+        let legacy = bincode::serialize(&LegacyDiskEntry {
+            key: "page:example.test:/flat".to_string(),
+            data: b"flat".to_vec(),
+            content_type: "text/html".to_string(),
+            tags: vec!["domain:example.test".to_string()],
+            created_at_epoch_secs: now_epoch_secs(),
+            ttl_seconds: 60,
+            stale_after_seconds: 60,
+        })
+        .unwrap();
+        let flat = dir.path().join("page_3aexample.test_3a_2fflat.bin");
+        std::fs::write(&flat, legacy).unwrap();
+
+        let cache = CacheManager::new(&disk_only_config(dir.path()));
+        assert!(!flat.exists());
+        assert_eq!(
+            cache.get("page:example.test:/flat").await,
+            Some(b"flat".to_vec())
+        );
+        assert_eq!(cache.purge_by_tag_count("domain:example.test").await, 1);
     }
 
     #[tokio::test]
@@ -1209,6 +2800,156 @@ mod tests {
         assert!(stats["l2"]["writes"].as_u64().unwrap_or(0) >= 1);
     }
 
+    #[tokio::test]
+    async fn test_stats_by_host_and_tag() {
+        let mut config = CacheConfig::default();
+        config.l2_enabled = false;
+        let cache = CacheManager::new(&config);
+
+        for (key, tags) in [
+            (
+                "page:a.test:/",
+                vec!["home".to_string(), "path:/".to_string()],
+            ),
+            ("page:a.test:/post", vec!["post_1".to_string()]),
+            ("page:b.test:/", vec!["home".to_string()]),
+        ] {
+            cache.set(key, b"payload".to_vec(), "text/html", tags).await;
+        }
+        cache.get("page:a.test:/").await;
+        cache.get("page:a.test:/missing").await;
+        cache.get("page:c.test:/").await;
+
+        assert!(cache.stats().get("hosts").is_none());
+        let stats = cache.detailed_stats(None);
+        let a = &stats["hosts"]["a.test"];
+        assert_eq!(a["entries"], 2);
+        assert!(a["size_bytes"].as_u64().unwrap() > 0);
+        assert_eq!(a["hits"], 1);
+        assert_eq!(a["misses"], 1);
+        assert_eq!(a["hit_rate"], 50.0);
+        assert_eq!(stats["hosts"]["b.test"]["hits"], 0);
+        assert_eq!(stats["hosts"]["c.test"]["entries"], 0);
+        assert_eq!(stats["hosts"]["c.test"]["misses"], 1);
+        assert_eq!(stats["tags"], json!({"home": 2, "post_1": 1}));
+
+        let stats = cache.detailed_stats(Some("B.test"));
+        assert_eq!(stats["hosts"].as_object().unwrap().len(), 1);
+        assert_eq!(stats["hosts"]["b.test"]["entries"], 1);
+        assert_eq!(stats["tags"], json!({"home": 1}));
+        assert_eq!(
+            cache.detailed_stats(Some("a.test"))["tags"],
+            json!({"home": 1, "post_1": 1})
+        );
+
+        // Entries leave their host's counts with them
+        cache.purge_by_tag("post_1").await;
+        let a = &cache.detailed_stats(None)["hosts"]["a.test"];
+        assert_eq!(a["entries"], 1);
+        assert_eq!(a["hits"], 1);
+        cache.purge_all().await;
+        let stats = cache.detailed_stats(None);
+        assert_eq!(stats["hosts"]["a.test"]["entries"], 0);
+        assert_eq!(stats["hosts"]["a.test"]["size_bytes"], 0);
+        assert_eq!(stats["tags"], json!({}));
+    }
+
+    #[tokio::test]
+    async fn test_overwriting_a_key_keeps_one_entry() {
+        let mut config = CacheConfig::default();
+        config.l2_enabled = false;
+        let cache = CacheManager::new(&config);
+
+        cache
+            .set("page:a.test:/", b"payload".to_vec(), "text/html", vec![])
+            .await;
+        let single = cache.stats()["size_bytes"].as_u64().unwrap();
+        for i in 0..100 {
+            cache
+                .set(
+                    "page:a.test:/",
+                    b"payload".to_vec(),
+                    "text/html",
+                    vec![format!("v{}", i)],
+                )
+                .await;
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats["entries"], 1);
+        // Tags count towards an entry's size
+        assert_eq!(stats["size_bytes"].as_u64().unwrap(), single + 3);
+        assert_eq!(cache.l1_lru.lock().len(), 1);
+        assert!(cache.tag_index.get("v0").is_none());
+        assert_eq!(cache.tag_index.get("v99").unwrap().len(), 1);
+        assert_eq!(cache.purge_by_tag_count("v98").await, 0);
+        assert_eq!(cache.purge_by_tag_count("v99").await, 1);
+        assert_eq!(cache.stats()["size_bytes"], 0);
+        assert!(cache.tag_index.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_under_memory_pressure() {
+        let mut config = CacheConfig::default();
+        config.l2_enabled = false;
+        config.memory_limit = "4K".to_string();
+        let cache = CacheManager::new(&config);
+
+        cache
+            .set("page:a.test:/old", vec![b'x'; 500], "text/html", vec![])
+            .await;
+        cache
+            .set("page:a.test:/new", vec![b'x'; 3300], "text/html", vec![])
+            .await;
+        cache.get("page:a.test:/new").await;
+        // Growing the least recently used page forces an eviction
+        cache
+            .set("page:a.test:/old", vec![b'x'; 800], "text/html", vec![])
+            .await;
+
+        let stored: u64 = cache.l1_cache.iter().map(|entry| entry.size_bytes()).sum();
+        assert_eq!(cache.stats()["size_bytes"].as_u64().unwrap(), stored);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_l1_entries() {
+        let mut config = CacheConfig::default();
+        config.l2_enabled = false;
+        config.compress_memory = true;
+        let cache = CacheManager::new(&config);
+
+        let page = b"<p>hello</p>".repeat(400);
+        cache
+            .set("page:example.com:/", page.clone(), "text/html", vec![])
+            .await;
+        // Below the threshold bodies stay as they are
+        cache
+            .set(
+                "page:example.com:/small",
+                b"tiny".to_vec(),
+                "text/plain",
+                vec![],
+            )
+            .await;
+
+        assert_eq!(cache.get("page:example.com:/").await, Some(page.clone()));
+        assert_eq!(
+            cache.get("page:example.com:/small").await,
+            Some(b"tiny".to_vec())
+        );
+
+        let stats = cache.stats();
+        let compression = &stats["l1"]["compression"];
+        assert_eq!(compression["entries"], 1);
+        assert_eq!(compression["original_bytes"], page.len() as u64);
+        assert!(compression["ratio"].as_f64().unwrap() > 10.0);
+        // Eviction accounting sees the compressed size
+        assert!(stats["size_bytes"].as_u64().unwrap() < page.len() as u64 / 10);
+
+        cache.remove("page:example.com:/").await;
+        assert_eq!(cache.stats()["l1"]["compression"]["entries"], 0);
+    }
+
     #[tokio::test]
     async fn test_l2_fallback_promotes_to_l1() {
         let dir = tempdir().unwrap();
@@ -1270,6 +3011,152 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_concurrent_misses_fill_once() {
+        let dir = tempdir().unwrap();
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.path().to_string_lossy().to_string();
+
+        let cache = Arc::new(CacheManager::new(&config));
+        let fills = Arc::new(AtomicU64::new(0));
+        let key = "page:example.com:/cold";
+
+        let requests: Vec<_> = (0..50)
+            .map(|_| {
+                let cache = cache.clone();
+                let fills = fills.clone();
+                tokio::spawn(async move {
+                    if let Some(entry) = cache.get_response(key).await {
+                        return entry.body;
+                    }
+                    let fill = cache.begin_fill(key).await;
+                    if fill.is_none() {
+                        if let Some(entry) = cache.get_response(key).await {
+                            return entry.body;
+                        }
+                    }
+                    fills.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    cache
+                        .set_with_lifetime(
+                            key,
+                            b"fresh".to_vec(),
+                            "text/html",
+                            vec![],
+                            CacheLifetime::from_ttl(Duration::from_secs(60)),
+                        )
+                        .await;
+                    Bytes::from_static(b"fresh")
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.await.unwrap(), "fresh");
+        }
+        assert_eq!(fills.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unstored_fill_releases_every_waiter() {
+        let dir = tempdir().unwrap();
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.path().to_string_lossy().to_string();
+
+        let cache = Arc::new(CacheManager::new(&config));
+        let key = "page:example.com:/uncacheable";
+        let fill = cache.begin_fill(key).await.unwrap();
+        // Other keys are filled independently
+        assert!(cache.begin_fill("page:example.com:/other").await.is_some());
+
+        let waiters: Vec<_> = (0..5)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.begin_fill(key).await.is_none() })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(waiters.iter().all(|waiter| !waiter.is_finished()));
+
+        // Nothing was stored: all of them go ahead at once, none holding the others
+        drop(fill);
+        for waiter in waiters {
+            let released = tokio::time::timeout(Duration::from_secs(1), waiter)
+                .await
+                .unwrap();
+            assert!(released.unwrap());
+        }
+        assert!(cache.begin_fill(key).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_live_tagged_drops_expired_entries_at_the_limit() {
+        let mut config = CacheConfig::default();
+        config.l2_enabled = false;
+        let cache = CacheManager::new(&config);
+
+        for (key, ttl) in [
+            ("page:a.test:/gone-1", 0),
+            ("page:a.test:/gone-2", 0),
+            ("page:a.test:/kept", 60),
+        ] {
+            cache
+                .set_with_lifetime(
+                    key,
+                    b"missing".to_vec(),
+                    "text/html",
+                    vec!["negative".to_string()],
+                    CacheLifetime::from_ttl(Duration::from_secs(ttl)),
+                )
+                .await;
+        }
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+
+        // Under the limit nothing is looked at
+        assert_eq!(cache.live_tagged("negative", 10).await, 3);
+        assert_eq!(cache.stats()["entries"], 3);
+        assert_eq!(cache.live_tagged("negative", 3).await, 1);
+        assert_eq!(cache.stats()["entries"], 1);
+        assert_eq!(cache.tag_index.get("negative").unwrap().len(), 1);
+        assert_eq!(cache.live_tagged("other", 0).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_entry_is_served_within_grace() {
+        let dir = tempdir().unwrap();
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.path().to_string_lossy().to_string();
+
+        let cache = CacheManager::new(&config);
+        let lifetime = CacheLifetime::with_grace(Duration::from_secs(1), Duration::from_secs(30));
+        assert_eq!(lifetime.ttl, Duration::from_secs(31));
+        let key = "page:example.com:/category/shoes";
+        cache
+            .set_with_lifetime(key, b"shoes".to_vec(), "text/html", vec![], lifetime)
+            .await;
+        let fresh = cache.get_allow_stale(key).await.unwrap();
+        assert!(!fresh.is_stale());
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let stale = cache.get_allow_stale(key).await.unwrap();
+        assert!(stale.is_stale());
+        assert_eq!(stale.body, "shoes");
+        // Still there for the next caller
+        assert!(cache.get_allow_stale(key).await.is_some());
+
+        // One refresh at a time per key
+        let claim = cache.begin_revalidation(key).unwrap();
+        assert!(cache.begin_revalidation(key).is_none());
+        assert!(cache
+            .begin_revalidation("page:example.com:/category/hats")
+            .is_some());
+        drop(claim);
+        assert!(cache.begin_revalidation(key).is_some());
+
+        // Callers that don't accept stale copies still miss
+        assert!(cache.get_response(key).await.is_none());
+    }
+
     #[tokio::test]
     async fn test_layer_toggles() {
         let dir = tempdir().unwrap();
@@ -1414,4 +3301,131 @@ mod tests {
         assert!(cache.get("page:example.com:/shop").await.is_none());
         assert_eq!(cache.get("page:other.com:/").await, Some(b"other".to_vec()));
     }
+
+    #[tokio::test]
+    async fn test_purge_by_url_and_path_prefix() {
+        let dir = tempdir().unwrap();
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.path().to_string_lossy().to_string();
+        config.l1_enabled = true;
+        config.l2_enabled = true;
+
+        let cache = CacheManager::new(&config);
+        let pages = [
+            "/category/",
+            "/category/page/2",
+            "/category/?page=3",
+            "/category/shoes?color=red&size=9",
+            "/category-archive",
+            "/categoryx/shoes",
+            "/shop?category=1",
+        ];
+        let key = |path: &str| {
+            build_page_cache_key_scoped("example.com", None, None, Some("mobile"), path)
+        };
+        for path in pages {
+            cache
+                .set(&key(path), path.as_bytes().to_vec(), "text/html", vec![])
+                .await;
+        }
+        let other = build_page_cache_key_scoped("other.com", None, None, None, "/category/a");
+        cache
+            .set(&other, b"other".to_vec(), "text/html", vec![])
+            .await;
+        let cached = |path: &'static str| {
+            let cache = &cache;
+            async move { cache.get(&key(path)).await.is_some() }
+        };
+
+        // An exact URL keeps the same path with another query string
+        assert_eq!(
+            cache
+                .purge_url("example.com", "/category/shoes?color=red&size=9")
+                .await,
+            2
+        );
+        assert!(!cached("/category/shoes?color=red&size=9").await);
+        assert!(cached("/category/").await);
+
+        // A directory prefix takes the page itself, its subpages and their
+        // query strings, but not siblings that merely share the name
+        assert!(cache.purge_url_prefix("example.com", "/category/").await >= 3);
+        for path in ["/category/", "/category/page/2", "/category/?page=3"] {
+            assert!(!cached(path).await, "{}", path);
+        }
+        for path in ["/category-archive", "/categoryx/shoes", "/shop?category=1"] {
+            assert!(cached(path).await, "{}", path);
+        }
+        assert!(cache.get(&other).await.is_some());
+
+        // Without a trailing slash it is a plain prefix
+        cache.purge_url_prefix("example.com", "/category").await;
+        assert!(!cached("/category-archive").await);
+        assert!(!cached("/categoryx/shoes").await);
+        assert!(cached("/shop?category=1").await);
+    }
+
+    #[tokio::test]
+    async fn test_purge_by_domain_clears_both_layers() {
+        let dir = tempdir().unwrap();
+        let mut config = CacheConfig::default();
+        config.disk_path = dir.path().to_string_lossy().to_string();
+        config.l1_enabled = true;
+        config.l2_enabled = true;
+
+        let cache = CacheManager::new(&config);
+        let shop = build_page_cache_key_scoped("Example.com:8080", None, None, None, "/shop");
+        let home = build_page_cache_key("example.com", "/");
+        let other = build_page_cache_key("example.org", "/");
+        for key in [&shop, &home, &other] {
+            cache.set(key, b"page".to_vec(), "text/html", vec![]).await;
+        }
+        // Stored under a key of its own, found through its tag
+        cache
+            .set(
+                "fragment:header",
+                b"nav".to_vec(),
+                "text/html",
+                vec!["domain:example.com".to_string()],
+            )
+            .await;
+
+        assert!(cache.purge_by_domain("EXAMPLE.COM").await >= 3);
+        assert!(cache.get(&shop).await.is_none());
+        assert!(cache.get(&home).await.is_none());
+        assert!(cache.get("fragment:header").await.is_none());
+        assert!(cache.get(&other).await.is_some());
+
+        // Gone from disk too, not just from memory
+        let reopened = CacheManager::new(&config);
+        assert!(reopened.get(&shop).await.is_none());
+        assert!(reopened.get(&other).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_degrades_to_l1_only() {
+        let mut config = CacheConfig::default();
+        config.storage = CacheStorage::Redis;
+        config.redis_url = Some("redis://127.0.0.1:9/".to_string());
+        config.redis_timeout_ms = 50;
+        config.redis_l1_ttl = 60;
+
+        let cache = CacheManager::new(&config);
+        assert_eq!(cache.stats()["l2"]["enabled"], true);
+        assert_eq!(cache.stats()["l2"]["available"], false);
+
+        // Within the backoff Redis is not contacted at all
+        let started = Instant::now();
+        for n in 0..20 {
+            let key = format!("page:example.com:/{}", n);
+            cache.set(&key, b"page".to_vec(), "text/html", vec![]).await;
+            assert_eq!(cache.get(&key).await, Some(b"page".to_vec()));
+        }
+        assert!(cache.get("page:example.com:/missing").await.is_none());
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        let stats = cache.stats();
+        assert_eq!(stats["l2"]["writes"], 0);
+        assert!(stats["l2"]["fallbacks"].as_u64().unwrap() > 0);
+    }
 }
@@ -2,17 +2,25 @@
 //!
 //! Command-line interface tools for VeloServe management.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
-use clap::Subcommand;
-use http_body_util::{BodyExt, Full};
-use hyper::{Method, Request};
+use clap::{Subcommand, ValueEnum};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::header::{HOST, LOCATION};
+use hyper::{Method, Request, StatusCode, Uri};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use serde_json::json;
 use std::fs;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+use crate::control::{self, ControlCommand, DEFAULT_CONTROL_SOCKET};
 
 // Unix-specific imports for signal handling
 #[cfg(unix)]
@@ -36,9 +44,33 @@ pub enum CacheCommand {
         /// Purge entries with a specific tag
         #[arg(long)]
         tag: Option<String>,
+
+        /// Purge one page, e.g. https://example.com/shop?page=2 (or a path with --domain)
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Purge every page under a path, e.g. https://example.com/category/
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Internal API base URL
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        api: String,
+
+        /// Send --all and --tag purges over this control socket instead of the API
+        #[arg(long)]
+        socket: Option<PathBuf>,
     },
     /// Show cache statistics
-    Stats,
+    Stats {
+        /// The server's control socket (server.control_socket)
+        #[arg(long, default_value = DEFAULT_CONTROL_SOCKET)]
+        socket: PathBuf,
+
+        /// Only list this host in the per-host table
+        #[arg(long)]
+        domain: Option<String>,
+    },
     /// Warm up cache
     Warm {
         /// URL list file
@@ -57,12 +89,112 @@ pub enum CacheCommand {
         #[arg(long)]
         deterministic: bool,
 
+        /// Request the URLs from here instead of queueing them on the server
+        #[arg(long)]
+        fetch: bool,
+
+        /// URLs fetched at once with --fetch
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+
+        /// Host header sent with --fetch, to warm a vhost through its IP
+        #[arg(long)]
+        host_header: Option<String>,
+
         /// Internal API base URL
         #[arg(long, default_value = "http://127.0.0.1:8080")]
         api: String,
     },
 }
 
+/// Runtime server control subcommands
+#[derive(Subcommand)]
+pub enum ServerCommand {
+    /// Reject write requests (POST/PUT/PATCH/DELETE) while keeping the site readable
+    Readonly {
+        /// Turn read-only mode on or off
+        state: Toggle,
+
+        /// Only apply to this domain (default: all sites)
+        #[arg(long)]
+        vhost: Option<String>,
+
+        /// Automatically turn read-only mode off after this many seconds
+        #[arg(long)]
+        duration: Option<u64>,
+
+        /// Internal API base URL
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        api: String,
+
+        /// `server.admin_token` of the running server
+        #[arg(long)]
+        token: Option<String>,
+    },
+}
+
+/// Runtime state snapshot subcommands
+#[derive(Subcommand)]
+pub enum StateCommand {
+    /// Write a snapshot of the running server's state (stdout by default)
+    Export {
+        /// Include cached response bodies, not just the cache index
+        #[arg(long)]
+        bodies: bool,
+
+        /// Maximum snapshot size in bytes
+        #[arg(long)]
+        max_bytes: Option<usize>,
+
+        /// Write the snapshot to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Internal API base URL
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        api: String,
+
+        /// `server.admin_token` of the running server
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Export over this control socket instead of the API
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Load a snapshot into the running server (stdin by default)
+    Import {
+        /// Snapshot file to read instead of stdin
+        input: Option<String>,
+
+        /// Internal API base URL
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        api: String,
+
+        /// `server.admin_token` of the running server
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Import over this control socket instead of the API
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+}
+
+/// On/off switch argument
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+/// Web server whose configuration `config import` reads
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ImportFormat {
+    Apache,
+    Nginx,
+}
+
 /// Configuration subcommands
 #[derive(Subcommand)]
 pub enum ConfigCommand {
@@ -74,6 +206,22 @@ pub enum ConfigCommand {
     Test,
     /// Show default configuration
     ShowDefault,
+    /// Convert an Apache or Nginx configuration to VeloServe TOML
+    Import {
+        /// Format of the input file
+        format: ImportFormat,
+        /// Path to httpd.conf, nginx.conf or a site file
+        input: PathBuf,
+        /// Output file path (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Strict mode: fail on unsupported directives
+        #[arg(long)]
+        strict: bool,
+        /// Only output [[virtualhost]] blocks (for appending to existing config)
+        #[arg(long)]
+        vhosts_only: bool,
+    },
     /// Convert Apache httpd.conf to VeloServe TOML
     ConvertApache {
         /// Path to Apache httpd.conf or vhost file
@@ -89,42 +237,91 @@ pub enum ConfigCommand {
         #[arg(long)]
         vhosts_only: bool,
     },
+    /// Convert an Nginx nginx.conf or site file to VeloServe TOML
+    ImportNginx {
+        /// Path to nginx.conf or a sites-enabled file
+        input: String,
+        /// Output file path (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Strict mode: fail on unsupported directives
+        #[arg(long)]
+        strict: bool,
+        /// Only output [[virtualhost]] blocks (for appending to existing config)
+        #[arg(long)]
+        vhosts_only: bool,
+    },
 }
 
 /// Handle cache commands
 pub async fn handle_cache_command(cmd: CacheCommand) -> Result<()> {
     match cmd {
-        CacheCommand::Purge { all, domain, tag } => {
-            if all {
+        CacheCommand::Purge {
+            all,
+            domain,
+            tag,
+            url,
+            prefix,
+            api,
+            socket,
+        } => {
+            if let Some(socket) = socket {
+                let command = match (all, tag) {
+                    (true, _) => ControlCommand::CachePurgeAll,
+                    (false, Some(tag)) => ControlCommand::CachePurgeTag(tag),
+                    (false, None) => {
+                        println!("Only --all and --tag can be sent over --socket");
+                        return Ok(());
+                    }
+                };
+                let result = control::request(&socket, &command).await?;
+                println!("Purged {} cache entries.", result["purged"]);
+                return Ok(());
+            }
+
+            let query = if all {
                 println!("Purging all cache entries...");
-                // In production, this would communicate with running server
-                send_management_command("cache.purge.all")?;
-                println!("Cache purged successfully.");
+                String::new()
+            } else if let Some(url) = url {
+                println!("Purging cache for URL: {}", url);
+                purge_query(&[("url", Some(&url)), ("domain", domain.as_ref())])
+            } else if let Some(prefix) = prefix {
+                println!("Purging cache under: {}", prefix);
+                purge_query(&[("prefix", Some(&prefix)), ("domain", domain.as_ref())])
             } else if let Some(domain) = domain {
                 println!("Purging cache for domain: {}", domain);
-                send_management_command(&format!("cache.purge.domain:{}", domain))?;
-                println!("Domain cache purged successfully.");
+                purge_query(&[("domain", Some(&domain))])
             } else if let Some(tag) = tag {
                 println!("Purging cache entries with tag: {}", tag);
-                send_management_command(&format!("cache.purge.tag:{}", tag))?;
-                println!("Tagged entries purged successfully.");
+                purge_query(&[("tag", Some(&tag))])
             } else {
-                println!("Please specify --all, --domain, or --tag");
-            }
+                println!("Please specify --all, --url, --prefix, --domain, or --tag");
+                return Ok(());
+            };
+            let response = post_api_json(
+                &api,
+                &format!("/api/v1/cache/purge{}", query),
+                &json!({}),
+                None,
+            )
+            .await?;
+            println!(
+                "{}",
+                response["message"].as_str().unwrap_or("Cache purged.")
+            );
         }
-        CacheCommand::Stats => {
-            println!("Cache Statistics:");
-            println!("-----------------");
-            // In production, fetch from running server
-            println!("Entries: N/A (server not running or not connected)");
-            println!("Memory: N/A");
-            println!("Hit Rate: N/A");
+        CacheCommand::Stats { socket, domain } => {
+            let stats = control::request(&socket, &ControlCommand::CacheStats).await?;
+            print_cache_stats(&stats, domain.as_deref());
         }
         CacheCommand::Warm {
             urls,
             url,
             domain,
             deterministic,
+            fetch,
+            concurrency,
+            host_header,
             api,
         } => {
             let mut targets = url;
@@ -133,6 +330,18 @@ pub async fn handle_cache_command(cmd: CacheCommand) -> Result<()> {
                 targets.extend(read_warm_urls_from_file(&file)?);
             }
 
+            if fetch {
+                if targets.is_empty() {
+                    println!("Please provide --url or --urls to fetch");
+                    return Ok(());
+                }
+                let targets = targets
+                    .iter()
+                    .map(|target| absolute_warm_url(target, domain.as_deref()))
+                    .collect();
+                return warm_by_fetching(targets, concurrency, host_header).await;
+            }
+
             if !deterministic && targets.is_empty() {
                 println!("Please provide --url, --urls, or --deterministic");
                 return Ok(());
@@ -152,6 +361,104 @@ pub async fn handle_cache_command(cmd: CacheCommand) -> Result<()> {
     Ok(())
 }
 
+/// Handle runtime server control commands
+pub async fn handle_server_command(cmd: ServerCommand) -> Result<()> {
+    match cmd {
+        ServerCommand::Readonly {
+            state,
+            vhost,
+            duration,
+            api,
+            token,
+        } => {
+            let payload = json!({
+                "enabled": matches!(state, Toggle::On),
+                "vhost": vhost,
+                "duration_secs": duration,
+            });
+            let response =
+                post_api_json(&api, "/api/v1/readonly", &payload, token.as_deref()).await?;
+            println!("Read-only mode updated:");
+            println!("{}", serde_json::to_string_pretty(&response["readonly"])?);
+        }
+    }
+    Ok(())
+}
+
+/// Handle runtime state snapshot commands
+pub async fn handle_state_command(cmd: StateCommand) -> Result<()> {
+    match cmd {
+        StateCommand::Export {
+            bodies,
+            max_bytes,
+            output,
+            api,
+            token,
+            socket,
+        } => {
+            let snapshot = if let Some(socket) = socket {
+                let command = ControlCommand::StateExport { bodies, max_bytes };
+                let (_, snapshot) = control::transfer(&socket, &command, &[]).await?;
+                Bytes::from(snapshot)
+            } else {
+                let mut path = format!("/api/v1/state/export?bodies={}", u8::from(bodies));
+                if let Some(max_bytes) = max_bytes {
+                    path.push_str(&format!("&max_bytes={}", max_bytes));
+                }
+                api_request(
+                    &api,
+                    Method::GET,
+                    &path,
+                    "application/json",
+                    Bytes::new(),
+                    token.as_deref(),
+                )
+                .await?
+            };
+            match output {
+                Some(file) => {
+                    fs::write(&file, &snapshot)?;
+                    eprintln!("Wrote {} byte state snapshot to {}", snapshot.len(), file);
+                }
+                None => std::io::stdout().write_all(&snapshot)?,
+            }
+        }
+        StateCommand::Import {
+            input,
+            api,
+            token,
+            socket,
+        } => {
+            let snapshot = match input {
+                Some(file) => fs::read(file)?,
+                None => {
+                    let mut buf = Vec::new();
+                    std::io::stdin().read_to_end(&mut buf)?;
+                    buf
+                }
+            };
+            let report = if let Some(socket) = socket {
+                let command = ControlCommand::StateImport(snapshot.len());
+                control::transfer(&socket, &command, &snapshot).await?.0
+            } else {
+                let response = api_request(
+                    &api,
+                    Method::POST,
+                    "/api/v1/state/import",
+                    "application/octet-stream",
+                    Bytes::from(snapshot),
+                    token.as_deref(),
+                )
+                .await?;
+                serde_json::from_slice(&response)?
+            };
+            println!("State imported:");
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+    Ok(())
+}
+
 /// Handle configuration commands
 pub fn handle_config_command(config_path: &Path, cmd: ConfigCommand) -> Result<()> {
     match cmd {
@@ -248,15 +555,15 @@ listen = "0.0.0.0:8080"
 # listen_ssl = "0.0.0.0:443"
 workers = "auto"
 max_connections = 10000
-keepalive_timeout = 75
-request_timeout = 60
+keepalive_timeout = "75s"
+request_timeout = "60s"
 
 [php]
 enable = true
 version = "8.2"
 workers = 16
 memory_limit = "256M"
-max_execution_time = 30
+max_execution_time = "30s"
 
 [cache]
 enable = true
@@ -293,53 +600,126 @@ warm_batch_size = 64
 "#;
             println!("{}", default_config);
         }
+        ConfigCommand::Import {
+            format,
+            input,
+            output,
+            strict,
+            vhosts_only,
+        } => {
+            import_config(format, &input, output.as_deref(), strict, vhosts_only)?;
+        }
         ConfigCommand::ConvertApache {
             input,
             output,
             strict,
             vhosts_only,
         } => {
-            use crate::apache_compat::{ApacheConfig, ApacheToVeloServeConverter};
+            import_config(
+                ImportFormat::Apache,
+                Path::new(&input),
+                output.as_deref().map(Path::new),
+                strict,
+                vhosts_only,
+            )?;
+        }
+        ConfigCommand::ImportNginx {
+            input,
+            output,
+            strict,
+            vhosts_only,
+        } => {
+            import_config(
+                ImportFormat::Nginx,
+                Path::new(&input),
+                output.as_deref().map(Path::new),
+                strict,
+                vhosts_only,
+            )?;
+        }
+    }
+    Ok(())
+}
 
-            println!("Converting Apache configuration: {}", input);
+/// Convert an Apache or Nginx configuration, printing it or writing it to
+/// `output`, then summarize the virtual hosts and what was left out
+fn import_config(
+    format: ImportFormat,
+    input: &Path,
+    output: Option<&Path>,
+    strict: bool,
+    vhosts_only: bool,
+) -> Result<()> {
+    use crate::apache_compat::{ApacheConfig, ApacheToVeloServeConverter};
+    use crate::nginx_compat::{NginxConfig, NginxToVeloServeConverter};
 
-            // Parse Apache config
-            let apache_config = ApacheConfig::from_file(&input)
+    let (config, warnings, toml_output) = match format {
+        ImportFormat::Apache => {
+            println!("Converting Apache configuration: {}", input.display());
+            let apache_config = ApacheConfig::from_file(input)
                 .map_err(|e| anyhow!("Failed to parse Apache config: {}", e))?;
-
             println!(
                 "✓ Parsed {} virtual hosts",
                 apache_config.virtual_hosts.len()
             );
 
-            // Convert to VeloServe
             let converter = ApacheToVeloServeConverter::new().strict(strict);
+            let (config, warnings) = converter.convert_with_warnings(&apache_config);
+            let toml_output = converter
+                .render(&config, vhosts_only)
+                .map_err(|e| anyhow!("Failed to convert Apache config: {}", e))?;
+            (config, warnings, toml_output)
+        }
+        ImportFormat::Nginx => {
+            println!("Converting Nginx configuration: {}", input.display());
+            let nginx_config = NginxConfig::from_file(input)
+                .map_err(|e| anyhow!("Failed to parse Nginx config: {}", e))?;
+            println!("✓ Parsed {} server blocks", nginx_config.servers.len());
 
-            let toml_output = if vhosts_only {
-                converter.to_toml_vhosts_only(&apache_config)
-            } else {
-                converter.to_toml(&apache_config)
-            };
+            let converter = NginxToVeloServeConverter::new().strict(strict);
+            let (config, warnings) = converter.convert_with_warnings(&nginx_config);
+            let toml_output = converter
+                .render(&config, vhosts_only)
+                .map_err(|e| anyhow!("Failed to convert Nginx config: {}", e))?;
+            (config, warnings, toml_output)
+        }
+    };
 
-            // Write output
-            if let Some(output_path) = output {
-                fs::write(&output_path, &toml_output)?;
-                println!("✓ Converted configuration written to: {}", output_path);
-            } else {
-                println!("\n=== Converted Configuration ===\n");
-                println!("{}", toml_output);
-            }
+    // Nothing is written when strict mode had to leave something out
+    if strict && !warnings.is_empty() {
+        println!("\n✗ Not converted in strict mode:");
+        for warning in &warnings {
+            println!("  - {}", warning);
+        }
+        bail!(
+            "{} item(s) could not be converted; nothing was written",
+            warnings.len()
+        );
+    }
 
-            // Summary
-            println!("\n=== Summary ===");
-            println!("Virtual Hosts: {}", apache_config.virtual_hosts.len());
-            for vhost in &apache_config.virtual_hosts {
-                if let Some(domain) = vhost.server_names.first() {
-                    println!("  - {} (port {})", domain, vhost.port);
-                }
-            }
+    if let Some(output_path) = output {
+        fs::write(output_path, &toml_output)?;
+        println!(
+            "✓ Converted configuration written to: {}",
+            output_path.display()
+        );
+    } else {
+        println!("\n=== Converted Configuration ===\n");
+        println!("{}", toml_output);
+    }
+
+    println!("\n=== Summary ===");
+    println!("Converted {} virtual hosts", config.virtualhost.len());
+    for vhost in &config.virtualhost {
+        println!("  - {} ({})", vhost.domain, vhost.root);
+    }
+    if !warnings.is_empty() {
+        println!("\n⚠ {} warnings:", warnings.len());
+        for warning in &warnings {
+            println!("  - {}", warning);
         }
     }
+
     Ok(())
 }
 
@@ -373,7 +753,7 @@ pub fn stop_server() -> Result<()> {
 }
 
 /// Show server status
-pub fn show_status() -> Result<()> {
+pub async fn show_status(api: &str) -> Result<()> {
     println!("VeloServe Status");
     println!("================");
 
@@ -398,15 +778,121 @@ pub fn show_status() -> Result<()> {
         println!("Status: Not running");
     }
 
+    // Runtime details are only available from a reachable server
+    let Ok(body) = api_request(
+        api,
+        Method::GET,
+        "/api/v1/status",
+        "application/json",
+        Bytes::new(),
+        None,
+    )
+    .await
+    else {
+        return Ok(());
+    };
+    let status: serde_json::Value = serde_json::from_slice(&body)?;
+    let schedules = status["cache_schedule"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    if !schedules.is_empty() {
+        println!();
+        println!("Cache schedules:");
+        for job in schedules {
+            println!(
+                "  {:<20} {:<20} {:<28} runs={} last={}{}",
+                job["name"].as_str().unwrap_or("-"),
+                job["cron"].as_str().unwrap_or("-"),
+                job["action"].as_str().unwrap_or("-"),
+                job["runs"],
+                job["last_run"].as_str().unwrap_or("never"),
+                job["active_until"]
+                    .as_str()
+                    .map(|until| format!(" (active until {})", until))
+                    .unwrap_or_default(),
+            );
+        }
+    }
+
     Ok(())
 }
 
-/// Send a management command to the running server
-fn send_management_command(cmd: &str) -> Result<()> {
-    // In production, this would use a Unix socket or HTTP API
-    // For now, just log
-    tracing::debug!("Management command: {}", cmd);
-    Ok(())
+/// Print `CacheManager::stats` as returned over the control socket
+fn print_cache_stats(stats: &serde_json::Value, domain: Option<&str>) {
+    println!("Cache Statistics:");
+    println!("-----------------");
+    println!("Enabled: {}", stats["enabled"]);
+    println!("Entries: {}", stats["entries"]);
+    println!(
+        "Memory: {} / {} bytes",
+        stats["size_bytes"], stats["max_memory"]
+    );
+    println!(
+        "Hit Rate: {:.1}%",
+        stats["hit_rate"].as_f64().unwrap_or(0.0)
+    );
+    println!("Skipped (too large): {}", stats["skipped_too_large"]);
+    for layer in ["l1", "l2"] {
+        let layer_stats = &stats[layer];
+        if layer_stats["enabled"] == true {
+            println!(
+                "{}: {} hits, {} misses, {} writes, {} evictions",
+                layer.to_uppercase(),
+                layer_stats["hits"],
+                layer_stats["misses"],
+                layer_stats["writes"],
+                layer_stats["evictions"]
+            );
+        } else {
+            println!("{}: disabled", layer.to_uppercase());
+        }
+    }
+
+    let hosts: Vec<(&String, &serde_json::Value)> = stats["hosts"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(host, _)| domain.is_none_or(|domain| domain.eq_ignore_ascii_case(host)))
+        .collect();
+    if !hosts.is_empty() {
+        println!();
+        println!(
+            "{:<32} {:>8} {:>12} {:>10} {:>10} {:>9}",
+            "HOST", "ENTRIES", "SIZE", "HITS", "MISSES", "HIT RATE"
+        );
+        for (host, host_stats) in hosts {
+            println!(
+                "{:<32} {:>8} {:>12} {:>10} {:>10} {:>8.1}%",
+                host,
+                host_stats["entries"],
+                host_stats["size_bytes"],
+                host_stats["hits"],
+                host_stats["misses"],
+                host_stats["hit_rate"].as_f64().unwrap_or(0.0)
+            );
+        }
+    }
+
+    if let Some(tags) = stats["tags"].as_object().filter(|tags| !tags.is_empty()) {
+        println!();
+        println!("{:<42} {:>8}", "TAG", "ENTRIES");
+        for (tag, entries) in tags {
+            println!("{:<42} {:>8}", tag, entries);
+        }
+    }
+}
+
+/// `?name=value&...` for the purge API, skipping unset parameters
+fn purge_query(params: &[(&str, Option<&String>)]) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = utf8_percent_encode((*value)?, NON_ALPHANUMERIC);
+            Some(format!("{}={}", name, value))
+        })
+        .collect();
+    format!("?{}", params.join("&"))
 }
 
 /// Send a signal to the running server (Unix only)
@@ -454,35 +940,440 @@ fn read_warm_urls_from_file(file_path: &str) -> Result<Vec<String>> {
         .collect())
 }
 
+/// `http://<domain><path>` for a bare path in a warm list
+fn absolute_warm_url(target: &str, domain: Option<&str>) -> String {
+    match domain {
+        Some(domain) if target.starts_with('/') => format!("http://{}{}", domain, target),
+        _ => target.to_string(),
+    }
+}
+
+/// Outcome of one `cache warm --fetch` request
+struct WarmFetch {
+    url: String,
+    result: Result<StatusCode>,
+    elapsed: Duration,
+}
+
+/// Fetch every URL, `concurrency` at a time, and report each one
+async fn warm_by_fetching(
+    urls: Vec<String>,
+    concurrency: usize,
+    host_header: Option<String>,
+) -> Result<()> {
+    let started = Instant::now();
+    let total = urls.len();
+    let fetches = fetch_warm_urls(urls, concurrency, host_header).await;
+
+    let mut failed = 0;
+    for fetch in &fetches {
+        let millis = fetch.elapsed.as_millis();
+        match &fetch.result {
+            Ok(status) if status.is_success() => {
+                println!("  {}  {:>5}ms  {}", status.as_u16(), millis, fetch.url)
+            }
+            Ok(status) => {
+                failed += 1;
+                println!("  {}  {:>5}ms  {}", status.as_u16(), millis, fetch.url);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("  ERR  {:>5}ms  {}: {}", millis, fetch.url, e);
+            }
+        }
+    }
+
+    println!(
+        "Warmed {} of {} URLs in {:.1}s",
+        total - failed,
+        total,
+        started.elapsed().as_secs_f64()
+    );
+    if failed > 0 {
+        return Err(anyhow!("{} of {} URLs failed", failed, total));
+    }
+    Ok(())
+}
+
+/// GET each URL, following redirects, in list order
+async fn fetch_warm_urls(
+    urls: Vec<String>,
+    concurrency: usize,
+    host_header: Option<String>,
+) -> Vec<WarmFetch> {
+    let client: Client<_, Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+
+    let handles: Vec<_> = urls
+        .into_iter()
+        .map(|url| {
+            let client = client.clone();
+            let permits = permits.clone();
+            let host_header = host_header.clone();
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let started = Instant::now();
+                let result = fetch_following_redirects(
+                    &client,
+                    &url,
+                    host_header.as_deref(),
+                    WARM_REQUEST_TIMEOUT,
+                )
+                .await;
+                WarmFetch {
+                    url,
+                    result,
+                    elapsed: started.elapsed(),
+                }
+            })
+        })
+        .collect();
+
+    let mut fetches = Vec::with_capacity(handles.len());
+    for handle in handles {
+        if let Ok(fetch) = handle.await {
+            fetches.push(fetch);
+        }
+    }
+    fetches
+}
+
+/// Most redirects followed for one warm URL
+const WARM_MAX_REDIRECTS: usize = 5;
+
+/// How long each request of a warm URL, body included, may take
+const WARM_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Status of the last response after following up to `WARM_MAX_REDIRECTS`
+///
+/// A redirect to the host named by `host_header` stays on the address the
+/// URL was given with, so warming through an IP keeps going to that IP.
+/// Redirects elsewhere, or to HTTPS, are reported rather than followed.
+async fn fetch_following_redirects(
+    client: &Client<HttpConnector, Empty<Bytes>>,
+    url: &str,
+    host_header: Option<&str>,
+    timeout: Duration,
+) -> Result<StatusCode> {
+    let mut uri: Uri = url.parse()?;
+    for _ in 0..=WARM_MAX_REDIRECTS {
+        let mut request = Request::builder().method(Method::GET).uri(uri.clone());
+        if let Some(host) = host_header {
+            request = request.header(HOST, host);
+        }
+        let request = request.body(Empty::new())?;
+        let (status, location) = tokio::time::timeout(timeout, async {
+            let response = client.request(request).await?;
+            let status = response.status();
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            // Read the body so the server finishes (and caches) the response
+            response.into_body().collect().await?;
+            Ok::<_, anyhow::Error>((status, location))
+        })
+        .await
+        .map_err(|_| anyhow!("{} timed out after {}s", uri, timeout.as_secs_f64()))??;
+
+        match location {
+            Some(location) if status.is_redirection() => {
+                uri = redirect_target(&uri, &location, host_header)?;
+            }
+            _ => return Ok(status),
+        }
+    }
+    Err(anyhow!("more than {} redirects", WARM_MAX_REDIRECTS))
+}
+
+/// Where a `Location` header sends a request for `current`, as long as it
+/// stays on plain HTTP to the same site: `current`'s address or the host
+/// named by `host_header`
+fn redirect_target(current: &Uri, location: &str, host_header: Option<&str>) -> Result<Uri> {
+    let path = if location.contains("://") || location.starts_with("//") {
+        let target: Uri = match location.strip_prefix("//") {
+            Some(rest) => format!("{}://{}", current.scheme_str().unwrap_or("http"), rest),
+            None => location.to_string(),
+        }
+        .parse()?;
+        if target.scheme_str() != Some("http") {
+            bail!("redirects to {}, which is not followed", location);
+        }
+        let authority = target.authority().map(|authority| authority.as_str());
+        let same_site = authority.is_some_and(|authority| {
+            host_header.is_some_and(|host| host.eq_ignore_ascii_case(authority))
+                || current
+                    .authority()
+                    .is_some_and(|current| current.as_str().eq_ignore_ascii_case(authority))
+        });
+        if !same_site {
+            bail!("redirects off-site to {}, which is not followed", location);
+        }
+        target
+            .path_and_query()
+            .map_or("/", |path| path.as_str())
+            .to_string()
+    } else if location.starts_with('/') {
+        location.to_string()
+    } else {
+        let base = current.path();
+        format!(
+            "{}{}",
+            &base[..base.rfind('/').map_or(0, |i| i + 1)],
+            location
+        )
+    };
+
+    let mut parts = current.clone().into_parts();
+    parts.path_and_query = Some(path.parse()?);
+    Ok(Uri::from_parts(parts)?)
+}
+
 async fn trigger_cache_warm_api(
     api_base: &str,
     urls: &[String],
     domain: Option<&str>,
     strategy: Option<&str>,
 ) -> Result<serde_json::Value> {
-    let endpoint = format!("{}/api/v1/cache/warm", api_base.trim_end_matches('/'));
     let payload = json!({
         "urls": urls,
         "domain": domain,
         "trigger": "manual",
         "strategy": strategy
     });
+    post_api_json(api_base, "/api/v1/cache/warm", &payload, None).await
+}
+
+async fn post_api_json(
+    api_base: &str,
+    path: &str,
+    payload: &serde_json::Value,
+    token: Option<&str>,
+) -> Result<serde_json::Value> {
+    let bytes = api_request(
+        api_base,
+        Method::POST,
+        path,
+        "application/json",
+        Bytes::from(payload.to_string()),
+        token,
+    )
+    .await?;
+    let parsed = serde_json::from_slice(&bytes)?;
+    Ok(parsed)
+}
+
+async fn api_request(
+    api_base: &str,
+    method: Method,
+    path: &str,
+    content_type: &str,
+    body: Bytes,
+    token: Option<&str>,
+) -> Result<Bytes> {
+    let endpoint = format!("{}{}", api_base.trim_end_matches('/'), path);
 
     let connector = HttpConnector::new();
     let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
-    let request = Request::builder()
-        .method(Method::POST)
+    let mut request = Request::builder()
+        .method(method)
         .uri(endpoint)
-        .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(payload.to_string())))?;
+        .header("Content-Type", content_type);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    let request = request.body(Full::new(body))?;
     let response = client.request(request).await?;
     let status = response.status();
     let bytes = response.into_body().collect().await?.to_bytes();
     if !status.is_success() {
         let text = String::from_utf8_lossy(&bytes);
-        return Err(anyhow!("warm API request failed ({}): {}", status, text));
+        return Err(anyhow!(
+            "{} API request failed ({}): {}",
+            path,
+            status,
+            text
+        ));
     }
 
-    let parsed = serde_json::from_slice(&bytes)?;
-    Ok(parsed)
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_import_apache_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("site.conf");
+        fs::write(
+            &input,
+            r#"
+<VirtualHost *:80>
+    ServerName example.com
+    ServerAlias www.example.com
+    DocumentRoot /var/www/example
+    DirectoryIndex index.html
+    RewriteEngine On
+    <Directory /var/www/example>
+        AllowOverride All
+    </Directory>
+</VirtualHost>
+"#,
+        )
+        .unwrap();
+        let output = dir.path().join("veloserve.toml");
+
+        handle_config_command(
+            Path::new("unused.toml"),
+            ConfigCommand::Import {
+                format: ImportFormat::Apache,
+                input: input.clone(),
+                output: Some(output.clone()),
+                strict: false,
+                vhosts_only: false,
+            },
+        )
+        .unwrap();
+
+        let config = Config::load(&output).unwrap();
+        assert_eq!(config.virtualhost.len(), 1);
+        assert_eq!(config.virtualhost[0].domain, "example.com");
+        assert_eq!(config.virtualhost[0].root, "/var/www/example");
+        assert_eq!(config.virtualhost[0].index, ["index.html"]);
+
+        // Strict mode refuses the RewriteEngine and <Directory> it can't
+        // convert, failing without touching the output
+        let strict = handle_config_command(
+            Path::new("unused.toml"),
+            ConfigCommand::Import {
+                format: ImportFormat::Apache,
+                input,
+                output: Some(output.clone()),
+                strict: true,
+                vhosts_only: true,
+            },
+        );
+        assert!(strict.is_err());
+        assert_eq!(Config::load(&output).unwrap().virtualhost.len(), 1);
+    }
+
+    /// An origin recording each request's Host and path; `/old` redirects
+    async fn stub_origin() -> (std::net::SocketAddr, Arc<parking_lot::Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let seen = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let log = log.clone();
+                let service =
+                    hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let host = req.headers()[HOST].to_str().unwrap_or_default().to_string();
+                        log.lock().push(format!("{}{}", host, req.uri().path()));
+                        let response = match req.uri().path() {
+                            "/old" => hyper::Response::builder()
+                                .status(StatusCode::MOVED_PERMANENTLY)
+                                .header(LOCATION, "http://example.test/new"),
+                            _ => hyper::Response::builder(),
+                        };
+                        async move { response.body(Full::new(Bytes::from_static(b"ok"))) }
+                    });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+                );
+            }
+        });
+        (addr, seen)
+    }
+
+    #[tokio::test]
+    async fn test_warm_fetches_every_url() {
+        let (addr, seen) = stub_origin().await;
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("urls.txt");
+        fs::write(&list, "# landing pages\n/\n/a\n/b\n\n/c\n/old\n").unwrap();
+
+        handle_cache_command(CacheCommand::Warm {
+            urls: Some(list.to_string_lossy().to_string()),
+            url: Vec::new(),
+            domain: Some(addr.to_string()),
+            deterministic: false,
+            fetch: true,
+            concurrency: 2,
+            host_header: Some("example.test".to_string()),
+            api: "http://127.0.0.1:1".to_string(),
+        })
+        .await
+        .unwrap();
+
+        let mut seen = seen.lock().clone();
+        seen.sort();
+        assert_eq!(
+            seen,
+            [
+                "example.test/",
+                "example.test/a",
+                "example.test/b",
+                "example.test/c",
+                "example.test/new",
+                "example.test/old"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        let current: Uri = "http://10.0.0.1/shop/list".parse().unwrap();
+        let target = |location| {
+            redirect_target(&current, location, Some("example.test"))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(target("/cart"), "http://10.0.0.1/cart");
+        assert_eq!(target("page2?x=1"), "http://10.0.0.1/shop/page2?x=1");
+        assert_eq!(target("http://example.test/new"), "http://10.0.0.1/new");
+        assert_eq!(target("http://10.0.0.1/next"), "http://10.0.0.1/next");
+        assert_eq!(target("//example.test/proto"), "http://10.0.0.1/proto");
+
+        // Leaving the site, or HTTP, is reported instead of followed, so the
+        // Host header never goes to another server
+        let error = |location| {
+            redirect_target(&current, location, Some("example.test"))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("http://cdn.test/a").contains("off-site"));
+        assert!(error("//cdn.test/a").contains("off-site"));
+        assert!(error("https://example.test/secure").contains("https://example.test/secure"));
+    }
+
+    #[tokio::test]
+    async fn test_warm_request_times_out() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let client: Client<_, Empty<Bytes>> =
+            Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let result = fetch_following_redirects(
+            &client,
+            &format!("http://{}/slow", addr),
+            None,
+            Duration::from_millis(200),
+        )
+        .await;
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+    }
 }
@@ -4,6 +4,7 @@
 
 use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,7 +18,7 @@ pub enum ConfigError {
 }
 
 /// Main configuration structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     /// Server settings
     #[serde(default)]
@@ -31,6 +32,10 @@ pub struct Config {
     #[serde(default)]
     pub cache: CacheConfig,
 
+    /// Static file settings
+    #[serde(default, rename = "static")]
+    pub static_files: StaticConfig,
+
     /// SSL/TLS settings
     #[serde(default)]
     pub ssl: Option<SslConfig>,
@@ -38,18 +43,18 @@ pub struct Config {
     /// Virtual hosts
     #[serde(default)]
     pub virtualhost: Vec<VirtualHostConfig>,
-}
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            server: ServerConfig::default(),
-            php: PhpConfig::default(),
-            cache: CacheConfig::default(),
-            ssl: None,
-            virtualhost: vec![],
-        }
-    }
+    /// Logging settings
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    /// Rate limiter settings
+    #[serde(default)]
+    pub limits: LimitsConfig,
+
+    /// Content types by file extension, merged over the built-in table
+    #[serde(default)]
+    pub mime_types: std::collections::HashMap<String, String>,
 }
 
 impl Config {
@@ -62,6 +67,7 @@ impl Config {
     }
 
     /// Load configuration from a string
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(contents: &str) -> Result<Self, ConfigError> {
         let config: Config = toml::from_str(contents)?;
         config.validate()?;
@@ -71,18 +77,207 @@ impl Config {
     /// Validate the configuration
     pub fn validate(&self) -> Result<(), ConfigError> {
         // Validate server settings
+        let mut listen = Vec::new();
+        for address in self.server.listen.addresses() {
+            let address = crate::server::ListenAddr::parse(address)
+                .map_err(|e| ConfigError::ValidationError(format!("server.listen: {}", e)))?;
+            if listen.contains(&address) {
+                return Err(ConfigError::ValidationError(format!(
+                    "server.listen: {} is listed twice",
+                    address
+                )));
+            }
+            listen.push(address);
+        }
+        if listen.is_empty() {
+            return Err(ConfigError::ValidationError(
+                "server.listen needs at least one address".to_string(),
+            ));
+        }
+        crate::server::parse_socket_mode(&self.server.socket_mode)
+            .map_err(|e| ConfigError::ValidationError(format!("server.socket_mode: {}", e)))?;
+
         if self.server.max_connections == 0 {
             return Err(ConfigError::ValidationError(
                 "max_connections must be greater than 0".to_string(),
             ));
         }
 
+        for (name, value) in [
+            ("server.max_uri_length", self.server.max_uri_length),
+            ("server.max_header_bytes", self.server.max_header_bytes),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::ValidationError(format!(
+                    "{} must be greater than 0",
+                    name
+                )));
+            }
+        }
+
+        if self
+            .server
+            .admin_token
+            .as_deref()
+            .is_some_and(|token| token.trim().len() < 16)
+        {
+            return Err(ConfigError::ValidationError(
+                "server.admin_token must be at least 16 characters".to_string(),
+            ));
+        }
+
+        for (name, value) in [
+            ("server.keepalive_timeout", self.server.keepalive_timeout),
+            ("server.request_timeout", self.server.request_timeout),
+        ] {
+            check_duration(name, value, MAX_TIMEOUT)?;
+        }
+        if let Some(api_timeout) = self.server.api_timeout {
+            check_duration("server.api_timeout", api_timeout, MAX_TIMEOUT)?;
+        }
+
+        // Validate per-vhost and per-location timeouts
+        for vhost in &self.virtualhost {
+            for (status, page) in &vhost.error_pages {
+                if !(400..=599).contains(status) {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' error_pages: {} is not an error status",
+                        vhost.domain, status
+                    )));
+                }
+                if !page.starts_with('/') {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' error_pages: '{}' must start with '/'",
+                        vhost.domain, page
+                    )));
+                }
+            }
+            for pattern in &vhost.deny_files {
+                if pattern.is_empty() || (pattern.contains('/') && !pattern.starts_with('/')) {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' deny_files: '{}' must be a file name glob or start with '/'",
+                        vhost.domain, pattern
+                    )));
+                }
+            }
+            if let Some(root) = &vhost.sendfile_root {
+                if !Path::new(root).is_absolute() {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' sendfile_root: '{}' must be an absolute path",
+                        vhost.domain, root
+                    )));
+                }
+            }
+            if let Some(fallback) = &vhost.spa_fallback {
+                if !fallback.starts_with('/')
+                    || fallback.ends_with('/')
+                    || fallback.split('/').any(|segment| segment == "..")
+                {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' spa_fallback: '{}' must be a file path starting with '/'",
+                        vhost.domain, fallback
+                    )));
+                }
+            }
+            for extension in &vhost.force_download {
+                let bare = extension.trim().trim_start_matches('.');
+                if bare.is_empty() || bare.contains(['/', '*', '?']) {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' force_download: '{}' must be a file extension",
+                        vhost.domain, extension
+                    )));
+                }
+            }
+            if let Some(cache) = &vhost.cache {
+                let invalid = cache.vary.iter().find(|entry| match entry.split_once(':') {
+                    Some((header, cookie)) => {
+                        !header.eq_ignore_ascii_case("cookie") || !is_token(cookie.trim())
+                    }
+                    None => !is_token(entry.trim()),
+                });
+                if let Some(entry) = invalid {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' cache.vary: '{}' must be a header name or Cookie:<name>",
+                        vhost.domain, entry
+                    )));
+                }
+                let invalid = cache
+                    .bypass_cookies
+                    .iter()
+                    .flatten()
+                    .find(|pattern| !is_token(&pattern.trim().replace(['*', '?'], "x")));
+                if let Some(pattern) = invalid {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' cache.bypass_cookies: '{}' is not a cookie name pattern",
+                        vhost.domain, pattern
+                    )));
+                }
+            }
+            if let Err(e) = crate::server::CacheControlRules::new(&vhost.static_cache_control) {
+                return Err(ConfigError::ValidationError(format!(
+                    "virtualhost '{}' static_cache_control: {}",
+                    vhost.domain, e
+                )));
+            }
+            if let Err(e) = crate::server::Rewrites::new(&vhost.rewrites) {
+                return Err(ConfigError::ValidationError(format!(
+                    "virtualhost '{}' rewrite: {}",
+                    vhost.domain, e
+                )));
+            }
+            if let Err(e) = crate::server::TryFiles::new(&vhost.try_files) {
+                return Err(ConfigError::ValidationError(format!(
+                    "virtualhost '{}' try_files: {}",
+                    vhost.domain, e
+                )));
+            }
+            if let Some(timeout) = vhost.request_timeout {
+                let name = format!("virtualhost '{}' request_timeout", vhost.domain);
+                check_duration(&name, timeout, MAX_TIMEOUT)?;
+            }
+            for location in &vhost.locations {
+                if !location.path.starts_with('/') {
+                    return Err(ConfigError::ValidationError(format!(
+                        "virtualhost '{}' location '{}': path must start with '/'",
+                        vhost.domain, location.path
+                    )));
+                }
+                if let Some(timeout) = location.request_timeout {
+                    let name = format!(
+                        "virtualhost '{}' location '{}' request_timeout",
+                        vhost.domain, location.path
+                    );
+                    check_duration(&name, timeout, MAX_TIMEOUT)?;
+                }
+                if let Some(timeout) = location.streaming_timeout {
+                    let name = format!(
+                        "virtualhost '{}' location '{}' streaming_timeout",
+                        vhost.domain, location.path
+                    );
+                    check_duration(&name, timeout, MAX_EXECUTION_TIME)?;
+                }
+                if let Some(proxy_pass) = &location.proxy_pass {
+                    crate::server::Upstream::parse(proxy_pass).map_err(|e| {
+                        ConfigError::ValidationError(format!(
+                            "virtualhost '{}' location '{}' proxy_pass: {}",
+                            vhost.domain, location.path, e
+                        ))
+                    })?;
+                }
+            }
+        }
+
         // Validate PHP settings
         if self.php.workers == 0 {
             return Err(ConfigError::ValidationError(
                 "php.workers must be greater than 0".to_string(),
             ));
         }
+        check_duration(
+            "php.max_execution_time",
+            self.php.max_execution_time,
+            MAX_EXECUTION_TIME,
+        )?;
 
         // Validate SSL settings if enabled
         if let Some(ref ssl) = self.ssl {
@@ -93,6 +288,120 @@ impl Config {
             }
         }
 
+        // Shared limiters count in the cache's Redis
+        if self.limits.invalidation == LimiterMode::Shared && self.cache.redis_url.is_none() {
+            return Err(ConfigError::ValidationError(
+                "limits.invalidation = \"shared\" requires cache.redis_url".to_string(),
+            ));
+        }
+        if self.limits.redis_timeout_ms == 0 {
+            return Err(ConfigError::ValidationError(
+                "limits.redis_timeout_ms must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.cache.storage == CacheStorage::Redis && self.cache.redis_timeout_ms == 0 {
+            return Err(ConfigError::ValidationError(
+                "cache.redis_timeout_ms must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate cache schedules
+        for (index, schedule) in self.cache.schedule.iter().enumerate() {
+            crate::server::ScheduledJob::compile(index, schedule).map_err(|e| {
+                ConfigError::ValidationError(format!("cache.schedule[{}]: {}", index, e))
+            })?;
+        }
+
+        // Validate image optimization settings
+        for format in &self.static_files.image_formats {
+            if !matches!(format.as_str(), "avif" | "webp") {
+                return Err(ConfigError::ValidationError(format!(
+                    "static.image_formats: unsupported format '{}' (expected avif or webp)",
+                    format
+                )));
+            }
+        }
+        if !(1..=100).contains(&self.static_files.image_quality) {
+            return Err(ConfigError::ValidationError(
+                "static.image_quality must be between 1 and 100".to_string(),
+            ));
+        }
+        if self.static_files.image_max_concurrency == 0 {
+            return Err(ConfigError::ValidationError(
+                "static.image_max_concurrency must be greater than 0".to_string(),
+            ));
+        }
+        let charset = &self.static_files.default_charset;
+        if !charset.is_empty() && !charset.bytes().all(is_token_byte) {
+            return Err(ConfigError::ValidationError(format!(
+                "static.default_charset: '{}' is not a charset name",
+                charset
+            )));
+        }
+
+        // Validate MIME type overrides
+        for (extension, content_type) in &self.mime_types {
+            let name = extension.strip_prefix('.').unwrap_or(extension);
+            if name.is_empty() || !name.bytes().all(is_token_byte) || name.contains('.') {
+                return Err(ConfigError::ValidationError(format!(
+                    "mime_types: '{}' is not a file extension",
+                    extension
+                )));
+            }
+            if !is_content_type(content_type) {
+                return Err(ConfigError::ValidationError(format!(
+                    "mime_types.{}: '{}' is not a valid content type",
+                    name, content_type
+                )));
+            }
+        }
+        if self.static_files.stream_chunk_size == 0 {
+            return Err(ConfigError::ValidationError(
+                "static.stream_chunk_size must be greater than 0".to_string(),
+            ));
+        }
+        if !(1..=9).contains(&self.server.compression.level) {
+            return Err(ConfigError::ValidationError(
+                "server.compression.level must be between 1 and 9".to_string(),
+            ));
+        }
+        for media in &self.server.compression.types {
+            if !media.contains('/') && !media.starts_with('*') {
+                return Err(ConfigError::ValidationError(format!(
+                    "server.compression.types: '{}' is not a media type",
+                    media
+                )));
+            }
+        }
+
+        crate::server::SecurityHeaders::new(&self.server.headers)
+            .map_err(|e| ConfigError::ValidationError(format!("server.headers: {}", e)))?;
+
+        crate::server::TrustedProxies::new(&self.server.trusted_proxies)
+            .map_err(|e| ConfigError::ValidationError(format!("server.trusted_proxies: {}", e)))?;
+
+        crate::server::TrustedProxies::new(&self.server.metrics_allow)
+            .map_err(|e| ConfigError::ValidationError(format!("server.metrics_allow: {}", e)))?;
+        crate::server::TrustedProxies::new(&self.cache.purge_allow)
+            .map_err(|e| ConfigError::ValidationError(format!("cache.purge_allow: {}", e)))?;
+
+        let sizes = [
+            ("memory_limit", Some(&self.cache.memory_limit)),
+            ("disk_limit", self.cache.disk_limit.as_ref()),
+            ("max_entry_size", self.cache.max_entry_size.as_ref()),
+        ];
+        for (name, size) in sizes {
+            if let Some(size) = size {
+                crate::cache::parse_size(size)
+                    .map_err(|e| ConfigError::ValidationError(format!("cache.{}: {}", name, e)))?;
+            }
+        }
+
+        // Validate the access log format
+        crate::server::LogFormat::compile(&self.logging)
+            .map_err(|e| ConfigError::ValidationError(format!("logging.format: {}", e)))?;
+
         Ok(())
     }
 
@@ -105,12 +414,47 @@ impl Config {
     }
 }
 
+/// `server.listen`: one address or a list of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Listen {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Listen {
+    /// Every configured address, in order
+    pub fn addresses(&self) -> &[String] {
+        match self {
+            Self::One(address) => std::slice::from_ref(address),
+            Self::Many(addresses) => addresses,
+        }
+    }
+}
+
+impl From<&str> for Listen {
+    fn from(address: &str) -> Self {
+        Self::One(address.to_string())
+    }
+}
+
+impl std::fmt::Display for Listen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.addresses().join(", "))
+    }
+}
+
 /// Server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
-    /// HTTP listen address
+    /// HTTP listen address, or `unix:<path>` for a Unix domain socket;
+    /// a list binds each of them
     #[serde(default = "default_listen")]
-    pub listen: String,
+    pub listen: Listen,
+
+    /// Permissions of a `unix:` listen socket (octal)
+    #[serde(default = "default_socket_mode")]
+    pub socket_mode: String,
 
     /// HTTPS listen address
     #[serde(default)]
@@ -124,35 +468,299 @@ pub struct ServerConfig {
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
 
-    /// Keep-alive timeout in seconds
-    #[serde(default = "default_keepalive_timeout")]
-    pub keepalive_timeout: u64,
-
-    /// Request timeout in seconds
-    #[serde(default = "default_request_timeout")]
-    pub request_timeout: u64,
+    /// Keep-alive timeout ("75s"; a bare integer is seconds)
+    #[serde(default = "default_keepalive_timeout", with = "duration_compat")]
+    pub keepalive_timeout: Duration,
+
+    /// Request timeout ("60s"; a bare integer is seconds)
+    #[serde(default = "default_request_timeout", with = "duration_compat")]
+    pub request_timeout: Duration,
+
+    /// Timeout for `/api/v1/*` and health endpoints (defaults to request_timeout)
+    #[serde(
+        default,
+        with = "duration_compat::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub api_timeout: Option<Duration>,
+
+    /// Log requests slower than this together with the timeout that applied
+    #[serde(
+        default,
+        with = "duration_compat::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub slow_request_threshold: Option<Duration>,
 
     /// Maximum request body size
     #[serde(default = "default_max_body_size")]
     pub max_body_size: String,
+
+    /// Longest request target (path and query) answered normally; longer
+    /// ones get `414 URI Too Long`
+    #[serde(default = "default_max_uri_length")]
+    pub max_uri_length: usize,
+
+    /// Largest header block (names, values and separators) answered
+    /// normally; larger ones get `431 Request Header Fields Too Large`
+    #[serde(default = "default_max_header_bytes")]
+    pub max_header_bytes: usize,
+
+    /// Hand large static files to the kernel with sendfile(2) on plain
+    /// HTTP/1.1 connections (Linux only)
+    #[serde(default)]
+    pub sendfile: bool,
+
+    /// Accept HTTP/2 with prior knowledge on the plain HTTP listeners. The
+    /// HTTP/1.1 `Upgrade: h2c` path is not offered
+    #[serde(default, alias = "h2c")]
+    pub h2c_prior_knowledge: bool,
+
+    /// Answer plain-HTTP requests with a 301 to the same URI over HTTPS,
+    /// except ACME HTTP-01 challenges
+    #[serde(default)]
+    pub redirect_to_https: bool,
+
+    /// User to switch to after binding listeners (e.g. "www-data")
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Group to switch to (defaults to the user's primary group)
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// Allow serving requests as root: `user` resolving to root, or no
+    /// `user` when started as root
+    #[serde(default)]
+    pub allow_root: bool,
+
+    /// Body of the 503 returned for writes while read-only mode is active
+    #[serde(default = "default_readonly_message")]
+    pub readonly_message: String,
+
+    /// Paths that still accept writes in read-only mode (e.g. "/wp-login.php")
+    #[serde(default)]
+    pub readonly_allow: Vec<String>,
+
+    /// On-the-fly response compression (`[server.compression]`)
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// How static file ETags are derived
+    #[serde(default)]
+    pub etag: EtagMode,
+
+    /// Proxies (addresses or CIDR networks) whose `X-Forwarded-For` is believed
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+
+    /// Clients (addresses or CIDR networks) that `/metrics` answers, besides
+    /// the server's own addresses; for anyone else the path is the vhost's
+    #[serde(default = "default_metrics_allow")]
+    pub metrics_allow: Vec<String>,
+
+    /// Access log file, in `logging.format` (default: the tracing log)
+    #[serde(default)]
+    pub access_log: Option<String>,
+
+    /// Unix socket the CLI's management commands are answered on
+    #[serde(default)]
+    pub control_socket: Option<String>,
+
+    /// Bearer token for the API calls that change server state (read-only
+    /// mode, state import); they are refused without it and off loopback
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    /// Security headers added to every response (`[server.headers]`)
+    #[serde(default)]
+    pub headers: HeadersConfig,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             listen: default_listen(),
+            socket_mode: default_socket_mode(),
             listen_ssl: None,
             workers: default_workers(),
             max_connections: default_max_connections(),
             keepalive_timeout: default_keepalive_timeout(),
             request_timeout: default_request_timeout(),
+            api_timeout: None,
+            slow_request_threshold: None,
             max_body_size: default_max_body_size(),
+            max_uri_length: default_max_uri_length(),
+            max_header_bytes: default_max_header_bytes(),
+            sendfile: false,
+            h2c_prior_knowledge: false,
+            redirect_to_https: false,
+            user: None,
+            group: None,
+            allow_root: false,
+            readonly_message: default_readonly_message(),
+            readonly_allow: Vec::new(),
+            compression: CompressionConfig::default(),
+            etag: EtagMode::default(),
+            trusted_proxies: Vec::new(),
+            metrics_allow: default_metrics_allow(),
+            access_log: None,
+            control_socket: None,
+            admin_token: None,
+            headers: HeadersConfig::default(),
         }
     }
 }
 
-fn default_listen() -> String {
-    "0.0.0.0:8080".to_string()
+/// Static file ETag flavour
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EtagMode {
+    /// Hash of the file contents, stable across deploys that only touch mtimes
+    Strong,
+    /// Path, size and mtime hash marked `W/`
+    Weak,
+    /// Path, size and mtime hash (the historical default)
+    #[default]
+    Mtime,
+}
+
+/// Response compression configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Compress text-like responses the client accepts encoded
+    #[serde(default = "default_true")]
+    pub enable: bool,
+
+    /// Encodings to offer, in order of preference
+    #[serde(default = "default_compression_algorithms")]
+    pub algorithms: Vec<CompressionAlgorithm>,
+
+    /// Bodies smaller than this many bytes are sent as-is
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: usize,
+
+    /// Compression level, 1 (fastest) to 9 (smallest)
+    #[serde(default = "default_compression_level")]
+    pub level: u32,
+
+    /// Media types to compress; `*` matches any run of characters
+    #[serde(default = "default_compression_types")]
+    pub types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            algorithms: default_compression_algorithms(),
+            min_size: default_compression_min_size(),
+            level: default_compression_level(),
+            types: default_compression_types(),
+        }
+    }
+}
+
+/// Content coding used for on-the-fly compression
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    #[serde(rename = "br")]
+    Brotli,
+    #[serde(rename = "gzip")]
+    Gzip,
+}
+
+fn default_compression_algorithms() -> Vec<CompressionAlgorithm> {
+    vec![CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip]
+}
+
+fn default_compression_min_size() -> usize {
+    1024
+}
+
+fn default_compression_level() -> u32 {
+    6
+}
+
+fn default_compression_types() -> Vec<String> {
+    [
+        "text/*",
+        "*+json",
+        "*+xml",
+        "application/json",
+        "application/javascript",
+        "application/x-javascript",
+        "application/xml",
+        "application/wasm",
+        "image/svg+xml",
+        "image/x-icon",
+        "image/vnd.microsoft.icon",
+        "font/ttf",
+        "font/otf",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Response headers set on every response (`[server.headers]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeadersConfig {
+    /// `Strict-Transport-Security`, sent on HTTPS responses only
+    #[serde(default)]
+    pub hsts: Option<HstsConfig>,
+
+    /// `X-Frame-Options` (e.g. "SAMEORIGIN")
+    #[serde(default)]
+    pub x_frame_options: Option<String>,
+
+    /// `Referrer-Policy` (e.g. "strict-origin-when-cross-origin")
+    #[serde(default)]
+    pub referrer_policy: Option<String>,
+
+    /// `Content-Security-Policy`
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+
+    /// Any other headers, by name
+    #[serde(default)]
+    pub custom: std::collections::BTreeMap<String, String>,
+
+    /// Replace these headers when the response (e.g. a PHP script) already
+    /// set them; by default the response's own value is kept
+    #[serde(default, rename = "override")]
+    pub override_existing: bool,
+}
+
+/// `Strict-Transport-Security` settings (`[server.headers.hsts]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HstsConfig {
+    /// Seconds browsers keep to HTTPS
+    #[serde(default = "default_hsts_max_age")]
+    pub max_age: u64,
+
+    /// Cover every subdomain as well
+    #[serde(default, alias = "includeSubDomains")]
+    pub include_subdomains: bool,
+
+    /// Ask to be included in browsers' preload lists
+    #[serde(default)]
+    pub preload: bool,
+}
+
+fn default_hsts_max_age() -> u64 {
+    31_536_000
+}
+
+fn default_readonly_message() -> String {
+    "This site is temporarily read-only. Please try again later.".to_string()
+}
+
+fn default_listen() -> Listen {
+    Listen::One("0.0.0.0:8080".to_string())
+}
+
+fn default_socket_mode() -> String {
+    "0660".to_string()
 }
 
 fn default_workers() -> String {
@@ -163,18 +771,26 @@ fn default_max_connections() -> usize {
     10000
 }
 
-fn default_keepalive_timeout() -> u64 {
-    75
+fn default_keepalive_timeout() -> Duration {
+    Duration::from_secs(75)
 }
 
-fn default_request_timeout() -> u64 {
-    60
+fn default_request_timeout() -> Duration {
+    Duration::from_secs(60)
 }
 
 fn default_max_body_size() -> String {
     "100M".to_string()
 }
 
+fn default_max_uri_length() -> usize {
+    8 * 1024
+}
+
+fn default_max_header_bytes() -> usize {
+    32 * 1024
+}
+
 /// PHP configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PhpConfig {
@@ -198,9 +814,21 @@ pub struct PhpConfig {
     #[serde(default = "default_memory_limit")]
     pub memory_limit: String,
 
-    /// Maximum execution time in seconds
-    #[serde(default = "default_max_execution_time")]
-    pub max_execution_time: u64,
+    /// Maximum execution time ("30s"; a bare integer is seconds)
+    #[serde(default = "default_max_execution_time", with = "duration_compat")]
+    pub max_execution_time: Duration,
+
+    /// Retries for transient PHP spawn failures (EAGAIN/ENOMEM)
+    #[serde(default = "default_spawn_retries")]
+    pub spawn_retries: u32,
+
+    /// Base backoff between spawn retries in milliseconds (doubled and jittered per attempt)
+    #[serde(default = "default_spawn_retry_backoff_ms")]
+    pub spawn_retry_backoff_ms: u64,
+
+    /// How long a PHP request waits for the pool to finish starting before getting a 503, in milliseconds
+    #[serde(default = "default_startup_grace_ms")]
+    pub startup_grace_ms: u64,
 
     /// Path to PHP binary (auto-discovers EA-PHP if not set)
     #[serde(default)]
@@ -236,6 +864,9 @@ impl Default for PhpConfig {
             workers: default_php_workers(),
             memory_limit: default_memory_limit(),
             max_execution_time: default_max_execution_time(),
+            spawn_retries: default_spawn_retries(),
+            spawn_retry_backoff_ms: default_spawn_retry_backoff_ms(),
+            startup_grace_ms: default_startup_grace_ms(),
             binary_path: None,
             socket_path: default_socket_path(),
             error_log: None,
@@ -282,8 +913,20 @@ fn default_memory_limit() -> String {
     "256M".to_string()
 }
 
-fn default_max_execution_time() -> u64 {
-    30
+fn default_max_execution_time() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_spawn_retries() -> u32 {
+    3
+}
+
+fn default_spawn_retry_backoff_ms() -> u64 {
+    50
+}
+
+fn default_startup_grace_ms() -> u64 {
+    2000
 }
 
 fn default_true() -> bool {
@@ -317,14 +960,41 @@ pub struct CacheConfig {
     #[serde(default = "default_cache_ttl")]
     pub default_ttl: u64,
 
+    /// Seconds an expired page is still served while one background request
+    /// refreshes it (stale-while-revalidate; 0 disables)
+    #[serde(default, alias = "stale_while_revalidate")]
+    pub stale_grace: u64,
+
+    /// Seconds a 404 page is cached, for requests without cookies
+    /// (0 disables)
+    #[serde(default)]
+    pub negative_ttl: u64,
+
     /// Redis URL (if using Redis backend)
     #[serde(default)]
     pub redis_url: Option<String>,
 
+    /// Connect/read timeout for cache calls to Redis in milliseconds
+    #[serde(default = "default_cache_redis_timeout_ms")]
+    pub redis_timeout_ms: u64,
+
+    /// Seconds an entry is served from L1 before Redis is asked again
+    #[serde(default = "default_cache_redis_l1_ttl")]
+    pub redis_l1_ttl: u64,
+
     /// Disk cache path
     #[serde(default = "default_cache_path")]
     pub disk_path: String,
 
+    /// Largest size of the disk cache ("10G"); unlimited when unset
+    #[serde(default)]
+    pub disk_limit: Option<String>,
+
+    /// Largest body stored in the page cache ("8M"); bigger responses are
+    /// served without being cached. Unlimited when unset
+    #[serde(default)]
+    pub max_entry_size: Option<String>,
+
     /// Enable cache warmer queue/worker.
     #[serde(default = "default_true")]
     pub warm_enabled: bool,
@@ -360,6 +1030,37 @@ pub struct CacheConfig {
     /// Maximum deterministic targets queued per run.
     #[serde(default = "default_warm_batch_size")]
     pub warm_batch_size: usize,
+
+    /// Scheduled purges and TTL override windows
+    #[serde(default)]
+    pub schedule: Vec<CacheScheduleConfig>,
+
+    /// Clients (addresses or CIDR networks) allowed to send `PURGE`
+    /// requests, besides the server's own addresses
+    #[serde(default = "default_purge_allow")]
+    pub purge_allow: Vec<String>,
+
+    /// Send `X-Cache`, `X-Cache-TTL` and `Age` so clients can see how the
+    /// page cache answered
+    #[serde(default = "default_true")]
+    pub debug_headers: bool,
+
+    /// Gzip bodies held in the L1 memory cache, so more pages fit in
+    /// `memory_limit`
+    #[serde(default)]
+    pub compress_memory: bool,
+
+    /// Smallest body in bytes gzipped by `compress_memory`
+    #[serde(default = "default_cache_compress_min_size")]
+    pub compress_min_size: usize,
+
+    /// Keep the memory cache across restarts in a snapshot under `disk_path`
+    #[serde(default)]
+    pub persist: bool,
+
+    /// Seconds between snapshots while running; 0 only writes one at shutdown
+    #[serde(default = "default_cache_persist_interval")]
+    pub persist_interval: u64,
 }
 
 impl Default for CacheConfig {
@@ -371,8 +1072,14 @@ impl Default for CacheConfig {
             storage: CacheStorage::Memory,
             memory_limit: default_cache_memory_limit(),
             default_ttl: default_cache_ttl(),
+            stale_grace: 0,
+            negative_ttl: 0,
             redis_url: None,
+            redis_timeout_ms: default_cache_redis_timeout_ms(),
+            redis_l1_ttl: default_cache_redis_l1_ttl(),
             disk_path: default_cache_path(),
+            disk_limit: None,
+            max_entry_size: None,
             warm_enabled: true,
             warm_schedule_secs: 0,
             warm_max_queue_size: default_warm_max_queue_size(),
@@ -382,10 +1089,25 @@ impl Default for CacheConfig {
             warm_retry_backoff_ms: default_warm_retry_backoff_ms(),
             warm_dedupe_window_secs: default_warm_dedupe_window_secs(),
             warm_batch_size: default_warm_batch_size(),
+            schedule: Vec::new(),
+            purge_allow: default_purge_allow(),
+            debug_headers: true,
+            compress_memory: false,
+            compress_min_size: default_cache_compress_min_size(),
+            persist: false,
+            persist_interval: default_cache_persist_interval(),
         }
     }
 }
 
+fn default_metrics_allow() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "::1".to_string()]
+}
+
+fn default_purge_allow() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "::1".to_string()]
+}
+
 fn default_cache_storage() -> CacheStorage {
     CacheStorage::Memory
 }
@@ -398,6 +1120,22 @@ fn default_cache_ttl() -> u64 {
     3600
 }
 
+fn default_cache_compress_min_size() -> usize {
+    1024
+}
+
+fn default_cache_persist_interval() -> u64 {
+    300
+}
+
+fn default_cache_redis_timeout_ms() -> u64 {
+    100
+}
+
+fn default_cache_redis_l1_ttl() -> u64 {
+    5
+}
+
 fn default_cache_path() -> String {
     "/var/cache/veloserve".to_string()
 }
@@ -430,6 +1168,109 @@ fn default_warm_batch_size() -> usize {
     64
 }
 
+/// One `[[cache.schedule]]` entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheScheduleConfig {
+    /// Name shown in status and logs (defaults to `schedule-<n>`)
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Cron expression (`sec min hour dom month dow`, or five fields without seconds)
+    pub cron: String,
+
+    /// `purge_all`, `purge_tag:<tag>` or `set_ttl:<path>:<secs>`
+    pub action: String,
+
+    /// How long a `set_ttl` override stays active after each run, in seconds
+    #[serde(default)]
+    pub duration: u64,
+}
+
+/// Static file configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticConfig {
+    /// Serve WebP/AVIF variants of JPEG/PNG images to clients that accept them
+    /// (requires the `image-optimize` build feature)
+    #[serde(default)]
+    pub image_optimize: bool,
+
+    /// Smallest source image (bytes) worth converting
+    #[serde(default = "default_image_min_size")]
+    pub image_min_size: u64,
+
+    /// Variant formats in order of preference
+    #[serde(default = "default_image_formats")]
+    pub image_formats: Vec<String>,
+
+    /// Encoder quality (1-100)
+    #[serde(default = "default_image_quality")]
+    pub image_quality: u8,
+
+    /// Maximum concurrent conversions
+    #[serde(default = "default_image_max_concurrency")]
+    pub image_max_concurrency: usize,
+
+    /// Responses larger than this many bytes are streamed from the file
+    #[serde(default = "default_stream_threshold")]
+    pub stream_threshold: u64,
+
+    /// Bytes read from the file per streamed chunk
+    #[serde(default = "default_stream_chunk_size")]
+    pub stream_chunk_size: usize,
+
+    /// Serve `<file>.br` / `<file>.gz` siblings to clients that accept them
+    #[serde(default)]
+    pub precompressed: bool,
+
+    /// Charset appended to text content types; empty to leave it off
+    #[serde(default = "default_charset")]
+    pub default_charset: String,
+}
+
+impl Default for StaticConfig {
+    fn default() -> Self {
+        Self {
+            image_optimize: false,
+            image_min_size: default_image_min_size(),
+            image_formats: default_image_formats(),
+            image_quality: default_image_quality(),
+            image_max_concurrency: default_image_max_concurrency(),
+            stream_threshold: default_stream_threshold(),
+            stream_chunk_size: default_stream_chunk_size(),
+            precompressed: false,
+            default_charset: default_charset(),
+        }
+    }
+}
+
+fn default_image_min_size() -> u64 {
+    10 * 1024
+}
+
+fn default_image_formats() -> Vec<String> {
+    vec!["avif".to_string(), "webp".to_string()]
+}
+
+fn default_image_quality() -> u8 {
+    75
+}
+
+fn default_image_max_concurrency() -> usize {
+    2
+}
+
+fn default_stream_threshold() -> u64 {
+    1024 * 1024
+}
+
+fn default_stream_chunk_size() -> usize {
+    64 * 1024
+}
+
+fn default_charset() -> String {
+    "utf-8".to_string()
+}
+
 /// Cache storage backend
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -452,7 +1293,7 @@ pub struct SslConfig {
     #[serde(default = "default_protocols")]
     pub protocols: Vec<String>,
 
-    /// Enable OCSP stapling
+    /// Staple OCSP responses from each certificate's responder
     #[serde(default)]
     pub ocsp_stapling: bool,
 }
@@ -461,6 +1302,67 @@ fn default_protocols() -> Vec<String> {
     vec!["TLSv1.2".to_string(), "TLSv1.3".to_string()]
 }
 
+/// Rate limiter configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Where the cache invalidation API rate limit is counted
+    #[serde(default)]
+    pub invalidation: LimiterMode,
+
+    /// Connect/read timeout for shared limiter calls to Redis in milliseconds
+    #[serde(default = "default_limits_redis_timeout_ms")]
+    pub redis_timeout_ms: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            invalidation: LimiterMode::default(),
+            redis_timeout_ms: default_limits_redis_timeout_ms(),
+        }
+    }
+}
+
+fn default_limits_redis_timeout_ms() -> u64 {
+    50
+}
+
+/// Where a rate limiter keeps its counters
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LimiterMode {
+    /// Per-instance, in memory
+    #[default]
+    Local,
+    /// In Redis (`cache.redis_url`), shared by every instance
+    Shared,
+}
+
+/// Logging configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Access log format: "combined", "common", "json" or a `$variable` template
+    #[serde(default = "default_log_format")]
+    pub format: String,
+
+    /// Field name -> `$variable` map used by the "json" format
+    #[serde(default)]
+    pub json_fields: std::collections::BTreeMap<String, String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: default_log_format(),
+            json_fields: std::collections::BTreeMap::new(),
+        }
+    }
+}
+
+fn default_log_format() -> String {
+    "combined".to_string()
+}
+
 /// Virtual host configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VirtualHostConfig {
@@ -490,9 +1392,172 @@ pub struct VirtualHostConfig {
     #[serde(default = "default_index_files")]
     pub index: Vec<String>,
 
-    /// Error pages
-    #[serde(default)]
+    /// Error pages by status, e.g. `{ 404 = "/errors/404.html" }`
+    #[serde(default, with = "error_pages_compat")]
     pub error_pages: std::collections::HashMap<u16, String>,
+
+    /// Request timeout for this site (overrides server.request_timeout)
+    #[serde(
+        default,
+        with = "duration_compat::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub request_timeout: Option<Duration>,
+
+    /// Per-path overrides (`[[virtualhost.location]]`)
+    #[serde(default, rename = "location", skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<LocationConfig>,
+
+    /// Path rewrites, in order (`[[virtualhost.rewrite]]`)
+    #[serde(default, rename = "rewrite", skip_serializing_if = "Vec::is_empty")]
+    pub rewrites: Vec<RewriteConfig>,
+
+    /// Serve `<file>.br` / `<file>.gz` siblings to clients that accept them
+    /// (overrides `static.precompressed`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precompressed: Option<bool>,
+
+    /// List directories that have no index file instead of answering 403
+    #[serde(default)]
+    pub autoindex: bool,
+
+    /// Whether paths may pass through symbolic links below `root`
+    #[serde(default)]
+    pub follow_symlinks: SymlinkPolicy,
+
+    /// Answer 403 for paths with a component starting with `.`
+    /// (`/.well-known/` stays reachable)
+    #[serde(default = "default_true")]
+    pub deny_dotfiles: bool,
+
+    /// Case-insensitive globs (`*`, `?`) answered with 403, e.g. `*.bak`;
+    /// without a `/` they match any path component, with one the whole path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_files: Vec<String>,
+
+    /// Nginx-style `try_files` for paths that aren't a file or directory,
+    /// e.g. `["$uri", "/index.html", "=404"]`; empty keeps the built-in
+    /// `$uri $uri/ /index.php$is_args$args`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub try_files: Vec<String>,
+
+    /// Directory PHP may hand files from with `X-Sendfile` (a path inside it)
+    /// or `X-Accel-Redirect` (a URI relative to it); unset disables both
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sendfile_root: Option<String>,
+
+    /// `Cache-Control` for static files by MIME type glob (`"image/*"`) or
+    /// file name glob (`"*.json"`), replacing the built-in policy
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub static_cache_control: std::collections::HashMap<String, String>,
+
+    /// Extensions of static files always sent as downloads
+    /// (`Content-Disposition: attachment`), e.g. `["zip", "csv", "tar.gz"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub force_download: Vec<String>,
+
+    /// File served (200, `no-cache`) for paths that aren't a file or a
+    /// directory with an index, e.g. `"/index.html"` for a single-page app;
+    /// takes the place of the PHP front controller
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spa_fallback: Option<String>,
+
+    /// Use `spa_fallback` for paths ending in a file extension as well;
+    /// off so a missing `/app.js` stays a 404
+    #[serde(default)]
+    pub spa_fallback_extensions: bool,
+
+    /// Redirect plain HTTP to HTTPS (overrides `server.redirect_to_https`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_to_https: Option<bool>,
+}
+
+/// Settings for a path inside a virtual host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationConfig {
+    /// Path rule (`/admin` matches `/admin/...`, `/export*` is a prefix)
+    pub path: String,
+
+    /// Request timeout for matching paths (overrides the vhost and server)
+    #[serde(
+        default,
+        with = "duration_compat::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub request_timeout: Option<Duration>,
+
+    /// How long a streamed (`X-Accel-Buffering: no`) response may run
+    #[serde(
+        default,
+        with = "duration_compat::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub streaming_timeout: Option<Duration>,
+
+    /// Treat matching paths as a user upload directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploads: Option<UploadPolicy>,
+
+    /// Pass matching requests, WebSocket upgrades included, to this
+    /// upstream (`http://127.0.0.1:6001`) instead of the docroot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_pass: Option<String>,
+}
+
+/// A `[[virtualhost.rewrite]]` rule, applied to the request path before it
+/// is resolved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteConfig {
+    /// Regex matched against the path
+    pub pattern: String,
+
+    /// New path, optionally with a query; `$1` or `${1}` insert captures
+    pub replacement: String,
+
+    /// What happens once the rule matched; unset, later rules see the new path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flags: Option<RewriteFlag>,
+}
+
+/// How a matching `[[virtualhost.rewrite]]` rule ends
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RewriteFlag {
+    /// Start over at the first rule with the new path
+    Last,
+    /// Answer 302 with the new URI as `Location`
+    Redirect,
+    /// Answer 301 with the new URI as `Location`
+    Permanent,
+}
+
+/// Protections for user upload directories
+///
+/// Every policy but `off` refuses to run PHP and sends
+/// `X-Content-Type-Options: nosniff`; they differ in how HTML, SVG and XML
+/// files are served.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadPolicy {
+    /// Serve risky types as downloads (`Content-Disposition: attachment`)
+    Attachment,
+    /// Serve risky types as `text/plain`
+    Plain,
+    /// No protections (turns off a platform preset)
+    Off,
+}
+
+/// Symbolic links a request path may cross (`follow_symlinks`)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Follow every link
+    #[default]
+    Always,
+    /// Refuse any path with a link below the document root (403)
+    Never,
+    /// Follow a link only when it has the same owner as its target
+    OwnerMatch,
 }
 
 fn default_index_files() -> Vec<String> {
@@ -510,13 +1575,227 @@ pub struct VHostCacheConfig {
     #[serde(default = "default_cache_ttl")]
     pub ttl: u64,
 
-    /// Vary headers
+    /// Overrides `cache.stale_grace` for this vhost
+    #[serde(default, alias = "stale_while_revalidate")]
+    pub stale_grace: Option<u64>,
+
+    /// Request headers whose values get their own cache entries
     #[serde(default)]
     pub vary: Vec<String>,
 
     /// Excluded paths from caching
     #[serde(default)]
     pub exclude: Vec<String>,
+
+    /// Query parameters a cached page may carry (`utm_*` matches by prefix,
+    /// `*` allows any); other query strings bypass the cache
+    #[serde(default)]
+    pub query_allow: Vec<String>,
+
+    /// Cookie names (`*` globs) whose presence bypasses the cache; defaults
+    /// to the WordPress login and cart cookies for `platform = "wordpress"`
+    #[serde(default)]
+    pub bypass_cookies: Option<Vec<String>>,
+
+    /// Content types never cached ("text/html; charset=iso-8859-1" matches
+    /// "text/html"; `*` wildcards allowed)
+    #[serde(default)]
+    pub exclude_types: Vec<String>,
+}
+
+/// Upper bound for connection-level timeouts
+const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Upper bound for a single PHP script run
+const MAX_EXECUTION_TIME: Duration = Duration::from_secs(24 * 3600);
+
+fn check_duration(name: &str, value: Duration, max: Duration) -> Result<(), ConfigError> {
+    if value.is_zero() || value > max {
+        return Err(ConfigError::ValidationError(format!(
+            "{} must be between 1ms and {}",
+            name,
+            format_duration(max)
+        )));
+    }
+    Ok(())
+}
+
+/// RFC 9110 `tchar`
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(is_token_byte)
+}
+
+/// True for `type/subtype` followed by any `; name=value` parameters
+fn is_content_type(value: &str) -> bool {
+    let mut parts = value.split(';');
+    let media = parts.next().unwrap_or("").trim();
+    let Some((kind, subtype)) = media.split_once('/') else {
+        return false;
+    };
+    if !is_token(kind) || !is_token(subtype) {
+        return false;
+    }
+    parts.all(|parameter| {
+        let Some((name, value)) = parameter.trim().split_once('=') else {
+            return false;
+        };
+        let quoted = value.len() >= 2
+            && value.starts_with('"')
+            && value.ends_with('"')
+            && value[1..value.len() - 1]
+                .bytes()
+                .all(|b| b == b'\t' || (b' '..=b'~').contains(&b) && b != b'"');
+        is_token(name) && (is_token(value) || quoted)
+    })
+}
+
+/// Parse a duration such as "500ms", "30s", "5m" or "1h30m"
+///
+/// Units are `ms`, `s`, `m`, `h` and `d`. A bare integer is taken as seconds,
+/// which keeps configs written before durations were strings working.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("invalid duration '{}'", value));
+        }
+        let amount: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("invalid duration '{}'", value))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            "d" => Duration::from_secs(86400),
+            "" => return Err(format!("missing unit in duration '{}'", value)),
+            other => return Err(format!("unknown unit '{}' in duration '{}'", other, value)),
+        };
+        rest = &rest[unit_len..];
+
+        let too_large = || format!("duration '{}' is too large", value);
+        let part = u32::try_from(amount)
+            .ok()
+            .and_then(|amount| unit.checked_mul(amount))
+            .ok_or_else(too_large)?;
+        total = total.checked_add(part).ok_or_else(too_large)?;
+    }
+    Ok(total)
+}
+
+/// Format a duration the way `parse_duration` reads it back
+pub fn format_duration(value: Duration) -> String {
+    let millis = value.as_millis();
+    if millis.is_multiple_of(1000) {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{}ms", millis)
+    }
+}
+
+/// Serde shim: durations are written as strings, and integers are still read as seconds
+mod duration_compat {
+    use super::{format_duration, parse_duration};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(*value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(secs) => Ok(Duration::from_secs(secs)),
+            Raw::Text(text) => parse_duration(&text).map_err(serde::de::Error::custom),
+        }
+    }
+
+    /// The same for optional settings
+    pub mod option {
+        use serde::{Deserializer, Serializer};
+        use std::time::Duration;
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            super::deserialize(deserializer).map(Some)
+        }
+    }
+}
+
+/// Serde shim: TOML table keys are strings, so statuses are read from and
+/// written as their decimal text
+mod error_pages_compat {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::{BTreeMap, HashMap};
+
+    pub fn serialize<S: Serializer>(
+        value: &HashMap<u16, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .iter()
+            .map(|(status, page)| (status.to_string(), page))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<u16, String>, D::Error> {
+        HashMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(status, page)| {
+                status
+                    .parse::<u16>()
+                    .map(|status| (status, page))
+                    .map_err(|_| {
+                        serde::de::Error::custom(format!(
+                            "error_pages key '{}' is not a status code",
+                            status
+                        ))
+                    })
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -526,7 +1805,7 @@ mod tests {
     #[test]
     fn test_default_config() {
         let config = Config::default();
-        assert_eq!(config.server.listen, "0.0.0.0:8080");
+        assert_eq!(config.server.listen.addresses(), ["0.0.0.0:8080"]);
         assert!(config.cache.enable);
         assert!(config.cache.l1_enabled);
         assert!(config.cache.l2_enabled);
@@ -552,12 +1831,506 @@ mod tests {
         "#;
 
         let config = Config::from_str(toml).unwrap();
-        assert_eq!(config.server.listen, "127.0.0.1:9000");
+        assert_eq!(config.server.listen.addresses(), ["127.0.0.1:9000"]);
         assert_eq!(config.server.workers, "4");
         assert_eq!(config.php.version, "8.3");
         assert_eq!(config.cache.default_ttl, 7200);
     }
 
+    #[test]
+    fn test_log_format_validation() {
+        let config = Config::from_str(
+            r#"
+            [logging]
+            format = "$remote_addr \"$request\" $status"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.logging.format, "$remote_addr \"$request\" $status");
+
+        let err = Config::from_str(
+            r#"
+            [logging]
+            format = "$remote_addr $no_such_variable"
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("$no_such_variable"));
+    }
+
+    #[test]
+    fn test_cache_schedule_validation() {
+        let config = Config::from_str(
+            r#"
+            [[cache.schedule]]
+            cron = "0 0 3 * * *"
+            action = "purge_all"
+
+            [[cache.schedule]]
+            name = "business-hours"
+            cron = "0 9 * * mon-fri"
+            action = "set_ttl:/news*:60"
+            duration = 28800
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.cache.schedule.len(), 2);
+        assert_eq!(config.cache.schedule[1].duration, 28800);
+
+        for (body, expected) in [
+            ("cron = \"* * *\"\naction = \"purge_all\"", "fields"),
+            ("cron = \"* * * * *\"\naction = \"flush\"", "unknown action"),
+            (
+                "cron = \"* * * * *\"\naction = \"set_ttl:/a:60\"",
+                "duration",
+            ),
+        ] {
+            let err = Config::from_str(&format!("[[cache.schedule]]\n{}", body)).unwrap_err();
+            assert!(err.to_string().contains("cache.schedule[0]"), "{}", err);
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_static_image_config() {
+        let config = Config::from_str(
+            r#"
+            [static]
+            image_optimize = true
+            image_formats = ["webp"]
+        "#,
+        )
+        .unwrap();
+        assert!(config.static_files.image_optimize);
+        assert_eq!(config.static_files.image_formats, vec!["webp"]);
+        assert_eq!(config.static_files.image_min_size, 10 * 1024);
+
+        let err = Config::from_str(
+            r#"
+            [static]
+            image_formats = ["jxl"]
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("jxl"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172_800)));
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("1.5s").is_err());
+
+        for value in ["500ms", "30s", "1500ms"] {
+            let parsed = parse_duration(value).unwrap();
+            assert_eq!(parse_duration(&format_duration(parsed)), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn test_integer_timeouts_parse_as_seconds() {
+        let old = Config::from_str(
+            r#"
+            [server]
+            keepalive_timeout = 75
+            request_timeout = 60
+
+            [php]
+            max_execution_time = 30
+        "#,
+        )
+        .unwrap();
+        let new = Config::from_str(
+            r#"
+            [server]
+            keepalive_timeout = "75s"
+            request_timeout = "1m"
+
+            [php]
+            max_execution_time = "30s"
+        "#,
+        )
+        .unwrap();
+        for config in [&old, &new] {
+            assert_eq!(config.server.keepalive_timeout, Duration::from_secs(75));
+            assert_eq!(config.server.request_timeout, Duration::from_secs(60));
+            assert_eq!(config.php.max_execution_time, Duration::from_secs(30));
+        }
+
+        let defaults = Config::default();
+        assert_eq!(
+            old.server.keepalive_timeout,
+            defaults.server.keepalive_timeout
+        );
+        assert_eq!(old.php.max_execution_time, defaults.php.max_execution_time);
+    }
+
+    #[test]
+    fn test_timeout_validation() {
+        let config = Config::from_str(
+            r#"
+            [server]
+            request_timeout = "500ms"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.request_timeout, Duration::from_millis(500));
+
+        for toml in [
+            "[server]\nkeepalive_timeout = 0",
+            "[server]\nrequest_timeout = \"2h\"",
+            "[php]\nmax_execution_time = \"0s\"",
+        ] {
+            let err = Config::from_str(toml).unwrap_err();
+            assert!(err.to_string().contains("must be between"), "{}", err);
+        }
+        assert!(Config::from_str("[server]\nrequest_timeout = \"soon\"").is_err());
+    }
+
+    #[test]
+    fn test_error_pages() {
+        let config = Config::from_str(
+            r#"
+            [[virtualhost]]
+            domain = "example.com"
+            root = "/var/www"
+            error_pages = { 404 = "/errors/404.html", 500 = "/errors/500.php" }
+        "#,
+        )
+        .unwrap();
+        let pages = &config.virtualhost[0].error_pages;
+        assert_eq!(pages[&404], "/errors/404.html");
+        assert_eq!(pages[&500], "/errors/500.php");
+
+        // Round-trips through the TOML writer
+        let written = toml::to_string(&config).unwrap();
+        assert_eq!(
+            Config::from_str(&written).unwrap().virtualhost[0].error_pages,
+            *pages
+        );
+
+        for (pages, needle) in [
+            ("{ 200 = \"/ok.html\" }", "not an error status"),
+            ("{ 404 = \"errors/404.html\" }", "must start with '/'"),
+            ("{ missing = \"/404.html\" }", "not a status code"),
+        ] {
+            let toml = format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\nerror_pages = {}",
+                pages
+            );
+            let err = Config::from_str(&toml).unwrap_err();
+            assert!(err.to_string().contains(needle), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_deny_files() {
+        let vhost = |extra: &str| {
+            format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\n{}",
+                extra
+            )
+        };
+        let config = Config::from_str(&vhost("deny_files = [\"*.bak\", \"/private/*\"]")).unwrap();
+        assert!(config.virtualhost[0].deny_dotfiles);
+        assert_eq!(config.virtualhost[0].deny_files, ["*.bak", "/private/*"]);
+
+        for pattern in ["\"\"", "\"private/*\""] {
+            let err = Config::from_str(&vhost(&format!("deny_files = [{}]", pattern))).unwrap_err();
+            assert!(err.to_string().contains("deny_files"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_try_files() {
+        let vhost = |extra: &str| {
+            format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\n{}",
+                extra
+            )
+        };
+        let config = Config::from_str(&vhost("")).unwrap();
+        assert!(config.virtualhost[0].try_files.is_empty());
+        let config = Config::from_str(&vhost("try_files = [\"$uri\", \"=404\"]")).unwrap();
+        assert_eq!(config.virtualhost[0].try_files, ["$uri", "=404"]);
+
+        let err = Config::from_str(&vhost("try_files = [\"=404\", \"$uri\"]")).unwrap_err();
+        assert!(err.to_string().contains("try_files"), "{}", err);
+    }
+
+    #[test]
+    fn test_sendfile_root() {
+        let vhost = |extra: &str| {
+            format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\n{}",
+                extra
+            )
+        };
+        assert!(Config::from_str(&vhost("")).unwrap().virtualhost[0]
+            .sendfile_root
+            .is_none());
+        let config = Config::from_str(&vhost("sendfile_root = \"/srv/private\"")).unwrap();
+        assert_eq!(
+            config.virtualhost[0].sendfile_root.as_deref(),
+            Some("/srv/private")
+        );
+
+        let err = Config::from_str(&vhost("sendfile_root = \"private\"")).unwrap_err();
+        assert!(err.to_string().contains("sendfile_root"), "{}", err);
+    }
+
+    #[test]
+    fn test_static_cache_control() {
+        let vhost = |extra: &str| {
+            format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\n{}",
+                extra
+            )
+        };
+        assert!(Config::from_str(&vhost("")).unwrap().virtualhost[0]
+            .static_cache_control
+            .is_empty());
+        let config = Config::from_str(&vhost(
+            "static_cache_control = { \"image/*\" = \"public, max-age=604800\", \"*.json\" = \"no-cache\" }",
+        ))
+        .unwrap();
+        let rules = &config.virtualhost[0].static_cache_control;
+        assert_eq!(rules["image/*"], "public, max-age=604800");
+        assert_eq!(rules["*.json"], "no-cache");
+
+        let err =
+            Config::from_str(&vhost("static_cache_control = { \"*.json\" = \"\" }")).unwrap_err();
+        assert!(err.to_string().contains("static_cache_control"), "{}", err);
+    }
+
+    #[test]
+    fn test_force_download() {
+        let vhost = |extra: &str| {
+            format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\n{}",
+                extra
+            )
+        };
+        assert!(Config::from_str(&vhost("")).unwrap().virtualhost[0]
+            .force_download
+            .is_empty());
+        let config = Config::from_str(&vhost("force_download = [\"zip\", \".CSV\"]")).unwrap();
+        assert_eq!(config.virtualhost[0].force_download, ["zip", ".CSV"]);
+
+        for bad in ["\"\"", "\".\"", "\"*.zip\"", "\"dl/zip\""] {
+            let err = Config::from_str(&vhost(&format!("force_download = [{}]", bad))).unwrap_err();
+            assert!(err.to_string().contains("force_download"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_listen_list() {
+        let config =
+            Config::from_str("[server]\nlisten = [\"0.0.0.0:8080\", \"[::]:8080\"]").unwrap();
+        assert_eq!(
+            config.server.listen.addresses(),
+            ["0.0.0.0:8080", "[::]:8080"]
+        );
+        assert_eq!(config.server.listen.to_string(), "0.0.0.0:8080, [::]:8080");
+
+        // A single address is written back as a string
+        let toml = toml::to_string(&Config::from_str("").unwrap()).unwrap();
+        assert!(toml.contains("listen = \"0.0.0.0:8080\""), "{}", toml);
+
+        for bad in [
+            "[]",
+            "[\"0.0.0.0:8080\", \"nope\"]",
+            "[\"[::]:80\", \"[::]:80\"]",
+        ] {
+            let err = Config::from_str(&format!("[server]\nlisten = {}", bad)).unwrap_err();
+            assert!(err.to_string().contains("server.listen"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_unix_listen() {
+        let config = Config::from_str("[server]\nlisten = \"unix:/run/veloserve.sock\"").unwrap();
+        assert_eq!(
+            config.server.listen.addresses(),
+            ["unix:/run/veloserve.sock"]
+        );
+        assert_eq!(config.server.socket_mode, "0660");
+
+        let err = Config::from_str("[server]\nlisten = \"localhost\"").unwrap_err();
+        assert!(err.to_string().contains("server.listen"), "{}", err);
+        let err = Config::from_str("[server]\nsocket_mode = \"rw\"").unwrap_err();
+        assert!(err.to_string().contains("server.socket_mode"), "{}", err);
+    }
+
+    #[test]
+    fn test_h2c_prior_knowledge() {
+        assert!(!Config::default().server.h2c_prior_knowledge);
+        for key in ["h2c_prior_knowledge", "h2c"] {
+            let config = Config::from_str(&format!("[server]\n{} = true", key)).unwrap();
+            assert!(config.server.h2c_prior_knowledge, "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_sendfile() {
+        assert!(!Config::default().server.sendfile);
+        let config = Config::from_str("[server]\nsendfile = true").unwrap();
+        assert!(config.server.sendfile);
+    }
+
+    #[test]
+    fn test_request_head_limits() {
+        let config = Config::default();
+        assert_eq!(config.server.max_uri_length, 8192);
+        assert_eq!(config.server.max_header_bytes, 32768);
+
+        let config =
+            Config::from_str("[server]\nmax_uri_length = 2048\nmax_header_bytes = 65536").unwrap();
+        assert_eq!(config.server.max_uri_length, 2048);
+        assert_eq!(config.server.max_header_bytes, 65536);
+
+        for key in ["max_uri_length", "max_header_bytes"] {
+            let err = Config::from_str(&format!("[server]\n{} = 0", key)).unwrap_err();
+            assert!(err.to_string().contains(key), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_spa_fallback() {
+        let vhost = |extra: &str| {
+            format!(
+                "[[virtualhost]]\ndomain = \"example.com\"\nroot = \"/var/www\"\n{}",
+                extra
+            )
+        };
+        let config = Config::from_str(&vhost("")).unwrap();
+        assert!(config.virtualhost[0].spa_fallback.is_none());
+        assert!(!config.virtualhost[0].spa_fallback_extensions);
+        let config = Config::from_str(&vhost(
+            "spa_fallback = \"/index.html\"\nspa_fallback_extensions = true",
+        ))
+        .unwrap();
+        assert_eq!(
+            config.virtualhost[0].spa_fallback.as_deref(),
+            Some("/index.html")
+        );
+        assert!(config.virtualhost[0].spa_fallback_extensions);
+
+        for bad in ["\"index.html\"", "\"/app/\"", "\"/../index.html\""] {
+            let err = Config::from_str(&vhost(&format!("spa_fallback = {}", bad))).unwrap_err();
+            assert!(err.to_string().contains("spa_fallback"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_cache_sizes_are_validated() {
+        let config =
+            Config::from_str("[cache]\ndisk_limit = \"4KB\"\nmax_entry_size = \"8M\"").unwrap();
+        assert_eq!(config.cache.disk_limit.as_deref(), Some("4KB"));
+
+        for (field, value) in [
+            ("memory_limit", "lots"),
+            ("disk_limit", "10 gigs"),
+            ("max_entry_size", "1.5M"),
+        ] {
+            let err = Config::from_str(&format!("[cache]\n{} = \"{}\"", field, value)).unwrap_err();
+            assert!(
+                err.to_string().contains(&format!("cache.{}", field)),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let config = Config::from_str(
+            r#"
+            [server]
+            trusted_proxies = ["10.0.0.0/8", "::1"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.trusted_proxies, ["10.0.0.0/8", "::1"]);
+        assert!(Config::default().server.trusted_proxies.is_empty());
+
+        let err = Config::from_str("[server]\ntrusted_proxies = [\"10.0.0.0/40\"]").unwrap_err();
+        assert!(
+            err.to_string().contains("server.trusted_proxies"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_compression_types() {
+        let config = Config::from_str(
+            r#"
+            [server.compression]
+            types = ["text/html", "application/*"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.server.compression.types,
+            ["text/html", "application/*"]
+        );
+        assert!(Config::default()
+            .server
+            .compression
+            .types
+            .contains(&"text/*".to_string()));
+
+        let err = Config::from_str("[server.compression]\ntypes = [\"html\"]").unwrap_err();
+        assert!(err.to_string().contains("not a media type"), "{}", err);
+    }
+
+    #[test]
+    fn test_mime_types() {
+        let config = Config::from_str(
+            r#"
+            [static]
+            default_charset = "iso-8859-1"
+
+            [mime_types]
+            m3u8 = "application/vnd.apple.mpegurl"
+            ".txt" = "text/plain; charset=\"us-ascii\""
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.static_files.default_charset, "iso-8859-1");
+        assert_eq!(config.mime_types["m3u8"], "application/vnd.apple.mpegurl");
+        assert_eq!(Config::default().static_files.default_charset, "utf-8");
+
+        for (table, needle) in [
+            ("m3u8 = \"mpegurl\"", "not a valid content type"),
+            (
+                "m3u8 = \"application/x mpegurl\"",
+                "not a valid content type",
+            ),
+            ("m3u8 = \"text/plain; charset\"", "not a valid content type"),
+            (
+                "m3u8 = \"text/plain\\r\\nX-Evil: 1\"",
+                "not a valid content type",
+            ),
+            ("\"tar.gz\" = \"application/gzip\"", "not a file extension"),
+            ("\"\" = \"text/plain\"", "not a file extension"),
+        ] {
+            let err = Config::from_str(&format!("[mime_types]\n{}", table)).unwrap_err();
+            assert!(err.to_string().contains(needle), "{}", err);
+        }
+
+        let err = Config::from_str("[static]\ndefault_charset = \"utf 8\"").unwrap_err();
+        assert!(err.to_string().contains("not a charset name"), "{}", err);
+    }
+
     #[test]
     fn test_worker_threads() {
         let mut config = Config::default();
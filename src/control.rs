@@ -0,0 +1,316 @@
+//! Control Socket Protocol
+//!
+//! The server listens on `server.control_socket`, a Unix socket only local
+//! administrators can reach, for management commands from the CLI. A client
+//! writes one command per line:
+//!
+//! - `cache.stats`
+//! - `cache.purge.all`
+//! - `cache.purge.tag:<tag>`
+//! - `state.export`, `state.export:bodies`, `state.export:bodies,max_bytes=<n>`
+//! - `state.import:<length>`, followed by that many snapshot bytes
+//!
+//! and reads one line of JSON back: `{"ok":true,"result":{...}}`, or
+//! `{"ok":false,"error":"..."}` for a command that failed or wasn't
+//! understood. A reply with `"payload":<length>` (the exported snapshot) is
+//! followed by that many raw bytes.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Where the CLI looks for the control socket unless told otherwise
+pub const DEFAULT_CONTROL_SOCKET: &str = "/run/veloserve/control.sock";
+
+/// Most the server reads from one control connection, besides payloads
+pub const MAX_COMMAND_BYTES: usize = 4096;
+
+/// Largest payload sent with a command or reply
+pub const MAX_PAYLOAD_BYTES: usize = crate::server::MAX_SNAPSHOT_BYTES;
+
+/// A control socket command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// `CacheManager::stats`
+    CacheStats,
+    /// Drop every cache entry
+    CachePurgeAll,
+    /// Drop the entries carrying a tag
+    CachePurgeTag(String),
+    /// Snapshot the runtime state; the reply carries it as its payload
+    StateExport {
+        bodies: bool,
+        max_bytes: Option<usize>,
+    },
+    /// Load a snapshot of this many bytes, sent right after the command line
+    StateImport(usize),
+}
+
+impl ControlCommand {
+    /// Parse one command line, without its newline
+    pub fn parse(line: &str) -> Result<Self, String> {
+        match line.trim() {
+            "cache.stats" => Ok(Self::CacheStats),
+            "cache.purge.all" => Ok(Self::CachePurgeAll),
+            "state.export" => Ok(Self::StateExport {
+                bodies: false,
+                max_bytes: None,
+            }),
+            line => {
+                if let Some(tag) = line.strip_prefix("cache.purge.tag:") {
+                    if tag.is_empty() {
+                        return Err("cache.purge.tag needs a tag".to_string());
+                    }
+                    Ok(Self::CachePurgeTag(tag.to_string()))
+                } else if let Some(options) = line.strip_prefix("state.export:") {
+                    Self::parse_export(options)
+                } else if let Some(len) = line.strip_prefix("state.import:") {
+                    match len.parse::<usize>() {
+                        Ok(len) if len <= MAX_PAYLOAD_BYTES => Ok(Self::StateImport(len)),
+                        Ok(_) => Err(format!(
+                            "state snapshots are limited to {} bytes",
+                            MAX_PAYLOAD_BYTES
+                        )),
+                        Err(_) => Err("state.import needs the snapshot length".to_string()),
+                    }
+                } else {
+                    Err(format!("unknown command '{}'", line))
+                }
+            }
+        }
+    }
+
+    fn parse_export(options: &str) -> Result<Self, String> {
+        let mut bodies = false;
+        let mut max_bytes = None;
+        for option in options.split(',') {
+            match option.split_once('=') {
+                None if option == "bodies" => bodies = true,
+                Some(("max_bytes", n)) => {
+                    max_bytes = Some(
+                        n.parse()
+                            .map_err(|_| "max_bytes must be a byte count".to_string())?,
+                    )
+                }
+                _ => return Err(format!("unknown state.export option '{}'", option)),
+            }
+        }
+        Ok(Self::StateExport { bodies, max_bytes })
+    }
+
+    /// Bytes the client sends after the command line
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Self::StateImport(len) => *len,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for ControlCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CacheStats => write!(f, "cache.stats"),
+            Self::CachePurgeAll => write!(f, "cache.purge.all"),
+            Self::CachePurgeTag(tag) => write!(f, "cache.purge.tag:{}", tag),
+            Self::StateExport { bodies, max_bytes } => {
+                let mut options = Vec::new();
+                if *bodies {
+                    options.push("bodies".to_string());
+                }
+                if let Some(max_bytes) = max_bytes {
+                    options.push(format!("max_bytes={}", max_bytes));
+                }
+                if options.is_empty() {
+                    write!(f, "state.export")
+                } else {
+                    write!(f, "state.export:{}", options.join(","))
+                }
+            }
+            Self::StateImport(len) => write!(f, "state.import:{}", len),
+        }
+    }
+}
+
+/// The reply to one command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlReply {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Length of the raw bytes sent after the reply line
+    #[serde(default, rename = "payload", skip_serializing_if = "Option::is_none")]
+    pub payload_len: Option<usize>,
+    /// Those bytes
+    #[serde(skip)]
+    pub payload: Vec<u8>,
+}
+
+impl ControlReply {
+    pub fn success(result: Value) -> Self {
+        Self {
+            ok: true,
+            result,
+            error: None,
+            payload_len: None,
+            payload: Vec::new(),
+        }
+    }
+
+    /// A successful reply followed by `payload`
+    pub fn with_payload(result: Value, payload: Vec<u8>) -> Self {
+        Self {
+            payload_len: Some(payload.len()),
+            payload,
+            ..Self::success(result)
+        }
+    }
+
+    pub fn failure(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            result: Value::Null,
+            error: Some(error.into()),
+            payload_len: None,
+            payload: Vec::new(),
+        }
+    }
+
+    /// The reply as sent: JSON on one line
+    pub fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self)
+            .unwrap_or_else(|_| r#"{"ok":false,"error":"reply could not be encoded"}"#.to_string());
+        line.push('\n');
+        line
+    }
+
+    /// Parse a reply line; the result of a successful command, or its error
+    pub fn parse(line: &str) -> anyhow::Result<Value> {
+        Self::parse_with_payload_len(line).map(|(result, _)| result)
+    }
+
+    /// As [`parse`](Self::parse), with the length of the payload that follows
+    fn parse_with_payload_len(line: &str) -> anyhow::Result<(Value, usize)> {
+        let reply: Self = serde_json::from_str(line.trim())
+            .map_err(|e| anyhow::anyhow!("Malformed control socket reply: {}", e))?;
+        if !reply.ok {
+            return Err(anyhow::anyhow!(
+                "{}",
+                reply.error.unwrap_or_else(|| "command failed".to_string())
+            ));
+        }
+        let payload_len = reply.payload_len.unwrap_or(0);
+        if payload_len > MAX_PAYLOAD_BYTES {
+            return Err(anyhow::anyhow!(
+                "Control socket reply payload of {} bytes is above the {} byte limit",
+                payload_len,
+                MAX_PAYLOAD_BYTES
+            ));
+        }
+        Ok((reply.result, payload_len))
+    }
+}
+
+/// Send `command` to the server listening on `socket` and return the result
+pub async fn request(socket: &std::path::Path, command: &ControlCommand) -> anyhow::Result<Value> {
+    transfer(socket, command, &[])
+        .await
+        .map(|(result, _)| result)
+}
+
+/// Send `command` followed by `upload` (its `payload_len` bytes) and return
+/// the result and the payload of the reply
+#[cfg(unix)]
+pub async fn transfer(
+    socket: &std::path::Path,
+    command: &ControlCommand,
+    upload: &[u8],
+) -> anyhow::Result<(Value, Vec<u8>)> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let stream = tokio::net::UnixStream::connect(socket).await.map_err(|e| {
+        anyhow::anyhow!(
+            "Cannot connect to control socket {}: {} (is the server running with server.control_socket set?)",
+            socket.display(),
+            e
+        )
+    })?;
+    let (read, mut write) = stream.into_split();
+    write.write_all(format!("{}\n", command).as_bytes()).await?;
+    write.write_all(upload).await?;
+
+    let mut reader = BufReader::new(read);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    if line.is_empty() {
+        return Err(anyhow::anyhow!("Control socket closed without a reply"));
+    }
+    let (result, payload_len) = ControlReply::parse_with_payload_len(&line)?;
+    let mut payload = vec![0; payload_len];
+    reader.read_exact(&mut payload).await?;
+    Ok((result, payload))
+}
+
+/// Control sockets need a Unix platform
+#[cfg(not(unix))]
+pub async fn transfer(
+    socket: &std::path::Path,
+    _command: &ControlCommand,
+    _upload: &[u8],
+) -> anyhow::Result<(Value, Vec<u8>)> {
+    Err(anyhow::anyhow!(
+        "Cannot connect to control socket {}: Unix sockets need a Unix platform",
+        socket.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_commands_round_trip() {
+        for command in [
+            ControlCommand::CacheStats,
+            ControlCommand::CachePurgeAll,
+            ControlCommand::CachePurgeTag("product:42".to_string()),
+            ControlCommand::StateExport {
+                bodies: false,
+                max_bytes: None,
+            },
+            ControlCommand::StateExport {
+                bodies: true,
+                max_bytes: Some(4096),
+            },
+            ControlCommand::StateImport(123),
+        ] {
+            assert_eq!(ControlCommand::parse(&command.to_string()), Ok(command));
+        }
+        assert!(ControlCommand::parse("cache.purge.tag:").is_err());
+        assert!(ControlCommand::parse("shutdown").is_err());
+        assert!(ControlCommand::parse("state.export:everything").is_err());
+        assert!(ControlCommand::parse("state.import:").is_err());
+        assert!(ControlCommand::parse(&format!("state.import:{}", MAX_PAYLOAD_BYTES + 1)).is_err());
+    }
+
+    #[test]
+    fn test_reply_lines() {
+        let line = ControlReply::success(json!({"entries": 3})).to_line();
+        assert!(line.ends_with('\n') && !line.trim_end().contains('\n'));
+        assert_eq!(ControlReply::parse(&line).unwrap()["entries"], 3);
+
+        let error = ControlReply::parse(&ControlReply::failure("nope").to_line()).unwrap_err();
+        assert_eq!(error.to_string(), "nope");
+
+        let line = ControlReply::with_payload(json!({"bytes": 3}), vec![1, 2, 3]).to_line();
+        assert_eq!(
+            ControlReply::parse_with_payload_len(&line).unwrap(),
+            (json!({"bytes": 3}), 3)
+        );
+        assert!(!line.contains("[1,2,3]"));
+    }
+}
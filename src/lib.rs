@@ -24,6 +24,8 @@ pub mod apache_compat;
 pub mod cache;
 pub mod cli;
 pub mod config;
+pub mod control;
+pub mod nginx_compat;
 pub mod php;
 pub mod server;
 
@@ -4,10 +4,10 @@
 
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
-use tracing::{error, info};
+use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use veloserve::cli::{self, CacheCommand, ConfigCommand};
+use veloserve::cli::{self, CacheCommand, ConfigCommand, ServerCommand, StateCommand};
 use veloserve::config::Config;
 use veloserve::server::Server;
 
@@ -43,12 +43,26 @@ enum Commands {
     /// Restart the server
     Restart,
     /// Show server status
-    Status,
+    Status {
+        /// Internal API base URL
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        api: String,
+    },
     /// Cache management commands
     Cache {
         #[command(subcommand)]
         command: CacheCommand,
     },
+    /// Runtime control of a running server
+    Server {
+        #[command(subcommand)]
+        command: ServerCommand,
+    },
+    /// Export or import runtime state for failover
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
     /// Configuration commands
     Config {
         #[command(subcommand)]
@@ -82,12 +96,18 @@ async fn main() -> anyhow::Result<()> {
             cli::stop_server()?;
             start_server(&cli.config, false).await?;
         }
-        Some(Commands::Status) => {
-            cli::show_status()?;
+        Some(Commands::Status { api }) => {
+            cli::show_status(&api).await?;
         }
         Some(Commands::Cache { command }) => {
             cli::handle_cache_command(command).await?;
         }
+        Some(Commands::Server { command }) => {
+            cli::handle_server_command(command).await?;
+        }
+        Some(Commands::State { command }) => {
+            cli::handle_state_command(command).await?;
+        }
         Some(Commands::Config { command }) => {
             cli::handle_config_command(&cli.config, command)?;
         }
@@ -125,7 +145,10 @@ async fn start_server(config_path: &PathBuf, foreground: bool) -> anyhow::Result
     }
 
     // Create and run server
-    let server = Server::new(config);
+    let mut server = Server::new(config);
+    if config_path.exists() {
+        server = server.with_config_path(config_path);
+    }
 
     info!("Starting HTTP server...");
     server.run().await?;
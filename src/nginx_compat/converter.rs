@@ -0,0 +1,354 @@
+//! Nginx to VeloServe Configuration Converter
+//!
+//! Converts parsed `server {}` blocks to VeloServe TOML format. PHP needs no
+//! `fastcgi_pass`: VeloServe runs `.php` files itself, and the usual
+//! `try_files $uri $uri/ /index.php?$args` front controller is its built-in
+//! behaviour, so both are dropped. A plain-HTTP server that only redirects
+//! to HTTPS becomes `redirect_to_https` on the site it redirects for.
+
+use tracing::warn;
+
+use crate::apache_compat::converter::{detect_platform, platform_cache, render_toml};
+use crate::config::{Config, Listen, LocationConfig, SymlinkPolicy, VirtualHostConfig};
+use crate::nginx_compat::{NginxConfig, NginxLocation, NginxServer};
+use crate::server::TryFiles;
+
+/// Header of a converted configuration file
+const TOML_HEADER: &str = "# VeloServe Configuration\n# Converted from nginx.conf\n\n";
+
+/// Front-controller fallbacks VeloServe's default `try_files` already covers
+const FRONT_CONTROLLERS: &[&str] = &[
+    "/index.php",
+    "/index.php?$args",
+    "/index.php?$query_string",
+    "/index.php$is_args$args",
+];
+
+/// Converts Nginx configuration to VeloServe configuration
+pub struct NginxToVeloServeConverter {
+    /// Enable strict mode (fail on unsupported directives)
+    strict: bool,
+}
+
+impl NginxToVeloServeConverter {
+    /// Create a new converter
+    pub fn new() -> Self {
+        Self { strict: false }
+    }
+
+    /// Enable strict mode
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Convert Nginx configuration to VeloServe Config, logging what
+    /// couldn't be converted
+    pub fn convert(&self, nginx: &NginxConfig) -> Config {
+        let (config, warnings) = self.convert_with_warnings(nginx);
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        config
+    }
+
+    /// Convert Nginx configuration to VeloServe Config, along with a
+    /// message for each server, name or directive that was left out
+    pub fn convert_with_warnings(&self, nginx: &NginxConfig) -> (Config, Vec<String>) {
+        let mut config = Config::default();
+        let mut warnings = Vec::new();
+
+        let (redirects, sites): (Vec<&NginxServer>, Vec<&NginxServer>) = nginx
+            .servers
+            .iter()
+            .partition(|server| is_https_redirect(server));
+        for server in sites {
+            let vhosts = match self.convert_server(server, &mut warnings) {
+                Ok(vhosts) => vhosts,
+                Err(e) => {
+                    warnings.push(format!(
+                        "Skipping nginx server {}: {}",
+                        server.server_names.join(" "),
+                        e
+                    ));
+                    continue;
+                }
+            };
+            for mut vhost in vhosts {
+                // The first server for a name wins, as VeloServe matches in order
+                if config.virtualhost.iter().any(|v| v.domain == vhost.domain) {
+                    warnings.push(format!(
+                        "Skipping duplicate nginx server_name {}",
+                        vhost.domain
+                    ));
+                    continue;
+                }
+                if redirects
+                    .iter()
+                    .any(|redirect| redirect.server_names.contains(&vhost.domain))
+                {
+                    vhost.redirect_to_https = Some(true);
+                }
+                config.virtualhost.push(vhost);
+            }
+        }
+
+        let (plain, ssl) = listen_addresses(&nginx.servers);
+        config.server.listen = match plain.len() {
+            0 => Listen::One("0.0.0.0:80".to_string()),
+            1 => Listen::One(plain[0].clone()),
+            _ => Listen::Many(plain),
+        };
+        config.server.listen_ssl = ssl;
+
+        (config, warnings)
+    }
+
+    /// Convert one `server` block to a VirtualHostConfig per server name
+    fn convert_server(
+        &self,
+        server: &NginxServer,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<VirtualHostConfig>, ConversionError> {
+        let root = server
+            .root
+            .clone()
+            .or_else(|| server.root_location().and_then(|l| l.root.clone()))
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|| {
+                if self.strict {
+                    String::new()
+                } else {
+                    "/var/www/html".to_string()
+                }
+            });
+
+        if root.is_empty() && self.strict {
+            return Err(ConversionError::MissingRoot);
+        }
+
+        let try_files = match server.try_files.as_slice() {
+            [] => server
+                .root_location()
+                .map(|location| location.try_files.as_slice())
+                .unwrap_or_default(),
+            try_files => try_files,
+        };
+        let try_files = self.unsupported(convert_try_files(try_files), warnings)?;
+
+        let mut locations = Vec::new();
+        for location in &server.locations {
+            if let Some(location) = self.unsupported(self.convert_location(location), warnings)? {
+                locations.push(location);
+            }
+        }
+
+        let index = if server.index.is_empty() {
+            vec!["index.php".to_string(), "index.html".to_string()]
+        } else {
+            server.index.clone()
+        };
+
+        let platform = detect_platform(&root);
+        let cache = platform_cache(&platform);
+
+        let ssl_certificate = server
+            .ssl_certificate
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string());
+        let ssl_certificate_key = server
+            .ssl_certificate_key
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string());
+
+        // `_` is nginx's catch-all placeholder
+        let mut domains: Vec<String> = server
+            .server_names
+            .iter()
+            .filter(|name| !name.is_empty() && *name != "_")
+            .cloned()
+            .collect();
+        if domains.is_empty() {
+            domains.push("*".to_string());
+        }
+
+        Ok(domains
+            .into_iter()
+            .map(|domain| VirtualHostConfig {
+                domain,
+                root: root.clone(),
+                platform: Some(platform.clone()),
+                ssl_certificate: ssl_certificate.clone(),
+                ssl_certificate_key: ssl_certificate_key.clone(),
+                cache: cache.clone(),
+                index: index.clone(),
+                error_pages: std::collections::HashMap::new(),
+                request_timeout: None,
+                locations: locations.clone(),
+                rewrites: Vec::new(),
+                precompressed: None,
+                autoindex: false,
+                follow_symlinks: SymlinkPolicy::Always,
+                deny_dotfiles: true,
+                deny_files: Vec::new(),
+                try_files: try_files.clone(),
+                sendfile_root: None,
+                static_cache_control: std::collections::HashMap::new(),
+                force_download: Vec::new(),
+                spa_fallback: None,
+                spa_fallback_extensions: false,
+                redirect_to_https: None,
+            })
+            .collect())
+    }
+
+    /// Convert a `location` block; `None` when VeloServe needs nothing for it
+    fn convert_location(
+        &self,
+        location: &NginxLocation,
+    ) -> Result<Option<LocationConfig>, ConversionError> {
+        // PHP runs in-process, and `location /`'s try_files became the vhost's
+        if location.fastcgi_pass.is_some() || location.is_root() {
+            return Ok(None);
+        }
+        if !location.try_files.is_empty() {
+            return Err(ConversionError::UnsupportedDirective(format!(
+                "try_files in location {}",
+                location.path
+            )));
+        }
+        let Some(proxy_pass) = &location.proxy_pass else {
+            return Ok(None);
+        };
+
+        let path = match location.modifier.as_deref() {
+            None | Some("^~") if location.path.ends_with('/') => location.path.clone(),
+            // A prefix without a trailing slash also matches `/apix`
+            None | Some("^~") => format!("{}*", location.path),
+            Some("=") => location.path.clone(),
+            Some(modifier) => {
+                return Err(ConversionError::UnsupportedDirective(format!(
+                    "regex location {} {}",
+                    modifier, location.path
+                )))
+            }
+        };
+
+        Ok(Some(LocationConfig {
+            path,
+            request_timeout: None,
+            streaming_timeout: None,
+            uploads: None,
+            proxy_pass: Some(proxy_pass.clone()),
+        }))
+    }
+
+    /// Outside strict mode, an unsupported directive is skipped with a warning
+    fn unsupported<T: Default>(
+        &self,
+        result: Result<T, ConversionError>,
+        warnings: &mut Vec<String>,
+    ) -> Result<T, ConversionError> {
+        match result {
+            Err(e @ ConversionError::UnsupportedDirective(_)) if !self.strict => {
+                warnings.push(format!("Not converted: {}", e));
+                Ok(T::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Generate VeloServe TOML string from Nginx config
+    pub fn to_toml(&self, nginx: &NginxConfig) -> Result<String, ConversionError> {
+        self.render(&self.convert(nginx), false)
+    }
+
+    /// Output only [[virtualhost]] blocks for appending to an existing base config.
+    pub fn to_toml_vhosts_only(&self, nginx: &NginxConfig) -> Result<String, ConversionError> {
+        self.render(&self.convert(nginx), true)
+    }
+
+    /// Serialize a converted configuration, or only its [[virtualhost]] blocks
+    pub fn render(&self, config: &Config, vhosts_only: bool) -> Result<String, ConversionError> {
+        render_toml(config, TOML_HEADER, vhosts_only)
+            .map_err(|e| ConversionError::Serialization(e.to_string()))
+    }
+}
+
+impl Default for NginxToVeloServeConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// True for a server with no root that only answers `return 301 https://...`
+fn is_https_redirect(server: &NginxServer) -> bool {
+    server.root.is_none()
+        && server.directives.iter().any(|directive| {
+            directive.name == "return"
+                && matches!(directive.args.as_slice(), [code, url]
+                    if (code == "301" || code == "302") && url.starts_with("https://"))
+        })
+}
+
+/// `try_files` for VeloServe; empty when nginx's is the built-in front
+/// controller (`$uri $uri/ /index.php?$args`)
+fn convert_try_files(entries: &[String]) -> Result<Vec<String>, ConversionError> {
+    let front_controller = matches!(
+        entries,
+        [uri, dir, index] if uri == "$uri" && dir == "$uri/" && FRONT_CONTROLLERS.contains(&index.as_str())
+    );
+    if entries.is_empty() || front_controller {
+        return Ok(Vec::new());
+    }
+    TryFiles::new(entries).map_err(|e| {
+        ConversionError::UnsupportedDirective(format!("try_files {}: {}", entries.join(" "), e))
+    })?;
+    Ok(entries.to_vec())
+}
+
+/// Plain and TLS listen addresses of every server; only the first TLS port
+/// is kept, as VeloServe has one `listen_ssl`
+fn listen_addresses(servers: &[NginxServer]) -> (Vec<String>, Option<String>) {
+    let mut plain: Vec<String> = Vec::new();
+    let mut ssl = None;
+    for listen in servers.iter().flat_map(|server| &server.listen) {
+        // `80`, `*:80` and `[::]:80` all mean every address
+        let address = match listen.address.rsplit_once(':') {
+            Some((host, _)) if host != "*" && host != "[::]" => listen.address.clone(),
+            _ => format!("0.0.0.0:{}", listen.port),
+        };
+        if listen.ssl {
+            ssl.get_or_insert(address);
+        } else if !plain.contains(&address) {
+            plain.push(address);
+        }
+    }
+    (plain, ssl)
+}
+
+/// Conversion errors
+#[derive(Debug)]
+pub enum ConversionError {
+    MissingRoot,
+    UnsupportedDirective(String),
+    Serialization(String),
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::MissingRoot => {
+                write!(f, "server block missing root")
+            }
+            ConversionError::UnsupportedDirective(d) => {
+                write!(f, "Unsupported directive: {}", d)
+            }
+            ConversionError::Serialization(e) => {
+                write!(f, "Failed to write TOML: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
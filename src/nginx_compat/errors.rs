@@ -0,0 +1,52 @@
+//! Nginx Configuration Parser Errors
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Result type for Nginx parsing operations
+pub type ParseResult<T> = Result<T, NginxParseError>;
+
+/// Errors that can occur while parsing Nginx configuration
+#[derive(Debug)]
+pub enum NginxParseError {
+    /// I/O error reading file
+    IoError { path: PathBuf, source: io::Error },
+
+    /// Syntax error at specific line
+    SyntaxError { line: usize, message: String },
+
+    /// Block opened at `line` is never closed
+    UnclosedBlock { line: usize },
+
+    /// A file includes itself, directly or through other files
+    CircularInclude { path: PathBuf },
+}
+
+impl fmt::Display for NginxParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NginxParseError::IoError { path, source } => {
+                write!(f, "I/O error reading '{}': {}", path.display(), source)
+            }
+            NginxParseError::SyntaxError { line, message } => {
+                write!(f, "Syntax error at line {}: {}", line, message)
+            }
+            NginxParseError::UnclosedBlock { line } => {
+                write!(f, "Block opened at line {} is never closed", line)
+            }
+            NginxParseError::CircularInclude { path } => {
+                write!(f, "Circular include of '{}'", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for NginxParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NginxParseError::IoError { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
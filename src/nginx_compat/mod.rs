@@ -0,0 +1,307 @@
+//! Nginx Configuration Compatibility Module
+//!
+//! This module reads Nginx nginx.conf and `sites-enabled` files so sites
+//! migrating from Nginx can be converted to VeloServe virtual hosts.
+//!
+//! Supported directives:
+//! - server {}, server_name, listen, root, index
+//! - ssl_certificate, ssl_certificate_key
+//! - location {} with try_files, fastcgi_pass and proxy_pass
+//! - include, when reading from a file
+
+use std::path::{Path, PathBuf};
+
+pub mod converter;
+pub mod errors;
+pub mod parser;
+
+pub use converter::NginxToVeloServeConverter;
+pub use errors::{NginxParseError, ParseResult};
+pub use parser::NginxConfigParser;
+
+/// One directive: `name args...;` or `name args... { block }`
+#[derive(Debug, Clone)]
+pub struct NginxDirective {
+    pub name: String,
+    pub args: Vec<String>,
+    /// Directives inside the braces, for block directives
+    pub block: Option<Vec<NginxDirective>>,
+    /// Line the directive starts on
+    pub line: usize,
+}
+
+/// A `listen` directive
+#[derive(Debug, Clone, Default)]
+pub struct NginxListen {
+    /// Address as written ("443", "[::]:80", "127.0.0.1:8080")
+    pub address: String,
+    pub port: u16,
+    pub ssl: bool,
+    pub default_server: bool,
+}
+
+/// A `location` block
+#[derive(Debug, Clone, Default)]
+pub struct NginxLocation {
+    /// `=`, `~`, `~*` or `^~`
+    pub modifier: Option<String>,
+    pub path: String,
+    pub root: Option<PathBuf>,
+    pub try_files: Vec<String>,
+    pub fastcgi_pass: Option<String>,
+    pub proxy_pass: Option<String>,
+    /// Directives inside the block
+    pub directives: Vec<NginxDirective>,
+}
+
+impl NginxLocation {
+    /// True for `location /`, the fallback for every request
+    pub fn is_root(&self) -> bool {
+        self.modifier.is_none() && self.path == "/"
+    }
+}
+
+/// A parsed `server` block
+#[derive(Debug, Clone, Default)]
+pub struct NginxServer {
+    /// Names from `server_name`
+    pub server_names: Vec<String>,
+    pub listen: Vec<NginxListen>,
+    pub root: Option<PathBuf>,
+    pub index: Vec<String>,
+    pub ssl_certificate: Option<PathBuf>,
+    pub ssl_certificate_key: Option<PathBuf>,
+    /// Server-level `try_files`
+    pub try_files: Vec<String>,
+    /// Locations, nested ones after their parent
+    pub locations: Vec<NginxLocation>,
+    /// Every directive of the block
+    pub directives: Vec<NginxDirective>,
+}
+
+impl NginxServer {
+    /// The `location /` block, if any
+    pub fn root_location(&self) -> Option<&NginxLocation> {
+        self.locations.iter().find(|location| location.is_root())
+    }
+}
+
+/// Main Nginx configuration structure
+#[derive(Debug, Clone, Default)]
+pub struct NginxConfig {
+    /// Top-level directives
+    pub directives: Vec<NginxDirective>,
+    /// Server blocks
+    pub servers: Vec<NginxServer>,
+}
+
+impl NginxConfig {
+    /// Parse Nginx configuration from file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> ParseResult<Self> {
+        NginxConfigParser::new().parse_file(path)
+    }
+
+    /// Parse Nginx configuration from string
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(content: &str) -> ParseResult<Self> {
+        NginxConfigParser::new().parse(content)
+    }
+
+    /// Get the server block for a specific domain
+    pub fn get_server(&self, domain: &str) -> Option<&NginxServer> {
+        self.servers
+            .iter()
+            .find(|server| server.server_names.iter().any(|name| name == domain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Listen;
+
+    const WORDPRESS: &str = r#"
+# WordPress site
+server {
+    listen 80;
+    listen [::]:80;
+    server_name example.com www.example.com;
+    return 301 https://$host$request_uri;
+}
+
+server {
+    listen 443 ssl http2;
+    server_name example.com www.example.com;
+    root /var/www/wordpress;
+    index index.php index.html;
+
+    ssl_certificate /etc/letsencrypt/live/example.com/fullchain.pem;
+    ssl_certificate_key "/etc/letsencrypt/live/example.com/privkey.pem";
+
+    location / {
+        try_files $uri $uri/ /index.php?$args;
+    }
+
+    location ~ \.php$ {
+        include snippets/fastcgi-php.conf;
+        fastcgi_pass unix:/run/php/php8.2-fpm.sock;
+    }
+
+    location /ws/ {
+        proxy_pass http://127.0.0.1:6001;
+        proxy_set_header Upgrade $http_upgrade;
+    }
+
+    location ~* \.(js|css|png)$ {
+        expires max;
+    }
+}
+"#;
+
+    #[test]
+    fn test_parse_wordpress_server() {
+        let config = NginxConfig::from_str(WORDPRESS).unwrap();
+        assert_eq!(config.servers.len(), 2);
+
+        let server = &config.servers[1];
+        assert_eq!(server.server_names, ["example.com", "www.example.com"]);
+        assert_eq!(server.root, Some(PathBuf::from("/var/www/wordpress")));
+        assert_eq!(server.index, ["index.php", "index.html"]);
+        assert_eq!(server.listen[0].port, 443);
+        assert!(server.listen[0].ssl);
+        assert_eq!(
+            server.ssl_certificate_key,
+            Some(PathBuf::from(
+                "/etc/letsencrypt/live/example.com/privkey.pem"
+            ))
+        );
+
+        let root = server.root_location().unwrap();
+        assert_eq!(root.try_files, ["$uri", "$uri/", "/index.php?$args"]);
+        let php = &server.locations[1];
+        assert_eq!(php.modifier.as_deref(), Some("~"));
+        assert_eq!(php.path, "\\.php$");
+        assert_eq!(
+            php.fastcgi_pass.as_deref(),
+            Some("unix:/run/php/php8.2-fpm.sock")
+        );
+
+        let redirect = &config.servers[0];
+        assert_eq!(redirect.listen[1].address, "[::]:80");
+        assert_eq!(redirect.listen[1].port, 80);
+    }
+
+    #[test]
+    fn test_convert_wordpress_server() {
+        let nginx = NginxConfig::from_str(WORDPRESS).unwrap();
+        let config = NginxToVeloServeConverter::new().convert(&nginx);
+
+        assert_eq!(config.server.listen, Listen::One("0.0.0.0:80".to_string()));
+        assert_eq!(config.server.listen_ssl.as_deref(), Some("0.0.0.0:443"));
+
+        let vhost = config
+            .virtualhost
+            .iter()
+            .find(|vhost| vhost.domain == "www.example.com" && vhost.root == "/var/www/wordpress")
+            .unwrap();
+        // The front controller is VeloServe's default
+        assert!(vhost.try_files.is_empty());
+        // The port 80 server only redirected
+        assert_eq!(vhost.redirect_to_https, Some(true));
+        assert_eq!(vhost.index, ["index.php", "index.html"]);
+        assert_eq!(
+            vhost.ssl_certificate.as_deref(),
+            Some("/etc/letsencrypt/live/example.com/fullchain.pem")
+        );
+        assert_eq!(vhost.locations.len(), 1);
+        assert_eq!(vhost.locations[0].path, "/ws/");
+        assert_eq!(
+            vhost.locations[0].proxy_pass.as_deref(),
+            Some("http://127.0.0.1:6001")
+        );
+
+        let toml = NginxToVeloServeConverter::new().to_toml(&nginx).unwrap();
+        let converted = crate::config::Config::from_str(&toml).unwrap();
+        assert_eq!(converted.virtualhost.len(), 2);
+
+        // Strict mode refuses what can't be carried over
+        let strict = NginxConfig::from_str(
+            "server { server_name a.test; root /srv/a; location /app { try_files $uri /app.html; } }",
+        )
+        .unwrap();
+        assert!(NginxToVeloServeConverter::new()
+            .strict(true)
+            .convert(&strict)
+            .virtualhost
+            .is_empty());
+    }
+
+    #[test]
+    fn test_custom_try_files() {
+        let nginx = NginxConfig::from_str(
+            "server { server_name spa.test; root /srv/spa; try_files $uri /index.html =404; }",
+        )
+        .unwrap();
+        let config = NginxToVeloServeConverter::new().convert(&nginx);
+        assert_eq!(
+            config.virtualhost[0].try_files,
+            ["$uri", "/index.html", "=404"]
+        );
+    }
+
+    #[test]
+    fn test_parse_file_expands_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("sites-enabled")).unwrap();
+        std::fs::create_dir_all(root.join("snippets")).unwrap();
+        std::fs::write(
+            root.join("nginx.conf"),
+            "http {\n    include sites-enabled/*;\n    include conf.d/*.conf;\n}\n",
+        )
+        .unwrap();
+        // Relative to nginx.conf's directory, not the site file's
+        std::fs::write(
+            root.join("sites-enabled/example.com"),
+            "server {\n    server_name example.com;\n    include snippets/root.conf;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("snippets/root.conf"), "root /var/www/example;\n").unwrap();
+
+        let config = NginxConfig::from_file(root.join("nginx.conf")).unwrap();
+        assert_eq!(config.servers.len(), 1);
+        assert_eq!(
+            config.servers[0].root,
+            Some(PathBuf::from("/var/www/example"))
+        );
+        let site = NginxConfig::from_file(root.join("sites-enabled/example.com")).unwrap();
+        assert_eq!(
+            site.servers[0].root,
+            Some(PathBuf::from("/var/www/example"))
+        );
+
+        std::fs::write(root.join("snippets/root.conf"), "include nginx.conf;\n").unwrap();
+        assert!(matches!(
+            NginxConfig::from_file(root.join("nginx.conf")),
+            Err(NginxParseError::CircularInclude { .. })
+        ));
+        std::fs::remove_file(root.join("snippets/root.conf")).unwrap();
+        assert!(matches!(
+            NginxConfig::from_file(root.join("nginx.conf")),
+            Err(NginxParseError::IoError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(matches!(
+            NginxConfig::from_str("server {\n    listen 80;\n"),
+            Err(NginxParseError::UnclosedBlock { line: 1 })
+        ));
+        assert!(matches!(
+            NginxConfig::from_str("server {\n    listen 80\n}\n"),
+            Err(NginxParseError::SyntaxError { line: 3, .. })
+        ));
+        assert!(NginxConfig::from_str("}").is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! Nginx Configuration Parser
+//!
+//! Parses nginx.conf and `sites-enabled` files into a directive tree, then
+//! picks out the `server {}` blocks.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::apache_compat::parser::expand_include;
+use crate::nginx_compat::{
+    errors::{NginxParseError, ParseResult},
+    NginxConfig, NginxDirective, NginxListen, NginxLocation, NginxServer,
+};
+
+/// A piece of configuration text
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A word or quoted string
+    Word(String),
+    /// `;`
+    End,
+    /// `{`
+    Open,
+    /// `}`
+    Close,
+}
+
+/// Parser for Nginx configuration files
+pub struct NginxConfigParser {
+    /// Enable verbose logging
+    verbose: bool,
+    /// Directory relative `include` paths resolve against, as nginx's
+    /// configuration prefix (defaults to the nearest directory up from the
+    /// parsed file that holds an nginx.conf, else the file's own)
+    conf_dir: Option<PathBuf>,
+}
+
+impl NginxConfigParser {
+    /// Create a new parser with default settings
+    pub fn new() -> Self {
+        Self {
+            verbose: false,
+            conf_dir: None,
+        }
+    }
+
+    /// Enable/disable verbose logging
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Set the directory relative include paths resolve against
+    pub fn conf_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.conf_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Parse configuration from a file, reading the files it includes
+    pub fn parse_file<P: AsRef<Path>>(&self, path: P) -> ParseResult<NginxConfig> {
+        let path = path.as_ref();
+        let directives = parse_directives(&read_file(path)?)?;
+        let conf_dir = self
+            .conf_dir
+            .clone()
+            .unwrap_or_else(|| default_conf_dir(path));
+        let mut visited = HashSet::from([canonical(path)?]);
+        let directives = self.expand_includes(directives, &conf_dir, &mut visited)?;
+
+        Ok(self.config(directives))
+    }
+
+    /// Parse configuration from string content; `include`s are left as
+    /// they are
+    pub fn parse(&self, content: &str) -> ParseResult<NginxConfig> {
+        Ok(self.config(parse_directives(content)?))
+    }
+
+    fn config(&self, directives: Vec<NginxDirective>) -> NginxConfig {
+        let mut servers = Vec::new();
+        self.collect_servers(&directives, &mut servers);
+
+        NginxConfig {
+            directives,
+            servers,
+        }
+    }
+
+    /// Replace each `include` with the directives of the files it names.
+    /// `visited` holds the canonical paths of the files being read.
+    fn expand_includes(
+        &self,
+        directives: Vec<NginxDirective>,
+        conf_dir: &Path,
+        visited: &mut HashSet<PathBuf>,
+    ) -> ParseResult<Vec<NginxDirective>> {
+        let mut expanded = Vec::new();
+        for mut directive in directives {
+            if let Some(block) = directive.block.take() {
+                directive.block = Some(self.expand_includes(block, conf_dir, visited)?);
+            } else if directive.name == "include" {
+                let [pattern] = directive.args.as_slice() else {
+                    return Err(NginxParseError::SyntaxError {
+                        line: directive.line,
+                        message: "include takes one file".to_string(),
+                    });
+                };
+                for file in include_files(&conf_dir.join(pattern))? {
+                    let path = canonical(&file)?;
+                    if !visited.insert(path.clone()) {
+                        return Err(NginxParseError::CircularInclude { path });
+                    }
+                    let included = parse_directives(&read_file(&file)?)?;
+                    expanded.extend(self.expand_includes(included, conf_dir, visited)?);
+                    visited.remove(&path);
+                }
+                continue;
+            }
+            expanded.push(directive);
+        }
+        Ok(expanded)
+    }
+
+    /// Find `server` blocks, at the top level of a site file or inside `http`
+    fn collect_servers(&self, directives: &[NginxDirective], servers: &mut Vec<NginxServer>) {
+        for directive in directives {
+            match (directive.name.as_str(), &directive.block) {
+                ("server", Some(block)) => servers.push(self.parse_server(block)),
+                ("http", Some(block)) => self.collect_servers(block, servers),
+                _ => {}
+            }
+        }
+    }
+
+    /// Parse the content of a `server` block
+    fn parse_server(&self, content: &[NginxDirective]) -> NginxServer {
+        let mut server = NginxServer {
+            directives: content.to_vec(),
+            ..Default::default()
+        };
+
+        for directive in content {
+            let args = &directive.args;
+            match directive.name.as_str() {
+                "server_name" => server.server_names.extend(args.iter().cloned()),
+                "listen" => match parse_listen(args) {
+                    Some(listen) => server.listen.push(listen),
+                    None => {
+                        if self.verbose {
+                            eprintln!(
+                                "Warning at line {}: unsupported listen {:?}",
+                                directive.line, args
+                            );
+                        }
+                    }
+                },
+                "root" => server.root = args.first().map(PathBuf::from),
+                "index" => server.index = args.clone(),
+                "ssl_certificate" => server.ssl_certificate = args.first().map(PathBuf::from),
+                "ssl_certificate_key" => {
+                    server.ssl_certificate_key = args.first().map(PathBuf::from)
+                }
+                "try_files" => server.try_files = args.clone(),
+                "location" => self.parse_location(directive, &mut server.locations),
+                _ => {}
+            }
+        }
+
+        server
+    }
+
+    /// Parse a `location` block, and the locations nested in it, into `locations`
+    fn parse_location(&self, directive: &NginxDirective, locations: &mut Vec<NginxLocation>) {
+        let (modifier, path) = match directive.args.as_slice() {
+            [path] => (None, path.clone()),
+            [modifier, path] => (Some(modifier.clone()), path.clone()),
+            _ => {
+                if self.verbose {
+                    eprintln!(
+                        "Warning at line {}: malformed location {:?}",
+                        directive.line, directive.args
+                    );
+                }
+                return;
+            }
+        };
+
+        let content = directive.block.as_deref().unwrap_or_default();
+        let mut location = NginxLocation {
+            modifier,
+            path,
+            directives: content.to_vec(),
+            ..Default::default()
+        };
+        let mut nested = Vec::new();
+        for inner in content {
+            let first = inner.args.first().cloned();
+            match inner.name.as_str() {
+                "root" => location.root = first.map(PathBuf::from),
+                "try_files" => location.try_files = inner.args.clone(),
+                "fastcgi_pass" => location.fastcgi_pass = first,
+                "proxy_pass" => location.proxy_pass = first,
+                "location" => self.parse_location(inner, &mut nested),
+                _ => {}
+            }
+        }
+
+        locations.push(location);
+        locations.extend(nested);
+    }
+}
+
+impl Default for NginxConfigParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_directives(content: &str) -> ParseResult<Vec<NginxDirective>> {
+    let mut tokens = tokenize(content)?.into_iter();
+    parse_block(&mut tokens, None)
+}
+
+/// Files an `include` names. As in nginx, a pattern that matches nothing is
+/// fine but a missing file is not.
+fn include_files(pattern: &Path) -> ParseResult<Vec<PathBuf>> {
+    let wildcard = pattern.to_string_lossy().contains(['*', '?']);
+    match expand_include(pattern) {
+        Ok(files) if !files.is_empty() || wildcard => Ok(files),
+        Ok(_) => Err(NginxParseError::IoError {
+            path: pattern.to_path_buf(),
+            source: io::Error::new(io::ErrorKind::NotFound, "no matching files"),
+        }),
+        Err(e) if wildcard && e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(NginxParseError::IoError {
+            path: pattern.to_path_buf(),
+            source: e,
+        }),
+    }
+}
+
+/// nginx resolves includes against the directory of nginx.conf, which a
+/// site file in `sites-enabled` or `conf.d` sits below
+fn default_conf_dir(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    dir.ancestors()
+        .find(|ancestor| ancestor.join("nginx.conf").is_file())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+fn read_file(path: &Path) -> ParseResult<String> {
+    fs::read_to_string(path).map_err(|e| NginxParseError::IoError {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+fn canonical(path: &Path) -> ParseResult<PathBuf> {
+    fs::canonicalize(path).map_err(|e| NginxParseError::IoError {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+/// Parse directives up to the `}` closing a block opened at `opened_at`, or
+/// to the end of input at the top level
+fn parse_block(
+    tokens: &mut impl Iterator<Item = (Token, usize)>,
+    opened_at: Option<usize>,
+) -> ParseResult<Vec<NginxDirective>> {
+    let mut directives = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut start = 0;
+
+    while let Some((token, line)) = tokens.next() {
+        match token {
+            Token::Word(word) => {
+                if words.is_empty() {
+                    start = line;
+                }
+                words.push(word);
+            }
+            Token::End | Token::Open if words.is_empty() => {
+                return Err(NginxParseError::SyntaxError {
+                    line,
+                    message: "missing directive name".to_string(),
+                });
+            }
+            Token::End => {
+                let name = words.remove(0);
+                directives.push(NginxDirective {
+                    name,
+                    args: std::mem::take(&mut words),
+                    block: None,
+                    line: start,
+                });
+            }
+            Token::Open => {
+                let name = words.remove(0);
+                let block = parse_block(tokens, Some(line))?;
+                directives.push(NginxDirective {
+                    name,
+                    args: std::mem::take(&mut words),
+                    block: Some(block),
+                    line: start,
+                });
+            }
+            Token::Close => {
+                if !words.is_empty() {
+                    return Err(NginxParseError::SyntaxError {
+                        line,
+                        message: format!("missing ';' after '{}'", words[0]),
+                    });
+                }
+                if opened_at.is_none() {
+                    return Err(NginxParseError::SyntaxError {
+                        line,
+                        message: "unexpected '}'".to_string(),
+                    });
+                }
+                return Ok(directives);
+            }
+        }
+    }
+
+    if let Some(line) = opened_at {
+        return Err(NginxParseError::UnclosedBlock { line });
+    }
+    if !words.is_empty() {
+        return Err(NginxParseError::SyntaxError {
+            line: start,
+            message: format!("missing ';' after '{}'", words[0]),
+        });
+    }
+    Ok(directives)
+}
+
+/// Split configuration text into tokens tagged with their line number
+fn tokenize(content: &str) -> ParseResult<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = content.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => {
+                // Comment up to the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            ';' => tokens.push((Token::End, line)),
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            '"' | '\'' => {
+                let start = line;
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => {
+                            if let Some(escaped) = chars.next() {
+                                word.push(escaped);
+                            }
+                        }
+                        Some(other) => {
+                            if other == '\n' {
+                                line += 1;
+                            }
+                            word.push(other);
+                        }
+                        None => {
+                            return Err(NginxParseError::SyntaxError {
+                                line: start,
+                                message: "unterminated quoted string".to_string(),
+                            })
+                        }
+                    }
+                }
+                tokens.push((Token::Word(word), start));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || matches!(next, ';' | '{' | '}') {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse `listen` arguments ("443 ssl http2", "[::]:80 default_server")
+fn parse_listen(args: &[String]) -> Option<NginxListen> {
+    let (address, flags) = args.split_first()?;
+    let port = match address.rsplit_once(':') {
+        // `unix:` sockets have no port and are left out
+        Some((_, port)) => port.parse().ok()?,
+        // A bare address listens on 80
+        None => address.parse().unwrap_or(80),
+    };
+    Some(NginxListen {
+        address: address.clone(),
+        port,
+        ssl: flags.iter().any(|flag| flag == "ssl"),
+        default_server: flags.iter().any(|flag| flag == "default_server"),
+    })
+}
@@ -1,6 +1,6 @@
 //! PHP Integration Module
 //!
-//! VeloServe supports two PHP execution modes:
+//! VeloServe supports three PHP execution modes:
 //!
 //! ## 1. CGI Mode (Default)
 //!
@@ -10,7 +10,16 @@
 //! ./veloserve --config veloserve.toml
 //! ```
 //!
-//! ## 2. Embedded SAPI Mode
+//! ## 2. Socket Mode (vephp)
+//!
+//! Sends requests to a persistent `vephp` worker over a Unix socket, reusing
+//! connections between requests. Falls back to CGI while the socket is
+//! unavailable.
+//! ```bash
+//! vephp --socket /run/veloserve/php.sock
+//! ```
+//!
+//! ## 3. Embedded SAPI Mode
 //!
 //! Links directly against `libphp.so` for maximum performance.
 //! PHP runs inside VeloServe - no process spawning!
@@ -47,22 +56,139 @@ pub mod ffi;
 // SAPI module for embedded PHP
 pub mod sapi;
 
-use crate::config::{PhpConfig, PhpMode};
+// Wire protocol shared with the vephp worker
+pub mod protocol;
+
+// Client for vephp's Unix socket
+#[cfg(unix)]
+pub mod socket;
+
+use crate::config::{format_duration, PhpConfig, PhpMode};
 use crate::php::sapi::PhpResponse;
+use crate::server::{ConnectionInfo, OriginalUri};
 use anyhow::{anyhow, Result};
+use bytes::Bytes;
 use hyper::http::request::Parts;
 use hyper::Request;
 use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
-use tokio::sync::Semaphore;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 
+/// Startup state of a PHP pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolState {
+    /// PHP is disabled in the configuration
+    Disabled,
+    /// The availability probe is still running
+    Starting,
+    /// PHP is available and accepting requests
+    Ready,
+    /// The probe finished but PHP is not usable
+    Unavailable,
+}
+
+impl PoolState {
+    /// Name used in status output
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PoolState::Disabled => "disabled",
+            PoolState::Starting => "starting",
+            PoolState::Ready => "ready",
+            PoolState::Unavailable => "unavailable",
+        }
+    }
+}
+
+/// Largest CGI header block read before giving up on streaming detection
+const MAX_CGI_HEAD: usize = 64 * 1024;
+
+/// Output of a CGI run
+pub enum CgiOutput {
+    /// Everything the script printed
+    Buffered(String),
+    /// The script sent `X-Accel-Buffering: no`; its body is still being produced
+    Streaming(Box<CgiStream>),
+}
+
+/// A running script whose header block has been read
+pub struct CgiStream {
+    /// CGI header block, including the blank line that ends it
+    pub head: String,
+    leftover: Vec<u8>,
+    child: Child,
+    stdout: ChildStdout,
+    stderr: JoinHandle<Vec<u8>>,
+    slot: Option<WorkerSlot>,
+}
+
+impl CgiStream {
+    /// Forward the rest of the output as it arrives, for at most `limit`.
+    ///
+    /// The script is killed when the limit passes or the receiver is dropped
+    /// (the client went away); it keeps its worker permit until then.
+    pub fn into_channel(self, limit: std::time::Duration) -> mpsc::Receiver<Bytes> {
+        let (tx, rx) = mpsc::channel(16);
+        let CgiStream {
+            head: _,
+            leftover,
+            mut child,
+            mut stdout,
+            stderr,
+            slot,
+        } = self;
+
+        tokio::spawn(async move {
+            let deadline = tokio::time::Instant::now() + limit;
+            let mut pending = (!leftover.is_empty()).then(|| Bytes::from(leftover));
+            let mut buf = vec![0u8; 8192];
+            loop {
+                if let Some(chunk) = pending.take() {
+                    if tx.send(chunk).await.is_err() {
+                        break;
+                    }
+                }
+                tokio::select! {
+                    read = stdout.read(&mut buf) => match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => pending = Some(Bytes::copy_from_slice(&buf[..n])),
+                    },
+                    _ = tokio::time::sleep_until(deadline) => {
+                        warn!("Streamed PHP response cut off after {}", format_duration(limit));
+                        break;
+                    }
+                    _ = tx.closed() => break,
+                }
+            }
+
+            let _ = child.start_kill();
+            let _ = child.wait().await;
+            log_stderr(&stderr.await.unwrap_or_default());
+            drop(slot);
+        });
+        rx
+    }
+}
+
+/// A held worker permit, counted in `active_workers` until dropped
+struct WorkerSlot {
+    _permit: OwnedSemaphorePermit,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for WorkerSlot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 /// PHP worker pool for executing PHP scripts
 pub struct PhpPool {
     /// Pool configuration
@@ -75,7 +201,7 @@ pub struct PhpPool {
     php_binary: PathBuf,
 
     /// Number of active workers
-    active_workers: AtomicUsize,
+    active_workers: Arc<AtomicUsize>,
 
     /// Request semaphore (limits concurrent PHP executions)
     semaphore: Arc<Semaphore>,
@@ -86,9 +212,25 @@ pub struct PhpPool {
     /// Is PHP actually available (binary found and working)
     available: AtomicBool,
 
+    /// Startup state, watched by requests that arrive before the probe finishes
+    state: watch::Sender<PoolState>,
+
+    /// How long the startup probe took in milliseconds
+    startup_ms: AtomicU64,
+
+    /// Spawn attempts retried after a transient failure
+    spawn_retries: AtomicU64,
+
+    /// Spawns that still failed after exhausting all retries
+    spawn_retry_exhausted: AtomicU64,
+
     /// PHP version string
     php_version: Mutex<Option<String>>,
 
+    /// Connections to vephp (socket mode only)
+    #[cfg(unix)]
+    socket: Option<socket::SocketClient>,
+
     /// Embedded PHP runtime (when using php-embed)
     #[cfg(feature = "php-embed")]
     embed_sapi: Mutex<Option<sapi::PhpSapi>>,
@@ -109,11 +251,22 @@ impl PhpPool {
             config: config.clone(),
             mode: config.mode.clone(),
             php_binary,
-            active_workers: AtomicUsize::new(0),
+            active_workers: Arc::new(AtomicUsize::new(0)),
             semaphore: Arc::new(Semaphore::new(config.workers)),
             running: AtomicBool::new(false),
             available: AtomicBool::new(false),
+            state: watch::Sender::new(if config.enable {
+                PoolState::Starting
+            } else {
+                PoolState::Disabled
+            }),
+            startup_ms: AtomicU64::new(0),
+            spawn_retries: AtomicU64::new(0),
+            spawn_retry_exhausted: AtomicU64::new(0),
             php_version: Mutex::new(None),
+            #[cfg(unix)]
+            socket: (config.mode == PhpMode::Socket)
+                .then(|| socket::SocketClient::new(&config.socket_path, config.workers)),
             #[cfg(feature = "php-embed")]
             embed_sapi: Mutex::new(None),
         }
@@ -124,8 +277,42 @@ impl PhpPool {
         self.available.load(Ordering::SeqCst)
     }
 
+    /// Current startup state
+    pub fn state(&self) -> PoolState {
+        *self.state.borrow()
+    }
+
+    /// Wait up to `grace` for a starting pool to become ready or fail
+    pub async fn wait_ready(&self, grace: std::time::Duration) -> PoolState {
+        let mut state = self.state.subscribe();
+        let _ = tokio::time::timeout(grace, state.wait_for(|state| *state != PoolState::Starting))
+            .await;
+        self.state()
+    }
+
     /// Start the PHP worker pool
+    ///
+    /// Safe to run in the background: until it returns, `state()` reports
+    /// `Starting` and PHP requests can `wait_ready` on it.
     pub async fn start(&self) -> Result<()> {
+        let started = std::time::Instant::now();
+        let result = self.probe().await;
+        self.startup_ms
+            .store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+
+        let state = if self.is_available() {
+            PoolState::Ready
+        } else if self.config.enable {
+            PoolState::Unavailable
+        } else {
+            PoolState::Disabled
+        };
+        self.state.send_replace(state);
+        result
+    }
+
+    /// Check that the configured PHP runtime works
+    async fn probe(&self) -> Result<()> {
         if !self.config.enable {
             info!("PHP support disabled in configuration");
             self.available.store(false, Ordering::SeqCst);
@@ -174,47 +361,29 @@ impl PhpPool {
                 let socket_path = &self.config.socket_path;
                 info!("PHP socket mode: connecting to vephp at {}", socket_path);
 
-                if std::path::Path::new(socket_path).exists() {
-                    info!("vephp socket found at {}", socket_path);
-                    *self.php_version.lock() = Some(format!("vephp ({})", socket_path));
-                    self.available.store(true, Ordering::SeqCst);
-                } else {
-                    warn!(
-                        "vephp socket not found at {}. Start vephp first: vephp -s {}",
-                        socket_path, socket_path
-                    );
-                    self.available.store(false, Ordering::SeqCst);
-                    return Ok(());
-                }
-            }
-            PhpMode::Cgi => {
-                // Verify PHP binary exists
-                if !self.php_binary.exists()
-                    && self.php_binary.to_str() != Some("php")
-                    && self.php_binary.to_str() != Some("php-cgi")
-                {
-                    warn!(
-                        "PHP binary not found at {:?}, PHP support disabled",
-                        self.php_binary
-                    );
-                    self.available.store(false, Ordering::SeqCst);
-                    return Ok(());
-                }
-
-                // Test PHP installation
-                match self.get_php_version().await {
-                    Ok(version) => {
-                        info!("PHP version: {}", version);
-                        *self.php_version.lock() = Some(version);
+                match self.socket_health_check().await {
+                    Ok(()) => {
+                        info!("vephp is answering at {}", socket_path);
+                        *self.php_version.lock() = Some(format!("vephp ({})", socket_path));
                         self.available.store(true, Ordering::SeqCst);
                     }
                     Err(e) => {
-                        warn!("PHP not working: {}, PHP support disabled", e);
-                        self.available.store(false, Ordering::SeqCst);
-                        return Ok(());
+                        // Requests retry the socket and run via php-cgi until vephp is up
+                        warn!(
+                            "vephp not reachable at {} ({}), falling back to CGI. Start vephp first: vephp -s {}",
+                            socket_path, e, socket_path
+                        );
+                        if !self.probe_cgi().await {
+                            return Ok(());
+                        }
                     }
                 }
             }
+            PhpMode::Cgi => {
+                if !self.probe_cgi().await {
+                    return Ok(());
+                }
+            }
         }
 
         self.running.store(true, Ordering::SeqCst);
@@ -227,6 +396,54 @@ impl PhpPool {
         Ok(())
     }
 
+    /// Check that the PHP binary exists and runs, marking the pool available if so
+    async fn probe_cgi(&self) -> bool {
+        // Verify PHP binary exists
+        if !self.php_binary.exists()
+            && self.php_binary.to_str() != Some("php")
+            && self.php_binary.to_str() != Some("php-cgi")
+        {
+            warn!(
+                "PHP binary not found at {:?}, PHP support disabled",
+                self.php_binary
+            );
+            self.available.store(false, Ordering::SeqCst);
+            return false;
+        }
+
+        // Test PHP installation
+        match self.get_php_version().await {
+            Ok(version) => {
+                info!("PHP version: {}", version);
+                *self.php_version.lock() = Some(version);
+                self.available.store(true, Ordering::SeqCst);
+                true
+            }
+            Err(e) => {
+                warn!("PHP not working: {}, PHP support disabled", e);
+                self.available.store(false, Ordering::SeqCst);
+                false
+            }
+        }
+    }
+
+    /// Ask vephp whether it is healthy
+    async fn socket_health_check(&self) -> Result<()> {
+        #[cfg(unix)]
+        if let Some(client) = &self.socket {
+            let request = protocol::PhpRequest::health_check();
+            let check = client.call(&request);
+            let response = tokio::time::timeout(std::time::Duration::from_secs(5), check)
+                .await
+                .map_err(|_| anyhow!("health check timed out"))??;
+            return match response.success {
+                true => Ok(()),
+                false => Err(anyhow!(response.error.unwrap_or_default())),
+            };
+        }
+        Err(anyhow!("vephp sockets require Unix"))
+    }
+
     /// Execute a PHP script with full CGI environment (like Nginx + PHP-FPM)
     ///
     /// # Arguments
@@ -302,7 +519,7 @@ impl PhpPool {
         script_name: &str,
         path_info: &str,
         body: &[u8],
-    ) -> Result<String> {
+    ) -> Result<CgiOutput> {
         if !self.is_available() {
             return Err(anyhow!("PHP support is not available"));
         }
@@ -311,16 +528,22 @@ impl PhpPool {
             return Err(anyhow!("PHP pool not in CGI/Socket mode"));
         }
 
-        // Acquire semaphore permit (limits concurrent PHP processes)
-        let _permit = self
+        // Acquire semaphore permit (limits concurrent PHP processes); a
+        // streaming response keeps it until the stream ends
+        let permit = self
             .semaphore
-            .acquire()
+            .clone()
+            .acquire_owned()
             .await
             .map_err(|_| anyhow!("Failed to acquire PHP worker permit"))?;
-
         self.active_workers.fetch_add(1, Ordering::SeqCst);
-        let result = self
-            .do_execute_cgi(
+        let slot = WorkerSlot {
+            _permit: permit,
+            active: self.active_workers.clone(),
+        };
+
+        let mut output = if self.mode == PhpMode::Socket {
+            self.execute_socket(
                 script_path,
                 req_parts,
                 doc_root,
@@ -328,10 +551,22 @@ impl PhpPool {
                 path_info,
                 body,
             )
-            .await;
-        self.active_workers.fetch_sub(1, Ordering::SeqCst);
-
-        result
+            .await?
+        } else {
+            self.do_execute_cgi(
+                script_path,
+                req_parts,
+                doc_root,
+                script_name,
+                path_info,
+                body,
+            )
+            .await?
+        };
+        if let CgiOutput::Streaming(stream) = &mut output {
+            stream.slot = Some(slot);
+        }
+        Ok(output)
     }
 
     /// Execute a PHP script (simple mode - for backward compatibility)
@@ -397,6 +632,8 @@ impl PhpPool {
         // Build command
         let mut cmd = Command::new(&self.php_binary);
         self.configure_php_command(&mut cmd);
+        // Request timeouts drop this future; don't leave the script running
+        cmd.kill_on_drop(true);
 
         // Execute the PHP script directly
         cmd.arg(script_path);
@@ -415,9 +652,7 @@ impl PhpPool {
             .stderr(Stdio::piped());
 
         // Spawn process
-        let mut child = cmd
-            .spawn()
-            .map_err(|e| anyhow!("Failed to spawn PHP: {}", e))?;
+        let mut child = self.spawn_php(&mut cmd).await?;
 
         // Write POST body to stdin
         if !body.is_empty() {
@@ -432,18 +667,15 @@ impl PhpPool {
         }
 
         // Wait for completion with timeout
-        let output = tokio::time::timeout(
-            std::time::Duration::from_secs(self.config.max_execution_time),
-            child.wait_with_output(),
-        )
-        .await
-        .map_err(|_| {
-            anyhow!(
-                "PHP script execution timed out after {}s",
-                self.config.max_execution_time
-            )
-        })?
-        .map_err(|e| anyhow!("Failed to execute PHP script: {}", e))?;
+        let output = tokio::time::timeout(self.config.max_execution_time, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "PHP script execution timed out after {}",
+                    format_duration(self.config.max_execution_time)
+                )
+            })?
+            .map_err(|e| anyhow!("Failed to execute PHP script: {}", e))?;
 
         // Log any errors
         if !output.stderr.is_empty() {
@@ -471,7 +703,7 @@ impl PhpPool {
         script_name: &str,
         path_info: &str,
         body: &[u8],
-    ) -> Result<String> {
+    ) -> Result<CgiOutput> {
         debug!(
             "Executing PHP CGI: {} (script_name={}, path_info={}, body_len={})",
             script_path.display(),
@@ -492,6 +724,7 @@ impl PhpPool {
         // Build command
         let mut cmd = Command::new(&self.php_binary);
         self.configure_php_command(&mut cmd);
+        cmd.kill_on_drop(true);
 
         // Execute the PHP script directly
         cmd.arg(script_path);
@@ -510,9 +743,7 @@ impl PhpPool {
             .stderr(Stdio::piped());
 
         // Spawn process
-        let mut child = cmd
-            .spawn()
-            .map_err(|e| anyhow!("Failed to spawn PHP: {}", e))?;
+        let mut child = self.spawn_php(&mut cmd).await?;
 
         // Write POST body to stdin
         if !body.is_empty() {
@@ -526,60 +757,231 @@ impl PhpPool {
             drop(stdin);
         }
 
-        // Wait for completion with timeout
-        let output = tokio::time::timeout(
-            std::time::Duration::from_secs(self.config.max_execution_time),
-            child.wait_with_output(),
-        )
-        .await
-        .map_err(|_| {
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("PHP stdout was not captured"))?;
+        // Drain stderr alongside stdout so a chatty script never blocks on it
+        let stderr = child.stderr.take();
+        let stderr = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            if let Some(mut stderr) = stderr {
+                let _ = stderr.read_to_end(&mut buf).await;
+            }
+            buf
+        });
+
+        let deadline = tokio::time::Instant::now() + self.config.max_execution_time;
+        let timed_out = || {
             anyhow!(
-                "PHP script execution timed out after {}s",
-                self.config.max_execution_time
+                "PHP script execution timed out after {}",
+                format_duration(self.config.max_execution_time)
             )
-        })?
+        };
+
+        // Read up to the end of the header block to see if the script wants streaming
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; 8192];
+        let head_end = loop {
+            if let Some(end) = cgi_head_end(&output) {
+                break Some(end);
+            }
+            if output.len() > MAX_CGI_HEAD {
+                break None;
+            }
+            let n = tokio::time::timeout_at(deadline, stdout.read(&mut buf))
+                .await
+                .map_err(|_| timed_out())?
+                .map_err(|e| anyhow!("Failed to read PHP output: {}", e))?;
+            if n == 0 {
+                break None;
+            }
+            output.extend_from_slice(&buf[..n]);
+        };
+
+        if let Some(end) = head_end {
+            let head = String::from_utf8_lossy(&output[..end]).into_owned();
+            if wants_streaming(&head) {
+                return Ok(CgiOutput::Streaming(Box::new(CgiStream {
+                    head,
+                    leftover: output.split_off(end),
+                    child,
+                    stdout,
+                    stderr,
+                    slot: None,
+                })));
+            }
+        }
+
+        // Wait for completion with timeout
+        let status = tokio::time::timeout_at(deadline, async {
+            stdout.read_to_end(&mut output).await?;
+            child.wait().await
+        })
+        .await
+        .map_err(|_| timed_out())?
         .map_err(|e| anyhow!("Failed to execute PHP script: {}", e))?;
+        let stderr = stderr.await.unwrap_or_default();
 
         // Log any errors
-        if !output.stderr.is_empty() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            if !stderr.trim().is_empty() {
-                warn!("PHP stderr: {}", stderr.trim());
-            }
-        }
+        log_stderr(&stderr);
 
         // Check exit status but still return output if we have it
-        if !output.status.success() && output.stdout.is_empty() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        if !status.success() && output.is_empty() {
+            let stderr = String::from_utf8_lossy(&stderr);
             return Err(anyhow!("PHP script failed: {}", stderr));
         }
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        Ok(CgiOutput::Buffered(
+            String::from_utf8_lossy(&output).to_string(),
+        ))
+    }
+
+    /// Internal: Execute PHP on the vephp worker, running it via CGI instead
+    /// while the socket is unavailable.
+    ///
+    /// vephp returns php-cgi's raw output, so the result is parsed like a CGI
+    /// response; it is always buffered.
+    async fn execute_socket(
+        &self,
+        script_path: &Path,
+        req_parts: &hyper::http::request::Parts,
+        doc_root: &Path,
+        script_name: &str,
+        path_info: &str,
+        body: &[u8],
+    ) -> Result<CgiOutput> {
+        #[cfg(unix)]
+        if let Some(client) = &self.socket {
+            debug!(
+                "Executing PHP via vephp: {} (script_name={}, path_info={}, body_len={})",
+                script_path.display(),
+                script_name,
+                path_info,
+                body.len()
+            );
+
+            let mut env =
+                build_cgi_env_from_parts(req_parts, script_path, doc_root, script_name, path_info);
+            if !body.is_empty() {
+                env.insert("CONTENT_LENGTH".to_string(), body.len().to_string());
+            }
+            let request = socket_request(
+                req_parts,
+                script_path,
+                doc_root,
+                env,
+                body,
+                self.config.max_execution_time,
+            );
+
+            let call = tokio::time::timeout(self.config.max_execution_time, client.call(&request));
+            match call.await {
+                Ok(Ok(response)) => return socket_output(response),
+                Ok(Err(socket::SocketError::Unavailable(e))) => debug!(
+                    "vephp unavailable at {} ({}), running {} via CGI",
+                    client.path().display(),
+                    e,
+                    script_path.display()
+                ),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => {
+                    return Err(anyhow!(
+                        "PHP script execution timed out after {}",
+                        format_duration(self.config.max_execution_time)
+                    ))
+                }
+            }
+        }
+
+        self.do_execute_cgi(
+            script_path,
+            req_parts,
+            doc_root,
+            script_name,
+            path_info,
+            body,
+        )
+        .await
     }
 
     /// Internal: Execute PHP with minimal environment
     async fn do_execute_simple(&self, script_path: &Path) -> Result<String> {
         let mut cmd = Command::new(&self.php_binary);
         self.configure_php_command(&mut cmd);
+        cmd.kill_on_drop(true);
         cmd.arg(script_path);
 
         if let Some(parent) = script_path.parent() {
             cmd.current_dir(parent);
         }
 
-        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
-        let output = tokio::time::timeout(
-            std::time::Duration::from_secs(self.config.max_execution_time),
-            cmd.output(),
-        )
-        .await
-        .map_err(|_| anyhow!("PHP script execution timed out"))?
-        .map_err(|e| anyhow!("Failed to execute PHP: {}", e))?;
+        let child = self.spawn_php(&mut cmd).await?;
+
+        let output = tokio::time::timeout(self.config.max_execution_time, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("PHP script execution timed out"))?
+            .map_err(|e| anyhow!("Failed to execute PHP: {}", e))?;
 
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
+    /// Spawn the PHP binary, retrying transient failures with jittered backoff.
+    ///
+    /// Called while the caller holds a worker permit, so retries do not let
+    /// more PHP processes run than `workers` allows.
+    async fn spawn_php(&self, cmd: &mut Command) -> Result<Child> {
+        let mut attempt = 0u32;
+        loop {
+            let err = match cmd.spawn() {
+                Ok(child) => return Ok(child),
+                Err(err) => err,
+            };
+
+            match classify_spawn_error(&err) {
+                SpawnErrorClass::Permanent => {
+                    return Err(match err.kind() {
+                        std::io::ErrorKind::NotFound => anyhow!(
+                            "Failed to spawn PHP: binary not found at {}",
+                            self.php_binary.display()
+                        ),
+                        std::io::ErrorKind::PermissionDenied => anyhow!(
+                            "Failed to spawn PHP: permission denied executing {}",
+                            self.php_binary.display()
+                        ),
+                        _ => anyhow!("Failed to spawn PHP: {}", err),
+                    });
+                }
+                SpawnErrorClass::Transient if attempt < self.config.spawn_retries => {
+                    attempt += 1;
+                    self.spawn_retries.fetch_add(1, Ordering::Relaxed);
+                    let delay = spawn_backoff(self.config.spawn_retry_backoff_ms, attempt);
+                    debug!(
+                        "Transient PHP spawn failure ({}), retry {}/{} in {:?}",
+                        err, attempt, self.config.spawn_retries, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                SpawnErrorClass::Transient => {
+                    self.spawn_retry_exhausted.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "PHP spawn still failing after {} retries: {}",
+                        self.config.spawn_retries, err
+                    );
+                    return Err(anyhow!(
+                        "Failed to spawn PHP after {} retries: {}",
+                        self.config.spawn_retries,
+                        err
+                    ));
+                }
+            }
+        }
+    }
+
     /// Configure PHP command with standard settings
     fn configure_php_command(&self, cmd: &mut Command) {
         // Memory limit
@@ -589,7 +991,8 @@ impl PhpPool {
         // Execution time
         cmd.arg("-d").arg(format!(
             "max_execution_time={}",
-            self.config.max_execution_time
+            // PHP only takes whole seconds; never round a short limit down to 0 (unlimited)
+            self.config.max_execution_time.as_secs_f64().ceil() as u64
         ));
 
         // Security settings
@@ -637,12 +1040,17 @@ impl PhpPool {
             "enabled": self.config.enable,
             "available": self.available.load(Ordering::SeqCst),
             "running": self.running.load(Ordering::SeqCst),
+            "state": self.state().as_str(),
+            "startup_ms": self.startup_ms.load(Ordering::Relaxed),
             "mode": format!("{:?}", self.mode),
             "version": self.php_version.lock().clone(),
             "max_workers": self.config.workers,
             "active_workers": self.active_workers.load(Ordering::SeqCst),
             "memory_limit": self.config.memory_limit,
-            "max_execution_time": self.config.max_execution_time,
+            "max_execution_time": format_duration(self.config.max_execution_time),
+            "spawn_retries": self.spawn_retries.load(Ordering::Relaxed),
+            "spawn_retry_exhausted": self.spawn_retry_exhausted.load(Ordering::Relaxed),
+            "embed_worker_restarts": sapi::worker_restarts(),
         })
     }
 
@@ -671,7 +1079,15 @@ impl PhpPool {
 
         #[cfg(not(feature = "php-embed"))]
         {
-            return Err(anyhow!("php-embed feature not compiled"));
+            let _ = (
+                script_path,
+                req_parts,
+                doc_root,
+                script_name,
+                path_info,
+                body,
+            );
+            Err(anyhow!("php-embed feature not compiled"))
         }
 
         #[cfg(feature = "php-embed")]
@@ -724,6 +1140,35 @@ impl PhpPool {
     }
 }
 
+/// Whether a failed PHP spawn is worth retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpawnErrorClass {
+    /// Resource pressure (EAGAIN, ENOMEM) that usually clears within milliseconds
+    Transient,
+    /// Missing binary, permission problems and anything else retrying won't fix
+    Permanent,
+}
+
+/// Classify a spawn error from `Command::spawn`
+fn classify_spawn_error(err: &std::io::Error) -> SpawnErrorClass {
+    match err.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::OutOfMemory => {
+            SpawnErrorClass::Transient
+        }
+        _ => SpawnErrorClass::Permanent,
+    }
+}
+
+/// Exponential backoff for spawn retry `attempt` (1-based) with 50-100% jitter
+fn spawn_backoff(base_ms: u64, attempt: u32) -> std::time::Duration {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let ceiling = base_ms.saturating_mul(1u64 << (attempt - 1).min(6));
+    let jitter = RandomState::new().build_hasher().finish() % (ceiling / 2 + 1);
+    std::time::Duration::from_millis(ceiling / 2 + jitter)
+}
+
 /// Find PHP binary on the system.
 ///
 /// Search order:
@@ -820,7 +1265,11 @@ fn build_cgi_env_from_parts(
     env.insert("REQUEST_METHOD".to_string(), parts.method.to_string());
 
     // Request URI (original, includes query string)
-    env.insert("REQUEST_URI".to_string(), parts.uri.to_string());
+    let request_uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |original| &original.0);
+    env.insert("REQUEST_URI".to_string(), request_uri.to_string());
 
     // Script name (URI path to the PHP script)
     env.insert("SCRIPT_NAME".to_string(), script_name.to_string());
@@ -900,9 +1349,7 @@ fn build_cgi_env_from_parts(
     // === PHP-specific variables ===
     env.insert("REDIRECT_STATUS".to_string(), "200".to_string());
     env.insert("PHP_SELF".to_string(), script_name.to_string());
-    env.insert("HTTPS".to_string(), "off".to_string());
-    env.insert("REMOTE_ADDR".to_string(), "127.0.0.1".to_string());
-    env.insert("REMOTE_PORT".to_string(), "0".to_string());
+    insert_connection_env(&mut env, parts.extensions.get::<ConnectionInfo>());
 
     env
 }
@@ -936,7 +1383,11 @@ fn build_cgi_env(
     env.insert("REQUEST_METHOD".to_string(), req.method().to_string());
 
     // Request URI (original, includes query string)
-    env.insert("REQUEST_URI".to_string(), req.uri().to_string());
+    let request_uri = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri(), |original| &original.0);
+    env.insert("REQUEST_URI".to_string(), request_uri.to_string());
 
     // Script name (URI path to the PHP script)
     env.insert("SCRIPT_NAME".to_string(), script_name.to_string());
@@ -1030,17 +1481,162 @@ fn build_cgi_env(
     // PHP_SELF - same as SCRIPT_NAME for direct requests
     env.insert("PHP_SELF".to_string(), script_name.to_string());
 
-    // HTTPS indicator
-    // TODO: Set this based on actual connection
-    env.insert("HTTPS".to_string(), "off".to_string());
-
-    // Remote address (would be filled in by the server)
-    env.insert("REMOTE_ADDR".to_string(), "127.0.0.1".to_string());
-    env.insert("REMOTE_PORT".to_string(), "0".to_string());
+    // Client address, HTTPS and the port we accepted on
+    insert_connection_env(&mut env, req.extensions().get::<ConnectionInfo>());
 
     env
 }
 
+/// Set `REMOTE_ADDR`, `REMOTE_PORT`, `HTTPS`, `REQUEST_SCHEME`, `SERVER_ADDR`
+/// and `SERVER_PORT` from the connection the request arrived on
+///
+/// Requests built without one (internal subrequests, tests) look like a
+/// plain HTTP request from localhost.
+fn insert_connection_env(env: &mut HashMap<String, String>, conn: Option<&ConnectionInfo>) {
+    let Some(conn) = conn else {
+        env.insert("HTTPS".to_string(), "off".to_string());
+        env.insert("REQUEST_SCHEME".to_string(), "http".to_string());
+        env.insert("REMOTE_ADDR".to_string(), "127.0.0.1".to_string());
+        env.insert("REMOTE_PORT".to_string(), "0".to_string());
+        return;
+    };
+
+    let https = if conn.https { "on" } else { "off" };
+    env.insert("HTTPS".to_string(), https.to_string());
+    env.insert("REQUEST_SCHEME".to_string(), conn.scheme().to_string());
+    env.insert(
+        "REMOTE_ADDR".to_string(),
+        conn.client.to_canonical().to_string(),
+    );
+    // The port a forwarded client used is unknown
+    let remote_port = if conn.is_forwarded() {
+        0
+    } else {
+        conn.peer.port()
+    };
+    env.insert("REMOTE_PORT".to_string(), remote_port.to_string());
+    if let Some(local) = conn.local {
+        env.insert(
+            "SERVER_ADDR".to_string(),
+            local.ip().to_canonical().to_string(),
+        );
+        env.insert("SERVER_PORT".to_string(), local.port().to_string());
+    }
+}
+
+/// End of the CGI header block (after its blank line), if it has arrived
+fn cgi_head_end(output: &[u8]) -> Option<usize> {
+    // Scripts that print no headers go straight to the body
+    if !output.first().is_some_and(|b| b.is_ascii_alphabetic()) {
+        return (!output.is_empty()).then_some(0);
+    }
+    let crlf = output
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|p| p + 4);
+    let lf = output.windows(2).position(|w| w == b"\n\n").map(|p| p + 2);
+    match (crlf, lf) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// True if the header block contains `X-Accel-Buffering: no`
+fn wants_streaming(head: &str) -> bool {
+    head.lines().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("x-accel-buffering")
+                && value.trim().eq_ignore_ascii_case("no")
+        })
+    })
+}
+
+/// Build the vephp request for a script run with CGI environment `env`
+#[cfg(unix)]
+fn socket_request(
+    req_parts: &Parts,
+    script_path: &Path,
+    doc_root: &Path,
+    env: HashMap<String, String>,
+    body: &[u8],
+    timeout: std::time::Duration,
+) -> protocol::PhpRequest {
+    let headers = req_parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let query_params = req_parts
+        .uri
+        .query()
+        .map(|query| {
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (form_decode(name), form_decode(value))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    protocol::PhpRequest {
+        request_type: protocol::RequestType::Execute,
+        script_path: script_path.to_path_buf(),
+        method: req_parts.method.to_string(),
+        uri: req_parts.uri.to_string(),
+        headers,
+        body: body.to_vec(),
+        query_params,
+        remote_addr: env.get("REMOTE_ADDR").cloned().unwrap_or_default(),
+        server_vars: env,
+        document_root: doc_root.to_path_buf(),
+        timeout_secs: timeout.as_secs_f64().ceil() as u32,
+    }
+}
+
+/// Decode one `application/x-www-form-urlencoded` query component
+#[cfg(unix)]
+fn form_decode(component: &str) -> String {
+    let component = component.replace('+', " ");
+    percent_encoding::percent_decode_str(&component)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Turn a vephp response into CGI output for `parse_php_response`
+#[cfg(unix)]
+fn socket_output(response: protocol::PhpResponse) -> Result<CgiOutput> {
+    if response.queued {
+        return Err(anyhow!("vephp queued the request instead of running it"));
+    }
+    if !response.success {
+        let error = response.error.unwrap_or(response.stderr);
+        return Err(anyhow!("PHP script failed: {}", error));
+    }
+    log_stderr(response.stderr.as_bytes());
+
+    // vephp passes php-cgi's own header block through in the body; anything
+    // it set itself goes in front of it
+    let mut output = String::new();
+    if response.status_code != 200 {
+        output.push_str(&format!("Status: {}\r\n", response.status_code));
+    }
+    for (name, value) in &response.headers {
+        output.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    output.push_str(&response.body);
+    Ok(CgiOutput::Buffered(output))
+}
+
+fn log_stderr(stderr: &[u8]) {
+    let stderr = String::from_utf8_lossy(stderr);
+    if !stderr.trim().is_empty() {
+        warn!("PHP stderr: {}", stderr.trim());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1056,4 +1652,380 @@ mod tests {
         // This would require mocking the request
         // For now, just verify the function signature works
     }
+
+    #[test]
+    fn test_cgi_env_connection() {
+        let env_for = |conn: Option<ConnectionInfo>| {
+            let mut builder = Request::get("/index.php").header("host", "example.com:8080");
+            if let Some(conn) = conn {
+                builder = builder.extension(conn);
+            }
+            let (parts, ()) = builder.body(()).unwrap().into_parts();
+            build_cgi_env_from_parts(
+                &parts,
+                Path::new("/var/www/index.php"),
+                Path::new("/var/www"),
+                "/index.php",
+                "",
+            )
+        };
+
+        let peer = "203.0.113.9:51234".parse().unwrap();
+        let env = env_for(Some(ConnectionInfo::new(
+            peer,
+            Some("192.0.2.1:443".parse().unwrap()),
+            true,
+        )));
+        assert_eq!(env["REMOTE_ADDR"], "203.0.113.9");
+        assert_eq!(env["REMOTE_PORT"], "51234");
+        assert_eq!(env["HTTPS"], "on");
+        assert_eq!(env["REQUEST_SCHEME"], "https");
+        assert_eq!(env["SERVER_ADDR"], "192.0.2.1");
+        assert_eq!(env["SERVER_PORT"], "443");
+        assert_eq!(env["HTTP_HOST"], "example.com:8080");
+
+        let env = env_for(Some(ConnectionInfo::new(
+            "[::ffff:198.51.100.4]:40000".parse().unwrap(),
+            Some("[::ffff:192.0.2.1]:80".parse().unwrap()),
+            false,
+        )));
+        assert_eq!(env["REMOTE_ADDR"], "198.51.100.4");
+        assert_eq!(env["SERVER_ADDR"], "192.0.2.1");
+        assert_eq!(env["SERVER_PORT"], "80");
+        assert_eq!(env["HTTPS"], "off");
+        assert_eq!(env["REQUEST_SCHEME"], "http");
+
+        // Behind a trusted proxy the forwarded client is REMOTE_ADDR
+        let proxies = crate::server::TrustedProxies::new(&["10.0.0.0/8".to_string()]).unwrap();
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.7".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let conn = ConnectionInfo::new("10.0.0.5:6000".parse().unwrap(), None, false)
+            .with_forwarded(&headers, &proxies);
+        let env = env_for(Some(conn));
+        assert_eq!(env["REMOTE_ADDR"], "198.51.100.7");
+        assert_eq!(env["REMOTE_PORT"], "0");
+        assert_eq!(env["HTTPS"], "on");
+        assert_eq!(env["REQUEST_SCHEME"], "https");
+
+        // The same headers from an untrusted peer are ignored
+        let conn = ConnectionInfo::new("203.0.113.9:6000".parse().unwrap(), None, false)
+            .with_forwarded(&headers, &proxies);
+        let env = env_for(Some(conn));
+        assert_eq!(env["REMOTE_ADDR"], "203.0.113.9");
+        assert_eq!(env["HTTPS"], "off");
+        // Without a local address the Host header's port stays
+        assert_eq!(env["SERVER_PORT"], "8080");
+
+        let env = env_for(None);
+        assert_eq!(env["REMOTE_ADDR"], "127.0.0.1");
+        assert_eq!(env["HTTPS"], "off");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_classify_spawn_error() {
+        use nix::errno::Errno;
+
+        let from_errno = |errno: Errno| std::io::Error::from_raw_os_error(errno as i32);
+        assert_eq!(
+            classify_spawn_error(&from_errno(Errno::EAGAIN)),
+            SpawnErrorClass::Transient
+        );
+        assert_eq!(
+            classify_spawn_error(&from_errno(Errno::ENOMEM)),
+            SpawnErrorClass::Transient
+        );
+        assert_eq!(
+            classify_spawn_error(&from_errno(Errno::ENOENT)),
+            SpawnErrorClass::Permanent
+        );
+        assert_eq!(
+            classify_spawn_error(&from_errno(Errno::EACCES)),
+            SpawnErrorClass::Permanent
+        );
+    }
+
+    #[test]
+    fn test_spawn_backoff_is_jittered_within_bounds() {
+        for attempt in 1..=4 {
+            let ceiling = 40u64 << (attempt - 1);
+            let delay = spawn_backoff(40, attempt).as_millis() as u64;
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{}ms", delay);
+        }
+        assert_eq!(spawn_backoff(0, 1), std::time::Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_spawn_error_is_not_retried() {
+        let config = PhpConfig {
+            binary_path: Some("/nonexistent/php-cgi".to_string()),
+            spawn_retry_backoff_ms: 1,
+            ..PhpConfig::default()
+        };
+        let pool = PhpPool::new(&config);
+
+        let err = pool
+            .spawn_php(&mut Command::new("/nonexistent/php-cgi"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("binary not found"));
+        assert_eq!(pool.spawn_retries.load(Ordering::Relaxed), 0);
+        assert_eq!(pool.spawn_retry_exhausted.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_wait_ready_tracks_slow_probe() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let php = dir.path().join("php");
+        std::fs::write(&php, "#!/bin/sh\nsleep 0.3\necho 'PHP 8.3.0 (cli)'\n").unwrap();
+        std::fs::set_permissions(&php, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = PhpConfig {
+            binary_path: Some(php.to_string_lossy().into_owned()),
+            ..PhpConfig::default()
+        };
+        let pool = Arc::new(PhpPool::new(&config));
+        assert_eq!(pool.state(), PoolState::Starting);
+
+        let starting = pool.clone();
+        tokio::spawn(async move { starting.start().await });
+
+        let grace = std::time::Duration::from_millis(20);
+        assert_eq!(pool.wait_ready(grace).await, PoolState::Starting);
+        let grace = std::time::Duration::from_secs(5);
+        assert_eq!(pool.wait_ready(grace).await, PoolState::Ready);
+        assert!(pool.stats()["startup_ms"].as_u64().unwrap() >= 300);
+
+        let disabled = PhpPool::new(&PhpConfig {
+            enable: false,
+            ..PhpConfig::default()
+        });
+        assert_eq!(disabled.state(), PoolState::Disabled);
+    }
+
+    /// Re-runs `nproc_exhausted_child` in a separate process so the lowered
+    /// RLIMIT_NPROC cannot starve the rest of the test suite.
+    #[cfg(unix)]
+    #[test]
+    fn test_spawn_retries_exhausted_under_rlimit_nproc() {
+        // RLIMIT_NPROC is not enforced for root
+        if nix::unistd::geteuid().is_root() {
+            return;
+        }
+
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "php::tests::nproc_exhausted_child",
+                "--test-threads=1",
+            ])
+            .env("VELOSERVE_NPROC_CHILD", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nproc_exhausted_child() {
+        use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+        if std::env::var_os("VELOSERVE_NPROC_CHILD").is_none() {
+            return;
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let config = PhpConfig {
+            binary_path: Some("/bin/true".to_string()),
+            spawn_retries: 2,
+            spawn_retry_backoff_ms: 1,
+            ..PhpConfig::default()
+        };
+        let pool = PhpPool::new(&config);
+
+        let (_, hard) = getrlimit(Resource::RLIMIT_NPROC).unwrap();
+        setrlimit(Resource::RLIMIT_NPROC, 0, hard).unwrap();
+
+        let err = runtime
+            .block_on(pool.spawn_php(&mut Command::new("/bin/true")))
+            .unwrap_err();
+        assert!(err.to_string().contains("after 2 retries"), "{}", err);
+        assert_eq!(pool.spawn_retries.load(Ordering::Relaxed), 2);
+        assert_eq!(pool.spawn_retry_exhausted.load(Ordering::Relaxed), 1);
+    }
+
+    /// Minimal vephp that echoes each request back behind a CGI header
+    /// block; returns the number of connections it has accepted
+    #[cfg(unix)]
+    fn stub_vephp(path: &Path) -> Arc<AtomicUsize> {
+        use protocol::{PhpRequest, PhpResponse, RequestType};
+
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut prefix = [0u8; 4];
+                    while stream.read_exact(&mut prefix).await.is_ok() {
+                        let mut payload = vec![0u8; protocol::frame_len(prefix).unwrap()];
+                        stream.read_exact(&mut payload).await.unwrap();
+                        let request: PhpRequest = protocol::decode_payload(&payload).unwrap();
+                        let response = match request.request_type {
+                            RequestType::Execute if request.script_path.ends_with("fail.php") => {
+                                PhpResponse::error("boom")
+                            }
+                            RequestType::Execute => PhpResponse::ok(
+                                &format!(
+                                    "Status: 201 Created\r\nContent-Type: text/plain\r\n\r\n{} {} q={} body={}",
+                                    request.server_vars["REQUEST_METHOD"],
+                                    request.server_vars["SCRIPT_NAME"],
+                                    request.query_params.get("q").map_or("", String::as_str),
+                                    String::from_utf8_lossy(&request.body)
+                                ),
+                                "",
+                            ),
+                            _ => PhpResponse::ok("healthy", ""),
+                        };
+                        let frame = protocol::encode_frame(&response).unwrap();
+                        stream.write_all(&frame).await.unwrap();
+                    }
+                });
+            }
+        });
+        connections
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_socket_mode_round_trips_through_vephp() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("php.sock");
+        let connections = stub_vephp(&socket_path);
+
+        let config = PhpConfig {
+            mode: PhpMode::Socket,
+            socket_path: socket_path.to_string_lossy().into_owned(),
+            binary_path: Some("/nonexistent/php-cgi".to_string()),
+            ..PhpConfig::default()
+        };
+        let pool = PhpPool::new(&config);
+        pool.start().await.unwrap();
+        assert_eq!(pool.state(), PoolState::Ready);
+
+        let (parts, ()) = Request::post("/form.php?q=a+b%21")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let doc_root = dir.path();
+        for _ in 0..3 {
+            let output = pool
+                .execute_cgi(
+                    &doc_root.join("form.php"),
+                    &parts,
+                    doc_root,
+                    "/form.php",
+                    "",
+                    b"x=1",
+                )
+                .await
+                .unwrap();
+            let CgiOutput::Buffered(output) = output else {
+                panic!("socket responses are buffered");
+            };
+            assert_eq!(
+                output,
+                "Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nPOST /form.php q=a b! body=x=1"
+            );
+        }
+        // The health check's connection is reused for every request
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.active_workers.load(Ordering::SeqCst), 0);
+
+        let err = pool
+            .execute_cgi(
+                &doc_root.join("fail.php"),
+                &parts,
+                doc_root,
+                "/fail.php",
+                "",
+                b"",
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("boom"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_socket_mode_falls_back_to_cgi() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let php = dir.path().join("php-cgi");
+        std::fs::write(
+            &php,
+            "#!/bin/sh\nif [ \"$1\" = -v ]; then echo 'PHP 8.3.0 (cgi-fcgi)'; exit 0; fi\nprintf 'Content-Type: text/plain\\r\\n\\r\\ncgi'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&php, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let socket_path = dir.path().join("php.sock");
+        let config = PhpConfig {
+            mode: PhpMode::Socket,
+            socket_path: socket_path.to_string_lossy().into_owned(),
+            binary_path: Some(php.to_string_lossy().into_owned()),
+            ..PhpConfig::default()
+        };
+        let pool = PhpPool::new(&config);
+        pool.start().await.unwrap();
+        assert_eq!(pool.state(), PoolState::Ready);
+
+        let (parts, ()) = Request::get("/index.php").body(()).unwrap().into_parts();
+        let script = dir.path().join("index.php");
+        let run = || pool.execute_cgi(&script, &parts, dir.path(), "/index.php", "", b"");
+        let CgiOutput::Buffered(output) = run().await.unwrap() else {
+            panic!("CGI fallback output is buffered");
+        };
+        assert_eq!(output, "Content-Type: text/plain\r\n\r\ncgi");
+
+        // Once vephp comes up, requests go to it again
+        let connections = stub_vephp(&socket_path);
+        let CgiOutput::Buffered(output) = run().await.unwrap() else {
+            panic!("socket responses are buffered");
+        };
+        assert!(output.ends_with("GET /index.php q= body="), "{}", output);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cgi_head_end() {
+        assert_eq!(
+            cgi_head_end(b"Content-Type: text/html\r\n\r\n<p>"),
+            Some(27)
+        );
+        assert_eq!(cgi_head_end(b"Status: 200\nX-A: b\n\ndata"), Some(20));
+        assert_eq!(cgi_head_end(b"Content-Type: text/ht"), None);
+        assert_eq!(cgi_head_end(b"<!doctype html>"), Some(0));
+        assert_eq!(cgi_head_end(b""), None);
+    }
+
+    #[test]
+    fn test_wants_streaming() {
+        assert!(wants_streaming(
+            "Content-Type: text/event-stream\r\nX-Accel-Buffering: no\r\n\r\n"
+        ));
+        assert!(wants_streaming("x-accel-buffering:NO\n\n"));
+        assert!(!wants_streaming("X-Accel-Buffering: yes\n\n"));
+        assert!(!wants_streaming("Content-Type: text/html\n\n"));
+    }
 }
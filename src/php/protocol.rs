@@ -0,0 +1,285 @@
+//! Communication Protocol
+//!
+//! Defines the protocol between VeloServe and veloserve-php workers.
+//! Uses bincode for efficient binary serialization.
+//!
+//! Each message is one frame: a big-endian `u32` payload length followed by
+//! the bincode payload. A connection carries any number of request/response
+//! pairs, so VeloServe can keep connections to vephp open between requests.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Largest frame either side accepts
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// Serialize `message` into a complete frame, length prefix included
+pub fn encode_frame<T: Serialize>(message: &T) -> io::Result<Vec<u8>> {
+    let payload =
+        bincode::serialize(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(frame_too_large(payload.len()));
+    }
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Payload length announced by a frame's length prefix
+pub fn frame_len(prefix: [u8; 4]) -> io::Result<usize> {
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(frame_too_large(len));
+    }
+    Ok(len)
+}
+
+/// Deserialize a frame's payload
+pub fn decode_payload<T: DeserializeOwned>(payload: &[u8]) -> io::Result<T> {
+    bincode::deserialize(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one frame
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> io::Result<()> {
+    writer.write_all(&encode_frame(message)?)?;
+    writer.flush()
+}
+
+/// Read one frame; `None` if the peer closed the connection between frames
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> io::Result<Option<T>> {
+    let mut prefix = [0u8; 4];
+    let mut filled = 0;
+    while filled < prefix.len() {
+        match reader.read(&mut prefix[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    let mut payload = vec![0u8; frame_len(prefix)?];
+    reader.read_exact(&mut payload)?;
+    decode_payload(&payload).map(Some)
+}
+
+fn frame_too_large(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "frame of {} bytes exceeds the {} byte limit",
+            len, MAX_FRAME_LEN
+        ),
+    )
+}
+
+/// Types of PHP requests
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum RequestType {
+    /// Execute a PHP script
+    Execute,
+    /// Health check
+    HealthCheck,
+    /// Get status
+    Status,
+}
+
+/// PHP request from VeloServe to veloserve-php
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PhpRequest {
+    /// Type of request
+    pub request_type: RequestType,
+    /// Path to PHP script
+    pub script_path: PathBuf,
+    /// Request method (GET, POST, etc.)
+    pub method: String,
+    /// Request URI
+    pub uri: String,
+    /// HTTP headers
+    pub headers: HashMap<String, String>,
+    /// POST data or request body
+    pub body: Vec<u8>,
+    /// Query string parameters
+    pub query_params: HashMap<String, String>,
+    /// Server/environment variables ($_SERVER)
+    pub server_vars: HashMap<String, String>,
+    /// Document root
+    pub document_root: PathBuf,
+    /// Remote IP address
+    pub remote_addr: String,
+    /// Request timeout
+    pub timeout_secs: u32,
+}
+
+impl PhpRequest {
+    /// Create a simple PHP execution request
+    pub fn execute(script_path: PathBuf) -> Self {
+        Self {
+            request_type: RequestType::Execute,
+            script_path,
+            method: "GET".to_string(),
+            uri: "/".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_params: HashMap::new(),
+            server_vars: HashMap::new(),
+            document_root: PathBuf::from("/var/www"),
+            remote_addr: "127.0.0.1".to_string(),
+            timeout_secs: 30,
+        }
+    }
+
+    /// Create a health check request
+    pub fn health_check() -> Self {
+        Self {
+            request_type: RequestType::HealthCheck,
+            script_path: PathBuf::new(),
+            method: "GET".to_string(),
+            uri: "/health".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_params: HashMap::new(),
+            server_vars: HashMap::new(),
+            document_root: PathBuf::from("/var/www"),
+            remote_addr: "127.0.0.1".to_string(),
+            timeout_secs: 5,
+        }
+    }
+}
+
+/// PHP response from veloserve-php to VeloServe
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PhpResponse {
+    /// Whether the request was successful
+    pub success: bool,
+    /// HTTP status code (if applicable)
+    pub status_code: u16,
+    /// Response headers
+    pub headers: HashMap<String, String>,
+    /// Response body (stdout from PHP)
+    pub body: String,
+    /// Error message (if any)
+    pub error: Option<String>,
+    ///stderr output
+    pub stderr: String,
+    /// Execution time in milliseconds
+    pub execution_time_ms: u64,
+    /// Whether response was queued
+    pub queued: bool,
+}
+
+impl PhpResponse {
+    /// Create a successful response
+    pub fn ok(body: &str, stderr: &str) -> Self {
+        Self {
+            success: true,
+            status_code: 200,
+            headers: HashMap::new(),
+            body: body.to_string(),
+            error: None,
+            stderr: stderr.to_string(),
+            execution_time_ms: 0,
+            queued: false,
+        }
+    }
+
+    /// Create an error response
+    pub fn error(message: &str) -> Self {
+        Self {
+            success: false,
+            status_code: 500,
+            headers: HashMap::new(),
+            body: String::new(),
+            error: Some(message.to_string()),
+            stderr: message.to_string(),
+            execution_time_ms: 0,
+            queued: false,
+        }
+    }
+
+    /// Create a queued response (will be processed later)
+    pub fn queued() -> Self {
+        Self {
+            success: true,
+            status_code: 202,
+            headers: HashMap::new(),
+            body: String::new(),
+            error: None,
+            stderr: String::new(),
+            execution_time_ms: 0,
+            queued: true,
+        }
+    }
+
+    /// Add a header to the response
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Set HTTP status code
+    pub fn with_status(mut self, code: u16) -> Self {
+        self.status_code = code;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let mut wire = Vec::new();
+        let mut request = PhpRequest::execute(PathBuf::from("/var/www/index.php"));
+        request.body = b"a=1".to_vec();
+        write_frame(&mut wire, &request).unwrap();
+        write_frame(&mut wire, &PhpRequest::health_check()).unwrap();
+
+        let mut reader = wire.as_slice();
+        let first: PhpRequest = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(first.script_path, PathBuf::from("/var/www/index.php"));
+        assert_eq!(first.body, b"a=1");
+        let second: PhpRequest = read_frame(&mut reader).unwrap().unwrap();
+        assert!(matches!(second.request_type, RequestType::HealthCheck));
+        assert!(read_frame::<_, PhpRequest>(&mut reader).unwrap().is_none());
+
+        // A truncated frame is an error, not a clean close
+        let mut truncated = &wire[..wire.len() - 1];
+        let _ = read_frame::<_, PhpRequest>(&mut truncated).unwrap();
+        assert!(read_frame::<_, PhpRequest>(&mut truncated).is_err());
+
+        let oversized = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        assert!(frame_len(oversized).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_large_frames_cross_a_socket() {
+        use std::os::unix::net::UnixStream;
+
+        // Far bigger than one socket read, so both sides have to loop
+        let body: String = (0..1024 * 1024)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        let echo = std::thread::spawn(move || {
+            let request: PhpRequest = read_frame(&mut server).unwrap().unwrap();
+            let body = String::from_utf8(request.body).unwrap();
+            write_frame(&mut server, &PhpResponse::ok(&body, "")).unwrap();
+        });
+
+        let mut request = PhpRequest::execute(PathBuf::from("/var/www/upload.php"));
+        request.body = body.clone().into_bytes();
+        write_frame(&mut client, &request).unwrap();
+        let response: PhpResponse = read_frame(&mut client).unwrap().unwrap();
+        echo.join().unwrap();
+
+        assert_eq!(response.body.len(), 1024 * 1024);
+        assert_eq!(response.body, body);
+    }
+}
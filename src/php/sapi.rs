@@ -22,19 +22,28 @@
 //! - Or compile PHP with `--enable-embed`
 
 use std::collections::HashMap;
+#[cfg(feature = "php-embed")]
 use std::ffi::CString;
+#[cfg(feature = "php-embed")]
 use std::os::raw::{c_char, c_int};
 use std::path::Path;
+#[cfg(feature = "php-embed")]
 use std::path::PathBuf;
+#[cfg(feature = "php-embed")]
 use std::ptr;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "php-embed")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicU64, Ordering};
 #[cfg(feature = "php-embed")]
 use std::sync::mpsc;
+#[cfg(feature = "php-embed")]
 use std::sync::Once;
 #[cfg(feature = "php-embed")]
 use std::thread;
 
+#[cfg(feature = "php-embed")]
 use parking_lot::Mutex;
+#[cfg(feature = "php-embed")]
 use tracing::{debug, error, info, warn};
 
 #[cfg(feature = "php-embed")]
@@ -50,8 +59,11 @@ use parking_lot::Mutex as ParkingMutex;
 // PHP SAPI Runtime
 // ============================================================================
 
+#[cfg(feature = "php-embed")]
 static PHP_INITIALIZED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "php-embed")]
 static PHP_INIT_ONCE: Once = Once::new();
+#[cfg(feature = "php-embed")]
 static PHP_INIT_ERROR: Mutex<Option<String>> = Mutex::new(None);
 #[cfg(feature = "php-embed")]
 static PHP_HOOKS_INSTALLED: Once = Once::new();
@@ -70,9 +82,18 @@ static REQUEST_CONTEXT: OnceCell<ParkingMutex<RequestContext>> = OnceCell::new()
 #[cfg(feature = "php-embed")]
 static PHP_ERROR_LOG_PATH: OnceCell<PathBuf> = OnceCell::new();
 
-/// Channel for sending PHP execution requests to the dedicated PHP thread
+/// Channel for sending PHP execution requests to the dedicated PHP thread,
+/// replaced whenever the thread is respawned
+#[cfg(feature = "php-embed")]
+static PHP_WORKER_TX: Mutex<Option<WorkerChannel>> = Mutex::new(None);
+#[cfg(feature = "php-embed")]
+static PHP_WORKER_CONFIG: OnceCell<PhpEmbedConfig> = OnceCell::new();
 #[cfg(feature = "php-embed")]
-static PHP_WORKER_TX: OnceCell<mpsc::SyncSender<PhpWorkerRequest>> = OnceCell::new();
+static PHP_WORKER_RESTARTS: AtomicU64 = AtomicU64::new(0);
+
+/// Pause before respawning a dead worker thread
+#[cfg(feature = "php-embed")]
+const RESPAWN_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
 
 /// Configuration for PHP embed initialization
 #[derive(Clone, Default)]
@@ -98,6 +119,26 @@ struct PhpWorkerRequest {
     response_tx: mpsc::SyncSender<Result<PhpResponse, String>>,
 }
 
+/// Sender for one incarnation of the worker thread
+#[cfg(feature = "php-embed")]
+#[derive(Clone)]
+struct WorkerChannel {
+    generation: u64,
+    tx: mpsc::SyncSender<PhpWorkerRequest>,
+}
+
+/// How the worker thread's request loop ended
+#[cfg(feature = "php-embed")]
+#[derive(Debug, PartialEq, Eq)]
+enum WorkerExit {
+    /// Channel closed: the server is shutting down or a newer worker took over
+    Shutdown,
+    /// `php_embed_init` failed
+    InitFailed,
+    /// A request panicked; the PHP runtime state can't be trusted any more
+    Panicked,
+}
+
 #[cfg(feature = "php-embed")]
 #[derive(Default)]
 struct EmbedCapture {
@@ -316,13 +357,67 @@ pub struct PhpSapi {
     initialized: bool,
     /// Request counter for statistics
     request_count: AtomicU64,
-    /// Output buffer for capturing PHP output
-    output_buffer: Mutex<Vec<u8>>,
+}
+
+/// Start a worker thread with a fresh channel, publishing its sender in `slot`
+#[cfg(feature = "php-embed")]
+fn spawn_php_worker(slot: &mut Option<WorkerChannel>, generation: u64) -> std::io::Result<()> {
+    let config = PHP_WORKER_CONFIG.get().cloned().unwrap_or_default();
+
+    // Create a bounded channel for sending work to the PHP thread
+    let (tx, rx) = mpsc::sync_channel::<PhpWorkerRequest>(32);
+    *slot = Some(WorkerChannel { generation, tx });
+
+    thread::Builder::new()
+        .name("php-embed-worker".to_string())
+        .spawn(move || {
+            let exit = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                run_php_worker(rx, config)
+            }))
+            .unwrap_or(WorkerExit::Panicked);
+
+            if exit == WorkerExit::Panicked {
+                PHP_INITIALIZED.store(false, Ordering::SeqCst);
+                thread::sleep(RESPAWN_DELAY);
+                respawn_php_worker(generation);
+            }
+        })
+        .map(|_| ())
+}
+
+/// Replace a dead worker, unless it has already been replaced
+#[cfg(feature = "php-embed")]
+fn respawn_php_worker(dead_generation: u64) {
+    let mut slot = PHP_WORKER_TX.lock();
+    if slot.as_ref().map(|worker| worker.generation) != Some(dead_generation) {
+        return;
+    }
+
+    let restarts = PHP_WORKER_RESTARTS.fetch_add(1, Ordering::Relaxed) + 1;
+    warn!(
+        "PHP embed worker thread died, respawning (restart #{})",
+        restarts
+    );
+    if let Err(e) = spawn_php_worker(&mut slot, dead_generation + 1) {
+        error!("Failed to respawn PHP worker thread: {}", e);
+    }
+}
+
+/// Number of times the embed worker thread was respawned
+pub fn worker_restarts() -> u64 {
+    #[cfg(feature = "php-embed")]
+    {
+        PHP_WORKER_RESTARTS.load(Ordering::Relaxed)
+    }
+    #[cfg(not(feature = "php-embed"))]
+    {
+        0
+    }
 }
 
 /// Run the PHP worker thread that handles all PHP execution
 #[cfg(feature = "php-embed")]
-fn run_php_worker(rx: mpsc::Receiver<PhpWorkerRequest>, config: PhpEmbedConfig) {
+fn run_php_worker(rx: mpsc::Receiver<PhpWorkerRequest>, config: PhpEmbedConfig) -> WorkerExit {
     info!("PHP worker thread starting...");
 
     unsafe {
@@ -412,7 +507,7 @@ fn run_php_worker(rx: mpsc::Receiver<PhpWorkerRequest>, config: PhpEmbedConfig)
             let err = format!("php_embed_init failed with code: {}", result);
             error!("{}", err);
             *PHP_INIT_ERROR.lock() = Some(err);
-            return;
+            return WorkerExit::InitFailed;
         }
 
         // CRITICAL: php_embed_init() calls php_request_startup() internally,
@@ -422,23 +517,44 @@ fn run_php_worker(rx: mpsc::Receiver<PhpWorkerRequest>, config: PhpEmbedConfig)
         b::php_request_shutdown(std::ptr::null_mut());
         debug!("Shut down initial boot request from php_embed_init");
 
+        *PHP_INIT_ERROR.lock() = None;
         PHP_INITIALIZED.store(true, Ordering::SeqCst);
         info!("PHP embed SAPI initialized on worker thread");
 
-        // Process requests from the channel
+        // Process requests from the channel; a panic fails its own request and
+        // retires this thread, and the next incarnation starts from a clean runtime
+        let mut exit = WorkerExit::Shutdown;
         while let Ok(req) = rx.recv() {
-            let result = execute_script_on_thread(
-                &req.script_path,
-                &req.server_vars,
-                &req.get_vars,
-                &req.post_data,
-                &req.headers,
-            );
-            let _ = req.response_tx.send(result);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                crate::server::test_trigger("embed", &req.script_path.to_string_lossy());
+                execute_script_on_thread(
+                    &req.script_path,
+                    &req.server_vars,
+                    &req.get_vars,
+                    &req.post_data,
+                    &req.headers,
+                )
+            }));
+            match result {
+                Ok(result) => {
+                    let _ = req.response_tx.send(result);
+                }
+                Err(_) => {
+                    error!(
+                        "PHP worker panicked executing {}",
+                        req.script_path.display()
+                    );
+                    let _ = req.response_tx.send(Err("PHP worker panicked".to_string()));
+                    exit = WorkerExit::Panicked;
+                    break;
+                }
+            }
         }
 
         info!("PHP worker thread shutting down...");
+        PHP_INITIALIZED.store(false, Ordering::SeqCst);
         b::php_embed_shutdown();
+        exit
     }
 }
 
@@ -747,7 +863,6 @@ impl PhpSapi {
         Self {
             initialized: false,
             request_count: AtomicU64::new(0),
-            output_buffer: Mutex::new(Vec::with_capacity(64 * 1024)), // 64KB initial
         }
     }
 
@@ -761,18 +876,11 @@ impl PhpSapi {
         PHP_INIT_ONCE.call_once(|| {
             info!("Initializing PHP embed SAPI with dedicated worker thread...");
 
-            // Create a bounded channel for sending work to the PHP thread
-            let (tx, rx) = mpsc::sync_channel::<PhpWorkerRequest>(32);
-
-            // Store the sender globally
-            let _ = PHP_WORKER_TX.set(tx);
+            // Kept for respawning the worker thread
+            let _ = PHP_WORKER_CONFIG.set(config);
 
             // Spawn the dedicated PHP worker thread
-            thread::Builder::new()
-                .name("php-embed-worker".to_string())
-                .spawn(move || {
-                    run_php_worker(rx, config);
-                })
+            spawn_php_worker(&mut PHP_WORKER_TX.lock(), 0)
                 .expect("Failed to spawn PHP worker thread");
 
             // Give the worker thread time to initialize
@@ -833,8 +941,9 @@ impl PhpSapi {
         );
 
         // Get the worker channel
-        let tx = PHP_WORKER_TX
-            .get()
+        let worker = PHP_WORKER_TX
+            .lock()
+            .clone()
             .ok_or_else(|| "PHP worker thread not initialized".to_string())?;
 
         // Create a response channel for this request
@@ -850,14 +959,21 @@ impl PhpSapi {
             response_tx,
         };
 
-        // Send request to worker thread
-        tx.send(request)
-            .map_err(|e| format!("Failed to send request to PHP worker: {}", e))?;
+        // Send request to worker thread; a closed channel means it died
+        if let Err(e) = worker.tx.send(request) {
+            respawn_php_worker(worker.generation);
+            return Err(format!("Failed to send request to PHP worker: {}", e));
+        }
 
         // Wait for response (with timeout)
-        response_rx
-            .recv_timeout(std::time::Duration::from_secs(300))
-            .map_err(|e| format!("Timeout waiting for PHP response: {}", e))?
+        match response_rx.recv_timeout(std::time::Duration::from_secs(300)) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                respawn_php_worker(worker.generation);
+                Err("PHP worker exited without responding".to_string())
+            }
+            Err(e) => Err(format!("Timeout waiting for PHP response: {}", e)),
+        }
     }
 
     /// Execute PHP code string
@@ -923,6 +1039,7 @@ impl PhpSapi {
             "mode": "sapi",
             "initialized": self.initialized,
             "request_count": self.request_count(),
+            "worker_restarts": worker_restarts(),
             "feature_enabled": cfg!(feature = "php-embed"),
         })
     }
@@ -0,0 +1,121 @@
+//! vephp Socket Client
+//!
+//! Sends PHP requests to a `vephp` worker over its Unix socket (`mode =
+//! "socket"`). Each request and response is one [`protocol`] frame, and
+//! connections are returned to an idle list after each exchange so requests
+//! don't pay for a new connection.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tracing::debug;
+
+use super::protocol::{self, PhpRequest, PhpResponse};
+
+/// Why a socket exchange failed
+#[derive(Debug)]
+pub enum SocketError {
+    /// vephp could not be reached; the request was never sent
+    Unavailable(io::Error),
+    /// The request was sent but no valid response came back
+    Exchange(io::Error),
+}
+
+impl std::fmt::Display for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketError::Unavailable(e) => write!(f, "vephp is unavailable: {}", e),
+            SocketError::Exchange(e) => write!(f, "vephp request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SocketError {}
+
+/// Pooled connections to one vephp socket
+pub struct SocketClient {
+    path: PathBuf,
+    idle: Mutex<Vec<UnixStream>>,
+    max_idle: usize,
+}
+
+impl SocketClient {
+    /// Client for the socket at `path`, keeping up to `max_idle` idle connections
+    pub fn new(path: impl Into<PathBuf>, max_idle: usize) -> Self {
+        Self {
+            path: path.into(),
+            idle: Mutex::new(Vec::new()),
+            max_idle,
+        }
+    }
+
+    /// Socket path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of idle connections kept for reuse
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().len()
+    }
+
+    /// Send `request` and wait for its response.
+    ///
+    /// A pooled connection that vephp has since closed is retried once on a
+    /// fresh connection. Dropping the future drops the connection with it, so
+    /// a response that arrives late is never read by the next request.
+    pub async fn call(&self, request: &PhpRequest) -> Result<PhpResponse, SocketError> {
+        let frame = protocol::encode_frame(request).map_err(SocketError::Exchange)?;
+
+        let pooled = self.idle.lock().pop();
+        if let Some(mut stream) = pooled {
+            match exchange(&mut stream, &frame).await {
+                Ok(response) => {
+                    self.release(stream);
+                    return Ok(response);
+                }
+                Err(e) if is_stale(&e) => {
+                    debug!("Pooled vephp connection was closed ({}), reconnecting", e);
+                }
+                Err(e) => return Err(SocketError::Exchange(e)),
+            }
+        }
+
+        let mut stream = UnixStream::connect(&self.path)
+            .await
+            .map_err(SocketError::Unavailable)?;
+        let response = exchange(&mut stream, &frame)
+            .await
+            .map_err(SocketError::Exchange)?;
+        self.release(stream);
+        Ok(response)
+    }
+
+    fn release(&self, stream: UnixStream) {
+        let mut idle = self.idle.lock();
+        if idle.len() < self.max_idle {
+            idle.push(stream);
+        }
+    }
+}
+
+/// Write one request frame and read one response frame
+async fn exchange(stream: &mut UnixStream, frame: &[u8]) -> io::Result<PhpResponse> {
+    stream.write_all(frame).await?;
+    let mut prefix = [0u8; 4];
+    stream.read_exact(&mut prefix).await?;
+    let mut payload = vec![0u8; protocol::frame_len(prefix)?];
+    stream.read_exact(&mut payload).await?;
+    protocol::decode_payload(&payload)
+}
+
+/// True if the error means the peer had closed the connection
+fn is_stale(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+    )
+}
@@ -0,0 +1,213 @@
+//! Minimal FastCGI Client
+//!
+//! Just enough of the FastCGI responder protocol to run requests on a
+//! php-cgi process started in FastCGI mode: one request per connection,
+//! no multiplexing and no management records.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+
+const VERSION: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+/// Each connection carries a single request
+const REQUEST_ID: u16 = 1;
+/// Largest content a single record can carry
+const MAX_CONTENT: usize = 65535;
+
+/// What the application sent back for one request
+#[derive(Debug, Default)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub app_status: u32,
+}
+
+/// Run one request over `stream` and collect the response
+///
+/// The body is written on its own thread while the output is read, as a
+/// script may fill the socket buffer before it reads its input.
+pub fn run(
+    mut stream: &UnixStream,
+    params: &HashMap<String, String>,
+    body: &[u8],
+) -> io::Result<Output> {
+    let mut head = Vec::new();
+    let mut begin = [0u8; 8];
+    begin[..2].copy_from_slice(&RESPONDER.to_be_bytes());
+    push_record(&mut head, BEGIN_REQUEST, &begin);
+    push_stream(&mut head, PARAMS, &encode_params(params));
+    stream.write_all(&head)?;
+
+    std::thread::scope(|scope| {
+        let writer = scope.spawn(move || {
+            let mut stream = stream;
+            let mut records = Vec::with_capacity(body.len() + 8 * (body.len() / MAX_CONTENT + 2));
+            push_stream(&mut records, STDIN, body);
+            // A script that answers without reading its input may close first
+            let _ = stream.write_all(&records);
+        });
+        let output = read_output(stream);
+        if output.is_err() {
+            // Unblock the writer if the application stopped reading
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        let _ = writer.join();
+        output
+    })
+}
+
+fn read_output(mut stream: &UnixStream) -> io::Result<Output> {
+    let mut output = Output::default();
+    loop {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header)?;
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut content = vec![0u8; len + header[6] as usize];
+        stream.read_exact(&mut content)?;
+        content.truncate(len);
+
+        match header[1] {
+            STDOUT => output.stdout.extend_from_slice(&content),
+            STDERR => output.stderr.extend_from_slice(&content),
+            END_REQUEST => {
+                if content.len() < 5 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "truncated FastCGI end-request record",
+                    ));
+                }
+                if content[4] != 0 {
+                    return Err(io::Error::other(format!(
+                        "FastCGI request rejected (protocol status {})",
+                        content[4]
+                    )));
+                }
+                output.app_status =
+                    u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+                return Ok(output);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn push_record(buf: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    buf.extend_from_slice(&[VERSION, kind]);
+    buf.extend_from_slice(&REQUEST_ID.to_be_bytes());
+    buf.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(content);
+}
+
+/// Write `data` as a stream of records, ended by an empty one
+fn push_stream(buf: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    for chunk in data.chunks(MAX_CONTENT) {
+        push_record(buf, kind, chunk);
+    }
+    push_record(buf, kind, &[]);
+}
+
+fn encode_params(params: &HashMap<String, String>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (name, value) in params {
+        push_length(&mut buf, name.len());
+        push_length(&mut buf, value.len());
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf
+}
+
+fn push_length(buf: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        buf.push(len as u8);
+    } else {
+        buf.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_param_lengths() {
+        let params = HashMap::from([("A".to_string(), "x".repeat(200))]);
+        let encoded = encode_params(&params);
+        assert_eq!(encoded[0], 1);
+        assert_eq!(encoded[1..5], [0x80, 0, 0, 200]);
+        assert_eq!(encoded.len(), 5 + 1 + 200);
+    }
+
+    #[test]
+    fn test_streams_are_split_and_terminated() {
+        let mut buf = Vec::new();
+        push_stream(&mut buf, STDIN, &vec![0u8; MAX_CONTENT + 1]);
+        // Two data records and the empty terminator
+        assert_eq!(buf.len(), 3 * 8 + MAX_CONTENT + 1);
+        assert_eq!(buf[4..6], [0xff, 0xff]);
+        assert_eq!(buf[buf.len() - 4..buf.len() - 2], [0, 0]);
+    }
+
+    /// Read records up to and including the end of `kind`'s stream,
+    /// returning that stream's length
+    fn drain(mut stream: &UnixStream, kind: u8) -> usize {
+        let mut total = 0;
+        loop {
+            let mut header = [0u8; 8];
+            stream.read_exact(&mut header).unwrap();
+            let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let mut content = vec![0u8; len + header[6] as usize];
+            stream.read_exact(&mut content).unwrap();
+            if header[1] == kind {
+                total += len;
+                if len == 0 {
+                    return total;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_bodies_do_not_deadlock() {
+        // The responder writes more than a socket buffer holds before it
+        // reads its input
+        let (client, server) = UnixStream::pair().unwrap();
+        std::thread::spawn(move || {
+            drain(&server, PARAMS);
+            let mut records = Vec::new();
+            push_stream(&mut records, STDOUT, &vec![b'z'; 1024 * 1024]);
+            records.truncate(records.len() - 8);
+            (&server).write_all(&records).unwrap();
+            let read = drain(&server, STDIN);
+            let mut records = Vec::new();
+            push_record(&mut records, STDOUT, format!(" read={}", read).as_bytes());
+            push_record(&mut records, END_REQUEST, &[0; 8]);
+            (&server).write_all(&records).unwrap();
+        });
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let body = vec![b'a'; 1024 * 1024];
+            let _ = tx.send(run(&client, &HashMap::new(), &body));
+        });
+        let output = rx
+            .recv_timeout(std::time::Duration::from_secs(20))
+            .expect("FastCGI request deadlocked")
+            .unwrap();
+        assert_eq!(output.app_status, 0);
+        assert!(output.stdout.ends_with(b" read=1048576"));
+        assert_eq!(
+            output.stdout.iter().filter(|&&b| b == b'z').count(),
+            1024 * 1024
+        );
+    }
+}
@@ -16,12 +16,17 @@ use std::env;
 use std::path::PathBuf;
 use std::process::exit;
 
+#[cfg(unix)]
+mod fastcgi;
+#[cfg(unix)]
 mod pool;
-mod protocol;
 mod server;
+#[cfg(unix)]
 mod worker;
 
 use server::PhpWorkerServer;
+// The wire protocol lives in the library so VeloServe and vephp share it
+use veloserve::php::protocol;
 
 pub const DEFAULT_SOCKET: &str = "/run/veloserve/php.sock";
 pub const DEFAULT_WORKERS: usize = 8;
@@ -42,6 +47,9 @@ fn print_usage() {
     );
     eprintln!("  -m, --memory <LIMIT>      PHP memory limit [default: 256M]");
     eprintln!("  -t, --timeout <SECS>      Max execution time [default: 30]");
+    eprintln!(
+        "  --max-requests <N>        Replace a worker after N requests, 0 = never [default: 0]"
+    );
     eprintln!("  -c, --config <FILE>       PHP ini file path");
     eprintln!("  --php <PATH>              Path to php-cgi binary (auto-detects EA-PHP)");
     eprintln!("  -d, --daemon              Run as daemon");
@@ -75,6 +83,7 @@ pub struct Config {
     pub workers: usize,
     pub memory_limit: String,
     pub max_execution_time: u32,
+    pub max_requests: u64,
     pub php_ini: Option<PathBuf>,
     pub php_binary: Option<PathBuf>,
     pub daemon: bool,
@@ -90,6 +99,7 @@ impl Default for Config {
             workers: DEFAULT_WORKERS,
             memory_limit: "256M".to_string(),
             max_execution_time: 30,
+            max_requests: 0,
             php_ini: None,
             php_binary: None,
             daemon: false,
@@ -184,6 +194,14 @@ fn parse_args() -> Config {
                     }
                 }
             }
+            "--max-requests" => {
+                i += 1;
+                if i < args.len() {
+                    if let Ok(n) = args[i].parse() {
+                        config.max_requests = n;
+                    }
+                }
+            }
             "-c" | "--config" => {
                 i += 1;
                 if i < args.len() {
@@ -239,14 +257,17 @@ fn main() {
     println!("[vephp] Workers: {}", config.workers);
     println!("[vephp] Memory limit: {}", config.memory_limit);
     println!("[vephp] Timeout: {}s", config.max_execution_time);
+    if config.max_requests > 0 {
+        println!("[vephp] Max requests per worker: {}", config.max_requests);
+    }
 
     if let Some(ref user) = config.user {
         println!("[vephp] Running as user: {}", user);
     }
 
-    let server = PhpWorkerServer::new(config, php_binary);
+    let result = PhpWorkerServer::new(config, php_binary).map_err(Into::into);
 
-    if let Err(e) = server.run() {
+    if let Err(e) = result.and_then(|server| server.run()) {
         eprintln!("[vephp] Fatal error: {}", e);
         exit(1);
     }
@@ -263,5 +284,6 @@ mod tests {
         assert_eq!(config.workers, DEFAULT_WORKERS);
         assert_eq!(config.memory_limit, "256M");
         assert_eq!(config.max_execution_time, 30);
+        assert_eq!(config.max_requests, 0);
     }
 }
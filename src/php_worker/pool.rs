@@ -2,26 +2,47 @@
 //!
 //! Manages a pool of PHP worker processes for handling concurrent requests.
 //! Uses EA-PHP, CloudLinux alt-PHP, or system php-cgi as the execution engine.
+//!
+//! Like PHP-FPM's `pm.max_requests`, a worker that has served `max_requests`
+//! requests is killed and replaced before it takes another one, so memory
+//! leaked by long-running extensions is given back.
 
 use std::collections::VecDeque;
 use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
 
 use crate::protocol::{PhpRequest, PhpResponse};
+use crate::worker::Worker;
+
+/// First delay before respawning a worker that failed to start
+const RESPAWN_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest delay between respawn attempts
+const MAX_RESPAWN_BACKOFF: Duration = Duration::from_secs(30);
 
-pub struct PhpWorker {
-    pub id: usize,
-    pub process: Child,
-    pub busy: bool,
+/// A place in the pool for one worker process
+struct Slot {
+    id: usize,
+    /// `None` while the process is being replaced
+    worker: Option<Worker>,
+    busy: bool,
+    /// Spawn attempts that have failed in a row
+    failures: u32,
+    /// When the next spawn may be tried
+    retry_at: Instant,
 }
 
 pub struct WorkerPool {
-    workers: Vec<PhpWorker>,
-    max_workers: usize,
+    slots: Vec<Slot>,
     memory_limit: String,
     max_execution_time: u32,
     php_ini: Option<PathBuf>,
     php_binary: PathBuf,
+    /// Holds the workers' FastCGI sockets
+    socket_dir: tempfile::TempDir,
+    /// Requests a worker serves before it is replaced (0 = never)
+    max_requests: u64,
+    /// Workers replaced after reaching `max_requests`
+    recycled: u64,
     request_queue: VecDeque<PhpRequest>,
 }
 
@@ -32,71 +53,130 @@ impl WorkerPool {
         max_execution_time: u32,
         php_ini: Option<PathBuf>,
         php_binary: PathBuf,
-    ) -> Self {
+        max_requests: u64,
+    ) -> std::io::Result<Self> {
+        let now = Instant::now();
         let mut pool = Self {
-            workers: Vec::with_capacity(max_workers),
-            max_workers,
+            slots: (0..max_workers)
+                .map(|id| Slot {
+                    id,
+                    worker: None,
+                    busy: false,
+                    failures: 0,
+                    retry_at: now,
+                })
+                .collect(),
             memory_limit,
             max_execution_time,
             php_ini,
             php_binary,
+            socket_dir: tempfile::Builder::new().prefix("vephp-").tempdir()?,
+            max_requests,
+            recycled: 0,
             request_queue: VecDeque::new(),
         };
 
         pool.spawn_workers();
-        pool
+        Ok(pool)
     }
 
+    /// Start a process in every empty slot whose backoff has passed
     fn spawn_workers(&mut self) {
-        for id in 0..self.max_workers {
-            match self.spawn_worker(id) {
-                Ok(worker) => {
-                    self.workers.push(worker);
-                }
-                Err(e) => {
-                    eprintln!("[vephp] Failed to spawn worker {}: {}", id, e);
+        let now = Instant::now();
+        for index in 0..self.slots.len() {
+            let slot = &mut self.slots[index];
+            if let Some(worker) = slot.worker.as_mut() {
+                if worker.is_alive() {
+                    continue;
                 }
+                eprintln!(
+                    "[vephp] Worker {} (pid {}) exited, respawning",
+                    slot.id,
+                    worker.pid()
+                );
+                slot.worker = None;
+            }
+            if slot.retry_at <= now {
+                self.spawn_worker(index);
             }
         }
     }
 
-    fn spawn_worker(&self, id: usize) -> Result<PhpWorker, Box<dyn std::error::Error>> {
-        let mut cmd = Command::new(&self.php_binary);
+    fn spawn_worker(&mut self, index: usize) {
+        let id = self.slots[index].id;
+        let spawned = Worker::spawn(
+            &self.php_binary,
+            self.php_ini.as_deref(),
+            &self.memory_limit,
+            self.max_execution_time,
+            self.socket_dir.path().join(format!("worker-{}.sock", id)),
+        );
 
-        if let Some(ref ini) = self.php_ini {
-            cmd.arg("-c").arg(ini);
+        let slot = &mut self.slots[index];
+        match spawned {
+            Ok(worker) => {
+                slot.worker = Some(worker);
+                slot.failures = 0;
+            }
+            Err(e) => {
+                // Keep the slot and try again later, backing off each time
+                let backoff = RESPAWN_BACKOFF
+                    .saturating_mul(1 << slot.failures.min(16))
+                    .min(MAX_RESPAWN_BACKOFF);
+                slot.failures += 1;
+                slot.retry_at = Instant::now() + backoff;
+                eprintln!(
+                    "[vephp] Failed to spawn worker {}: {} (retrying in {:?})",
+                    id, e, backoff
+                );
+            }
         }
+    }
 
-        cmd.arg("-d")
-            .arg(format!("memory_limit={}", self.memory_limit));
-        cmd.arg("-d")
-            .arg(format!("max_execution_time={}", self.max_execution_time));
-        cmd.arg("-q");
-
-        cmd.stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let process = cmd.spawn()?;
-
-        Ok(PhpWorker {
-            id,
-            process,
-            busy: false,
-        })
+    /// Replace the worker at `index` with a fresh process
+    ///
+    /// If the replacement can't be spawned the slot stays empty and is
+    /// retried with backoff, rather than reusing a worker past its limit.
+    fn recycle(&mut self, index: usize) {
+        if let Some(mut worker) = self.slots[index].worker.take() {
+            worker.kill();
+        }
+        self.recycled += 1;
+        self.spawn_worker(index);
     }
 
     pub fn execute(&mut self, request: &PhpRequest) -> PhpResponse {
-        if let Some(worker) = self.workers.iter_mut().find(|w| !w.busy) {
-            worker.busy = true;
-            let result = run_php(
-                &self.php_binary,
-                &self.memory_limit,
-                self.max_execution_time,
-                request,
-            );
-            worker.busy = false;
-            result
+        self.spawn_workers();
+
+        if let Some(index) = self
+            .slots
+            .iter()
+            .position(|s| s.worker.is_some() && !s.busy)
+        {
+            self.slots[index].busy = true;
+            let worker = self.slots[index].worker.as_mut().expect("live worker");
+            let result = worker.execute(request);
+            let served = worker.requests();
+            self.slots[index].busy = false;
+
+            match result {
+                Ok(response) => {
+                    if self.max_requests > 0 && served >= self.max_requests {
+                        self.recycle(index);
+                    }
+                    response
+                }
+                Err(e) => {
+                    let id = self.slots[index].id;
+                    if let Some(mut worker) = self.slots[index].worker.take() {
+                        worker.kill();
+                    }
+                    self.spawn_worker(index);
+                    PhpResponse::error(&format!("PHP worker {} failed: {}", id, e))
+                }
+            }
+        } else if self.slots.iter().all(|s| s.worker.is_none()) {
+            PhpResponse::error("No PHP workers are running")
         } else if self.request_queue.len() < 100 {
             self.request_queue.push_back(request.clone());
             PhpResponse::queued()
@@ -106,63 +186,158 @@ impl WorkerPool {
     }
 
     pub fn status_json(&self) -> String {
-        let total = self.workers.len();
-        let busy = self.workers.iter().filter(|w| w.busy).count();
+        let total = self.slots.iter().filter(|s| s.worker.is_some()).count();
+        let busy = self.slots.iter().filter(|s| s.busy).count();
         let available = total - busy;
         let queued = self.request_queue.len();
 
         format!(
-            "{{\"total_workers\":{},\"busy\":{},\"available\":{},\"queued\":{},\"php_binary\":\"{}\"}}",
-            total, busy, available, queued,
+            "{{\"total_workers\":{},\"busy\":{},\"available\":{},\"queued\":{},\"recycled\":{},\"max_requests\":{},\"php_binary\":\"{}\"}}",
+            total, busy, available, queued, self.recycled, self.max_requests,
             self.php_binary.display()
         )
     }
 
     pub fn shutdown(&mut self) {
-        for worker in &mut self.workers {
-            let _ = worker.process.kill();
+        for slot in &mut self.slots {
+            if let Some(mut worker) = slot.worker.take() {
+                worker.kill();
+            }
         }
-        self.workers.clear();
+        self.slots.clear();
     }
 }
 
-fn run_php(
-    php_binary: &std::path::Path,
-    memory_limit: &str,
-    max_execution_time: u32,
-    request: &PhpRequest,
-) -> PhpResponse {
-    let mut cmd = Command::new(php_binary);
-    cmd.arg("-d").arg(format!("memory_limit={}", memory_limit));
-    cmd.arg("-d")
-        .arg(format!("max_execution_time={}", max_execution_time));
-    cmd.arg(&request.script_path);
-
-    for (key, value) in &request.server_vars {
-        cmd.env(key, value);
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shutdown();
     }
+}
 
-    match cmd.output() {
-        Ok(result) => {
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            let stderr = String::from_utf8_lossy(&result.stderr);
-
-            if result.status.success() {
-                PhpResponse::ok(&stdout, &stderr)
-            } else {
-                PhpResponse::error(&format!(
-                    "PHP exit code {:?}: {}",
-                    result.status.code(),
-                    stderr
-                ))
-            }
-        }
-        Err(e) => PhpResponse::error(&format!("Failed to execute PHP ({:?}): {}", php_binary, e)),
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A stand-in for php-cgi in FastCGI mode: accepts on stdin and answers
+    /// each request with its PID, how many requests it has served and how
+    /// many body bytes it read
+    fn fake_php(dir: &std::path::Path) -> PathBuf {
+        let php = dir.join("php-cgi");
+        std::fs::write(
+            &php,
+            r#"#!/usr/bin/perl
+open(my $listen, '<&=', 0) or die "fd 0: $!";
+my $served = 0;
+while (accept(my $conn, $listen)) {
+    my ($id, $bytes) = (1, 0);
+    while (read($conn, my $header, 8) == 8) {
+        my ($type, $rid, $len, $pad) = unpack('xCnnC', $header);
+        read($conn, my $content, $len + $pad) if $len + $pad;
+        $id = $rid;
+        $bytes += $len if $type == 5;
+        last if $type == 5 && $len == 0;
     }
+    $served++;
+    my $out = "Content-Type: text/plain\r\n\r\npid=$$ served=$served bytes=$bytes";
+    print $conn pack('CCnnCx', 1, 6, $id, length($out), 0), $out;
+    print $conn pack('CCnnCx', 1, 3, $id, 8, 0), pack('NCx3', 0, 0);
+    close($conn);
 }
+"#,
+        )
+        .unwrap();
+        std::fs::set_permissions(&php, std::fs::Permissions::from_mode(0o755)).unwrap();
+        php
+    }
 
-impl Drop for WorkerPool {
-    fn drop(&mut self) {
-        self.shutdown();
+    /// The `pid=` and `served=` values the stub reported
+    fn served_by(response: &PhpResponse) -> (String, u64) {
+        assert!(response.success, "{:?}", response.error);
+        let field = |name: &str| {
+            response
+                .body
+                .split_whitespace()
+                .find_map(|part| part.strip_prefix(name))
+                .unwrap_or_else(|| panic!("no {} in {:?}", name, response.body))
+                .to_string()
+        };
+        (field("pid="), field("served=").parse().unwrap())
+    }
+
+    #[test]
+    fn test_workers_serve_requests_until_recycled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pool =
+            WorkerPool::new(1, "64M".to_string(), 5, None, fake_php(dir.path()), 3).unwrap();
+        let mut request = PhpRequest::execute(dir.path().join("index.php"));
+        request.body = vec![b'a'; 200 * 1024];
+
+        // The same process serves requests until it reaches the limit
+        let (original, _) = served_by(&pool.execute(&request));
+        for expected in 2..=3 {
+            let response = pool.execute(&request);
+            assert!(response.body.ends_with("bytes=204800"), "{}", response.body);
+            assert_eq!(served_by(&response), (original.clone(), expected));
+        }
+
+        // The next request goes to a fresh process
+        let (replacement, served) = served_by(&pool.execute(&request));
+        assert_ne!(replacement, original);
+        assert_eq!(served, 1);
+        assert_eq!(pool.recycled, 1);
+        assert!(pool.status_json().contains("\"recycled\":1"));
+
+        // 0 never recycles
+        let mut pool =
+            WorkerPool::new(1, "64M".to_string(), 5, None, fake_php(dir.path()), 0).unwrap();
+        let (original, _) = served_by(&pool.execute(&request));
+        for expected in 2..=5 {
+            assert_eq!(
+                served_by(&pool.execute(&request)),
+                (original.clone(), expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_spawns_keep_the_slot_and_back_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let request = PhpRequest::execute(dir.path().join("index.php"));
+        let php = dir.path().join("php-cgi");
+        let mut pool = WorkerPool::new(1, "64M".to_string(), 5, None, php, 1).unwrap();
+        assert_eq!(pool.slots.len(), 1);
+        assert_eq!(pool.slots[0].failures, 1);
+
+        // Still inside the backoff, so no new attempt is made
+        assert!(!pool.execute(&request).success);
+        assert_eq!(pool.slots[0].failures, 1);
+
+        // Once the binary exists the slot is filled again
+        fake_php(dir.path());
+        std::thread::sleep(RESPAWN_BACKOFF * 2);
+        served_by(&pool.execute(&request));
+        assert_eq!(pool.slots[0].failures, 0);
+    }
+
+    #[test]
+    fn test_dead_workers_are_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pool =
+            WorkerPool::new(1, "64M".to_string(), 5, None, fake_php(dir.path()), 0).unwrap();
+        let request = PhpRequest::execute(dir.path().join("index.php"));
+        let (original, _) = served_by(&pool.execute(&request));
+
+        let worker = pool.slots[0].worker.as_mut().unwrap();
+        nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(worker.pid() as i32),
+            nix::sys::signal::Signal::SIGKILL,
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let (replacement, served) = served_by(&pool.execute(&request));
+        assert_ne!(replacement, original);
+        assert_eq!(served, 1);
     }
 }
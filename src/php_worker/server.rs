@@ -4,8 +4,6 @@
 //! and dispatches them to worker processes using EA-PHP or system PHP.
 //! Unix-only: uses Unix domain sockets for IPC.
 
-#[cfg(unix)]
-use std::io::{Read, Write};
 #[cfg(unix)]
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
@@ -17,7 +15,7 @@ use std::thread;
 #[cfg(unix)]
 use crate::pool::WorkerPool;
 #[cfg(unix)]
-use crate::protocol::{PhpRequest, PhpResponse, RequestType};
+use crate::protocol::{self, PhpRequest, PhpResponse, RequestType};
 use crate::Config;
 
 pub struct PhpWorkerServer {
@@ -27,7 +25,7 @@ pub struct PhpWorkerServer {
 }
 
 impl PhpWorkerServer {
-    pub fn new(config: Config, php_binary: PathBuf) -> Self {
+    pub fn new(config: Config, php_binary: PathBuf) -> std::io::Result<Self> {
         #[cfg(unix)]
         let pool = Arc::new(Mutex::new(WorkerPool::new(
             config.workers,
@@ -35,13 +33,16 @@ impl PhpWorkerServer {
             config.max_execution_time,
             config.php_ini.clone(),
             php_binary,
-        )));
+            config.max_requests,
+        )?));
+        #[cfg(not(unix))]
+        let _ = php_binary;
 
-        Self {
+        Ok(Self {
             config,
             #[cfg(unix)]
             pool,
-        }
+        })
     }
 
     #[cfg(unix)]
@@ -99,54 +100,39 @@ fn handle_connection(
     pool: Arc<Mutex<WorkerPool>>,
     verbose: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut buffer = [0u8; 65536];
-    let bytes_read = stream.read(&mut buffer)?;
-
-    if bytes_read == 0 {
-        return Ok(());
-    }
-
-    let request: PhpRequest = match bincode::deserialize(&buffer[..bytes_read]) {
-        Ok(req) => req,
-        Err(e) => {
-            let response = PhpResponse::error(&format!("Invalid request: {}", e));
-            send_response(&mut stream, &response)?;
-            return Ok(());
-        }
-    };
-
-    if verbose {
-        println!(
-            "[vephp] Request: {:?} {}",
-            request.request_type,
-            request.script_path.display()
-        );
-    }
-
-    let response = match request.request_type {
-        RequestType::Execute => {
-            let mut pool = pool.lock().unwrap();
-            pool.execute(&request)
-        }
-        RequestType::HealthCheck => PhpResponse::ok("healthy", ""),
-        RequestType::Status => {
-            let pool = pool.lock().unwrap();
-            PhpResponse::ok("status", &pool.status_json())
+    // VeloServe keeps connections open, so serve requests until it hangs up
+    loop {
+        let request: PhpRequest = match protocol::read_frame(&mut stream) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                let response = PhpResponse::error(&format!("Invalid request: {}", e));
+                protocol::write_frame(&mut stream, &response)?;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if verbose {
+            println!(
+                "[vephp] Request: {:?} {}",
+                request.request_type,
+                request.script_path.display()
+            );
         }
-    };
-
-    send_response(&mut stream, &response)?;
 
-    Ok(())
-}
+        let response = match request.request_type {
+            RequestType::Execute => {
+                let mut pool = pool.lock().unwrap();
+                pool.execute(&request)
+            }
+            RequestType::HealthCheck => PhpResponse::ok("healthy", ""),
+            RequestType::Status => {
+                let pool = pool.lock().unwrap();
+                PhpResponse::ok("status", &pool.status_json())
+            }
+        };
 
-#[cfg(unix)]
-fn send_response(
-    stream: &mut UnixStream,
-    response: &PhpResponse,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let response_bytes = bincode::serialize(response)?;
-    stream.write_all(&response_bytes)?;
-    stream.flush()?;
-    Ok(())
+        protocol::write_frame(&mut stream, &response)?;
+    }
 }
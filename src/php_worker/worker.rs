@@ -2,72 +2,109 @@
 //!
 //! Manages a single PHP worker process and communication with it.
 //! Uses EA-PHP, CloudLinux alt-PHP, or system php-cgi.
-
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-
+//!
+//! The worker is php-cgi in FastCGI mode: it is started with a listening
+//! Unix socket as its stdin, as spawn-fcgi does, and serves one request per
+//! connection until it is killed.
+
+use std::collections::HashMap;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use crate::fastcgi;
 use crate::protocol::{PhpRequest, PhpResponse};
 
 /// Individual PHP worker process
 pub struct Worker {
-    id: usize,
     process: Child,
-    stdin: ChildStdin,
-    stdout: ChildStdout,
+    socket: PathBuf,
+    /// Requests served by this process
+    requests: u64,
+    timeout: Option<Duration>,
 }
 
 impl Worker {
-    /// Spawn a new PHP worker process using the specified PHP binary
+    /// Spawn a new PHP worker process listening on `socket`
     pub fn spawn(
-        id: usize,
-        php_binary: &PathBuf,
-        php_ini: Option<&PathBuf>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+        php_binary: &Path,
+        php_ini: Option<&Path>,
+        memory_limit: &str,
+        max_execution_time: u32,
+        socket: PathBuf,
+    ) -> std::io::Result<Self> {
+        // A previous process in this slot may have left its socket behind
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket)?;
+
         let mut cmd = Command::new(php_binary);
 
         if let Some(ini) = php_ini {
             cmd.arg("-c").arg(ini);
         }
 
-        cmd.arg("-q");
+        cmd.arg("-d").arg(format!("memory_limit={}", memory_limit));
+        cmd.arg("-d")
+            .arg(format!("max_execution_time={}", max_execution_time));
 
-        cmd.stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+        // One process per worker, replaced only by the pool
+        cmd.env("PHP_FCGI_CHILDREN", "0")
+            .env("PHP_FCGI_MAX_REQUESTS", "0");
 
-        let mut process = cmd.spawn()?;
-        let stdin = process.stdin.take().ok_or("Failed to get stdin")?;
-        let stdout = process.stdout.take().ok_or("Failed to get stdout")?;
+        cmd.stdin(Stdio::from(OwnedFd::from(listener)))
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit());
+
+        let process = cmd.spawn()?;
 
         Ok(Self {
-            id,
             process,
-            stdin,
-            stdout,
+            socket,
+            requests: 0,
+            // Leave PHP's own limit room to report the timeout itself
+            timeout: (max_execution_time > 0)
+                .then(|| Duration::from_secs(u64::from(max_execution_time) + 5)),
         })
     }
 
     /// Execute a PHP request in this worker
-    pub fn execute(
-        &mut self,
-        request: &PhpRequest,
-    ) -> Result<PhpResponse, Box<dyn std::error::Error>> {
-        // Serialize request
-        let request_bytes = bincode::serialize(request)?;
-
-        // Send to worker
-        self.stdin.write_all(&request_bytes)?;
-        self.stdin.flush()?;
-
-        // Read response
-        let mut buffer = vec![0u8; 65536];
-        let bytes_read = self.stdout.read(&mut buffer)?;
-
-        // Deserialize response
-        let response: PhpResponse = bincode::deserialize(&buffer[..bytes_read])?;
-
-        Ok(response)
+    ///
+    /// An `Err` means the worker itself failed and should be replaced; errors
+    /// from the script come back as an unsuccessful response.
+    pub fn execute(&mut self, request: &PhpRequest) -> std::io::Result<PhpResponse> {
+        let stream = UnixStream::connect(&self.socket)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let mut params: HashMap<String, String> = request.server_vars.clone();
+        params
+            .entry("SCRIPT_FILENAME".to_string())
+            .or_insert_with(|| request.script_path.display().to_string());
+        params
+            .entry("CONTENT_LENGTH".to_string())
+            .or_insert_with(|| request.body.len().to_string());
+        params
+            .entry("REQUEST_METHOD".to_string())
+            .or_insert_with(|| request.method.clone());
+        params
+            .entry("REDIRECT_STATUS".to_string())
+            .or_insert_with(|| "200".to_string());
+
+        let output = fastcgi::run(&stream, &params, &request.body)?;
+        self.requests += 1;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.app_status == 0 {
+            Ok(PhpResponse::ok(&stdout, &stderr))
+        } else {
+            Ok(PhpResponse::error(&format!(
+                "PHP exit code {}: {}",
+                output.app_status, stderr
+            )))
+        }
     }
 
     /// Check if worker is still alive
@@ -78,21 +115,27 @@ impl Worker {
         }
     }
 
-    /// Get worker ID
-    pub fn id(&self) -> usize {
-        self.id
+    /// Process ID of the php-cgi process
+    pub fn pid(&self) -> u32 {
+        self.process.id()
+    }
+
+    /// Requests served by this process
+    pub fn requests(&self) -> u64 {
+        self.requests
     }
 
     /// Kill the worker process
-    pub fn kill(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.process.kill()?;
-        Ok(())
+    pub fn kill(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = std::fs::remove_file(&self.socket);
     }
 }
 
 impl Drop for Worker {
     fn drop(&mut self) {
-        let _ = self.process.kill();
+        self.kill();
     }
 }
 
@@ -102,10 +145,11 @@ mod tests {
 
     #[test]
     fn test_worker_spawn() {
+        let dir = tempfile::tempdir().unwrap();
         let php = PathBuf::from("php-cgi");
-        if let Ok(mut worker) = Worker::spawn(0, &php, None) {
+        if let Ok(mut worker) = Worker::spawn(&php, None, "64M", 5, dir.path().join("0.sock")) {
             assert!(worker.is_alive());
-            let _ = worker.kill();
+            worker.kill();
         }
     }
 }
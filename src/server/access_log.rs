@@ -0,0 +1,131 @@
+//! Access log file
+//!
+//! With `server.access_log` set, rendered lines go to a dedicated writer
+//! task over a bounded channel, so a slow disk never holds up a response.
+//! The task buffers writes and flushes once a second (and when it shuts
+//! down). If it falls behind, lines are dropped rather than queued without
+//! bound, and the number dropped is logged at the next flush.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Lines waiting for the writer before new ones are dropped
+const QUEUE_LINES: usize = 8192;
+
+/// How often buffered lines are flushed to the file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Write buffer size
+const BUFFER_BYTES: usize = 64 * 1024;
+
+/// Where access log lines go: the file writer once opened, else tracing
+#[derive(Default)]
+pub struct AccessLog {
+    sender: OnceLock<mpsc::Sender<String>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// Open `path` for appending and start the writer task
+    ///
+    /// Called once, before privileges are dropped, so the file may live in
+    /// a directory only root can write.
+    pub async fn open(&self, path: &Path) -> std::io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (sender, receiver) = mpsc::channel(QUEUE_LINES);
+        if self.sender.set(sender).is_err() {
+            return Ok(());
+        }
+        info!("Writing access log to {:?}", path);
+        tokio::spawn(run_writer(
+            BufWriter::with_capacity(BUFFER_BYTES, file),
+            receiver,
+            self.dropped.clone(),
+        ));
+        Ok(())
+    }
+
+    /// Log one rendered line
+    pub fn write(&self, line: String) {
+        match self.sender.get() {
+            Some(sender) => {
+                if sender.try_send(line).is_err() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            None => info!(target: "veloserve::access", "{}", line),
+        }
+    }
+}
+
+async fn run_writer(
+    mut writer: BufWriter<tokio::fs::File>,
+    mut lines: mpsc::Receiver<String>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    let mut dirty = false;
+    loop {
+        tokio::select! {
+            line = lines.recv() => {
+                let Some(mut line) = line else { break };
+                line.push('\n');
+                if let Err(e) = writer.write_all(line.as_bytes()).await {
+                    warn!("Failed to write access log: {}", e);
+                }
+                dirty = true;
+            }
+            _ = flush.tick() => {
+                let lost = dropped.swap(0, Ordering::Relaxed);
+                if lost > 0 {
+                    warn!("Access log writer fell behind, dropped {} lines", lost);
+                }
+                if dirty {
+                    if let Err(e) = writer.flush().await {
+                        warn!("Failed to flush access log: {}", e);
+                    }
+                    dirty = false;
+                }
+            }
+        }
+    }
+    let _ = writer.flush().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lines_reach_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        std::fs::write(&path, "earlier\n").unwrap();
+
+        let log = AccessLog::default();
+        log.open(&path).await.unwrap();
+        log.write("GET / 200".to_string());
+        log.write("GET /a 404".to_string());
+
+        let mut contents = String::new();
+        for _ in 0..40 {
+            contents = std::fs::read_to_string(&path).unwrap();
+            if contents.lines().count() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(contents, "earlier\nGET / 200\nGET /a 404\n");
+    }
+}
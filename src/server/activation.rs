@@ -0,0 +1,184 @@
+//! systemd socket activation
+//!
+//! When started from a `.socket` unit, systemd passes already-bound listening
+//! sockets as file descriptors 3.. and describes them with `LISTEN_FDS`,
+//! `LISTEN_PID` and `LISTEN_FDNAMES` (see sd_listen_fds(3)). VeloServe adopts
+//! those sockets instead of binding `server.listen`/`server.listen_ssl`, so
+//! ports 80/443 can be served without ever running as root.
+
+use std::net::SocketAddr;
+
+use anyhow::{anyhow, Result};
+
+/// First file descriptor passed by systemd
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Listening sockets handed over by the service manager
+#[derive(Debug, Default)]
+pub struct ActivatedListeners {
+    sockets: Vec<ActivatedSocket>,
+}
+
+#[derive(Debug)]
+struct ActivatedSocket {
+    /// Name from `LISTEN_FDNAMES` (`FileDescriptorName=` in the socket unit)
+    name: Option<String>,
+    /// Address the socket is bound to
+    addr: SocketAddr,
+    listener: std::net::TcpListener,
+}
+
+impl ActivatedListeners {
+    /// Adopt sockets passed via `LISTEN_FDS`, or `None` when not socket-activated.
+    pub fn from_env() -> Result<Option<Self>> {
+        let fds = match std::env::var("LISTEN_FDS") {
+            Ok(fds) => fds,
+            Err(_) => return Ok(None),
+        };
+
+        // The variables are inherited by children; only the intended process adopts them
+        if let Ok(pid) = std::env::var("LISTEN_PID") {
+            if pid.trim().parse::<u32>().ok() != Some(std::process::id()) {
+                return Ok(None);
+            }
+        }
+
+        let count: i32 = fds
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid LISTEN_FDS value: {:?}", fds))?;
+        let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+            .map(|names| names.split(':').map(str::to_string).collect())
+            .unwrap_or_default();
+
+        Self::adopt(count, &names).map(Some)
+    }
+
+    #[cfg(unix)]
+    fn adopt(count: i32, names: &[String]) -> Result<Self> {
+        let mut sockets = Vec::new();
+        for index in 0..count.max(0) {
+            let fd = SD_LISTEN_FDS_START + index;
+            let listener = adopt_tcp_listener(fd)
+                .map_err(|e| anyhow!("Socket-activated fd {} is unusable: {}", fd, e))?;
+            let addr = listener.local_addr()?;
+            let name = names
+                .get(index as usize)
+                .filter(|name| !name.is_empty() && name.as_str() != "unknown")
+                .cloned();
+            sockets.push(ActivatedSocket {
+                name,
+                addr,
+                listener,
+            });
+        }
+        Ok(Self { sockets })
+    }
+
+    #[cfg(not(unix))]
+    fn adopt(_count: i32, _names: &[String]) -> Result<Self> {
+        Err(anyhow!("Socket activation is only supported on Unix"))
+    }
+
+    /// Take the socket named `name`, falling back to one bound to `addr`.
+    pub fn take(&mut self, name: &str, addr: Option<SocketAddr>) -> Option<std::net::TcpListener> {
+        let index = self
+            .sockets
+            .iter()
+            .position(|s| s.name.as_deref() == Some(name))
+            .or_else(|| {
+                let addr = addr?;
+                self.sockets.iter().position(|s| s.addr == addr)
+            })?;
+        Some(self.sockets.remove(index).listener)
+    }
+
+    /// Number of sockets not yet claimed by a listener
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    /// Returns true when every passed socket has been claimed
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    /// Descriptions of unclaimed sockets, for logging
+    pub fn describe_remaining(&self) -> Vec<String> {
+        self.sockets
+            .iter()
+            .map(|s| match &s.name {
+                Some(name) => format!("{} ({})", s.addr, name),
+                None => s.addr.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Take ownership of `fd` after checking it is a listening TCP socket.
+#[cfg(unix)]
+fn adopt_tcp_listener(fd: i32) -> std::io::Result<std::net::TcpListener> {
+    use socket2::{Socket, Type};
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: systemd hands these descriptors to this process exclusively; they
+    // are adopted once and closed when the returned listener is dropped.
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+
+    if socket.r#type()? != Type::STREAM {
+        return Err(Error::new(ErrorKind::InvalidInput, "not a stream socket"));
+    }
+    if socket.local_addr()?.as_socket().is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "not an IPv4/IPv6 socket",
+        ));
+    }
+    #[cfg(target_os = "linux")]
+    if !socket.is_listener()? {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "socket is not listening",
+        ));
+    }
+
+    // Keep the sockets out of spawned PHP processes
+    socket.set_cloexec(true)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listener(name: Option<&str>) -> ActivatedSocket {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        ActivatedSocket {
+            name: name.map(str::to_string),
+            addr: listener.local_addr().unwrap(),
+            listener,
+        }
+    }
+
+    #[test]
+    fn test_take_prefers_name_then_address() {
+        let unnamed = listener(None);
+        let unnamed_addr = unnamed.addr;
+        let https = listener(Some("https"));
+        let https_addr = https.addr;
+        let mut activated = ActivatedListeners {
+            sockets: vec![unnamed, https],
+        };
+
+        let taken = activated.take("https", Some(unnamed_addr)).unwrap();
+        assert_eq!(taken.local_addr().unwrap(), https_addr);
+
+        assert!(activated.take("http", None).is_none());
+        let taken = activated.take("http", Some(unnamed_addr)).unwrap();
+        assert_eq!(taken.local_addr().unwrap(), unnamed_addr);
+        assert!(activated.is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Directory listings
+//!
+//! Vhosts with `autoindex = true` answer a request for a directory without an
+//! index file with a listing in the style of Nginx's autoindex: a parent link,
+//! then subdirectories and files, each sorted by name, with their
+//! last-modified time and size. Dotfiles are left out, as are symlinks the
+//! vhost's `follow_symlinks` would refuse to serve. Names are percent-encoded
+//! in links and HTML-escaped everywhere else.
+
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::fs;
+
+use crate::config::SymlinkPolicy;
+use crate::server::symlinks;
+
+/// Column the modification time starts at, as in Nginx
+const NAME_WIDTH: usize = 50;
+
+/// Bytes that can't appear raw in a path segment of a link
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'\'')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Render the listing of `dir`, requested as `uri_path` (still percent-encoded)
+pub async fn render(dir: &Path, uri_path: &str, policy: SymlinkPolicy) -> io::Result<String> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        if policy != SymlinkPolicy::Always {
+            let link = fs::symlink_metadata(entry.path()).await?;
+            if link.file_type().is_symlink()
+                && !symlinks::link_allowed(&entry.path(), &link, policy)
+            {
+                continue;
+            }
+        }
+        // Follows symlinks; dangling ones are skipped
+        let Ok(metadata) = fs::metadata(entry.path()).await else {
+            continue;
+        };
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    // Links are absolute so they work with or without the trailing slash
+    let base = format!("{}/", uri_path.trim_end_matches('/'));
+    let title = escape_html(&percent_decode_str(&base).decode_utf8_lossy());
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<hr>\n<pre>\n"
+    );
+    if base != "/" {
+        let parent = base[..base.len() - 1]
+            .rsplit_once('/')
+            .map(|(parent, _)| format!("{}/", parent))
+            .unwrap_or_else(|| "/".to_string());
+        html.push_str(&format!("<a href=\"{}\">../</a>\n", escape_html(&parent)));
+    }
+
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let href = format!(
+            "{}{}{}",
+            base,
+            utf8_percent_encode(&entry.name, SEGMENT),
+            suffix
+        );
+        let label = format!("{}{}", entry.name, suffix);
+        let padding = NAME_WIDTH.saturating_sub(label.chars().count()).max(1);
+        let modified = entry
+            .modified
+            .map(|time| {
+                DateTime::<Utc>::from(time)
+                    .format("%d-%b-%Y %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".repeat(17));
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        html.push_str(&format!(
+            "<a href=\"{}\">{}</a>{}{} {:>19}\n",
+            escape_html(&href),
+            escape_html(&label),
+            " ".repeat(padding),
+            modified,
+            size
+        ));
+    }
+
+    html.push_str("</pre>\n<hr>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_listing_is_sorted_escaped_and_hides_dotfiles() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b.txt"), "12345").unwrap();
+        std::fs::write(dir.path().join("a <b>&\"c\".txt"), "").unwrap();
+        std::fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+        std::fs::create_dir(dir.path().join("zdir")).unwrap();
+
+        let html = render(dir.path(), "/files/my%20stuff", SymlinkPolicy::Always)
+            .await
+            .unwrap();
+
+        assert!(html.contains("<title>Index of /files/my stuff/</title>"));
+        assert!(html.contains("<a href=\"/files/\">../</a>"));
+        assert!(!html.contains(".env"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains(
+            "<a href=\"/files/my%20stuff/a%20%3Cb%3E&amp;%22c%22.txt\">a &lt;b&gt;&amp;&quot;c&quot;.txt</a>"
+        ));
+
+        // Directories first, then files by name
+        let zdir = html.find("zdir/").unwrap();
+        let a = html.find("a &lt;b&gt;").unwrap();
+        let b = html.find(">b.txt<").unwrap();
+        assert!(zdir < a && a < b);
+
+        let b_line = html.lines().find(|line| line.contains(">b.txt<")).unwrap();
+        assert!(b_line.ends_with(" 5"), "{}", b_line);
+    }
+
+    #[tokio::test]
+    async fn test_root_listing_has_no_parent_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let html = render(dir.path(), "/", SymlinkPolicy::Always)
+            .await
+            .unwrap();
+        assert!(html.contains("Index of /<"));
+        assert!(!html.contains("../"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_refused_symlinks_are_not_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("real.txt"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real.txt"), dir.path().join("link.txt"))
+            .unwrap();
+
+        let html = render(dir.path(), "/", SymlinkPolicy::Always)
+            .await
+            .unwrap();
+        assert!(html.contains(">link.txt<"));
+
+        let html = render(dir.path(), "/", SymlinkPolicy::Never).await.unwrap();
+        assert!(html.contains(">real.txt<"));
+        assert!(!html.contains("link.txt"));
+    }
+}
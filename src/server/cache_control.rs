@@ -0,0 +1,155 @@
+//! Page cache lifetime from response headers
+//!
+//! PHP responses say how long they may be cached. `s-maxage` wins over
+//! `max-age`, which wins over `Expires`; a response that says none of these
+//! keeps the vhost TTL. `no-store`, `no-cache` and `private` keep any
+//! response out of the shared cache, as does a PHP page already expired.
+
+use std::time::{Duration, SystemTime};
+
+use hyper::header::{CACHE_CONTROL, DATE, EXPIRES};
+use hyper::http::HeaderMap;
+
+use crate::server::static_files::parse_http_date;
+
+/// Directives that keep a response out of the page cache
+const UNCACHEABLE: &[&str] = &["no-store", "no-cache", "private"];
+
+/// How long a response may live in the page cache, `None` if not at all
+pub fn response_ttl(headers: &HeaderMap, default: Duration) -> Option<Duration> {
+    let mut max_age = None;
+    let mut s_maxage = None;
+    for (name, value) in directives(headers) {
+        if is_uncacheable(name) {
+            return None;
+        }
+        let seconds = || value.and_then(|v| v.parse::<u64>().ok());
+        if name.eq_ignore_ascii_case("s-maxage") {
+            s_maxage = seconds().or(Some(0));
+        } else if name.eq_ignore_ascii_case("max-age") {
+            max_age = seconds().or(Some(0));
+        }
+    }
+
+    let ttl = match s_maxage.or(max_age) {
+        Some(seconds) => Duration::from_secs(seconds),
+        None => match headers.get(EXPIRES) {
+            // An Expires that can't be read means already expired
+            Some(expires) => expires_ttl(headers, expires.to_str().ok()?)?,
+            None => default,
+        },
+    };
+    (!ttl.is_zero()).then_some(ttl)
+}
+
+/// Whether the response's Cache-Control keeps it out of the page cache
+pub fn is_private(headers: &HeaderMap) -> bool {
+    directives(headers).any(|(name, _)| is_uncacheable(name))
+}
+
+fn is_uncacheable(directive: &str) -> bool {
+    UNCACHEABLE
+        .iter()
+        .any(|blocked| directive.eq_ignore_ascii_case(blocked))
+}
+
+/// Cache-Control directives as `(name, value)`, across all header lines
+fn directives(headers: &HeaderMap) -> impl Iterator<Item = (&str, Option<&str>)> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive.trim(), None),
+        })
+}
+
+/// Freshness from `Expires`, measured against the response's `Date`
+fn expires_ttl(headers: &HeaderMap, expires: &str) -> Option<Duration> {
+    let expires = parse_http_date(expires).ok()?;
+    let date = headers
+        .get(DATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_http_date(value).ok())
+        .unwrap_or_else(SystemTime::now);
+    expires.duration_since(date).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    const DEFAULT: Duration = Duration::from_secs(3600);
+
+    fn ttl(headers: &[(&'static str, &'static str)]) -> Option<u64> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        response_ttl(&map, DEFAULT).map(|ttl| ttl.as_secs())
+    }
+
+    #[test]
+    fn test_ttl_precedence() {
+        assert_eq!(ttl(&[]), Some(3600));
+        assert_eq!(ttl(&[("cache-control", "public, max-age=60")]), Some(60));
+        assert_eq!(
+            ttl(&[("cache-control", "public, max-age=60, s-maxage=600")]),
+            Some(600)
+        );
+        // Directives may be split across header lines, in any order
+        assert_eq!(
+            ttl(&[
+                ("cache-control", "S-MAXAGE=\"600\""),
+                ("cache-control", "max-age=60"),
+            ]),
+            Some(600)
+        );
+        // Cache-Control beats Expires
+        assert_eq!(
+            ttl(&[
+                ("cache-control", "max-age=60"),
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("expires", "Sun, 06 Nov 1994 09:49:37 GMT"),
+            ]),
+            Some(60)
+        );
+        assert_eq!(
+            ttl(&[
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("expires", "Sun, 06 Nov 1994 08:59:37 GMT"),
+            ]),
+            Some(600)
+        );
+        // Other directives leave the vhost TTL alone
+        assert_eq!(
+            ttl(&[("cache-control", "public, must-revalidate")]),
+            Some(3600)
+        );
+    }
+
+    #[test]
+    fn test_uncacheable_responses() {
+        for value in [
+            "no-store",
+            "No-Cache",
+            "private, max-age=600",
+            "public, s-maxage=600, no-cache=\"Set-Cookie\"",
+            "max-age=0",
+            "s-maxage=0, max-age=60",
+        ] {
+            assert_eq!(ttl(&[("cache-control", value)]), None, "{}", value);
+        }
+        assert_eq!(ttl(&[("expires", "0")]), None);
+        assert_eq!(
+            ttl(&[
+                ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("expires", "Sun, 06 Nov 1994 08:00:00 GMT"),
+            ]),
+            None
+        );
+    }
+}
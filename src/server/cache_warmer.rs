@@ -1,15 +1,24 @@
+use crate::cache::Revalidation;
 use crate::config::{CacheConfig, Config, VirtualHostConfig};
+use crate::server::ListenAddr;
 
 use bytes::Bytes;
 use dashmap::DashMap;
 use http_body_util::{BodyExt, Empty};
-use hyper::{Method, Request};
+use hyper::body::Incoming;
+use hyper::header::{
+    HeaderMap, HeaderValue, CONNECTION, CONTENT_LENGTH, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_RANGE, IF_UNMODIFIED_SINCE, RANGE, TRANSFER_ENCODING,
+};
+use hyper::{Method, Request, Response};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::hash_map::RandomState;
 use std::collections::HashSet;
+use std::hash::BuildHasher;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
@@ -17,7 +26,11 @@ use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::{mpsc, Semaphore};
 use tokio::time::timeout;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
+
+/// Marks the requests [`CacheWarmer::revalidate`] sends; only its value
+/// from this process is honoured
+const REVALIDATE_HEADER: &str = "x-veloserve-revalidate";
 
 #[derive(Debug, Clone)]
 pub struct WarmTarget {
@@ -64,6 +77,8 @@ pub struct CacheWarmer {
     pending: DashMap<String, u64>,
     stats: WarmStats,
     started: AtomicBool,
+    /// Value of [`REVALIDATE_HEADER`], random per process
+    revalidate_token: String,
 }
 
 impl CacheWarmer {
@@ -78,6 +93,10 @@ impl CacheWarmer {
             pending: DashMap::new(),
             stats: WarmStats::default(),
             started: AtomicBool::new(false),
+            revalidate_token: format!(
+                "{:016x}",
+                RandomState::new().hash_one((std::process::id(), SystemTime::now()))
+            ),
         });
 
         warmer.clone().spawn_dispatcher(receiver);
@@ -185,12 +204,7 @@ impl CacheWarmer {
     }
 
     async fn warm_once(&self, target: &WarmTarget) -> anyhow::Result<()> {
-        let origin = local_origin(&self.config.server.listen)?;
-        let uri = format!("{}{}", origin, target.path);
-
-        let connector = HttpConnector::new();
-        let client: Client<_, Empty<Bytes>> =
-            Client::builder(TokioExecutor::new()).build(connector);
+        let (listen, uri) = local_target(&self.config, &target.path)?;
         let request = Request::builder()
             .method(Method::GET)
             .uri(uri)
@@ -200,7 +214,7 @@ impl CacheWarmer {
 
         let response = timeout(
             Duration::from_millis(self.cache_config.warm_request_timeout_ms),
-            client.request(request),
+            send_local(&listen, request),
         )
         .await
         .map_err(|_| anyhow::anyhow!("warm request timeout"))??;
@@ -218,6 +232,64 @@ impl CacheWarmer {
         }
     }
 
+    /// True if `headers` mark a refresh sent by [`Self::revalidate`], which
+    /// must bypass the page cache
+    pub fn is_revalidation(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(REVALIDATE_HEADER)
+            .is_some_and(|value| value.as_bytes() == self.revalidate_token.as_bytes())
+    }
+
+    /// Request `uri` (path and query) from this server again in the
+    /// background, with the stale hit's request headers so it lands on the
+    /// same cache key; `claim` is released once the refresh is done
+    ///
+    /// The client's validators and `Range` are dropped, so the refresh gets
+    /// the whole page rather than a 304, 412 or 206 that can't be stored.
+    pub fn revalidate(&self, uri: String, mut headers: HeaderMap, claim: Revalidation) {
+        for name in [
+            CONNECTION,
+            CONTENT_LENGTH,
+            TRANSFER_ENCODING,
+            IF_NONE_MATCH,
+            IF_MODIFIED_SINCE,
+            IF_MATCH,
+            IF_UNMODIFIED_SINCE,
+            IF_RANGE,
+            RANGE,
+        ] {
+            headers.remove(name);
+        }
+        let Ok(token) = HeaderValue::from_str(&self.revalidate_token) else {
+            return;
+        };
+        headers.insert(REVALIDATE_HEADER, token);
+        let config = self.config.clone();
+        let limit = Duration::from_millis(self.cache_config.warm_request_timeout_ms);
+
+        tokio::spawn(async move {
+            let _claim = claim;
+            let outcome = async {
+                let (listen, target) = local_target(&config, &uri)?;
+                let mut request = Request::builder()
+                    .method(Method::GET)
+                    .uri(target)
+                    .body(Empty::new())?;
+                *request.headers_mut() = headers;
+                let response = timeout(limit, send_local(&listen, request))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("timed out"))??;
+                let status = response.status();
+                let _ = response.into_body().collect().await;
+                anyhow::Ok(status)
+            };
+            match outcome.await {
+                Ok(status) => debug!("Revalidated {} ({})", uri, status),
+                Err(err) => warn!("Revalidating {} failed: {}", uri, err),
+            }
+        });
+    }
+
     pub async fn enqueue_deterministic(&self, trigger: &str) -> anyhow::Result<serde_json::Value> {
         let mut targets = Vec::new();
         for vhost in &self.config.virtualhost {
@@ -397,11 +469,12 @@ impl CacheWarmer {
 
     pub fn stats_json(&self) -> serde_json::Value {
         let samples = self.stats.latency_samples.load(Ordering::Relaxed);
-        let avg_latency_ms = if samples == 0 {
-            0
-        } else {
-            self.stats.latency_total_ms.load(Ordering::Relaxed) / samples
-        };
+        let avg_latency_ms = self
+            .stats
+            .latency_total_ms
+            .load(Ordering::Relaxed)
+            .checked_div(samples)
+            .unwrap_or(0);
 
         json!({
             "enabled": self.cache_config.warm_enabled,
@@ -432,14 +505,57 @@ impl CacheWarmer {
     }
 }
 
-fn local_origin(listen: &str) -> anyhow::Result<String> {
-    let addr: SocketAddr = listen.parse()?;
+/// The listener to reach this server on and the URI for `path` there;
+/// any listener reaches the same server, so the first is used
+fn local_target(config: &Config, path: &str) -> anyhow::Result<(ListenAddr, String)> {
+    let listen = config
+        .server
+        .listen
+        .addresses()
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("no listen address"))?;
+    let listen = ListenAddr::parse(listen).map_err(anyhow::Error::msg)?;
+    let uri = match &listen {
+        ListenAddr::Tcp(addr) => format!("{}{}", local_origin(*addr), path),
+        ListenAddr::Unix(_) => path.to_string(),
+    };
+    Ok((listen, uri))
+}
+
+fn local_origin(addr: SocketAddr) -> String {
     let host = if addr.ip().is_unspecified() {
         "127.0.0.1".to_string()
     } else {
         addr.ip().to_string()
     };
-    Ok(format!("http://{}:{}", host, addr.port()))
+    format!("http://{}:{}", host, addr.port())
+}
+
+/// Send `request` to this server's own listener
+async fn send_local(
+    listen: &ListenAddr,
+    request: Request<Empty<Bytes>>,
+) -> anyhow::Result<Response<Incoming>> {
+    match listen {
+        ListenAddr::Tcp(_) => {
+            let client: Client<_, Empty<Bytes>> =
+                Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+            Ok(client.request(request).await?)
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            let (mut sender, conn) =
+                hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+            tokio::spawn(conn);
+            Ok(sender.send_request(request).await?)
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(path) => Err(anyhow::anyhow!(
+            "cannot connect to {}: Unix sockets need a Unix platform",
+            path.display()
+        )),
+    }
 }
 
 fn discover_key_landing_paths(root: &str) -> Vec<String> {
@@ -0,0 +1,282 @@
+//! Client addresses
+//!
+//! Every request carries a [`ConnectionInfo`] extension describing the
+//! connection it arrived on, so PHP gets the real `REMOTE_ADDR`, `HTTPS` and
+//! `SERVER_PORT` instead of placeholders.
+//!
+//! Behind a load balancer or CDN the peer is the proxy. When the peer is in
+//! `server.trusted_proxies`, the client is taken from `X-Forwarded-For`,
+//! walking it from the right and skipping trusted hops (Nginx's `real_ip`
+//! with `real_ip_recursive on`), and the scheme from `X-Forwarded-Proto`.
+//! Untrusted peers can't spoof either.
+
+use std::net::{IpAddr, SocketAddr};
+
+use hyper::header::HeaderMap;
+
+/// The connection a request arrived on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Address of the peer that connected
+    pub peer: SocketAddr,
+    /// Our end of the connection
+    pub local: Option<SocketAddr>,
+    /// Whether the connection is TLS
+    pub tls: bool,
+    /// Whether the client used HTTPS: `tls`, unless a trusted proxy's
+    /// `X-Forwarded-Proto` says otherwise
+    pub https: bool,
+    /// The client: the peer, or whom a trusted proxy forwarded for
+    pub client: IpAddr,
+}
+
+impl ConnectionInfo {
+    /// A connection whose client is the peer itself
+    pub fn new(peer: SocketAddr, local: Option<SocketAddr>, tls: bool) -> Self {
+        Self {
+            peer,
+            local,
+            tls,
+            https: tls,
+            client: peer.ip(),
+        }
+    }
+
+    /// A connection on a Unix socket, whose peer is reported as `127.0.0.1`
+    pub fn unix() -> Self {
+        Self::new(SocketAddr::from(([127, 0, 0, 1], 0)), None, false)
+    }
+
+    /// True if the client address came from `X-Forwarded-For`
+    pub fn is_forwarded(&self) -> bool {
+        self.client != self.peer.ip()
+    }
+
+    /// The scheme the client used, as in `$scheme`
+    pub fn scheme(&self) -> &'static str {
+        if self.https {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// Resolve the client and scheme from `headers` when the peer is a
+    /// trusted proxy
+    pub fn with_forwarded(mut self, headers: &HeaderMap, trusted: &TrustedProxies) -> Self {
+        self.client = trusted.client(self.peer.ip(), headers);
+        if let Some(https) = trusted.forwarded_https(self.peer.ip(), headers) {
+            self.https = https;
+        }
+        self
+    }
+}
+
+/// Networks whose `X-Forwarded-For` is believed (`server.trusted_proxies`)
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parse addresses and CIDR networks ("10.0.0.0/8", "::1")
+    pub fn new(entries: &[String]) -> Result<Self, String> {
+        let networks = entries
+            .iter()
+            .map(|entry| parse_network(entry))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { networks })
+    }
+
+    /// True if `addr` is a trusted proxy
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        self.networks
+            .iter()
+            .any(|&(network, prefix)| in_network(addr, network, prefix))
+    }
+
+    /// The client behind `peer`: the rightmost untrusted `X-Forwarded-For`
+    /// hop when `peer` is trusted, otherwise `peer`
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let hops: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            // A garbled hop ends the chain; nothing left of it can be trusted
+            let Ok(addr) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = addr;
+            if !self.contains(addr) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Whether `X-Forwarded-Proto` from a trusted `peer` says HTTPS
+    ///
+    /// The rightmost value is the one the nearest proxy saw; anything other
+    /// than `http` or `https` is ignored.
+    pub fn forwarded_https(&self, peer: IpAddr, headers: &HeaderMap) -> Option<bool> {
+        if !self.contains(peer) {
+            return None;
+        }
+        let proto = headers
+            .get_all("x-forwarded-proto")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .next_back()?
+            .trim();
+        if proto.eq_ignore_ascii_case("https") {
+            Some(true)
+        } else if proto.eq_ignore_ascii_case("http") {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_network(entry: &str) -> Result<(IpAddr, u8), String> {
+    let invalid = || format!("'{}' is not an IP address or CIDR network", entry);
+    let (addr, prefix) = match entry.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry.trim(), None),
+    };
+    let addr = addr
+        .parse::<IpAddr>()
+        .map_err(|_| invalid())?
+        .to_canonical();
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+        None => max,
+    };
+    if prefix > max {
+        return Err(invalid());
+    }
+    Ok((addr, prefix))
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted(entries: &[&str]) -> TrustedProxies {
+        let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+        TrustedProxies::new(&entries).unwrap()
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_trusted_networks() {
+        let proxies = trusted(&["10.0.0.0/8", "192.0.2.7", "2001:db8::/32"]);
+        assert!(proxies.contains(ip("10.1.2.3")));
+        assert!(proxies.contains(ip("192.0.2.7")));
+        assert!(!proxies.contains(ip("192.0.2.8")));
+        assert!(proxies.contains(ip("2001:db8::1")));
+        assert!(!proxies.contains(ip("2001:db9::1")));
+        // IPv4-mapped IPv6 peers match their IPv4 network
+        assert!(proxies.contains(ip("::ffff:10.0.0.1")));
+
+        assert!(trusted(&["0.0.0.0/0"]).contains(ip("203.0.113.9")));
+        assert!(!TrustedProxies::default().contains(ip("127.0.0.1")));
+
+        for bad in ["10.0.0.0/33", "localhost", "10.0.0.0/x", "::1/129"] {
+            assert!(TrustedProxies::new(&[bad.to_string()]).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "198.51.100.1, 203.0.113.5, 10.0.0.2".parse().unwrap(),
+        );
+
+        // Trusted hops are skipped; the spoofable leftmost entry is not reached
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("203.0.113.5"));
+        // An untrusted peer is the client whatever it claims
+        assert_eq!(
+            proxies.client(ip("203.0.113.9"), &headers),
+            ip("203.0.113.9")
+        );
+        // Without the header, the proxy is all we know
+        assert_eq!(
+            proxies.client(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+
+        headers.insert("x-forwarded-for", "garbage, 10.0.0.3".parse().unwrap());
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("10.0.0.3"));
+
+        // Repeated headers form one list
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "198.51.100.1".parse().unwrap());
+        headers.append("x-forwarded-for", "10.0.0.2".parse().unwrap());
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("198.51.100.1"));
+
+        let peer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let conn = ConnectionInfo::new(peer, None, false).with_forwarded(&headers, &proxies);
+        assert_eq!(conn.client, ip("198.51.100.1"));
+        assert!(conn.is_forwarded());
+    }
+
+    #[test]
+    fn test_forwarded_proto() {
+        let proxies = trusted(&["10.0.0.0/8", "fd00::/8"]);
+        let conn = |peer: &str, tls: bool, protos: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for proto in protos {
+                headers.append("x-forwarded-proto", proto.parse().unwrap());
+            }
+            ConnectionInfo::new(peer.parse().unwrap(), None, tls).with_forwarded(&headers, &proxies)
+        };
+
+        assert!(conn("10.0.0.1:5000", false, &["https"]).https);
+        assert!(conn("[fd00::1]:5000", false, &["HTTPS"]).https);
+        assert!(!conn("10.0.0.1:5000", true, &["http"]).https);
+        assert_eq!(conn("10.0.0.1:5000", false, &["https"]).scheme(), "https");
+        // The nearest proxy's view wins over what the client sent it
+        assert!(!conn("10.0.0.1:5000", false, &["https, http"]).https);
+        assert!(!conn("10.0.0.1:5000", false, &["https", "http"]).https);
+        // Junk and missing headers leave the connection's own scheme
+        assert!(conn("10.0.0.1:5000", true, &["gopher"]).https);
+        assert!(conn("10.0.0.1:5000", true, &[]).https);
+
+        // Untrusted peers can't claim HTTPS, IPv4 or IPv6
+        assert!(!conn("203.0.113.9:5000", false, &["https"]).https);
+        assert!(!conn("[2001:db8::1]:5000", false, &["https"]).https);
+        assert!(conn("203.0.113.9:5000", true, &["http"]).https);
+    }
+}
@@ -0,0 +1,379 @@
+//! On-the-fly response compression
+//!
+//! Buffered responses with a text-like `Content-Type` (HTML, CSS, JavaScript,
+//! JSON, XML, SVG) are compressed with the `[server.compression]` algorithm
+//! the client gives the highest q-value, ties going to the earlier one in
+//! `algorithms`. Responses are left alone when they are
+//! already encoded (precompressed siblings, PHP's own `ob_gzhandler`), carry a
+//! `Content-Range`, ask for `Cache-Control: no-transform`, are streamed, or
+//! are smaller than `min_size`.
+//!
+//! Compression runs after the page cache, so cached pages are stored once,
+//! uncompressed, and encoded per client on the way out. A compressed
+//! response's ETag is made weak and suffixed with the coding, so a cache
+//! between us and the client can never confuse it with the identity body.
+//!
+//! HEAD responses get the headers the matching GET would get, judged by their
+//! declared `Content-Length`. Nothing is encoded, so the compressed length is
+//! unknown and `Content-Length` is dropped, as Nginx does.
+
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Body;
+use hyper::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, VARY,
+};
+use hyper::http::{HeaderMap, HeaderValue};
+use hyper::{Method, Response};
+
+use crate::config::{CompressionAlgorithm, CompressionConfig};
+use crate::server::static_files::preferred_encoding;
+use crate::server::streaming::StreamingBody;
+use crate::server::vhost::content_type_matches;
+
+/// Brotli window size (log2), the encoder's default
+const BROTLI_WINDOW: u32 = 22;
+
+/// Compress `response` if the client and the content allow it
+///
+/// `method` and `accept_encoding` are the request's method and
+/// `Accept-Encoding` header.
+pub async fn compress(
+    config: &CompressionConfig,
+    method: &Method,
+    accept_encoding: Option<&str>,
+    mut response: Response<Full<Bytes>>,
+) -> Result<Response<Full<Bytes>>> {
+    let head = *method == Method::HEAD;
+    if !config.enable || !is_candidate(&response, head, config) {
+        return Ok(response);
+    }
+
+    // Whether or not this client gets it encoded, the response varies on it
+    add_vary(response.headers_mut());
+
+    let Some(algorithm) =
+        accept_encoding.and_then(|accept| preferred_encoding(accept, &config.algorithms, token))
+    else {
+        return Ok(response);
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let compressed = if head {
+        None
+    } else {
+        let body = body
+            .collect()
+            .await
+            .map(|c| c.to_bytes())
+            .unwrap_or_default();
+        let level = config.level;
+        let compressed = tokio::task::spawn_blocking(move || encode(algorithm, level, &body))
+            .await
+            .map_err(|e| anyhow!("Compression task failed: {}", e))??;
+        Some(compressed)
+    };
+
+    let headers = &mut parts.headers;
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static(token(algorithm)));
+    match &compressed {
+        Some(compressed) => {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(compressed.len()));
+        }
+        None => {
+            headers.remove(CONTENT_LENGTH);
+        }
+    }
+    // Byte ranges would address the identity body
+    headers.remove(ACCEPT_RANGES);
+    if let Some(etag) = headers.get(ETAG).and_then(|value| value.to_str().ok()) {
+        let opaque = etag.trim_start_matches("W/").trim_matches('"');
+        if let Ok(value) =
+            HeaderValue::from_str(&format!("W/\"{}-{}\"", opaque, etag_suffix(algorithm)))
+        {
+            headers.insert(ETAG, value);
+        }
+    }
+
+    Ok(Response::from_parts(
+        parts,
+        Full::new(compressed.map(Bytes::from).unwrap_or_default()),
+    ))
+}
+
+/// True if the response is buffered, unencoded, compressible and big enough
+///
+/// A HEAD response has no body, so its declared length is used instead.
+fn is_candidate(response: &Response<Full<Bytes>>, head: bool, config: &CompressionConfig) -> bool {
+    if response.extensions().get::<StreamingBody>().is_some() {
+        return false;
+    }
+    let status = response.status();
+    if status.is_informational() || matches!(status.as_u16(), 204 | 206 | 304) {
+        return false;
+    }
+    let headers = response.headers();
+    if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+        return false;
+    }
+    let no_transform = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.to_ascii_lowercase().contains("no-transform"));
+    if no_transform {
+        return false;
+    }
+    let compressible = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| is_compressible(&config.types, content_type));
+    let len = if head {
+        headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    } else {
+        body_len(response)
+    };
+    compressible && len >= config.min_size
+}
+
+/// True if `content_type` matches one of the configured `types`
+///
+/// The defaults cover text-like media; images, video, audio and archives are
+/// already compressed.
+pub fn is_compressible(types: &[String], content_type: &str) -> bool {
+    content_type_matches(types, content_type)
+}
+
+fn body_len(response: &Response<Full<Bytes>>) -> usize {
+    response.body().size_hint().exact().unwrap_or(0) as usize
+}
+
+/// Append `Accept-Encoding` to `Vary` unless it is already listed
+fn add_vary(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("accept-encoding")
+        });
+    if !listed {
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+fn encode(algorithm: CompressionAlgorithm, level: u32, body: &[u8]) -> Result<Vec<u8>> {
+    match algorithm {
+        CompressionAlgorithm::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(body)?;
+            Ok(encoder.finish()?)
+        }
+        CompressionAlgorithm::Brotli => {
+            let mut output = Vec::new();
+            {
+                let mut encoder =
+                    brotli::CompressorWriter::new(&mut output, 4096, level, BROTLI_WINDOW);
+                encoder.write_all(body)?;
+            }
+            Ok(output)
+        }
+    }
+}
+
+/// `Content-Encoding` token
+fn token(algorithm: CompressionAlgorithm) -> &'static str {
+    match algorithm {
+        CompressionAlgorithm::Brotli => "br",
+        CompressionAlgorithm::Gzip => "gzip",
+    }
+}
+
+fn etag_suffix(algorithm: CompressionAlgorithm) -> &'static str {
+    match algorithm {
+        CompressionAlgorithm::Brotli => "br",
+        CompressionAlgorithm::Gzip => "gz",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn html(len: usize) -> Response<Full<Bytes>> {
+        Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CONTENT_LENGTH, len)
+            .header(ETAG, "\"abc\"")
+            .header(ACCEPT_RANGES, "bytes")
+            .body(Full::new(Bytes::from("<p>hello</p>".repeat(len / 12))))
+            .unwrap()
+    }
+
+    async fn body(response: Response<Full<Bytes>>) -> Bytes {
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_compresses_with_preferred_accepted_algorithm() {
+        let config = CompressionConfig::default();
+        let original = "<p>hello</p>".repeat(200);
+
+        let response = compress(&config, &Method::GET, Some("gzip, deflate, br"), html(2400))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(response.headers()[ETAG], "W/\"abc-br\"");
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+        assert!(!response.headers().contains_key(ACCEPT_RANGES));
+        let compressed = body(response).await;
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, original);
+
+        let response = compress(&config, &Method::GET, Some("gzip;q=1, br;q=0"), html(2400))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[ETAG], "W/\"abc-gz\"");
+        let length: usize = response.headers()[CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let compressed = body(response).await;
+        assert_eq!(length, compressed.len());
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, original);
+
+        // A higher q-value beats our order; a tie goes to our order
+        for (accept, expected) in [
+            ("br;q=0.5, gzip;q=0.9", "gzip"),
+            ("gzip;q=0.5, br;q=0.5", "br"),
+            ("gzip;q=0.5, *", "br"),
+        ] {
+            let response = compress(&config, &Method::GET, Some(accept), html(2400))
+                .await
+                .unwrap();
+            assert_eq!(response.headers()[CONTENT_ENCODING], expected, "{}", accept);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skipped_responses() {
+        let config = CompressionConfig::default();
+
+        // The client doesn't accept any configured coding, but the
+        // response still varies on the header
+        let response = compress(&config, &Method::GET, None, html(2400))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+
+        // Too small
+        let response = compress(&config, &Method::GET, Some("gzip"), html(120))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert!(!response.headers().contains_key(VARY));
+
+        // Already compressed media
+        let mut image = html(2400);
+        image
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        let response = compress(&config, &Method::GET, Some("gzip"), image)
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+
+        // no-transform
+        let mut pinned = html(2400);
+        pinned.headers_mut().insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("public, no-transform"),
+        );
+        let response = compress(&config, &Method::GET, Some("gzip"), pinned)
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+
+        // Disabled
+        let disabled = CompressionConfig {
+            enable: false,
+            ..CompressionConfig::default()
+        };
+        let response = compress(&disabled, &Method::GET, Some("gzip"), html(2400))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn test_head_gets_the_get_headers() {
+        let config = CompressionConfig::default();
+        let mut head = html(2400);
+        *head.body_mut() = Full::new(Bytes::new());
+
+        let response = compress(&config, &Method::HEAD, Some("br"), head)
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(response.headers()[ETAG], "W/\"abc-br\"");
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+        assert!(body(response).await.is_empty());
+
+        // Judged by the declared length, not the empty body
+        let mut small = html(120);
+        *small.body_mut() = Full::new(Bytes::new());
+        let response = compress(&config, &Method::HEAD, Some("br"), small)
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[CONTENT_LENGTH], "120");
+    }
+
+    #[test]
+    fn test_compressible_types() {
+        let types = CompressionConfig::default().types;
+        for media in [
+            "text/html; charset=utf-8",
+            "text/css",
+            "application/javascript",
+            "application/json",
+            "application/ld+json",
+            "image/svg+xml",
+        ] {
+            assert!(is_compressible(&types, media), "{}", media);
+        }
+        for media in ["image/png", "video/mp4", "application/zip", "font/woff2"] {
+            assert!(!is_compressible(&types, media), "{}", media);
+        }
+
+        // A configured list replaces the defaults
+        let types = vec!["text/html".to_string(), "application/*".to_string()];
+        assert!(is_compressible(&types, "text/html; charset=utf-8"));
+        assert!(is_compressible(&types, "Application/Zip"));
+        assert!(!is_compressible(&types, "text/css"));
+    }
+}
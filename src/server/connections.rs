@@ -0,0 +1,145 @@
+//! Connection limit
+//!
+//! Every accepted connection (HTTP and HTTPS alike) holds a permit from one
+//! semaphore sized to `server.max_connections` until it closes. When none is
+//! free, the accept loop waits up to [`ADMIT_WAIT`] for one and otherwise
+//! closes the new connection straight away, so a flood of idle or
+//! slow-sending clients runs into the limit instead of exhausting file
+//! descriptors. Accepting pauses while the loop waits, which leaves further
+//! connections in the kernel backlog.
+//!
+//! Rejections are logged once when they start and then summarised at most
+//! every [`LOG_INTERVAL`].
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde_json::json;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// How long a new connection may wait for a slot before it is closed
+pub const ADMIT_WAIT: Duration = Duration::from_millis(50);
+
+/// Minimum time between rejection log lines
+pub const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Caps the number of open connections
+pub struct ConnectionLimiter {
+    permits: Arc<Semaphore>,
+    max: usize,
+    rejected: AtomicU64,
+    /// When the last rejection line was logged, and rejections since
+    log: Mutex<Option<(Instant, u64)>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_connections)),
+            max: max_connections,
+            rejected: AtomicU64::new(0),
+            log: Mutex::new(None),
+        }
+    }
+
+    /// A slot for a newly accepted connection, held until it is dropped
+    ///
+    /// `None` means the limit was reached and the connection should be closed.
+    pub async fn admit(&self, remote_addr: SocketAddr) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        if let Ok(Ok(permit)) =
+            tokio::time::timeout(ADMIT_WAIT, self.permits.clone().acquire_owned()).await
+        {
+            return Some(permit);
+        }
+
+        self.record_rejection(remote_addr);
+        None
+    }
+
+    fn record_rejection(&self, remote_addr: SocketAddr) {
+        let total = self.rejected.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = Instant::now();
+        let mut log = self.log.lock();
+        match log.as_mut() {
+            Some((last_logged, unlogged)) if now.duration_since(*last_logged) < LOG_INTERVAL => {
+                *unlogged += 1;
+            }
+            Some((last_logged, unlogged)) => {
+                warn!(
+                    "Connection limit of {} still reached: closed {} connections since last report ({} total)",
+                    self.max,
+                    *unlogged + 1,
+                    total
+                );
+                *last_logged = now;
+                *unlogged = 0;
+            }
+            None => {
+                warn!(
+                    "Connection limit of {} reached, closing new connections (first from {})",
+                    self.max, remote_addr
+                );
+                *log = Some((now, 0));
+            }
+        }
+    }
+
+    /// Connections currently holding a slot
+    pub fn in_flight(&self) -> usize {
+        self.max - self.permits.available_permits()
+    }
+
+    /// Limit and counters for the metrics API
+    pub fn stats_json(&self) -> serde_json::Value {
+        json!({
+            "max": self.max,
+            "in_flight": self.in_flight(),
+            "rejected_total": self.rejected.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connections_over_the_limit_are_rejected() {
+        let limiter = ConnectionLimiter::new(2);
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+
+        let first = limiter.admit(addr).await.unwrap();
+        let _second = limiter.admit(addr).await.unwrap();
+        assert_eq!(limiter.in_flight(), 2);
+
+        assert!(limiter.admit(addr).await.is_none());
+        assert_eq!(limiter.stats_json()["rejected_total"], 1);
+
+        // A closed connection frees its slot
+        drop(first);
+        assert_eq!(limiter.in_flight(), 1);
+        assert!(limiter.admit(addr).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_waiting_connection_gets_a_freed_slot() {
+        let limiter = Arc::new(ConnectionLimiter::new(1));
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+
+        let held = limiter.admit(addr).await.unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(ADMIT_WAIT / 5).await;
+            drop(held);
+        });
+
+        assert!(limiter.admit(addr).await.is_some());
+        assert_eq!(limiter.stats_json()["rejected_total"], 0);
+    }
+}
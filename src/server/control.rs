@@ -0,0 +1,150 @@
+//! Control socket server
+//!
+//! Answers the CLI's management commands on `server.control_socket`; the
+//! protocol is in [`crate::control`].
+
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, error, info, warn};
+
+use crate::control::{ControlCommand, ControlReply, MAX_COMMAND_BYTES};
+use crate::server::state::{export_state, import_state, ExportOptions, RuntimeState};
+use crate::server::{HandlerServices, WarmRequestPayload, MAX_SNAPSHOT_BYTES};
+
+/// Serve control connections until the listener fails
+pub async fn serve(listener: UnixListener, services: HandlerServices) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Control socket accept error: {}", e);
+                continue;
+            }
+        };
+        let services = services.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &services).await {
+                debug!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Answer each command line until the client hangs up
+async fn handle_connection(stream: UnixStream, services: &HandlerServices) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read.take(MAX_COMMAND_BYTES as u64)).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match ControlCommand::parse(&line) {
+            Ok(command) => {
+                let mut upload = vec![0; command.payload_len()];
+                if !upload.is_empty() {
+                    // The payload gets its own allowance on top of the command limit
+                    let reader = lines.get_mut();
+                    reader
+                        .get_mut()
+                        .set_limit((upload.len() + MAX_COMMAND_BYTES) as u64);
+                    reader.read_exact(&mut upload).await?;
+                }
+                execute(command, upload, services).await
+            }
+            Err(e) => ControlReply::failure(e),
+        };
+        write.write_all(reply.to_line().as_bytes()).await?;
+        write.write_all(&reply.payload).await?;
+    }
+    Ok(())
+}
+
+/// Run one command against the running server
+async fn execute(
+    command: ControlCommand,
+    upload: Vec<u8>,
+    services: &HandlerServices,
+) -> ControlReply {
+    let cache = &services.cache;
+    match command {
+        ControlCommand::CacheStats => ControlReply::success(cache.detailed_stats(None)),
+        // As for API purges, read-only mode protects the cache being served
+        ControlCommand::CachePurgeAll | ControlCommand::CachePurgeTag(_)
+            if services.readonly.is_global() =>
+        {
+            ControlReply::failure("purges are suspended while read-only mode is on")
+        }
+        ControlCommand::CachePurgeAll => {
+            let entries = cache.stats()["entries"].as_u64().unwrap_or(0);
+            cache.purge_all().await;
+            info!("Purged all cache entries (control socket)");
+            ControlReply::success(json!({ "purged": entries }))
+        }
+        ControlCommand::CachePurgeTag(tag) => {
+            let purged = cache.purge_by_tag_count(&tag).await;
+            info!("Purged cache tag {} (control socket)", tag);
+            ControlReply::success(json!({ "tag": tag, "purged": purged }))
+        }
+        ControlCommand::StateExport { bodies, max_bytes } => {
+            let options = ExportOptions {
+                include_bodies: bodies,
+                max_bytes: max_bytes.map_or(MAX_SNAPSHOT_BYTES, |n| n.min(MAX_SNAPSHOT_BYTES)),
+            };
+            match export_state(runtime_state(services), options) {
+                Ok(snapshot) => {
+                    info!(
+                        "Exported runtime state ({} bytes, bodies: {}, control socket)",
+                        snapshot.len(),
+                        bodies
+                    );
+                    ControlReply::with_payload(json!({ "bytes": snapshot.len() }), snapshot)
+                }
+                Err(e) => ControlReply::failure(e.to_string()),
+            }
+        }
+        ControlCommand::StateImport(_) => import(&upload, services).await,
+    }
+}
+
+/// Load a snapshot and queue its index-only entries for warming
+async fn import(snapshot: &[u8], services: &HandlerServices) -> ControlReply {
+    let report = match import_state(runtime_state(services), snapshot).await {
+        Ok(report) => report,
+        Err(e) => return ControlReply::failure(e.to_string()),
+    };
+    for skipped in &report.skipped {
+        warn!(
+            "Skipped state section {} (version {}): {}",
+            skipped.id, skipped.version, skipped.reason
+        );
+    }
+
+    let mut result = report.to_json();
+    if !report.warm_urls.is_empty() {
+        match services
+            .warmer
+            .enqueue_from_payload(WarmRequestPayload {
+                urls: report.warm_urls.clone(),
+                domain: None,
+                trigger: Some("state-import".to_string()),
+                strategy: Some("urls".to_string()),
+            })
+            .await
+        {
+            Ok(warm) => result["warm"] = warm,
+            Err(e) => return ControlReply::failure(e.to_string()),
+        }
+    }
+    info!(
+        "Imported runtime state: {} entries restored, {} queued for warming (control socket)",
+        report.restored,
+        report.warm_urls.len()
+    );
+    ControlReply::success(result)
+}
+
+fn runtime_state(services: &HandlerServices) -> RuntimeState<'_> {
+    RuntimeState {
+        cache: &services.cache,
+        invalidations: Some(&services.invalidations),
+        limits: Some(&services.limits),
+    }
+}
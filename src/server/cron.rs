@@ -0,0 +1,201 @@
+//! Cron expressions
+//!
+//! Six fields (`sec min hour day-of-month month day-of-week`) or the classic
+//! five without seconds, which then fire at second 0. Each field accepts `*`,
+//! values, ranges (`1-5`), steps (`*/15`, `10-40/10`) and comma lists; months
+//! and weekdays also take three-letter names (`jan`, `mon`). Day of week runs
+//! from 0 (Sunday) to 7 (Sunday again). As in cron, when both day fields are
+//! restricted a time matches if either of them does.
+
+use chrono::{Datelike, Timelike};
+
+const MONTH_NAMES: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpr {
+    /// Parse a five- or six-field expression
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let fields = match fields.len() {
+            6 => fields,
+            5 => std::iter::once("0").chain(fields).collect(),
+            n => return Err(format!("expected 5 or 6 fields, found {}", n)),
+        };
+
+        let mut weekdays = parse_field(fields[5], 0, 7, WEEKDAY_NAMES, 0)
+            .map_err(|e| format!("day of week: {}", e))?;
+        // 7 is Sunday as well
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            seconds: parse_field(fields[0], 0, 59, &[], 0)
+                .map_err(|e| format!("seconds: {}", e))?,
+            minutes: parse_field(fields[1], 0, 59, &[], 0)
+                .map_err(|e| format!("minutes: {}", e))?,
+            hours: parse_field(fields[2], 0, 23, &[], 0).map_err(|e| format!("hours: {}", e))?,
+            days: parse_field(fields[3], 1, 31, &[], 0)
+                .map_err(|e| format!("day of month: {}", e))?,
+            months: parse_field(fields[4], 1, 12, MONTH_NAMES, 1)
+                .map_err(|e| format!("month: {}", e))?,
+            weekdays,
+            any_day: fields[3] == "*",
+            any_weekday: fields[5] == "*",
+        })
+    }
+
+    /// True if the expression fires at the given second
+    pub fn matches<T: Datelike + Timelike>(&self, time: &T) -> bool {
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        };
+
+        bit(self.seconds, time.second())
+            && bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+            && day_matches
+    }
+}
+
+fn bit(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+/// Parse one field into a bitmask; `names[i]` stands for `name_base + i`
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    name_base: u32,
+) -> Result<u64, String> {
+    let value = |raw: &str| -> Result<u32, String> {
+        let lower = raw.to_ascii_lowercase();
+        let parsed = match names.iter().position(|name| *name == lower) {
+            Some(index) => index as u32 + name_base,
+            None => raw
+                .parse::<u32>()
+                .map_err(|_| format!("invalid value '{}'", raw))?,
+        };
+        if parsed < min || parsed > max {
+            return Err(format!("{} is outside {}-{}", parsed, min, max));
+        }
+        Ok(parsed)
+    };
+
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step '{}'", step))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/10` runs from 5 to the end of the field
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let single = value(range)?;
+                    (single, single)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("range {}-{} is reversed", start, end));
+        }
+
+        for current in (start..=end).step_by(step as usize) {
+            mask |= 1 << current;
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> chrono::NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d)
+            .unwrap()
+            .and_hms_opt(h, mi, s)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_five_fields_fire_at_second_zero() {
+        let nightly = CronExpr::parse("30 3 * * *").unwrap();
+        assert!(nightly.matches(&at(2024, 5, 1, 3, 30, 0)));
+        assert!(!nightly.matches(&at(2024, 5, 1, 3, 30, 1)));
+        assert!(!nightly.matches(&at(2024, 5, 1, 4, 30, 0)));
+    }
+
+    #[test]
+    fn test_steps_ranges_and_lists() {
+        let expr = CronExpr::parse("*/15 0,30 9-17 * * mon-fri").unwrap();
+        // 2024-05-03 is a Friday, 2024-05-04 a Saturday
+        assert!(expr.matches(&at(2024, 5, 3, 9, 30, 45)));
+        assert!(!expr.matches(&at(2024, 5, 3, 9, 30, 50)));
+        assert!(!expr.matches(&at(2024, 5, 3, 18, 0, 0)));
+        assert!(!expr.matches(&at(2024, 5, 4, 9, 0, 0)));
+
+        let offset = CronExpr::parse("5/20 * * * * *").unwrap();
+        assert!(offset.matches(&at(2024, 1, 1, 0, 0, 25)));
+        assert!(!offset.matches(&at(2024, 1, 1, 0, 0, 0)));
+    }
+
+    #[test]
+    fn test_day_fields_are_ored() {
+        // The 1st of the month or any Sunday
+        let expr = CronExpr::parse("0 0 0 1 * 7").unwrap();
+        assert!(expr.matches(&at(2024, 5, 1, 0, 0, 0)));
+        assert!(expr.matches(&at(2024, 5, 5, 0, 0, 0)));
+        assert!(!expr.matches(&at(2024, 5, 6, 0, 0, 0)));
+
+        let month = CronExpr::parse("0 0 12 * dec *").unwrap();
+        assert!(month.matches(&at(2024, 12, 24, 12, 0, 0)));
+        assert!(!month.matches(&at(2024, 11, 24, 12, 0, 0)));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(CronExpr::parse("* * * *").is_err());
+        assert!(CronExpr::parse("60 * * * * *").is_err());
+        assert!(CronExpr::parse("* * * 0 * *").is_err());
+        assert!(CronExpr::parse("*/0 * * * * *").is_err());
+        assert!(CronExpr::parse("10-5 * * * * *").is_err());
+        assert!(CronExpr::parse("* * * * foo *").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Document root health
+//!
+//! A docroot on NFS or FUSE can fail in ways a local disk doesn't: reads
+//! return EIO, handles go stale (ESTALE) or the transport drops (ENOTCONN),
+//! and an unmounted docroot simply vanishes. Path probes tell those failures
+//! apart from an ordinary missing file, so an outage answers `503` with
+//! `Retry-After` (or a cached page) instead of 404s, 500s or a front
+//! controller run against the wrong tree.
+//!
+//! A vhost stays degraded from its first failure until a probe of its
+//! docroot succeeds again. Failures are logged once per vhost and then
+//! summarised at most every [`LOG_INTERVAL`].
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use serde_json::json;
+use tracing::{info, warn};
+
+/// Minimum time between failure log lines for one vhost
+pub const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What a path lookup found
+#[derive(Debug)]
+pub enum Probe {
+    File,
+    Dir,
+    /// Not there (ENOENT, ENOTDIR, no permission, ...)
+    Missing,
+    /// The filesystem itself failed
+    Unavailable(io::Error),
+}
+
+/// Look up `path`, separating filesystem failures from missing files
+pub fn probe(path: &Path) -> Probe {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Probe::Dir,
+        Ok(_) => Probe::File,
+        Err(e) if is_filesystem_failure(&e) => Probe::Unavailable(e),
+        Err(_) => Probe::Missing,
+    }
+}
+
+/// Probe a docroot; anything but a readable directory counts as unavailable
+pub fn probe_root(root: &Path) -> Result<(), io::Error> {
+    match probe(root) {
+        Probe::Dir => Ok(()),
+        Probe::Unavailable(e) => Err(e),
+        Probe::File => Err(io::Error::other("document root is not a directory")),
+        Probe::Missing => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "document root is missing",
+        )),
+    }
+}
+
+/// True for errors meaning the filesystem, not the file, is the problem
+pub fn is_filesystem_failure(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+        err.raw_os_error().is_some_and(|code| {
+            matches!(
+                Errno::from_i32(code),
+                Errno::EIO | Errno::ESTALE | Errno::ENOTCONN
+            )
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// The filesystem failure behind an error chain, if any
+pub fn filesystem_failure(err: &anyhow::Error) -> Option<&io::Error> {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .find(|io_err| is_filesystem_failure(io_err))
+}
+
+#[derive(Debug)]
+struct Degraded {
+    root: PathBuf,
+    since: u64,
+    last_error: String,
+    failures: u64,
+    last_logged: Instant,
+    unlogged: u64,
+}
+
+/// Degraded docroots, by vhost domain
+#[derive(Debug, Default)]
+pub struct DocrootHealth {
+    vhosts: DashMap<String, Degraded>,
+}
+
+impl DocrootHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_degraded(&self, domain: &str) -> bool {
+        !self.vhosts.is_empty() && self.vhosts.contains_key(domain)
+    }
+
+    /// Count a failed request and mark the vhost degraded
+    pub fn record_failure(&self, domain: &str, root: &Path, error: &io::Error) {
+        let now = Instant::now();
+        let mut first = false;
+        let mut entry = self.vhosts.entry(domain.to_string()).or_insert_with(|| {
+            first = true;
+            Degraded {
+                root: root.to_path_buf(),
+                since: now_epoch_secs(),
+                last_error: String::new(),
+                failures: 0,
+                last_logged: now,
+                unlogged: 0,
+            }
+        });
+        entry.failures += 1;
+        entry.last_error = error.to_string();
+
+        if first {
+            warn!(
+                "Document root {} for {} is unavailable, answering 503: {}",
+                root.display(),
+                domain,
+                error
+            );
+        } else if now.duration_since(entry.last_logged) >= LOG_INTERVAL {
+            warn!(
+                "Document root {} for {} still unavailable ({} failed requests since last report): {}",
+                root.display(),
+                domain,
+                entry.unlogged + 1,
+                error
+            );
+            entry.last_logged = now;
+            entry.unlogged = 0;
+        } else {
+            entry.unlogged += 1;
+        }
+    }
+
+    /// Clear the degraded mark after a successful probe
+    pub fn record_recovery(&self, domain: &str) {
+        if let Some((_, degraded)) = self.vhosts.remove(domain) {
+            info!(
+                "Document root {} for {} is available again after {}s ({} failed requests)",
+                degraded.root.display(),
+                domain,
+                now_epoch_secs().saturating_sub(degraded.since),
+                degraded.failures
+            );
+        }
+    }
+
+    /// Degraded vhosts for the status API
+    pub fn status_json(&self) -> serde_json::Value {
+        let mut vhosts: Vec<_> = self
+            .vhosts
+            .iter()
+            .map(|entry| {
+                json!({
+                    "domain": entry.key(),
+                    "root": entry.root.display().to_string(),
+                    "since": entry.since,
+                    "failures": entry.failures,
+                    "last_error": entry.last_error,
+                })
+            })
+            .collect();
+        vhosts.sort_by(|a, b| a["domain"].as_str().cmp(&b["domain"].as_str()));
+        serde_json::Value::Array(vhosts)
+    }
+}
+
+fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filesystem_failures_are_told_apart() {
+        use nix::errno::Errno;
+
+        for errno in [Errno::EIO, Errno::ESTALE, Errno::ENOTCONN] {
+            assert!(is_filesystem_failure(&io::Error::from_raw_os_error(
+                errno as i32
+            )));
+        }
+        for errno in [Errno::ENOENT, Errno::ENOTDIR, Errno::EACCES] {
+            assert!(!is_filesystem_failure(&io::Error::from_raw_os_error(
+                errno as i32
+            )));
+        }
+
+        let wrapped = anyhow::Error::new(io::Error::from_raw_os_error(Errno::ESTALE as i32))
+            .context("serving /index.html");
+        assert!(filesystem_failure(&wrapped).is_some());
+        assert!(filesystem_failure(&anyhow::anyhow!("File too large")).is_none());
+    }
+
+    #[test]
+    fn test_probe_separates_missing_from_failed_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.html"), "a").unwrap();
+
+        assert!(matches!(probe(&dir.path().join("a.html")), Probe::File));
+        assert!(matches!(probe(&dir.path().join("nope")), Probe::Missing));
+        assert!(probe_root(dir.path()).is_ok());
+        assert!(probe_root(&dir.path().join("a.html")).is_err());
+        assert!(probe_root(&dir.path().join("gone")).is_err());
+    }
+
+    #[test]
+    fn test_degraded_until_recovery() {
+        let health = DocrootHealth::new();
+        assert!(!health.is_degraded("example.com"));
+
+        let error = io::Error::other("stale file handle");
+        health.record_failure("example.com", Path::new("/srv/nfs"), &error);
+        health.record_failure("example.com", Path::new("/srv/nfs"), &error);
+        assert!(health.is_degraded("example.com"));
+        assert!(!health.is_degraded("other.com"));
+
+        let status = health.status_json();
+        assert_eq!(status[0]["domain"], "example.com");
+        assert_eq!(status[0]["failures"], 2);
+
+        health.record_recovery("example.com");
+        assert!(!health.is_degraded("example.com"));
+        assert_eq!(health.status_json(), json!([]));
+    }
+}
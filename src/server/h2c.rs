@@ -0,0 +1,132 @@
+//! HTTP/2 over cleartext with prior knowledge (`server.h2c_prior_knowledge`)
+//!
+//! A client with prior knowledge opens the connection with the HTTP/2
+//! preface instead of an HTTP/1.1 request line. The accept loop reads just
+//! enough to tell the two apart with [`sniff`], then hands the connection to
+//! the matching hyper builder behind a [`Rewind`] that replays what was read.
+//!
+//! `Upgrade: h2c` on an HTTP/1.1 request is ignored and the request answered
+//! over HTTP/1.1, as RFC 9113 permits; that mechanism is deprecated and hyper
+//! can't carry the upgraded request over as the first HTTP/2 stream.
+
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+/// First bytes of every HTTP/2 connection
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Read from `stream` until it either starts with [`PREFACE`] or can't;
+/// returns which, and the bytes read
+pub async fn sniff<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(bool, Bytes)> {
+    let mut read = BytesMut::with_capacity(PREFACE.len());
+    while read.len() < PREFACE.len() {
+        if !PREFACE.starts_with(&read) {
+            return Ok((false, read.freeze()));
+        }
+        if stream.read_buf(&mut read).await? == 0 {
+            return Ok((false, read.freeze()));
+        }
+    }
+    Ok((read.starts_with(PREFACE), read.freeze()))
+}
+
+/// A stream whose first reads return bytes already taken from it
+pub struct Rewind<S> {
+    prefix: Bytes,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub fn new(prefix: Bytes, inner: S) -> Self {
+        Self { prefix, inner }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.prefix.has_remaining() {
+            let n = self.prefix.len().min(buf.remaining());
+            buf.put_slice(&self.prefix[..n]);
+            self.prefix.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn sniffed(sent: &[u8]) -> (bool, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        tokio::io::AsyncWriteExt::write_all(&mut client, sent)
+            .await
+            .unwrap();
+        drop(client);
+
+        let (h2, prefix) = sniff(&mut server).await.unwrap();
+        let mut replayed = Vec::new();
+        Rewind::new(prefix, server)
+            .read_to_end(&mut replayed)
+            .await
+            .unwrap();
+        (h2, replayed)
+    }
+
+    #[tokio::test]
+    async fn test_sniff_preface() {
+        let mut h2 = PREFACE.to_vec();
+        h2.extend_from_slice(b"\x00\x00\x00\x04\x00\x00\x00\x00\x00");
+        assert_eq!(sniffed(&h2).await, (true, h2.clone()));
+
+        let http1 = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert_eq!(sniffed(http1).await, (false, http1.to_vec()));
+
+        // Cut short, or close but not quite
+        assert_eq!(
+            sniffed(b"PRI * HTTP").await,
+            (false, b"PRI * HTTP".to_vec())
+        );
+        let almost = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r";
+        assert_eq!(sniffed(almost).await, (false, almost.to_vec()));
+    }
+}
@@ -3,31 +3,94 @@
 //! Handles incoming HTTP requests similar to Nginx/Apache/LiteSpeed.
 //! Supports static files, PHP processing, and URL rewriting.
 
-use crate::cache::{build_page_cache_key, build_page_cache_key_scoped, CacheManager};
-use crate::config::Config;
+use crate::cache::{
+    build_page_cache_key, build_page_cache_key_scoped, normalize_host, CacheLifetime, CacheManager,
+    CacheStore, CachedResponse, ENTRY_FORMAT_VERSION,
+};
+use crate::config::{Config, SymlinkPolicy, UploadPolicy};
 use crate::php::sapi::PhpResponse;
-use crate::php::PhpPool;
+use crate::php::{CgiOutput, PhpPool, PoolState};
+use crate::server::autoindex;
+use crate::server::cache_control;
 use crate::server::cache_warmer::{CacheWarmer, WarmRequestPayload};
+use crate::server::client_addr::{ConnectionInfo, TrustedProxies};
+use crate::server::compression;
+use crate::server::connections::ConnectionLimiter;
+use crate::server::docroot::{self, DocrootHealth, Probe};
+use crate::server::image_optimizer::ImageOptimizer;
+use crate::server::invalidation_guard::{self, InvalidationGuard, InvalidationGuardResult};
+use crate::server::litespeed::{self, CacheDirective, LiteSpeedCache, Purge};
+use crate::server::log_format::UpstreamTime;
+use crate::server::metrics::Metrics;
+use crate::server::panics;
+use crate::server::proxy;
+use crate::server::readonly::{is_write_method, ReadOnlyMode};
+use crate::server::rewrite::{OriginalUri, Rewrite};
+use crate::server::scheduler::CacheScheduler;
+use crate::server::shared_limits::{Limiter, SharedLimits};
+use crate::server::state::{
+    export_state, import_state, ExportOptions, RuntimeState, MAX_SNAPSHOT_BYTES,
+};
 use crate::server::static_files::StaticFileHandler;
+use crate::server::streaming::StreamingBody;
+use crate::server::symlinks;
+use crate::server::vhost::{
+    content_type_matches, expand_try_uri, query_allowed, CompiledConfig, CompiledVhost, DenyList,
+    TryFile, TryFiles, DEFAULT_DOC_ROOT, DEFAULT_INDEX_FILES,
+};
+use crate::server::zerocopy::ZeroCopySlot;
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use dashmap::DashMap;
-use http_body_util::{BodyExt, Full};
-use hyper::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, SET_COOKIE};
-use hyper::http::{HeaderMap, HeaderValue};
-use hyper::{Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::header::{
+    ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION,
+    CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, ETAG, EXPIRES, HOST, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, PRAGMA, RANGE, SET_COOKIE, VARY, WARNING,
+    X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use hyper::http::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, Request, Response, StatusCode, Uri};
 use once_cell::sync::Lazy;
-use parking_lot::Mutex;
 use serde::Deserialize;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
+/// Marks a response built by the handler's own error helpers, which the
+/// vhost's `error_pages` may replace
+#[derive(Debug, Clone, Copy)]
+struct BuiltinError;
+
+/// Tag of every cached 404 (`cache.negative_ttl`), alone and as
+/// `negative:<host>`
+const NEGATIVE_TAG: &str = "negative";
+/// Tag of a cached built-in 404, which `error_pages` still replaces on hits
+const BUILTIN_ERROR_TAG: &str = "builtin-error";
+/// Cached 404s at most, so scans for missing URLs can't push real pages
+/// out of L1
+const MAX_NEGATIVE_ENTRIES: usize = 1024;
+
+/// Long-lived services shared by every request
+#[derive(Clone)]
+pub struct HandlerServices {
+    pub cache: Arc<CacheManager>,
+    pub warmer: Arc<CacheWarmer>,
+    pub php_pool: Arc<PhpPool>,
+    pub readonly: Arc<ReadOnlyMode>,
+    pub images: Arc<ImageOptimizer>,
+    pub scheduler: Arc<CacheScheduler>,
+    pub limits: Arc<SharedLimits>,
+    pub invalidations: Arc<InvalidationGuard>,
+    pub docroots: Arc<DocrootHealth>,
+    pub connections: Arc<ConnectionLimiter>,
+    pub metrics: Arc<Metrics>,
+}
+
 /// Request handler for VeloServe
 ///
 /// Implements request handling similar to traditional web servers:
@@ -37,12 +100,63 @@ use tracing::{debug, info, warn};
 /// - Try-files pattern for clean URLs
 pub struct RequestHandler {
     config: Arc<Config>,
+    compiled: Arc<CompiledConfig>,
     cache: Arc<CacheManager>,
     warmer: Arc<CacheWarmer>,
     php_pool: Arc<PhpPool>,
+    readonly: Arc<ReadOnlyMode>,
+    images: Arc<ImageOptimizer>,
+    scheduler: Arc<CacheScheduler>,
+    limits: Arc<SharedLimits>,
+    invalidations: Arc<InvalidationGuard>,
+    docroots: Arc<DocrootHealth>,
+    connections: Arc<ConnectionLimiter>,
+    metrics: Arc<Metrics>,
     static_handler: StaticFileHandler,
 }
 
+/// A PHP response naming a file to send in its place (`X-Sendfile`,
+/// `X-Accel-Redirect`); also marks the response that replaced it, which
+/// the page cache must not store
+#[derive(Debug, Clone)]
+struct Sendfile {
+    /// Header it came from
+    header: &'static str,
+    /// Filesystem path (`X-Sendfile`) or URI below `sendfile_root`
+    target: String,
+    /// Content-Type the script set, if it did
+    content_type: Option<String>,
+}
+
+/// Headers a script's hand-off response keeps on the file it names
+const SENDFILE_MERGED_HEADERS: &[HeaderName] = &[
+    CONTENT_DISPOSITION,
+    SET_COOKIE,
+    CACHE_CONTROL,
+    EXPIRES,
+    PRAGMA,
+    X_FRAME_OPTIONS,
+    X_CONTENT_TYPE_OPTIONS,
+];
+
+/// Where `try_files` sends a request
+#[derive(Debug)]
+enum TryTarget {
+    /// Serve this file; PHP scripts run with its name and PATH_INFO
+    File(PhpPathInfo),
+    /// Answer with this status
+    Status(StatusCode),
+}
+
+/// ACME HTTP-01 challenges, never redirected to HTTPS
+const ACME_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Seconds a page cache entry stays fresh (`cache.debug_headers`)
+const X_CACHE_TTL: &str = "x-cache-ttl";
+
+/// Try-files used when no virtual host matches the request
+static DEFAULT_TRY_FILES: Lazy<TryFiles> = Lazy::new(TryFiles::default);
+
 /// Result of resolving a PHP script path
 #[derive(Debug)]
 struct PhpPathInfo {
@@ -59,61 +173,47 @@ struct CacheContext {
     key: String,
     domain: String,
     path: String,
+    /// `http://host/path?query` as requested, kept on the entry for re-warming
+    url: String,
+    /// Used when the response doesn't set its own lifetime
     ttl: Duration,
+    /// An active `set_ttl` window, which beats the response's own lifetime
+    scheduled_ttl: Option<Duration>,
+    /// How long past its TTL the page may still be served while refreshed
+    stale_grace: Duration,
+    /// Content types the vhost keeps out of the cache
+    exclude_types: Arc<Vec<String>>,
+    /// The request has cookies, so cached 404s are neither served nor stored
+    cookies: bool,
+}
+
+impl CacheContext {
+    /// Whether a cached entry may answer this request
+    fn serves(&self, entry: &CachedResponse) -> bool {
+        !(self.cookies && entry.status == StatusCode::NOT_FOUND.as_u16())
+    }
 }
 
-const INVALIDATION_DEDUPE_WINDOW_SECS: u64 = 15;
-const INVALIDATION_RATE_WINDOW_SECS: u64 = 60;
-const INVALIDATION_RATE_LIMIT: usize = 120;
 const INVALIDATION_MAX_TARGETS: usize = 128;
 const INVALIDATION_MAX_GROUPS: usize = 32;
 const INVALIDATION_MAX_TAGS_PER_GROUP: usize = 64;
 
-static INVALIDATION_GUARD: Lazy<InvalidationGuard> = Lazy::new(InvalidationGuard::default);
-
-#[derive(Default)]
-struct InvalidationGuard {
-    dedupe: DashMap<String, u64>,
-    recent_requests: Mutex<VecDeque<u64>>,
-}
-
-enum InvalidationGuardResult {
-    Accepted { deduped: bool },
-    RateLimited,
-}
-
-impl InvalidationGuard {
-    fn evaluate(&self, dedupe_key: &str) -> InvalidationGuardResult {
-        let now = now_epoch_secs();
-
-        {
-            let mut requests = self.recent_requests.lock();
-            while let Some(oldest) = requests.front().copied() {
-                if now.saturating_sub(oldest) >= INVALIDATION_RATE_WINDOW_SECS {
-                    requests.pop_front();
-                } else {
-                    break;
-                }
-            }
-            if requests.len() >= INVALIDATION_RATE_LIMIT {
-                return InvalidationGuardResult::RateLimited;
-            }
-            requests.push_back(now);
-        }
-
-        self.dedupe
-            .retain(|_, ts| now.saturating_sub(*ts) <= INVALIDATION_DEDUPE_WINDOW_SECS);
-
-        let deduped = self
-            .dedupe
-            .get(dedupe_key)
-            .map(|ts| now.saturating_sub(*ts) <= INVALIDATION_DEDUPE_WINDOW_SECS)
-            .unwrap_or(false);
-        self.dedupe.insert(dedupe_key.to_string(), now);
-
-        InvalidationGuardResult::Accepted { deduped }
-    }
-}
+/// Types a browser renders as active content; `+xml` types count as well
+const RISKY_UPLOAD_TYPES: &[&str] = &["text/html", "text/xml", "application/xml"];
+
+/// Response headers that are connection- or hit-specific and never stored in
+/// the page cache (lowercase, as `HeaderName::as_str` returns them)
+const UNCACHEABLE_RESPONSE_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "content-length",
+    "date",
+    "age",
+    "set-cookie",
+    "x-cache",
+    "x-cache-ttl",
+];
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -156,69 +256,289 @@ struct InvalidationRequest {
     idempotency_key: Option<String>,
 }
 
+/// Body of `POST /api/v1/readonly`
+#[derive(Debug, Deserialize)]
+struct ReadOnlyToggle {
+    enabled: bool,
+    #[serde(default)]
+    vhost: Option<String>,
+    #[serde(default)]
+    duration_secs: Option<u64>,
+}
+
 impl RequestHandler {
-    /// Create a new request handler
-    pub fn new(
-        config: Arc<Config>,
-        cache: Arc<CacheManager>,
-        warmer: Arc<CacheWarmer>,
-        php_pool: Arc<PhpPool>,
-    ) -> Self {
-        let static_handler = StaticFileHandler::new();
+    /// Create a new request handler bound to one configuration snapshot
+    pub fn new(compiled: Arc<CompiledConfig>, services: &HandlerServices) -> Self {
+        let static_handler = StaticFileHandler::from_config(&compiled.config.static_files)
+            .with_etag_mode(compiled.config.server.etag)
+            .with_mime_types(compiled.mime_types.clone());
 
         Self {
-            config,
-            cache,
-            warmer,
-            php_pool,
+            config: compiled.config.clone(),
+            compiled,
+            cache: services.cache.clone(),
+            warmer: services.warmer.clone(),
+            php_pool: services.php_pool.clone(),
+            readonly: services.readonly.clone(),
+            images: services.images.clone(),
+            scheduler: services.scheduler.clone(),
+            limits: services.limits.clone(),
+            invalidations: services.invalidations.clone(),
+            docroots: services.docroots.clone(),
+            connections: services.connections.clone(),
+            metrics: services.metrics.clone(),
             static_handler,
         }
     }
 
     /// Handle an incoming request
     ///
+    /// Built-in 403, 404 and 500 pages are replaced by the vhost's
+    /// `error_pages` entry when it has one, and the result is compressed
+    /// for the client; see [`Self::route`] for how the request itself is
+    /// processed.
+    pub async fn handle(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        // API endpoints keep their own error bodies
+        let api = req.uri().path().starts_with("/api/v1/");
+        let vhost = self.find_vhost(&req);
+        let error_pages = vhost
+            .filter(|vhost| !api && !vhost.config.error_pages.is_empty())
+            .map(|vhost| (vhost, req.headers().clone()));
+        let method = req.method().clone();
+        let accept_encoding = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let https = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .is_some_and(|conn| conn.https);
+
+        let response = self.route(req).await?;
+        let mut response = match error_pages {
+            Some((vhost, headers)) if response.extensions().get::<BuiltinError>().is_some() => {
+                self.error_page(vhost, &method, headers, response).await?
+            }
+            _ => response,
+        };
+        self.compiled
+            .security_headers
+            .apply(response.headers_mut(), https);
+        compression::compress(
+            &self.compiled.config.server.compression,
+            &method,
+            accept_encoding.as_deref(),
+            response,
+        )
+        .await
+    }
+
+    /// Route a request to the file, PHP script or endpoint that answers it
+    ///
     /// Request processing order (similar to Nginx/Apache):
-    /// 1. Internal endpoints (health, API)
+    /// 0. Oversized request targets and header blocks (414, 431)
+    /// 1. Internal endpoints (health, metrics, API), then HTTPS redirects,
+    ///    rewrite rules and `proxy_pass` locations
     /// 2. Check if exact file exists
-    /// 3. If directory, try index files
+    /// 3. If directory, redirect to its trailing-slash URL or try index files
     /// 4. If PHP file, execute with PATH_INFO
     /// 5. Try files pattern for clean URLs
     /// 6. Return 404
-    pub async fn handle(
+    async fn route(
         &self,
-        req: Request<hyper::body::Incoming>,
+        mut req: Request<hyper::body::Incoming>,
     ) -> Result<Response<Full<Bytes>>> {
         let method = req.method().clone();
-        let path = req.uri().path().to_string();
+        let mut path = req.uri().path().to_string();
+
+        if let Some(response) = self.oversized_request(&req) {
+            return response;
+        }
 
         // Health check endpoint (internal)
         if path == "/health" || path == "/healthz" {
             return self.health_check();
         }
+        if path == "/ready" || path == "/readyz" {
+            return self.readiness_check();
+        }
+        // Anyone else gets whatever the vhost serves at that path
+        if path == "/metrics" && self.client_allowed(&req, &self.compiled.metrics_allow) {
+            return self.prometheus_metrics();
+        }
 
         // API endpoints (internal)
         if path.starts_with("/api/v1/") {
             return self.handle_api(req).await;
         }
 
+        panics::test_trigger("handler", &path);
+
         // Find the virtual host and document root
-        let (doc_root, vhost) = self.find_vhost(&req);
+        let vhost = self.find_vhost(&req);
+        let doc_root = vhost
+            .map(|v| v.root.clone())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DOC_ROOT));
         debug!("Document root: {:?}, path: {}", doc_root, path);
 
-        let cache_context = self.cache_context(&req, &path, vhost);
-        if let Some(context) = &cache_context {
-            if let Some((data, content_type)) = self.cache.get_with_metadata(&context.key).await {
-                return self.cached_response(&method, &data, &content_type);
+        // Cache plugins invalidate pages with `PURGE <path>`
+        if method.as_str() == "PURGE" {
+            return self.purge_request(&req).await;
+        }
+
+        // Our own background refreshes arrive over plain HTTP too
+        let revalidation = self.warmer.is_revalidation(req.headers());
+
+        // ACME HTTP-01 challenges must stay reachable over plain HTTP
+        if self.redirects_to_https(&req, vhost)
+            && !revalidation
+            && !path.starts_with(ACME_CHALLENGE_PREFIX)
+        {
+            return self.https_redirect(&req);
+        }
+
+        let readonly = self.readonly.is_active(self.request_host(&req));
+        if readonly && is_write_method(&method) && !self.compiled.readonly_allow.matches(&path) {
+            info!("Rejected {} {} (read-only mode)", method, path);
+            return self.readonly_rejected();
+        }
+
+        // Everything from here on sees the rewritten path
+        let rewrite = vhost.and_then(|vhost| vhost.rewrites.apply(&path, req.uri().query()));
+        match rewrite {
+            Some(Rewrite::Internal(uri)) => {
+                debug!("Rewrote {} to {}", req.uri(), uri);
+                let Ok(uri) = uri.parse::<hyper::Uri>() else {
+                    warn!("Rewrite of {} produced an invalid URI: {}", path, uri);
+                    return self.internal_error("Invalid rewrite");
+                };
+                let original = std::mem::replace(req.uri_mut(), uri);
+                req.extensions_mut().insert(OriginalUri(original));
+                path = req.uri().path().to_string();
+            }
+            Some(Rewrite::Redirect { status, location }) => {
+                debug!("Redirecting {} to {} (rewrite)", path, location);
+                return self.rewrite_redirect(status, &location);
+            }
+            Some(Rewrite::Loop) => {
+                warn!("Rewrite cycle for {} {}", method, path);
+                return self.internal_error("Rewrite cycle");
+            }
+            None => {}
+        }
+
+        // Proxied locations never touch the docroot
+        if let Some(upstream) = vhost.and_then(|vhost| vhost.upstream(&path)) {
+            debug!("Proxying {} {} to {}", method, path, upstream);
+            return match proxy::forward(req, upstream, &self.compiled.trusted_proxies).await {
+                Ok(response) => Ok(response),
+                Err(e) => proxy::bad_gateway(upstream, &e),
+            };
+        }
+
+        // Dotfiles and deny_files globs never reach the filesystem
+        let decoded = percent_encoding::percent_decode_str(&path).decode_utf8_lossy();
+        let denied = match vhost {
+            Some(vhost) => vhost.deny.matches(&decoded),
+            None => DenyList::default().matches(&decoded),
+        };
+        if denied {
+            debug!("Refused {} {} (denied file)", method, path);
+            return self.forbidden("Access to this file is denied");
+        }
+
+        let cache_decision = self.cache_context(&req, &path, vhost);
+        let cache = cache_decision.as_ref().map_err(|reason| *reason);
+        let cache_context = cache.ok();
+
+        // A degraded docroot isn't touched again until a probe says it's back
+        if let Some(vhost) = vhost {
+            if self.docroots.is_degraded(&vhost.config.domain) {
+                match docroot::probe_root(&vhost.root) {
+                    Ok(()) => self.docroots.record_recovery(&vhost.config.domain),
+                    Err(e) => return self.docroot_unavailable(vhost, &e, cache_context, &method),
+                }
+            }
+        }
+
+        if let Some(context) = cache_context {
+            // While read-only, anything we still have beats going to PHP
+            if readonly {
+                if let Some(entry) = self
+                    .cache
+                    .inspect(&context.key)
+                    .filter(|entry| context.serves(entry))
+                {
+                    let status = if entry.is_stale() { "STALE" } else { "HIT" };
+                    return self.cached_response(&method, &entry, status);
+                }
+            }
+            if revalidation {
+                debug!("Refreshing stale page {}", context.key);
+            } else if let Some(entry) = self
+                .cache
+                .get_allow_stale(&context.key)
+                .await
+                .filter(|entry| context.serves(entry))
+            {
+                if !entry.is_stale() {
+                    debug!("Page cache hit for {} {}", method, context.key);
+                    return self.cached_response(&method, &entry, "HIT");
+                }
+                debug!("Serving stale page {} {}", method, context.key);
+                if let Some(claim) = self.cache.begin_revalidation(&context.key) {
+                    let uri = req
+                        .uri()
+                        .path_and_query()
+                        .map_or_else(|| path.clone(), |pq| pq.as_str().to_string());
+                    let mut headers = req.headers().clone();
+                    if !headers.contains_key(HOST) {
+                        if let Some(authority) = req.uri().authority() {
+                            if let Ok(host) = HeaderValue::from_str(authority.as_str()) {
+                                headers.insert(HOST, host);
+                            }
+                        }
+                    }
+                    self.warmer.revalidate(uri, headers, claim);
+                }
+                return self.cached_response(&method, &entry, "STALE");
+            } else {
+                debug!("Page cache miss for {} {}", method, context.key);
             }
         }
 
+        // Concurrent misses for one page generate it once; the others wait
+        // and are served what it stored
+        let _fill = match cache_context {
+            Some(context) if method == Method::GET && !revalidation => {
+                let fill = self.cache.begin_fill(&context.key).await;
+                if fill.is_none() {
+                    if let Some(entry) = self
+                        .cache
+                        .get_response(&context.key)
+                        .await
+                        .filter(|entry| context.serves(entry))
+                    {
+                        debug!("Page cache filled for {} {}", method, context.key);
+                        return self.cached_response(&method, &entry, "HIT");
+                    }
+                }
+                fill
+            }
+            _ => None,
+        };
+
         // Get index files from vhost config or use defaults
         let index_files = vhost.map(|v| v.index.clone()).unwrap_or_else(|| {
-            vec![
-                "index.php".to_string(),
-                "index.html".to_string(),
-                "index.htm".to_string(),
-            ]
+            DEFAULT_INDEX_FILES
+                .iter()
+                .map(|index| index.to_string())
+                .collect()
         });
 
         // Read the request body for POST/PUT requests
@@ -244,58 +564,108 @@ impl RequestHandler {
 
         // Step 1: Try the exact URI as a file
         let file_path = self.resolve_path(&doc_root, &path);
+        let file_probe = docroot::probe(&file_path);
+        if let (Probe::Unavailable(e), Some(vhost)) = (&file_probe, vhost) {
+            return self.docroot_unavailable(vhost, e, cache_context, &method);
+        }
+
+        let symlink_policy = vhost
+            .map(|vhost| vhost.config.follow_symlinks)
+            .unwrap_or_default();
+        if let Some(status) = self.path_refusal(&doc_root, &file_path, symlink_policy) {
+            return self.refuse_path(status, &file_path, cache, &method).await;
+        }
 
-        if file_path.is_file() {
+        if matches!(file_probe, Probe::File) {
             // Exact file exists
             if self.is_php_file(&file_path) {
                 // PHP file - execute it
                 let response = self
                     .execute_php(req_parts, &doc_root, &file_path, &path, "", body)
                     .await?;
-                return self
-                    .finalize_response(response, cache_context.as_ref(), &method)
-                    .await;
+                return self.finalize_response(response, cache, &method).await;
             } else {
                 // Static file - serve it
-                let response = self.serve_static_parts(req_parts, &file_path).await?;
-                return self
-                    .finalize_response(response, cache_context.as_ref(), &method)
-                    .await;
+                let response = match self
+                    .serve_static_parts(req_parts, &doc_root, &file_path)
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(e) => return self.static_error(e, vhost, cache_context, &method),
+                };
+                return self.finalize_response(response, cache, &method).await;
             }
         }
 
         // Step 2: If directory, try index files (like DirectoryIndex in Apache)
-        if file_path.is_dir() {
+        if matches!(file_probe, Probe::Dir) {
+            // Relative links in the index resolve against the directory only
+            // when its URL ends in a slash (Apache's DirectorySlash)
+            if !path.ends_with('/') {
+                return self.directory_redirect(req_parts);
+            }
             for index in &index_files {
                 let index_path = file_path.join(index);
                 if index_path.is_file() {
+                    if let Some(status) = self.path_refusal(&doc_root, &index_path, symlink_policy)
+                    {
+                        return self.refuse_path(status, &index_path, cache, &method).await;
+                    }
                     let index_uri = format!("{}/{}", path.trim_end_matches('/'), index);
 
                     if self.is_php_file(&index_path) {
                         let response = self
                             .execute_php(req_parts, &doc_root, &index_path, &index_uri, "", body)
                             .await?;
-                        return self
-                            .finalize_response(response, cache_context.as_ref(), &method)
-                            .await;
+                        return self.finalize_response(response, cache, &method).await;
                     } else {
-                        let response = self.serve_static_parts(req_parts, &index_path).await?;
-                        return self
-                            .finalize_response(response, cache_context.as_ref(), &method)
-                            .await;
+                        let response = match self
+                            .serve_static_parts(req_parts, &doc_root, &index_path)
+                            .await
+                        {
+                            Ok(response) => response,
+                            Err(e) => return self.static_error(e, vhost, cache_context, &method),
+                        };
+                        return self.finalize_response(response, cache, &method).await;
                     }
                 }
             }
-            // No index file found - return 403 (no directory listing)
+            // No index file found - list it if the vhost allows, else 403
+            if vhost.is_some_and(|vhost| vhost.config.autoindex) {
+                // Listings change with the directory, so they skip the page cache
+                let listing = self.directory_listing(req_parts, &file_path, &path, symlink_policy);
+                return match listing.await {
+                    Ok(response) => Ok(response),
+                    Err(e) => self.static_error(e, vhost, cache_context, &method),
+                };
+            }
+            if let Some(fallback) = self.spa_fallback(vhost, &doc_root, &path, &method) {
+                return self
+                    .serve_spa_fallback(req_parts, &doc_root, &fallback, vhost, &method)
+                    .await;
+            }
             let response = self.forbidden("Directory listing denied")?;
-            return self
-                .finalize_response(response, cache_context.as_ref(), &method)
-                .await;
+            return self.finalize_response(response, cache, &method).await;
+        }
+
+        // The path is missing; make sure the docroot itself didn't vanish
+        // before answering 404 or handing the request to a front controller
+        if let Some(vhost) = vhost {
+            if let Err(e) = docroot::probe_root(&vhost.root) {
+                return self.docroot_unavailable(vhost, &e, cache_context, &method);
+            }
         }
 
         // Step 3: Check for PHP file with PATH_INFO
         // This handles URLs like /index.php/page/1 or /blog.php/post/hello
         if let Some(php_info) = self.resolve_php_path_info(&doc_root, &path) {
+            if let Some(status) =
+                self.path_refusal(&doc_root, &php_info.script_filename, symlink_policy)
+            {
+                return self
+                    .refuse_path(status, &php_info.script_filename, cache, &method)
+                    .await;
+            }
             let response = self
                 .execute_php(
                     req_parts,
@@ -306,41 +676,270 @@ impl RequestHandler {
                     body,
                 )
                 .await?;
+            return self.finalize_response(response, cache, &method).await;
+        }
+
+        // Step 4: a single-page app's shell, in place of the front controller
+        if let Some(fallback) = self.spa_fallback(vhost, &doc_root, &path, &method) {
             return self
-                .finalize_response(response, cache_context.as_ref(), &method)
+                .serve_spa_fallback(req_parts, &doc_root, &fallback, vhost, &method)
                 .await;
         }
 
-        // Step 4: Try files pattern (like Nginx try_files $uri $uri/ /index.php$is_args$args)
+        // Step 5: try_files (Nginx-style; `$uri $uri/ /index.php$is_args$args` by default)
         // This is essential for WordPress, Laravel, and other frameworks with clean URLs
-        if self.php_pool.is_available() {
-            // Try /index.php with the original URI as PATH_INFO
-            let front_controller = doc_root.join("index.php");
-            if front_controller.is_file() {
-                debug!(
-                    "Using front controller pattern: index.php with PATH_INFO={}",
-                    path
-                );
-                let response = self
-                    .execute_php(
-                        req_parts,
-                        &doc_root,
-                        &front_controller,
-                        "/index.php",
-                        &path,
-                        body,
-                    )
-                    .await?;
-                return self
-                    .finalize_response(response, cache_context.as_ref(), &method)
-                    .await;
+        let try_files = vhost.map_or(&*DEFAULT_TRY_FILES, |vhost| &vhost.try_files);
+        let target = self.resolve_try_files(
+            &doc_root,
+            try_files,
+            &path,
+            req_parts.uri.query(),
+            &index_files,
+        );
+        let target = match target {
+            TryTarget::File(target) => target,
+            // Step 6: Nothing found - return 404 (or the configured `=code`)
+            TryTarget::Status(status) => {
+                let response = self.status_response(status)?;
+                return self.finalize_response(response, cache, &method).await;
             }
+        };
+        if let Some(status) = self.path_refusal(&doc_root, &target.script_filename, symlink_policy)
+        {
+            return self
+                .refuse_path(status, &target.script_filename, cache, &method)
+                .await;
         }
+        let response = if self.is_php_file(&target.script_filename) {
+            debug!(
+                "Using try_files target {} with PATH_INFO={}",
+                target.script_name, target.path_info
+            );
+            self.execute_php(
+                req_parts,
+                &doc_root,
+                &target.script_filename,
+                &target.script_name,
+                &target.path_info,
+                body,
+            )
+            .await?
+        } else {
+            match self
+                .serve_static_parts(req_parts, &doc_root, &target.script_filename)
+                .await
+            {
+                Ok(response) => response,
+                Err(e) => return self.static_error(e, vhost, cache_context, &method),
+            }
+        };
+        self.finalize_response(response, cache, &method).await
+    }
 
-        // Step 5: Nothing found - return 404
-        let response = self.not_found()?;
-        self.finalize_response(response, cache_context.as_ref(), &method)
-            .await
+    /// 414 or 431 for a request past `server.max_uri_length` or
+    /// `server.max_header_bytes`
+    ///
+    /// Header bytes are counted as on the wire: each name and value plus
+    /// `": "` and the line break.
+    fn oversized_request(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Option<Result<Response<Full<Bytes>>>> {
+        let server = &self.config.server;
+        let uri_length = req
+            .uri()
+            .path_and_query()
+            .map_or(0, |target| target.as_str().len());
+        if uri_length > server.max_uri_length {
+            debug!(
+                "Refused {} request: URI is {} bytes (max_uri_length = {})",
+                req.method(),
+                uri_length,
+                server.max_uri_length
+            );
+            return Some(self.error_response(
+                StatusCode::URI_TOO_LONG,
+                "The requested URI is longer than this server allows.",
+            ));
+        }
+
+        let header_bytes: usize = req
+            .headers()
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum();
+        if header_bytes > server.max_header_bytes {
+            debug!(
+                "Refused {} {}: headers are {} bytes (max_header_bytes = {})",
+                req.method(),
+                req.uri().path(),
+                header_bytes,
+                server.max_header_bytes
+            );
+            return Some(self.error_response(
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "The request's header fields are larger than this server allows.",
+            ));
+        }
+        None
+    }
+
+    /// The vhost's `spa_fallback` file, if it applies to this request
+    ///
+    /// Only GET and HEAD fall back, and paths ending in a file extension only
+    /// with `spa_fallback_extensions`. A fallback that doesn't exist is
+    /// skipped so the request carries on to `try_files`.
+    fn spa_fallback(
+        &self,
+        vhost: Option<&CompiledVhost>,
+        doc_root: &Path,
+        path: &str,
+        method: &Method,
+    ) -> Option<PathBuf> {
+        let vhost = vhost?;
+        let fallback = vhost.config.spa_fallback.as_deref()?;
+        if method != Method::GET && method != Method::HEAD {
+            return None;
+        }
+        let has_extension = !path.ends_with('/') && Path::new(path).extension().is_some();
+        if has_extension && !vhost.config.spa_fallback_extensions {
+            return None;
+        }
+        let file = self.resolve_path(doc_root, fallback);
+        if !file.is_file() {
+            debug!(
+                "spa_fallback {} for {} is missing",
+                fallback, vhost.config.domain
+            );
+            return None;
+        }
+        Some(file)
+    }
+
+    /// Serve a `spa_fallback` file under the requested URL
+    ///
+    /// The response is marked `no-cache` so clients pick up a new build, and
+    /// stays out of the page cache, which would otherwise keep a copy of the
+    /// same file for every route.
+    async fn serve_spa_fallback(
+        &self,
+        req_parts: &hyper::http::request::Parts,
+        doc_root: &Path,
+        file: &Path,
+        vhost: Option<&CompiledVhost>,
+        method: &Method,
+    ) -> Result<Response<Full<Bytes>>> {
+        let symlink_policy = vhost
+            .map(|vhost| vhost.config.follow_symlinks)
+            .unwrap_or_default();
+        if let Some(status) = self.path_refusal(doc_root, file, symlink_policy) {
+            return self
+                .refuse_path(status, file, Err("spa_fallback"), method)
+                .await;
+        }
+        let mut response = match self.serve_static_parts(req_parts, doc_root, file).await {
+            Ok(response) => response,
+            Err(e) => return self.static_error(e, vhost, None, method),
+        };
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        Ok(response)
+    }
+
+    /// Walk `try_files` for a path that is neither a file nor a directory
+    ///
+    /// Candidates are served only if they exist. The fallback URI is served
+    /// with the request path as PATH_INFO (the front controller), unless it
+    /// is missing or is a PHP script while PHP is unavailable.
+    fn resolve_try_files(
+        &self,
+        doc_root: &Path,
+        try_files: &TryFiles,
+        path: &str,
+        query: Option<&str>,
+        index_files: &[String],
+    ) -> TryTarget {
+        for template in try_files.candidates() {
+            let uri = expand_try_uri(template, path, query);
+            let file = self.resolve_path(doc_root, &uri);
+            if uri.ends_with('/') {
+                let index = index_files
+                    .iter()
+                    .find(|index| file.join(index.as_str()).is_file());
+                if let (true, Some(index)) = (file.is_dir(), index) {
+                    return TryTarget::File(PhpPathInfo {
+                        script_filename: file.join(index),
+                        script_name: format!("{}{}", uri, index),
+                        path_info: String::new(),
+                    });
+                }
+            } else if file.is_file() {
+                return TryTarget::File(PhpPathInfo {
+                    script_filename: file,
+                    script_name: uri,
+                    path_info: String::new(),
+                });
+            }
+        }
+
+        let template = match try_files.fallback() {
+            TryFile::Status(status) => return TryTarget::Status(*status),
+            TryFile::Uri(template) => template,
+        };
+        let uri = expand_try_uri(template, path, query);
+        let file = self.resolve_path(doc_root, &uri);
+        // A pool that is still starting counts; execute_php waits for it
+        let php_usable = matches!(
+            self.php_pool.state(),
+            PoolState::Ready | PoolState::Starting
+        );
+        if !file.is_file() || (self.is_php_file(&file) && !php_usable) {
+            return TryTarget::Status(StatusCode::NOT_FOUND);
+        }
+        TryTarget::File(PhpPathInfo {
+            script_filename: file,
+            script_name: uri,
+            path_info: path.to_string(),
+        })
+    }
+
+    /// Why `path` must not be served: 403 if it crosses a symlink the vhost's
+    /// `follow_symlinks` refuses, 404 if it resolves outside `doc_root`
+    fn path_refusal(
+        &self,
+        doc_root: &Path,
+        path: &Path,
+        symlink_policy: SymlinkPolicy,
+    ) -> Option<StatusCode> {
+        if !symlinks::allowed(doc_root, path, symlink_policy) {
+            Some(StatusCode::FORBIDDEN)
+        } else if !symlinks::inside_root(doc_root, path) {
+            Some(StatusCode::NOT_FOUND)
+        } else {
+            None
+        }
+    }
+
+    /// Answer a request for a path refused by [`Self::path_refusal`]
+    async fn refuse_path(
+        &self,
+        status: StatusCode,
+        path: &Path,
+        cache: Result<&CacheContext, &'static str>,
+        method: &Method,
+    ) -> Result<Response<Full<Bytes>>> {
+        let response = if status == StatusCode::FORBIDDEN {
+            info!(
+                "Refused {:?}: crosses a symbolic link (follow_symlinks)",
+                path
+            );
+            self.forbidden("Symbolic link not allowed")?
+        } else {
+            info!("Refused {:?}: resolves outside the document root", path);
+            self.not_found()?
+        };
+        self.finalize_response(response, cache, method).await
     }
 
     /// Check if a file is a PHP file
@@ -400,10 +999,111 @@ impl RequestHandler {
         path_info: &str,
         body: Vec<u8>,
     ) -> Result<Response<Full<Bytes>>> {
-        // Check if PHP is available
-        if !self.php_pool.is_available() {
-            warn!("PHP requested but not available: {}", script_name);
-            return self.internal_error("PHP is not available on this server");
+        // Whichever way the script was resolved, its file decides
+        if self
+            .upload_policy(req_parts, doc_root, script_path)
+            .is_some()
+        {
+            warn!(
+                "Refused to run PHP in an upload directory: {}",
+                script_path.display()
+            );
+            let mut response = self.forbidden("PHP execution is not allowed here")?;
+            response
+                .headers_mut()
+                .insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+            return Ok(response);
+        }
+
+        let started = Instant::now();
+        let mut response = self
+            .run_php(
+                req_parts,
+                doc_root,
+                script_path,
+                script_name,
+                path_info,
+                body,
+            )
+            .await?;
+        let (litespeed, purge) = litespeed::take(response.headers_mut());
+        if !purge.is_empty() {
+            self.litespeed_purge(req_parts, &purge).await;
+        }
+        if litespeed != LiteSpeedCache::default() {
+            response.extensions_mut().insert(litespeed);
+        }
+        if let Some(sendfile) = response.extensions_mut().remove::<Sendfile>() {
+            response = self.send_file(req_parts, sendfile, response).await?;
+        }
+        let elapsed = started.elapsed();
+        self.metrics
+            .record_php(elapsed, response.status().is_server_error());
+        response.extensions_mut().insert(UpstreamTime(elapsed));
+        Ok(response)
+    }
+
+    /// Act on a PHP response's `X-LiteSpeed-Purge`: tags or `*`, both only
+    /// ever reaching pages of the requested site
+    async fn litespeed_purge(&self, req_parts: &hyper::http::request::Parts, purge: &Purge) {
+        let host = req_parts
+            .headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+        for tag in &purge.refused {
+            warn!(
+                "Ignored X-LiteSpeed-Purge of reserved tag {} for {}",
+                tag, host
+            );
+        }
+        if !purge.all && purge.tags.is_empty() {
+            return;
+        }
+        if self.purges_suspended(Some(host)) {
+            info!("Ignored X-LiteSpeed-Purge for {} (read-only mode)", host);
+            return;
+        }
+        if purge.all {
+            let purged = self.cache.purge_by_domain(host).await;
+            debug!(
+                "X-LiteSpeed-Purge: * removed {} entries of {}",
+                purged, host
+            );
+        }
+        for tag in &purge.tags {
+            let purged = self
+                .cache
+                .purge_by_tag_count(&litespeed::host_tag(host, tag))
+                .await;
+            debug!(
+                "X-LiteSpeed-Purge: {} removed {} entries of {}",
+                tag, purged, host
+            );
+        }
+    }
+
+    async fn run_php(
+        &self,
+        req_parts: &hyper::http::request::Parts,
+        doc_root: &Path,
+        script_path: &Path,
+        script_name: &str,
+        path_info: &str,
+        body: Vec<u8>,
+    ) -> Result<Response<Full<Bytes>>> {
+        // Requests arriving during startup wait briefly for the pool
+        let grace = Duration::from_millis(self.config.php.startup_grace_ms);
+        match self.php_pool.wait_ready(grace).await {
+            PoolState::Ready => {}
+            PoolState::Starting => {
+                warn!("PHP still starting, deferring: {}", script_name);
+                return self.php_starting();
+            }
+            PoolState::Disabled | PoolState::Unavailable => {
+                warn!("PHP requested but not available: {}", script_name);
+                return self.internal_error("PHP is not available on this server");
+            }
         }
 
         debug!(
@@ -414,6 +1114,8 @@ impl RequestHandler {
             body.len()
         );
 
+        panics::test_trigger("php", &script_path.to_string_lossy());
+
         // Choose execution mode: embed or CGI
         if self.php_pool.is_embed_mode() {
             match self
@@ -448,9 +1150,24 @@ impl RequestHandler {
                 )
                 .await
             {
-                Ok(output) => {
-                    // Parse PHP output (may contain headers)
-                    self.parse_php_response(&output)
+                // Parse PHP output (may contain headers)
+                Ok(CgiOutput::Buffered(output)) => self.parse_php_response(&output),
+                Ok(CgiOutput::Streaming(stream)) => {
+                    let mut response = self.parse_php_response(&stream.head)?;
+                    // A handed-off file replaces whatever the script prints
+                    if response.extensions().get::<Sendfile>().is_some() {
+                        return Ok(response);
+                    }
+                    let host = req_parts
+                        .headers
+                        .get("host")
+                        .and_then(|h| h.to_str().ok())
+                        .unwrap_or("localhost");
+                    let limit = self.compiled.streaming_timeout(host, req_parts.uri.path());
+                    response
+                        .extensions_mut()
+                        .insert(StreamingBody::new(stream.into_channel(limit)));
+                    Ok(response)
                 }
                 Err(e) => {
                     warn!("PHP execution error: {}", e);
@@ -468,13 +1185,30 @@ impl RequestHandler {
         builder = builder.status(status);
 
         let mut content_type_set = false;
+        let mut sendfile = None;
         // Headers is a Vec to support multiple headers with same name (e.g., Set-Cookie)
         for (name, value) in &resp.headers {
             if name.eq_ignore_ascii_case("content-type") {
                 content_type_set = true;
             }
+            if let Some(header) = sendfile_header(name) {
+                sendfile = Some((header, value.clone()));
+                continue;
+            }
             builder = builder.header(name.as_str(), value.as_str());
         }
+        if let Some((header, target)) = sendfile {
+            let content_type = resp
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+                .map(|(_, value)| value.clone());
+            builder = builder.extension(Sendfile {
+                header,
+                target,
+                content_type,
+            });
+        }
 
         if !content_type_set {
             builder = builder.header("Content-Type", "text/html; charset=utf-8");
@@ -502,6 +1236,8 @@ impl RequestHandler {
         let mut builder = Response::builder();
         let mut status = StatusCode::OK;
         let mut content_type = "text/html; charset=utf-8".to_string();
+        let mut content_type_set = false;
+        let mut sendfile = None;
         let mut body = output;
 
         // Check if output starts with HTTP headers
@@ -570,6 +1306,12 @@ impl RequestHandler {
                                 }
                                 "content-type" => {
                                     content_type = value.to_string();
+                                    content_type_set = true;
+                                }
+                                // Served in place of the body, never forwarded
+                                "x-sendfile" | "x-accel-redirect" => {
+                                    sendfile = sendfile_header(name)
+                                        .map(|header| (header, value.to_string()));
                                 }
                                 "location" => {
                                     if status == StatusCode::OK {
@@ -577,8 +1319,15 @@ impl RequestHandler {
                                     }
                                     builder = builder.header("Location", value);
                                 }
+                                // Honoured by the CGI reader, never forwarded
+                                "x-accel-buffering" => {}
+                                // Read and removed again in execute_php
+                                litespeed::CACHE_CONTROL | litespeed::TAG | litespeed::PURGE => {
+                                    builder = builder.header(name, value);
+                                }
                                 "set-cookie"
                                 | "cache-control"
+                                | "content-disposition"
                                 | "expires"
                                 | "pragma"
                                 | "x-powered-by"
@@ -595,42 +1344,271 @@ impl RequestHandler {
                 }
             }
         }
-
-        builder
-            .status(status)
-            .header("Content-Type", &content_type)
-            .header("Server", crate::SERVER_NAME)
-            .header("X-Powered-By", format!("VeloServe/{}", crate::VERSION))
-            .body(Full::new(Bytes::from(body.to_string())))
-            .map_err(|e| anyhow!("Failed to build response: {}", e))
+
+        if let Some((header, target)) = sendfile {
+            builder = builder.extension(Sendfile {
+                header,
+                target,
+                content_type: content_type_set.then(|| content_type.clone()),
+            });
+        }
+
+        builder
+            .status(status)
+            .header("Content-Type", &content_type)
+            .header("Server", crate::SERVER_NAME)
+            .header("X-Powered-By", format!("VeloServe/{}", crate::VERSION))
+            .body(Full::new(Bytes::from(body.to_string())))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Serve the file a script named with `X-Sendfile`/`X-Accel-Redirect`
+    ///
+    /// The file must lie inside the vhost's `sendfile_root`. It is served
+    /// like a static file (ranges, conditional requests) whatever the
+    /// request method, keeping the script's Content-Type, Content-Disposition,
+    /// cookies and caching headers.
+    async fn send_file(
+        &self,
+        req_parts: &hyper::http::request::Parts,
+        sendfile: Sendfile,
+        php: Response<Full<Bytes>>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let host = req_parts
+            .headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+        let vhost = self.compiled.find(host);
+        let root = vhost.and_then(|vhost| vhost.config.sendfile_root.as_deref());
+        let Some(root) = root.map(Path::new) else {
+            warn!(
+                "Ignoring {} from PHP: no sendfile_root for {}",
+                sendfile.header, host
+            );
+            return self.internal_error("File hand-off is not enabled for this site");
+        };
+
+        let file = if sendfile.header == "X-Sendfile" {
+            PathBuf::from(&sendfile.target)
+        } else {
+            let uri = sendfile.target.split('?').next().unwrap_or_default();
+            self.resolve_path(root, uri)
+        };
+        let inside = file
+            .canonicalize()
+            .ok()
+            .zip(root.canonicalize().ok())
+            .is_some_and(|(file, root)| file.starts_with(root));
+        if !inside || !file.is_file() {
+            warn!(
+                "{} target {} is missing or outside {}",
+                sendfile.header,
+                sendfile.target,
+                root.display()
+            );
+            return self.not_found();
+        }
+        debug!("Sending {} for PHP ({})", file.display(), sendfile.header);
+
+        let method = if req_parts.method == Method::HEAD {
+            Method::HEAD
+        } else {
+            Method::GET
+        };
+        // The script's own Content-Disposition is merged over this below
+        let mut static_handler = self
+            .static_handler_for(&method)
+            .with_zero_copy(req_parts.extensions.get::<ZeroCopySlot>().cloned());
+        if let Some(vhost) = vhost {
+            static_handler = static_handler.with_force_download(vhost.force_download.clone());
+        }
+        let mut response = match static_handler
+            .serve_conditional(&file, &req_parts.headers, false)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to send {}: {}", file.display(), e);
+                return self.internal_error("Failed to read file");
+            }
+        };
+
+        let multipart = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("multipart/"));
+        let headers = response.headers_mut();
+        for name in SENDFILE_MERGED_HEADERS {
+            if php.headers().contains_key(name) {
+                headers.remove(name);
+                for value in php.headers().get_all(name) {
+                    headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+        let content_type = sendfile
+            .content_type
+            .as_deref()
+            .and_then(|value| HeaderValue::from_str(value).ok());
+        if let (Some(content_type), false) = (content_type, multipart) {
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+        response.extensions_mut().insert(sendfile);
+        Ok(response)
     }
 
-    /// Serve a static file
-    async fn serve_static(
+    /// Serve a static file (using request parts)
+    async fn serve_static_parts(
         &self,
-        req: &Request<hyper::body::Incoming>,
+        req_parts: &hyper::http::request::Parts,
+        doc_root: &Path,
         path: &Path,
     ) -> Result<Response<Full<Bytes>>> {
         // Only GET and HEAD for static files
-        if req.method() != Method::GET && req.method() != Method::HEAD {
+        if req_parts.method != Method::GET && req_parts.method != Method::HEAD {
             return self.method_not_allowed();
         }
 
-        self.static_handler.serve(path).await
+        let host = req_parts
+            .headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+        let vhost = self.compiled.find(host);
+        let mut static_handler = self
+            .static_handler_for(&req_parts.method)
+            .with_zero_copy(req_parts.extensions.get::<ZeroCopySlot>().cloned());
+        if let Some(vhost) = vhost {
+            static_handler = static_handler
+                .with_cache_control(vhost.static_cache_control.clone())
+                .with_force_download(vhost.force_download.clone());
+        }
+
+        // A client with a current copy gets a 304 (and a failed
+        // precondition a 412) before any conversion
+        let mut response = if self.images.applies_to(path) {
+            let accept = req_parts
+                .headers
+                .get(ACCEPT)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("");
+            let variant = self.images.negotiated(path, accept).await;
+            match static_handler
+                .preconditions(
+                    path,
+                    &req_parts.headers,
+                    false,
+                    variant.map(|format| format.extension()),
+                )
+                .await?
+            {
+                Some(response) => response,
+                None => self.serve_image(req_parts, &static_handler, path).await?,
+            }
+        } else {
+            let precompressed = vhost
+                .and_then(|vhost| vhost.config.precompressed)
+                .unwrap_or(self.config.static_files.precompressed);
+            static_handler
+                .serve_conditional(path, &req_parts.headers, precompressed)
+                .await?
+        };
+        if let Some(policy) = self.upload_policy(req_parts, doc_root, path) {
+            apply_upload_policy(&mut response, policy);
+        }
+        Ok(response)
+    }
+
+    /// The static file handler, answering HEAD requests without reading files
+    fn static_handler_for(&self, method: &Method) -> StaticFileHandler {
+        self.static_handler
+            .clone()
+            .with_head(method == Method::HEAD)
     }
 
-    /// Serve a static file (using request parts)
-    async fn serve_static_parts(
+    /// Render an autoindex listing of `dir`
+    async fn directory_listing(
         &self,
         req_parts: &hyper::http::request::Parts,
-        path: &Path,
+        dir: &Path,
+        uri_path: &str,
+        symlink_policy: SymlinkPolicy,
     ) -> Result<Response<Full<Bytes>>> {
-        // Only GET and HEAD for static files
         if req_parts.method != Method::GET && req_parts.method != Method::HEAD {
             return self.method_not_allowed();
         }
 
-        self.static_handler.serve(path).await
+        let html = autoindex::render(dir, uri_path, symlink_policy).await?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CACHE_CONTROL, "no-cache")
+            .header("Server", crate::SERVER_NAME)
+            .body(Full::new(Bytes::from(html)))
+            .map_err(|e| anyhow!("Failed to build directory listing: {}", e))
+    }
+
+    /// Serve an image, converted to a format the client accepts when possible
+    async fn serve_image(
+        &self,
+        req_parts: &hyper::http::request::Parts,
+        static_handler: &StaticFileHandler,
+        path: &Path,
+    ) -> Result<Response<Full<Bytes>>> {
+        let accept = req_parts
+            .headers
+            .get(ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("");
+        let mut response = match self.images.variant(path, accept).await {
+            Some(variant) => {
+                static_handler
+                    .serve_variant(
+                        path,
+                        &variant.path,
+                        variant.format.mime_type(),
+                        variant.format.extension(),
+                        &req_parts.headers,
+                    )
+                    .await?
+            }
+            None => {
+                static_handler
+                    .serve(path, &req_parts.headers, false)
+                    .await?
+            }
+        };
+        response
+            .headers_mut()
+            .insert(VARY, HeaderValue::from_static("Accept, Accept-Encoding"));
+        Ok(response)
+    }
+
+    /// Upload protections for a file under `doc_root`
+    ///
+    /// Matched on the file's place in the document root rather than the
+    /// request URI, so encoded characters, doubled slashes and PATH_INFO
+    /// suffixes can't step around an upload location.
+    fn upload_policy(
+        &self,
+        req_parts: &hyper::http::request::Parts,
+        doc_root: &Path,
+        file: &Path,
+    ) -> Option<UploadPolicy> {
+        let host = req_parts
+            .headers
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("localhost");
+        let vhost = self.compiled.find(host)?;
+        let relative = file.strip_prefix(doc_root).ok()?;
+        let path: String = relative
+            .components()
+            .map(|component| format!("/{}", component.as_os_str().to_string_lossy()))
+            .collect();
+        vhost.upload_policy(&path)
     }
 
     /// Handle API requests
@@ -645,11 +1623,14 @@ impl RequestHandler {
             return self.api_status();
         }
         if method == Method::GET && path == "/api/v1/cache/stats" {
-            return self.api_cache_stats();
+            return self.api_cache_stats(&req);
         }
         if method == Method::GET && path == "/api/v1/cache/config" {
             return self.api_cache_config();
         }
+        if method == Method::GET && path == "/api/v1/cache/entry" {
+            return self.api_cache_entry(&req);
+        }
         if (method == Method::GET || method == Method::POST) && path == "/api/v1/cache/purge" {
             return self.api_cache_purge(&req).await;
         }
@@ -668,10 +1649,65 @@ impl RequestHandler {
         if method == Method::GET && path == "/api/v1/workers" {
             return self.api_workers();
         }
+        if method == Method::GET && path == "/api/v1/readonly" {
+            return self.json_response(self.readonly.status_json());
+        }
+        if method == Method::POST && path == "/api/v1/readonly" {
+            if let Some((status, reason)) = self.admin_rejection(&req) {
+                return self.json_error_response(status, reason, None);
+            }
+            return self.api_readonly(req).await;
+        }
+        if method == Method::GET && path == "/api/v1/state/export" {
+            if let Some((status, reason)) = self.admin_rejection(&req) {
+                return self.json_error_response(status, reason, None);
+            }
+            return self.api_state_export(&req);
+        }
+        if method == Method::POST && path == "/api/v1/state/import" {
+            if let Some((status, reason)) = self.admin_rejection(&req) {
+                return self.json_error_response(status, reason, None);
+            }
+            return self.api_state_import(req).await;
+        }
 
         self.not_found()
     }
 
+    /// Why `req` may not call an endpoint that changes server state (read-only
+    /// mode, state import), if it may not: those only answer loopback peers
+    /// that present `Authorization: Bearer <server.admin_token>`
+    fn admin_rejection(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Option<(StatusCode, &'static str)> {
+        let Some(token) = self.config.server.admin_token.as_deref() else {
+            return Some((
+                StatusCode::FORBIDDEN,
+                "set server.admin_token to enable this endpoint",
+            ));
+        };
+        // The peer, not the client: a forwarded request came from elsewhere
+        let loopback = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .is_some_and(|conn| {
+                !conn.is_forwarded() && conn.peer.ip().to_canonical().is_loopback()
+            });
+        if !loopback {
+            return Some((StatusCode::FORBIDDEN, "only available from loopback"));
+        }
+        let presented = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !presented.is_some_and(|presented| tokens_match(presented.trim(), token.trim())) {
+            return Some((StatusCode::UNAUTHORIZED, "missing or wrong admin token"));
+        }
+        None
+    }
+
     /// API: Server status
     fn api_status(&self) -> Result<Response<Full<Bytes>>> {
         let status = serde_json::json!({
@@ -680,41 +1716,218 @@ impl RequestHandler {
             "server": crate::SERVER_NAME,
             "php_available": self.php_pool.is_available(),
             "cache_enabled": self.config.cache.enable,
+            "readonly": self.readonly.status_json(),
+            "cache_schedule": self.scheduler.status_json(),
+            "degraded_docroots": self.docroots.status_json(),
+            "requests_total": self.metrics.requests_total(),
+            "responses": self.metrics.responses_json(),
         });
 
         self.json_response(status)
     }
 
-    /// API: Cache statistics
-    fn api_cache_stats(&self) -> Result<Response<Full<Bytes>>> {
+    /// API: Toggle read-only mode (`{"enabled": true, "vhost": "example.com", "duration_secs": 600}`)
+    async fn api_readonly(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let body = req.into_body().collect().await?.to_bytes();
+        let toggle: ReadOnlyToggle = match serde_json::from_slice(&body) {
+            Ok(toggle) => toggle,
+            Err(err) => {
+                return self.json_error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!(
+                    "invalid read-only payload: {}. expected JSON with enabled/vhost/duration_secs",
+                    err
+                ),
+                    None,
+                )
+            }
+        };
+
+        let vhost = toggle.vhost.as_deref();
+        if toggle.enabled {
+            self.readonly
+                .enable(vhost, toggle.duration_secs.map(Duration::from_secs));
+            warn!(
+                "Read-only mode enabled for {} (duration: {})",
+                vhost.unwrap_or("all sites"),
+                toggle
+                    .duration_secs
+                    .map_or("until disabled".to_string(), |secs| format!("{}s", secs))
+            );
+        } else {
+            self.readonly.disable(vhost);
+            info!(
+                "Read-only mode disabled for {}",
+                vhost.unwrap_or("all sites")
+            );
+        }
+
+        self.json_response(serde_json::json!({
+            "success": true,
+            "readonly": self.readonly.status_json(),
+        }))
+    }
+
+    /// API: Snapshot runtime state (`?bodies=1` includes cached bodies, `?max_bytes=` caps the size)
+    fn api_state_export(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let query = req.uri().query().unwrap_or("");
+        let include_bodies = matches!(
+            self.query_param(query, "bodies").as_deref(),
+            Some("1" | "true" | "yes")
+        );
+        let max_bytes = match self.query_param(query, "max_bytes") {
+            Some(raw) => match raw.parse::<usize>() {
+                Ok(limit) => limit.min(MAX_SNAPSHOT_BYTES),
+                Err(_) => {
+                    return self.json_error_response(
+                        StatusCode::BAD_REQUEST,
+                        "max_bytes must be a byte count",
+                        None,
+                    )
+                }
+            },
+            None => MAX_SNAPSHOT_BYTES,
+        };
+
+        let snapshot = match export_state(
+            self.runtime_state(),
+            ExportOptions {
+                include_bodies,
+                max_bytes,
+            },
+        ) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                return self.json_error_response(
+                    StatusCode::INSUFFICIENT_STORAGE,
+                    &err.to_string(),
+                    None,
+                )
+            }
+        };
+        info!(
+            "Exported runtime state ({} bytes, bodies: {})",
+            snapshot.len(),
+            include_bodies
+        );
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/octet-stream")
+            .header("Server", crate::SERVER_NAME)
+            .body(Full::new(Bytes::from(snapshot)))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// The state `/api/v1/state/*` snapshots
+    fn runtime_state(&self) -> RuntimeState<'_> {
+        RuntimeState {
+            cache: &self.cache,
+            invalidations: Some(&self.invalidations),
+            limits: Some(&self.limits),
+        }
+    }
+
+    /// API: Load a snapshot from `/api/v1/state/export`; index-only entries are re-warmed
+    async fn api_state_import(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let body = match Limited::new(req.into_body(), MAX_SNAPSHOT_BYTES)
+            .collect()
+            .await
+        {
+            Ok(collected) => collected.to_bytes(),
+            Err(err) => {
+                return self.json_error_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    &format!("failed to read state snapshot: {}", err),
+                    None,
+                )
+            }
+        };
+
+        let report = match import_state(self.runtime_state(), &body).await {
+            Ok(report) => report,
+            Err(err) => {
+                return self.json_error_response(StatusCode::BAD_REQUEST, &err.to_string(), None)
+            }
+        };
+        for skipped in &report.skipped {
+            warn!(
+                "Skipped state section {} (version {}): {}",
+                skipped.id, skipped.version, skipped.reason
+            );
+        }
+
+        let mut payload = report.to_json();
+        if !report.warm_urls.is_empty() {
+            let warm = self
+                .warmer
+                .enqueue_from_payload(WarmRequestPayload {
+                    urls: report.warm_urls.clone(),
+                    domain: None,
+                    trigger: Some("state-import".to_string()),
+                    strategy: Some("urls".to_string()),
+                })
+                .await?;
+            payload["warm"] = warm;
+        }
+        info!(
+            "Imported runtime state: {} entries restored, {} queued for warming",
+            report.restored,
+            report.warm_urls.len()
+        );
+
+        payload["success"] = serde_json::Value::Bool(true);
+        self.json_response(payload)
+    }
+
+    /// API: Cache statistics; `?domain=` narrows the host and tag breakdown
+    /// to one site
+    fn api_cache_stats(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let domain = self.query_param(req.uri().query().unwrap_or(""), "domain");
         self.json_response(serde_json::json!({
-            "cache": self.cache.stats(),
+            "cache": self.cache.detailed_stats(domain.as_deref()),
             "warming": self.warmer.stats_json()
         }))
     }
 
-    /// API: Cache configuration
+    /// API: Cache configuration, with each vhost's effective page cache
+    /// settings (the global switch and default TTL applied)
     fn api_cache_config(&self) -> Result<Response<Full<Bytes>>> {
         let vhosts: Vec<serde_json::Value> = self
-            .config
-            .virtualhost
+            .compiled
+            .vhosts()
             .iter()
             .map(|vhost| {
-                let (enabled, ttl, exclude) = if let Some(cache) = &vhost.cache {
-                    (cache.enable, cache.ttl, cache.exclude.clone())
-                } else {
-                    (
-                        self.config.cache.enable,
-                        self.config.cache.default_ttl,
-                        Vec::<String>::new(),
-                    )
-                };
+                let cache = vhost.config.cache.as_ref();
+                let ttl = vhost
+                    .cache_ttl
+                    .unwrap_or(Duration::from_secs(self.config.cache.default_ttl));
 
                 serde_json::json!({
-                    "domain": vhost.domain,
-                    "cache_enabled": enabled,
-                    "ttl": ttl,
-                    "exclude": exclude,
+                    "domain": vhost.config.domain,
+                    "cache_enabled": self.config.cache.enable && vhost.cache_enabled,
+                    "ttl": ttl.as_secs(),
+                    "ttl_source": if vhost.cache_ttl.is_some() { "vhost" } else { "default" },
+                    "stale_grace": vhost
+                        .cache_stale_grace
+                        .unwrap_or(Duration::from_secs(self.config.cache.stale_grace))
+                        .as_secs(),
+                    "exclude": cache.map(|c| c.exclude.clone()).unwrap_or_default(),
+                    "exclude_types": vhost.cache_exclude_types.as_slice(),
+                    "query_allow": vhost.cache_query_allow.as_slice(),
+                    "vary": cache.map(|c| c.vary.clone()).unwrap_or_default(),
                 })
             })
             .collect();
@@ -727,6 +1940,7 @@ impl RequestHandler {
                 "storage": self.config.cache.storage,
                 "memory_limit": self.config.cache.memory_limit,
                 "default_ttl": self.config.cache.default_ttl,
+                "negative_ttl": self.config.cache.negative_ttl,
                 "disk_path": self.config.cache.disk_path,
                 "redis_url": self.config.cache.redis_url,
             },
@@ -734,6 +1948,62 @@ impl RequestHandler {
         }))
     }
 
+    /// API: Inspect a single cache entry (by `key`, or `domain` + `path` with
+    /// optional `site`/`store`/`variant` scope)
+    fn api_cache_entry(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let query = req.uri().query().unwrap_or("");
+        let key = match (
+            self.query_param(query, "key"),
+            self.query_param(query, "domain"),
+            self.query_param(query, "path"),
+        ) {
+            (Some(key), _, _) => key,
+            (None, Some(domain), Some(path)) => build_page_cache_key_scoped(
+                &domain,
+                self.query_param(query, "site").as_deref(),
+                self.query_param(query, "store").as_deref(),
+                self.query_param(query, "variant").as_deref(),
+                &path,
+            ),
+            _ => {
+                return self.json_error_response(
+                    StatusCode::BAD_REQUEST,
+                    "expected `key` or `domain` and `path` query parameters",
+                    None,
+                )
+            }
+        };
+
+        let Some(entry) = self.cache.inspect(&key) else {
+            return self.json_error_response(
+                StatusCode::NOT_FOUND,
+                &format!("no cache entry for key: {}", key),
+                None,
+            );
+        };
+
+        self.json_response(serde_json::json!({
+            "key": key,
+            "format_version": ENTRY_FORMAT_VERSION,
+            "status": entry.status,
+            "headers": entry.headers,
+            "validators": entry.validators,
+            "vary": entry.vary,
+            "tags": entry.tags,
+            "stored_at": entry.stored_at,
+            "age": entry.age_seconds(),
+            "ttl": entry.ttl.as_secs(),
+            "stale_after": entry.stale_after.as_secs(),
+            "expired": entry.is_expired(),
+            "stale": entry.is_stale(),
+            "body_bytes": entry.body.len(),
+            "size_bytes": entry.size_bytes(),
+        }))
+    }
+
     /// API: Purge cache
     async fn api_cache_purge(
         &self,
@@ -744,19 +2014,55 @@ impl RequestHandler {
         let domain = self.query_param(query, "domain");
         let key = self.query_param(query, "key");
         let path = self.query_param(query, "path");
+        let url = self.query_param(query, "url");
+        let prefix = self.query_param(query, "prefix");
+
+        // `url` and `prefix` name the host themselves, or take `domain`
+        let target = match url.as_deref().or(prefix.as_deref()) {
+            Some(target) => match split_purge_url(target, domain.as_deref()) {
+                Some(target) => Some(target),
+                None => {
+                    return self.json_error_response(
+                        StatusCode::BAD_REQUEST,
+                        "url and prefix need an absolute URL or a path with domain",
+                        None,
+                    )
+                }
+            },
+            None => None,
+        };
+        let purge_domain = target
+            .as_ref()
+            .map(|(host, _)| host.as_str())
+            .or(domain.as_deref());
+        if self.purges_suspended(purge_domain) {
+            return self.purge_suspended_response(None);
+        }
 
         let message = if let Some(key) = key {
             self.cache.remove(&key).await;
             format!("Purged cache key: {}", key)
+        } else if let Some((host, path)) = target {
+            if url.is_some() {
+                let purged = self.cache.purge_url(&host, &path).await;
+                format!(
+                    "Purged page cache entries for URL: {}{} ({})",
+                    host, path, purged
+                )
+            } else {
+                let purged = self.cache.purge_url_prefix(&host, &path).await;
+                format!(
+                    "Purged page cache entries under: {}{} ({})",
+                    host, path, purged
+                )
+            }
         } else if let (Some(domain), Some(path)) = (domain.clone(), path) {
-            let base_key = build_page_cache_key(&domain, &path);
-            let key_prefix = format!("{}:", base_key);
-            let mut purged = self.cache.purge_by_prefix_count(&key_prefix).await;
-            purged += self.cache.remove_with_count(&base_key).await;
+            let purged = self.cache.purge_url(&domain, &path).await;
+            let key_prefix = format!("{}:", build_page_cache_key(&domain, &path));
             format!("Purged page cache entries: {} ({})", key_prefix, purged)
         } else if let Some(domain) = domain {
-            self.cache.purge_by_tag(&format!("domain:{}", domain)).await;
-            format!("Purged cache for domain: {}", domain)
+            let purged = self.cache.purge_by_domain(&domain).await;
+            format!("Purged cache for domain: {} ({})", domain, purged)
         } else if let Some(tag) = tag {
             self.cache.purge_by_tag(&tag).await;
             format!("Purged cache tag: {}", tag)
@@ -810,9 +2116,20 @@ impl RequestHandler {
                 Some(request_id),
             );
         }
+        if self.purges_suspended(invalidation.domain.as_deref()) {
+            return self.purge_suspended_response(Some(request_id));
+        }
 
         let dedupe_key = self.dedupe_key(&invalidation, &headers);
-        let guard_result = INVALIDATION_GUARD.evaluate(&dedupe_key);
+        let shared = self
+            .limits
+            .hit(
+                Limiter::Invalidation,
+                invalidation_guard::RATE_LIMIT as u64,
+                Duration::from_secs(invalidation_guard::RATE_WINDOW_SECS),
+            )
+            .await;
+        let guard_result = self.invalidations.evaluate(&dedupe_key, shared);
         match guard_result {
             InvalidationGuardResult::RateLimited => {
                 info!(
@@ -996,12 +2313,18 @@ impl RequestHandler {
         let l1_misses = cache_stats["l1"]["misses"].as_u64().unwrap_or(0);
         let l2_misses = cache_stats["l2"]["misses"].as_u64().unwrap_or(0);
         let metrics = serde_json::json!({
-            "requests_total": 0,
+            "requests_total": self.metrics.requests_total(),
+            "responses": self.metrics.responses_json(),
             "cache_hits": l1_hits + l2_hits,
             "cache_misses": l1_misses + l2_misses,
             "cache_hit_rate": cache_stats["hit_rate"],
             "php_available": self.php_pool.is_available(),
             "cache_warming": self.warmer.stats_json(),
+            "image_optimization": self.images.stats_json(),
+            "cache_schedule": self.scheduler.status_json(),
+            "limits": self.limits.stats_json(),
+            "panics_total": panics::panics_total(),
+            "connections": self.connections.stats_json(),
         });
 
         self.json_response(metrics)
@@ -1023,25 +2346,16 @@ impl RequestHandler {
     }
 
     /// Find virtual host for request
-    fn find_vhost(
-        &self,
-        req: &Request<hyper::body::Incoming>,
-    ) -> (PathBuf, Option<&crate::config::VirtualHostConfig>) {
-        let host = req
-            .headers()
+    fn find_vhost(&self, req: &Request<hyper::body::Incoming>) -> Option<&CompiledVhost> {
+        self.compiled.find(self.request_host(req))
+    }
+
+    /// `Host` header value, or "localhost" when missing
+    fn request_host<'r>(&self, req: &'r Request<hyper::body::Incoming>) -> &'r str {
+        req.headers()
             .get("host")
             .and_then(|h| h.to_str().ok())
-            .unwrap_or("localhost");
-
-        let host = host.split(':').next().unwrap_or(host);
-
-        for vhost in &self.config.virtualhost {
-            if vhost.domain == host || vhost.domain == "*" {
-                return (PathBuf::from(&vhost.root), Some(vhost));
-            }
-        }
-
-        (PathBuf::from("/var/www/html"), None)
+            .unwrap_or("localhost")
     }
 
     /// Resolve path to file system path (with security checks)
@@ -1051,18 +2365,23 @@ impl RequestHandler {
             .decode_utf8_lossy()
             .to_string();
 
-        // Security: prevent directory traversal
+        // Security: prevent directory traversal. Only plain names survive, so
+        // neither `..` nor a decoded leading `/` (`%2F`) can leave `doc_root`
         let path = PathBuf::from(&decoded);
         let normalized: PathBuf = path
             .components()
-            .filter(|c| !matches!(c, std::path::Component::ParentDir))
+            .filter(|c| matches!(c, std::path::Component::Normal(_)))
             .collect();
 
         doc_root.join(normalized)
     }
 
     /// Generate cache key for request
-    fn cache_key(&self, req: &Request<hyper::body::Incoming>) -> String {
+    fn cache_key(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        vhost: Option<&CompiledVhost>,
+    ) -> String {
         let host = req
             .headers()
             .get("host")
@@ -1078,7 +2397,7 @@ impl RequestHandler {
             host,
             self.cache_site(req).as_deref(),
             self.cache_store(req).as_deref(),
-            self.cache_variant(req).as_deref(),
+            self.cache_variant(req, vhost).as_deref(),
             path,
         )
     }
@@ -1103,14 +2422,24 @@ impl RequestHandler {
             .map(|value| value.to_ascii_lowercase())
     }
 
-    fn cache_variant(&self, req: &Request<hyper::body::Incoming>) -> Option<String> {
-        req.headers()
+    fn cache_variant(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        vhost: Option<&CompiledVhost>,
+    ) -> Option<String> {
+        let base = req
+            .headers()
             .get("x-veloserve-cache-variant")
             .or_else(|| req.headers().get("accept-language"))
             .and_then(|value| value.to_str().ok())
             .map(str::trim)
             .filter(|value| !value.is_empty())
-            .map(|value| value.to_ascii_lowercase())
+            .map(|value| value.to_ascii_lowercase());
+
+        match vhost {
+            Some(vhost) => vhost.cache_vary.variant(req.headers(), base),
+            None => base,
+        }
     }
 
     fn query_param(&self, query: &str, key: &str) -> Option<String> {
@@ -1170,7 +2499,73 @@ impl RequestHandler {
             }
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    /// Purges are skipped while read-only mode covers the target, so a
+    /// compromised or broken backend cannot empty the cache we are serving from
+    fn purges_suspended(&self, domain: Option<&str>) -> bool {
+        match domain {
+            Some(domain) => self.readonly.is_active(domain),
+            None => self.readonly.is_global(),
+        }
+    }
+
+    /// Whether the client is in `allow` or is the server itself
+    fn client_allowed<B>(&self, req: &Request<B>, allow: &TrustedProxies) -> bool {
+        req.extensions()
+            .get::<ConnectionInfo>()
+            .is_some_and(|conn| {
+                let client = conn.client.to_canonical();
+                allow.contains(client)
+                    || conn
+                        .local
+                        .is_some_and(|local| local.ip().to_canonical() == client)
+            })
+    }
+
+    /// `PURGE <path>` from an allowed client: drop the page a GET of the same
+    /// host and path would be served from, along with its variants
+    async fn purge_request(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        if !self.client_allowed(req, &self.compiled.purge_allow) {
+            info!(
+                "Refused PURGE {} (client not in cache.purge_allow)",
+                req.uri()
+            );
+            return self.method_not_allowed();
+        }
+
+        let host = self.request_host(req);
+        if self.purges_suspended(Some(host)) {
+            return self.purge_suspended_response(None);
+        }
+        let path = req
+            .uri()
+            .path_and_query()
+            .map_or(req.uri().path(), |pq| pq.as_str());
+        let purged = self.cache.purge_url(host, path).await;
+        debug!("PURGE {} {} removed {} entries", host, path, purged);
+
+        self.json_response(serde_json::json!({
+            "success": true,
+            "purged": purged > 0,
+            "entries": purged,
+            "key": build_page_cache_key(host, path),
+        }))
+    }
+
+    fn purge_suspended_response(
+        &self,
+        request_id: Option<String>,
+    ) -> Result<Response<Full<Bytes>>> {
+        self.json_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "cache purges are suspended while read-only mode is active",
+            request_id,
+        )
     }
 
     fn normalize_and_validate_invalidation(&self, req: &mut InvalidationRequest) -> Result<()> {
@@ -1310,10 +2705,19 @@ impl RequestHandler {
         &self,
         req: &Request<hyper::body::Incoming>,
         path: &str,
-        vhost: Option<&crate::config::VirtualHostConfig>,
-    ) -> Option<CacheContext> {
-        if !self.config.cache.enable || !self.is_cacheable_request(req, path, vhost) {
-            return None;
+        vhost: Option<&CompiledVhost>,
+    ) -> Result<CacheContext, &'static str> {
+        if !self.config.cache.enable {
+            return Err("cache disabled");
+        }
+        if let Some(reason) = self.cache_bypass_reason(req, path, vhost) {
+            debug!(
+                "Page cache bypass for {} {}: {}",
+                req.method(),
+                path,
+                reason
+            );
+            return Err(reason);
         }
 
         let host = req
@@ -1321,48 +2725,68 @@ impl RequestHandler {
             .get("host")
             .and_then(|h| h.to_str().ok())
             .unwrap_or("localhost");
-        let host = host.split(':').next().unwrap_or(host).to_string();
+        let host = normalize_host(host);
+        let url = format!(
+            "http://{}{}",
+            host,
+            req.uri()
+                .path_and_query()
+                .map_or(req.uri().path(), |pq| pq.as_str())
+        );
 
         let ttl = vhost
-            .and_then(|v| v.cache.as_ref().map(|c| c.ttl))
-            .unwrap_or(self.config.cache.default_ttl);
+            .and_then(|v| v.cache_ttl)
+            .unwrap_or(Duration::from_secs(self.config.cache.default_ttl));
 
-        Some(CacheContext {
-            key: self.cache_key(req),
+        Ok(CacheContext {
+            key: self.cache_key(req, vhost),
             domain: host,
             path: path.to_string(),
-            ttl: Duration::from_secs(ttl),
+            url,
+            ttl,
+            scheduled_ttl: self.scheduler.ttl_override(path),
+            stale_grace: vhost
+                .and_then(|v| v.cache_stale_grace)
+                .unwrap_or(Duration::from_secs(self.config.cache.stale_grace)),
+            exclude_types: vhost
+                .map(|v| v.cache_exclude_types.clone())
+                .unwrap_or_default(),
+            cookies: req.headers().contains_key(COOKIE),
         })
     }
 
-    fn is_cacheable_request(
+    /// Why a request skips the page cache, if it does
+    fn cache_bypass_reason(
         &self,
         req: &Request<hyper::body::Incoming>,
         path: &str,
-        vhost: Option<&crate::config::VirtualHostConfig>,
-    ) -> bool {
+        vhost: Option<&CompiledVhost>,
+    ) -> Option<&'static str> {
         if req.method() != Method::GET && req.method() != Method::HEAD {
-            return false;
-        }
-        if req.uri().query().is_some() {
-            return false;
-        }
-        if self.is_authenticated_request(req) {
-            return false;
+            return Some("method");
         }
-
         if let Some(vhost) = vhost {
-            if let Some(vhost_cache) = &vhost.cache {
-                if !vhost_cache.enable {
-                    return false;
-                }
-                if self.is_excluded_path(path, &vhost_cache.exclude) {
-                    return false;
-                }
+            if !vhost.cache_enabled {
+                return Some("cache disabled for vhost");
+            }
+            if vhost.cache_exclude.excludes(path, req.uri().query()) {
+                return Some("excluded path");
             }
         }
+        if let Some(query) = req.uri().query() {
+            let allowed = vhost.map_or(&[][..], |v| v.cache_query_allow.as_slice());
+            if !query_allowed(allowed, query) {
+                return Some("query string");
+            }
+        }
+        if vhost.is_some_and(|v| v.cache_bypass_cookies.matches(req.headers())) {
+            return Some("cookie");
+        }
+        if self.is_authenticated_request(req) {
+            return Some("authenticated");
+        }
 
-        true
+        None
     }
 
     fn is_authenticated_request(&self, req: &Request<hyper::body::Incoming>) -> bool {
@@ -1382,72 +2806,96 @@ impl RequestHandler {
             || cookie.contains("woocommerce")
     }
 
-    fn is_excluded_path(&self, path: &str, rules: &[String]) -> bool {
-        rules.iter().any(|rule| {
-            if let Some(prefix) = rule.strip_suffix('*') {
-                path.starts_with(prefix)
-            } else {
-                path == rule || path.starts_with(&format!("{}/", rule.trim_end_matches('/')))
-            }
-        })
-    }
-
     fn cached_response(
         &self,
         method: &Method,
-        body: &[u8],
-        content_type: &str,
+        entry: &CachedResponse,
+        cache_status: &'static str,
     ) -> Result<Response<Full<Bytes>>> {
-        let mut builder = Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_TYPE, content_type)
-            .header("Server", crate::SERVER_NAME)
-            .header("X-Powered-By", format!("VeloServe/{}", crate::VERSION))
-            .header("X-Cache", "HIT");
+        let mut builder = Response::builder().status(entry.status);
+        for (name, value) in &entry.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        if entry.header("server").is_none() {
+            builder = builder.header("Server", crate::SERVER_NAME);
+        }
+        builder = builder.header("X-Cache", cache_status);
+        if self.config.cache.debug_headers {
+            let age = entry.age_seconds();
+            let ttl = entry.stale_after.as_secs().saturating_sub(age);
+            builder = builder
+                .header(AGE, age.to_string())
+                .header(X_CACHE_TTL, ttl.to_string());
+        }
+        if cache_status == "STALE" {
+            builder = builder.header(WARNING, "110 - \"Response is Stale\"");
+        }
+        if entry.tags.iter().any(|tag| tag == BUILTIN_ERROR_TAG) {
+            builder = builder.extension(BuiltinError);
+        }
 
         if method == Method::HEAD {
-            builder = builder.header(CONTENT_LENGTH, body.len().to_string());
+            builder = builder.header(CONTENT_LENGTH, entry.body.len().to_string());
             return builder
                 .body(Full::new(Bytes::new()))
                 .map_err(|e| anyhow!("Failed to build cached HEAD response: {}", e));
         }
 
         builder
-            .body(Full::new(Bytes::from(body.to_vec())))
+            .body(Full::new(entry.body.clone()))
             .map_err(|e| anyhow!("Failed to build cached response: {}", e))
     }
 
     async fn finalize_response(
         &self,
-        response: Response<Full<Bytes>>,
-        cache_context: Option<&CacheContext>,
+        mut response: Response<Full<Bytes>>,
+        cache: Result<&CacheContext, &'static str>,
         method: &Method,
     ) -> Result<Response<Full<Bytes>>> {
-        let Some(context) = cache_context else {
-            return Ok(response);
+        let context = match cache {
+            Ok(context) => context,
+            Err(reason) => return Ok(self.cache_bypassed(response, Some(reason))),
         };
 
-        if method != Method::GET {
-            return Ok(response);
-        }
-
-        if response.status() != StatusCode::OK {
-            return Ok(response);
+        // 404s are kept for cache.negative_ttl, so scans for missing URLs
+        // don't all reach the front controller
+        let negative = response.status() == StatusCode::NOT_FOUND
+            && self.config.cache.negative_ttl > 0
+            && !context.cookies;
+        if let Some(reason) = uncacheable_response(&response, method, negative) {
+            debug!("Not caching {}: {}", context.key, reason);
+            return Ok(self.cache_bypassed(response, None));
         }
-
-        if response.headers().contains_key(SET_COOKIE) {
-            return Ok(response);
+        if negative
+            && self
+                .cache
+                .live_tagged(NEGATIVE_TAG, MAX_NEGATIVE_ENTRIES)
+                .await
+                >= MAX_NEGATIVE_ENTRIES
+        {
+            debug!("Not caching {}: negative entry limit", context.key);
+            return Ok(self.cache_bypassed(response, None));
         }
 
-        let cache_control = response
-            .headers()
-            .get(CACHE_CONTROL)
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("")
-            .to_ascii_lowercase();
-        if cache_control.contains("no-store") || cache_control.contains("private") {
-            return Ok(response);
-        }
+        // PHP says how long its pages keep (X-LiteSpeed-Cache-Control first);
+        // static HTML is revalidated by browsers (max-age=0) but keeps the
+        // vhost TTL here
+        let litespeed = response.extensions_mut().remove::<LiteSpeedCache>();
+        let ttl = match litespeed.as_ref().and_then(|ls| ls.directive) {
+            Some(CacheDirective::Bypass) => None,
+            // Front controllers mark their 404s no-cache
+            _ if negative => Some(Duration::from_secs(self.config.cache.negative_ttl)),
+            Some(CacheDirective::Store(ttl)) => Some(ttl.unwrap_or(context.ttl)),
+            None if response.extensions().get::<UpstreamTime>().is_some() => {
+                cache_control::response_ttl(response.headers(), context.ttl)
+            }
+            None => (!cache_control::is_private(response.headers())).then_some(context.ttl),
+        };
+        let Some(ttl) = ttl else {
+            debug!("Not caching {}: Cache-Control", context.key);
+            return Ok(self.cache_bypassed(response, None));
+        };
+        let ttl = context.scheduled_ttl.filter(|_| !negative).unwrap_or(ttl);
 
         let content_type = response
             .headers()
@@ -1455,36 +2903,197 @@ impl RequestHandler {
             .and_then(|h| h.to_str().ok())
             .unwrap_or("text/html; charset=utf-8")
             .to_string();
+        if content_type_matches(&context.exclude_types, &content_type) {
+            debug!("Not caching {}: excluded {}", context.key, content_type);
+            return Ok(self.cache_bypassed(response, None));
+        }
         if !content_type.to_ascii_lowercase().starts_with("text/html") {
-            return Ok(response);
+            debug!("Not caching {}: {}", context.key, content_type);
+            return Ok(self.cache_bypassed(response, None));
         }
 
         let (parts, body) = response.into_parts();
         let body = body.collect().await?.to_bytes();
-        let body_vec = body.to_vec();
 
-        self.cache
-            .set_with_ttl(
+        let headers = parts
+            .headers
+            .iter()
+            .filter(|(name, _)| !UNCACHEABLE_RESPONSE_HEADERS.contains(&name.as_str()))
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+        let mut tags = vec![
+            format!("domain:{}", context.domain),
+            format!("path:{}{}", context.domain, context.path),
+        ];
+        tags.extend(
+            litespeed
+                .iter()
+                .flat_map(|ls| &ls.tags)
+                .map(|tag| litespeed::host_tag(&context.domain, tag)),
+        );
+        if negative {
+            tags.push(NEGATIVE_TAG.to_string());
+            tags.push(format!("{}:{}", NEGATIVE_TAG, context.domain));
+            if parts.extensions.get::<BuiltinError>().is_some() {
+                tags.push(BUILTIN_ERROR_TAG.to_string());
+            }
+        }
+        let entry = CachedResponse::new(parts.status.as_u16(), headers, body.clone())
+            .with_tags(tags)
+            .with_url(context.url.clone());
+        let stored = self
+            .cache
+            .set_response(
                 &context.key,
-                body_vec,
-                &content_type,
-                vec![
-                    format!("domain:{}", context.domain),
-                    format!("path:{}{}", context.domain, context.path),
-                ],
-                context.ttl,
+                entry,
+                CacheLifetime::with_grace(
+                    ttl,
+                    if negative {
+                        Duration::ZERO
+                    } else {
+                        context.stale_grace
+                    },
+                ),
             )
             .await;
 
         let mut response = Response::from_parts(parts, Full::new(body));
+        if stored == CacheStore::TooLarge {
+            response
+                .headers_mut()
+                .insert("X-Cache", HeaderValue::from_static("BYPASS(size)"));
+            return Ok(response);
+        }
         response
             .headers_mut()
             .insert("X-Cache", HeaderValue::from_static("MISS"));
+        if self.config.cache.debug_headers {
+            response
+                .headers_mut()
+                .insert(X_CACHE_TTL, HeaderValue::from(ttl.as_secs()));
+        }
         Ok(response)
     }
 
+    /// Mark a response the page cache had no part in; debug headers tell a
+    /// request skipped for its cookies apart from the others
+    fn cache_bypassed(
+        &self,
+        mut response: Response<Full<Bytes>>,
+        reason: Option<&'static str>,
+    ) -> Response<Full<Bytes>> {
+        if self.config.cache.enable {
+            let value = if self.config.cache.debug_headers && reason == Some("cookie") {
+                "BYPASS(cookie)"
+            } else {
+                "BYPASS"
+            };
+            response
+                .headers_mut()
+                .insert("X-Cache", HeaderValue::from_static(value));
+        }
+        response
+    }
+
     // === Response Helpers ===
 
+    /// A failing docroot: whatever the cache still has for the page, else 503
+    fn docroot_unavailable(
+        &self,
+        vhost: &CompiledVhost,
+        error: &std::io::Error,
+        cache_context: Option<&CacheContext>,
+        method: &Method,
+    ) -> Result<Response<Full<Bytes>>> {
+        self.docroots
+            .record_failure(&vhost.config.domain, &vhost.root, error);
+
+        let cached = cache_context.and_then(|context| {
+            self.cache
+                .inspect(&context.key)
+                .filter(|entry| context.serves(entry))
+        });
+        if let Some(entry) = cached {
+            let status = if entry.is_stale() { "STALE" } else { "HIT" };
+            return self.cached_response(method, &entry, status);
+        }
+
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header("Server", crate::SERVER_NAME)
+            .header("Retry-After", "10")
+            .body(Full::new(Bytes::from(
+                "Site temporarily unavailable, please retry",
+            )))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Turn a static file error into a 503 if the filesystem failed
+    fn static_error(
+        &self,
+        error: anyhow::Error,
+        vhost: Option<&CompiledVhost>,
+        cache_context: Option<&CacheContext>,
+        method: &Method,
+    ) -> Result<Response<Full<Bytes>>> {
+        match (docroot::filesystem_failure(&error), vhost) {
+            (Some(io_error), Some(vhost)) => {
+                self.docroot_unavailable(vhost, io_error, cache_context, method)
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn readonly_rejected(&self) -> Result<Response<Full<Bytes>>> {
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header("Server", crate::SERVER_NAME)
+            .header("Retry-After", "120")
+            .body(Full::new(Bytes::from(
+                self.config.server.readonly_message.clone(),
+            )))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    fn php_starting(&self) -> Result<Response<Full<Bytes>>> {
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header("Server", crate::SERVER_NAME)
+            .header("Retry-After", "1")
+            .body(Full::new(Bytes::from("PHP is starting, please retry")))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Readiness: 503 until every pool has finished starting
+    fn readiness_check(&self) -> Result<Response<Full<Bytes>>> {
+        let php = self.php_pool.state();
+        let ready = php != PoolState::Starting;
+        let body = serde_json::json!({
+            "ready": ready,
+            "php": php.as_str(),
+        });
+        let status = if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .header("Server", crate::SERVER_NAME)
+            .body(Full::new(Bytes::from(body.to_string())))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
     fn health_check(&self) -> Result<Response<Full<Bytes>>> {
         Response::builder()
             .status(StatusCode::OK)
@@ -1494,6 +3103,85 @@ impl RequestHandler {
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
 
+    /// Counters and histograms in the Prometheus text format
+    fn prometheus_metrics(&self) -> Result<Response<Full<Bytes>>> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .header("Server", crate::SERVER_NAME)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Full::new(Bytes::from(self.metrics.render_prometheus())))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Serve the vhost's page for a built-in error, keeping the error status
+    ///
+    /// The page is served from the docroot like any file (PHP pages run as
+    /// a GET for the page's own path), but never goes through error pages
+    /// again: if it is missing or fails, the built-in page is sent instead.
+    async fn error_page(
+        &self,
+        vhost: &CompiledVhost,
+        method: &Method,
+        headers: HeaderMap,
+        builtin: Response<Full<Bytes>>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let status = builtin.status();
+        let Some(page) = vhost.config.error_pages.get(&status.as_u16()) else {
+            return Ok(builtin);
+        };
+        let file = self.resolve_path(&vhost.root, page);
+        if !file.is_file() {
+            warn!(
+                "Error page {} for {} in {} is missing, using the built-in page",
+                page, status, vhost.config.domain
+            );
+            return Ok(builtin);
+        }
+
+        let method = if *method == Method::HEAD {
+            Method::HEAD
+        } else {
+            Method::GET
+        };
+        let (mut parts, ()) = Request::builder()
+            .method(method)
+            .uri(page.as_str())
+            .body(())?
+            .into_parts();
+        parts.headers = headers;
+        // Ranges and validators were meant for the original resource
+        for name in [RANGE, IF_RANGE, IF_NONE_MATCH, IF_MODIFIED_SINCE] {
+            parts.headers.remove(name);
+        }
+
+        let served = if self.is_php_file(&file) {
+            self.execute_php(&parts, &vhost.root, &file, page, "", Vec::new())
+                .await
+        } else {
+            self.static_handler_for(&parts.method)
+                .serve(&file, &parts.headers, false)
+                .await
+        };
+        let mut response = match served {
+            Ok(response) if response.extensions().get::<BuiltinError>().is_none() => response,
+            Ok(_) => return Ok(builtin),
+            Err(e) => {
+                warn!(
+                    "Error page {} for {} in {} failed, using the built-in page: {}",
+                    page, status, vhost.config.domain, e
+                );
+                return Ok(builtin);
+            }
+        };
+
+        *response.status_mut() = status;
+        for name in [ETAG, LAST_MODIFIED, ACCEPT_RANGES] {
+            response.headers_mut().remove(name);
+        }
+        Ok(response)
+    }
+
     fn not_found(&self) -> Result<Response<Full<Bytes>>> {
         let body = r#"<!DOCTYPE html>
 <html>
@@ -1510,6 +3198,7 @@ impl RequestHandler {
             .status(StatusCode::NOT_FOUND)
             .header("Content-Type", "text/html; charset=utf-8")
             .header("Server", crate::SERVER_NAME)
+            .extension(BuiltinError)
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
@@ -1533,10 +3222,138 @@ impl RequestHandler {
             .status(StatusCode::FORBIDDEN)
             .header("Content-Type", "text/html; charset=utf-8")
             .header("Server", crate::SERVER_NAME)
+            .extension(BuiltinError)
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
 
+    /// `301` to the trailing-slash URL of a directory, keeping the query
+    ///
+    /// The Location is absolute when the request named a usable host, with
+    /// the scheme of the connection it arrived on.
+    fn directory_redirect(
+        &self,
+        parts: &hyper::http::request::Parts,
+    ) -> Result<Response<Full<Bytes>>> {
+        let mut target = format!("{}/", parts.uri.path());
+        if let Some(query) = parts.uri.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+
+        let host = parts
+            .uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| parts.headers.get(HOST).and_then(|h| h.to_str().ok()))
+            .filter(|host| is_redirect_host(host));
+        let location = match host {
+            Some(host) => {
+                let scheme = parts
+                    .extensions
+                    .get::<ConnectionInfo>()
+                    .map_or("http", ConnectionInfo::scheme);
+                format!("{}://{}{}", scheme, host, target)
+            }
+            None => target,
+        };
+        debug!("Redirecting directory {} to {}", parts.uri.path(), location);
+
+        Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header("Location", location)
+            .header("Content-Type", "text/plain")
+            .header("Server", crate::SERVER_NAME)
+            .body(Full::new(Bytes::from("Moved Permanently")))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Whether `req` came over plain HTTP to a site that wants HTTPS
+    fn redirects_to_https(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+        vhost: Option<&CompiledVhost>,
+    ) -> bool {
+        let enabled = vhost
+            .and_then(|vhost| vhost.config.redirect_to_https)
+            .unwrap_or(self.compiled.config.server.redirect_to_https);
+        enabled
+            && !req
+                .extensions()
+                .get::<ConnectionInfo>()
+                .is_some_and(|conn| conn.https)
+    }
+
+    /// 301 to the same host, path and query over HTTPS
+    fn https_redirect(
+        &self,
+        req: &Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let host = req
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| req.headers().get(HOST).and_then(|h| h.to_str().ok()))
+            .filter(|host| is_redirect_host(host));
+        let Some(host) = host else {
+            return self.error_response(StatusCode::BAD_REQUEST, "Missing or invalid Host header");
+        };
+        let port = self
+            .compiled
+            .https_port
+            .map(|port| format!(":{}", port))
+            .unwrap_or_default();
+        let target = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+        let location = format!("https://{}{}{}", strip_port(host), port, target);
+        debug!("Redirecting {} to {}", req.uri(), location);
+
+        Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header("Location", location)
+            .header("Content-Type", "text/plain")
+            .header("Server", crate::SERVER_NAME)
+            .body(Full::new(Bytes::from("Moved Permanently")))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Answer a `redirect` / `permanent` rewrite rule
+    fn rewrite_redirect(
+        &self,
+        status: StatusCode,
+        location: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        Response::builder()
+            .status(status)
+            .header("Location", location)
+            .header("Content-Type", "text/plain")
+            .header("Server", crate::SERVER_NAME)
+            .body(Full::new(Bytes::from(
+                status.canonical_reason().unwrap_or_default(),
+            )))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Built-in response for a `try_files` `=code`
+    fn status_response(&self, status: StatusCode) -> Result<Response<Full<Bytes>>> {
+        match status {
+            StatusCode::NOT_FOUND => self.not_found(),
+            StatusCode::FORBIDDEN => self.forbidden("Access denied"),
+            StatusCode::INTERNAL_SERVER_ERROR => self.internal_error("Internal server error"),
+            _ => Response::builder()
+                .status(status)
+                .header("Content-Type", "text/plain")
+                .header("Server", crate::SERVER_NAME)
+                .extension(BuiltinError)
+                .body(Full::new(Bytes::from(
+                    status.canonical_reason().unwrap_or_default(),
+                )))
+                .map_err(|e| anyhow!("Failed to build response: {}", e)),
+        }
+    }
+
     fn method_not_allowed(&self) -> Result<Response<Full<Bytes>>> {
         Response::builder()
             .status(StatusCode::METHOD_NOT_ALLOWED)
@@ -1547,6 +3364,35 @@ impl RequestHandler {
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
 
+    /// Built-in HTML page for any error status
+    fn error_response(&self, status: StatusCode, message: &str) -> Result<Response<Full<Bytes>>> {
+        let title = format!(
+            "{} {}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or_default()
+        );
+        let body = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><title>{title}</title></head>
+<body>
+<h1>{title}</h1>
+<p>{message}</p>
+<hr>
+<p><em>VeloServe</em></p>
+</body>
+</html>"#
+        );
+
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .header("Server", crate::SERVER_NAME)
+            .extension(BuiltinError)
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
     fn internal_error(&self, message: &str) -> Result<Response<Full<Bytes>>> {
         let body = format!(
             r#"<!DOCTYPE html>
@@ -1566,6 +3412,7 @@ impl RequestHandler {
             .status(StatusCode::INTERNAL_SERVER_ERROR)
             .header("Content-Type", "text/html; charset=utf-8")
             .header("Server", crate::SERVER_NAME)
+            .extension(BuiltinError)
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| anyhow!("Failed to build response: {}", e))
     }
@@ -1606,6 +3453,134 @@ impl RequestHandler {
     }
 }
 
+/// Why a response can't be stored in the page cache, if it can't, with
+/// `negative` letting a 404 in
+fn uncacheable_response(
+    response: &Response<Full<Bytes>>,
+    method: &Method,
+    negative: bool,
+) -> Option<&'static str> {
+    // Streamed bodies and files handed off by PHP are never stored
+    if response.extensions().get::<StreamingBody>().is_some() {
+        return Some("streamed body");
+    }
+    if response.extensions().get::<Sendfile>().is_some() {
+        return Some("sendfile");
+    }
+    if method != Method::GET {
+        return Some("method");
+    }
+    if response.status() != StatusCode::OK
+        && !(negative && response.status() == StatusCode::NOT_FOUND)
+    {
+        return Some("status");
+    }
+    if response.headers().contains_key(SET_COOKIE) {
+        return Some("Set-Cookie");
+    }
+    // Entries aren't keyed by Accept-Encoding, so encoded bodies stay out
+    if response.headers().contains_key(CONTENT_ENCODING) {
+        return Some("Content-Encoding");
+    }
+    None
+}
+
+/// Compare admin tokens without stopping at the first differing byte
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Host and path (with query) of a purge target: an absolute URL, or a path
+/// on `domain`
+fn split_purge_url(target: &str, domain: Option<&str>) -> Option<(String, String)> {
+    if target.starts_with('/') {
+        return domain.map(|domain| (domain.to_string(), target.to_string()));
+    }
+    let uri: Uri = target.parse().ok()?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) {
+        return None;
+    }
+    let host = uri.host()?.to_string();
+    let path = uri
+        .path_and_query()
+        .map_or_else(|| "/".to_string(), |pq| pq.as_str().to_string());
+    Some((host, path))
+}
+
+/// Keep browsers from rendering uploaded files as pages on the site origin
+fn apply_upload_policy(response: &mut Response<Full<Bytes>>, policy: UploadPolicy) {
+    let headers = response.headers_mut();
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+    let risky = headers
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(|content_type| {
+            let essence = content_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase();
+            RISKY_UPLOAD_TYPES.contains(&essence.as_str()) || essence.ends_with("+xml")
+        })
+        .unwrap_or(false);
+    if !risky {
+        return;
+    }
+
+    match policy {
+        UploadPolicy::Attachment => {
+            // A force_download disposition already names the file
+            if !headers.contains_key(CONTENT_DISPOSITION) {
+                headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+            }
+        }
+        UploadPolicy::Plain => {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+        }
+        UploadPolicy::Off => {}
+    }
+}
+
+/// True if `host` is a plain `host[:port]` that can go in a redirect
+///
+/// Anything else (userinfo, paths, stray characters) falls back to a
+/// relative Location rather than pointing the client somewhere odd.
+fn is_redirect_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b':' | b'[' | b']'))
+}
+
+/// `host` without a trailing `:port`; bracketed IPv6 addresses are kept whole
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    }
+}
+
+/// The canonical name of a PHP hand-off header, if `name` is one
+fn sendfile_header(name: &str) -> Option<&'static str> {
+    if name.eq_ignore_ascii_case("x-sendfile") {
+        Some("X-Sendfile")
+    } else if name.eq_ignore_ascii_case("x-accel-redirect") {
+        Some("X-Accel-Redirect")
+    } else {
+        None
+    }
+}
+
 fn normalize_domain(raw: &str) -> Result<String> {
     let trimmed = raw.trim().trim_end_matches('.').to_ascii_lowercase();
     if trimmed.is_empty() {
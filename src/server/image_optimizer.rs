@@ -0,0 +1,406 @@
+//! Next-gen image variants
+//!
+//! With `static.image_optimize` enabled, JPEG and PNG files of at least
+//! `static.image_min_size` bytes are served as AVIF or WebP to clients whose
+//! `Accept` header lists those formats. A variant is converted on its first
+//! request on the blocking pool (at most `static.image_max_concurrency` at a
+//! time) and stored under `<cache.disk_path>/images`, named after the source
+//! path, size and mtime so that editing the source produces a new variant.
+//! Conversions that fail or do not shrink the image leave an empty marker
+//! file, and the original is served from then on.
+//!
+//! The codecs are only compiled in with the `image-optimize` cargo feature;
+//! without it the option is ignored and originals are always served.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use serde_json::json;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+
+/// Variant formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Avif,
+    Webp,
+}
+
+impl ImageFormat {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "avif" => Some(Self::Avif),
+            "webp" => Some(Self::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Avif => "image/avif",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+/// A converted image ready to be served
+#[derive(Debug, Clone)]
+pub struct ImageVariant {
+    pub path: PathBuf,
+    pub format: ImageFormat,
+}
+
+#[derive(Default)]
+struct ImageStats {
+    variants_served: AtomicU64,
+    conversions: AtomicU64,
+    failures: AtomicU64,
+    bytes_saved: AtomicU64,
+}
+
+/// Converts and caches image variants for static file responses
+pub struct ImageOptimizer {
+    enabled: bool,
+    min_size: u64,
+    formats: Vec<ImageFormat>,
+    quality: u8,
+    dir: PathBuf,
+    permits: Semaphore,
+    stats: ImageStats,
+}
+
+impl ImageOptimizer {
+    pub fn new(config: &Config) -> Self {
+        let settings = &config.static_files;
+        let enabled = settings.image_optimize && cfg!(feature = "image-optimize");
+        if settings.image_optimize && !enabled {
+            warn!("static.image_optimize is set but veloserve was built without the image-optimize feature; serving original images");
+        }
+
+        Self {
+            enabled,
+            min_size: settings.image_min_size,
+            formats: settings
+                .image_formats
+                .iter()
+                .filter_map(|name| ImageFormat::parse(name))
+                .collect(),
+            quality: settings.image_quality,
+            dir: Path::new(&config.cache.disk_path).join("images"),
+            permits: Semaphore::new(settings.image_max_concurrency.max(1)),
+            stats: ImageStats::default(),
+        }
+    }
+
+    /// True if the response for `path` depends on the request's `Accept` header
+    pub fn applies_to(&self, path: &Path) -> bool {
+        self.enabled && source_extension(path).is_some()
+    }
+
+    /// Format [`Self::variant`] would serve `source` in for `accept`, without
+    /// converting anything
+    pub async fn negotiated(&self, source: &Path, accept: &str) -> Option<ImageFormat> {
+        if !self.applies_to(source) {
+            return None;
+        }
+        let format = negotiate(accept, &self.formats)?;
+        let metadata = tokio::fs::metadata(source).await.ok()?;
+        (metadata.len() >= self.min_size).then_some(format)
+    }
+
+    /// Variant of `source` to serve for `accept`, converting it on first use
+    pub async fn variant(&self, source: &Path, accept: &str) -> Option<ImageVariant> {
+        let format = self.negotiated(source, accept).await?;
+        let metadata = tokio::fs::metadata(source).await.ok()?;
+
+        let path = self.variant_path(source, &metadata, format);
+        let size = match tokio::fs::metadata(&path).await {
+            Ok(existing) => existing.len(),
+            Err(_) => self.convert_to(source, &path, format).await,
+        };
+        if size == 0 {
+            return None;
+        }
+
+        self.stats.variants_served.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .bytes_saved
+            .fetch_add(metadata.len().saturating_sub(size), Ordering::Relaxed);
+        Some(ImageVariant { path, format })
+    }
+
+    /// Counters for `/api/v1/metrics`
+    pub fn stats_json(&self) -> serde_json::Value {
+        json!({
+            "enabled": self.enabled,
+            "variants_served": self.stats.variants_served.load(Ordering::Relaxed),
+            "conversions": self.stats.conversions.load(Ordering::Relaxed),
+            "failures": self.stats.failures.load(Ordering::Relaxed),
+            "bytes_saved": self.stats.bytes_saved.load(Ordering::Relaxed),
+        })
+    }
+
+    fn variant_path(
+        &self,
+        source: &Path,
+        metadata: &std::fs::Metadata,
+        format: ImageFormat,
+    ) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        if let Some(modified) = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        {
+            modified.as_nanos().hash(&mut hasher);
+        }
+        self.quality.hash(&mut hasher);
+        self.dir
+            .join(format!("{:016x}.{}", hasher.finish(), format.extension()))
+    }
+
+    /// Convert `source` into `target`, returning the stored size (0 for a marker)
+    async fn convert_to(&self, source: &Path, target: &Path, format: ImageFormat) -> u64 {
+        let Ok(_permit) = self.permits.acquire().await else {
+            return 0;
+        };
+        // Another request may have finished the same conversion while we waited
+        if let Ok(existing) = tokio::fs::metadata(target).await {
+            return existing.len();
+        }
+
+        let Ok(original) = tokio::fs::read(source).await else {
+            return 0;
+        };
+        let original_len = original.len();
+        let quality = self.quality;
+        let converted = tokio::task::spawn_blocking(move || encode(&original, format, quality))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+
+        let output = match converted {
+            Ok(output) if output.len() < original_len => {
+                self.stats.conversions.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "Converted {:?} to {} ({} -> {} bytes)",
+                    source,
+                    format.extension(),
+                    original_len,
+                    output.len()
+                );
+                output
+            }
+            Ok(_) => {
+                debug!(
+                    "{} variant of {:?} is not smaller; serving the original",
+                    format.extension(),
+                    source
+                );
+                Vec::new()
+            }
+            Err(err) => {
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Failed to convert {:?} to {}: {}",
+                    source,
+                    format.extension(),
+                    err
+                );
+                Vec::new()
+            }
+        };
+
+        if let Err(err) = write_atomic(target, &output).await {
+            warn!("Failed to store image variant {:?}: {}", target, err);
+            return 0;
+        }
+        output.len() as u64
+    }
+}
+
+async fn write_atomic(target: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = target.with_extension(format!("tmp{}", std::process::id()));
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, target).await
+}
+
+fn source_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => Some("jpeg"),
+        "png" => Some("png"),
+        _ => None,
+    }
+}
+
+/// First configured format the client explicitly accepts (wildcards do not count)
+fn negotiate(accept: &str, formats: &[ImageFormat]) -> Option<ImageFormat> {
+    let accepted: Vec<&str> = accept
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let media = params.next()?.trim();
+            let rejected = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            (!rejected).then_some(media)
+        })
+        .collect();
+
+    formats.iter().copied().find(|format| {
+        accepted
+            .iter()
+            .any(|media| media.eq_ignore_ascii_case(format.mime_type()))
+    })
+}
+
+#[cfg(feature = "image-optimize")]
+fn encode(original: &[u8], format: ImageFormat, quality: u8) -> Result<Vec<u8>> {
+    use image::codecs::avif::AvifEncoder;
+    use image::codecs::webp::WebPEncoder;
+
+    let decoded = image::load_from_memory(original)?;
+    let mut output = Vec::new();
+    match format {
+        ImageFormat::Avif => {
+            decoded.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut output,
+                8,
+                quality,
+            ))?;
+        }
+        // The bundled WebP encoder is lossless; `quality` only applies to AVIF
+        ImageFormat::Webp => {
+            decoded
+                .to_rgba8()
+                .write_with_encoder(WebPEncoder::new_lossless(&mut output))?;
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(not(feature = "image-optimize"))]
+fn encode(_original: &[u8], _format: ImageFormat, _quality: u8) -> Result<Vec<u8>> {
+    Err(anyhow::anyhow!("built without the image-optimize feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimizer(dir: &Path, min_size: u64) -> ImageOptimizer {
+        let mut config = Config::default();
+        config.cache.disk_path = dir.to_string_lossy().to_string();
+        config.static_files.image_optimize = true;
+        config.static_files.image_min_size = min_size;
+        ImageOptimizer::new(&config)
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let both = [ImageFormat::Avif, ImageFormat::Webp];
+        assert_eq!(
+            negotiate("image/avif,image/webp,image/*,*/*;q=0.8", &both),
+            Some(ImageFormat::Avif)
+        );
+        assert_eq!(negotiate("image/webp,*/*", &both), Some(ImageFormat::Webp));
+        assert_eq!(
+            negotiate("image/avif;q=0, image/webp", &both),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(negotiate("image/*,*/*", &both), None);
+        assert_eq!(negotiate("image/avif", &[ImageFormat::Webp]), None);
+    }
+
+    #[test]
+    fn test_variant_path_tracks_source_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let images = optimizer(dir.path(), 0);
+        let source = dir.path().join("photo.jpg");
+
+        std::fs::write(&source, b"one").unwrap();
+        let before = images.variant_path(
+            &source,
+            &std::fs::metadata(&source).unwrap(),
+            ImageFormat::Webp,
+        );
+        std::fs::write(&source, b"longer").unwrap();
+        let after = images.variant_path(
+            &source,
+            &std::fs::metadata(&source).unwrap(),
+            ImageFormat::Webp,
+        );
+
+        assert_ne!(before, after);
+        assert!(before.starts_with(dir.path().join("images")));
+        assert_eq!(after.extension().unwrap(), "webp");
+    }
+
+    #[tokio::test]
+    async fn test_unconvertible_images_fall_back_to_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let images = optimizer(dir.path(), 0);
+        let source = dir.path().join("broken.png");
+        std::fs::write(&source, b"not a png").unwrap();
+
+        assert!(images.variant(&source, "image/webp").await.is_none());
+        assert!(images.variant(&source, "image/webp").await.is_none());
+        assert!(!images.applies_to(&dir.path().join("logo.svg")));
+        if images.enabled {
+            assert_eq!(images.stats.failures.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    #[cfg(feature = "image-optimize")]
+    #[tokio::test]
+    async fn test_png_is_served_as_webp() {
+        let dir = tempfile::tempdir().unwrap();
+        let images = optimizer(dir.path(), 0);
+        let source = dir.path().join("flat.png");
+
+        // Uncompressed-ish PNG of a flat colour shrinks well as lossless WebP
+        let pixels = image::RgbImage::from_pixel(256, 256, image::Rgb([200, 30, 30]));
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(pixels)
+            .write_with_encoder(image::codecs::png::PngEncoder::new_with_quality(
+                &mut png,
+                image::codecs::png::CompressionType::Fast,
+                image::codecs::png::FilterType::NoFilter,
+            ))
+            .unwrap();
+        std::fs::write(&source, &png).unwrap();
+
+        let variant = images.variant(&source, "image/webp").await.unwrap();
+        assert_eq!(variant.format, ImageFormat::Webp);
+        let stored = std::fs::read(&variant.path).unwrap();
+        assert!(!stored.is_empty() && stored.len() < png.len());
+        assert!(image::load_from_memory(&stored).is_ok());
+
+        // Below the size threshold the original is served
+        let small = optimizer(dir.path(), png.len() as u64 + 1);
+        assert!(small.variant(&source, "image/webp").await.is_none());
+    }
+}
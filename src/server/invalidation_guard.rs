@@ -0,0 +1,176 @@
+//! Invalidation rate guard
+//!
+//! `POST /api/v1/cache/invalidate` is deduplicated and rate limited per
+//! instance: repeats of a request within `DEDUPE_WINDOW_SECS` are answered
+//! without purging again, and at most `RATE_LIMIT` requests are accepted per
+//! `RATE_WINDOW_SECS` unless a shared limiter already counted them in Redis.
+//! Both tables can be carried over to another instance in a state snapshot.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::server::shared_limits::SharedVerdict;
+
+pub(crate) const DEDUPE_WINDOW_SECS: u64 = 15;
+pub(crate) const RATE_WINDOW_SECS: u64 = 60;
+pub(crate) const RATE_LIMIT: usize = 120;
+
+/// Dedupe table and local rate window of the invalidation API
+#[derive(Default)]
+pub struct InvalidationGuard {
+    dedupe: DashMap<String, u64>,
+    recent_requests: Mutex<VecDeque<u64>>,
+}
+
+pub(crate) enum InvalidationGuardResult {
+    Accepted { deduped: bool },
+    RateLimited,
+}
+
+/// The guard's tables as carried in a state snapshot
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvalidationSnapshot {
+    /// Epoch seconds of the requests in the rate window, oldest first
+    pub requests: Vec<u64>,
+    /// Dedupe keys and the epoch second each was last seen
+    pub dedupe: Vec<(String, u64)>,
+}
+
+impl InvalidationGuard {
+    /// `shared` is the cluster-wide verdict; the local window only counts when Redis did not
+    pub(crate) fn evaluate(
+        &self,
+        dedupe_key: &str,
+        shared: SharedVerdict,
+    ) -> InvalidationGuardResult {
+        let now = now_epoch_secs();
+
+        if shared == SharedVerdict::Limited {
+            return InvalidationGuardResult::RateLimited;
+        }
+        if shared == SharedVerdict::Local {
+            let mut requests = self.recent_requests.lock();
+            while let Some(oldest) = requests.front().copied() {
+                if now.saturating_sub(oldest) >= RATE_WINDOW_SECS {
+                    requests.pop_front();
+                } else {
+                    break;
+                }
+            }
+            if requests.len() >= RATE_LIMIT {
+                return InvalidationGuardResult::RateLimited;
+            }
+            requests.push_back(now);
+        }
+
+        self.dedupe
+            .retain(|_, ts| now.saturating_sub(*ts) <= DEDUPE_WINDOW_SECS);
+
+        let deduped = self
+            .dedupe
+            .get(dedupe_key)
+            .map(|ts| now.saturating_sub(*ts) <= DEDUPE_WINDOW_SECS)
+            .unwrap_or(false);
+        self.dedupe.insert(dedupe_key.to_string(), now);
+
+        InvalidationGuardResult::Accepted { deduped }
+    }
+
+    /// The entries still inside their windows
+    pub fn snapshot(&self) -> InvalidationSnapshot {
+        let now = now_epoch_secs();
+        InvalidationSnapshot {
+            requests: self
+                .recent_requests
+                .lock()
+                .iter()
+                .copied()
+                .filter(|ts| now.saturating_sub(*ts) < RATE_WINDOW_SECS)
+                .collect(),
+            dedupe: self
+                .dedupe
+                .iter()
+                .filter(|entry| now.saturating_sub(*entry.value()) <= DEDUPE_WINDOW_SECS)
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect(),
+        }
+    }
+
+    /// Merge a snapshot into the tables; entries that have left their
+    /// windows since it was taken are dropped
+    pub fn restore(&self, snapshot: InvalidationSnapshot) {
+        let now = now_epoch_secs();
+
+        let mut requests = self.recent_requests.lock();
+        let mut merged: Vec<u64> = requests
+            .iter()
+            .copied()
+            .chain(snapshot.requests)
+            .filter(|ts| now.saturating_sub(*ts) < RATE_WINDOW_SECS)
+            .collect();
+        merged.sort_unstable();
+        let excess = merged.len().saturating_sub(RATE_LIMIT);
+        *requests = merged.into_iter().skip(excess).collect();
+        drop(requests);
+
+        for (key, seen) in snapshot.dedupe {
+            if now.saturating_sub(seen) > DEDUPE_WINDOW_SECS {
+                continue;
+            }
+            let mut current = self.dedupe.entry(key).or_insert(seen);
+            *current = (*current).max(seen);
+        }
+    }
+}
+
+fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restored_window_keeps_counting() {
+        let primary = InvalidationGuard::default();
+        for _ in 0..RATE_LIMIT - 1 {
+            primary.evaluate("a", SharedVerdict::Local);
+        }
+
+        let standby = InvalidationGuard::default();
+        standby.restore(primary.snapshot());
+        assert!(matches!(
+            standby.evaluate("a", SharedVerdict::Local),
+            InvalidationGuardResult::Accepted { deduped: true }
+        ));
+        assert!(matches!(
+            standby.evaluate("b", SharedVerdict::Local),
+            InvalidationGuardResult::RateLimited
+        ));
+    }
+
+    #[test]
+    fn test_restore_drops_entries_outside_their_windows() {
+        let now = now_epoch_secs();
+        let guard = InvalidationGuard::default();
+        guard.restore(InvalidationSnapshot {
+            requests: vec![now - RATE_WINDOW_SECS, now - 1],
+            dedupe: vec![
+                ("old".to_string(), now - DEDUPE_WINDOW_SECS - 1),
+                ("new".to_string(), now - 1),
+            ],
+        });
+
+        let snapshot = guard.snapshot();
+        assert_eq!(snapshot.requests, vec![now - 1]);
+        assert_eq!(snapshot.dedupe, vec![("new".to_string(), now - 1)]);
+    }
+}
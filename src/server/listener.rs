@@ -0,0 +1,249 @@
+//! Listening sockets
+//!
+//! `server.listen` is a TCP address (`0.0.0.0:8080`) or `unix:` followed by
+//! a socket path (`unix:/run/veloserve.sock`) for deployments behind a local
+//! reverse proxy. Both serve HTTP/1.1 through one accept loop, generic over
+//! [`Listener`].
+//!
+//! TCP streams come wrapped in a [`ZeroCopyStream`], which can send large
+//! static files with sendfile(2) once `server.sendfile` enables it.
+//!
+//! Peers on a Unix socket have no address. They are reported as `127.0.0.1`,
+//! so access logs, `REMOTE_ADDR` and `trusted_proxies` treat them like a proxy
+//! on the loopback interface.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+
+use super::zerocopy::{ZeroCopySlot, ZeroCopyStream};
+use super::ConnectionInfo;
+
+/// Prefix of a Unix socket path in `server.listen`
+const UNIX_PREFIX: &str = "unix:";
+
+/// Where the plain HTTP listener binds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// Parse `server.listen`
+    pub fn parse(listen: &str) -> Result<Self, String> {
+        if let Some(path) = listen.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                return Err(format!("'{}' is missing the socket path", listen));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        listen.parse().map(Self::Tcp).map_err(|_| {
+            format!(
+                "'{}' is neither an address (0.0.0.0:8080) nor unix:<path>",
+                listen
+            )
+        })
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Parse `server.socket_mode`, an octal permission string such as `"0660"`
+pub fn parse_socket_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode.trim(), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("'{}' is not an octal file mode like \"0660\"", mode))
+}
+
+/// A listening socket the HTTP accept loop can serve
+pub trait Listener: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Wait for the next connection
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Stream, ConnectionInfo)>> + Send;
+
+    /// Let `stream` send large static files itself (`server.sendfile`);
+    /// `None` where it can't
+    fn zero_copy(_stream: &mut Self::Stream) -> Option<ZeroCopySlot> {
+        None
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = ZeroCopyStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, ConnectionInfo)> {
+        let (stream, peer) = TcpListener::accept(self).await?;
+        let conn = ConnectionInfo::new(peer, stream.local_addr().ok(), false);
+        Ok((ZeroCopyStream::new(stream), conn))
+    }
+
+    fn zero_copy(stream: &mut Self::Stream) -> Option<ZeroCopySlot> {
+        stream.enable()
+    }
+}
+
+#[cfg(unix)]
+impl Listener for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, ConnectionInfo)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((stream, ConnectionInfo::unix()))
+    }
+}
+
+/// Bind a TCP listener on `addr`
+///
+/// IPv6 sockets are made v6-only, so `[::]:8080` and `0.0.0.0:8080` can be
+/// listed together on hosts where IPv6 sockets would otherwise take the
+/// IPv4 wildcard as well.
+pub fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // As tokio's own bind does, so a restart can reuse the port at once
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Bind a Unix socket at `path` with permissions `mode`
+///
+/// A socket file left behind by an earlier run is removed first. Anything
+/// else at `path`, or a socket another process still accepts on, is an
+/// error rather than being replaced.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path, mode: u32) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another process", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            ListenAddr::parse("127.0.0.1:8080").unwrap(),
+            ListenAddr::Tcp("127.0.0.1:8080".parse().unwrap())
+        );
+        let unix = ListenAddr::parse("unix:/run/veloserve.sock").unwrap();
+        assert_eq!(unix, ListenAddr::Unix(PathBuf::from("/run/veloserve.sock")));
+        assert_eq!(unix.to_string(), "unix:/run/veloserve.sock");
+
+        for bad in ["unix:", "localhost:8080", "8080"] {
+            assert!(ListenAddr::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("0660"), Ok(0o660));
+        assert_eq!(parse_socket_mode("777"), Ok(0o777));
+        for bad in ["", "0888", "1777", "rw-rw----"] {
+            assert!(parse_socket_mode(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_tcp_ipv4_and_ipv6_wildcards() {
+        if std::net::TcpListener::bind("[::1]:0").is_err() {
+            // No IPv6 on this host
+            return;
+        }
+        let v4 = bind_tcp("0.0.0.0:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let v6 = bind_tcp(SocketAddr::from(([0u16; 8], port)))
+            .unwrap_or_else(|e| panic!("binding [::]:{} next to 0.0.0.0: {}", port, e));
+        assert_eq!(v6.local_addr().unwrap().port(), port);
+
+        // Each family reaches its own listener
+        let (_client, accepted) = tokio::join!(
+            tokio::net::TcpStream::connect(("127.0.0.1", port)),
+            v4.accept()
+        );
+        assert!(accepted.unwrap().1.is_ipv4());
+        let (_client, accepted) =
+            tokio::join!(tokio::net::TcpStream::connect(("::1", port)), v6.accept());
+        assert!(accepted.unwrap().1.is_ipv6());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_unix_replaces_stale_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("veloserve.sock");
+
+        let listener = bind_unix(&path, 0o600).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Still accepting: refused
+        assert_eq!(
+            bind_unix(&path, 0o660).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // Left behind: replaced
+        drop(listener);
+        assert!(path.exists());
+        bind_unix(&path, 0o660).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        // Not a socket: kept
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, "keep me").unwrap();
+        assert!(bind_unix(&file, 0o660).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep me");
+    }
+}
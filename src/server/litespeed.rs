@@ -0,0 +1,282 @@
+//! LiteSpeed Cache plugin headers
+//!
+//! The LiteSpeed Cache plugins drive the page cache with response headers
+//! meant for the server alone:
+//!
+//! - `X-LiteSpeed-Cache-Control`: `public, max-age=N` stores the page (for
+//!   N seconds, else the vhost TTL); `no-cache`, `no-store` and `private`
+//!   keep it out. It beats the response's own `Cache-Control`.
+//! - `X-LiteSpeed-Tag`: comma-separated tags for the stored page.
+//! - `X-LiteSpeed-Purge`: tags to drop (`tag=archive, post_123`), or `*` for
+//!   every page of the site. `public` and `stale` are accepted and ignored;
+//!   `;`-separated groups marked `private` target a private cache we don't
+//!   keep and are skipped.
+//!
+//! PHP responses have these headers taken off before anything else sees
+//! them, so they never reach the client or the cache.
+//!
+//! Tags are stored as `ls:<host>:<tag>`, so sites sharing the server can
+//! use the same tag names without purging each other's pages. Purges naming
+//! the tags VeloServe sets itself (`domain:`, `path:`, `negative`) are
+//! refused.
+
+use std::time::Duration;
+
+use hyper::http::HeaderMap;
+
+use crate::cache::normalize_host;
+
+pub const CACHE_CONTROL: &str = "x-litespeed-cache-control";
+pub const TAG: &str = "x-litespeed-tag";
+pub const PURGE: &str = "x-litespeed-purge";
+
+/// What `X-LiteSpeed-Cache-Control` asks of the page cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheDirective {
+    /// `no-cache`, `no-store`, `private` or `max-age=0`
+    Bypass,
+    /// Anything else, with the `max-age` if one was given
+    Store(Option<Duration>),
+}
+
+/// The page cache half of a PHP response's LiteSpeed headers, attached to
+/// the response as an extension
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiteSpeedCache {
+    pub directive: Option<CacheDirective>,
+    pub tags: Vec<String>,
+}
+
+/// Pages `X-LiteSpeed-Purge` asked to drop
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Purge {
+    /// `*`: every page of the site
+    pub all: bool,
+    pub tags: Vec<String>,
+    /// Reserved tags the response asked for, left alone
+    pub refused: Vec<String>,
+}
+
+impl Purge {
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.tags.is_empty() && self.refused.is_empty()
+    }
+}
+
+/// The cache tag for LiteSpeed tag `tag` on a page of `host`
+pub fn host_tag(host: &str, tag: &str) -> String {
+    format!("ls:{}:{}", normalize_host(host), tag)
+}
+
+/// Tags VeloServe sets on pages itself, which plugins may not purge
+pub fn is_reserved(tag: &str) -> bool {
+    tag.starts_with("domain:")
+        || tag.starts_with("path:")
+        || tag == "negative"
+        || tag.starts_with("negative:")
+}
+
+/// Remove the LiteSpeed headers from a response and return what they said
+pub fn take(headers: &mut HeaderMap) -> (LiteSpeedCache, Purge) {
+    let cache = LiteSpeedCache {
+        directive: parse_cache_control(&take_values(headers, CACHE_CONTROL)),
+        tags: parse_tags(&take_values(headers, TAG)),
+    };
+    let purge = parse_purge(&take_values(headers, PURGE));
+    (cache, purge)
+}
+
+/// Every line of a header, removed from the map
+fn take_values(headers: &mut HeaderMap, name: &str) -> Vec<String> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::to_string)
+        .collect();
+    headers.remove(name);
+    values
+}
+
+/// Comma-separated items across all header lines
+fn items(values: &[String]) -> impl Iterator<Item = &str> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn parse_cache_control(values: &[String]) -> Option<CacheDirective> {
+    let mut max_age = None;
+    let mut seen = false;
+    for item in items(values) {
+        seen = true;
+        let (name, value) = match item.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (item, None),
+        };
+        if ["no-cache", "no-store", "private"]
+            .iter()
+            .any(|blocked| name.eq_ignore_ascii_case(blocked))
+        {
+            return Some(CacheDirective::Bypass);
+        }
+        if name.eq_ignore_ascii_case("max-age") {
+            max_age = value.and_then(|v| v.parse::<u64>().ok()).or(Some(0));
+        }
+    }
+    match max_age {
+        Some(0) => Some(CacheDirective::Bypass),
+        Some(seconds) => Some(CacheDirective::Store(Some(Duration::from_secs(seconds)))),
+        None => seen.then_some(CacheDirective::Store(None)),
+    }
+}
+
+fn parse_tags(values: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in items(values) {
+        if !tags.iter().any(|seen| seen == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+fn parse_purge(values: &[String]) -> Purge {
+    let mut purge = Purge::default();
+    for group in values.iter().flat_map(|value| value.split(';')) {
+        let group = [group.to_string()];
+        if items(&group).any(|item| item.eq_ignore_ascii_case("private")) {
+            continue;
+        }
+        for item in items(&group) {
+            if item.eq_ignore_ascii_case("public") || item.eq_ignore_ascii_case("stale") {
+                continue;
+            }
+            let tag = match item.split_once('=') {
+                Some((name, tag)) if name.trim().eq_ignore_ascii_case("tag") => tag.trim(),
+                _ => item,
+            };
+            if tag == "*" {
+                purge.all = true;
+            } else if is_reserved(tag) {
+                if !purge.refused.iter().any(|seen| seen == tag) {
+                    purge.refused.push(tag.to_string());
+                }
+            } else if !tag.is_empty() && !purge.tags.iter().any(|seen| seen == tag) {
+                purge.tags.push(tag.to_string());
+            }
+        }
+    }
+    purge
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn headers(lines: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in lines {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let directive = |value: &'static str| {
+            take(&mut headers(&[("x-litespeed-cache-control", value)]))
+                .0
+                .directive
+        };
+        assert_eq!(
+            directive("public,max-age=604800"),
+            Some(CacheDirective::Store(Some(Duration::from_secs(604800))))
+        );
+        assert_eq!(directive("public"), Some(CacheDirective::Store(None)));
+        assert_eq!(directive("no-cache"), Some(CacheDirective::Bypass));
+        assert_eq!(directive("public, Private"), Some(CacheDirective::Bypass));
+        assert_eq!(directive("max-age=0"), Some(CacheDirective::Bypass));
+        assert_eq!(directive("max-age=soon"), Some(CacheDirective::Bypass));
+        assert_eq!(take(&mut HeaderMap::new()).0.directive, None);
+    }
+
+    #[test]
+    fn test_tags() {
+        let (cache, _) = take(&mut headers(&[
+            ("x-litespeed-tag", "b1a_HTTP.200, b1a_Po.123"),
+            ("x-litespeed-tag", "b1a_Po.123,,b1a_F"),
+        ]));
+        assert_eq!(cache.tags, ["b1a_HTTP.200", "b1a_Po.123", "b1a_F"]);
+    }
+
+    #[test]
+    fn test_purge() {
+        let purge = |value: &'static str| take(&mut headers(&[("x-litespeed-purge", value)])).1;
+        assert_eq!(
+            purge("tag=archive, post_123"),
+            Purge {
+                all: false,
+                tags: vec!["archive".to_string(), "post_123".to_string()],
+                refused: Vec::new(),
+            }
+        );
+        assert_eq!(
+            purge("public,stale,b1a_Po.1"),
+            Purge {
+                all: false,
+                tags: vec!["b1a_Po.1".to_string()],
+                refused: Vec::new(),
+            }
+        );
+        assert!(purge("*").all);
+        assert!(purge("tag=*").all);
+        // The private half is for a cache we don't keep
+        assert_eq!(
+            purge("public,home; private,*"),
+            Purge {
+                all: false,
+                tags: vec!["home".to_string()],
+                refused: Vec::new(),
+            }
+        );
+        assert!(purge("").is_empty());
+        // Tags VeloServe sets itself are not the plugin's to purge
+        assert_eq!(
+            purge("tag=domain:example.com, path:example.com/, negative, negative:a.test, home"),
+            Purge {
+                all: false,
+                tags: vec!["home".to_string()],
+                refused: vec![
+                    "domain:example.com".to_string(),
+                    "path:example.com/".to_string(),
+                    "negative".to_string(),
+                    "negative:a.test".to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_host_tag() {
+        assert_eq!(host_tag("Example.com:8080", "home"), "ls:example.com:home");
+        assert!(!is_reserved("negative_feedback"));
+        assert!(!is_reserved(&host_tag("a.test", "domain:b.test")));
+    }
+
+    #[test]
+    fn test_headers_are_removed() {
+        let mut map = headers(&[
+            ("x-litespeed-cache-control", "public"),
+            ("x-litespeed-tag", "home"),
+            ("x-litespeed-purge", "*"),
+            ("content-type", "text/html"),
+        ]);
+        take(&mut map);
+        assert_eq!(map.len(), 1);
+        assert!(map.contains_key("content-type"));
+    }
+}
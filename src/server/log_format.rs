@@ -0,0 +1,650 @@
+//! Access log formats
+//!
+//! `logging.format` is either a preset (`combined`, `common`, `json`) or an
+//! nginx-style template such as
+//! `$remote_addr - $host [$time_iso8601] "$request" $status $body_bytes_sent`.
+//! Templates may also use Apache `LogFormat` directives (`%h %l %u %t "%r"
+//! %>s %b "%{Referer}i" %D`), which are translated to the same variables.
+//! Formats are compiled once per configuration load into a [`LogFormat`] render
+//! plan; rendering a request only walks the plan.
+//!
+//! Escaping: values are written verbatim except that control and non-ASCII
+//! bytes become `\xHH`. Inside a quoted field (a variable preceded by an
+//! unmatched `"` in the template) `"` and `\` are backslash-escaped as well.
+//! Empty values render as `-`. The JSON format uses the same variable names as
+//! field values, e.g. `[logging.json_fields] client = "$remote_addr"`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use tracing::warn;
+
+use crate::config::LoggingConfig;
+
+/// Apache/nginx "combined" log format
+pub const COMBINED_FORMAT: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent \"$http_referer\" \"$http_user_agent\"";
+
+/// Apache/nginx "common" log format
+pub const COMMON_FORMAT: &str =
+    "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent";
+
+/// Fields emitted by `format = "json"` when `json_fields` is empty
+const DEFAULT_JSON_FIELDS: &[(&str, LogVariable)] = &[
+    ("time", LogVariable::TimeIso8601),
+    ("remote_addr", LogVariable::RemoteAddr),
+    ("host", LogVariable::Host),
+    ("method", LogVariable::RequestMethod),
+    ("uri", LogVariable::RequestUri),
+    ("protocol", LogVariable::ServerProtocol),
+    ("status", LogVariable::Status),
+    ("body_bytes_sent", LogVariable::BodyBytesSent),
+    ("referer", LogVariable::HttpReferer),
+    ("user_agent", LogVariable::HttpUserAgent),
+    ("request_time", LogVariable::RequestTime),
+    ("upstream_time", LogVariable::UpstreamTime),
+    ("cache_status", LogVariable::CacheStatus),
+];
+
+/// Per-request values a log format can reference
+#[derive(Debug, Clone)]
+pub struct AccessLogContext {
+    /// Client address
+    pub remote_addr: Option<IpAddr>,
+    /// Scheme the client used (`http` or `https`)
+    pub scheme: &'static str,
+    /// Authenticated user, if any
+    pub remote_user: Option<String>,
+    /// `Host` header without port
+    pub host: Option<String>,
+    /// Time the request was received
+    pub time: DateTime<Local>,
+    /// Request method
+    pub method: String,
+    /// Request path and query string
+    pub uri: String,
+    /// Protocol version (e.g. "HTTP/1.1")
+    pub protocol: String,
+    /// Response status code
+    pub status: u16,
+    /// Response body size in bytes
+    pub body_bytes_sent: u64,
+    /// `Referer` header
+    pub referer: Option<String>,
+    /// `User-Agent` header
+    pub user_agent: Option<String>,
+    /// Total time spent handling the request
+    pub request_time: Duration,
+    /// Time spent in PHP, if the request reached it
+    pub upstream_time: Option<Duration>,
+    /// Page cache outcome (`HIT`, `MISS`, ...)
+    pub cache_status: Option<String>,
+    /// Request id from `x-veloserve-request-id`/`x-request-id`
+    pub request_id: Option<String>,
+}
+
+/// PHP execution time, attached to responses as an extension for `$upstream_time`
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamTime(pub Duration);
+
+/// A variable usable in log formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogVariable {
+    RemoteAddr,
+    RemoteUser,
+    Scheme,
+    Host,
+    TimeLocal,
+    TimeIso8601,
+    Request,
+    RequestMethod,
+    RequestUri,
+    Uri,
+    Args,
+    ServerProtocol,
+    Status,
+    BodyBytesSent,
+    HttpReferer,
+    HttpUserAgent,
+    RequestTime,
+    RequestTimeMicros,
+    UpstreamTime,
+    CacheStatus,
+    RequestId,
+}
+
+impl LogVariable {
+    /// Every supported variable with its name (without `$`)
+    pub const ALL: &'static [(&'static str, LogVariable)] = &[
+        ("remote_addr", LogVariable::RemoteAddr),
+        ("remote_user", LogVariable::RemoteUser),
+        ("scheme", LogVariable::Scheme),
+        ("host", LogVariable::Host),
+        ("time_local", LogVariable::TimeLocal),
+        ("time_iso8601", LogVariable::TimeIso8601),
+        ("request", LogVariable::Request),
+        ("request_method", LogVariable::RequestMethod),
+        ("request_uri", LogVariable::RequestUri),
+        ("uri", LogVariable::Uri),
+        ("args", LogVariable::Args),
+        ("server_protocol", LogVariable::ServerProtocol),
+        ("status", LogVariable::Status),
+        ("body_bytes_sent", LogVariable::BodyBytesSent),
+        ("http_referer", LogVariable::HttpReferer),
+        ("http_user_agent", LogVariable::HttpUserAgent),
+        ("request_time", LogVariable::RequestTime),
+        ("request_time_us", LogVariable::RequestTimeMicros),
+        ("upstream_time", LogVariable::UpstreamTime),
+        ("cache_status", LogVariable::CacheStatus),
+        ("request_id", LogVariable::RequestId),
+    ];
+
+    /// Look up a variable by name (without `$`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(current, _)| *current == name)
+            .map(|(_, variable)| *variable)
+    }
+
+    /// Append the value to `out`, or nothing if it is unset
+    fn write(self, ctx: &AccessLogContext, out: &mut String) {
+        match self {
+            LogVariable::RemoteAddr => {
+                if let Some(addr) = ctx.remote_addr {
+                    let _ = write!(out, "{}", addr);
+                }
+            }
+            LogVariable::RemoteUser => out.push_str(ctx.remote_user.as_deref().unwrap_or("")),
+            LogVariable::Scheme => out.push_str(ctx.scheme),
+            LogVariable::Host => out.push_str(ctx.host.as_deref().unwrap_or("")),
+            LogVariable::TimeLocal => {
+                let _ = write!(out, "{}", ctx.time.format("%d/%b/%Y:%H:%M:%S %z"));
+            }
+            LogVariable::TimeIso8601 => {
+                let _ = write!(out, "{}", ctx.time.format("%Y-%m-%dT%H:%M:%S%:z"));
+            }
+            LogVariable::Request => {
+                let _ = write!(out, "{} {} {}", ctx.method, ctx.uri, ctx.protocol);
+            }
+            LogVariable::RequestMethod => out.push_str(&ctx.method),
+            LogVariable::RequestUri => out.push_str(&ctx.uri),
+            LogVariable::Uri => out.push_str(ctx.uri.split('?').next().unwrap_or("")),
+            LogVariable::Args => out.push_str(ctx.uri.split_once('?').map_or("", |(_, q)| q)),
+            LogVariable::ServerProtocol => out.push_str(&ctx.protocol),
+            LogVariable::Status => {
+                let _ = write!(out, "{}", ctx.status);
+            }
+            LogVariable::BodyBytesSent => {
+                let _ = write!(out, "{}", ctx.body_bytes_sent);
+            }
+            LogVariable::HttpReferer => out.push_str(ctx.referer.as_deref().unwrap_or("")),
+            LogVariable::HttpUserAgent => out.push_str(ctx.user_agent.as_deref().unwrap_or("")),
+            LogVariable::RequestTime => {
+                let _ = write!(out, "{:.3}", ctx.request_time.as_secs_f64());
+            }
+            LogVariable::RequestTimeMicros => {
+                let _ = write!(out, "{}", ctx.request_time.as_micros());
+            }
+            LogVariable::UpstreamTime => {
+                if let Some(upstream) = ctx.upstream_time {
+                    let _ = write!(out, "{:.3}", upstream.as_secs_f64());
+                }
+            }
+            LogVariable::CacheStatus => out.push_str(ctx.cache_status.as_deref().unwrap_or("")),
+            LogVariable::RequestId => out.push_str(ctx.request_id.as_deref().unwrap_or("")),
+        }
+    }
+
+    /// Value as JSON: numbers for counters and timings, `null` when unset
+    fn json(self, ctx: &AccessLogContext) -> serde_json::Value {
+        match self {
+            LogVariable::Status => ctx.status.into(),
+            LogVariable::BodyBytesSent => ctx.body_bytes_sent.into(),
+            LogVariable::RequestTime => ctx.request_time.as_secs_f64().into(),
+            LogVariable::RequestTimeMicros => (ctx.request_time.as_micros() as u64).into(),
+            LogVariable::UpstreamTime => ctx
+                .upstream_time
+                .map_or(serde_json::Value::Null, |t| t.as_secs_f64().into()),
+            _ => {
+                let mut value = String::new();
+                self.write(ctx, &mut value);
+                if value.is_empty() {
+                    serde_json::Value::Null
+                } else {
+                    value.into()
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable { variable: LogVariable, quoted: bool },
+}
+
+#[derive(Debug, Clone)]
+enum Plan {
+    Template(Vec<Segment>),
+    Json(Vec<(String, LogVariable)>),
+}
+
+/// A compiled access log format
+#[derive(Debug, Clone)]
+pub struct LogFormat {
+    plan: Plan,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self::template(COMBINED_FORMAT).expect("combined format is valid")
+    }
+}
+
+impl LogFormat {
+    /// Compile `logging.format` / `logging.json_fields`
+    pub fn compile(config: &LoggingConfig) -> Result<Self, String> {
+        match config.format.as_str() {
+            "combined" => Self::template(COMBINED_FORMAT),
+            "common" => Self::template(COMMON_FORMAT),
+            "json" => Self::json(&config.json_fields),
+            template => Self::template(template),
+        }
+    }
+
+    /// Compile an nginx-style template, which may use Apache directives
+    pub fn template(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut in_quotes = false;
+        let mut rest = template;
+
+        while let Some(pos) = rest.find(['$', '%']) {
+            let (text, after) = rest.split_at(pos);
+            in_quotes ^= text.matches('"').count() % 2 == 1;
+            literal.push_str(text);
+
+            if let Some(after) = after.strip_prefix('%') {
+                let (directive, remainder) = match apache_directive(after) {
+                    Ok(Some(found)) => found,
+                    Ok(None) => {
+                        literal.push('%');
+                        rest = after;
+                        continue;
+                    }
+                    Err(e) => {
+                        // Templates written before directives were recognized
+                        // may use `%` as text, so keep it rather than fail
+                        warn!("{}; logging it as text (write `%%` for a literal `%`)", e);
+                        literal.push('%');
+                        rest = after;
+                        continue;
+                    }
+                };
+                let (before, variable, after_text) = match directive {
+                    Directive::Literal(text) => (text, None, ""),
+                    Directive::Variable(variable) => ("", Some(variable), ""),
+                    Directive::Bracketed(variable) => ("[", Some(variable), "]"),
+                };
+                literal.push_str(before);
+                if let Some(variable) = variable {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Variable {
+                        variable,
+                        quoted: in_quotes,
+                    });
+                }
+                literal.push_str(after_text);
+                rest = remainder;
+                continue;
+            }
+
+            let after = &after[1..];
+            let (name, remainder) = if let Some(braced) = after.strip_prefix('{') {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated `${{` in log format: {}", template))?;
+                (&braced[..end], &braced[end + 1..])
+            } else {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                after.split_at(end)
+            };
+
+            if name.is_empty() {
+                literal.push('$');
+                rest = after;
+                continue;
+            }
+
+            let variable = LogVariable::from_name(name)
+                .ok_or_else(|| format!("unknown log format variable `${}`", name))?;
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Variable {
+                variable,
+                quoted: in_quotes,
+            });
+            rest = remainder;
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self {
+            plan: Plan::Template(segments),
+        })
+    }
+
+    /// Compile a JSON field map (`field = "$variable"`); empty uses the default field set
+    pub fn json(fields: &BTreeMap<String, String>) -> Result<Self, String> {
+        let fields = if fields.is_empty() {
+            DEFAULT_JSON_FIELDS
+                .iter()
+                .map(|(name, variable)| (name.to_string(), *variable))
+                .collect()
+        } else {
+            fields
+                .iter()
+                .map(|(field, value)| {
+                    let name = value.strip_prefix('$').ok_or_else(|| {
+                        format!(
+                            "logging.json_fields.{} must be a `$variable`, got `{}`",
+                            field, value
+                        )
+                    })?;
+                    let variable = LogVariable::from_name(name)
+                        .ok_or_else(|| format!("unknown log format variable `${}`", name))?;
+                    Ok((field.clone(), variable))
+                })
+                .collect::<Result<_, String>>()?
+        };
+
+        Ok(Self {
+            plan: Plan::Json(fields),
+        })
+    }
+
+    /// Render one log line (without trailing newline)
+    pub fn render(&self, ctx: &AccessLogContext) -> String {
+        match &self.plan {
+            Plan::Template(segments) => {
+                let mut out = String::with_capacity(192);
+                let mut value = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => out.push_str(text),
+                        Segment::Variable { variable, quoted } => {
+                            value.clear();
+                            variable.write(ctx, &mut value);
+                            push_escaped(&mut out, &value, *quoted);
+                        }
+                    }
+                }
+                out
+            }
+            Plan::Json(fields) => {
+                let object: serde_json::Map<String, serde_json::Value> = fields
+                    .iter()
+                    .map(|(field, variable)| (field.clone(), variable.json(ctx)))
+                    .collect();
+                serde_json::Value::Object(object).to_string()
+            }
+        }
+    }
+}
+
+/// What an Apache `LogFormat` directive renders as
+enum Directive {
+    Literal(&'static str),
+    Variable(LogVariable),
+    /// The variable inside `[...]`, as `%t` writes it
+    Bracketed(LogVariable),
+}
+
+/// Parse the Apache directive at the start of `after` (the text following a
+/// `%`) and return it with the rest of the template
+///
+/// `None` means the `%` is literal text (`100% `); a directive VeloServe has
+/// no value for is an error, which the caller reports and writes as text.
+fn apache_directive(after: &str) -> Result<Option<(Directive, &str)>, String> {
+    if let Some(braced) = after.strip_prefix('{') {
+        let end = braced
+            .find('}')
+            .ok_or_else(|| format!("unterminated `%{{` in log format: %{}", after))?;
+        let (name, remainder) = (&braced[..end], &braced[end + 1..]);
+        let variable = match remainder.chars().next() {
+            Some('i') if name.eq_ignore_ascii_case("referer") => LogVariable::HttpReferer,
+            Some('i') if name.eq_ignore_ascii_case("user-agent") => LogVariable::HttpUserAgent,
+            Some('i') if name.eq_ignore_ascii_case("host") => LogVariable::Host,
+            _ => {
+                let kind = remainder
+                    .chars()
+                    .next()
+                    .map(String::from)
+                    .unwrap_or_default();
+                return Err(format!(
+                    "unsupported log format directive `%{{{}}}{}`",
+                    name, kind
+                ));
+            }
+        };
+        return Ok(Some((Directive::Variable(variable), &remainder[1..])));
+    }
+
+    // `%>s` / `%<s`: final or original status, the same thing here
+    let body = after
+        .strip_prefix('>')
+        .or_else(|| after.strip_prefix('<'))
+        .unwrap_or(after);
+    let Some(letter) = body.chars().next() else {
+        return Ok(None);
+    };
+    let directive = match letter {
+        '%' if body.len() == after.len() => Directive::Literal("%"),
+        'h' | 'a' => Directive::Variable(LogVariable::RemoteAddr),
+        'l' => Directive::Literal("-"),
+        'u' => Directive::Variable(LogVariable::RemoteUser),
+        't' => Directive::Bracketed(LogVariable::TimeLocal),
+        'r' => Directive::Variable(LogVariable::Request),
+        's' => Directive::Variable(LogVariable::Status),
+        'b' | 'B' => Directive::Variable(LogVariable::BodyBytesSent),
+        'D' => Directive::Variable(LogVariable::RequestTimeMicros),
+        'm' => Directive::Variable(LogVariable::RequestMethod),
+        'U' => Directive::Variable(LogVariable::Uri),
+        'H' => Directive::Variable(LogVariable::ServerProtocol),
+        'v' => Directive::Variable(LogVariable::Host),
+        c if c.is_ascii_alphabetic() || body.len() != after.len() => {
+            return Err(format!(
+                "unsupported log format directive `%{}`",
+                &after[..after.len() - body.len() + c.len_utf8()]
+            ));
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((directive, &body[letter.len_utf8()..])))
+}
+
+fn push_escaped(out: &mut String, value: &str, quoted: bool) {
+    if value.is_empty() {
+        out.push('-');
+        return;
+    }
+    for ch in value.chars() {
+        match ch {
+            '"' | '\\' if quoted => {
+                out.push('\\');
+                out.push(ch);
+            }
+            ' '..='~' => out.push(ch),
+            _ => {
+                let mut buf = [0u8; 4];
+                for byte in ch.encode_utf8(&mut buf).bytes() {
+                    let _ = write!(out, "\\x{:02X}", byte);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context() -> AccessLogContext {
+        AccessLogContext {
+            remote_addr: Some("192.0.2.7".parse().unwrap()),
+            scheme: "https",
+            remote_user: None,
+            host: Some("example.com".to_string()),
+            time: Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap(),
+            method: "GET".to_string(),
+            uri: "/shop?page=2".to_string(),
+            protocol: "HTTP/1.1".to_string(),
+            status: 200,
+            body_bytes_sent: 5120,
+            referer: Some("https://example.com/\"home\"".to_string()),
+            user_agent: Some("curl/8.0\n".to_string()),
+            request_time: Duration::from_millis(12),
+            upstream_time: Some(Duration::from_millis(9)),
+            cache_status: Some("MISS".to_string()),
+            request_id: None,
+        }
+    }
+
+    fn template_config(format: &str) -> LoggingConfig {
+        LoggingConfig {
+            format: format.to_string(),
+            ..LoggingConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_render_custom_template() {
+        let format = LogFormat::compile(&template_config(
+            "$remote_addr - $host [$time_iso8601] \"$request\" $status $body_bytes_sent $request_time $upstream_time $cache_status",
+        ))
+        .unwrap();
+        let time = context().time.format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+        assert_eq!(
+            format.render(&context()),
+            format!(
+                "192.0.2.7 - example.com [{}] \"GET /shop?page=2 HTTP/1.1\" 200 5120 0.012 0.009 MISS",
+                time
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_combined_escapes_quoted_fields() {
+        let format = LogFormat::compile(&template_config("combined")).unwrap();
+        let line = format.render(&context());
+        assert!(line.starts_with("192.0.2.7 - - ["));
+        assert!(line.ends_with(
+            "\"GET /shop?page=2 HTTP/1.1\" 200 5120 \"https://example.com/\\\"home\\\"\" \"curl/8.0\\x0A\""
+        ));
+    }
+
+    #[test]
+    fn test_render_braced_and_unset_variables() {
+        let format =
+            LogFormat::template("${status}ms=$upstream_time id=$request_id $ cost $scheme")
+                .unwrap();
+        let mut ctx = context();
+        ctx.upstream_time = None;
+        assert_eq!(format.render(&ctx), "200ms=- id=- $ cost https");
+    }
+
+    #[test]
+    fn test_render_json_fields() {
+        let mut config = template_config("json");
+        config
+            .json_fields
+            .insert("client".to_string(), "$remote_addr".to_string());
+        config
+            .json_fields
+            .insert("status".to_string(), "$status".to_string());
+        config
+            .json_fields
+            .insert("user".to_string(), "$remote_user".to_string());
+        config
+            .json_fields
+            .insert("ua".to_string(), "$http_user_agent".to_string());
+
+        let line = LogFormat::compile(&config).unwrap().render(&context());
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["client"], "192.0.2.7");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["user"], serde_json::Value::Null);
+        assert_eq!(value["ua"], "curl/8.0\n");
+
+        let default = LogFormat::compile(&template_config("json")).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&default.render(&context())).unwrap();
+        assert_eq!(value["cache_status"], "MISS");
+        assert_eq!(value["body_bytes_sent"], 5120);
+    }
+
+    #[test]
+    fn test_render_apache_directives() {
+        let format = LogFormat::compile(&template_config(
+            "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\" %D 100%",
+        ))
+        .unwrap();
+        let time = context().time.format("%d/%b/%Y:%H:%M:%S %z").to_string();
+        assert_eq!(
+            format.render(&context()),
+            format!(
+                "192.0.2.7 - - [{}] \"GET /shop?page=2 HTTP/1.1\" 200 5120 \"https://example.com/\\\"home\\\"\" \"curl/8.0\\x0A\" 12000 100%",
+                time
+            )
+        );
+
+        // Apache and nginx spellings of combined render the same line
+        let apache = LogFormat::compile(&template_config(
+            "%h - %u %t \"%r\" %s %b \"%{referer}i\" \"%{user-agent}i\"",
+        ))
+        .unwrap();
+        assert_eq!(
+            apache.render(&context()),
+            LogFormat::default().render(&context())
+        );
+
+        // Unsupported directives are written as text
+        for text in ["%Z", "%{Cookie}i", "%{Referer}o", "%>x", "%{Referer"] {
+            assert!(apache_directive(&text[1..]).is_err(), "{}", text);
+            assert_eq!(
+                LogFormat::template(text).unwrap().render(&context()),
+                text,
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            LogFormat::template("%% %").unwrap().render(&context()),
+            "% %"
+        );
+    }
+
+    #[test]
+    fn test_unknown_variables_are_rejected() {
+        let err = LogFormat::compile(&template_config("$remote_addr $bogus")).unwrap_err();
+        assert!(err.contains("$bogus"));
+
+        let mut config = template_config("json");
+        config
+            .json_fields
+            .insert("x".to_string(), "remote_addr".to_string());
+        assert!(LogFormat::compile(&config).is_err());
+    }
+}
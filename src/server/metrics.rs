@@ -0,0 +1,285 @@
+//! Request metrics
+//!
+//! Counters and latency histograms shared by every request, updated with
+//! relaxed atomics and rendered for Prometheus at `GET /metrics`. Requests
+//! are counted as they arrive and their responses once the body has been
+//! sent (or abandoned); PHP runs are recorded as soon as the script's
+//! response is ready.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Status classes counted by `veloserve_responses_total`
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Latency histogram with fixed buckets
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket (not cumulative); the last is `+Inf`
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += self.buckets[BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count.load(Ordering::Relaxed));
+    }
+}
+
+/// Server-wide request counters
+#[derive(Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    responses: [AtomicU64; STATUS_CLASSES.len()],
+    bytes_sent: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    php_executions: AtomicU64,
+    php_errors: AtomicU64,
+    request_duration: Histogram,
+    php_duration: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request as it arrives
+    pub fn request_started(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a response once its body is out
+    ///
+    /// `cache_status` is the response's `X-Cache` value: `HIT` and `STALE`
+    /// count as hits, `MISS` as a miss and anything else as neither.
+    pub fn record_response(
+        &self,
+        status: u16,
+        bytes_sent: u64,
+        duration: Duration,
+        cache_status: Option<&str>,
+    ) {
+        if let Some(class) = self.responses.get((status / 100).wrapping_sub(1) as usize) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+        match cache_status {
+            Some("HIT" | "STALE") => self.cache_hits.fetch_add(1, Ordering::Relaxed),
+            Some("MISS") => self.cache_misses.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        self.request_duration.observe(duration);
+    }
+
+    /// Record a PHP run; `failed` when it ended in a server error
+    pub fn record_php(&self, duration: Duration, failed: bool) {
+        self.php_executions.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.php_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.php_duration.observe(duration);
+    }
+
+    /// Requests received since startup
+    pub fn requests_total(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Responses sent since startup by status class, as JSON
+    pub fn responses_json(&self) -> serde_json::Value {
+        STATUS_CLASSES
+            .iter()
+            .zip(&self.responses)
+            .map(|(class, count)| (class.to_string(), count.load(Ordering::Relaxed).into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "veloserve_requests_total",
+            "Requests received",
+            &self.requests,
+        );
+
+        header(
+            &mut out,
+            "veloserve_responses_total",
+            "Responses by status class",
+            "counter",
+        );
+        for (class, count) in STATUS_CLASSES.iter().zip(&self.responses) {
+            let _ = writeln!(
+                out,
+                "veloserve_responses_total{{class=\"{}\"}} {}",
+                class,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        counter(
+            &mut out,
+            "veloserve_response_bytes_total",
+            "Response body bytes sent",
+            &self.bytes_sent,
+        );
+        counter(
+            &mut out,
+            "veloserve_cache_hits_total",
+            "Responses served from the page cache",
+            &self.cache_hits,
+        );
+        counter(
+            &mut out,
+            "veloserve_cache_misses_total",
+            "Cacheable responses not found in the page cache",
+            &self.cache_misses,
+        );
+        counter(
+            &mut out,
+            "veloserve_php_executions_total",
+            "PHP scripts run",
+            &self.php_executions,
+        );
+        counter(
+            &mut out,
+            "veloserve_php_errors_total",
+            "PHP runs that ended in a server error",
+            &self.php_errors,
+        );
+        counter_value(
+            &mut out,
+            "veloserve_panics_total",
+            "Request handler panics caught",
+            super::panics::panics_total(),
+        );
+
+        self.request_duration.render(
+            &mut out,
+            "veloserve_request_duration_seconds",
+            "Time from receiving a request to sending the last body byte",
+        );
+        self.php_duration.render(
+            &mut out,
+            "veloserve_php_duration_seconds",
+            "PHP execution time",
+        );
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    counter_value(out, name, help, value.load(Ordering::Relaxed));
+}
+
+fn counter_value(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = Metrics::new();
+        for _ in 0..3 {
+            metrics.request_started();
+        }
+        metrics.record_response(200, 512, Duration::from_millis(3), Some("HIT"));
+        metrics.record_response(404, 100, Duration::from_millis(40), None);
+        metrics.record_response(502, 0, Duration::from_secs(20), Some("MISS"));
+        metrics.record_php(Duration::from_millis(30), false);
+        metrics.record_php(Duration::from_millis(300), true);
+
+        let text = metrics.render_prometheus();
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "# TYPE veloserve_requests_total counter",
+            "veloserve_requests_total 3",
+            "veloserve_responses_total{class=\"2xx\"} 1",
+            "veloserve_responses_total{class=\"4xx\"} 1",
+            "veloserve_responses_total{class=\"5xx\"} 1",
+            "veloserve_response_bytes_total 612",
+            "veloserve_cache_hits_total 1",
+            "veloserve_cache_misses_total 1",
+            "veloserve_php_executions_total 2",
+            "veloserve_php_errors_total 1",
+            "# TYPE veloserve_request_duration_seconds histogram",
+            "veloserve_request_duration_seconds_bucket{le=\"0.005\"} 1",
+            "veloserve_request_duration_seconds_bucket{le=\"0.05\"} 2",
+            "veloserve_request_duration_seconds_bucket{le=\"10\"} 2",
+            "veloserve_request_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "veloserve_request_duration_seconds_sum 20.043",
+            "veloserve_request_duration_seconds_count 3",
+            "veloserve_php_duration_seconds_bucket{le=\"0.025\"} 0",
+            "veloserve_php_duration_seconds_bucket{le=\"0.05\"} 1",
+            "veloserve_php_duration_seconds_bucket{le=\"0.5\"} 2",
+            "veloserve_php_duration_seconds_count 2",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "missing {:?} in\n{}",
+                expected,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_requests_are_counted_on_arrival() {
+        let metrics = Metrics::new();
+        metrics.request_started();
+        metrics.request_started();
+        assert_eq!(metrics.requests_total(), 2);
+        assert_eq!(metrics.responses_json()["2xx"], 0);
+
+        metrics.record_response(204, 0, Duration::ZERO, None);
+        metrics.record_response(301, 0, Duration::ZERO, None);
+        assert_eq!(metrics.requests_total(), 2);
+        assert_eq!(
+            metrics.responses_json(),
+            serde_json::json!({"1xx": 0, "2xx": 1, "3xx": 1, "4xx": 0, "5xx": 0})
+        );
+    }
+}
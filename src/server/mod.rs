@@ -2,16 +2,83 @@
 //!
 //! Core HTTP/1.1 and HTTP/2 server implementation using Hyper and Tokio.
 
+mod access_log;
+mod activation;
+mod autoindex;
+mod cache_control;
 mod cache_warmer;
+mod client_addr;
+mod compression;
+mod connections;
+#[cfg(unix)]
+mod control;
+mod cron;
+mod docroot;
+mod h2c;
 mod handler;
+mod image_optimizer;
+mod invalidation_guard;
+mod listener;
+mod litespeed;
+mod log_format;
+mod metrics;
+mod ocsp;
+mod panics;
+#[cfg(unix)]
+mod privileges;
+mod proxy;
+mod readonly;
+mod reload;
+mod response;
+mod rewrite;
 mod router;
+mod scheduler;
+mod security_headers;
+mod shared_limits;
+mod state;
 mod static_files;
+mod streaming;
+mod symlinks;
 pub mod tls;
+mod vhost;
+mod zerocopy;
 
+pub use access_log::AccessLog;
+pub use activation::ActivatedListeners;
 pub use cache_warmer::{CacheWarmer, WarmRequestPayload};
-pub use handler::RequestHandler;
-pub use router::Router;
-pub use static_files::StaticFileHandler;
+pub use client_addr::{ConnectionInfo, TrustedProxies};
+pub use connections::ConnectionLimiter;
+pub use cron::CronExpr;
+pub use docroot::{DocrootHealth, Probe};
+pub use handler::{HandlerServices, RequestHandler};
+pub use image_optimizer::{ImageFormat, ImageOptimizer, ImageVariant};
+pub use invalidation_guard::{InvalidationGuard, InvalidationSnapshot};
+#[cfg(unix)]
+pub use listener::{bind_tcp, bind_unix};
+pub use listener::{parse_socket_mode, ListenAddr, Listener};
+pub use log_format::{AccessLogContext, LogFormat, LogVariable, UpstreamTime};
+pub use metrics::Metrics;
+pub use panics::{isolate, panics_total, test_trigger, Isolated, RequestPanic};
+#[cfg(unix)]
+pub use privileges::PrivilegeDrop;
+pub use proxy::Upstream;
+pub use readonly::ReadOnlyMode;
+pub use reload::{reload, restart_required, ReloadReport};
+pub use rewrite::{OriginalUri, Rewrite, Rewrites};
+pub use router::{RouteHandler, RouteMatch, Router};
+pub use scheduler::{CacheScheduler, ScheduledJob};
+pub use security_headers::SecurityHeaders;
+pub use shared_limits::{Limiter, LimitsSnapshot, SharedLimits, SharedVerdict};
+pub use state::{
+    export_state, import_state, ExportOptions, ImportReport, RuntimeState, MAX_SNAPSHOT_BYTES,
+};
+pub use static_files::{CacheControlRules, MimeTypes, StaticFileHandler};
+pub use streaming::{ResponseBody, StreamingBody};
+pub(crate) use vhost::glob_matches;
+pub use vhost::{
+    CacheExclude, CacheVary, CompiledConfig, CompiledLocation, CompiledVhost, ConfigHandle,
+    DenyList, PathMatcher, RequestTimeout, TryFile, TryFiles,
+};
 
 use crate::cache::CacheManager;
 use crate::config::Config;
@@ -24,57 +91,140 @@ use hyper::server::conn::http1;
 use hyper::server::conn::http2;
 use hyper::service::service_fn;
 use hyper::{Request, Response};
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioIo, TokioTimer};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 /// VeloServe HTTP Server
 pub struct Server {
     config: Arc<Config>,
-    cache: Arc<CacheManager>,
-    warmer: Arc<CacheWarmer>,
-    php_pool: Arc<PhpPool>,
+    /// File the configuration was loaded from, re-read on SIGHUP
+    config_path: Option<PathBuf>,
+    context: Arc<ServerContext>,
+}
+
+/// A plain HTTP listener bound from `server.listen`
+enum HttpListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+/// Shared state handed to every request
+struct ServerContext {
+    config: Arc<ConfigHandle>,
+    services: HandlerServices,
+    access_log: AccessLog,
 }
 
 impl Server {
     /// Create a new server instance
     pub fn new(config: Config) -> Self {
+        panics::install_hook();
         let config = Arc::new(config);
         let cache = Arc::new(CacheManager::new(&config.cache));
         let warmer = CacheWarmer::new(config.clone());
         let php_pool = Arc::new(PhpPool::new(&config.php));
+        let config_handle = Arc::new(ConfigHandle::new(config.clone()));
+        let readonly = Arc::new(ReadOnlyMode::default());
+        let scheduler = CacheScheduler::new(config_handle.clone(), cache.clone(), readonly.clone());
+        let context = Arc::new(ServerContext {
+            config: config_handle,
+            services: HandlerServices {
+                cache,
+                warmer,
+                php_pool,
+                readonly,
+                images: Arc::new(ImageOptimizer::new(&config)),
+                scheduler,
+                limits: Arc::new(SharedLimits::new(&config)),
+                invalidations: Arc::new(InvalidationGuard::default()),
+                docroots: Arc::new(DocrootHealth::new()),
+                connections: Arc::new(ConnectionLimiter::new(config.server.max_connections)),
+                metrics: Arc::new(Metrics::new()),
+            },
+            access_log: AccessLog::default(),
+        });
 
         Self {
             config,
-            cache,
-            warmer,
-            php_pool,
+            config_path: None,
+            context,
         }
     }
 
-    /// Run the server (HTTP + optional HTTPS)
-    pub async fn run(&self) -> Result<()> {
-        let addr: SocketAddr = self.config.server.listen.parse()?;
+    /// Re-read `path` on SIGHUP
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
 
-        info!("Starting VeloServe on {}", addr);
+    /// Load the configuration file again and swap it in
+    ///
+    /// See the `reload` module for what takes effect without a restart.
+    pub fn reload(&self) -> Result<ReloadReport> {
+        let path = self
+            .config_path
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("no configuration file to reload"))?;
+        reload::reload(path, &self.config, &self.context.config)
+    }
 
+    /// Run the server (HTTP + optional HTTPS)
+    pub async fn run(&self) -> Result<()> {
+        let listen = self
+            .config
+            .server
+            .listen
+            .addresses()
+            .iter()
+            .map(|address| ListenAddr::parse(address))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(anyhow::Error::msg)?;
+
+        info!("Starting VeloServe on {}", self.config.server.listen);
+
+        // Probe PHP in the background so the listeners bind right away;
+        // PHP requests wait on the pool state for up to php.startup_grace_ms
         if self.config.php.enable {
             info!(
                 "Starting PHP worker pool with {} workers",
                 self.config.php.workers
             );
-            self.php_pool.start().await?;
+            let php_pool = self.context.services.php_pool.clone();
+            tokio::spawn(async move {
+                if let Err(e) = php_pool.start().await {
+                    error!("PHP worker pool failed to start: {}", e);
+                }
+            });
+        }
+        if self.config.cache.persist {
+            self.restore_cache().await;
+            self.save_cache_periodically();
+        }
+        self.context.services.warmer.start();
+        self.context.services.cache.start();
+        self.context.services.scheduler.start();
+
+        let mut activated = ActivatedListeners::from_env()?;
+        if let Some(ref sockets) = activated {
+            info!(
+                "systemd socket activation in use ({} socket(s) passed)",
+                sockets.len()
+            );
         }
-        self.warmer.start();
 
-        let http_listener = TcpListener::bind(addr).await?;
-        info!("Server listening on http://{}", addr);
+        let mut http_listeners = Vec::new();
+        for listen in listen {
+            http_listeners.push(self.bind_http(listen, activated.as_mut()).await?);
+        }
 
-        // Start HTTPS listener if configured and certs are available
-        let tls_handle = if tls::can_enable_tls(&self.config) {
+        // Bind HTTPS listener if configured and certs are available
+        let tls = if tls::can_enable_tls(&self.config) {
             let ssl_addr: SocketAddr = self
                 .config
                 .server
@@ -86,25 +236,25 @@ impl Server {
             match tls::build_tls_config(&self.config) {
                 Ok(tls_config) => {
                     let tls_acceptor = TlsAcceptor::from(Arc::new(tls_config));
-                    let tls_listener = TcpListener::bind(ssl_addr).await?;
-                    info!("Server listening on https://{}", ssl_addr);
-
-                    let config = self.config.clone();
-                    let cache = self.cache.clone();
-                    let warmer = self.warmer.clone();
-                    let php_pool = self.php_pool.clone();
-
-                    Some(tokio::spawn(async move {
-                        Self::accept_tls_loop(
-                            tls_listener,
-                            tls_acceptor,
-                            config,
-                            cache,
-                            warmer,
-                            php_pool,
-                        )
-                        .await;
-                    }))
+                    let tls_listener = match activated
+                        .as_mut()
+                        .and_then(|a| a.take("https", Some(ssl_addr)))
+                    {
+                        Some(listener) => {
+                            let listener = TcpListener::from_std(listener)?;
+                            info!(
+                                "Server listening on https://{} (socket-activated)",
+                                listener.local_addr()?
+                            );
+                            listener
+                        }
+                        None => {
+                            let listener = bind_tcp(ssl_addr)?;
+                            info!("Server listening on https://{}", ssl_addr);
+                            listener
+                        }
+                    };
+                    Some((tls_listener, tls_acceptor))
                 }
                 Err(e) => {
                     error!("Failed to configure TLS, HTTPS disabled: {}", e);
@@ -115,8 +265,90 @@ impl Server {
             None
         };
 
-        // HTTP accept loop (runs forever)
-        self.accept_http_loop(http_listener).await;
+        if let Some(ref sockets) = activated {
+            if !sockets.is_empty() {
+                warn!(
+                    "Ignoring unused socket-activated listeners: {}",
+                    sockets.describe_remaining().join(", ")
+                );
+            }
+        }
+
+        if let Some(path) = &self.config.server.access_log {
+            self.context
+                .access_log
+                .open(path.as_ref())
+                .await
+                .map_err(|e| anyhow::anyhow!("Cannot open access log {}: {}", path, e))?;
+        }
+
+        #[cfg(unix)]
+        let control = match &self.config.server.control_socket {
+            Some(path) => Some(bind_control_socket(path.as_ref())?),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if self.config.server.control_socket.is_some() {
+            warn!("server.control_socket needs a Unix platform; not listening on it");
+        }
+
+        // Everything privileged (binding, reading TLS keys, opening logs) is done
+        #[cfg(unix)]
+        privileges::drop_privileges(&self.config)?;
+
+        #[cfg(unix)]
+        if let Some(listener) = control {
+            tokio::spawn(control::serve(listener, self.context.services.clone()));
+        }
+
+        #[cfg(unix)]
+        self.reload_on_sighup()?;
+
+        let tls_handle = tls.map(|(tls_listener, tls_acceptor)| {
+            let context = self.context.clone();
+            tokio::spawn(async move {
+                Self::accept_tls_loop(tls_listener, tls_acceptor, context).await;
+            })
+        });
+
+        // One accept loop per HTTP listener; they run forever
+        let http_handles: Vec<_> = http_listeners
+            .into_iter()
+            .map(|listener| {
+                let context = self.context.clone();
+                match listener {
+                    HttpListener::Tcp(listener) => {
+                        tokio::spawn(Self::accept_http_loop(listener, context))
+                    }
+                    #[cfg(unix)]
+                    HttpListener::Unix(listener) => {
+                        tokio::spawn(Self::accept_http_loop(listener, context))
+                    }
+                }
+            })
+            .collect();
+        let accept_loops = async {
+            for handle in http_handles {
+                if let Err(e) = handle.await {
+                    error!("HTTP accept loop ended: {}", e);
+                }
+            }
+        };
+        if self.config.cache.persist {
+            tokio::select! {
+                _ = accept_loops => {}
+                _ = shutdown_signal() => {
+                    info!("Shutting down, saving the memory cache");
+                    save_cache(
+                        self.context.services.cache.clone(),
+                        state::persist_path(&self.config.cache),
+                    )
+                    .await;
+                }
+            }
+        } else {
+            accept_loops.await;
+        }
 
         if let Some(h) = tls_handle {
             h.abort();
@@ -124,40 +356,211 @@ impl Server {
         Ok(())
     }
 
-    async fn accept_http_loop(&self, listener: TcpListener) {
+    /// Load the snapshot `cache.persist` left behind by the last run
+    async fn restore_cache(&self) {
+        let path = state::persist_path(&self.config.cache);
+        // Created while still privileged so the privilege drop hands it over
+        if let Err(e) = std::fs::create_dir_all(&self.config.cache.disk_path) {
+            warn!(
+                "Cannot create cache directory {}: {}",
+                self.config.cache.disk_path, e
+            );
+        }
+
+        match state::load_from_file(&self.context.services.cache, &path).await {
+            Ok(Some(report)) => {
+                info!(
+                    "Restored {} cache entries from {} ({} expired)",
+                    report.restored,
+                    path.display(),
+                    report.expired
+                );
+                for section in &report.skipped {
+                    warn!(
+                        "Skipped section {} of {}: {}",
+                        section.id,
+                        path.display(),
+                        section.reason
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Ignoring cache snapshot {}, starting empty: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Save the memory cache every `cache.persist_interval` seconds
+    fn save_cache_periodically(&self) {
+        let interval = self.config.cache.persist_interval;
+        if interval == 0 {
+            return;
+        }
+        let cache = self.context.services.cache.clone();
+        let path = state::persist_path(&self.config.cache);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(std::time::Duration::from_secs(interval));
+            // The first tick completes immediately
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                save_cache(cache.clone(), path.clone()).await;
+            }
+        });
+    }
+
+    /// Reload the configuration on every SIGHUP
+    #[cfg(unix)]
+    fn reload_on_sighup(&self) -> Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let path = self.config_path.clone();
+        let running = self.config.clone();
+        let handle = self.context.config.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                let Some(path) = &path else {
+                    warn!("SIGHUP received, but there is no configuration file to reload");
+                    continue;
+                };
+                info!("SIGHUP received, reloading {}", path.display());
+                match reload::reload(path, &running, &handle) {
+                    Ok(report) => {
+                        info!("Configuration reloaded ({} virtual hosts)", report.vhosts);
+                        if !report.restart_required.is_empty() {
+                            warn!(
+                                "Changes to {} need a restart to take effect",
+                                report.restart_required.join(", ")
+                            );
+                        }
+                    }
+                    Err(e) => error!(
+                        "Configuration reload failed, keeping the current one: {}",
+                        e
+                    ),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Bind one `server.listen` address, adopting a socket-activated
+    /// listener for it when systemd passed one
+    async fn bind_http(
+        &self,
+        listen: ListenAddr,
+        activated: Option<&mut ActivatedListeners>,
+    ) -> Result<HttpListener> {
+        match listen {
+            ListenAddr::Tcp(addr) => match activated.and_then(|a| a.take("http", Some(addr))) {
+                Some(listener) => {
+                    let listener = TcpListener::from_std(listener)?;
+                    info!(
+                        "Server listening on http://{} (socket-activated)",
+                        listener.local_addr()?
+                    );
+                    Ok(HttpListener::Tcp(listener))
+                }
+                None => {
+                    let listener = bind_tcp(addr)
+                        .map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", addr, e))?;
+                    info!("Server listening on http://{}", addr);
+                    Ok(HttpListener::Tcp(listener))
+                }
+            },
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                let mode = parse_socket_mode(&self.config.server.socket_mode)
+                    .map_err(anyhow::Error::msg)?;
+                let listener = bind_unix(&path, mode)
+                    .map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", path.display(), e))?;
+                info!("Server listening on unix:{}", path.display());
+                Ok(HttpListener::Unix(listener))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(path) => Err(anyhow::anyhow!(
+                "Cannot listen on {}: Unix sockets need a Unix platform",
+                path.display()
+            )),
+        }
+    }
+
+    async fn accept_http_loop<L: Listener>(listener: L, context: Arc<ServerContext>) {
         loop {
-            let (stream, remote_addr) = match listener.accept().await {
+            let (mut stream, conn) = match listener.accept().await {
                 Ok(conn) => conn,
                 Err(e) => {
                     error!("HTTP accept error: {}", e);
                     continue;
                 }
             };
-            debug!("Accepted HTTP connection from {}", remote_addr);
+            debug!("Accepted HTTP connection from {}", conn.peer);
 
-            let config = self.config.clone();
-            let cache = self.cache.clone();
-            let warmer = self.warmer.clone();
-            let php_pool = self.php_pool.clone();
+            let Some(permit) = context.services.connections.admit(conn.peer).await else {
+                drop(stream);
+                continue;
+            };
+            let context = context.clone();
 
             tokio::spawn(async move {
-                let io = TokioIo::new(stream);
-                let service = service_fn(move |req: Request<hyper::body::Incoming>| {
-                    let config = config.clone();
-                    let cache = cache.clone();
-                    let warmer = warmer.clone();
-                    let php_pool = php_pool.clone();
-                    async move {
-                        handle_request(req, remote_addr, config, cache, warmer, php_pool, false)
-                            .await
+                let _permit = permit;
+                let (sendfile, h2c, keepalive_timeout) = {
+                    let compiled = context.config.load();
+                    let server = &compiled.config.server;
+                    (
+                        server.sendfile,
+                        server.h2c_prior_knowledge,
+                        server.keepalive_timeout,
+                    )
+                };
+
+                // With h2c on, the first bytes tell HTTP/2 prior knowledge
+                // from HTTP/1.1; they are replayed to whichever serves it
+                let (h2, prefix) = if h2c {
+                    match tokio::time::timeout(keepalive_timeout, h2c::sniff(&mut stream)).await {
+                        Ok(Ok(sniffed)) => sniffed,
+                        Ok(Err(e)) => {
+                            debug!("Reading from {} failed: {}", conn.peer, e);
+                            return;
+                        }
+                        Err(_) => return,
                     }
-                });
+                } else {
+                    (false, Bytes::new())
+                };
 
-                let conn = http1::Builder::new()
-                    .keep_alive(true)
-                    .serve_connection(io, service);
+                let zero_copy = if sendfile && !h2 {
+                    L::zero_copy(&mut stream)
+                } else {
+                    None
+                };
+                let mut http1 = http1_builder(&context);
+                if zero_copy.is_some() {
+                    // Body chunks must reach the stream as they are
+                    http1.writev(true);
+                }
+                let io = TokioIo::new(h2c::Rewind::new(prefix, stream));
+                let service = service_fn(move |mut req: Request<hyper::body::Incoming>| {
+                    if let Some(slot) = &zero_copy {
+                        req.extensions_mut().insert(slot.clone());
+                    }
+                    let context = context.clone();
+                    async move { handle_request(req, conn, context).await }
+                });
 
-                if let Err(e) = conn.await {
+                let served = if h2 {
+                    debug!("HTTP/2 prior knowledge from {}", conn.peer);
+                    http2_builder(keepalive_timeout)
+                        .serve_connection(io, service)
+                        .await
+                } else {
+                    http1.serve_connection(io, service).with_upgrades().await
+                };
+                if let Err(e) = served {
                     if !is_connection_closed_error(&e) {
                         error!("Connection error: {}", e);
                     }
@@ -169,10 +572,7 @@ impl Server {
     async fn accept_tls_loop(
         listener: TcpListener,
         acceptor: TlsAcceptor,
-        config: Arc<Config>,
-        cache: Arc<CacheManager>,
-        warmer: Arc<CacheWarmer>,
-        php_pool: Arc<PhpPool>,
+        context: Arc<ServerContext>,
     ) {
         loop {
             let (stream, remote_addr) = match listener.accept().await {
@@ -182,39 +582,51 @@ impl Server {
                     continue;
                 }
             };
+            let conn = ConnectionInfo::new(remote_addr, stream.local_addr().ok(), true);
 
+            let Some(permit) = context.services.connections.admit(remote_addr).await else {
+                drop(stream);
+                continue;
+            };
             let acceptor = acceptor.clone();
-            let config = config.clone();
-            let cache = cache.clone();
-            let warmer = warmer.clone();
-            let php_pool = php_pool.clone();
+            let context = context.clone();
 
             tokio::spawn(async move {
-                let tls_stream = match acceptor.accept(stream).await {
-                    Ok(s) => s,
-                    Err(e) => {
-                        debug!("TLS handshake failed from {}: {}", remote_addr, e);
-                        return;
-                    }
-                };
-
+                let _permit = permit;
+                // The handshake gets the same budget as reading request headers
+                let handshake_timeout = context.config.load().config.server.keepalive_timeout;
+                let tls_stream =
+                    match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                        Ok(Ok(s)) => s,
+                        Ok(Err(e)) => {
+                            debug!("TLS handshake failed from {}: {}", remote_addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake from {} timed out", remote_addr);
+                            return;
+                        }
+                    };
+
+                // ALPN picks the protocol; clients that don't negotiate get HTTP/1.1
+                let h2 = tls_stream.get_ref().1.alpn_protocol() == Some(b"h2".as_slice());
                 let io = TokioIo::new(tls_stream);
+                let keepalive_timeout = context.config.load().config.server.keepalive_timeout;
+                let http1 = http1_builder(&context);
                 let service = service_fn(move |req: Request<hyper::body::Incoming>| {
-                    let config = config.clone();
-                    let cache = cache.clone();
-                    let warmer = warmer.clone();
-                    let php_pool = php_pool.clone();
-                    async move {
-                        handle_request(req, remote_addr, config, cache, warmer, php_pool, true)
-                            .await
-                    }
+                    let context = context.clone();
+                    async move { handle_request(req, conn, context).await }
                 });
 
-                let conn = http1::Builder::new()
-                    .keep_alive(true)
-                    .serve_connection(io, service);
-
-                if let Err(e) = conn.await {
+                let served = if h2 {
+                    debug!("Negotiated HTTP/2 with {}", remote_addr);
+                    http2_builder(keepalive_timeout)
+                        .serve_connection(io, service)
+                        .await
+                } else {
+                    http1.serve_connection(io, service).with_upgrades().await
+                };
+                if let Err(e) = served {
                     if !is_connection_closed_error(&e) {
                         error!("TLS connection error: {}", e);
                     }
@@ -222,49 +634,99 @@ impl Server {
             });
         }
     }
+}
 
-    /// Run the server with HTTP/2 support (requires TLS)
-    #[allow(dead_code)]
-    pub async fn run_h2(&self, listener: TcpListener) -> Result<()> {
-        info!("Starting HTTP/2 server");
-
-        loop {
-            let (stream, remote_addr) = listener.accept().await?;
-            debug!("Accepted HTTP/2 connection from {}", remote_addr);
-
-            let config = self.config.clone();
-            let cache = self.cache.clone();
-            let warmer = self.warmer.clone();
-            let php_pool = self.php_pool.clone();
-
-            tokio::spawn(async move {
-                let io = TokioIo::new(stream);
-
-                let service = service_fn(move |req: Request<hyper::body::Incoming>| {
-                    let config = config.clone();
-                    let cache = cache.clone();
-                    let warmer = warmer.clone();
-                    let php_pool = php_pool.clone();
-
-                    async move {
-                        handle_request(req, remote_addr, config, cache, warmer, php_pool, true)
-                            .await
-                    }
-                });
-
-                let conn = http2::Builder::new(TokioExecutor).serve_connection(io, service);
+/// Smallest read buffer hyper accepts
+const MIN_HTTP1_BUFFER: usize = 8192;
+
+/// Write the `cache.persist` snapshot off the async workers
+async fn save_cache(cache: Arc<CacheManager>, path: PathBuf) {
+    let target = path.clone();
+    match tokio::task::spawn_blocking(move || state::save_to_file(&cache, &target)).await {
+        Ok(Ok(bytes)) => debug!(
+            "Saved the memory cache to {} ({} bytes)",
+            path.display(),
+            bytes
+        ),
+        Ok(Err(e)) => error!("Cannot save the memory cache to {}: {}", path.display(), e),
+        Err(e) => error!("Saving the memory cache failed: {}", e),
+    }
+}
 
-                if let Err(e) = conn.await {
-                    error!("HTTP/2 connection error: {}", e);
+/// Resolves on SIGTERM or SIGINT
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
                 }
-            });
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
         }
     }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Bind `server.control_socket`, readable and writable by the server's
+/// owner only
+#[cfg(unix)]
+fn bind_control_socket(path: &std::path::Path) -> Result<tokio::net::UnixListener> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| anyhow::anyhow!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    let listener = bind_unix(path, 0o600).map_err(|e| {
+        anyhow::anyhow!("Cannot listen on control socket {}: {}", path.display(), e)
+    })?;
+    info!("Control socket listening on {}", path.display());
+    Ok(listener)
+}
+
+/// HTTP/1 connection settings from the current config snapshot
+///
+/// `keepalive_timeout` bounds reading each request head: both the idle wait
+/// between keep-alive requests and a client that stalls mid-headers are
+/// disconnected when it runs out. Heads up to twice `max_uri_length` plus
+/// `max_header_bytes` are read so the handler can answer them with a 414 or
+/// 431 page; anything larger is refused by hyper before it is buffered.
+fn http1_builder(context: &ServerContext) -> http1::Builder {
+    let compiled = context.config.load();
+    let server = &compiled.config.server;
+    let max_head = (server.max_uri_length + server.max_header_bytes).saturating_mul(2);
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .keep_alive(true)
+        .header_read_timeout(server.keepalive_timeout)
+        .max_buf_size(max_head.max(MIN_HTTP1_BUFFER));
+    builder
+}
+
+/// HTTP/2 connection settings
+///
+/// Idle connections are probed with a PING every `keepalive_timeout` and
+/// dropped when one goes unanswered.
+fn http2_builder(keepalive_timeout: std::time::Duration) -> http2::Builder<TokioExecutor> {
+    let mut builder = http2::Builder::new(TokioExecutor);
+    builder
+        .timer(TokioTimer::new())
+        .keep_alive_interval(keepalive_timeout);
+    builder
 }
 
 /// Check if error is just a closed connection (not worth logging)
 fn is_connection_closed_error(e: &hyper::Error) -> bool {
-    if e.is_incomplete_message() {
+    // Idle or stalled clients dropped by header_read_timeout
+    if e.is_incomplete_message() || e.is_timeout() {
         return true;
     }
     if let Some(source) = e.source() {
@@ -282,27 +744,70 @@ fn is_connection_closed_error(e: &hyper::Error) -> bool {
 
 /// Handle incoming HTTP request
 async fn handle_request(
-    req: Request<hyper::body::Incoming>,
-    remote_addr: SocketAddr,
-    config: Arc<Config>,
-    cache: Arc<CacheManager>,
-    warmer: Arc<CacheWarmer>,
-    php_pool: Arc<PhpPool>,
-    _is_https: bool,
-) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    mut req: Request<hyper::body::Incoming>,
+    conn: ConnectionInfo,
+    context: Arc<ServerContext>,
+) -> Result<Response<ResponseBody>, hyper::Error> {
+    // HTTP/2 carries the host in the :authority pseudo-header; vhost
+    // lookup and everything after it read Host
+    if !req.headers().contains_key(hyper::header::HOST) {
+        let authority = req
+            .uri()
+            .authority()
+            .and_then(|authority| hyper::header::HeaderValue::from_str(authority.as_str()).ok());
+        if let Some(authority) = authority {
+            req.headers_mut().insert(hyper::header::HOST, authority);
+        }
+    }
+
     let method = req.method().clone();
     let uri = req.uri().clone();
     let start = std::time::Instant::now();
+    let received_at = chrono::Local::now();
 
-    debug!("{} {} from {}", method, uri, remote_addr);
+    debug!("{} {} from {}", method, uri, conn.peer);
+    context.services.metrics.request_started();
 
-    // Create request handler
-    let handler = RequestHandler::new(config, cache, warmer, php_pool);
-
-    // Handle the request
-    let response = match handler.handle(req).await {
-        Ok(resp) => resp,
-        Err(e) => {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let host = header("host").map(|host| host.split(':').next().unwrap_or("").to_string());
+    let referer = header("referer");
+    let user_agent = header("user-agent");
+    let request_id = header("x-veloserve-request-id").or_else(|| header("x-request-id"));
+    let protocol = format!("{:?}", req.version());
+
+    // Create request handler on the current config snapshot
+    let compiled = context.config.load();
+
+    // PHP and the access log see the client behind a trusted proxy
+    let conn = conn.with_forwarded(req.headers(), &compiled.trusted_proxies);
+    req.extensions_mut().insert(conn);
+    let handler = RequestHandler::new(compiled.clone(), &context.services);
+
+    let timeout = compiled.request_timeout(host.as_deref().unwrap_or("localhost"), uri.path());
+
+    // Handle the request; dropping the future on timeout also kills any PHP child,
+    // and a panic in any phase is contained to this request
+    let handling = panics::isolate(handler.handle(req));
+    let mut response = match tokio::time::timeout(timeout.duration, handling).await {
+        Ok(Ok(Ok(resp))) => resp,
+        Ok(Err(panic)) => {
+            error!(
+                "Request handler panicked on {} {}: {}\n{}",
+                method, uri, panic, panic.backtrace
+            );
+            Response::builder()
+                .status(500)
+                .header("Content-Type", "text/plain")
+                .header("Server", crate::SERVER_NAME)
+                .body(Full::new(Bytes::from("Internal Server Error")))
+                .unwrap()
+        }
+        Ok(Ok(Err(e))) => {
             error!("Request handling error: {}", e);
             Response::builder()
                 .status(500)
@@ -311,21 +816,87 @@ async fn handle_request(
                 .body(Full::new(Bytes::from("Internal Server Error")))
                 .unwrap()
         }
+        Err(_) => {
+            warn!(
+                target: "veloserve::slow",
+                "{} {} timed out after {} ({})",
+                method,
+                uri,
+                crate::config::format_duration(timeout.duration),
+                timeout.source
+            );
+            Response::builder()
+                .status(504)
+                .header("Content-Type", "text/plain")
+                .header("Server", crate::SERVER_NAME)
+                .body(Full::new(Bytes::from("Gateway Timeout")))
+                .unwrap()
+        }
     };
 
-    let duration = start.elapsed();
-    let status = response.status();
+    let elapsed = start.elapsed();
+    if let Some(threshold) = compiled.config.server.slow_request_threshold {
+        if elapsed >= threshold && elapsed < timeout.duration {
+            warn!(
+                target: "veloserve::slow",
+                "{} {} took {}ms (timeout {} from {})",
+                method,
+                uri,
+                elapsed.as_millis(),
+                crate::config::format_duration(timeout.duration),
+                timeout.source
+            );
+        }
+    }
 
-    info!(
-        "{} {} {} {} {:?}",
-        remote_addr,
-        method,
-        uri,
-        status.as_u16(),
-        duration
-    );
+    let server = context;
+    let mut context = AccessLogContext {
+        remote_addr: Some(conn.client),
+        scheme: conn.scheme(),
+        remote_user: None,
+        host,
+        time: received_at,
+        method: method.to_string(),
+        uri: uri
+            .path_and_query()
+            .map(|pq| pq.as_str().to_string())
+            .unwrap_or_else(|| uri.path().to_string()),
+        protocol,
+        status: response.status().as_u16(),
+        body_bytes_sent: 0,
+        referer,
+        user_agent,
+        request_time: elapsed,
+        upstream_time: response
+            .extensions()
+            .get::<UpstreamTime>()
+            .map(|upstream| upstream.0),
+        cache_status: response
+            .headers()
+            .get("X-Cache")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        request_id,
+    };
+    // Metrics and the access log still see the page cache status
+    if !compiled.config.cache.debug_headers {
+        response.headers_mut().remove("X-Cache");
+    }
 
-    Ok(response)
+    // Logged once the body is out, with the bytes actually sent
+    Ok(response::finalize(response, &method, move |sent| {
+        context.body_bytes_sent = sent;
+        context.request_time = start.elapsed();
+        server.services.metrics.record_response(
+            context.status,
+            sent,
+            context.request_time,
+            context.cache_status.as_deref(),
+        );
+        server
+            .access_log
+            .write(compiled.log_format.render(&context));
+    }))
 }
 
 /// Tokio executor for HTTP/2
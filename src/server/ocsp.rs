@@ -0,0 +1,672 @@
+//! OCSP stapling (`ssl.ocsp_stapling`)
+//!
+//! For each certificate we read the responder URL from its Authority
+//! Information Access extension, POST an OCSP request for it and hand the
+//! DER response to rustls, which staples it into handshakes. A response is
+//! only stapled if it is signed by the issuer (or a responder the issuer
+//! delegated to), answers for the served certificate, says "good" and hasn't
+//! passed its `nextUpdate`.
+//!
+//! Certificates and responses are decoded with x509-parser; only the small
+//! request is encoded by hand.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request, StatusCode};
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use rustls::pki_types::CertificateDer;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::der_parser::asn1_rs::{
+    Any, BitString, Class, Enumerated, FromDer, GeneralizedTime, OctetString, Oid, Tag,
+};
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::oid_registry::{OID_HASH_SHA1, OID_PKIX_ACCESS_DESCRIPTOR_OCSP};
+use x509_parser::time::ASN1Time;
+use x509_parser::verify::verify_signature;
+use x509_parser::x509::AlgorithmIdentifier;
+
+/// How long a responder gets to answer
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest OCSP response accepted
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// How far ahead of our clock a response's `thisUpdate` may be
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+
+/// id-pkix-ocsp-basic, 1.3.6.1.5.5.7.48.1.1
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// A fetched OCSP response, ready to staple
+#[derive(Debug, Clone)]
+pub struct Staple {
+    /// DER `OCSPResponse`, as sent to clients
+    pub response: Vec<u8>,
+    /// When the responder says a newer response will be available
+    pub next_update: Option<SystemTime>,
+}
+
+/// The request to send for the first certificate of `chain`, whose issuer
+/// must come second
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcspRequest {
+    /// Responder URL from the certificate
+    pub url: String,
+    /// DER `OCSPRequest`
+    pub body: Vec<u8>,
+    /// The certificate asked about, which the response must answer for
+    cert_id: CertId,
+    /// DER issuer certificate, whose key signs responses
+    issuer: Vec<u8>,
+}
+
+impl OcspRequest {
+    pub fn new(chain: &[CertificateDer<'_>]) -> Result<Self> {
+        let leaf = chain.first().context("empty certificate chain")?;
+        let (_, leaf) = X509Certificate::from_der(leaf).context("unreadable certificate")?;
+        let url = ocsp_url(&leaf).context("certificate names no OCSP responder")?;
+        let issuer_der = chain
+            .get(1)
+            .context("chain has no issuer certificate after the leaf")?;
+        let (_, issuer) =
+            X509Certificate::from_der(issuer_der).context("unreadable issuer certificate")?;
+        if leaf.verify_signature(Some(issuer.public_key())).is_err() {
+            bail!("the certificate after the leaf did not sign it");
+        }
+
+        let cert_id = CertId {
+            name_hash: sha1(leaf.issuer().as_raw()),
+            key_hash: sha1(&issuer.public_key().subject_public_key.data),
+            serial: leaf.raw_serial().to_vec(),
+        };
+        // OCSPRequest { TBSRequest { requestList { Request { reqCert } } } }
+        let request = der(TAG_SEQUENCE, &cert_id.encode());
+        let request_list = der(TAG_SEQUENCE, &request);
+        let tbs_request = der(TAG_SEQUENCE, &request_list);
+        Ok(Self {
+            url,
+            body: der(TAG_SEQUENCE, &tbs_request),
+            cert_id,
+            issuer: issuer_der.to_vec(),
+        })
+    }
+}
+
+/// A certificate as OCSP names it: by its issuer and serial number
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CertId {
+    /// SHA-1 of the issuer's name
+    name_hash: Vec<u8>,
+    /// SHA-1 of the issuer's public key
+    key_hash: Vec<u8>,
+    /// Serial number, as DER INTEGER content
+    serial: Vec<u8>,
+}
+
+impl CertId {
+    fn encode(&self) -> Vec<u8> {
+        let hash_algorithm = der(
+            TAG_SEQUENCE,
+            &[der(TAG_OID, OID_HASH_SHA1.as_bytes()), der(TAG_NULL, &[])].concat(),
+        );
+        der(
+            TAG_SEQUENCE,
+            &[
+                hash_algorithm,
+                der(TAG_OCTET_STRING, &self.name_hash),
+                der(TAG_OCTET_STRING, &self.key_hash),
+                der(TAG_INTEGER, &self.serial),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Decode a CertID's content; one hashed with anything but SHA-1 can't
+    /// be ours
+    fn decode(content: &[u8]) -> Result<Option<Self>> {
+        let (rest, algorithm) = AlgorithmIdentifier::from_der(content)?;
+        if algorithm.algorithm != OID_HASH_SHA1 {
+            return Ok(None);
+        }
+        let (rest, name_hash) = OctetString::from_der(rest)?;
+        let (rest, key_hash) = OctetString::from_der(rest)?;
+        let (serial, _) = take(rest, Tag::Integer)?;
+        Ok(Some(Self {
+            name_hash: name_hash.as_cow().to_vec(),
+            key_hash: key_hash.as_cow().to_vec(),
+            serial: serial.to_vec(),
+        }))
+    }
+}
+
+/// Send `request` to its responder
+pub async fn fetch(request: &OcspRequest) -> Result<Staple> {
+    if !request.url.starts_with("http://") && !request.url.starts_with("https://") {
+        bail!("unsupported responder URL {}", request.url);
+    }
+    let connector = HttpsConnectorBuilder::new()
+        .with_provider_and_webpki_roots(rustls::crypto::ring::default_provider())?
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+    let http = Request::builder()
+        .method(Method::POST)
+        .uri(&request.url)
+        .header(CONTENT_TYPE, "application/ocsp-request")
+        .body(Full::new(Bytes::from(request.body.clone())))?;
+
+    let body = tokio::time::timeout(FETCH_TIMEOUT, async {
+        let response = client.request(http).await?;
+        if response.status() != StatusCode::OK {
+            bail!("responder answered {}", response.status());
+        }
+        Limited::new(response.into_body(), MAX_RESPONSE_BYTES)
+            .collect()
+            .await
+            .map(|body| body.to_bytes())
+            .map_err(|e| anyhow!("reading response: {}", e))
+    })
+    .await
+    .map_err(|_| anyhow!("{} timed out", request.url))??;
+
+    let next_update = parse_response(&body, request, SystemTime::now())?;
+    Ok(Staple {
+        response: body.to_vec(),
+        next_update,
+    })
+}
+
+/// Check an `OCSPResponse` is a signed, current "good" answer for
+/// `request`'s certificate and return its `nextUpdate`
+pub fn parse_response(
+    response: &[u8],
+    request: &OcspRequest,
+    now: SystemTime,
+) -> Result<Option<SystemTime>> {
+    let (outer, _) = take(response, Tag::Sequence)?;
+    let (rest, status) = Enumerated::from_der(outer)?;
+    if status.0 != 0 {
+        bail!("responder answered status {}", status.0);
+    }
+    // responseBytes [0] { responseType, response OCTET STRING }
+    let (bytes, _) = take_tagged(rest, 0)?;
+    let (bytes, _) = take(bytes.context("response has no body")?, Tag::Sequence)?;
+    let (rest, kind) = Oid::from_der(bytes)?;
+    if kind.as_bytes() != OID_OCSP_BASIC {
+        bail!("unsupported response type {}", kind);
+    }
+    let (_, basic) = OctetString::from_der(rest)?;
+    let basic = basic.as_cow();
+
+    // BasicOCSPResponse { tbsResponseData, signatureAlgorithm, signature, certs [0] }
+    let (basic, _) = take(basic, Tag::Sequence)?;
+    let (rest, _) = Any::from_der(basic)?;
+    let tbs = &basic[..basic.len() - rest.len()];
+    let (rest, algorithm) = AlgorithmIdentifier::from_der(rest)?;
+    let (rest, signature) = BitString::from_der(rest)?;
+    let mut certs = Vec::new();
+    if let (Some(list), _) = take_tagged(rest, 0)? {
+        let (mut list, _) = take(list, Tag::Sequence)?;
+        while !list.is_empty() {
+            let (rest, cert) = X509Certificate::from_der(list)?;
+            certs.push(cert);
+            list = rest;
+        }
+    }
+
+    let (_, issuer) = X509Certificate::from_der(&request.issuer)?;
+    let checked_at = ASN1Time::from_timestamp(unix_seconds(now)?)?;
+    let signed = std::iter::once(&issuer)
+        .chain(
+            certs
+                .iter()
+                .filter(|cert| is_delegated_responder(cert, &issuer, checked_at)),
+        )
+        .any(|signer| verify_signature(signer.public_key(), &algorithm, &signature, tbs).is_ok());
+    if !signed {
+        bail!("response is not signed by the issuer or a responder it delegated to");
+    }
+
+    // ResponseData { version [0], responderID, producedAt, responses, ... }
+    let (data, _) = take(tbs, Tag::Sequence)?;
+    let (_, data) = take_tagged(data, 0)?;
+    let (data, _) = Any::from_der(data)?;
+    let (data, _) = GeneralizedTime::from_der(data)?;
+    let (mut responses, _) = take(data, Tag::Sequence)?;
+    let single = loop {
+        if responses.is_empty() {
+            bail!("response does not cover this certificate");
+        }
+        let (single, rest) = take(responses, Tag::Sequence)?;
+        responses = rest;
+        let (cert_id, single) = take(single, Tag::Sequence)?;
+        if CertId::decode(cert_id)?.as_ref() == Some(&request.cert_id) {
+            break single;
+        }
+    };
+
+    // SingleResponse { certID, certStatus, thisUpdate, nextUpdate [0], ... },
+    // where a `good` certStatus is [0] IMPLICIT NULL
+    let (rest, status) = Any::from_der(single)?;
+    if status.class() != Class::ContextSpecific || status.tag() != Tag(0) {
+        bail!("certificate status is not good");
+    }
+    let (rest, this_update) = GeneralizedTime::from_der(rest)?;
+    if system_time(&this_update)? > now + MAX_CLOCK_SKEW {
+        bail!("response is not valid until {}", this_update);
+    }
+    match take_tagged(rest, 0)?.0 {
+        Some(next_update) => {
+            let (_, next_update) = GeneralizedTime::from_der(next_update)?;
+            let until = system_time(&next_update)?;
+            if until <= now {
+                bail!("response expired at {}", next_update);
+            }
+            Ok(Some(until))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Whether `cert` is a current OCSP responder certificate issued by `issuer`
+fn is_delegated_responder(
+    cert: &X509Certificate<'_>,
+    issuer: &X509Certificate<'_>,
+    now: ASN1Time,
+) -> bool {
+    cert.issuer().as_raw() == issuer.subject().as_raw()
+        && cert.validity().is_valid_at(now)
+        && cert.verify_signature(Some(issuer.public_key())).is_ok()
+        && matches!(cert.extended_key_usage(), Ok(Some(usage)) if usage.value.ocsp_signing)
+}
+
+/// The OCSP responder in the Authority Information Access extension
+fn ocsp_url(cert: &X509Certificate<'_>) -> Option<String> {
+    cert.extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(access) => {
+                access.iter().find_map(|desc| match desc.access_location {
+                    GeneralName::URI(uri)
+                        if desc.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP =>
+                    {
+                        Some(uri.to_string())
+                    }
+                    _ => None,
+                })
+            }
+            _ => None,
+        })
+}
+
+/// Split the element with `tag` off the front of `input`, returning its
+/// content and what follows it
+fn take(input: &[u8], tag: Tag) -> Result<(&[u8], &[u8])> {
+    let (rest, element) = Any::from_der(input)?;
+    element.tag().assert_eq(tag)?;
+    Ok((element.data, rest))
+}
+
+/// Like [`take`] for an optional context-specific `[tag]`; nothing is
+/// consumed when `input` doesn't start with one
+fn take_tagged(input: &[u8], tag: u32) -> Result<(Option<&[u8]>, &[u8])> {
+    match Any::from_der(input) {
+        Ok((rest, element))
+            if element.class() == Class::ContextSpecific && element.tag() == Tag(tag) =>
+        {
+            Ok((Some(element.data), rest))
+        }
+        _ => Ok((None, input)),
+    }
+}
+
+fn sha1(data: &[u8]) -> Vec<u8> {
+    digest(&SHA1_FOR_LEGACY_USE_ONLY, data).as_ref().to_vec()
+}
+
+fn system_time(time: &GeneralizedTime) -> Result<SystemTime> {
+    Ok(time.utc_datetime()?.into())
+}
+
+fn unix_seconds(time: SystemTime) -> Result<i64> {
+    let seconds = time.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+    Ok(i64::try_from(seconds)?)
+}
+
+/// Encode one DER element
+pub(crate) fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// A test PKI and OCSP responses signed with its keys
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, CustomExtension, DnType,
+        ExtendedKeyUsagePurpose, IsCa, KeyPair, SerialNumber,
+    };
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    const TAG_BIT_STRING: u8 = 0x03;
+    const TAG_ENUMERATED: u8 = 0x0a;
+    const TAG_GENERALIZED_TIME: u8 = 0x18;
+    /// `[0]` constructed
+    const TAG_CONTEXT_0: u8 = 0xa0;
+    /// `byKey [2]` constructed
+    const TAG_BY_KEY: u8 = 0xa2;
+    /// `uniformResourceIdentifier` in a GeneralName
+    const TAG_URI: u8 = 0x86;
+    /// `good` in a SingleResponse
+    pub const STATUS_GOOD: &[u8] = &[0x80, 0x00];
+    /// `revoked` with only a revocationTime
+    pub const STATUS_REVOKED: &[u8] = &[
+        0xa1, 0x11, 0x18, 0x0f, b'2', b'0', b'2', b'6', b'0', b'1', b'0', b'1', b'0', b'0', b'0',
+        b'0', b'0', b'0', b'Z',
+    ];
+    /// id-pe-authorityInfoAccess
+    const OID_AUTHORITY_INFO_ACCESS: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 1];
+    /// ecdsa-with-SHA256, 1.2.840.10045.4.3.2
+    const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+    /// A CA and a certificate it issued, with serial 0x42
+    pub struct Pki {
+        pub ca: Certificate,
+        pub ca_key: KeyPair,
+        pub leaf: Certificate,
+        pub leaf_key: KeyPair,
+    }
+
+    impl Pki {
+        /// The leaf names `ocsp_url` as its responder
+        pub fn new(ocsp_url: Option<&str>) -> Self {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "Test CA");
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = params.self_signed(&ca_key).unwrap();
+
+            let leaf_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.serial_number = Some(SerialNumber::from_slice(&[0x42]));
+            if let Some(url) = ocsp_url {
+                let description = [
+                    der(TAG_OID, OID_PKIX_ACCESS_DESCRIPTOR_OCSP.as_bytes()),
+                    der(TAG_URI, url.as_bytes()),
+                ]
+                .concat();
+                params
+                    .custom_extensions
+                    .push(CustomExtension::from_oid_content(
+                        OID_AUTHORITY_INFO_ACCESS,
+                        der(TAG_SEQUENCE, &der(TAG_SEQUENCE, &description)),
+                    ));
+            }
+            let leaf = params.signed_by(&leaf_key, &ca, &ca_key).unwrap();
+            Self {
+                ca,
+                ca_key,
+                leaf,
+                leaf_key,
+            }
+        }
+
+        /// The leaf followed by the CA
+        pub fn chain(&self) -> Vec<CertificateDer<'static>> {
+            vec![self.leaf.der().clone(), self.ca.der().clone()]
+        }
+
+        /// A responder certificate from the CA, allowed to sign OCSP
+        /// responses if `ocsp_signing`
+        pub fn responder(&self, ocsp_signing: bool) -> (Certificate, KeyPair) {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "Test OCSP");
+            if ocsp_signing {
+                params.extended_key_usages = vec![ExtendedKeyUsagePurpose::OcspSigning];
+            }
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            (cert, key)
+        }
+    }
+
+    /// The parts of a BasicOCSPResponse a test may want to vary
+    pub struct Response {
+        /// DER CertID
+        pub cert_id: Vec<u8>,
+        /// DER certStatus
+        pub status: &'static [u8],
+        pub this_update: &'static str,
+        pub next_update: Option<&'static str>,
+        /// Certificates sent along, for a delegated responder
+        pub certs: Vec<CertificateDer<'static>>,
+    }
+
+    impl Response {
+        /// A `good` answer for `request`'s certificate
+        pub fn good(request: &OcspRequest, next_update: Option<&'static str>) -> Self {
+            Self {
+                cert_id: request.cert_id.encode(),
+                status: STATUS_GOOD,
+                this_update: "20260101000000Z",
+                next_update,
+                certs: Vec::new(),
+            }
+        }
+
+        /// The whole `OCSPResponse`, signed with `key`
+        pub fn sign(&self, key: &KeyPair) -> Vec<u8> {
+            let time = |value: &str| der(TAG_GENERALIZED_TIME, value.as_bytes());
+            let mut single = [
+                self.cert_id.clone(),
+                self.status.to_vec(),
+                time(self.this_update),
+            ]
+            .concat();
+            if let Some(next_update) = self.next_update {
+                single.extend(der(TAG_CONTEXT_0, &time(next_update)));
+            }
+            let data = [
+                der(TAG_BY_KEY, &der(TAG_OCTET_STRING, &[7; 20])),
+                time(self.this_update),
+                der(TAG_SEQUENCE, &der(TAG_SEQUENCE, &single)),
+            ]
+            .concat();
+            let tbs = der(TAG_SEQUENCE, &data);
+
+            let rng = SystemRandom::new();
+            let signer = EcdsaKeyPair::from_pkcs8(
+                &ECDSA_P256_SHA256_ASN1_SIGNING,
+                &key.serialize_der(),
+                &rng,
+            )
+            .unwrap();
+            let signature = signer.sign(&rng, &tbs).unwrap();
+            let mut basic = [
+                tbs,
+                der(TAG_SEQUENCE, &der(TAG_OID, OID_ECDSA_SHA256)),
+                der(TAG_BIT_STRING, &[&[0], signature.as_ref()].concat()),
+            ]
+            .concat();
+            if !self.certs.is_empty() {
+                let certs: Vec<u8> = self.certs.iter().flat_map(|c| c.to_vec()).collect();
+                basic.extend(der(TAG_CONTEXT_0, &der(TAG_SEQUENCE, &certs)));
+            }
+
+            let bytes = [
+                der(TAG_OID, OID_OCSP_BASIC),
+                der(TAG_OCTET_STRING, &der(TAG_SEQUENCE, &basic)),
+            ]
+            .concat();
+            der(
+                TAG_SEQUENCE,
+                &[
+                    der(TAG_ENUMERATED, &[0]),
+                    der(TAG_CONTEXT_0, &der(TAG_SEQUENCE, &bytes)),
+                ]
+                .concat(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    /// 2026-10-17T00:00:00Z
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_792_195_200)
+    }
+
+    fn request(pki: &Pki) -> OcspRequest {
+        OcspRequest::new(&pki.chain()).unwrap()
+    }
+
+    #[test]
+    fn test_request_for_chain() {
+        let pki = Pki::new(Some("http://ocsp.example.net/"));
+        let request = request(&pki);
+        assert_eq!(request.url, "http://ocsp.example.net/");
+
+        // The CertID names the issuer by name and key hash, then the serial
+        let (_, ca) = X509Certificate::from_der(pki.ca.der()).unwrap();
+        assert_eq!(request.cert_id.name_hash, sha1(ca.subject().as_raw()));
+        assert_eq!(
+            request.cert_id.key_hash,
+            sha1(&ca.public_key().subject_public_key.data)
+        );
+        assert_eq!(request.cert_id.serial, [0x42]);
+        assert!(request
+            .body
+            .windows(request.cert_id.encode().len())
+            .any(|window| window == request.cert_id.encode()));
+
+        // Without a responder or an issuer there is nothing to ask
+        let bare = Pki::new(None);
+        assert!(OcspRequest::new(&bare.chain()).is_err());
+        assert!(OcspRequest::new(&pki.chain()[..1]).is_err());
+        // Nor when the second certificate didn't issue the first
+        let other = Pki::new(None);
+        assert!(OcspRequest::new(&[pki.leaf.der().clone(), other.ca.der().clone()]).is_err());
+    }
+
+    #[test]
+    fn test_response_next_update() {
+        let pki = Pki::new(Some("http://ocsp.example.net/"));
+        let request = request(&pki);
+
+        let response = Response::good(&request, Some("20261024120000Z")).sign(&pki.ca_key);
+        assert_eq!(
+            parse_response(&response, &request, now()).unwrap(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_792_843_200))
+        );
+        let response = Response::good(&request, None).sign(&pki.ca_key);
+        assert_eq!(parse_response(&response, &request, now()).unwrap(), None);
+
+        // tryLater, with no responseBytes
+        let try_later = der(TAG_SEQUENCE, &[0x0a, 0x01, 0x03]);
+        assert!(parse_response(&try_later, &request, now()).is_err());
+        assert!(parse_response(b"not der", &request, now()).is_err());
+    }
+
+    #[test]
+    fn test_response_must_be_current() {
+        let pki = Pki::new(Some("http://ocsp.example.net/"));
+        let request = request(&pki);
+
+        let expired = Response::good(&request, Some("20261016000000Z")).sign(&pki.ca_key);
+        let error = parse_response(&expired, &request, now()).unwrap_err();
+        assert!(error.to_string().contains("expired"), "{}", error);
+
+        let early = Response {
+            this_update: "20261018000000Z",
+            ..Response::good(&request, None)
+        };
+        assert!(parse_response(&early.sign(&pki.ca_key), &request, now()).is_err());
+    }
+
+    #[test]
+    fn test_response_must_answer_for_the_certificate() {
+        let pki = Pki::new(Some("http://ocsp.example.net/"));
+        let request = request(&pki);
+
+        let revoked = Response {
+            status: STATUS_REVOKED,
+            ..Response::good(&request, None)
+        };
+        assert!(parse_response(&revoked.sign(&pki.ca_key), &request, now()).is_err());
+
+        let other_serial = CertId {
+            serial: vec![0x43],
+            ..request.cert_id.clone()
+        };
+        let other = Response {
+            cert_id: other_serial.encode(),
+            ..Response::good(&request, None)
+        };
+        let error = parse_response(&other.sign(&pki.ca_key), &request, now()).unwrap_err();
+        assert!(error.to_string().contains("does not cover"), "{}", error);
+    }
+
+    #[test]
+    fn test_response_signer() {
+        let pki = Pki::new(Some("http://ocsp.example.net/"));
+        let request = request(&pki);
+
+        // Signed by someone else entirely
+        let forged = Response::good(&request, None).sign(&pki.leaf_key);
+        let error = parse_response(&forged, &request, now()).unwrap_err();
+        assert!(error.to_string().contains("not signed"), "{}", error);
+
+        // A responder the CA delegated OCSP signing to
+        let (responder, key) = pki.responder(true);
+        let delegated = Response {
+            certs: vec![responder.der().clone()],
+            ..Response::good(&request, None)
+        };
+        assert!(parse_response(&delegated.sign(&key), &request, now()).is_ok());
+
+        // A certificate from the CA without the OCSP signing usage
+        let (responder, key) = pki.responder(false);
+        let undelegated = Response {
+            certs: vec![responder.der().clone()],
+            ..Response::good(&request, None)
+        };
+        assert!(parse_response(&undelegated.sign(&key), &request, now()).is_err());
+    }
+}
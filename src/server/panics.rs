@@ -0,0 +1,166 @@
+//! Panic isolation for request handling
+//!
+//! A panic in a handler phase must not take the connection task (or anything
+//! else) down with it. [`isolate`] polls the handler future inside
+//! `catch_unwind` and turns a panic into an [`Err`] carrying the message,
+//! location and backtrace, which the caller answers with a `500`.
+//!
+//! The panic hook installed by [`install_hook`] records those details for
+//! panics raised while an isolated future is being polled and leaves every
+//! other panic to the previously installed hook.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+static HOOK: Once = Once::new();
+static PANICS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static ISOLATED: Cell<bool> = const { Cell::new(false) };
+    static LAST_PANIC: RefCell<Option<(String, Backtrace)>> = const { RefCell::new(None) };
+}
+
+/// A panic caught while handling a request
+#[derive(Debug)]
+pub struct RequestPanic {
+    pub message: String,
+    pub location: String,
+    pub backtrace: String,
+}
+
+impl fmt::Display for RequestPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "panicked at {}: {}", self.location, self.message)
+    }
+}
+
+/// Install the recording panic hook (idempotent)
+pub fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if ISOLATED.with(Cell::get) {
+                let location = info
+                    .location()
+                    .map(|location| location.to_string())
+                    .unwrap_or_else(|| "<unknown>".to_string());
+                let backtrace = Backtrace::force_capture();
+                LAST_PANIC.with(|last| *last.borrow_mut() = Some((location, backtrace)));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+/// Number of request panics caught since startup
+pub fn panics_total() -> u64 {
+    PANICS_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Run `future`, converting a panic in any poll into [`RequestPanic`]
+pub fn isolate<F: Future>(future: F) -> Isolated<F> {
+    Isolated { future }
+}
+
+/// Future returned by [`isolate`]
+pub struct Isolated<F> {
+    future: F,
+}
+
+impl<F: Future> Future for Isolated<F> {
+    type Output = Result<F::Output, RequestPanic>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of the pinned wrapper
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.future) };
+
+        let outer = ISOLATED.with(|isolated| isolated.replace(true));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx)));
+        ISOLATED.with(|isolated| isolated.set(outer));
+
+        match result {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => {
+                PANICS_TOTAL.fetch_add(1, Ordering::Relaxed);
+                let (location, backtrace) = LAST_PANIC
+                    .with(|last| last.borrow_mut().take())
+                    .map(|(location, backtrace)| (location, backtrace.to_string()))
+                    .unwrap_or_else(|| ("<unknown>".to_string(), String::new()));
+                Poll::Ready(Err(RequestPanic {
+                    message: payload_message(payload.as_ref()),
+                    location,
+                    backtrace,
+                }))
+            }
+        }
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+/// Deliberately panic when `VELOSERVE_TEST_PANIC` is `<point>:<needle>` and
+/// `subject` contains the needle; only built with the `panic-tests` feature
+#[cfg(feature = "panic-tests")]
+pub fn test_trigger(point: &str, subject: &str) {
+    static TRIGGER: once_cell::sync::Lazy<Option<(String, String)>> =
+        once_cell::sync::Lazy::new(|| {
+            let value = std::env::var("VELOSERVE_TEST_PANIC").ok()?;
+            let (point, needle) = value.split_once(':')?;
+            Some((point.to_string(), needle.to_string()))
+        });
+
+    if let Some((trigger, needle)) = TRIGGER.as_ref() {
+        if trigger == point && subject.contains(needle.as_str()) {
+            panic!("test panic requested for {}", subject);
+        }
+    }
+}
+
+#[cfg(not(feature = "panic-tests"))]
+#[inline(always)]
+pub fn test_trigger(_point: &str, _subject: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panic_becomes_error() {
+        install_hook();
+        let before = panics_total();
+
+        let caught = isolate(async {
+            tokio::task::yield_now().await;
+            panic!("handler exploded");
+        })
+        .await;
+        let caught: RequestPanic = match caught {
+            Ok(()) => panic!("panic was not caught"),
+            Err(caught) => caught,
+        };
+        assert_eq!(caught.message, "handler exploded");
+        assert!(caught.location.contains("panics.rs"), "{}", caught.location);
+        assert!(panics_total() > before);
+
+        // The hook is only diverted while isolated
+        assert!(!ISOLATED.with(Cell::get));
+        assert_eq!(isolate(async { 7 }).await.unwrap(), 7);
+    }
+}
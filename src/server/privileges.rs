@@ -0,0 +1,234 @@
+//! Privilege dropping
+//!
+//! VeloServe binds its listeners (and reads TLS keys) with whatever privileges
+//! it was started with, then switches to `server.user`/`server.group` before
+//! accepting the first connection.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use nix::unistd::{FchownatFlags, Gid, Group, Uid, User};
+use tracing::info;
+
+use crate::config::{CacheStorage, Config, ServerConfig};
+
+/// Resolved account to switch to after binding
+#[derive(Debug, Clone)]
+pub struct PrivilegeDrop {
+    user: String,
+    uid: Uid,
+    gid: Gid,
+}
+
+impl PrivilegeDrop {
+    /// Resolve `server.user`/`server.group`, or `None` when no user is configured.
+    pub fn from_config(server: &ServerConfig) -> Result<Option<Self>> {
+        let Some(ref user_name) = server.user else {
+            if server.group.is_some() {
+                return Err(anyhow!("server.group requires server.user to be set"));
+            }
+            return Ok(None);
+        };
+
+        let user = User::from_name(user_name)?
+            .ok_or_else(|| anyhow!("server.user '{}' does not exist", user_name))?;
+
+        let gid = match server.group {
+            Some(ref group_name) => {
+                Group::from_name(group_name)?
+                    .ok_or_else(|| anyhow!("server.group '{}' does not exist", group_name))?
+                    .gid
+            }
+            None => user.gid,
+        };
+
+        if user.uid.is_root() && !server.allow_root {
+            return Err(anyhow!(
+                "server.user '{}' is root; set server.allow_root = true to serve requests as root",
+                user_name
+            ));
+        }
+
+        Ok(Some(Self {
+            user: user.name,
+            uid: user.uid,
+            gid,
+        }))
+    }
+
+    /// Hand directories VeloServe writes to after the drop over to the target account.
+    pub fn prepare_paths(&self, config: &Config) -> Result<()> {
+        if !Uid::effective().is_root() {
+            return Ok(());
+        }
+
+        let disk_cache = config.cache.l2_enabled && config.cache.storage != CacheStorage::Redis;
+        if disk_cache || config.cache.persist {
+            let disk_path = Path::new(&config.cache.disk_path);
+            if disk_path.exists() {
+                self.chown_recursive(disk_path).map_err(|e| {
+                    anyhow!(
+                        "Failed to hand cache directory {} to '{}': {}",
+                        disk_path.display(),
+                        self.user,
+                        e
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Chown `path` and everything below it. Symlinks are chowned themselves
+    /// and never followed, so a link in the cache can't hand out other files.
+    fn chown_recursive(&self, path: &Path) -> Result<()> {
+        nix::unistd::fchownat(
+            None,
+            path,
+            Some(self.uid),
+            Some(self.gid),
+            FchownatFlags::NoFollowSymlink,
+        )?;
+        if std::fs::symlink_metadata(path)?.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                self.chown_recursive(&entry?.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch the whole process to the target account.
+    pub fn apply(&self) -> Result<()> {
+        if Uid::effective() == self.uid && Gid::effective() == self.gid {
+            return Ok(());
+        }
+
+        if !Uid::effective().is_root() {
+            return Err(anyhow!(
+                "Cannot switch to user '{}': VeloServe is not running as root",
+                self.user
+            ));
+        }
+
+        // Group first: once the uid changes we no longer may change groups
+        nix::unistd::setgroups(&[self.gid])
+            .map_err(|e| anyhow!("setgroups({}) failed: {}", self.gid, e))?;
+        nix::unistd::setgid(self.gid).map_err(|e| anyhow!("setgid({}) failed: {}", self.gid, e))?;
+        nix::unistd::setuid(self.uid).map_err(|e| anyhow!("setuid({}) failed: {}", self.uid, e))?;
+
+        if !self.uid.is_root() && nix::unistd::setuid(Uid::from_raw(0)).is_ok() {
+            return Err(anyhow!(
+                "Privilege drop to '{}' could be reverted",
+                self.user
+            ));
+        }
+
+        info!(
+            "Dropped privileges to user '{}' (uid={}, gid={})",
+            self.user, self.uid, self.gid
+        );
+        Ok(())
+    }
+}
+
+/// Drop privileges after listeners are bound, before any request is served.
+pub fn drop_privileges(config: &Config) -> Result<()> {
+    match PrivilegeDrop::from_config(&config.server)? {
+        Some(target) => {
+            target.prepare_paths(config)?;
+            target.apply()
+        }
+        None => refuse_root(&config.server, Uid::effective().is_root()),
+    }
+}
+
+/// Without `server.user` the server keeps the account it started as, which
+/// may only be root with `server.allow_root`
+fn refuse_root(server: &ServerConfig, running_as_root: bool) -> Result<()> {
+    if running_as_root && !server.allow_root {
+        return Err(anyhow!(
+            "Running as root; set server.user to drop privileges after binding, \
+             or server.allow_root = true to serve requests as root"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_requires_user() {
+        let server = ServerConfig {
+            group: Some("nogroup".to_string()),
+            ..ServerConfig::default()
+        };
+        assert!(PrivilegeDrop::from_config(&server).is_err());
+    }
+
+    #[test]
+    fn test_root_user_requires_allow_root() {
+        let mut server = ServerConfig {
+            user: Some("root".to_string()),
+            ..ServerConfig::default()
+        };
+        let err = PrivilegeDrop::from_config(&server).unwrap_err();
+        assert!(err.to_string().contains("allow_root"));
+
+        server.allow_root = true;
+        assert!(PrivilegeDrop::from_config(&server).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_staying_root_requires_allow_root() {
+        let mut server = ServerConfig::default();
+        let err = refuse_root(&server, true).unwrap_err();
+        assert!(err.to_string().contains("allow_root"));
+        assert!(refuse_root(&server, false).is_ok());
+
+        server.allow_root = true;
+        assert!(refuse_root(&server, true).is_ok());
+    }
+
+    #[test]
+    fn test_chown_does_not_follow_symlinks() {
+        use std::os::unix::fs::MetadataExt;
+
+        if !Uid::effective().is_root() {
+            return;
+        }
+        let outside = tempfile::NamedTempFile::new().unwrap();
+        let outside_dir = tempfile::tempdir().unwrap();
+        std::fs::write(outside_dir.path().join("secret"), "key").unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(cache.path().join("entry"), "page").unwrap();
+        std::os::unix::fs::symlink(outside.path(), cache.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(outside_dir.path(), cache.path().join("dir-link")).unwrap();
+
+        let target = PrivilegeDrop {
+            user: "test".to_string(),
+            uid: Uid::from_raw(4242),
+            gid: Gid::from_raw(4242),
+        };
+        target.chown_recursive(cache.path()).unwrap();
+
+        let owner = |path: &Path| std::fs::symlink_metadata(path).unwrap().uid();
+        assert_eq!(owner(&cache.path().join("entry")), 4242);
+        assert_eq!(owner(&cache.path().join("link")), 4242);
+        assert_eq!(owner(&cache.path().join("dir-link")), 4242);
+        assert_eq!(owner(outside.path()), 0);
+        assert_eq!(owner(&outside_dir.path().join("secret")), 0);
+    }
+
+    #[test]
+    fn test_unknown_user_is_rejected() {
+        let server = ServerConfig {
+            user: Some("veloserve-no-such-user".to_string()),
+            ..ServerConfig::default()
+        };
+        let err = PrivilegeDrop::from_config(&server).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+}
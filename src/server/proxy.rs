@@ -0,0 +1,335 @@
+//! Reverse proxy locations (`proxy_pass`)
+//!
+//! Requests under a `[[virtualhost.location]]` with `proxy_pass` are sent to
+//! that upstream instead of the docroot, one upstream connection per
+//! request. Bodies stream both ways. The client's `Host` is kept, hop-by-hop
+//! headers are dropped, and the upstream learns who asked from
+//! `X-Forwarded-For` and `X-Forwarded-Proto`. A chain that came from a
+//! trusted proxy is kept with that proxy appended, as with Nginx's
+//! `$proxy_add_x_forwarded_for`; anyone else's is replaced.
+//!
+//! A WebSocket handshake (`Upgrade: websocket`) goes through with its
+//! `Upgrade`, `Connection` and `Sec-WebSocket-*` headers unchanged. When the
+//! upstream answers `101 Switching Protocols`, the client gets that answer
+//! and both connections are joined into a byte tunnel until either side
+//! closes.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use anyhow::{anyhow, Context as _, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, UPGRADE};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use super::client_addr::{ConnectionInfo, TrustedProxies};
+use super::streaming::StreamingBody;
+
+/// Headers that describe one connection and are not passed on
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Where a `proxy_pass` location sends requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    /// `host:port` to connect to
+    authority: String,
+}
+
+impl Upstream {
+    /// Parse `proxy_pass`: `http://host[:port]`, without a path
+    pub fn parse(proxy_pass: &str) -> Result<Self, String> {
+        let rest = proxy_pass
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("'{}' must start with http://", proxy_pass))?;
+        let authority = rest.strip_suffix('/').unwrap_or(rest);
+        if authority.is_empty() || authority.contains(['/', '?', '#', '@']) {
+            return Err(format!(
+                "'{}' must be http://host[:port] without a path",
+                proxy_pass
+            ));
+        }
+        let uri: hyper::http::uri::Authority = authority
+            .parse()
+            .map_err(|_| format!("'{}' has an invalid host", proxy_pass))?;
+        let port = uri.port_u16().unwrap_or(80);
+        Ok(Self {
+            authority: format!("{}:{}", uri.host(), port),
+        })
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}", self.authority)
+    }
+}
+
+/// Whether `headers` ask to switch the connection to WebSocket
+pub fn is_websocket(headers: &HeaderMap) -> bool {
+    let upgrade = headers
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let connection = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    upgrade && connection
+}
+
+/// `X-Forwarded-For` for the upstream: a trusted peer's chain with the peer
+/// appended, otherwise just the peer
+fn forwarded_for(headers: &HeaderMap, conn: &ConnectionInfo, trusted: &TrustedProxies) -> String {
+    let peer = conn.peer.ip().to_canonical();
+    let received: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect();
+    if received.is_empty() || !trusted.contains(peer) {
+        return peer.to_string();
+    }
+    format!("{}, {}", received.join(", "), peer)
+}
+
+/// Send `req` to `upstream` and answer with what it returns
+pub async fn forward(
+    mut req: Request<Incoming>,
+    upstream: &Upstream,
+    trusted: &TrustedProxies,
+) -> Result<Response<Full<Bytes>>> {
+    let websocket = is_websocket(req.headers());
+    let client_upgrade = websocket.then(|| hyper::upgrade::on(&mut req));
+
+    let (mut parts, body) = req.into_parts();
+    let upgrade = websocket.then(|| parts.headers.get(UPGRADE).cloned());
+    strip_hop_by_hop(&mut parts.headers);
+    if let Some(Some(upgrade)) = upgrade {
+        parts.headers.insert(UPGRADE, upgrade);
+        parts
+            .headers
+            .insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    }
+    if let Some(conn) = parts.extensions.get::<ConnectionInfo>() {
+        let chain = forwarded_for(&parts.headers, conn, trusted);
+        parts.headers.insert(
+            HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_str(&chain)?,
+        );
+        parts.headers.insert(
+            HeaderName::from_static("x-forwarded-proto"),
+            HeaderValue::from_static(conn.scheme()),
+        );
+    }
+    // Origin form; the client's Host header names the site
+    parts.uri = parts
+        .uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/")
+        .parse()?;
+    parts.version = hyper::Version::HTTP_11;
+
+    let stream = TcpStream::connect(&upstream.authority)
+        .await
+        .with_context(|| format!("connecting to {}", upstream))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .with_context(|| format!("handshake with {}", upstream))?;
+    let upstream_name = upstream.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = connection.with_upgrades().await {
+            debug!("Connection to {} ended: {}", upstream_name, e);
+        }
+    });
+
+    let mut response = sender
+        .send_request(Request::from_parts(parts, body))
+        .await
+        .with_context(|| format!("request to {}", upstream))?;
+
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+        if let Some(client_upgrade) = client_upgrade {
+            let upstream_upgrade = hyper::upgrade::on(&mut response);
+            let upstream_name = upstream.to_string();
+            tokio::spawn(async move {
+                match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                    Ok((client, upstream)) => {
+                        let mut client = TokioIo::new(client);
+                        let mut upstream = TokioIo::new(upstream);
+                        match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                            Ok((up, down)) => debug!(
+                                "WebSocket tunnel to {} closed ({} bytes up, {} down)",
+                                upstream_name, up, down
+                            ),
+                            Err(e) => {
+                                debug!("WebSocket tunnel to {} failed: {}", upstream_name, e)
+                            }
+                        }
+                    }
+                    Err(e) => warn!("WebSocket upgrade via {} failed: {}", upstream_name, e),
+                }
+            });
+            let (parts, _) = response.into_parts();
+            return Ok(Response::from_parts(parts, Full::new(Bytes::new())));
+        }
+    }
+
+    let (mut parts, body) = response.into_parts();
+    strip_hop_by_hop(&mut parts.headers);
+    parts
+        .extensions
+        .insert(StreamingBody::from_body(UpstreamBody(body).boxed()));
+    Ok(Response::from_parts(parts, Full::new(Bytes::new())))
+}
+
+/// Drop hop-by-hop headers, including those `Connection` names
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|token| HeaderName::from_bytes(token.trim().as_bytes()).ok())
+        .collect();
+    for name in named {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+/// An upstream response body; a read error ends it early, and hyper then
+/// closes the client connection because the declared length was not met
+struct UpstreamBody(Incoming);
+
+impl Body for UpstreamBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        match ready!(Pin::new(&mut self.0).poll_frame(cx)) {
+            Some(Ok(frame)) => Poll::Ready(Some(Ok(frame))),
+            Some(Err(e)) => {
+                warn!("Reading from upstream failed mid-response: {}", e);
+                Poll::Ready(None)
+            }
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.0.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.0.size_hint()
+    }
+}
+
+/// Answer for an upstream that can't be reached
+pub fn bad_gateway(upstream: &Upstream, error: &anyhow::Error) -> Result<Response<Full<Bytes>>> {
+    warn!("Proxying to {} failed: {:#}", upstream, error);
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .header("Server", crate::SERVER_NAME)
+        .body(Full::new(Bytes::from("Bad Gateway")))
+        .map_err(|e| anyhow!("Failed to build response: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_upstream() {
+        for (proxy_pass, expected) in [
+            ("http://127.0.0.1:8080", "http://127.0.0.1:8080"),
+            ("http://localhost/", "http://localhost:80"),
+            ("http://[::1]:6001", "http://[::1]:6001"),
+        ] {
+            assert_eq!(Upstream::parse(proxy_pass).unwrap().to_string(), expected);
+        }
+        for bad in [
+            "127.0.0.1:8080",
+            "https://example.com",
+            "http://",
+            "http://host/app",
+            "http://user@host",
+        ] {
+            assert!(Upstream::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_forwarded_for() {
+        let trusted = TrustedProxies::new(&["10.0.0.0/8".to_string()]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", HeaderValue::from_static("203.0.113.7"));
+        headers.append("x-forwarded-for", HeaderValue::from_static("10.1.1.1"));
+
+        // A trusted proxy's chain is extended with the proxy
+        let proxy = ConnectionInfo::new("10.0.0.2:4000".parse().unwrap(), None, false)
+            .with_forwarded(&headers, &trusted);
+        assert_eq!(
+            forwarded_for(&headers, &proxy, &trusted),
+            "203.0.113.7, 10.1.1.1, 10.0.0.2"
+        );
+
+        // Anyone else's claims are dropped
+        let direct = ConnectionInfo::new("198.51.100.4:4000".parse().unwrap(), None, false)
+            .with_forwarded(&headers, &trusted);
+        assert_eq!(forwarded_for(&headers, &direct, &trusted), "198.51.100.4");
+
+        // A trusted proxy that sent no chain is the whole chain
+        assert_eq!(
+            forwarded_for(&HeaderMap::new(), &proxy, &trusted),
+            "10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn test_websocket_and_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(UPGRADE, HeaderValue::from_static("WebSocket"));
+        headers.insert(CONNECTION, HeaderValue::from_static("keep-alive, Upgrade"));
+        assert!(is_websocket(&headers));
+
+        headers.insert(CONNECTION, HeaderValue::from_static("close, x-trace"));
+        assert!(!is_websocket(&headers));
+
+        headers.insert("x-trace", HeaderValue::from_static("1"));
+        headers.insert("sec-websocket-key", HeaderValue::from_static("abc"));
+        strip_hop_by_hop(&mut headers);
+        assert!(headers.get("x-trace").is_none());
+        assert!(headers.get(UPGRADE).is_none());
+        assert!(headers.get(CONNECTION).is_none());
+        assert_eq!(headers["sec-websocket-key"], "abc");
+    }
+}
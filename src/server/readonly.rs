@@ -0,0 +1,200 @@
+//! Read-only mode
+//!
+//! Incident switch that rejects write methods while reads keep being served.
+//! It can be enabled server-wide or for single domains, optionally for a
+//! limited time, and is toggled at runtime through `POST /api/v1/readonly`
+//! (`veloserve server readonly on|off`), which takes `server.admin_token`
+//! and only answers loopback peers.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use hyper::Method;
+use parking_lot::RwLock;
+use serde_json::json;
+
+/// One enabled read-only window
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    since: u64,
+    until: Option<u64>,
+}
+
+impl Window {
+    fn new(duration: Option<Duration>) -> Self {
+        let since = now_epoch_secs();
+        Self {
+            since,
+            until: duration.map(|d| since.saturating_add(d.as_secs().max(1))),
+        }
+    }
+
+    fn is_active(&self, now: u64) -> bool {
+        match self.until {
+            Some(until) => now < until,
+            None => true,
+        }
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        json!({
+            "since": self.since,
+            "expires_at": self.until,
+        })
+    }
+}
+
+/// Runtime read-only state shared by all request handlers
+#[derive(Debug, Default)]
+pub struct ReadOnlyMode {
+    global: RwLock<Option<Window>>,
+    /// Lowercased domain -> window
+    vhosts: DashMap<String, Window>,
+}
+
+impl ReadOnlyMode {
+    /// Enable read-only mode server-wide or for `vhost`, optionally expiring after `duration`
+    pub fn enable(&self, vhost: Option<&str>, duration: Option<Duration>) {
+        let window = Window::new(duration);
+        match vhost {
+            Some(domain) => {
+                self.vhosts.insert(domain.to_ascii_lowercase(), window);
+            }
+            None => *self.global.write() = Some(window),
+        }
+    }
+
+    /// Disable read-only mode server-wide or for `vhost`
+    pub fn disable(&self, vhost: Option<&str>) {
+        match vhost {
+            Some(domain) => {
+                self.vhosts.remove(&domain.to_ascii_lowercase());
+            }
+            None => *self.global.write() = None,
+        }
+    }
+
+    /// True if read-only mode currently applies server-wide
+    pub fn is_global(&self) -> bool {
+        let now = now_epoch_secs();
+        let current = *self.global.read();
+        match current {
+            Some(window) if window.is_active(now) => true,
+            Some(_) => {
+                let mut global = self.global.write();
+                if global.is_some_and(|window| !window.is_active(now)) {
+                    *global = None;
+                }
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// True if read-only mode currently applies to `host` (port ignored)
+    pub fn is_active(&self, host: &str) -> bool {
+        if self.is_global() {
+            return true;
+        }
+        if self.vhosts.is_empty() {
+            return false;
+        }
+
+        let host = host.split(':').next().unwrap_or(host).to_ascii_lowercase();
+        let now = now_epoch_secs();
+        let Some(active) = self.vhosts.get(&host).map(|w| w.is_active(now)) else {
+            return false;
+        };
+        if !active {
+            self.vhosts
+                .remove_if(&host, |_, window| !window.is_active(now));
+        }
+        active
+    }
+
+    /// Current state for `/api/v1/status`
+    pub fn status_json(&self) -> serde_json::Value {
+        let now = now_epoch_secs();
+        self.vhosts.retain(|_, window| window.is_active(now));
+        let global = self.is_global();
+        let vhosts: serde_json::Map<String, serde_json::Value> = self
+            .vhosts
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().to_json()))
+            .collect();
+
+        json!({
+            "global": global,
+            "global_window": if global { self.global.read().map(Window::to_json) } else { None },
+            "vhosts": vhosts,
+        })
+    }
+}
+
+/// Methods rejected while read-only mode is active
+pub fn is_write_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_and_vhost_scopes() {
+        let mode = ReadOnlyMode::default();
+        assert!(!mode.is_active("example.com"));
+
+        mode.enable(Some("Example.com"), None);
+        assert!(mode.is_active("example.com:8080"));
+        assert!(!mode.is_active("other.test"));
+
+        mode.enable(None, None);
+        assert!(mode.is_active("other.test"));
+
+        mode.disable(None);
+        mode.disable(Some("example.com"));
+        assert!(!mode.is_active("example.com"));
+    }
+
+    #[test]
+    fn test_window_expires() {
+        let mode = ReadOnlyMode::default();
+        mode.enable(None, Some(Duration::from_secs(60)));
+        assert!(mode.is_active("example.com"));
+
+        // Backdate the window past its expiry
+        *mode.global.write() = Some(Window {
+            since: 0,
+            until: Some(1),
+        });
+        mode.vhosts.insert(
+            "example.com".to_string(),
+            Window {
+                since: 0,
+                until: Some(1),
+            },
+        );
+        assert!(!mode.is_active("example.com"));
+        assert_eq!(mode.status_json()["global"], false);
+        assert!(mode.vhosts.is_empty());
+    }
+
+    #[test]
+    fn test_write_methods() {
+        assert!(is_write_method(&Method::POST));
+        assert!(is_write_method(&Method::DELETE));
+        assert!(!is_write_method(&Method::GET));
+        assert!(!is_write_method(&Method::HEAD));
+    }
+}
@@ -0,0 +1,174 @@
+//! Configuration reload
+//!
+//! `veloserve config reload` sends SIGHUP. The server then reads its
+//! configuration file again, validates it and swaps it into the
+//! [`ConfigHandle`]: requests that start afterwards see the new virtual
+//! hosts, document roots, index files, error pages, cache TTLs, timeouts and
+//! so on, while requests in flight finish on the snapshot they started with.
+//! A file that fails to load or validate is logged and the running
+//! configuration stays in place.
+//!
+//! Some settings are only read at startup (listeners, the PHP pool, cache
+//! storage). Changes to those are reported as needing a restart; the new
+//! values take effect the next time the server starts.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use crate::config::Config;
+use crate::server::vhost::ConfigHandle;
+
+/// Settings only read at startup, with the keys inside each that are picked
+/// up on reload anyway
+const RESTART_ONLY: &[(&str, &[&str])] = &[
+    ("server.listen", &[]),
+    ("server.socket_mode", &[]),
+    ("server.listen_ssl", &[]),
+    ("server.workers", &[]),
+    ("server.max_connections", &[]),
+    ("server.user", &[]),
+    ("server.group", &[]),
+    ("server.allow_root", &[]),
+    ("server.access_log", &[]),
+    ("server.control_socket", &[]),
+    ("ssl", &[]),
+    ("php", &["startup_grace_ms"]),
+    (
+        "cache",
+        &[
+            "enable",
+            "default_ttl",
+            "stale_grace",
+            "negative_ttl",
+            "schedule",
+            "purge_allow",
+            "debug_headers",
+        ],
+    ),
+    ("limits", &[]),
+    ("static.image_optimize", &[]),
+    ("static.image_min_size", &[]),
+    ("static.image_formats", &[]),
+    ("static.image_quality", &[]),
+    ("static.image_max_concurrency", &[]),
+];
+
+/// What a reload changed
+#[derive(Debug)]
+pub struct ReloadReport {
+    /// Virtual hosts in the new configuration
+    pub vhosts: usize,
+    /// Changed settings that only apply after a restart
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Load `path` and make it the current configuration
+///
+/// `running` is the configuration the server started with, which
+/// restart-only settings are compared against.
+pub fn reload(path: &Path, running: &Config, handle: &ConfigHandle) -> Result<ReloadReport> {
+    let config = Config::load(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    let report = ReloadReport {
+        vhosts: config.virtualhost.len(),
+        restart_required: restart_required(running, &config),
+    };
+    handle.replace(Arc::new(config));
+    Ok(report)
+}
+
+/// Restart-only settings that differ between `running` and `new`
+pub fn restart_required(running: &Config, new: &Config) -> Vec<&'static str> {
+    let (running, new) = match (serde_json::to_value(running), serde_json::to_value(new)) {
+        (Ok(running), Ok(new)) => (running, new),
+        _ => return Vec::new(),
+    };
+    RESTART_ONLY
+        .iter()
+        .filter(|(name, reloadable)| {
+            let pointer = format!("/{}", name.replace('.', "/"));
+            let setting = |config: &serde_json::Value| {
+                let mut value = config.pointer(&pointer).cloned().unwrap_or_default();
+                if let Some(section) = value.as_object_mut() {
+                    for key in reloadable.iter() {
+                        section.remove(*key);
+                    }
+                }
+                value
+            };
+            setting(&running) != setting(&new)
+        })
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vhosts(handle: &ConfigHandle) -> Vec<(String, String)> {
+        handle
+            .load()
+            .config
+            .virtualhost
+            .iter()
+            .map(|vhost| (vhost.domain.clone(), vhost.root.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_reload_swaps_vhosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("veloserve.toml");
+        let initial = "[server]\nlisten = \"127.0.0.1:8080\"\n\n\
+                       [[virtualhost]]\ndomain = \"a.test\"\nroot = \"/srv/a\"\n";
+        std::fs::write(&path, initial).unwrap();
+        let running = Config::load(&path).unwrap();
+        let handle = ConfigHandle::new(Arc::new(running.clone()));
+
+        std::fs::write(
+            &path,
+            "[server]\nlisten = \"127.0.0.1:8080\"\n\n\
+             [[virtualhost]]\ndomain = \"a.test\"\nroot = \"/srv/a2\"\n\n\
+             [[virtualhost]]\ndomain = \"b.test\"\nroot = \"/srv/b\"\n",
+        )
+        .unwrap();
+        let report = reload(&path, &running, &handle).unwrap();
+        assert_eq!(report.vhosts, 2);
+        assert!(report.restart_required.is_empty());
+        assert_eq!(
+            vhosts(&handle),
+            [
+                ("a.test".to_string(), "/srv/a2".to_string()),
+                ("b.test".to_string(), "/srv/b".to_string())
+            ]
+        );
+        assert!(handle.load().find("b.test").is_some());
+
+        // An invalid file leaves the current configuration in place
+        std::fs::write(&path, "[[virtualhost]]\ndomain = \"c.test\"\n").unwrap();
+        assert!(reload(&path, &running, &handle).is_err());
+        assert_eq!(vhosts(&handle).len(), 2);
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let running = Config::default();
+        let mut new = Config::default();
+        new.cache.default_ttl += 60;
+        new.cache.enable = !new.cache.enable;
+        new.php.startup_grace_ms += 1;
+        new.server.request_timeout += std::time::Duration::from_secs(1);
+        assert!(restart_required(&running, &new).is_empty());
+
+        new.server.listen = "127.0.0.1:9999".into();
+        new.cache.memory_limit = "1G".to_string();
+        new.php.workers += 1;
+        new.static_files.image_quality = new.static_files.image_quality.wrapping_add(1);
+        assert_eq!(
+            restart_required(&running, &new),
+            ["server.listen", "php", "cache", "static.image_quality"]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Response finalization
+//!
+//! Every response passes through [`finalize`] on its way to hyper, after all
+//! handler-side rewriting (PHP header parsing, cache replay, upload policies,
+//! streaming) is done. It is the one place body lengths are settled:
+//!
+//! - buffered bodies are sent with a `Content-Length` equal to the body
+//!   actually sent; a stale value means some phase changed the body without
+//!   updating it, which trips a debug assertion
+//! - streamed bodies of known length (static files) are sent with that length;
+//!   other streamed bodies lose any `Content-Length` and go out chunked
+//! - bodiless responses (HEAD, 1xx, 204, 304) keep the length the handler declared
+//!
+//! The body is also wrapped to count the bytes handed to the connection, and
+//! the count is reported once the body is finished or dropped (client gone).
+
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::CONTENT_LENGTH;
+use hyper::http::HeaderValue;
+use hyper::{Method, Response, StatusCode};
+
+use crate::server::streaming::{self, ResponseBody};
+
+type Completion = Box<dyn FnOnce(u64) + Send + Sync>;
+
+/// Settle the body length and start counting sent bytes
+///
+/// `on_complete` receives the number of body bytes sent.
+pub fn finalize<F>(
+    response: Response<Full<Bytes>>,
+    method: &Method,
+    on_complete: F,
+) -> Response<ResponseBody>
+where
+    F: FnOnce(u64) + Send + Sync + 'static,
+{
+    let bodiless = *method == Method::HEAD || is_bodiless(response.status());
+    let mut response = streaming::into_response_body(response);
+
+    let length = response.body().size_hint().exact();
+    let headers = response.headers_mut();
+    match length {
+        _ if bodiless => {}
+        Some(length) => {
+            let declared = headers
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            debug_assert!(
+                declared.is_none_or(|declared| declared == length),
+                "Content-Length {:?} does not match the {} byte body",
+                declared,
+                length
+            );
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+        None => {
+            headers.remove(CONTENT_LENGTH);
+        }
+    }
+
+    response.map(|inner| {
+        CountedBody {
+            inner,
+            sent: 0,
+            on_complete: Some(Box::new(on_complete)),
+        }
+        .boxed()
+    })
+}
+
+fn is_bodiless(status: StatusCode) -> bool {
+    status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+}
+
+/// Body wrapper counting the data frames passed on
+struct CountedBody {
+    inner: ResponseBody,
+    sent: u64,
+    on_complete: Option<Completion>,
+}
+
+impl CountedBody {
+    fn complete(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(self.sent);
+        }
+    }
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        let this = &mut *self;
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.sent += data.len() as u64;
+                }
+            }
+            _ => this.complete(),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::streaming::StreamingBody;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    fn recorder() -> (Arc<AtomicU64>, impl FnOnce(u64) + Send + Sync + 'static) {
+        let sent = Arc::new(AtomicU64::new(u64::MAX));
+        let record = sent.clone();
+        (sent, move |bytes| record.store(bytes, Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body_gets_exact_length() {
+        let (sent, record) = recorder();
+        let response = finalize(
+            Response::new(Full::new(Bytes::from("hello"))),
+            &Method::GET,
+            record,
+        );
+        assert_eq!(response.headers()[CONTENT_LENGTH], "5");
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), 5);
+        assert_eq!(sent.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_is_chunked_and_counted() {
+        let (tx, rx) = mpsc::channel(4);
+        let mut response = Response::new(Full::new(Bytes::new()));
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(0));
+        response.extensions_mut().insert(StreamingBody::new(rx));
+
+        let (sent, record) = recorder();
+        let response = finalize(response, &Method::GET, record);
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+
+        tx.send(Bytes::from("data: 1\n\n")).await.unwrap();
+        tx.send(Bytes::from("data: 22\n\n")).await.unwrap();
+        drop(tx);
+        response.into_body().collect().await.unwrap();
+        assert_eq!(sent.load(Ordering::SeqCst), 19);
+    }
+
+    #[test]
+    fn test_head_keeps_declared_length() {
+        let mut response = Response::new(Full::new(Bytes::new()));
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(1234));
+
+        let (sent, record) = recorder();
+        let response = finalize(response, &Method::HEAD, record);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "1234");
+
+        // Dropped unsent, like hyper does for HEAD
+        drop(response);
+        assert_eq!(sent.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "does not match")]
+    fn test_stale_length_is_caught() {
+        let mut response = Response::new(Full::new(Bytes::from("rewritten body")));
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(4));
+        finalize(response, &Method::GET, |_| {});
+    }
+}
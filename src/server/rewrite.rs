@@ -0,0 +1,319 @@
+//! Path rewrites (`[[virtualhost.rewrite]]`)
+//!
+//! Rules run in order against the request path before anything is resolved,
+//! much like Nginx's `rewrite`. A matching rule replaces the path, with `$1`
+//! or `${name}` standing for the pattern's captures, and later rules see the
+//! new path. `last` starts over at the first rule instead, and `redirect` /
+//! `permanent` answer 302 / 301 right away.
+//!
+//! A replacement with a query replaces the request's query, with the
+//! original arguments appended unless it ends in `?`; one without a query
+//! keeps the original. Rules that keep restarting each other stop after
+//! [`MAX_CYCLES`] passes and the request fails with 500.
+//!
+//! Unless the replacement is written as an absolute URL, what it expands to
+//! always stays on this site: leading slashes (and backslashes) collapse to
+//! one, so a capture of `//evil.example` can't become a redirect off-site.
+
+use hyper::{StatusCode, Uri};
+use regex::Regex;
+
+use crate::config::{RewriteConfig, RewriteFlag};
+
+/// Passes through the rules before a request counts as looping
+pub const MAX_CYCLES: usize = 10;
+
+/// The URI a request arrived with, kept on rewritten requests so PHP still
+/// sees it as `REQUEST_URI`
+#[derive(Debug, Clone)]
+pub struct OriginalUri(pub Uri);
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Regex,
+    replacement: String,
+    /// Written as `http://` or `https://` URL, so it may leave the site
+    absolute: bool,
+    flag: Option<RewriteFlag>,
+}
+
+/// A vhost's compiled rewrite rules
+#[derive(Debug, Clone, Default)]
+pub struct Rewrites {
+    rules: Vec<Rule>,
+}
+
+/// What the rules made of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// Serve this path and query instead
+    Internal(String),
+    /// Send the client to `location`
+    Redirect {
+        status: StatusCode,
+        location: String,
+    },
+    /// The rules went past [`MAX_CYCLES`] passes
+    Loop,
+}
+
+impl Rewrites {
+    /// Compile `[[virtualhost.rewrite]]` rules
+    pub fn new(configs: &[RewriteConfig]) -> Result<Self, String> {
+        let rules = configs
+            .iter()
+            .map(|config| {
+                let pattern = Regex::new(&config.pattern)
+                    .map_err(|e| format!("invalid pattern '{}': {}", config.pattern, e))?;
+                let redirect = matches!(
+                    config.flags,
+                    Some(RewriteFlag::Redirect | RewriteFlag::Permanent)
+                );
+                let absolute = config.replacement.starts_with("http://")
+                    || config.replacement.starts_with("https://");
+                let allowed = config.replacement.starts_with(['/', '$']) || (redirect && absolute);
+                if !allowed {
+                    return Err(format!(
+                        "replacement '{}' must start with '/' or a capture",
+                        config.replacement
+                    ));
+                }
+                Ok(Rule {
+                    pattern,
+                    replacement: config.replacement.clone(),
+                    absolute,
+                    flag: config.flags,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Run the rules over a request path; `None` if none matched
+    pub fn apply(&self, path: &str, query: Option<&str>) -> Option<Rewrite> {
+        let mut path = path.to_string();
+        let mut query = query.map(str::to_string);
+        let mut rewritten = false;
+
+        'passes: for _ in 0..MAX_CYCLES {
+            for rule in &self.rules {
+                let Some(captures) = rule.pattern.captures(&path) else {
+                    continue;
+                };
+                let mut expanded = String::new();
+                captures.expand(&rule.replacement, &mut expanded);
+                if !rule.absolute {
+                    expanded = local_path(&expanded);
+                }
+                let (new_path, new_query) = split_query(expanded, query.as_deref());
+                path = new_path;
+                query = new_query;
+                rewritten = true;
+
+                match rule.flag {
+                    Some(RewriteFlag::Redirect) => {
+                        return Some(redirect(StatusCode::FOUND, &path, query.as_deref()))
+                    }
+                    Some(RewriteFlag::Permanent) => {
+                        return Some(redirect(
+                            StatusCode::MOVED_PERMANENTLY,
+                            &path,
+                            query.as_deref(),
+                        ))
+                    }
+                    Some(RewriteFlag::Last) => continue 'passes,
+                    None => {}
+                }
+            }
+            return rewritten.then(|| Rewrite::Internal(join_query(&path, query.as_deref())));
+        }
+        Some(Rewrite::Loop)
+    }
+}
+
+/// `expanded` as a path on this site: one leading slash, never `//host`
+/// (or `/\\host`, which browsers read the same way)
+fn local_path(expanded: &str) -> String {
+    format!("/{}", expanded.trim_start_matches(['/', '\\']))
+}
+
+/// Split an expanded replacement into path and query, merging in the
+/// request's own query
+fn split_query(expanded: String, original: Option<&str>) -> (String, Option<String>) {
+    let Some((path, query)) = expanded.split_once('?') else {
+        return (expanded, original.map(str::to_string));
+    };
+    let query = match query.strip_suffix('?') {
+        // A trailing `?` drops the original arguments
+        Some(query) => query.to_string(),
+        None if query.is_empty() => return (path.to_string(), None),
+        None => match original {
+            Some(original) if !original.is_empty() => format!("{}&{}", query, original),
+            _ => query.to_string(),
+        },
+    };
+    (path.to_string(), (!query.is_empty()).then_some(query))
+}
+
+fn join_query(path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    }
+}
+
+fn redirect(status: StatusCode, path: &str, query: Option<&str>) -> Rewrite {
+    Rewrite::Redirect {
+        status,
+        location: join_query(path, query),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(&str, &str, Option<RewriteFlag>)]) -> Rewrites {
+        let configs: Vec<RewriteConfig> = rules
+            .iter()
+            .map(|(pattern, replacement, flags)| RewriteConfig {
+                pattern: pattern.to_string(),
+                replacement: replacement.to_string(),
+                flags: *flags,
+            })
+            .collect();
+        Rewrites::new(&configs).unwrap()
+    }
+
+    #[test]
+    fn test_capture_group_rewrite() {
+        let rewrites = rules(&[
+            (
+                "^/blog/(\\d+)/(?P<slug>[a-z-]+)$",
+                "/post.php?id=$1&slug=${slug}",
+                None,
+            ),
+            ("^/old/(.*)$", "/new/$1", None),
+        ]);
+        assert_eq!(
+            rewrites.apply("/blog/42/hello-world", None),
+            Some(Rewrite::Internal(
+                "/post.php?id=42&slug=hello-world".to_string()
+            ))
+        );
+        // The original query is appended to the replacement's
+        assert_eq!(
+            rewrites.apply("/blog/42/hello-world", Some("preview=1")),
+            Some(Rewrite::Internal(
+                "/post.php?id=42&slug=hello-world&preview=1".to_string()
+            ))
+        );
+        // ...or kept when the replacement has none
+        assert_eq!(
+            rewrites.apply("/old/a/b.html", Some("x=1")),
+            Some(Rewrite::Internal("/new/a/b.html?x=1".to_string()))
+        );
+        assert_eq!(rewrites.apply("/blog/latest", None), None);
+    }
+
+    #[test]
+    fn test_rules_chain_in_order() {
+        let rewrites = rules(&[
+            ("^/a$", "/b", None),
+            ("^/b$", "/c", None),
+            ("^/c$", "/a", None),
+        ]);
+        // Each rule sees the path the previous ones left
+        assert_eq!(
+            rewrites.apply("/a", None),
+            Some(Rewrite::Internal("/a".to_string()))
+        );
+        assert_eq!(
+            rewrites.apply("/b", None),
+            Some(Rewrite::Internal("/a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_redirects() {
+        let rewrites = rules(&[
+            ("^/shop/(.*)$", "/store/$1", Some(RewriteFlag::Permanent)),
+            (
+                "^/sale$",
+                "https://example.com/deals?",
+                Some(RewriteFlag::Redirect),
+            ),
+        ]);
+        assert_eq!(
+            rewrites.apply("/shop/shoes", Some("size=9")),
+            Some(Rewrite::Redirect {
+                status: StatusCode::MOVED_PERMANENTLY,
+                location: "/store/shoes?size=9".to_string(),
+            })
+        );
+        assert_eq!(
+            rewrites.apply("/sale", Some("ref=mail")),
+            Some(Rewrite::Redirect {
+                status: StatusCode::FOUND,
+                location: "https://example.com/deals".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_captures_cannot_redirect_off_site() {
+        let rewrites = rules(&[
+            ("^/go(.*)$", "$1", Some(RewriteFlag::Redirect)),
+            ("^/old/(.*)$", "/$1", Some(RewriteFlag::Permanent)),
+        ]);
+        for (path, location) in [
+            ("/go//evil.example/x", "/evil.example/x"),
+            ("/go/\\evil.example", "/evil.example"),
+            ("/gohttps://evil.example", "/https://evil.example"),
+            ("/old//evil.example", "/evil.example"),
+            ("/old/shoes", "/shoes"),
+        ] {
+            match rewrites.apply(path, None) {
+                Some(Rewrite::Redirect { location: got, .. }) => {
+                    assert_eq!(got, location, "{}", path)
+                }
+                other => panic!("{}: {:?}", path, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_last_restarts_and_loops_are_capped() {
+        let rewrites = rules(&[
+            ("^/a$", "/b", None),
+            ("^/legacy/(.*)$", "/$1", Some(RewriteFlag::Last)),
+        ]);
+        // `last` sends /legacy/a back through the first rule
+        assert_eq!(
+            rewrites.apply("/legacy/a", None),
+            Some(Rewrite::Internal("/b".to_string()))
+        );
+
+        let looping = rules(&[
+            ("^/ping$", "/pong", Some(RewriteFlag::Last)),
+            ("^/pong$", "/ping", Some(RewriteFlag::Last)),
+        ]);
+        assert_eq!(looping.apply("/ping", None), Some(Rewrite::Loop));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for (pattern, replacement, flags) in [
+            ("^/(unclosed$", "/x", None),
+            ("^/x$", "relative", None),
+            ("^/x$", "https://example.com/", None),
+        ] {
+            let config = RewriteConfig {
+                pattern: pattern.to_string(),
+                replacement: replacement.to_string(),
+                flags,
+            };
+            assert!(Rewrites::new(&[config]).is_err(), "{}", pattern);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Cache schedules
+//!
+//! `[[cache.schedule]]` entries run cache actions on cron expressions: full
+//! purges, tag purges, and `set_ttl` windows that change the page cache TTL of
+//! matching paths for `duration` seconds after each run. The scheduler only
+//! ever evaluates the current second, so runs missed while the server was
+//! down or stalled are skipped rather than replayed.
+//!
+//! Jobs are rebuilt whenever the configuration snapshot changes (config
+//! reloads); run counters and active TTL windows carry over for jobs whose
+//! definition did not change.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::cache::CacheManager;
+use crate::config::{CacheScheduleConfig, Config};
+use crate::server::cron::CronExpr;
+use crate::server::readonly::ReadOnlyMode;
+use crate::server::vhost::{ConfigHandle, PathMatcher};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What a schedule does when it fires
+#[derive(Debug, Clone)]
+enum ScheduleAction {
+    PurgeAll,
+    PurgeTag(String),
+    SetTtl { matcher: PathMatcher, ttl: Duration },
+}
+
+impl ScheduleAction {
+    fn parse(action: &str) -> Result<Self, String> {
+        if action == "purge_all" {
+            return Ok(Self::PurgeAll);
+        }
+        if let Some(tag) = action.strip_prefix("purge_tag:") {
+            if tag.is_empty() {
+                return Err("purge_tag needs a tag".to_string());
+            }
+            return Ok(Self::PurgeTag(tag.to_string()));
+        }
+        if let Some(rest) = action.strip_prefix("set_ttl:") {
+            let (rule, secs) = rest
+                .rsplit_once(':')
+                .ok_or("expected set_ttl:<path>:<secs>")?;
+            if !rule.starts_with('/') {
+                return Err(format!("set_ttl path '{}' must start with '/'", rule));
+            }
+            let secs: u64 = secs
+                .parse()
+                .map_err(|_| format!("invalid set_ttl seconds '{}'", secs))?;
+            return Ok(Self::SetTtl {
+                matcher: PathMatcher::new(&[rule.to_string()]),
+                ttl: Duration::from_secs(secs),
+            });
+        }
+        Err(format!(
+            "unknown action '{}' (expected purge_all, purge_tag:<tag> or set_ttl:<path>:<secs>)",
+            action
+        ))
+    }
+}
+
+/// A compiled `[[cache.schedule]]` entry with its run history
+#[derive(Debug)]
+pub struct ScheduledJob {
+    name: String,
+    source: CacheScheduleConfig,
+    cron: CronExpr,
+    action: ScheduleAction,
+    runs: u64,
+    last_run: Option<i64>,
+    last_result: Option<String>,
+}
+
+impl ScheduledJob {
+    /// Compile the `index`-th schedule entry
+    pub fn compile(index: usize, config: &CacheScheduleConfig) -> Result<Self, String> {
+        let cron = CronExpr::parse(&config.cron)?;
+        let action = ScheduleAction::parse(&config.action)?;
+        if matches!(action, ScheduleAction::SetTtl { .. }) && config.duration == 0 {
+            return Err("set_ttl needs a duration (seconds the override stays active)".to_string());
+        }
+
+        Ok(Self {
+            name: config
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("schedule-{}", index + 1)),
+            source: config.clone(),
+            cron,
+            action,
+            runs: 0,
+            last_run: None,
+            last_result: None,
+        })
+    }
+
+    fn same_definition(&self, other: &ScheduledJob) -> bool {
+        self.name == other.name
+            && self.source.cron == other.source.cron
+            && self.source.action == other.source.action
+            && self.source.duration == other.source.duration
+    }
+}
+
+struct TtlOverride {
+    job: String,
+    matcher: PathMatcher,
+    ttl: Duration,
+    until: i64,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// Configuration the jobs were built from
+    source: Option<Arc<Config>>,
+    jobs: Vec<ScheduledJob>,
+    last_second: Option<i64>,
+}
+
+/// Background task running `[[cache.schedule]]` entries
+pub struct CacheScheduler {
+    config: Arc<ConfigHandle>,
+    cache: Arc<CacheManager>,
+    readonly: Arc<ReadOnlyMode>,
+    state: Mutex<SchedulerState>,
+    /// Active `set_ttl` windows, consulted for every cacheable request
+    overrides: RwLock<Vec<TtlOverride>>,
+}
+
+impl CacheScheduler {
+    pub fn new(
+        config: Arc<ConfigHandle>,
+        cache: Arc<CacheManager>,
+        readonly: Arc<ReadOnlyMode>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            cache,
+            readonly,
+            state: Mutex::new(SchedulerState::default()),
+            overrides: RwLock::new(Vec::new()),
+        })
+    }
+
+    /// Spawn the scheduler loop
+    pub fn start(self: &Arc<Self>) {
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                scheduler.tick(Local::now()).await;
+            }
+        });
+    }
+
+    /// Page cache TTL imposed by an active `set_ttl` window for `path`
+    pub fn ttl_override(&self, path: &str) -> Option<Duration> {
+        let overrides = self.overrides.read();
+        if overrides.is_empty() {
+            return None;
+        }
+        let now = Local::now().timestamp();
+        overrides
+            .iter()
+            .find(|o| o.until > now && o.matcher.matches(path))
+            .map(|o| o.ttl)
+    }
+
+    /// Schedules and their last runs for `/api/v1/status`
+    pub fn status_json(&self) -> serde_json::Value {
+        self.reload_if_changed();
+        let now = Local::now().timestamp();
+        // Same lock order as `reload_if_changed`: state, then overrides
+        let state = self.state.lock();
+        let overrides = self.overrides.read();
+        let jobs: Vec<serde_json::Value> = state
+            .jobs
+            .iter()
+            .map(|job| {
+                let active_until = overrides
+                    .iter()
+                    .find(|o| o.job == job.name && o.until > now)
+                    .map(|o| format_time(o.until));
+                json!({
+                    "name": job.name,
+                    "cron": job.source.cron,
+                    "action": job.source.action,
+                    "runs": job.runs,
+                    "last_run": job.last_run.map(format_time),
+                    "last_result": job.last_result,
+                    "active_until": active_until,
+                })
+            })
+            .collect();
+        json!(jobs)
+    }
+
+    /// Run every job due at `now` (once per second)
+    async fn tick(&self, now: DateTime<Local>) {
+        self.reload_if_changed();
+        let second = now.timestamp();
+        let due: Vec<(String, ScheduleAction, u64)> = {
+            let mut state = self.state.lock();
+            if state.last_second.is_some_and(|last| last >= second) {
+                return;
+            }
+            state.last_second = Some(second);
+            state
+                .jobs
+                .iter()
+                .filter(|job| job.cron.matches(&now))
+                .map(|job| (job.name.clone(), job.action.clone(), job.source.duration))
+                .collect()
+        };
+        self.overrides.write().retain(|o| o.until > second);
+
+        for (name, action, duration) in due {
+            let result = self.execute(&name, action, duration, second).await;
+            info!("Cache schedule '{}' ran: {}", name, result);
+
+            let mut state = self.state.lock();
+            if let Some(job) = state.jobs.iter_mut().find(|job| job.name == name) {
+                job.runs += 1;
+                job.last_run = Some(second);
+                job.last_result = Some(result);
+            }
+        }
+    }
+
+    async fn execute(&self, name: &str, action: ScheduleAction, duration: u64, now: i64) -> String {
+        match action {
+            ScheduleAction::PurgeAll | ScheduleAction::PurgeTag(_) if self.readonly.is_global() => {
+                "skipped (read-only mode)".to_string()
+            }
+            ScheduleAction::PurgeAll => {
+                self.cache.purge_all().await;
+                "purged all entries".to_string()
+            }
+            ScheduleAction::PurgeTag(tag) => {
+                let purged = self.cache.purge_by_tag_count(&tag).await;
+                format!("purged {} entries tagged '{}'", purged, tag)
+            }
+            ScheduleAction::SetTtl { matcher, ttl } => {
+                let until = now + duration as i64;
+                let mut overrides = self.overrides.write();
+                overrides.retain(|o| o.job != name);
+                overrides.push(TtlOverride {
+                    job: name.to_string(),
+                    matcher,
+                    ttl,
+                    until,
+                });
+                format!("ttl {}s until {}", ttl.as_secs(), format_time(until))
+            }
+        }
+    }
+
+    /// Rebuild the jobs if the configuration snapshot was replaced
+    fn reload_if_changed(&self) {
+        let compiled = self.config.load();
+        let mut state = self.state.lock();
+        if state
+            .source
+            .as_ref()
+            .is_some_and(|source| Arc::ptr_eq(source, &compiled.config))
+        {
+            return;
+        }
+
+        let mut jobs = Vec::new();
+        for (index, schedule) in compiled.config.cache.schedule.iter().enumerate() {
+            match ScheduledJob::compile(index, schedule) {
+                Ok(mut job) => {
+                    if let Some(previous) = state.jobs.iter().find(|p| p.same_definition(&job)) {
+                        job.runs = previous.runs;
+                        job.last_run = previous.last_run;
+                        job.last_result = previous.last_result.clone();
+                    }
+                    jobs.push(job);
+                }
+                Err(err) => warn!("Ignoring cache.schedule[{}]: {}", index, err),
+            }
+        }
+
+        let kept: Vec<&str> = jobs
+            .iter()
+            .filter(|job| state.jobs.iter().any(|p| p.same_definition(job)))
+            .map(|job| job.name.as_str())
+            .collect();
+        self.overrides
+            .write()
+            .retain(|o| kept.contains(&o.job.as_str()));
+
+        if state.source.is_some() {
+            info!("Reloaded {} cache schedule(s)", jobs.len());
+        }
+        state.jobs = jobs;
+        state.source = Some(compiled.config.clone());
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheLifetime, CachedResponse};
+    use crate::config::CacheConfig;
+
+    fn schedule(name: &str, cron: &str, action: &str, duration: u64) -> CacheScheduleConfig {
+        CacheScheduleConfig {
+            name: Some(name.to_string()),
+            cron: cron.to_string(),
+            action: action.to_string(),
+            duration,
+        }
+    }
+
+    fn config(schedules: Vec<CacheScheduleConfig>) -> Arc<Config> {
+        Arc::new(Config {
+            cache: CacheConfig {
+                l2_enabled: false,
+                schedule: schedules,
+                ..CacheConfig::default()
+            },
+            ..Config::default()
+        })
+    }
+
+    fn at(second: i64) -> DateTime<Local> {
+        Local.timestamp_opt(second, 0).unwrap()
+    }
+
+    async fn scheduler_with(schedules: Vec<CacheScheduleConfig>) -> Arc<CacheScheduler> {
+        let config = config(schedules);
+        let cache = Arc::new(CacheManager::new(&config.cache));
+        cache
+            .set_response(
+                "page:example.test:/",
+                CachedResponse::from_bytes("home", "text/html").with_tags(vec!["home".into()]),
+                CacheLifetime::from_ttl(Duration::from_secs(600)),
+            )
+            .await;
+        CacheScheduler::new(
+            Arc::new(ConfigHandle::new(config)),
+            cache,
+            Arc::new(ReadOnlyMode::default()),
+        )
+    }
+
+    fn runs(scheduler: &CacheScheduler) -> Vec<u64> {
+        scheduler.state.lock().jobs.iter().map(|j| j.runs).collect()
+    }
+
+    #[tokio::test]
+    async fn test_runs_once_per_matching_second() {
+        // Fires at second 0 and 30 of every minute
+        let scheduler = scheduler_with(vec![schedule(
+            "tags",
+            "0,30 * * * * *",
+            "purge_tag:home",
+            0,
+        )])
+        .await;
+        let base = 1_700_000_040; // second 0 of a minute
+
+        scheduler.tick(at(base)).await;
+        scheduler.tick(at(base)).await;
+        assert_eq!(runs(&scheduler), vec![1]);
+        assert!(scheduler.cache.inspect("page:example.test:/").is_none());
+
+        scheduler.tick(at(base + 1)).await;
+        assert_eq!(runs(&scheduler), vec![1]);
+
+        // The run at +30 was missed while stalled: skipped, not replayed
+        scheduler.tick(at(base + 45)).await;
+        assert_eq!(runs(&scheduler), vec![1]);
+
+        let status = scheduler.status_json();
+        assert_eq!(status[0]["name"], "tags");
+        assert_eq!(status[0]["runs"], 1);
+        assert_eq!(status[0]["last_result"], "purged 1 entries tagged 'home'");
+    }
+
+    #[tokio::test]
+    async fn test_ttl_window() {
+        let scheduler = scheduler_with(vec![schedule(
+            "short",
+            "* * * * * *",
+            "set_ttl:/news*:30",
+            60,
+        )])
+        .await;
+        let now = Local::now().timestamp();
+
+        scheduler.tick(at(now)).await;
+        assert_eq!(
+            scheduler.ttl_override("/news/today"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(scheduler.ttl_override("/shop"), None);
+
+        // Once the window has passed the override is dropped
+        scheduler.state.lock().jobs.clear();
+        scheduler.tick(at(now + 61)).await;
+        assert!(scheduler.overrides.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reload_rebuilds_jobs() {
+        let scheduler =
+            scheduler_with(vec![schedule("nightly", "0 0 3 * * *", "purge_all", 0)]).await;
+        let three_am = Local
+            .with_ymd_and_hms(2024, 5, 1, 3, 0, 0)
+            .single()
+            .unwrap();
+        scheduler.tick(three_am).await;
+        assert_eq!(runs(&scheduler), vec![1]);
+
+        scheduler.config.replace(config(vec![
+            schedule("nightly", "0 0 3 * * *", "purge_all", 0),
+            schedule("hourly", "0 0 * * * *", "purge_tag:home", 0),
+        ]));
+        scheduler.tick(three_am + chrono::Duration::hours(1)).await;
+        assert_eq!(runs(&scheduler), vec![1, 1]);
+
+        scheduler.config.replace(config(vec![schedule(
+            "nightly",
+            "0 0 4 * * *",
+            "purge_all",
+            0,
+        )]));
+        assert_eq!(scheduler.status_json()[0]["runs"], 0);
+    }
+}
@@ -0,0 +1,169 @@
+//! Security headers (`[server.headers]`)
+//!
+//! The configured headers go on every response, whether it came from a
+//! static file, PHP, the page cache or an error page. `Strict-Transport-
+//! Security` is only sent over HTTPS, the only place browsers honour it.
+//! A header the response already carries, such as a PHP script's own
+//! `Content-Security-Policy`, is kept unless `override` is set.
+
+use hyper::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_FRAME_OPTIONS,
+};
+use hyper::HeaderMap;
+
+use crate::config::HeadersConfig;
+
+/// Compiled `[server.headers]`
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    /// `Strict-Transport-Security` value, for HTTPS responses
+    hsts: Option<HeaderValue>,
+    /// Headers for every response, in configuration order
+    headers: Vec<(HeaderName, HeaderValue)>,
+    override_existing: bool,
+}
+
+impl SecurityHeaders {
+    /// Check and compile `[server.headers]`
+    pub fn new(config: &HeadersConfig) -> Result<Self, String> {
+        let hsts = config
+            .hsts
+            .as_ref()
+            .map(|hsts| {
+                let mut value = format!("max-age={}", hsts.max_age);
+                if hsts.include_subdomains {
+                    value.push_str("; includeSubDomains");
+                }
+                if hsts.preload {
+                    value.push_str("; preload");
+                }
+                HeaderValue::from_str(&value).map_err(|e| format!("hsts: {}", e))
+            })
+            .transpose()?;
+
+        let named = [
+            (X_FRAME_OPTIONS, &config.x_frame_options),
+            (REFERRER_POLICY, &config.referrer_policy),
+            (CONTENT_SECURITY_POLICY, &config.content_security_policy),
+        ];
+        let mut headers: Vec<(HeaderName, HeaderValue)> = Vec::new();
+        for (name, value) in named {
+            if let Some(value) = value {
+                headers.push((name.clone(), header_value(&name, value)?));
+            }
+        }
+        for (name, value) in &config.custom {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("'{}' is not a valid header name", name))?;
+            if name == STRICT_TRANSPORT_SECURITY {
+                return Err("set Strict-Transport-Security with [server.headers.hsts]".to_string());
+            }
+            if headers.iter().any(|(existing, _)| *existing == name) {
+                return Err(format!("{} is set twice", name));
+            }
+            let value = header_value(&name, value)?;
+            headers.push((name, value));
+        }
+
+        Ok(Self {
+            hsts,
+            headers,
+            override_existing: config.override_existing,
+        })
+    }
+
+    /// Add the headers to a response sent over HTTPS or plain HTTP
+    pub fn apply(&self, response: &mut HeaderMap, https: bool) {
+        let hsts = self
+            .hsts
+            .as_ref()
+            .filter(|_| https)
+            .map(|value| (&STRICT_TRANSPORT_SECURITY, value));
+        let headers = self.headers.iter().map(|(name, value)| (name, value));
+        for (name, value) in hsts.into_iter().chain(headers) {
+            if self.override_existing || !response.contains_key(name) {
+                response.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn header_value(name: &HeaderName, value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|_| format!("invalid value for {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HstsConfig;
+
+    fn config() -> HeadersConfig {
+        HeadersConfig {
+            hsts: Some(HstsConfig {
+                max_age: 63_072_000,
+                include_subdomains: true,
+                preload: true,
+            }),
+            x_frame_options: Some("SAMEORIGIN".to_string()),
+            content_security_policy: Some("default-src 'self'".to_string()),
+            custom: [("Permissions-Policy".to_string(), "camera=()".to_string())].into(),
+            ..HeadersConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_hsts_only_over_https() {
+        let headers = SecurityHeaders::new(&config()).unwrap();
+
+        let mut https = HeaderMap::new();
+        headers.apply(&mut https, true);
+        assert_eq!(
+            https[STRICT_TRANSPORT_SECURITY],
+            "max-age=63072000; includeSubDomains; preload"
+        );
+        assert_eq!(https[X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(https["permissions-policy"], "camera=()");
+        assert!(!https.contains_key(REFERRER_POLICY));
+
+        let mut plain = HeaderMap::new();
+        headers.apply(&mut plain, false);
+        assert!(!plain.contains_key(STRICT_TRANSPORT_SECURITY));
+        assert_eq!(plain[CONTENT_SECURITY_POLICY], "default-src 'self'");
+    }
+
+    #[test]
+    fn test_existing_headers_are_kept_unless_overridden() {
+        let mut response = HeaderMap::new();
+        response.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        SecurityHeaders::new(&config())
+            .unwrap()
+            .apply(&mut response, false);
+        assert_eq!(response[X_FRAME_OPTIONS], "DENY");
+
+        let overriding = HeadersConfig {
+            override_existing: true,
+            ..config()
+        };
+        SecurityHeaders::new(&overriding)
+            .unwrap()
+            .apply(&mut response, false);
+        assert_eq!(response[X_FRAME_OPTIONS], "SAMEORIGIN");
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        for custom in [
+            ("bad header", "x"),
+            ("X-Test", "line\nbreak"),
+            ("Strict-Transport-Security", "max-age=1"),
+            ("x-frame-options", "DENY"),
+        ] {
+            let config = HeadersConfig {
+                custom: [(custom.0.to_string(), custom.1.to_string())].into(),
+                ..config()
+            };
+            assert!(SecurityHeaders::new(&config).is_err(), "{:?}", custom);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! Cluster-wide rate limits
+//!
+//! A limiter in `shared` mode counts its hits in Redis (`cache.redis_url`) so
+//! every instance behind a load balancer draws from the same budget. Each hit
+//! is one Lua-scripted INCR + PEXPIRE on a fixed-window key, made under
+//! `limits.redis_timeout_ms`. When Redis fails, the limiter falls back to its
+//! local in-memory counter and leaves Redis alone for a few seconds, so an
+//! outage costs at most one timeout per backoff period. A running backoff is
+//! carried over to another instance by state snapshots.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use redis::{Client, Connection, Script};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::{Config, LimiterMode};
+
+/// How long Redis is skipped after a failed call
+const REDIS_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Idle connections kept for reuse
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+const KEY_PREFIX: &str = "veloserve:v1:limit";
+
+/// Count a hit in a fixed window, starting the expiry on the first one
+static INCR_WINDOW: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r"
+local hits = redis.call('INCR', KEYS[1])
+if hits == 1 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+end
+return hits
+",
+    )
+});
+
+/// Limiters that can be counted cluster-wide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limiter {
+    /// `POST /api/v1/cache/invalidate`
+    Invalidation,
+}
+
+impl Limiter {
+    fn name(&self) -> &'static str {
+        match self {
+            Limiter::Invalidation => "invalidation",
+        }
+    }
+}
+
+/// Result of counting a hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedVerdict {
+    /// Counted in Redis and within the limit
+    Allowed,
+    /// Counted in Redis and over the limit
+    Limited,
+    /// Not counted in Redis (local mode or Redis unavailable); use the local counter
+    Local,
+}
+
+/// Limiter state as carried in a state snapshot
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitsSnapshot {
+    /// Milliseconds left of the Redis backoff, if one was running
+    pub redis_backoff_ms: Option<u64>,
+}
+
+/// Redis-backed counters for limiters in shared mode
+pub struct SharedLimits {
+    invalidation: LimiterMode,
+    redis: Option<Arc<SharedRedis>>,
+    shared_hits: AtomicU64,
+    fallbacks: AtomicU64,
+}
+
+struct SharedRedis {
+    client: Client,
+    timeout: Duration,
+    idle: Mutex<Vec<Connection>>,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl SharedLimits {
+    /// Set up from the configuration; Redis is only contacted on the first shared hit
+    pub fn new(config: &Config) -> Self {
+        let invalidation = config.limits.invalidation;
+        let redis = match (&config.cache.redis_url, invalidation) {
+            (Some(url), LimiterMode::Shared) => match Client::open(url.as_str()) {
+                Ok(client) => Some(Arc::new(SharedRedis {
+                    client,
+                    timeout: Duration::from_millis(config.limits.redis_timeout_ms),
+                    idle: Mutex::new(Vec::new()),
+                    down_until: Mutex::new(None),
+                })),
+                Err(e) => {
+                    warn!(
+                        "Invalid Redis URL for shared limits, counting locally: {}",
+                        e
+                    );
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        Self {
+            invalidation,
+            redis,
+            shared_hits: AtomicU64::new(0),
+            fallbacks: AtomicU64::new(0),
+        }
+    }
+
+    fn mode(&self, limiter: Limiter) -> LimiterMode {
+        match limiter {
+            Limiter::Invalidation => self.invalidation,
+        }
+    }
+
+    /// Count one hit against `limiter`, allowing `limit` hits per `window`
+    pub async fn hit(&self, limiter: Limiter, limit: u64, window: Duration) -> SharedVerdict {
+        if self.mode(limiter) == LimiterMode::Local {
+            return SharedVerdict::Local;
+        }
+        let Some(redis) = &self.redis else {
+            return SharedVerdict::Local;
+        };
+        if redis.backing_off() {
+            self.fallbacks.fetch_add(1, Ordering::Relaxed);
+            return SharedVerdict::Local;
+        }
+
+        let key = window_key(limiter, window);
+        let blocking = redis.clone();
+        let hits = tokio::task::spawn_blocking(move || blocking.incr(&key, window)).await;
+
+        match hits {
+            Ok(Ok(hits)) => {
+                self.shared_hits.fetch_add(1, Ordering::Relaxed);
+                if hits <= limit {
+                    SharedVerdict::Allowed
+                } else {
+                    SharedVerdict::Limited
+                }
+            }
+            Ok(Err(e)) => {
+                warn!(
+                    "Shared {} limiter unavailable, counting locally for {}s: {}",
+                    limiter.name(),
+                    REDIS_BACKOFF.as_secs(),
+                    e
+                );
+                redis.back_off();
+                self.fallbacks.fetch_add(1, Ordering::Relaxed);
+                SharedVerdict::Local
+            }
+            Err(e) => {
+                warn!("Shared limiter task failed: {}", e);
+                self.fallbacks.fetch_add(1, Ordering::Relaxed);
+                SharedVerdict::Local
+            }
+        }
+    }
+
+    /// Modes and counters for the metrics API
+    pub fn stats_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "invalidation": match self.invalidation {
+                LimiterMode::Local => "local",
+                LimiterMode::Shared => "shared",
+            },
+            "shared_hits": self.shared_hits.load(Ordering::Relaxed),
+            "local_fallbacks": self.fallbacks.load(Ordering::Relaxed),
+            "redis_backing_off": self.redis.as_ref().map(|redis| redis.backing_off()).unwrap_or(false),
+        })
+    }
+}
+
+impl SharedLimits {
+    /// The running Redis backoff, if any
+    pub fn snapshot(&self) -> LimitsSnapshot {
+        let remaining = self.redis.as_ref().and_then(|redis| {
+            redis
+                .down_until
+                .lock()
+                .and_then(|until| until.checked_duration_since(Instant::now()))
+        });
+        LimitsSnapshot {
+            redis_backoff_ms: remaining.map(|left| left.as_millis() as u64),
+        }
+    }
+
+    /// Resume a Redis backoff from a snapshot, when this instance uses Redis
+    /// too; it never runs longer than a backoff of its own would
+    pub fn restore(&self, snapshot: &LimitsSnapshot) {
+        let (Some(redis), Some(ms)) = (&self.redis, snapshot.redis_backoff_ms) else {
+            return;
+        };
+        let until = Instant::now() + Duration::from_millis(ms).min(REDIS_BACKOFF);
+        let mut down_until = redis.down_until.lock();
+        if down_until.is_none_or(|current| current < until) {
+            *down_until = Some(until);
+        }
+    }
+}
+
+impl SharedRedis {
+    fn backing_off(&self) -> bool {
+        matches!(*self.down_until.lock(), Some(until) if Instant::now() < until)
+    }
+
+    fn back_off(&self) {
+        *self.down_until.lock() = Some(Instant::now() + REDIS_BACKOFF);
+    }
+
+    fn connection(&self) -> redis::RedisResult<Connection> {
+        if let Some(conn) = self.idle.lock().pop() {
+            return Ok(conn);
+        }
+        let conn = self.client.get_connection_with_timeout(self.timeout)?;
+        conn.set_read_timeout(Some(self.timeout))?;
+        conn.set_write_timeout(Some(self.timeout))?;
+        Ok(conn)
+    }
+
+    fn incr(&self, key: &str, window: Duration) -> redis::RedisResult<u64> {
+        let mut conn = self.connection()?;
+        let hits = INCR_WINDOW
+            .key(key)
+            .arg(window.as_millis() as u64)
+            .invoke::<u64>(&mut conn)?;
+
+        let mut idle = self.idle.lock();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(conn);
+        }
+        Ok(hits)
+    }
+}
+
+/// Key of the fixed window the current time falls into
+fn window_key(limiter: Limiter, window: Duration) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let window_secs = window.as_secs().max(1);
+    format!("{}:{}:{}", KEY_PREFIX, limiter.name(), now / window_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_config(redis_url: &str) -> Config {
+        let mut config = Config::default();
+        config.cache.redis_url = Some(redis_url.to_string());
+        config.limits.invalidation = LimiterMode::Shared;
+        config.limits.redis_timeout_ms = 50;
+        config
+    }
+
+    #[tokio::test]
+    async fn test_local_mode_never_touches_redis() {
+        let limits = SharedLimits::new(&Config::default());
+        let verdict = limits
+            .hit(Limiter::Invalidation, 1, Duration::from_secs(60))
+            .await;
+        assert_eq!(verdict, SharedVerdict::Local);
+        assert_eq!(limits.stats_json()["local_fallbacks"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_falls_back_and_backs_off() {
+        // Nothing listens on the discard port
+        let limits = SharedLimits::new(&shared_config("redis://127.0.0.1:9/"));
+
+        let started = Instant::now();
+        let verdict = limits
+            .hit(Limiter::Invalidation, 1, Duration::from_secs(60))
+            .await;
+        assert_eq!(verdict, SharedVerdict::Local);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // Within the backoff Redis is skipped entirely
+        let started = Instant::now();
+        let verdict = limits
+            .hit(Limiter::Invalidation, 1, Duration::from_secs(60))
+            .await;
+        assert_eq!(verdict, SharedVerdict::Local);
+        assert!(started.elapsed() < Duration::from_millis(20));
+
+        let stats = limits.stats_json();
+        assert_eq!(stats["local_fallbacks"], 2);
+        assert_eq!(stats["redis_backing_off"], true);
+    }
+
+    #[tokio::test]
+    async fn test_backoff_carries_over_to_a_standby() {
+        let primary = SharedLimits::new(&shared_config("redis://127.0.0.1:9/"));
+        assert_eq!(primary.snapshot(), LimitsSnapshot::default());
+        primary
+            .hit(Limiter::Invalidation, 1, Duration::from_secs(60))
+            .await;
+        let snapshot = primary.snapshot();
+        assert!(snapshot.redis_backoff_ms.is_some());
+
+        let standby = SharedLimits::new(&shared_config("redis://127.0.0.1:9/"));
+        standby.restore(&snapshot);
+        assert_eq!(standby.stats_json()["redis_backing_off"], true);
+
+        // Without Redis there is nothing to back off from
+        let local = SharedLimits::new(&Config::default());
+        local.restore(&snapshot);
+        assert_eq!(local.snapshot(), LimitsSnapshot::default());
+    }
+
+    #[test]
+    fn test_window_key_is_per_limiter_and_window() {
+        let key = window_key(Limiter::Invalidation, Duration::from_secs(60));
+        assert!(key.starts_with("veloserve:v1:limit:invalidation:"));
+    }
+}
@@ -0,0 +1,888 @@
+//! Runtime state snapshots
+//!
+//! `GET /api/v1/state/export` serializes in-memory runtime state into a
+//! versioned binary snapshot and `POST /api/v1/state/import` loads it into
+//! another running instance (`veloserve state export|import`), so a standby
+//! can take over traffic with a warm cache. Imports are only taken from
+//! loopback with `server.admin_token`.
+//!
+//! Layout (integers little-endian):
+//!
+//! ```text
+//! "VSSTATE" | version u16 | created_at u64 | section count u32 | header crc32 u32
+//! per section: id u16 | version u16 | length u64 | crc32 u32 | payload
+//! ```
+//!
+//! Sections:
+//!
+//! - `page_cache`: the L1 index, negative entries included, optionally with
+//!   bodies; entries without a body are re-warmed from their request URL
+//! - `invalidations`: the invalidation API's dedupe table and local rate window
+//! - `shared_limits`: a running Redis backoff of the shared limiters
+//!
+//! Each section carries its own version and checksum. Sections with an
+//! unknown id, a newer version or a checksum mismatch are skipped and
+//! reported while the remaining sections are still imported.
+//!
+//! The same snapshots go over the control socket (`state.export`,
+//! `state.import`). With `cache.persist` a snapshot of the page cache alone,
+//! bodies included, is kept in `PERSIST_FILE` under `cache.disk_path` and
+//! loaded again at startup.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::Crc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::cache::{CacheManager, CachedResponse};
+use crate::config::CacheConfig;
+use crate::server::invalidation_guard::{InvalidationGuard, InvalidationSnapshot};
+use crate::server::shared_limits::{LimitsSnapshot, SharedLimits};
+
+/// Version of the snapshot container
+pub const STATE_FORMAT_VERSION: u16 = 1;
+/// Largest snapshot accepted by import, and the default export budget
+pub const MAX_SNAPSHOT_BYTES: usize = 256 * 1024 * 1024;
+
+/// Snapshot written by `cache.persist`, under `cache.disk_path`
+pub const PERSIST_FILE: &str = "memory-cache.vsstate";
+
+const STATE_MAGIC: &[u8; 7] = b"VSSTATE";
+const HEADER_LEN: usize = 7 + 2 + 8 + 4 + 4;
+const SECTION_HEADER_LEN: usize = 2 + 2 + 8 + 4;
+
+const SECTION_PAGE_CACHE: u16 = 1;
+/// Version 2 added the request URL of each entry
+const PAGE_CACHE_SECTION_VERSION: u16 = 2;
+const SECTION_INVALIDATIONS: u16 = 2;
+const INVALIDATIONS_SECTION_VERSION: u16 = 1;
+const SECTION_SHARED_LIMITS: u16 = 3;
+const SHARED_LIMITS_SECTION_VERSION: u16 = 1;
+
+/// The runtime state a snapshot is taken from or loaded into
+#[derive(Clone, Copy)]
+pub struct RuntimeState<'a> {
+    pub cache: &'a CacheManager,
+    /// Left out by `cache.persist`, which only keeps the page cache
+    pub invalidations: Option<&'a InvalidationGuard>,
+    pub limits: Option<&'a SharedLimits>,
+}
+
+impl<'a> RuntimeState<'a> {
+    /// Only the page cache
+    pub fn cache_only(cache: &'a CacheManager) -> Self {
+        Self {
+            cache,
+            invalidations: None,
+            limits: None,
+        }
+    }
+}
+
+/// Export settings
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    /// Include response bodies instead of only the key index
+    pub include_bodies: bool,
+    /// Upper bound for the snapshot size; bodies, then index records, are
+    /// dropped (least recently used first) once it is reached
+    pub max_bytes: usize,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            include_bodies: false,
+            max_bytes: MAX_SNAPSHOT_BYTES,
+        }
+    }
+}
+
+/// One page cache entry in a snapshot
+#[derive(Serialize, Deserialize)]
+struct PageCacheRecord {
+    key: String,
+    status: u16,
+    tags: Vec<String>,
+    stored_at: u64,
+    ttl_seconds: u64,
+    stale_after_seconds: u64,
+    size_bytes: u64,
+    /// Full entry in the persistent cache format when bodies were exported
+    entry: Option<Vec<u8>>,
+    /// URL the entry was requested with, to re-warm it without a body
+    url: Option<String>,
+}
+
+/// A page cache record of section version 1, which had no URL
+#[derive(Deserialize)]
+struct PageCacheRecordV1 {
+    key: String,
+    status: u16,
+    tags: Vec<String>,
+    stored_at: u64,
+    ttl_seconds: u64,
+    stale_after_seconds: u64,
+    size_bytes: u64,
+    entry: Option<Vec<u8>>,
+}
+
+impl From<PageCacheRecordV1> for PageCacheRecord {
+    fn from(record: PageCacheRecordV1) -> Self {
+        Self {
+            key: record.key,
+            status: record.status,
+            tags: record.tags,
+            stored_at: record.stored_at,
+            ttl_seconds: record.ttl_seconds,
+            stale_after_seconds: record.stale_after_seconds,
+            size_bytes: record.size_bytes,
+            entry: record.entry,
+            url: None,
+        }
+    }
+}
+
+/// Section skipped during import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedSection {
+    pub id: u16,
+    pub version: u16,
+    pub reason: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Names of the sections that were loaded
+    pub sections: Vec<&'static str>,
+    pub skipped: Vec<SkippedSection>,
+    /// Cache entries restored with their bodies
+    pub restored: usize,
+    /// Entries that expired before they could be restored
+    pub expired: usize,
+    /// URLs of index-only (or unreadable) entries, to be re-warmed
+    pub warm_urls: Vec<String>,
+    /// Index-only entries without a URL to re-warm them from
+    pub unwarmable: usize,
+}
+
+impl ImportReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        let skipped: Vec<serde_json::Value> = self
+            .skipped
+            .iter()
+            .map(|section| {
+                json!({
+                    "id": section.id,
+                    "version": section.version,
+                    "reason": section.reason,
+                })
+            })
+            .collect();
+
+        json!({
+            "sections": self.sections,
+            "skipped_sections": skipped,
+            "restored": self.restored,
+            "expired": self.expired,
+            "warm_targets": self.warm_urls.len(),
+            "unwarmable": self.unwarmable,
+        })
+    }
+}
+
+/// Serialize the current runtime state
+///
+/// The small sections come first; the page cache gets what is left of
+/// `max_bytes`.
+pub fn export_state(state: RuntimeState<'_>, options: ExportOptions) -> Result<Vec<u8>> {
+    let mut writer = SnapshotWriter::default();
+    if let Some(guard) = state.invalidations {
+        writer.section(
+            SECTION_INVALIDATIONS,
+            INVALIDATIONS_SECTION_VERSION,
+            bincode::serialize(&guard.snapshot())?,
+        );
+    }
+    if let Some(limits) = state.limits {
+        writer.section(
+            SECTION_SHARED_LIMITS,
+            SHARED_LIMITS_SECTION_VERSION,
+            bincode::serialize(&limits.snapshot())?,
+        );
+    }
+    let budget = options
+        .max_bytes
+        .saturating_sub(HEADER_LEN + writer.len() + SECTION_HEADER_LEN);
+    writer.section(
+        SECTION_PAGE_CACHE,
+        PAGE_CACHE_SECTION_VERSION,
+        export_page_cache(state.cache, options.include_bodies, budget)?,
+    );
+
+    let snapshot = writer.finish();
+    if snapshot.len() > options.max_bytes {
+        bail!(
+            "state snapshot is {} bytes, above the {} byte limit",
+            snapshot.len(),
+            options.max_bytes
+        );
+    }
+    Ok(snapshot)
+}
+
+/// Load a snapshot produced by [`export_state`]
+///
+/// Fails only if the container itself is unusable; problems inside a section
+/// skip that section.
+pub async fn import_state(state: RuntimeState<'_>, raw: &[u8]) -> Result<ImportReport> {
+    if raw.len() > MAX_SNAPSHOT_BYTES {
+        bail!(
+            "state snapshot is {} bytes, above the {} byte limit",
+            raw.len(),
+            MAX_SNAPSHOT_BYTES
+        );
+    }
+
+    let mut report = ImportReport::default();
+    for section in read_sections(raw)? {
+        let skip = |reason: String| SkippedSection {
+            id: section.id,
+            version: section.version,
+            reason,
+        };
+
+        if section.checksum != crc32(section.payload) {
+            report.skipped.push(skip("checksum mismatch".to_string()));
+            continue;
+        }
+
+        let Some((name, newest)) = known_section(section.id) else {
+            report.skipped.push(skip("unknown section".to_string()));
+            continue;
+        };
+        if section.version > newest {
+            report.skipped.push(skip(format!(
+                "unsupported {} version (newest supported is {})",
+                name, newest
+            )));
+            continue;
+        }
+
+        let imported = match section.id {
+            SECTION_PAGE_CACHE => {
+                import_page_cache(state.cache, section.version, section.payload, &mut report).await
+            }
+            SECTION_INVALIDATIONS => match state.invalidations {
+                Some(guard) => bincode::deserialize::<InvalidationSnapshot>(section.payload)
+                    .map(|snapshot| guard.restore(snapshot))
+                    .map_err(|err| anyhow!("malformed {}: {}", name, err)),
+                None => Err(anyhow!("{} is not restored here", name)),
+            },
+            _ => match state.limits {
+                Some(limits) => bincode::deserialize::<LimitsSnapshot>(section.payload)
+                    .map(|snapshot| limits.restore(&snapshot))
+                    .map_err(|err| anyhow!("malformed {}: {}", name, err)),
+                None => Err(anyhow!("{} is not restored here", name)),
+            },
+        };
+        match imported {
+            Ok(()) => report.sections.push(name),
+            Err(err) => report.skipped.push(skip(err.to_string())),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Name and newest supported version of a section id
+fn known_section(id: u16) -> Option<(&'static str, u16)> {
+    match id {
+        SECTION_PAGE_CACHE => Some(("page_cache", PAGE_CACHE_SECTION_VERSION)),
+        SECTION_INVALIDATIONS => Some(("invalidations", INVALIDATIONS_SECTION_VERSION)),
+        SECTION_SHARED_LIMITS => Some(("shared_limits", SHARED_LIMITS_SECTION_VERSION)),
+        _ => None,
+    }
+}
+
+/// Where `cache.persist` keeps its snapshot
+pub fn persist_path(config: &CacheConfig) -> PathBuf {
+    Path::new(&config.disk_path).join(PERSIST_FILE)
+}
+
+/// Write the memory cache with bodies to `path`, no larger than
+/// `cache.memory_limit`, and return the snapshot size
+///
+/// The snapshot goes to a temporary file renamed over `path`, so a crash
+/// while writing keeps the previous one. Each save gets its own temporary
+/// file, so saves that overlap (shutdown and the periodic one) can't mix
+/// their writes.
+pub fn save_to_file(cache: &CacheManager, path: &Path) -> Result<usize> {
+    let max_bytes = usize::try_from(cache.memory_limit())
+        .unwrap_or(usize::MAX)
+        .min(MAX_SNAPSHOT_BYTES);
+    let snapshot = export_state(
+        RuntimeState::cache_only(cache),
+        ExportOptions {
+            include_bodies: true,
+            max_bytes,
+        },
+    )?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let mut partial = tempfile::Builder::new()
+        .prefix(".snapshot-")
+        .suffix(".partial")
+        .tempfile_in(dir)?;
+    partial.write_all(&snapshot)?;
+    partial.persist(path).map_err(|e| e.error)?;
+    Ok(snapshot.len())
+}
+
+/// Load a snapshot written by [`save_to_file`]; `None` if there is none
+pub async fn load_from_file(cache: &CacheManager, path: &Path) -> Result<Option<ImportReport>> {
+    let len = match tokio::fs::metadata(path).await {
+        Ok(meta) => meta.len(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if len > MAX_SNAPSHOT_BYTES as u64 {
+        bail!(
+            "state snapshot is {} bytes, above the {} byte limit",
+            len,
+            MAX_SNAPSHOT_BYTES
+        );
+    }
+
+    let raw = tokio::fs::read(path).await?;
+    import_state(RuntimeState::cache_only(cache), &raw)
+        .await
+        .map(Some)
+}
+
+fn export_page_cache(cache: &CacheManager, include_bodies: bool, budget: usize) -> Result<Vec<u8>> {
+    let mut records = Vec::new();
+    // bincode length prefix of the record list
+    let mut used = 8usize;
+
+    for (key, entry) in cache.snapshot_entries() {
+        let mut record = PageCacheRecord {
+            key: key.clone(),
+            status: entry.status,
+            tags: entry.tags.clone(),
+            stored_at: entry.stored_at,
+            ttl_seconds: entry.ttl.as_secs(),
+            stale_after_seconds: entry.stale_after.as_secs(),
+            size_bytes: entry.size_bytes(),
+            entry: None,
+            url: entry.url.clone(),
+        };
+        let index_size = bincode::serialized_size(&record)? as usize;
+        if used + index_size > budget {
+            break;
+        }
+
+        if include_bodies {
+            let encoded = entry.encode(&key)?;
+            // Option tag + Vec length prefix + payload
+            let entry_size = 8 + encoded.len();
+            if used + index_size + entry_size <= budget {
+                record.entry = Some(encoded);
+                used += entry_size;
+            }
+        }
+
+        used += index_size;
+        records.push(record);
+    }
+
+    Ok(bincode::serialize(&records)?)
+}
+
+async fn import_page_cache(
+    cache: &CacheManager,
+    version: u16,
+    payload: &[u8],
+    report: &mut ImportReport,
+) -> Result<()> {
+    let records: Vec<PageCacheRecord> = if version == 1 {
+        bincode::deserialize::<Vec<PageCacheRecordV1>>(payload)
+            .map(|records| records.into_iter().map(Into::into).collect())
+    } else {
+        bincode::deserialize(payload)
+    }
+    .map_err(|err| anyhow!("malformed page_cache: {}", err))?;
+    let now = now_epoch_secs();
+
+    // Records are most recently used first; restore the others before them so
+    // they are the ones evicted if the cache fills up
+    for record in records.into_iter().rev() {
+        if now.saturating_sub(record.stored_at) > record.ttl_seconds {
+            report.expired += 1;
+            continue;
+        }
+
+        // Entries written with another cache entry format fall back to a re-warm
+        let entry = record
+            .entry
+            .as_deref()
+            .and_then(CachedResponse::decode)
+            .filter(|(key, _)| *key == record.key);
+        match entry {
+            Some((key, entry)) => {
+                if cache.restore_response(&key, entry).await {
+                    report.restored += 1;
+                } else {
+                    report.expired += 1;
+                }
+            }
+            None => match record.url {
+                Some(url) => report.warm_urls.push(url),
+                None => report.unwarmable += 1,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct SnapshotWriter {
+    sections: Vec<(u16, u16, Vec<u8>)>,
+}
+
+impl SnapshotWriter {
+    /// Bytes the sections added so far take up
+    fn len(&self) -> usize {
+        self.sections
+            .iter()
+            .map(|(_, _, payload)| SECTION_HEADER_LEN + payload.len())
+            .sum()
+    }
+
+    fn section(&mut self, id: u16, version: u16, payload: Vec<u8>) {
+        self.sections.push((id, version, payload));
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.len());
+        out.extend_from_slice(STATE_MAGIC);
+        out.extend_from_slice(&STATE_FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&now_epoch_secs().to_le_bytes());
+        out.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        let header_crc = crc32(&out);
+        out.extend_from_slice(&header_crc.to_le_bytes());
+
+        for (id, version, payload) in self.sections {
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&version.to_le_bytes());
+            out.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+            out.extend_from_slice(&crc32(&payload).to_le_bytes());
+            out.extend_from_slice(&payload);
+        }
+        out
+    }
+}
+
+struct RawSection<'a> {
+    id: u16,
+    version: u16,
+    checksum: u32,
+    payload: &'a [u8],
+}
+
+fn read_sections(raw: &[u8]) -> Result<Vec<RawSection<'_>>> {
+    let mut reader = Reader(raw);
+    if reader.take(STATE_MAGIC.len())? != STATE_MAGIC {
+        bail!("not a veloserve state snapshot");
+    }
+    let version = reader.u16()?;
+    let _created_at = reader.u64()?;
+    let count = reader.u32()?;
+    let header_crc = reader.u32()?;
+    if header_crc != crc32(&raw[..HEADER_LEN - 4]) {
+        bail!("state snapshot header checksum mismatch");
+    }
+    if version > STATE_FORMAT_VERSION {
+        bail!(
+            "state snapshot format version {} is newer than supported version {}",
+            version,
+            STATE_FORMAT_VERSION
+        );
+    }
+
+    let mut sections = Vec::new();
+    for _ in 0..count {
+        let id = reader.u16()?;
+        let version = reader.u16()?;
+        let len = usize::try_from(reader.u64()?)?;
+        let checksum = reader.u32()?;
+        let payload = reader.take(len)?;
+        sections.push(RawSection {
+            id,
+            version,
+            checksum,
+            payload,
+        });
+    }
+    if !reader.0.is_empty() {
+        bail!("trailing data after the last state snapshot section");
+    }
+    Ok(sections)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("state snapshot is truncated");
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{build_page_cache_key_scoped, CacheLifetime};
+    use crate::config::CacheConfig;
+
+    fn memory_cache() -> CacheManager {
+        CacheManager::new(&CacheConfig {
+            enable: true,
+            l1_enabled: true,
+            l2_enabled: false,
+            ..CacheConfig::default()
+        })
+    }
+
+    async fn populated_cache() -> (CacheManager, String) {
+        let cache = memory_cache();
+        let key =
+            build_page_cache_key_scoped("example.test", None, None, None, "/catalog/a.html?page=2");
+        let entry = CachedResponse::new(
+            203,
+            vec![
+                ("Content-Type".to_string(), "text/html".to_string()),
+                ("ETag".to_string(), "\"a1\"".to_string()),
+            ],
+            "<h1>A</h1>".repeat(200),
+        )
+        .with_tags(vec!["catalog".to_string()])
+        .with_url("http://example.test/catalog/a.html?page=2");
+        cache
+            .set_response(
+                &key,
+                entry,
+                CacheLifetime::from_ttl(Duration::from_secs(3600)),
+            )
+            .await;
+        (cache, key)
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_between_instances() {
+        let (primary, key) = populated_cache().await;
+        let snapshot = export_state(
+            RuntimeState::cache_only(&primary),
+            ExportOptions {
+                include_bodies: true,
+                ..ExportOptions::default()
+            },
+        )
+        .unwrap();
+
+        let standby = memory_cache();
+        let report = import_state(RuntimeState::cache_only(&standby), &snapshot)
+            .await
+            .unwrap();
+        assert_eq!(report.sections, vec!["page_cache"]);
+        assert_eq!(report.restored, 1);
+        assert!(report.skipped.is_empty());
+
+        let original = primary.inspect(&key).unwrap();
+        let restored = standby.get_response(&key).await.unwrap();
+        assert_eq!(restored, original);
+
+        // Tags were indexed on the standby as well
+        assert_eq!(standby.purge_by_tag_count("catalog").await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_index_only_export_becomes_warm_targets() {
+        let (primary, key) = populated_cache().await;
+        let snapshot =
+            export_state(RuntimeState::cache_only(&primary), ExportOptions::default()).unwrap();
+
+        let standby = memory_cache();
+        let report = import_state(RuntimeState::cache_only(&standby), &snapshot)
+            .await
+            .unwrap();
+        assert_eq!(report.restored, 0);
+        assert_eq!(
+            report.warm_urls,
+            vec!["http://example.test/catalog/a.html?page=2"]
+        );
+        assert!(standby.inspect(&key).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_size_limit_drops_bodies_first() {
+        let (primary, _) = populated_cache().await;
+        let full = export_state(
+            RuntimeState::cache_only(&primary),
+            ExportOptions {
+                include_bodies: true,
+                ..ExportOptions::default()
+            },
+        )
+        .unwrap();
+        let limited = export_state(
+            RuntimeState::cache_only(&primary),
+            ExportOptions {
+                include_bodies: true,
+                max_bytes: full.len() - 1,
+            },
+        )
+        .unwrap();
+        assert!(limited.len() < full.len());
+
+        let report = import_state(RuntimeState::cache_only(&memory_cache()), &limited)
+            .await
+            .unwrap();
+        assert_eq!(report.restored, 0);
+        assert_eq!(report.warm_urls.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_partial_import_skips_bad_sections() {
+        let (primary, key) = populated_cache().await;
+        let mut writer = SnapshotWriter::default();
+        writer.section(
+            SECTION_PAGE_CACHE,
+            PAGE_CACHE_SECTION_VERSION,
+            export_page_cache(&primary, true, MAX_SNAPSHOT_BYTES).unwrap(),
+        );
+        writer.section(SECTION_PAGE_CACHE, PAGE_CACHE_SECTION_VERSION + 1, vec![1]);
+        writer.section(99, 1, vec![1, 2, 3]);
+        writer.section(SECTION_PAGE_CACHE, PAGE_CACHE_SECTION_VERSION, vec![0xff]);
+        let mut snapshot = writer.finish();
+        // Corrupt the payload of the last section without touching its checksum
+        *snapshot.last_mut().unwrap() = 0xfe;
+
+        let standby = memory_cache();
+        let report = import_state(RuntimeState::cache_only(&standby), &snapshot)
+            .await
+            .unwrap();
+        assert_eq!(report.sections, vec!["page_cache"]);
+        assert_eq!(report.restored, 1);
+        let reasons: Vec<&str> = report.skipped.iter().map(|s| s.reason.as_str()).collect();
+        assert_eq!(reasons.len(), 3);
+        assert!(reasons[0].starts_with("unsupported page_cache version"));
+        assert_eq!(reasons[1], "unknown section");
+        assert_eq!(reasons[2], "checksum mismatch");
+        assert!(standby.inspect(&key).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_damaged_container() {
+        let (primary, _) = populated_cache().await;
+        let snapshot =
+            export_state(RuntimeState::cache_only(&primary), ExportOptions::default()).unwrap();
+        let cache = memory_cache();
+
+        assert!(
+            import_state(RuntimeState::cache_only(&cache), b"not a snapshot")
+                .await
+                .is_err()
+        );
+        assert!(import_state(
+            RuntimeState::cache_only(&cache),
+            &snapshot[..snapshot.len() - 1]
+        )
+        .await
+        .is_err());
+
+        let mut header = snapshot.clone();
+        header[8] ^= 0xff;
+        assert!(import_state(RuntimeState::cache_only(&cache), &header)
+            .await
+            .is_err());
+
+        let mut newer = SnapshotWriter::default().finish();
+        newer[7..9].copy_from_slice(&(STATE_FORMAT_VERSION + 1).to_le_bytes());
+        let crc = crc32(&newer[..HEADER_LEN - 4]);
+        newer[HEADER_LEN - 4..HEADER_LEN].copy_from_slice(&crc.to_le_bytes());
+        assert!(import_state(RuntimeState::cache_only(&cache), &newer)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_persist_file_survives_restart() {
+        let (before, key) = populated_cache().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join(PERSIST_FILE);
+
+        let after = memory_cache();
+        assert!(load_from_file(&after, &path).await.unwrap().is_none());
+
+        assert!(save_to_file(&before, &path).unwrap() > 0);
+        // Overlapping saves each write their own temporary file
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| save_to_file(&before, &path).unwrap());
+            }
+        });
+        let files: Vec<_> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, [PERSIST_FILE]);
+        let report = load_from_file(&after, &path).await.unwrap().unwrap();
+        assert_eq!(report.restored, 1);
+        assert_eq!(
+            after.get_response(&key).await.unwrap(),
+            before.inspect(&key).unwrap()
+        );
+
+        fs::write(&path, b"VSSTATE garbage").unwrap();
+        assert!(load_from_file(&memory_cache(), &path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limiter_sections_roundtrip() {
+        let (primary_cache, _) = populated_cache().await;
+        let primary_guard = InvalidationGuard::default();
+        primary_guard.restore(InvalidationSnapshot {
+            requests: vec![now_epoch_secs()],
+            dedupe: vec![("tag:catalog".to_string(), now_epoch_secs())],
+        });
+        let limits = SharedLimits::new(&crate::config::Config::default());
+        let primary = RuntimeState {
+            cache: &primary_cache,
+            invalidations: Some(&primary_guard),
+            limits: Some(&limits),
+        };
+        let snapshot = export_state(primary, ExportOptions::default()).unwrap();
+
+        let standby_cache = memory_cache();
+        let standby_guard = InvalidationGuard::default();
+        let standby = RuntimeState {
+            cache: &standby_cache,
+            invalidations: Some(&standby_guard),
+            limits: Some(&limits),
+        };
+        let report = import_state(standby, &snapshot).await.unwrap();
+        assert_eq!(
+            report.sections,
+            vec!["invalidations", "shared_limits", "page_cache"]
+        );
+        assert_eq!(standby_guard.snapshot(), primary_guard.snapshot());
+
+        // A newer invalidations section is skipped; the others still load
+        let mut writer = SnapshotWriter::default();
+        writer.section(
+            SECTION_INVALIDATIONS,
+            INVALIDATIONS_SECTION_VERSION + 1,
+            vec![0xff],
+        );
+        writer.section(
+            SECTION_SHARED_LIMITS,
+            SHARED_LIMITS_SECTION_VERSION,
+            bincode::serialize(&LimitsSnapshot::default()).unwrap(),
+        );
+        let report = import_state(standby, &writer.finish()).await.unwrap();
+        assert_eq!(report.sections, vec!["shared_limits"]);
+        assert_eq!(
+            report.skipped[0].reason,
+            format!(
+                "unsupported invalidations version (newest supported is {})",
+                INVALIDATIONS_SECTION_VERSION
+            )
+        );
+
+        // The persisted cache snapshot has nowhere to restore them
+        let report = import_state(RuntimeState::cache_only(&memory_cache()), &snapshot)
+            .await
+            .unwrap();
+        assert_eq!(report.sections, vec!["page_cache"]);
+        assert_eq!(report.skipped.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_version_one_page_cache_still_imports() {
+        #[derive(Serialize)]
+        struct V1 {
+            key: String,
+            status: u16,
+            tags: Vec<String>,
+            stored_at: u64,
+            ttl_seconds: u64,
+            stale_after_seconds: u64,
+            size_bytes: u64,
+            entry: Option<Vec<u8>>,
+        }
+        let record = V1 {
+            key: "page:example.test:/a".to_string(),
+            status: 200,
+            tags: Vec::new(),
+            stored_at: now_epoch_secs(),
+            ttl_seconds: 60,
+            stale_after_seconds: 60,
+            size_bytes: 1,
+            entry: None,
+        };
+        let mut writer = SnapshotWriter::default();
+        writer.section(
+            SECTION_PAGE_CACHE,
+            1,
+            bincode::serialize(&vec![record]).unwrap(),
+        );
+
+        let report = import_state(RuntimeState::cache_only(&memory_cache()), &writer.finish())
+            .await
+            .unwrap();
+        assert_eq!(report.sections, vec!["page_cache"]);
+        assert!(report.warm_urls.is_empty());
+        assert_eq!(report.unwarmable, 1);
+    }
+}
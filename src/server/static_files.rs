@@ -2,20 +2,333 @@
 //!
 //! Serves static files like Nginx/Apache/LiteSpeed with:
 //! - Proper MIME type detection
-//! - ETag and Last-Modified headers
-//! - Conditional requests (If-None-Match, If-Modified-Since)
-//! - Cache-Control headers based on file type
+//! - ETag (metadata or content hash, see `server.etag`) and Last-Modified headers
+//! - Conditional requests (If-Match, If-Unmodified-Since, If-None-Match,
+//!   If-Modified-Since)
+//! - Cache-Control headers based on file type, overridable per vhost
 //! - Content-Length header
+//! - Byte ranges (Range, If-Range) with 206 and 416 responses; several ranges
+//!   are sent as `multipart/byteranges`
+//! - Precompressed `.br` / `.gz` siblings (like gzip_static/brotli_static)
+//! - Large bodies streamed from the file in chunks, small ones read at once
 
 use anyhow::{anyhow, Result};
-use bytes::Bytes;
-use http_body_util::Full;
+use bytes::{Bytes, BytesMut};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH,
+    CONTENT_RANGE, CONTENT_TYPE, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+    IF_UNMODIFIED_SINCE, RANGE,
+};
+use hyper::http::response::Builder;
 use hyper::{Response, StatusCode};
-use std::path::Path;
-use std::time::SystemTime;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::io::{Read, SeekFrom};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime};
 use tokio::fs::{self, File};
-use tokio::io::AsyncReadExt;
-use tracing::debug;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+use tracing::{debug, warn};
+
+use crate::config::{Config, EtagMode, StaticConfig};
+use crate::server::streaming::StreamingBody;
+use crate::server::vhost::glob_matches;
+use crate::server::zerocopy::ZeroCopySlot;
+
+/// Most ranges honoured in one `Range` header; more get the full body
+const MAX_RANGES: usize = 16;
+
+/// Files whose content hash is remembered for strong ETags
+const CONTENT_HASH_ENTRIES: usize = 10_000;
+
+/// Content hashes by path, each valid while the file keeps its size and mtime
+static CONTENT_HASHES: Lazy<Mutex<LruCache<PathBuf, ContentHash>>> = Lazy::new(|| {
+    Mutex::new(LruCache::new(
+        NonZeroUsize::new(CONTENT_HASH_ENTRIES).expect("non-zero capacity"),
+    ))
+});
+
+#[derive(Clone)]
+struct ContentHash {
+    size: u64,
+    modified: SystemTime,
+    hash: String,
+}
+
+/// Built-in content types by extension; text types get
+/// `static.default_charset` appended
+const BUILTIN_MIME_TYPES: &[(&str, &str)] = &[
+    // HTML & Templates
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"),
+    // CSS
+    ("css", "text/css"),
+    // JavaScript
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    // Images
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("tiff", "image/tiff"),
+    ("tif", "image/tiff"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Documents
+    ("pdf", "application/pdf"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("rtf", "application/rtf"),
+    // Media - Video
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("avi", "video/x-msvideo"),
+    ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"),
+    // Media - Audio
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("m4a", "audio/mp4"),
+    // Archives
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("gzip", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("rar", "application/vnd.rar"),
+    ("7z", "application/x-7z-compressed"),
+    ("bz2", "application/x-bzip2"),
+    // Web Assembly
+    ("wasm", "application/wasm"),
+    // Manifest files
+    ("webmanifest", "application/manifest+json"),
+    ("appcache", "text/cache-manifest"),
+    // Data formats
+    ("yaml", "text/yaml"),
+    ("yml", "text/yaml"),
+    ("toml", "text/toml"),
+    // Source code (for syntax highlighting)
+    ("php", "text/x-php"),
+    ("py", "text/x-python"),
+    ("rb", "text/x-ruby"),
+    ("rs", "text/x-rust"),
+    ("go", "text/x-go"),
+    ("java", "text/x-java"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("hpp", "text/x-c++"),
+    ("cc", "text/x-c++"),
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+];
+
+/// Content type of files with an unknown extension
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// The built-in table with the default charset
+static DEFAULT_MIME_TYPES: Lazy<Arc<MimeTypes>> = Lazy::new(|| Arc::new(MimeTypes::default()));
+
+/// Extension to `Content-Type` table, built once per configuration load
+///
+/// `[mime_types]` entries replace built-in ones for the same extension.
+#[derive(Debug)]
+pub struct MimeTypes {
+    by_extension: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    /// Merge `[mime_types]` over the built-in table
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.mime_types, &config.static_files.default_charset)
+    }
+
+    /// Merge `overrides` over the built-in table, appending `charset` to
+    /// text types that don't name one
+    pub fn new(overrides: &HashMap<String, String>, charset: &str) -> Self {
+        let mut by_extension: HashMap<String, String> = BUILTIN_MIME_TYPES
+            .iter()
+            .map(|(extension, media)| (extension.to_string(), with_charset(media, charset)))
+            .collect();
+        for (extension, media) in overrides {
+            let extension = extension.strip_prefix('.').unwrap_or(extension);
+            by_extension.insert(
+                extension.to_ascii_lowercase(),
+                with_charset(media.trim(), charset),
+            );
+        }
+        Self { by_extension }
+    }
+
+    /// Content type for `path`'s extension
+    pub fn lookup(&self, path: &Path) -> &str {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| self.by_extension.get(&e.to_ascii_lowercase()))
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_MIME_TYPE)
+    }
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self::new(&HashMap::new(), "utf-8")
+    }
+}
+
+/// Types that get `static.default_charset`, as in Nginx's `charset_types`;
+/// JSON defines no charset parameter, and source files are served as-is
+const CHARSET_TYPES: &[&str] = &[
+    "text/html",
+    "text/css",
+    "text/plain",
+    "text/xml",
+    "text/csv",
+    "text/markdown",
+    "text/javascript",
+    "application/javascript",
+    "application/xhtml+xml",
+    "application/rss+xml",
+    "application/atom+xml",
+];
+
+/// Append `; charset=` to [`CHARSET_TYPES`] without one
+fn with_charset(media: &str, charset: &str) -> String {
+    let essence = media_essence(media);
+    let textual = CHARSET_TYPES.contains(&essence.as_str());
+    let has_charset = media.split(';').skip(1).any(|parameter| {
+        parameter
+            .trim()
+            .to_ascii_lowercase()
+            .starts_with("charset=")
+    });
+    if charset.is_empty() || !textual || has_charset {
+        media.to_string()
+    } else {
+        format!("{}; charset={}", media, charset)
+    }
+}
+
+/// Lowercased `type/subtype` without parameters
+fn media_essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// A vhost's `static_cache_control` table, built once per configuration load
+///
+/// Keys containing `/` are globs on the MIME type (`image/*`, `text/html`),
+/// the rest globs on the file name (`*.json`, `robots.txt`; `.json` is short
+/// for `*.json`), all case-insensitive. A file-name pattern beats a MIME
+/// pattern, and among patterns of the same kind the most specific one (the
+/// most characters that aren't `*` or `?`) wins, then the first
+/// alphabetically. Files no pattern matches keep the built-in policy.
+#[derive(Debug, Default)]
+pub struct CacheControlRules {
+    /// Rules in precedence order
+    rules: Vec<CacheControlRule>,
+}
+
+#[derive(Debug)]
+struct CacheControlRule {
+    glob: String,
+    /// Matched against the MIME type rather than the file name
+    by_mime: bool,
+    value: String,
+}
+
+impl CacheControlRules {
+    /// Compile `overrides`; fails on an empty pattern or an unusable value
+    pub fn new(overrides: &HashMap<String, String>) -> Result<Self, String> {
+        let mut rules = overrides
+            .iter()
+            .map(|(pattern, value)| {
+                let mut glob = pattern.trim().to_lowercase();
+                if glob.is_empty() {
+                    return Err("empty pattern".to_string());
+                }
+                let value = value.trim();
+                if value.is_empty() || HeaderValue::from_str(value).is_err() {
+                    return Err(format!("invalid value for '{}': {:?}", pattern, value));
+                }
+                let by_mime = glob.contains('/');
+                if !by_mime && glob.starts_with('.') && !glob.contains(['*', '?']) {
+                    glob.insert(0, '*');
+                }
+                Ok(CacheControlRule {
+                    glob,
+                    by_mime,
+                    value: value.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let specificity = |rule: &CacheControlRule| {
+            rule.glob
+                .chars()
+                .filter(|c| !matches!(c, '*' | '?'))
+                .count()
+        };
+        rules.sort_by(|a, b| {
+            a.by_mime
+                .cmp(&b.by_mime)
+                .then_with(|| specificity(b).cmp(&specificity(a)))
+                .then_with(|| a.glob.cmp(&b.glob))
+        });
+        Ok(Self { rules })
+    }
+
+    /// The configured `Cache-Control` for `path` served as `mime_type`
+    pub fn lookup(&self, path: &Path, mime_type: &str) -> Option<&str> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let essence = media_essence(mime_type);
+        self.rules
+            .iter()
+            .find(|rule| {
+                let text = if rule.by_mime { &essence } else { &name };
+                glob_matches(&rule.glob, text)
+            })
+            .map(|rule| rule.value.as_str())
+    }
+}
 
 /// Handler for serving static files
 ///
@@ -24,67 +337,137 @@ use tracing::debug;
 /// - ETag generation for cache validation  
 /// - Last-Modified headers
 /// - Configurable cache control
+#[derive(Clone)]
 pub struct StaticFileHandler {
-    /// Maximum file size to serve (prevents memory issues)
-    max_file_size: u64,
+    /// Bodies longer than this are streamed rather than read into memory
+    stream_threshold: u64,
+    /// Bytes read per streamed chunk
+    chunk_size: usize,
+    /// How ETags are derived
+    etag_mode: EtagMode,
+    /// Content types by extension
+    mime_types: Arc<MimeTypes>,
+    /// The vhost's `Cache-Control` overrides
+    cache_control: Arc<CacheControlRules>,
+    /// Lowercased extensions sent as attachments
+    force_download: Arc<Vec<String>>,
+    /// Answer with headers only, without reading file contents
+    head: bool,
+    /// Hand streamed bodies to the connection's sendfile(2) path
+    zero_copy: Option<ZeroCopySlot>,
 }
 
 impl StaticFileHandler {
-    /// Create a new static file handler
+    /// Create a new static file handler with default settings
     pub fn new() -> Self {
+        Self::from_config(&StaticConfig::default())
+    }
+
+    /// Create a static file handler from the `[static]` settings
+    pub fn from_config(config: &StaticConfig) -> Self {
         Self {
-            max_file_size: 100 * 1024 * 1024, // 100MB
+            stream_threshold: config.stream_threshold,
+            chunk_size: config.stream_chunk_size,
+            etag_mode: EtagMode::default(),
+            mime_types: DEFAULT_MIME_TYPES.clone(),
+            cache_control: Arc::default(),
+            force_download: Arc::default(),
+            head: false,
+            zero_copy: None,
         }
     }
 
-    /// Serve a static file
-    pub async fn serve(&self, path: &Path) -> Result<Response<Full<Bytes>>> {
-        // Check if file exists
-        if !path.exists() {
-            return Err(anyhow!("File not found: {:?}", path));
-        }
+    /// Use `server.etag` for entity tags
+    pub fn with_etag_mode(mut self, etag_mode: EtagMode) -> Self {
+        self.etag_mode = etag_mode;
+        self
+    }
 
-        // Check if it's a file (not a directory)
-        if !path.is_file() {
-            return Err(anyhow!("Not a file: {:?}", path));
-        }
+    /// Answer a HEAD request: the same status and headers, including the
+    /// `Content-Length` a GET would get, but the file is never read
+    pub fn with_head(mut self, head: bool) -> Self {
+        self.head = head;
+        self
+    }
+
+    /// Send streamed bodies through the connection's `slot` with
+    /// sendfile(2) instead of reading them (`server.sendfile`)
+    pub fn with_zero_copy(mut self, slot: Option<ZeroCopySlot>) -> Self {
+        self.zero_copy = slot;
+        self
+    }
+
+    /// Use the merged `[mime_types]` table
+    pub fn with_mime_types(mut self, mime_types: Arc<MimeTypes>) -> Self {
+        self.mime_types = mime_types;
+        self
+    }
+
+    /// Use a vhost's `static_cache_control` overrides
+    pub fn with_cache_control(mut self, cache_control: Arc<CacheControlRules>) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    /// Send files with these extensions (lowercase, without the dot; may
+    /// span dots, as in `tar.gz`) as attachments
+    pub fn with_force_download(mut self, extensions: Arc<Vec<String>>) -> Self {
+        self.force_download = extensions;
+        self
+    }
 
-        // Get file metadata
+    /// Serve a static file, honouring a `Range` in `headers`
+    ///
+    /// With `precompressed`, a `.br` or `.gz` sibling is sent instead when
+    /// the client accepts that encoding and the sibling is at least as new as
+    /// the file itself.
+    pub async fn serve(
+        &self,
+        path: &Path,
+        headers: &HeaderMap,
+        precompressed: bool,
+    ) -> Result<Response<Full<Bytes>>> {
+        // Get file metadata; I/O errors are passed on as-is so callers can
+        // tell a failing filesystem from a missing file
         let metadata = fs::metadata(path).await?;
-        let file_size = metadata.len();
 
-        // Check file size
-        if file_size > self.max_file_size {
-            return Err(anyhow!("File too large: {} bytes", file_size));
+        // Check if it's a file (not a directory)
+        if !metadata.is_file() {
+            return Err(anyhow!("Not a file: {:?}", path));
         }
 
-        // Get modification time for Last-Modified and ETag
-        let modified = metadata.modified().ok();
-        let etag = self.generate_etag(path, file_size, modified);
-        let last_modified = modified.map(|t| format_http_date(t));
-
         // Determine MIME type
         let mime_type = self.guess_mime_type(path);
 
+        let Representation {
+            path: body_path,
+            encoding,
+            modified,
+            etag,
+        } = self
+            .representation(path, &metadata, headers, precompressed)
+            .await;
+        let body_path = body_path.as_path();
+        let last_modified = modified.map(format_http_date);
+
         debug!(
-            "Serving {:?} ({}, {} bytes, etag={})",
-            path, mime_type, file_size, etag
+            "Serving {:?} ({}, {:?}, etag={})",
+            body_path, mime_type, encoding, etag
         );
 
-        // Read file contents
-        let mut file = File::open(path).await?;
-        let mut contents = Vec::with_capacity(file_size as usize);
-        file.read_to_end(&mut contents).await?;
+        let file = File::open(body_path).await?;
+        let size = file.metadata().await?.len();
 
         // Build response with headers like Nginx/Apache
         let mut builder = Response::builder()
-            .status(StatusCode::OK)
             .header("Content-Type", mime_type)
-            .header("Content-Length", file_size)
             .header("Server", crate::SERVER_NAME)
             .header("Accept-Ranges", "bytes")
-            .header("ETag", format!("\"{}\"", etag))
+            .header("ETag", &etag)
             .header("X-Content-Type-Options", "nosniff");
+        if let Some(encoding) = encoding {
+            builder = builder.header(CONTENT_ENCODING, encoding.token());
+        }
 
         // Add Last-Modified header
         if let Some(ref lm) = last_modified {
@@ -92,58 +475,441 @@ impl StaticFileHandler {
         }
 
         // Add Cache-Control based on file type
-        builder = builder.header("Cache-Control", self.cache_control(mime_type));
+        builder = builder.header("Cache-Control", self.cache_control(path, mime_type));
 
         // Add Vary header for encoded content
         builder = builder.header("Vary", "Accept-Encoding");
 
-        builder
-            .body(Full::new(Bytes::from(contents)))
-            .map_err(|e| anyhow!("Failed to build response: {}", e))
+        if let Some(disposition) = self.attachment(path) {
+            builder = builder.header(CONTENT_DISPOSITION, disposition);
+        }
+
+        let validators = Validators {
+            etag: &etag,
+            last_modified: last_modified.as_deref(),
+        };
+        self.ranged_response(builder, file, size, headers, &validators)
+            .await
+    }
+
+    /// Serve a converted image variant of `source`
+    ///
+    /// Validators derive from the source file, with the ETag suffixed by the
+    /// variant format so caches keep variants apart.
+    pub async fn serve_variant(
+        &self,
+        source: &Path,
+        variant: &Path,
+        mime_type: &str,
+        etag_suffix: &str,
+        headers: &HeaderMap,
+    ) -> Result<Response<Full<Bytes>>> {
+        let metadata = fs::metadata(source).await?;
+        let modified = metadata.modified().ok();
+        let etag = self
+            .entity_tag(source, metadata.len(), modified, Some(etag_suffix))
+            .await;
+
+        let file = File::open(variant).await?;
+        let size = file.metadata().await?.len();
+        debug!("Serving {:?} as {} ({} bytes)", source, mime_type, size);
+
+        let last_modified = modified.map(format_http_date);
+        let mut builder = Response::builder()
+            .header("Content-Type", mime_type)
+            .header("Server", crate::SERVER_NAME)
+            .header("Accept-Ranges", "bytes")
+            .header("ETag", &etag)
+            .header("X-Content-Type-Options", "nosniff")
+            .header("Cache-Control", self.cache_control(source, mime_type));
+        if let Some(ref lm) = last_modified {
+            builder = builder.header("Last-Modified", lm);
+        }
+        if let Some(disposition) = self.attachment(source) {
+            builder = builder.header(CONTENT_DISPOSITION, disposition);
+        }
+
+        let validators = Validators {
+            etag: &etag,
+            last_modified: last_modified.as_deref(),
+        };
+        self.ranged_response(builder, file, size, headers, &validators)
+            .await
     }
 
-    /// Serve with conditional request support (304 Not Modified)
+    /// Finish a static response from `file`, cutting the body down to a
+    /// requested range and streaming it when it is over the threshold
+    async fn ranged_response(
+        &self,
+        builder: Builder,
+        mut file: File,
+        size: u64,
+        headers: &HeaderMap,
+        validators: &Validators<'_>,
+    ) -> Result<Response<Full<Bytes>>> {
+        let range = match headers.get(RANGE).and_then(|h| h.to_str().ok()) {
+            Some(range) if if_range_matches(headers, validators) => parse_range(range, size),
+            _ => ByteRange::Full,
+        };
+
+        let (builder, first, length) = match range {
+            ByteRange::Full => (builder.status(StatusCode::OK), 0, size),
+            ByteRange::Partial(first, last) => (
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, size)),
+                first,
+                last - first + 1,
+            ),
+            ByteRange::Multiple(ranges) => {
+                return self.multipart_response(builder, file, size, &ranges).await;
+            }
+            ByteRange::Unsatisfiable => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", size))
+                    .header(CONTENT_LENGTH, 0)
+                    .body(Full::new(Bytes::new()))
+                    .map_err(|e| anyhow!("Failed to build response: {}", e));
+            }
+        };
+
+        let builder = builder.header(CONTENT_LENGTH, length);
+        if self.head {
+            return builder
+                .body(Full::new(Bytes::new()))
+                .map_err(|e| anyhow!("Failed to build response: {}", e));
+        }
+        if first > 0 {
+            file.seek(SeekFrom::Start(first)).await?;
+        }
+
+        let response = if length > self.stream_threshold {
+            let body = match &self.zero_copy {
+                Some(slot) => slot.body(file.into_std().await, first, length).boxed(),
+                None => FileBody::new(file, length, self.chunk_size).boxed(),
+            };
+            builder
+                .extension(StreamingBody::from_body(body))
+                .body(Full::new(Bytes::new()))
+        } else {
+            let mut contents = Vec::with_capacity(length as usize);
+            file.take(length).read_to_end(&mut contents).await?;
+            if contents.len() as u64 != length {
+                return Err(anyhow!("File changed while being read"));
+            }
+            builder.body(Full::new(Bytes::from(contents)))
+        };
+        response.map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// A `206` carrying `ranges` of `file` as `multipart/byteranges`, each
+    /// part with its own Content-Type and Content-Range
+    async fn multipart_response(
+        &self,
+        mut builder: Builder,
+        file: File,
+        size: u64,
+        ranges: &[(u64, u64)],
+    ) -> Result<Response<Full<Bytes>>> {
+        let content_type = builder
+            .headers_ref()
+            .and_then(|headers| headers.get(CONTENT_TYPE))
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let boundary = multipart_boundary();
+
+        let parts = ranges
+            .iter()
+            .map(|&(first, last)| {
+                let mut head = format!("\r\n--{}\r\n", boundary);
+                if let Some(content_type) = &content_type {
+                    head.push_str(&format!("Content-Type: {}\r\n", content_type));
+                }
+                head.push_str(&format!(
+                    "Content-Range: bytes {}-{}/{}\r\n\r\n",
+                    first, last, size
+                ));
+                RangePart {
+                    head: Bytes::from(head),
+                    first,
+                    length: last - first + 1,
+                }
+            })
+            .collect();
+        let trailer = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+        let body = MultipartBody::new(file, parts, trailer, self.chunk_size);
+        let length = body.remaining;
+
+        if let Some(headers) = builder.headers_mut() {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))?,
+            );
+        }
+        let builder = builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(CONTENT_LENGTH, length);
+        if self.head {
+            return builder
+                .body(Full::new(Bytes::new()))
+                .map_err(|e| anyhow!("Failed to build response: {}", e));
+        }
+
+        let response = if length > self.stream_threshold {
+            builder
+                .extension(StreamingBody::from_body(body.boxed()))
+                .body(Full::new(Bytes::new()))
+        } else {
+            let contents = body
+                .collect()
+                .await
+                .unwrap_or_else(|never| match never {})
+                .to_bytes();
+            if contents.len() as u64 != length {
+                return Err(anyhow!("File changed while being read"));
+            }
+            builder.body(Full::new(contents))
+        };
+        response.map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
+
+    /// Serve with conditional request support (304 Not Modified, 412
+    /// Precondition Failed)
+    ///
+    /// The preconditions in `headers` are checked before anything else, so a
+    /// conditional range request revalidates first; otherwise this is
+    /// [`Self::serve`].
     pub async fn serve_conditional(
         &self,
         path: &Path,
-        if_none_match: Option<&str>,
-        if_modified_since: Option<&str>,
+        headers: &HeaderMap,
+        precompressed: bool,
     ) -> Result<Response<Full<Bytes>>> {
-        // Get file metadata first
+        match self
+            .preconditions(path, headers, precompressed, None)
+            .await?
+        {
+            Some(response) => Ok(response),
+            None => self.serve(path, headers, precompressed).await,
+        }
+    }
+
+    /// A `412 Precondition Failed` or `304 Not Modified` for `path` when the
+    /// request's preconditions call for one
+    ///
+    /// They are evaluated in RFC 9110 order: `If-Match`, or
+    /// `If-Unmodified-Since` when it is absent, then `If-None-Match`, or
+    /// `If-Modified-Since` when it is absent. `If-Match` compares strongly and
+    /// exactly, so only the tag of the representation being served satisfies
+    /// it. `If-None-Match` uses weak comparison, so it matches in every ETag
+    /// mode, and also takes the tags we send for on-the-fly encodings
+    /// (`W/"…-gz"`). Dates that don't parse are ignored. With `precompressed`,
+    /// the validators are those of the sibling [`Self::serve`] would send;
+    /// `variant` is the ETag suffix of an image variant that will be served
+    /// in place of the file (`"webp"`).
+    pub async fn preconditions(
+        &self,
+        path: &Path,
+        headers: &HeaderMap,
+        precompressed: bool,
+        variant: Option<&str>,
+    ) -> Result<Option<Response<Full<Bytes>>>> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|h: &HeaderValue| h.to_str().ok())
+        };
+        let (if_match, if_unmodified_since) = (header(IF_MATCH), header(IF_UNMODIFIED_SINCE));
+        let (if_none_match, if_modified_since) = (header(IF_NONE_MATCH), header(IF_MODIFIED_SINCE));
+        if if_match.is_none()
+            && if_unmodified_since.is_none()
+            && if_none_match.is_none()
+            && if_modified_since.is_none()
+        {
+            return Ok(None);
+        }
+
         let metadata = fs::metadata(path).await?;
-        let file_size = metadata.len();
-        let modified = metadata.modified().ok();
-        let etag = self.generate_etag(path, file_size, modified);
-
-        // Check If-None-Match (ETag)
-        if let Some(client_etag) = if_none_match {
-            let client_etag = client_etag.trim_matches('"');
-            if client_etag == etag || client_etag == "*" {
-                return Ok(Response::builder()
-                    .status(StatusCode::NOT_MODIFIED)
-                    .header("Server", crate::SERVER_NAME)
-                    .header("ETag", format!("\"{}\"", etag))
-                    .body(Full::new(Bytes::new()))
-                    .unwrap());
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        let Representation {
+            modified, mut etag, ..
+        } = self
+            .representation(path, &metadata, headers, precompressed)
+            .await;
+        if let Some(suffix) = variant {
+            etag = self
+                .entity_tag(path, metadata.len(), modified, Some(suffix))
+                .await;
+        }
+
+        // HTTP dates have whole seconds, so compare at that resolution
+        let unmodified_since = |date: &str, file_modified: SystemTime| {
+            parse_http_date(date)
+                .map(|client_time| unix_secs(file_modified) <= unix_secs(client_time))
+        };
+
+        let failed = match (if_match, if_unmodified_since, modified) {
+            (Some(if_match), _, _) => !match_includes(if_match, &etag),
+            (None, Some(ius), Some(file_modified)) => {
+                unmodified_since(ius, file_modified).is_ok_and(|unmodified| !unmodified)
             }
+            _ => false,
+        };
+        if failed {
+            let mut builder = Response::builder()
+                .status(StatusCode::PRECONDITION_FAILED)
+                .header("Server", crate::SERVER_NAME)
+                .header("ETag", &etag);
+            if let Some(modified) = modified {
+                builder = builder.header("Last-Modified", format_http_date(modified));
+            }
+            return builder
+                .body(Full::new(Bytes::new()))
+                .map(Some)
+                .map_err(|e| anyhow!("Failed to build response: {}", e));
+        }
+
+        let current = match (if_none_match, if_modified_since, modified) {
+            (Some(if_none_match), _, _) => none_match_includes(if_none_match, &etag),
+            (None, Some(ims), Some(file_modified)) => unmodified_since(ims, file_modified)
+                .is_ok_and(|unmodified| unmodified)
+                .then(|| etag.clone()),
+            _ => None,
+        };
+        let Some(etag) = current else {
+            return Ok(None);
+        };
+
+        // The headers a 200 would carry that describe the representation
+        let mut builder = Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("Server", crate::SERVER_NAME)
+            .header("ETag", etag)
+            .header(
+                "Cache-Control",
+                self.cache_control(path, self.guess_mime_type(path)),
+            )
+            .header("Vary", "Accept-Encoding");
+        if let Some(modified) = modified {
+            builder = builder.header("Last-Modified", format_http_date(modified));
         }
+        builder
+            .body(Full::new(Bytes::new()))
+            .map(Some)
+            .map_err(|e| anyhow!("Failed to build response: {}", e))
+    }
 
-        // Check If-Modified-Since
-        if let (Some(ims), Some(file_modified)) = (if_modified_since, modified) {
-            if let Ok(client_time) = parse_http_date(ims) {
-                if file_modified <= client_time {
-                    return Ok(Response::builder()
-                        .status(StatusCode::NOT_MODIFIED)
-                        .header("Server", crate::SERVER_NAME)
-                        .header("ETag", format!("\"{}\"", etag))
-                        .body(Full::new(Bytes::new()))
-                        .unwrap());
+    /// The file that answers a request for `path`, with its validators
+    ///
+    /// With `precompressed`, a sibling the client accepts replaces the file,
+    /// and its own size and mtime make the validators, so a rebuilt sibling
+    /// gets a new ETag; the tag is marked per encoding.
+    async fn representation(
+        &self,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+        headers: &HeaderMap,
+        precompressed: bool,
+    ) -> Representation {
+        let modified = metadata.modified().ok();
+        let encoded = if precompressed {
+            self.precompressed_sibling(path, modified, headers).await
+        } else {
+            None
+        };
+        let (path, encoding, size, modified) = match encoded {
+            Some((sibling, encoding, metadata)) => (
+                sibling,
+                Some(encoding),
+                metadata.len(),
+                metadata.modified().ok(),
+            ),
+            None => (path.to_path_buf(), None, metadata.len(), modified),
+        };
+        let etag = self
+            .entity_tag(
+                &path,
+                size,
+                modified,
+                encoding.map(Precompressed::etag_suffix),
+            )
+            .await;
+        Representation {
+            path,
+            encoding,
+            modified,
+            etag,
+        }
+    }
+
+    /// A usable `.br` / `.gz` sibling of `path` the client accepts, with its
+    /// metadata, trying the client's preferred encoding first (brotli on a tie)
+    async fn precompressed_sibling(
+        &self,
+        path: &Path,
+        modified: Option<SystemTime>,
+        headers: &HeaderMap,
+    ) -> Option<(PathBuf, Precompressed, std::fs::Metadata)> {
+        let accept = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+        let mut encodings = [Precompressed::Brotli, Precompressed::Gzip];
+        encodings.sort_by(|a, b| {
+            encoding_quality(accept, b.token()).total_cmp(&encoding_quality(accept, a.token()))
+        });
+        for encoding in encodings {
+            if !accepts_encoding(accept, encoding.token()) {
+                continue;
+            }
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(encoding.extension());
+            let sibling = PathBuf::from(sibling);
+
+            let Ok(metadata) = fs::metadata(&sibling).await else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            // A sibling older than the original is left over from a previous deploy
+            if let (Some(original), Ok(compressed)) = (modified, metadata.modified()) {
+                if compressed < original {
+                    continue;
                 }
             }
+            return Some((sibling, encoding, metadata));
         }
+        None
+    }
 
-        // Serve the full file
-        self.serve(path).await
+    /// The quoted entity tag for `path`, with `suffix` appended for
+    /// encodings and variants and `W/` in weak mode
+    async fn entity_tag(
+        &self,
+        path: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+        suffix: Option<&str>,
+    ) -> String {
+        let opaque = match (self.etag_mode, modified) {
+            (EtagMode::Strong, Some(modified)) => match content_hash(path, size, modified).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    debug!("Falling back to a metadata ETag for {:?}: {}", path, e);
+                    self.generate_etag(path, size, Some(modified))
+                }
+            },
+            _ => self.generate_etag(path, size, modified),
+        };
+        let opaque = match suffix {
+            Some(suffix) => format!("{}-{}", opaque, suffix),
+            None => opaque,
+        };
+        match self.etag_mode {
+            EtagMode::Weak => format!("W/\"{}\"", opaque),
+            EtagMode::Strong | EtagMode::Mtime => format!("\"{}\"", opaque),
+        }
     }
 
     /// Generate ETag from file metadata
@@ -165,123 +931,47 @@ impl StaticFileHandler {
     }
 
     /// Guess MIME type from file extension
-    fn guess_mime_type(&self, path: &Path) -> &'static str {
-        let extension = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-
-        match extension.as_str() {
-            // HTML & Templates
-            "html" | "htm" => "text/html; charset=utf-8",
-            "xhtml" => "application/xhtml+xml; charset=utf-8",
-
-            // CSS
-            "css" => "text/css; charset=utf-8",
-
-            // JavaScript
-            "js" | "mjs" => "application/javascript; charset=utf-8",
-            "json" => "application/json; charset=utf-8",
-            "map" => "application/json",
-
-            // Images
-            "png" => "image/png",
-            "jpg" | "jpeg" => "image/jpeg",
-            "gif" => "image/gif",
-            "webp" => "image/webp",
-            "svg" => "image/svg+xml",
-            "ico" => "image/x-icon",
-            "avif" => "image/avif",
-            "bmp" => "image/bmp",
-            "tiff" | "tif" => "image/tiff",
-
-            // Fonts
-            "woff" => "font/woff",
-            "woff2" => "font/woff2",
-            "ttf" => "font/ttf",
-            "otf" => "font/otf",
-            "eot" => "application/vnd.ms-fontobject",
-
-            // Documents
-            "pdf" => "application/pdf",
-            "xml" => "application/xml",
-            "txt" => "text/plain; charset=utf-8",
-            "md" => "text/markdown; charset=utf-8",
-            "csv" => "text/csv; charset=utf-8",
-            "rtf" => "application/rtf",
-
-            // Media - Video
-            "mp4" => "video/mp4",
-            "webm" => "video/webm",
-            "ogv" => "video/ogg",
-            "avi" => "video/x-msvideo",
-            "mov" => "video/quicktime",
-            "mkv" => "video/x-matroska",
-
-            // Media - Audio
-            "mp3" => "audio/mpeg",
-            "ogg" | "oga" => "audio/ogg",
-            "wav" => "audio/wav",
-            "flac" => "audio/flac",
-            "aac" => "audio/aac",
-            "m4a" => "audio/mp4",
-
-            // Archives
-            "zip" => "application/zip",
-            "gz" | "gzip" => "application/gzip",
-            "tar" => "application/x-tar",
-            "rar" => "application/vnd.rar",
-            "7z" => "application/x-7z-compressed",
-            "bz2" => "application/x-bzip2",
-
-            // Web Assembly
-            "wasm" => "application/wasm",
-
-            // Manifest files
-            "webmanifest" => "application/manifest+json",
-            "appcache" => "text/cache-manifest",
-
-            // Data formats
-            "yaml" | "yml" => "text/yaml",
-            "toml" => "text/toml",
-
-            // Source code (for syntax highlighting)
-            "php" => "text/x-php",
-            "py" => "text/x-python",
-            "rb" => "text/x-ruby",
-            "rs" => "text/x-rust",
-            "go" => "text/x-go",
-            "java" => "text/x-java",
-            "c" | "h" => "text/x-c",
-            "cpp" | "hpp" | "cc" => "text/x-c++",
-            "sh" | "bash" => "text/x-shellscript",
-
-            // Default
-            _ => "application/octet-stream",
-        }
-    }
-
-    /// Get appropriate Cache-Control header based on MIME type
-    /// Similar to Nginx/Apache defaults
-    fn cache_control(&self, mime_type: &str) -> &'static str {
+    fn guess_mime_type(&self, path: &Path) -> &str {
+        self.mime_types.lookup(path)
+    }
+
+    /// `Content-Disposition: attachment` naming `path`, when its extension
+    /// is in `force_download`
+    fn attachment(&self, path: &Path) -> Option<HeaderValue> {
+        let name = path.file_name()?.to_string_lossy();
+        let lower = name.to_lowercase();
+        self.force_download
+            .iter()
+            .any(|extension| {
+                lower
+                    .strip_suffix(extension.as_str())
+                    .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+            })
+            .then(|| attachment_disposition(&name))
+    }
+
+    /// Cache-Control for `path` served as `mime_type`: the vhost's override
+    /// if one matches, else a default based on the MIME type similar to
+    /// Nginx/Apache
+    fn cache_control<'a>(&'a self, path: &Path, mime_type: &str) -> &'a str {
+        if let Some(value) = self.cache_control.lookup(path, mime_type) {
+            return value;
+        }
+        let mime_type = media_essence(mime_type);
         // Static assets that rarely change - aggressive caching
         if mime_type.starts_with("image/")
             || mime_type.starts_with("font/")
-            || mime_type == "application/javascript; charset=utf-8"
-            || mime_type == "text/css; charset=utf-8"
+            || mime_type == "application/javascript"
+            || mime_type == "text/javascript"
+            || mime_type == "text/css"
             || mime_type == "application/wasm"
         {
             // 1 year cache for static assets (like Nginx)
             "public, max-age=31536000, immutable"
         }
         // HTML files - allow revalidation while enabling server-side page cache.
-        else if mime_type.starts_with("text/html") {
-            "public, max-age=0, must-revalidate"
-        }
         // JSON/API responses - short cache
-        else if mime_type == "application/json" || mime_type == "application/json; charset=utf-8"
-        {
+        else if mime_type == "text/html" || mime_type == "application/json" {
             "public, max-age=0, must-revalidate"
         }
         // Media files - moderate caching
@@ -301,6 +991,552 @@ impl Default for StaticFileHandler {
     }
 }
 
+/// Hash of the file's contents, reused while its size and mtime stay put
+async fn content_hash(path: &Path, size: u64, modified: SystemTime) -> std::io::Result<String> {
+    if let Some(cached) = CONTENT_HASHES.lock().get(path) {
+        if cached.size == size && cached.modified == modified {
+            return Ok(cached.hash.clone());
+        }
+    }
+
+    let file_path = path.to_path_buf();
+    let (hash, unchanged) = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&file_path)?;
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+        // A file rewritten while it was read may not match its metadata
+        let metadata = file.metadata()?;
+        let unchanged = metadata.len() == size && metadata.modified().ok() == Some(modified);
+        Ok::<_, std::io::Error>((format!("{:032x}", hasher.digest128()), unchanged))
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+
+    if unchanged {
+        CONTENT_HASHES.lock().put(
+            path.to_path_buf(),
+            ContentHash {
+                size,
+                modified,
+                hash: hash.clone(),
+            },
+        );
+    }
+    Ok(hash)
+}
+
+/// The tag in an `If-None-Match` list that names `etag` or a suffixed
+/// encoding/variant of it, compared weakly (`*` names `etag` itself)
+fn none_match_includes(if_none_match: &str, etag: &str) -> Option<String> {
+    if_none_match.split(',').find_map(|candidate| {
+        let candidate = candidate.trim();
+        if candidate == "*" {
+            return Some(etag.to_string());
+        }
+        names_entity(candidate, etag).then(|| candidate.to_string())
+    })
+}
+
+/// True when an `If-Match` list names exactly `etag`, compared strongly:
+/// weak tags on either side never match
+fn match_includes(if_match: &str, etag: &str) -> bool {
+    if_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        candidate == "*" || !etag.starts_with("W/") && candidate == etag
+    })
+}
+
+/// True when `candidate` is `etag`, or its tag for an encoding or variant,
+/// ignoring any `W/` prefix
+fn names_entity(candidate: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        tag.trim()
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .to_string()
+    };
+    opaque(candidate)
+        .strip_prefix(opaque(etag).as_str())
+        .is_some_and(|suffix| suffix.is_empty() || suffix.starts_with('-'))
+}
+
+/// Encodings served from precompressed siblings, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Precompressed {
+    Brotli,
+    Gzip,
+}
+
+impl Precompressed {
+    /// `Content-Encoding` token
+    fn token(self) -> &'static str {
+        match self {
+            Precompressed::Brotli => "br",
+            Precompressed::Gzip => "gzip",
+        }
+    }
+
+    /// Suffix of the sibling file
+    fn extension(self) -> &'static str {
+        match self {
+            Precompressed::Brotli => ".br",
+            Precompressed::Gzip => ".gz",
+        }
+    }
+
+    fn etag_suffix(self) -> &'static str {
+        match self {
+            Precompressed::Brotli => "br",
+            Precompressed::Gzip => "gz",
+        }
+    }
+}
+
+/// True if an `Accept-Encoding` value allows `coding` (q=0 refuses it)
+pub fn accepts_encoding(accept: &str, coding: &str) -> bool {
+    encoding_quality(accept, coding) > 0.0
+}
+
+/// The q-value an `Accept-Encoding` value gives `coding`: its own entry's,
+/// else the `*` entry's, else 0
+pub fn encoding_quality(accept: &str, coding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+    wildcard
+}
+
+/// The candidate coding the client rates highest, ties going to the earlier
+/// candidate; `None` if it accepts none of them
+pub fn preferred_encoding<T: Copy>(
+    accept: &str,
+    candidates: &[T],
+    token: impl Fn(T) -> &'static str,
+) -> Option<T> {
+    let mut best: Option<(T, f32)> = None;
+    for &candidate in candidates {
+        let quality = encoding_quality(accept, token(candidate));
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((candidate, quality));
+        }
+    }
+    best.map(|(candidate, _)| candidate)
+}
+
+/// The file sent for a request, from [`StaticFileHandler::representation`]
+struct Representation {
+    path: PathBuf,
+    encoding: Option<Precompressed>,
+    modified: Option<SystemTime>,
+    etag: String,
+}
+
+/// Validators of the representation being served, for `If-Range`
+struct Validators<'a> {
+    etag: &'a str,
+    last_modified: Option<&'a str>,
+}
+
+/// What a `Range` header asks of a representation
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No usable range: send the whole representation
+    Full,
+    /// Inclusive first and last byte
+    Partial(u64, u64),
+    /// Several disjoint ranges, in file order, as inclusive first and last bytes
+    Multiple(Vec<(u64, u64)>),
+    /// No requested byte exists
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header against a representation of `size` bytes
+///
+/// Overlapping and adjacent ranges are merged. So that a `Range` header
+/// can't amplify a response, more than [`MAX_RANGES`] ranges, or ranges
+/// adding up to more than the whole file, get the full body instead; so does
+/// anything that doesn't parse, as RFC 9110 allows.
+fn parse_range(header: &str, size: u64) -> ByteRange {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return ByteRange::Full;
+    }
+
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in specs {
+        match parse_range_spec(spec, size) {
+            ByteRange::Partial(first, last) => ranges.push((first, last)),
+            ByteRange::Unsatisfiable => {}
+            _ => return ByteRange::Full,
+        }
+    }
+    match ranges.len() {
+        0 => return ByteRange::Unsatisfiable,
+        1 => return ByteRange::Partial(ranges[0].0, ranges[0].1),
+        _ => {}
+    }
+    let requested: u64 = ranges.iter().map(|(first, last)| last - first + 1).sum();
+    if requested > size {
+        return ByteRange::Full;
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1 + 1 => previous.1 = previous.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    match merged[..] {
+        [(first, last)] => ByteRange::Partial(first, last),
+        _ => ByteRange::Multiple(merged),
+    }
+}
+
+/// Parse one range of a `Range` header
+fn parse_range_spec(spec: &str, size: u64) -> ByteRange {
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    let parse = |value: &str| -> Option<Option<u64>> {
+        let value = value.trim();
+        if value.is_empty() {
+            Some(None)
+        } else if value.bytes().all(|b| b.is_ascii_digit()) {
+            value.parse().ok().map(Some)
+        } else {
+            None
+        }
+    };
+    let (Some(first), Some(last)) = (parse(first), parse(last)) else {
+        return ByteRange::Full;
+    };
+
+    match (first, last) {
+        // Suffix range: the last N bytes
+        (None, Some(0)) => ByteRange::Unsatisfiable,
+        (None, Some(_)) if size == 0 => ByteRange::Full,
+        (None, Some(suffix)) => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+        (None, None) => ByteRange::Full,
+        // Invalid, so the header is ignored, even on an empty file
+        (Some(first), Some(last)) if last < first => ByteRange::Full,
+        (Some(first), _) if first >= size => ByteRange::Unsatisfiable,
+        (Some(first), None) => ByteRange::Partial(first, size - 1),
+        (Some(first), Some(last)) => ByteRange::Partial(first, last.min(size - 1)),
+    }
+}
+
+/// True when `If-Range` is absent or still matches the representation
+///
+/// Only strong validators count (RFC 9110 13.1.5): an entity tag must match
+/// exactly and weak tags never do; a date must equal `Last-Modified`, which is
+/// itself only strong once the file has been unchanged for a full second.
+fn if_range_matches(headers: &HeaderMap, validators: &Validators) -> bool {
+    let Some(value) = headers.get(IF_RANGE).and_then(|h| h.to_str().ok()) else {
+        return true;
+    };
+    let value = value.trim();
+    if value.starts_with("W/") {
+        false
+    } else if value.starts_with('"') {
+        value == validators.etag
+    } else {
+        let Some(last_modified) = validators
+            .last_modified
+            .and_then(|lm| parse_http_date(lm).ok())
+        else {
+            return false;
+        };
+        let settled = SystemTime::now()
+            .duration_since(last_modified)
+            .is_ok_and(|age| age >= Duration::from_secs(1));
+        settled && parse_http_date(value).ok() == Some(last_modified)
+    }
+}
+
+/// Body reading a file in chunks as the connection asks for them
+///
+/// Nothing is read ahead of the client, so a connection that goes away stops
+/// the reads. A read error or an early end of file (the file shrank) ends the
+/// body short; hyper then closes the connection because the declared length
+/// was not met.
+struct FileBody {
+    file: File,
+    remaining: u64,
+    chunk_size: usize,
+    buf: BytesMut,
+}
+
+impl FileBody {
+    fn new(file: File, length: u64, chunk_size: usize) -> Self {
+        Self {
+            file,
+            remaining: length,
+            chunk_size,
+            buf: BytesMut::new(),
+        }
+    }
+}
+
+impl Body for FileBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        let this = &mut *self;
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        let want = this.remaining.min(this.chunk_size as u64) as usize;
+        this.buf.resize(want, 0);
+        let mut read_buf = ReadBuf::new(&mut this.buf[..]);
+        let read = ready!(Pin::new(&mut this.file).poll_read(cx, &mut read_buf))
+            .map(|()| read_buf.filled().len());
+
+        match read {
+            Ok(0) => {
+                warn!(
+                    "Static file ended {} bytes short of its length",
+                    this.remaining
+                );
+                this.remaining = 0;
+                Poll::Ready(None)
+            }
+            Ok(n) => {
+                this.remaining -= n as u64;
+                let chunk = this.buf.split_to(n).freeze();
+                Poll::Ready(Some(Ok(Frame::data(chunk))))
+            }
+            Err(e) => {
+                warn!("Reading static file failed mid-response: {}", e);
+                this.remaining = 0;
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// One range of a multipart body: its part header, then `length` bytes
+/// of the file from `first`
+struct RangePart {
+    head: Bytes,
+    first: u64,
+    length: u64,
+}
+
+/// What a [`MultipartBody`] is doing between frames
+enum PartState {
+    /// Between parts
+    Idle,
+    /// Seeking to the start of a range of this many bytes
+    Seeking(u64),
+    /// Reading a range; this many bytes are left
+    Reading(u64),
+}
+
+/// Body of a `multipart/byteranges` response: each part's header followed
+/// by its range of the file, then the closing boundary
+///
+/// As with [`FileBody`], a read error or an early end of file ends the body
+/// short.
+struct MultipartBody {
+    file: File,
+    parts: VecDeque<RangePart>,
+    trailer: Option<Bytes>,
+    state: PartState,
+    remaining: u64,
+    chunk_size: usize,
+    buf: BytesMut,
+}
+
+impl MultipartBody {
+    fn new(file: File, parts: VecDeque<RangePart>, trailer: Bytes, chunk_size: usize) -> Self {
+        let remaining = parts
+            .iter()
+            .map(|part| part.head.len() as u64 + part.length)
+            .sum::<u64>()
+            + trailer.len() as u64;
+        Self {
+            file,
+            parts,
+            trailer: Some(trailer),
+            state: PartState::Idle,
+            remaining,
+            chunk_size,
+            buf: BytesMut::new(),
+        }
+    }
+
+    fn cut_short(&mut self) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        self.parts.clear();
+        self.trailer = None;
+        self.remaining = 0;
+        Poll::Ready(None)
+    }
+}
+
+impl Body for MultipartBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        let this = &mut *self;
+        loop {
+            match this.state {
+                PartState::Idle => {
+                    let Some(part) = this.parts.pop_front() else {
+                        this.remaining = 0;
+                        return Poll::Ready(this.trailer.take().map(|t| Ok(Frame::data(t))));
+                    };
+                    if let Err(e) = Pin::new(&mut this.file).start_seek(SeekFrom::Start(part.first))
+                    {
+                        warn!("Seeking in static file failed mid-response: {}", e);
+                        return this.cut_short();
+                    }
+                    this.state = PartState::Seeking(part.length);
+                    this.remaining -= part.head.len() as u64;
+                    return Poll::Ready(Some(Ok(Frame::data(part.head))));
+                }
+                PartState::Seeking(length) => {
+                    if let Err(e) = ready!(Pin::new(&mut this.file).poll_complete(cx)) {
+                        warn!("Seeking in static file failed mid-response: {}", e);
+                        return this.cut_short();
+                    }
+                    this.state = PartState::Reading(length);
+                }
+                PartState::Reading(0) => this.state = PartState::Idle,
+                PartState::Reading(left) => {
+                    let want = left.min(this.chunk_size as u64) as usize;
+                    this.buf.resize(want, 0);
+                    let mut read_buf = ReadBuf::new(&mut this.buf[..]);
+                    let read = ready!(Pin::new(&mut this.file).poll_read(cx, &mut read_buf))
+                        .map(|()| read_buf.filled().len());
+                    return match read {
+                        Ok(0) => {
+                            warn!("Static file ended {} bytes short of a range", left);
+                            this.cut_short()
+                        }
+                        Ok(n) => {
+                            this.state = PartState::Reading(left - n as u64);
+                            this.remaining -= n as u64;
+                            Poll::Ready(Some(Ok(Frame::data(this.buf.split_to(n).freeze()))))
+                        }
+                        Err(e) => {
+                            warn!("Reading static file failed mid-response: {}", e);
+                            this.cut_short()
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// A random `multipart/byteranges` boundary
+fn multipart_boundary() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Characters allowed unescaped in an RFC 8187 `filename*` value (attr-char)
+const ATTR_CHAR_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// `attachment; filename="…"` for `name` (RFC 6266)
+///
+/// The quoted name is an ASCII fallback with quotes and backslashes escaped
+/// and other characters replaced by `_`; names that aren't plain ASCII also
+/// get the exact name as `filename*=UTF-8''…`.
+fn attachment_disposition(name: &str) -> HeaderValue {
+    let mut value = String::from("attachment; filename=\"");
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => {
+                value.push('\\');
+                value.push(c);
+            }
+            ' '..='~' => value.push(c),
+            _ => value.push('_'),
+        }
+    }
+    value.push('"');
+    if !name.chars().all(|c| matches!(c, ' '..='~')) {
+        value.push_str("; filename*=UTF-8''");
+        value.extend(utf8_percent_encode(name, ATTR_CHAR_ESCAPES));
+    }
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
 /// Format a SystemTime as an HTTP date (RFC 7231)
 fn format_http_date(time: SystemTime) -> String {
     use chrono::{DateTime, Utc};
@@ -310,25 +1546,35 @@ fn format_http_date(time: SystemTime) -> String {
 }
 
 /// Parse an HTTP date string
-fn parse_http_date(s: &str) -> Result<SystemTime> {
-    use chrono::{DateTime, Utc};
+pub(crate) fn parse_http_date(s: &str) -> Result<SystemTime> {
+    use chrono::{DateTime, NaiveDateTime, Utc};
 
-    // Try RFC 7231 format first
-    if let Ok(dt) = DateTime::parse_from_str(s, "%a, %d %b %Y %H:%M:%S GMT") {
-        return Ok(dt.with_timezone(&Utc).into());
-    }
+    let s = s.trim();
 
-    // Try other common formats
+    // IMF-fixdate, or any RFC 2822 date with a zone or numeric offset
     if let Ok(dt) = DateTime::parse_from_rfc2822(s) {
         return Ok(dt.with_timezone(&Utc).into());
     }
 
+    // Obsolete forms HTTP/1.1 recipients must still accept (RFC 850 and
+    // asctime), and an IMF-fixdate missing its zone, read as GMT
+    for format in [
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+        "%a, %d %b %Y %H:%M:%S",
+    ] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+            return Ok(dt.and_utc().into());
+        }
+    }
+
     Err(anyhow!("Invalid date format"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use http_body_util::BodyExt;
 
     #[test]
     fn test_mime_types() {
@@ -347,6 +1593,13 @@ mod tests {
             "application/javascript; charset=utf-8"
         );
         assert_eq!(handler.guess_mime_type(Path::new("image.png")), "image/png");
+        for (file, media) in [
+            ("index.php", "text/x-php"),
+            ("config.yml", "text/yaml"),
+            ("app.js.map", "application/json"),
+        ] {
+            assert_eq!(handler.guess_mime_type(Path::new(file)), media);
+        }
         assert_eq!(
             handler.guess_mime_type(Path::new("font.woff2")),
             "font/woff2"
@@ -357,20 +1610,893 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mime_type_overrides() {
+        let overrides = HashMap::from([
+            (
+                "m3u8".to_string(),
+                "application/vnd.apple.mpegurl".to_string(),
+            ),
+            (".JS".to_string(), "text/javascript".to_string()),
+            (
+                "txt".to_string(),
+                "text/plain; charset=us-ascii".to_string(),
+            ),
+        ]);
+        let handler = StaticFileHandler::new()
+            .with_mime_types(Arc::new(MimeTypes::new(&overrides, "iso-8859-1")));
+
+        assert_eq!(
+            handler.guess_mime_type(Path::new("live/index.m3u8")),
+            "application/vnd.apple.mpegurl"
+        );
+        assert_eq!(
+            handler.guess_mime_type(Path::new("app.js")),
+            "text/javascript; charset=iso-8859-1"
+        );
+        assert_eq!(
+            handler.guess_mime_type(Path::new("notes.txt")),
+            "text/plain; charset=us-ascii"
+        );
+        // Built-in entries pick up the configured charset
+        assert_eq!(
+            handler.guess_mime_type(Path::new("index.html")),
+            "text/html; charset=iso-8859-1"
+        );
+        assert_eq!(handler.guess_mime_type(Path::new("image.png")), "image/png");
+        assert_eq!(
+            handler.cache_control(
+                Path::new("app.js"),
+                handler.guess_mime_type(Path::new("app.js"))
+            ),
+            "public, max-age=31536000, immutable"
+        );
+
+        // An empty charset leaves text types bare
+        let bare = MimeTypes::new(&HashMap::new(), "");
+        assert_eq!(bare.lookup(Path::new("style.css")), "text/css");
+    }
+
     #[test]
     fn test_cache_control() {
         let handler = StaticFileHandler::new();
 
+        let path = Path::new("file");
+
         // Static assets should have long cache
-        assert!(handler.cache_control("image/png").contains("31536000"));
-        assert!(handler.cache_control("font/woff2").contains("31536000"));
+        assert!(handler
+            .cache_control(path, "image/png")
+            .contains("31536000"));
+        assert!(handler
+            .cache_control(path, "font/woff2")
+            .contains("31536000"));
 
         // HTML should require revalidation but avoid no-store.
-        let html_policy = handler.cache_control("text/html; charset=utf-8");
+        let html_policy = handler.cache_control(path, "text/html; charset=utf-8");
         assert!(html_policy.contains("must-revalidate"));
         assert!(!html_policy.contains("no-store"));
     }
 
+    #[test]
+    fn test_cache_control_overrides() {
+        let overrides: HashMap<String, String> = [
+            ("image/*", "public, max-age=604800"),
+            ("image/svg+xml", "public, max-age=60"),
+            ("TEXT/HTML", "no-cache"),
+            ("*.json", "private, max-age=30"),
+            ("manifest.json", "no-store"),
+            (".webmanifest", "public, max-age=300"),
+            ("application/json", "public, max-age=5"),
+        ]
+        .into_iter()
+        .map(|(pattern, value)| (pattern.to_string(), value.to_string()))
+        .collect();
+        let rules = Arc::new(CacheControlRules::new(&overrides).unwrap());
+        let handler = StaticFileHandler::new().with_cache_control(rules.clone());
+        let policy = |name: &str| {
+            let path = Path::new(name);
+            handler
+                .cache_control(path, handler.guess_mime_type(path))
+                .to_string()
+        };
+
+        // MIME globs, with the exact type beating the wildcard
+        assert_eq!(
+            rules.lookup(Path::new("a.png"), "image/png"),
+            Some("public, max-age=604800")
+        );
+        assert_eq!(
+            rules.lookup(Path::new("a.svg"), "image/svg+xml"),
+            Some("public, max-age=60")
+        );
+        assert_eq!(
+            rules.lookup(Path::new("a.html"), "text/html; charset=utf-8"),
+            Some("no-cache")
+        );
+
+        // File-name patterns beat MIME patterns, the most specific first
+        assert_eq!(policy("data.json"), "private, max-age=30");
+        assert_eq!(policy("Manifest.JSON"), "no-store");
+        assert_eq!(policy("site.webmanifest"), "public, max-age=300");
+        assert_eq!(policy("logo.png"), "public, max-age=604800");
+
+        // Unmatched files keep the built-in policy
+        let overrides = HashMap::from([("image/*".to_string(), "no-cache".to_string())]);
+        let handler = StaticFileHandler::new()
+            .with_cache_control(Arc::new(CacheControlRules::new(&overrides).unwrap()));
+        let path = Path::new("app.css");
+        assert_eq!(
+            handler.cache_control(path, "text/css"),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(
+            handler.cache_control(Path::new("a.gif"), "image/gif"),
+            "no-cache"
+        );
+
+        for (pattern, value) in [("", "no-cache"), ("*.json", " "), ("*.json", "bad\nvalue")] {
+            let overrides = HashMap::from([(pattern.to_string(), value.to_string())]);
+            assert!(CacheControlRules::new(&overrides).is_err(), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_attachment_disposition() {
+        assert_eq!(
+            attachment_disposition("report.csv"),
+            "attachment; filename=\"report.csv\""
+        );
+        assert_eq!(
+            attachment_disposition("say \"hi\"\\.zip"),
+            "attachment; filename=\"say \\\"hi\\\"\\\\.zip\""
+        );
+        assert_eq!(
+            attachment_disposition("résumé 2024.pdf"),
+            "attachment; filename=\"r_sum_ 2024.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%202024.pdf"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_force_download() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "dump.SQL",
+            "backup.tar.gz",
+            "notes.gz",
+            "data.csv",
+            "zip",
+            "über.zip",
+        ] {
+            std::fs::write(dir.path().join(name), "x").unwrap();
+        }
+        let handler = StaticFileHandler::new().with_force_download(Arc::new(vec![
+            "sql".to_string(),
+            "tar.gz".to_string(),
+            "zip".to_string(),
+        ]));
+        let disposition = |name: &'static str| {
+            let handler = handler.clone();
+            let path = dir.path().join(name);
+            async move {
+                let response = handler
+                    .serve(&path, &HeaderMap::new(), false)
+                    .await
+                    .unwrap();
+                response
+                    .headers()
+                    .get(CONTENT_DISPOSITION)
+                    .map(|value| value.to_str().unwrap().to_string())
+            }
+        };
+
+        assert_eq!(
+            disposition("dump.SQL").await.as_deref(),
+            Some("attachment; filename=\"dump.SQL\"")
+        );
+        assert_eq!(
+            disposition("backup.tar.gz").await.as_deref(),
+            Some("attachment; filename=\"backup.tar.gz\"")
+        );
+        assert_eq!(
+            disposition("über.zip").await.as_deref(),
+            Some("attachment; filename=\"_ber.zip\"; filename*=UTF-8''%C3%BCber.zip")
+        );
+        // Only whole extensions count, and a bare name is no extension
+        assert_eq!(disposition("notes.gz").await, None);
+        assert_eq!(disposition("data.csv").await, None);
+        assert_eq!(disposition("zip").await, None);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        use ByteRange::*;
+
+        assert_eq!(parse_range("bytes=0-499", 1000), Partial(0, 499));
+        assert_eq!(parse_range("bytes=500-", 1000), Partial(500, 999));
+        assert_eq!(parse_range("bytes=-500", 1000), Partial(500, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), Partial(0, 999));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Partial(900, 999));
+        assert_eq!(parse_range("bytes=999-999", 1000), Partial(999, 999));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Unsatisfiable);
+        assert_eq!(parse_range("bytes=-500", 0), Full);
+
+        // Ignored rather than rejected
+        assert_eq!(parse_range("bytes=500-100", 1000), Full);
+        assert_eq!(parse_range("bytes=2000-100", 1000), Full);
+        assert_eq!(parse_range("bytes=5-2", 0), Full);
+        assert_eq!(parse_range("bytes=0-0", 0), Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-0,-1", 0), Full);
+        assert_eq!(
+            parse_range("bytes=0-1,5-9", 1000),
+            Multiple(vec![(0, 1), (5, 9)])
+        );
+        assert_eq!(
+            parse_range("bytes=500-599, -100, 0-99", 1000),
+            Multiple(vec![(0, 99), (500, 599), (900, 999)])
+        );
+        // Overlapping and adjacent ranges merge, down to a single range
+        assert_eq!(
+            parse_range("bytes=0-99,50-149,150-199,300-", 1000),
+            Multiple(vec![(0, 199), (300, 999)])
+        );
+        assert_eq!(parse_range("bytes=0-99,100-199", 1000), Partial(0, 199));
+        // Unsatisfiable parts are dropped
+        assert_eq!(parse_range("bytes=0-9,2000-", 1000), Partial(0, 9));
+        assert_eq!(parse_range("bytes=1000-,2000-", 1000), Unsatisfiable);
+        // More bytes than the file, or too many ranges, get the full body
+        assert_eq!(parse_range("bytes=0-,0-", 1000), Full);
+        assert_eq!(parse_range("bytes=0-599,400-999", 1000), Full);
+        let many = (0..17)
+            .map(|i| format!("{0}-{0}", i * 10))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parse_range(&format!("bytes={}", many.join(",")), 1000),
+            Full
+        );
+        assert_eq!(parse_range("bytes=0-1,x-2", 1000), Full);
+        assert_eq!(parse_range("items=0-1", 1000), Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), Full);
+        assert_eq!(parse_range("bytes=+1-2", 1000), Full);
+        assert_eq!(parse_range("bytes=-", 1000), Full);
+    }
+
+    #[test]
+    fn test_accepts_encoding() {
+        assert!(accepts_encoding("gzip, deflate, br", "br"));
+        assert!(accepts_encoding("GZIP", "gzip"));
+        assert!(accepts_encoding("br;q=0.5, gzip", "br"));
+        assert!(!accepts_encoding("br;q=0, gzip", "br"));
+        assert!(!accepts_encoding("gzip", "br"));
+        assert!(!accepts_encoding("identity", "gzip"));
+        assert!(accepts_encoding("*", "br"));
+        assert!(!accepts_encoding("*, br;q=0", "br"));
+        assert!(!accepts_encoding("*;q=0", "gzip"));
+
+        assert_eq!(encoding_quality("gzip;q=0.8, *;q=0.1", "gzip"), 0.8);
+        assert_eq!(encoding_quality("gzip;q=0.8, *;q=0.1", "br"), 0.1);
+        assert_eq!(encoding_quality("gzip", "br"), 0.0);
+
+        let token = |coding: &'static str| coding;
+        let candidates = ["br", "gzip"];
+        assert_eq!(
+            preferred_encoding("gzip, br", &candidates, token),
+            Some("br")
+        );
+        assert_eq!(
+            preferred_encoding("br;q=0.5, gzip", &candidates, token),
+            Some("gzip")
+        );
+        assert_eq!(
+            preferred_encoding("gzip;q=0.2, *", &candidates, token),
+            Some("br")
+        );
+        assert_eq!(
+            preferred_encoding("br;q=0, gzip;q=0", &candidates, token),
+            None
+        );
+        assert_eq!(preferred_encoding("identity", &candidates, token), None);
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_siblings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.js");
+        std::fs::write(&path, "plain").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), "gzipped").unwrap();
+        std::fs::write(dir.path().join("app.js.br"), "brotli").unwrap();
+        let handler = StaticFileHandler::new();
+
+        let serve = |accept: &'static str, precompressed: bool| {
+            let handler = &handler;
+            let path = path.clone();
+            async move {
+                let mut headers = HeaderMap::new();
+                headers.insert(ACCEPT_ENCODING, accept.parse().unwrap());
+                let response = handler.serve(&path, &headers, precompressed).await.unwrap();
+                let (parts, body) = response.into_parts();
+                let body = body.collect().await.unwrap().to_bytes();
+                (parts.headers, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let (br, body) = serve("gzip, br", true).await;
+        assert_eq!(body, "brotli");
+        assert_eq!(br[CONTENT_ENCODING], "br");
+        assert_eq!(br["content-type"], "application/javascript; charset=utf-8");
+        assert_eq!(br["vary"], "Accept-Encoding");
+
+        let (gz, body) = serve("gzip", true).await;
+        assert_eq!(body, "gzipped");
+        assert_eq!(gz[CONTENT_ENCODING], "gzip");
+
+        let (plain, body) = serve("identity", true).await;
+        assert_eq!(body, "plain");
+        assert!(!plain.contains_key(CONTENT_ENCODING));
+
+        // Each encoding is its own representation
+        assert_ne!(br["etag"], gz["etag"]);
+        assert_ne!(gz["etag"], plain["etag"]);
+
+        // Rebuilding a sibling changes its tag even if the original didn't change
+        std::fs::write(dir.path().join("app.js.gz"), "gzipped again").unwrap();
+        let (rebuilt, body) = serve("gzip", true).await;
+        assert_eq!(body, "gzipped again");
+        assert_ne!(rebuilt["etag"], gz["etag"]);
+        assert_eq!(serve("identity", true).await.0["etag"], plain["etag"]);
+
+        // Off unless enabled
+        let (_, body) = serve("br", false).await;
+        assert_eq!(body, "plain");
+
+        // Siblings older than the original are ignored
+        let gz = std::fs::File::options()
+            .write(true)
+            .open(dir.path().join("app.js.gz"))
+            .unwrap();
+        gz.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        std::fs::remove_file(dir.path().join("app.js.br")).unwrap();
+        let (headers, body) = serve("br, gzip", true).await;
+        assert_eq!(body, "plain");
+        assert!(!headers.contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn test_range_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let handler = StaticFileHandler::new();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=2-5".parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "4");
+        let etag = response.headers()["etag"].clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"2345");
+
+        headers.insert(RANGE, "bytes=10-".parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes */10");
+
+        // If-Range with a current ETag keeps the range, a stale one drops it
+        headers.insert(RANGE, "bytes=-3".parse().unwrap());
+        headers.insert(IF_RANGE, etag.clone());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 7-9/10");
+        headers.insert(IF_RANGE, "\"outdated\"".parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "10");
+
+        // Weak tags never match, even the current one
+        let weak = format!("W/{}", etag.to_str().unwrap());
+        headers.insert(IF_RANGE, weak.parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"0123456789abcdefghij").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=15-17,2-4".parse().unwrap());
+        for stream_threshold in [u64::MAX, 0] {
+            let handler = StaticFileHandler::from_config(&StaticConfig {
+                stream_threshold,
+                stream_chunk_size: 2,
+                ..StaticConfig::default()
+            });
+            let response = handler.serve(&path, &headers, false).await.unwrap();
+            let response = crate::server::streaming::into_response_body(response);
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert!(!response.headers().contains_key(CONTENT_RANGE));
+            let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap();
+            let boundary = content_type
+                .strip_prefix("multipart/byteranges; boundary=")
+                .unwrap()
+                .to_string();
+            let length: usize = response.headers()[CONTENT_LENGTH]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let expected = format!(
+                "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 2-4/20\r\n\r\n234\
+                 \r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 15-17/20\r\n\r\nfgh\
+                 \r\n--{b}--\r\n",
+                b = boundary
+            );
+            assert_eq!(String::from_utf8_lossy(&body), expected);
+            assert_eq!(body.len(), length);
+        }
+
+        // HEAD declares the same length without a body
+        let handler = StaticFileHandler::new().with_head(true);
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(response.headers().contains_key(CONTENT_LENGTH));
+        assert!(response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_if_range_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let handler = StaticFileHandler::new();
+
+        let serve = |if_range: &'static str| {
+            let handler = &handler;
+            let path = &path;
+            async move {
+                let mut headers = HeaderMap::new();
+                headers.insert(RANGE, "bytes=0-3".parse().unwrap());
+                headers.insert(IF_RANGE, if_range.parse().unwrap());
+                handler.serve(path, &headers, false).await.unwrap().status()
+            }
+        };
+
+        // The current Last-Modified, in each HTTP-date form
+        for current in [
+            "Tue, 14 Nov 2023 22:13:20 GMT",
+            "Tuesday, 14-Nov-23 22:13:20 GMT",
+            "Tue Nov 14 22:13:20 2023",
+        ] {
+            assert_eq!(
+                serve(current).await,
+                StatusCode::PARTIAL_CONTENT,
+                "{}",
+                current
+            );
+        }
+        // Any other date means the file changed: send all of it
+        for stale in [
+            "Tue, 14 Nov 2023 22:13:19 GMT",
+            "Wed, 15 Nov 2023 08:00:00 GMT",
+            "soon",
+        ] {
+            assert_eq!(serve(stale).await, StatusCode::OK, "{}", stale);
+        }
+
+        // A file modified this very second has no strong date yet
+        let now = SystemTime::now();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(now)
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=0-3".parse().unwrap());
+        headers.insert(IF_RANGE, format_http_date(now).parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_large_bodies_are_streamed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let handler = StaticFileHandler::from_config(&StaticConfig {
+            stream_threshold: 4,
+            stream_chunk_size: 3,
+            ..StaticConfig::default()
+        });
+
+        let collect = |response: Response<Full<Bytes>>| async move {
+            assert!(response.extensions().get::<StreamingBody>().is_some());
+            let response = crate::server::streaming::into_response_body(response);
+            let length = response.headers()[CONTENT_LENGTH].clone();
+            assert_eq!(
+                response.body().size_hint().exact(),
+                Some(length.to_str().unwrap().parse().unwrap())
+            );
+            let mut body = response.into_body();
+            let mut chunks = Vec::new();
+            while let Some(frame) = body.frame().await {
+                chunks.push(frame.unwrap().into_data().unwrap());
+            }
+            chunks
+        };
+
+        let response = handler
+            .serve(&path, &HeaderMap::new(), false)
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_LENGTH], "10");
+        assert_eq!(collect(response).await, ["012", "345", "678", "9"]);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=3-8".parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(collect(response).await, ["345", "678"]);
+
+        // Up to the threshold the body is read at once
+        headers.insert(RANGE, "bytes=3-6".parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert!(response.extensions().get::<StreamingBody>().is_none());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"3456");
+    }
+
+    #[tokio::test]
+    async fn test_head_requests_skip_the_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mp4");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let handler = StaticFileHandler::from_config(&StaticConfig {
+            stream_threshold: 4,
+            ..StaticConfig::default()
+        })
+        .with_head(true);
+
+        let response = handler
+            .serve(&path, &HeaderMap::new(), false)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "10");
+        assert!(response.headers().contains_key("etag"));
+        assert!(response.extensions().get::<StreamingBody>().is_none());
+        assert!(response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=3-8".parse().unwrap());
+        let response = handler.serve(&path, &headers, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "6");
+        assert!(response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_strong_etags_follow_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = StaticFileHandler::new().with_etag_mode(EtagMode::Strong);
+        let etag = |path: PathBuf| {
+            let handler = &handler;
+            async move {
+                let response = handler
+                    .serve(&path, &HeaderMap::new(), false)
+                    .await
+                    .unwrap();
+                response.headers()["etag"].to_str().unwrap().to_string()
+            }
+        };
+
+        // The same bytes deployed at another time (and path) keep their tag
+        let first = dir.path().join("app.js");
+        std::fs::write(&first, "console.log(1);").unwrap();
+        let second = dir.path().join("copy.js");
+        std::fs::write(&second, "console.log(1);").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&second)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .unwrap();
+        let tag = etag(first.clone()).await;
+        assert!(tag.starts_with('"') && !tag.starts_with("W/"), "{}", tag);
+        assert_eq!(etag(second).await, tag);
+        assert!(CONTENT_HASHES.lock().contains(&first));
+
+        // New content, new tag, even at the same size
+        std::fs::write(&first, "console.log(2);").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&first)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_ne!(etag(first).await, tag);
+    }
+
+    fn conditional(if_none_match: Option<&str>, if_modified_since: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = if_none_match {
+            headers.insert(IF_NONE_MATCH, value.parse().unwrap());
+        }
+        if let Some(value) = if_modified_since {
+            headers.insert(IF_MODIFIED_SINCE, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_conditional_requests_in_each_etag_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("style.css");
+        std::fs::write(&path, "body {}").unwrap();
+        let far_future = "Fri, 01 Jan 2100 00:00:00 GMT";
+
+        for mode in [EtagMode::Strong, EtagMode::Weak, EtagMode::Mtime] {
+            let handler = StaticFileHandler::new().with_etag_mode(mode);
+            let response = handler
+                .serve(&path, &HeaderMap::new(), false)
+                .await
+                .unwrap();
+            let etag = response.headers()["etag"].to_str().unwrap().to_string();
+            assert_eq!(etag.starts_with("W/"), mode == EtagMode::Weak, "{:?}", mode);
+
+            // Weak comparison: the tag matches with or without W/, in a
+            // list, or as the tag of a compressed copy; the 304 names the
+            // representation the client holds
+            let opaque = etag.trim_start_matches("W/");
+            let gzipped = format!("W/{}-gz\"", opaque.trim_end_matches('"'));
+            for (if_none_match, echoed) in [
+                (etag.clone(), etag.clone()),
+                (opaque.to_string(), opaque.to_string()),
+                (format!("W/{}", opaque), format!("W/{}", opaque)),
+                (format!("\"other\", {}", etag), etag.clone()),
+                (gzipped.clone(), gzipped.clone()),
+                ("*".to_string(), etag.clone()),
+            ] {
+                let response = handler
+                    .serve_conditional(&path, &conditional(Some(&if_none_match), None), false)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    response.status(),
+                    StatusCode::NOT_MODIFIED,
+                    "{:?} {}",
+                    mode,
+                    if_none_match
+                );
+                assert_eq!(response.headers()["etag"], echoed.as_str());
+                assert!(response.headers().contains_key("last-modified"));
+            }
+
+            // Only whole tags match, not other hashes sharing a prefix
+            let longer = format!("{}0\"", opaque.trim_end_matches('"'));
+            let response = handler
+                .serve_conditional(&path, &conditional(Some(&longer), None), false)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", mode);
+
+            // A conditional range revalidates before the range applies
+            let mut headers = conditional(Some(&etag), None);
+            headers.insert(RANGE, "bytes=0-1".parse().unwrap());
+            let response = handler
+                .serve_conditional(&path, &headers, false)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+            // A stale tag wins over a matching If-Modified-Since
+            let response = handler
+                .serve_conditional(
+                    &path,
+                    &conditional(Some("\"stale\""), Some(far_future)),
+                    false,
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", mode);
+            let response = handler
+                .serve_conditional(&path, &conditional(None, Some(far_future)), false)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            " Sun, 06 Nov 1994 08:49:37 GMT ",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 09:49:37 +0100",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(date).ok(), Some(expected), "{}", date);
+        }
+        assert!(parse_http_date("yesterday").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_preconditions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+        std::fs::write(&path, "{}").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let past = "Sat, 01 Jan 2000 00:00:00 GMT";
+        let future = "Fri, 01 Jan 2100 00:00:00 GMT";
+
+        let handler = StaticFileHandler::new();
+        let response = handler
+            .serve(&path, &HeaderMap::new(), false)
+            .await
+            .unwrap();
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let status = |headers: Vec<(hyper::header::HeaderName, String)>| {
+            let handler = handler.clone();
+            let path = path.clone();
+            async move {
+                let mut map = HeaderMap::new();
+                for (name, value) in headers {
+                    map.insert(name, value.parse().unwrap());
+                }
+                let response = handler.serve_conditional(&path, &map, false).await.unwrap();
+                if response.status() == StatusCode::PRECONDITION_FAILED {
+                    assert!(response.headers().contains_key("etag"));
+                    assert!(response.body().size_hint().exact() == Some(0));
+                }
+                response.status()
+            }
+        };
+
+        // If-Match: the current tag, a list naming it, or * let the request through
+        for if_match in [
+            etag.clone(),
+            format!("\"other\", {}", etag),
+            "*".to_string(),
+        ] {
+            assert_eq!(
+                status(vec![(IF_MATCH, if_match.clone())]).await,
+                StatusCode::OK,
+                "{}",
+                if_match
+            );
+        }
+        // ...but another tag, the tag of another representation, or the
+        // current one marked weak fails it
+        for if_match in [
+            "\"other\"".to_string(),
+            format!("{}-webp\"", etag.trim_end_matches('"')),
+            format!("W/{}", etag),
+        ] {
+            assert_eq!(
+                status(vec![(IF_MATCH, if_match.clone())]).await,
+                StatusCode::PRECONDITION_FAILED,
+                "{}",
+                if_match
+            );
+        }
+
+        // If-Unmodified-Since, with or without the zone; bad dates are ignored
+        let exact = format_http_date(modified);
+        for (date, expected) in [
+            (past, StatusCode::PRECONDITION_FAILED),
+            ("Sat, 01 Jan 2000 00:00:00", StatusCode::PRECONDITION_FAILED),
+            (future, StatusCode::OK),
+            ("Fri, 01 Jan 2100 00:00:00", StatusCode::OK),
+            (exact.as_str(), StatusCode::OK),
+            (exact.trim_end_matches(" GMT"), StatusCode::OK),
+            ("not a date", StatusCode::OK),
+        ] {
+            assert_eq!(
+                status(vec![(IF_UNMODIFIED_SINCE, date.to_string())]).await,
+                expected,
+                "{}",
+                date
+            );
+        }
+
+        // An image variant is matched by its own tag only
+        let webp = format!("{}-webp\"", etag.trim_end_matches('"'));
+        for (if_match, passes) in [(&webp, true), (&etag, false)] {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_MATCH, if_match.parse().unwrap());
+            let response = handler
+                .preconditions(&path, &headers, false, Some("webp"))
+                .await
+                .unwrap();
+            assert_eq!(response.is_none(), passes, "{}", if_match);
+        }
+
+        // A matching If-Match overrides If-Unmodified-Since
+        assert_eq!(
+            status(vec![
+                (IF_MATCH, etag.clone()),
+                (IF_UNMODIFIED_SINCE, past.to_string())
+            ])
+            .await,
+            StatusCode::OK
+        );
+
+        // A failed If-Match wins over a matching If-None-Match
+        assert_eq!(
+            status(vec![
+                (IF_MATCH, "\"other\"".to_string()),
+                (IF_NONE_MATCH, etag.clone())
+            ])
+            .await,
+            StatusCode::PRECONDITION_FAILED
+        );
+        // Both passing revalidates as before
+        assert_eq!(
+            status(vec![
+                (IF_MATCH, etag.clone()),
+                (IF_NONE_MATCH, etag.clone())
+            ])
+            .await,
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status(vec![
+                (IF_UNMODIFIED_SINCE, future.to_string()),
+                (IF_MODIFIED_SINCE, future.to_string())
+            ])
+            .await,
+            StatusCode::NOT_MODIFIED
+        );
+
+        // Weak ETags never satisfy If-Match, not even their own
+        let weak = StaticFileHandler::new().with_etag_mode(EtagMode::Weak);
+        let response = weak.serve(&path, &HeaderMap::new(), false).await.unwrap();
+        let weak_tag = response.headers()["etag"].clone();
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MATCH, weak_tag);
+        let response = weak
+            .serve_conditional(&path, &headers, false)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
     #[test]
     fn test_etag_generation() {
         let handler = StaticFileHandler::new();
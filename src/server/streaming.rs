@@ -0,0 +1,100 @@
+//! Streamed response bodies
+//!
+//! Handlers build `Full<Bytes>` responses. A handler that wants to stream
+//! (PHP output sent with `X-Accel-Buffering: no`, large static files) leaves
+//! the body empty and attaches a [`StreamingBody`] extension holding the real
+//! body, such as the receiving end of a channel; [`into_response_body`] swaps
+//! it in just before the response goes to hyper, which writes each chunk as
+//! soon as it arrives. There is no response compression to hold chunks back.
+
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Frame};
+use hyper::Response;
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+
+/// Body type handed to hyper
+pub type ResponseBody = BoxBody<Bytes, Infallible>;
+
+/// Response extension carrying a body that is still being produced
+///
+/// Extensions must be `Clone + Sync`, so the body sits behind a shared slot.
+#[derive(Clone)]
+pub struct StreamingBody(Arc<Mutex<Option<ResponseBody>>>);
+
+impl StreamingBody {
+    /// Stream chunks from a channel until the sender is dropped
+    pub fn new(rx: mpsc::Receiver<Bytes>) -> Self {
+        Self::from_body(ChannelBody(rx).boxed())
+    }
+
+    /// Stream any body; its size hint decides whether a length is sent
+    pub fn from_body(body: ResponseBody) -> Self {
+        Self(Arc::new(Mutex::new(Some(body))))
+    }
+
+    fn take(&self) -> Option<ResponseBody> {
+        self.0.lock().take()
+    }
+}
+
+/// Body yielding chunks from a channel until the sender is dropped
+struct ChannelBody(mpsc::Receiver<Bytes>);
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        self.0
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|data| Ok(Frame::data(data))))
+    }
+}
+
+/// Convert a handler response, streaming it if it carries a [`StreamingBody`]
+pub fn into_response_body(response: Response<Full<Bytes>>) -> Response<ResponseBody> {
+    let (mut parts, body) = response.into_parts();
+    match parts
+        .extensions
+        .remove::<StreamingBody>()
+        .and_then(|s| s.take())
+    {
+        Some(body) => Response::from_parts(parts, body),
+        None => Response::from_parts(parts, body.boxed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_streaming_extension_replaces_body() {
+        let (tx, rx) = mpsc::channel(4);
+        let mut response = Response::new(Full::new(Bytes::new()));
+        response.extensions_mut().insert(StreamingBody::new(rx));
+
+        tx.send(Bytes::from("data: 1\n\n")).await.unwrap();
+        tx.send(Bytes::from("data: 2\n\n")).await.unwrap();
+        drop(tx);
+
+        let body = into_response_body(response)
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, Bytes::from("data: 1\n\ndata: 2\n\n"));
+    }
+}
@@ -0,0 +1,160 @@
+//! Symbolic link policy (`follow_symlinks`) and docroot containment
+//!
+//! The equivalent of Nginx's `disable_symlinks`: with `never`, a request whose
+//! path crosses a symlink anywhere below the document root is refused, and
+//! with `owner_match` each symlink must belong to the owner of its target.
+//! The document root itself is trusted, so a docroot that is a symlink (a
+//! common deploy layout) keeps working.
+//!
+//! Components are checked one at a time with `lstat`, so a symlink in the
+//! middle of the path is caught even when the final file is a regular one.
+//! Missing components end the walk; the caller answers 404 as usual.
+//!
+//! Whatever the policy, a path must resolve inside the document root:
+//! [`inside_root`] compares the canonical path with the canonical root, so a
+//! link that leads out of the tree is treated as missing.
+
+use std::io;
+use std::path::Path;
+
+use crate::config::SymlinkPolicy;
+
+/// True if `path` under `doc_root` may be served under `policy`
+pub fn allowed(doc_root: &Path, path: &Path, policy: SymlinkPolicy) -> bool {
+    if policy == SymlinkPolicy::Always {
+        return true;
+    }
+    let Ok(relative) = path.strip_prefix(doc_root) else {
+        // Outside the docroot nothing vouches for the path
+        return false;
+    };
+
+    let mut current = doc_root.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        let metadata = match std::fs::symlink_metadata(&current) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return true,
+            Err(_) => return false,
+        };
+        if metadata.file_type().is_symlink() && !link_allowed(&current, &metadata, policy) {
+            return false;
+        }
+    }
+    true
+}
+
+/// True if `path` resolves to a file or directory inside `doc_root`
+///
+/// Paths that can't be resolved (missing, `ENOTDIR`, I/O errors) count as
+/// inside; nothing can be served from them, and the caller reports why.
+pub fn inside_root(doc_root: &Path, path: &Path) -> bool {
+    let Ok(resolved) = path.canonicalize() else {
+        return true;
+    };
+    doc_root
+        .canonicalize()
+        .is_ok_and(|root| resolved.starts_with(root))
+}
+
+/// True if the symlink at `link` (with `lstat` metadata `metadata`) may be
+/// followed under `policy`
+pub fn link_allowed(link: &Path, metadata: &std::fs::Metadata, policy: SymlinkPolicy) -> bool {
+    match policy {
+        SymlinkPolicy::Always => true,
+        SymlinkPolicy::Never => false,
+        SymlinkPolicy::OwnerMatch => match std::fs::metadata(link) {
+            Ok(target) => same_owner(metadata, &target),
+            // A dangling link has no owner to match
+            Err(_) => false,
+        },
+    }
+}
+
+#[cfg(unix)]
+fn same_owner(link: &std::fs::Metadata, target: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    link.uid() == target.uid()
+}
+
+#[cfg(not(unix))]
+fn same_owner(_link: &std::fs::Metadata, _target: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("real")).unwrap();
+        std::fs::write(root.join("real/page.html"), "hi").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(root.join("real/page.html"), root.join("page.html")).unwrap();
+        std::os::unix::fs::symlink(root.join("gone"), root.join("dangling")).unwrap();
+
+        let direct = root.join("real/page.html");
+        let through_dir = root.join("linked/page.html");
+        let file_link = root.join("page.html");
+        let missing = root.join("real/missing.html");
+
+        for path in [&direct, &through_dir, &file_link, &missing] {
+            assert!(allowed(root, path, SymlinkPolicy::Always));
+        }
+
+        assert!(allowed(root, &direct, SymlinkPolicy::Never));
+        assert!(allowed(root, &missing, SymlinkPolicy::Never));
+        assert!(!allowed(root, &through_dir, SymlinkPolicy::Never));
+        assert!(!allowed(root, &file_link, SymlinkPolicy::Never));
+
+        // Links we created point at files we own
+        assert!(allowed(root, &through_dir, SymlinkPolicy::OwnerMatch));
+        assert!(allowed(root, &file_link, SymlinkPolicy::OwnerMatch));
+        assert!(!allowed(
+            root,
+            &root.join("dangling"),
+            SymlinkPolicy::OwnerMatch
+        ));
+
+        // The docroot itself may be a symlink
+        let alias = tempfile::tempdir().unwrap();
+        let linked_root = alias.path().join("current");
+        std::os::unix::fs::symlink(root, &linked_root).unwrap();
+        assert!(allowed(
+            &linked_root,
+            &linked_root.join("real/page.html"),
+            SymlinkPolicy::Never
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_inside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        std::fs::write(root.join("real/page.html"), "hi").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "outside").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("inner")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("escape")).unwrap();
+
+        assert!(inside_root(&root, &root.join("real/page.html")));
+        assert!(inside_root(&root, &root.join("inner/page.html")));
+        assert!(inside_root(&root, &root.join("real/missing.html")));
+        assert!(inside_root(&root, &root.join("real/page.html/path-info")));
+        assert!(!inside_root(&root, &root.join("escape/secret.txt")));
+        assert!(!inside_root(&root, &root.join("escape")));
+
+        // Through a symlinked docroot
+        let linked_root = dir.path().join("current");
+        std::os::unix::fs::symlink(&root, &linked_root).unwrap();
+        assert!(inside_root(
+            &linked_root,
+            &linked_root.join("real/page.html")
+        ));
+    }
+}
@@ -3,36 +3,116 @@
 //! Loads certificates from config (global [ssl] + per-vhost ssl_certificate/ssl_certificate_key)
 //! and builds a rustls ServerConfig with SNI-based certificate resolution.
 
+use std::collections::HashMap;
 use std::io::BufReader;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use parking_lot::{Mutex, RwLock};
 use rustls::server::{ClientHello, ResolvesServerCert};
 use rustls::sign::CertifiedKey;
 use rustls::ServerConfig;
 use tracing::{info, warn};
 
 use crate::config::Config;
+use crate::server::ocsp::{self, OcspRequest};
+
+/// Wait before asking a failed OCSP responder again
+const STAPLE_RETRY: Duration = Duration::from_secs(300);
+/// Bounds on the wait between OCSP refreshes, which is otherwise half the
+/// time left until the response's `nextUpdate`
+const STAPLE_MIN_REFRESH: Duration = Duration::from_secs(60);
+const STAPLE_MAX_REFRESH: Duration = Duration::from_secs(12 * 3600);
 
 /// SNI-aware certificate resolver that picks the right cert per domain.
 #[derive(Debug)]
 pub struct VeloServeCertResolver {
-    default: Option<Arc<CertifiedKey>>,
-    certs: std::collections::HashMap<String, Arc<CertifiedKey>>,
+    default: Option<CertSlot>,
+    certs: HashMap<String, CertSlot>,
+}
+
+/// A loaded certificate and the OCSP response stapled to it, if any
+#[derive(Debug)]
+struct CertSlot {
+    /// Domain, or "default" for `[ssl]`
+    name: String,
+    key: RwLock<Arc<CertifiedKey>>,
+    /// Set when stapling is enabled and the certificate names a responder
+    ocsp_request: Option<OcspRequest>,
+    /// When the stapled response stops being valid
+    staple_until: Mutex<Option<SystemTime>>,
+}
+
+impl CertSlot {
+    fn new(name: &str, key: CertifiedKey, stapling: bool) -> Self {
+        let ocsp_request = if stapling {
+            OcspRequest::new(&key.cert)
+                .map_err(|e| info!("Not stapling OCSP for {}: {:#}", name, e))
+                .ok()
+        } else {
+            None
+        };
+        Self {
+            name: name.to_string(),
+            key: RwLock::new(Arc::new(key)),
+            ocsp_request,
+            staple_until: Mutex::new(None),
+        }
+    }
+
+    /// Fetch a fresh OCSP response; the wait before the next refresh
+    async fn refresh_staple(&self, request: &OcspRequest) -> Duration {
+        match ocsp::fetch(request).await {
+            Ok(staple) => {
+                info!("Stapled OCSP response for {}", self.name);
+                let delay = staple
+                    .next_update
+                    .and_then(|next| next.duration_since(SystemTime::now()).ok())
+                    .map_or(STAPLE_MAX_REFRESH, |left| left / 2);
+                self.set_staple(Some(staple.response), staple.next_update);
+                delay.clamp(STAPLE_MIN_REFRESH, STAPLE_MAX_REFRESH)
+            }
+            Err(e) => {
+                // Keep serving the last response while it is still valid
+                warn!("OCSP stapling for {} failed: {:#}", self.name, e);
+                let expired = self
+                    .staple_until
+                    .lock()
+                    .is_some_and(|until| until <= SystemTime::now());
+                if expired {
+                    self.set_staple(None, None);
+                }
+                STAPLE_RETRY
+            }
+        }
+    }
+
+    fn set_staple(&self, response: Option<Vec<u8>>, until: Option<SystemTime>) {
+        let mut key = CertifiedKey::clone(&self.key.read());
+        key.ocsp = response;
+        *self.key.write() = Arc::new(key);
+        *self.staple_until.lock() = until;
+    }
+
+    fn current(&self) -> Arc<CertifiedKey> {
+        self.key.read().clone()
+    }
 }
 
 impl VeloServeCertResolver {
     pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
         let mut resolver = Self {
             default: None,
-            certs: std::collections::HashMap::new(),
+            certs: HashMap::new(),
         };
+        let stapling = config.ssl.as_ref().is_some_and(|ssl| ssl.ocsp_stapling);
 
         if let Some(ref ssl) = config.ssl {
             match load_certified_key(&ssl.cert, &ssl.key) {
                 Ok(ck) => {
                     info!("Loaded global SSL cert from {}", ssl.cert);
-                    resolver.default = Some(Arc::new(ck));
+                    resolver.default = Some(CertSlot::new("default", ck, stapling));
                 }
                 Err(e) => warn!("Failed to load global SSL cert: {}", e),
             }
@@ -45,7 +125,10 @@ impl VeloServeCertResolver {
                 match load_certified_key(cert_path, key_path) {
                     Ok(ck) => {
                         info!("Loaded SSL cert for {} from {}", vhost.domain, cert_path);
-                        resolver.certs.insert(vhost.domain.clone(), Arc::new(ck));
+                        resolver.certs.insert(
+                            vhost.domain.clone(),
+                            CertSlot::new(&vhost.domain, ck, stapling),
+                        );
                     }
                     Err(e) => warn!("Failed to load SSL cert for {}: {}", vhost.domain, e),
                 }
@@ -58,27 +141,67 @@ impl VeloServeCertResolver {
 
         Ok(resolver)
     }
+
+    /// Certificates with an OCSP responder to ask
+    fn stapled(&self) -> impl Iterator<Item = (&CertSlot, &OcspRequest)> {
+        self.default
+            .iter()
+            .chain(self.certs.values())
+            .filter_map(|slot| slot.ocsp_request.as_ref().map(|request| (slot, request)))
+    }
+
+    /// Fetch OCSP responses for every stapled certificate; the wait before
+    /// the next refresh
+    pub async fn refresh_staples(&self) -> Duration {
+        let mut next = STAPLE_MAX_REFRESH;
+        for (slot, request) in self.stapled() {
+            next = next.min(slot.refresh_staple(request).await);
+        }
+        next
+    }
+
+    /// Staple OCSP responses now and keep them fresh in the background, for
+    /// as long as the resolver is in use
+    pub fn spawn_stapling(self: &Arc<Self>) {
+        if self.stapled().next().is_none() {
+            return;
+        }
+        let resolver = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while let Some(current) = resolver.upgrade() {
+                let delay = current.refresh_staples().await;
+                drop(current);
+                tokio::time::sleep(delay).await;
+            }
+        });
+    }
 }
 
 impl ResolvesServerCert for VeloServeCertResolver {
     fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
         if let Some(sni) = client_hello.server_name() {
-            if let Some(ck) = self.certs.get(sni) {
-                return Some(ck.clone());
+            if let Some(slot) = self.certs.get(sni) {
+                return Some(slot.current());
             }
         }
-        self.default.clone()
+        self.default.as_ref().map(CertSlot::current)
     }
 }
 
+/// Protocols offered through ALPN, most preferred first
+pub const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+/// Build the rustls config for HTTPS, offering HTTP/2 and HTTP/1.1 via ALPN
 pub fn build_tls_config(config: &Config) -> Result<ServerConfig, Box<dyn std::error::Error>> {
-    let resolver = VeloServeCertResolver::from_config(config)?;
+    let resolver = Arc::new(VeloServeCertResolver::from_config(config)?);
+    resolver.spawn_stapling();
 
-    let tls_config =
+    let mut tls_config =
         ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
             .with_safe_default_protocol_versions()?
             .with_no_client_auth()
-            .with_cert_resolver(Arc::new(resolver));
+            .with_cert_resolver(resolver);
+    tls_config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
 
     Ok(tls_config)
 }
@@ -119,9 +242,127 @@ pub fn can_enable_tls(config: &Config) -> bool {
     config.virtualhost.iter().any(|v| {
         v.ssl_certificate
             .as_ref()
-            .map_or(false, |p| Path::new(p).exists())
+            .is_some_and(|p| Path::new(p).exists())
             && v.ssl_certificate_key
                 .as_ref()
-                .map_or(false, |p| Path::new(p).exists())
+                .is_some_and(|p| Path::new(p).exists())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ocsp::testing::{Pki, Response};
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response as HttpResponse};
+    use hyper_util::rt::TokioIo;
+    use rustls::pki_types::PrivateKeyDer;
+    use tokio::net::TcpListener;
+
+    /// An OCSP responder answering every request with the current `body`,
+    /// reporting each request's content type and body
+    async fn responder() -> (
+        String,
+        Arc<Mutex<Vec<u8>>>,
+        tokio::sync::mpsc::UnboundedReceiver<(String, Bytes)>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ocsp", listener.local_addr().unwrap());
+        let body = Arc::new(Mutex::new(Vec::new()));
+        let (seen, requests) = tokio::sync::mpsc::unbounded_channel();
+        let answer = body.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let body = answer.clone();
+                let seen = seen.clone();
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let body = body.lock().clone();
+                    let seen = seen.clone();
+                    async move {
+                        let content_type = req.headers()[hyper::header::CONTENT_TYPE]
+                            .to_str()
+                            .unwrap()
+                            .to_string();
+                        let request = req.into_body().collect().await?.to_bytes();
+                        let _ = seen.send((content_type, request));
+                        Ok::<_, hyper::Error>(HttpResponse::new(Full::new(Bytes::from(body))))
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        (url, body, requests)
+    }
+
+    fn resolver(pki: &Pki) -> VeloServeCertResolver {
+        let private_key = PrivateKeyDer::Pkcs8(pki.leaf_key.serialize_der().into());
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(&private_key).unwrap();
+        VeloServeCertResolver {
+            default: Some(CertSlot::new(
+                "default",
+                CertifiedKey::new(pki.chain(), signing_key),
+                true,
+            )),
+            certs: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ocsp_response_is_stapled() {
+        let (url, body, mut requests) = responder().await;
+        let pki = Pki::new(Some(&url));
+        let resolver = resolver(&pki);
+        let slot = resolver.default.as_ref().unwrap();
+        let request = slot.ocsp_request.as_ref().unwrap();
+        let staple = Response::good(request, Some("20990101000000Z")).sign(&pki.ca_key);
+        *body.lock() = staple.clone();
+        assert!(slot.current().ocsp.is_none());
+
+        let delay = resolver.refresh_staples().await;
+        assert_eq!(delay, STAPLE_MAX_REFRESH);
+        assert_eq!(slot.current().ocsp.as_deref(), Some(staple.as_slice()));
+
+        let (content_type, sent) = requests.recv().await.unwrap();
+        assert_eq!(content_type, "application/ocsp-request");
+        assert_eq!(sent, request.body);
+    }
+
+    #[tokio::test]
+    async fn test_failed_fetch_serves_without_staple() {
+        // A responder that answers with something other than OCSP
+        let (url, body, _requests) = responder().await;
+        *body.lock() = b"<html>".to_vec();
+        let pki = Pki::new(Some(&url));
+        let resolver = resolver(&pki);
+
+        assert_eq!(resolver.refresh_staples().await, STAPLE_RETRY);
+        let slot = resolver.default.as_ref().unwrap();
+        assert!(slot.current().ocsp.is_none());
+
+        // An expired staple is dropped; TLS carries on without one
+        slot.set_staple(Some(vec![1]), Some(SystemTime::UNIX_EPOCH));
+        resolver.refresh_staples().await;
+        assert!(slot.current().ocsp.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_or_forged_responses_are_not_stapled() {
+        let (url, body, _requests) = responder().await;
+        let pki = Pki::new(Some(&url));
+        let resolver = resolver(&pki);
+        let slot = resolver.default.as_ref().unwrap();
+        let request = slot.ocsp_request.as_ref().unwrap();
+
+        *body.lock() = Response::good(request, Some("20260102000000Z")).sign(&pki.ca_key);
+        assert_eq!(resolver.refresh_staples().await, STAPLE_RETRY);
+        assert!(slot.current().ocsp.is_none());
+
+        *body.lock() = Response::good(request, Some("20990101000000Z")).sign(&pki.leaf_key);
+        assert_eq!(resolver.refresh_staples().await, STAPLE_RETRY);
+        assert!(slot.current().ocsp.is_none());
+    }
+}
@@ -0,0 +1,1428 @@
+//! Compiled virtual hosts
+//!
+//! Everything the request path derives from a `[[virtualhost]]` block (document
+//! root, index list, cache exclude matchers, ...) is built once per
+//! configuration load into a [`CompiledVhost`]. The compiled vhosts and the
+//! `Config` they came from live in one [`CompiledConfig`] snapshot, which is
+//! swapped as a unit through [`ConfigHandle`] so a request never mixes old and
+//! new rules and nothing is recompiled per request.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::COOKIE;
+use hyper::{HeaderMap, StatusCode};
+use parking_lot::RwLock;
+
+use tracing::warn;
+
+use crate::config::{Config, UploadPolicy, VirtualHostConfig};
+use crate::server::client_addr::TrustedProxies;
+use crate::server::log_format::LogFormat;
+use crate::server::proxy::Upstream;
+use crate::server::rewrite::Rewrites;
+use crate::server::security_headers::SecurityHeaders;
+use crate::server::static_files::{CacheControlRules, MimeTypes};
+
+/// Document root used when no virtual host matches the request
+pub const DEFAULT_DOC_ROOT: &str = "/var/www/html";
+
+/// Index files tried when no virtual host matches the request
+pub const DEFAULT_INDEX_FILES: &[&str] = &["index.php", "index.html", "index.htm"];
+
+/// How long past `php.max_execution_time` the default request timeout waits
+pub const PHP_TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Upload directory protected by the WordPress preset
+pub const WORDPRESS_UPLOADS: &str = "/wp-content/uploads";
+
+/// Cookies that keep WordPress visitors out of the page cache: logged-in
+/// users, password-protected posts, carts and commenters
+pub const WORDPRESS_BYPASS_COOKIES: &[&str] = &[
+    "wordpress_logged_in_*",
+    "wp-postpass_*",
+    "woocommerce_items_in_cart",
+    "comment_author_*",
+];
+
+/// Precompiled path rule list (`/admin` matches `/admin` and `/admin/...`,
+/// `/tmp*` matches any path starting with `/tmp`)
+#[derive(Debug, Clone, Default)]
+pub struct PathMatcher {
+    exact: Vec<String>,
+    directories: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl PathMatcher {
+    /// Compile a list of rules
+    pub fn new(rules: &[String]) -> Self {
+        let mut matcher = Self::default();
+        for rule in rules {
+            if let Some(prefix) = rule.strip_suffix('*') {
+                matcher.prefixes.push(prefix.to_string());
+            } else {
+                matcher.exact.push(rule.clone());
+                matcher
+                    .directories
+                    .push(format!("{}/", rule.trim_end_matches('/')));
+            }
+        }
+        matcher
+    }
+
+    /// Returns true if `path` matches any rule
+    pub fn matches(&self, path: &str) -> bool {
+        self.exact.iter().any(|rule| path == rule)
+            || self.directories.iter().any(|dir| path.starts_with(dir))
+            || self.prefixes.iter().any(|prefix| path.starts_with(prefix))
+    }
+
+    /// Returns true if no rules were configured
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.prefixes.is_empty()
+    }
+}
+
+/// A vhost's `cache.exclude` rules
+///
+/// A plain rule works as in [`PathMatcher`]; one with `*` (any run of
+/// characters, `/` included) or `?` (any one character) is a glob over the
+/// path, and over `path?query` when the request has a query string, so
+/// `/index.php?s=*` catches search pages. A rule starting with `!` takes
+/// matching paths back in; the last rule that matches decides.
+#[derive(Debug, Clone, Default)]
+pub struct CacheExclude {
+    rules: Vec<ExcludeRule>,
+}
+
+#[derive(Debug, Clone)]
+struct ExcludeRule {
+    pattern: ExcludePattern,
+    negated: bool,
+}
+
+#[derive(Debug, Clone)]
+enum ExcludePattern {
+    /// A path and everything below it, as in [`PathMatcher`]
+    Path {
+        exact: String,
+        directory: String,
+    },
+    Glob(String),
+}
+
+impl ExcludeRule {
+    fn new(pattern: &str, negated: bool) -> Self {
+        let pattern = if pattern.contains(['*', '?']) {
+            ExcludePattern::Glob(pattern.to_string())
+        } else {
+            ExcludePattern::Path {
+                exact: pattern.to_string(),
+                directory: format!("{}/", pattern.trim_end_matches('/')),
+            }
+        };
+        Self { pattern, negated }
+    }
+
+    fn matches(&self, path: &str, query: Option<&str>) -> bool {
+        match &self.pattern {
+            ExcludePattern::Path { exact, directory } => {
+                path == exact || path.starts_with(directory.as_str())
+            }
+            ExcludePattern::Glob(pattern) => {
+                glob_matches(pattern, path)
+                    || query
+                        .is_some_and(|query| glob_matches(pattern, &format!("{}?{}", path, query)))
+            }
+        }
+    }
+}
+
+impl CacheExclude {
+    /// Compile a list of rules
+    pub fn new(rules: &[String]) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| match rule.strip_prefix('!') {
+                Some(pattern) => ExcludeRule::new(pattern, true),
+                None => ExcludeRule::new(rule, false),
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// True if a request for `path` with `query` skips the page cache
+    pub fn excludes(&self, path: &str, query: Option<&str>) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, query))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// A vhost's `cache.vary`: request values that get their own page cache
+/// entries
+///
+/// Each entry names a request header (`Accept-Encoding`) or, as
+/// `Cookie:<name>`, a single cookie. Values are trimmed and lowercased; a
+/// missing one counts as empty, so every request on the vhost carries the
+/// same set of tokens.
+#[derive(Debug, Clone, Default)]
+pub struct CacheVary {
+    sources: Vec<VarySource>,
+}
+
+#[derive(Debug, Clone)]
+enum VarySource {
+    /// Lowercased header name
+    Header(String),
+    Cookie(String),
+}
+
+impl CacheVary {
+    /// Compile the `cache.vary` entries
+    pub fn new(entries: &[String]) -> Self {
+        let sources = entries
+            .iter()
+            .map(|entry| match entry.split_once(':') {
+                Some((header, cookie)) if header.eq_ignore_ascii_case("cookie") => {
+                    VarySource::Cookie(cookie.trim().to_string())
+                }
+                _ => VarySource::Header(entry.trim().to_ascii_lowercase()),
+            })
+            .collect();
+        Self { sources }
+    }
+
+    /// The cache key variant for a request: a hash of one `name=value`
+    /// token per vary entry, then `base` (its language or explicit variant)
+    ///
+    /// Keys replace most punctuation and keep 64 characters of the variant,
+    /// which would let different values share an entry; the hash doesn't.
+    pub fn variant(&self, headers: &HeaderMap, base: Option<String>) -> Option<String> {
+        if self.sources.is_empty() {
+            return base;
+        }
+
+        let tokens = self.sources.iter().map(|source| match source {
+            VarySource::Header(name) => {
+                let value = headers
+                    .get_all(name.as_str())
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .map(str::trim)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!("{}={}", name, value.to_ascii_lowercase())
+            }
+            VarySource::Cookie(cookie) => {
+                let value = headers
+                    .get_all(COOKIE)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(';'))
+                    .find_map(|pair| {
+                        let (name, value) = pair.split_once('=')?;
+                        (name.trim() == cookie).then(|| value.trim())
+                    })
+                    .unwrap_or("");
+                format!("cookie:{}={}", cookie, value.to_ascii_lowercase())
+            }
+        });
+        let tokens = tokens.collect::<Vec<_>>().join(";");
+        let hash = xxhash_rust::xxh3::xxh3_64(tokens.as_bytes());
+        Some(match base {
+            Some(base) => format!("{:016x}-{}", hash, base),
+            None => format!("{:016x}", hash),
+        })
+    }
+}
+
+/// Cookie names (`*` and `?` globs, case-sensitive) that keep a request out
+/// of the page cache, for both lookup and store
+#[derive(Debug, Clone, Default)]
+pub struct BypassCookies {
+    patterns: Vec<String>,
+}
+
+impl BypassCookies {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        Self {
+            patterns: patterns
+                .iter()
+                .map(|pattern| pattern.as_ref().trim().to_string())
+                .collect(),
+        }
+    }
+
+    /// Whether any cookie the request carries has a matching name
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .map(|pair| pair.split_once('=').map_or(pair, |(name, _)| name).trim())
+            .any(|name| {
+                self.patterns
+                    .iter()
+                    .any(|pattern| glob_matches(pattern, name))
+            })
+    }
+}
+
+/// Paths refused with 403 before the filesystem is consulted
+/// (`deny_dotfiles`, `deny_files`)
+#[derive(Debug, Clone)]
+pub struct DenyList {
+    dotfiles: bool,
+    /// Lowercased globs matched against each path component
+    names: Vec<String>,
+    /// Lowercased globs matched against the whole path
+    paths: Vec<String>,
+}
+
+impl Default for DenyList {
+    /// Dotfiles only, for requests no vhost answers
+    fn default() -> Self {
+        Self {
+            dotfiles: true,
+            names: Vec::new(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl DenyList {
+    /// Compile a vhost's `deny_dotfiles` and `deny_files`
+    pub fn new(dotfiles: bool, patterns: &[String]) -> Self {
+        let (paths, names) = patterns
+            .iter()
+            .map(|pattern| pattern.to_lowercase())
+            .partition(|pattern| pattern.contains('/'));
+        Self {
+            dotfiles,
+            names,
+            paths,
+        }
+    }
+
+    /// Returns true if the decoded request `path` must not be served
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.to_lowercase();
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+        let denied_segment = segments.any(|segment| {
+            (self.dotfiles && is_dotfile(segment))
+                || self.names.iter().any(|glob| glob_matches(glob, segment))
+        });
+        denied_segment || self.paths.iter().any(|glob| glob_matches(glob, &path))
+    }
+}
+
+/// `.env`, `.git`, ... but not `.well-known`, nor `.` and `..`, which
+/// path resolution deals with
+fn is_dotfile(segment: &str) -> bool {
+    segment.starts_with('.') && !matches!(segment, "." | ".." | ".well-known")
+}
+
+/// Match `text` against a glob where `*` is any run of characters and `?`
+/// one character
+pub(crate) fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut g, mut t) = (0, 0);
+    // Last `*` seen and the text position it currently absorbs up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    g = star_g + 1;
+                    t = star_t + 1;
+                    star = Some((star_g, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// True if the media type of `content_type`, without parameters, matches
+/// one of the `patterns` globs (case-insensitive)
+pub(crate) fn content_type_matches(patterns: &[String], content_type: &str) -> bool {
+    let media = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| glob_matches(&pattern.to_ascii_lowercase(), &media))
+}
+
+/// True if every parameter of `query` is named by `allowed` (`utm_*` matches
+/// by prefix, `*` allows any)
+pub(crate) fn query_allowed(allowed: &[String], query: &str) -> bool {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .all(|pair| {
+            let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+            allowed.iter().any(|rule| match rule.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == rule,
+            })
+        })
+}
+
+/// Variables a `try_files` entry may use
+const TRY_FILES_VARIABLES: &[&str] = &["$uri", "$is_args", "$args", "$query_string"];
+
+/// One `try_files` entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryFile {
+    /// A URI template such as `$uri/` or `/index.php$is_args$args`
+    Uri(String),
+    /// `=404`: answer with this status
+    Status(StatusCode),
+}
+
+/// A vhost's `try_files`: candidates checked in order, then the fallback
+///
+/// As in Nginx, every entry but the last is a file (or, ending in `/`, a
+/// directory with an index file) that is served if it exists. The last is
+/// the fallback: a URI served whatever it is, typically a front controller,
+/// or `=code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryFiles {
+    candidates: Vec<String>,
+    fallback: TryFile,
+}
+
+impl Default for TryFiles {
+    /// `$uri $uri/ /index.php$is_args$args`, the built-in routing
+    fn default() -> Self {
+        Self {
+            candidates: vec!["$uri".to_string(), "$uri/".to_string()],
+            fallback: TryFile::Uri("/index.php$is_args$args".to_string()),
+        }
+    }
+}
+
+impl TryFiles {
+    /// Parse a `try_files` list; an empty one is the default
+    pub fn new(entries: &[String]) -> Result<Self, String> {
+        let Some((last, candidates)) = entries.split_last() else {
+            return Ok(Self::default());
+        };
+        for entry in candidates {
+            if entry.starts_with('=') {
+                return Err(format!("'{}' is only allowed as the last entry", entry));
+            }
+            check_try_uri(entry)?;
+        }
+        let fallback = match last.strip_prefix('=') {
+            Some(code) => code
+                .parse::<u16>()
+                .ok()
+                .filter(|code| (200..600).contains(code))
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .map(TryFile::Status)
+                .ok_or_else(|| format!("'{}' is not a valid status", last))?,
+            None => {
+                check_try_uri(last)?;
+                TryFile::Uri(last.clone())
+            }
+        };
+        Ok(Self {
+            candidates: candidates.to_vec(),
+            fallback,
+        })
+    }
+
+    /// Entries checked for an existing file or directory
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    /// What answers when no candidate exists
+    pub fn fallback(&self) -> &TryFile {
+        &self.fallback
+    }
+}
+
+/// Substitute the request's `$uri` and query into a `try_files` entry,
+/// keeping only the path
+pub fn expand_try_uri(template: &str, path: &str, query: Option<&str>) -> String {
+    let is_args = if query.is_some() { "?" } else { "" };
+    let expanded = template
+        .replace("$is_args", is_args)
+        .replace("$query_string", query.unwrap_or(""))
+        .replace("$args", query.unwrap_or(""))
+        .replace("$uri", path);
+    match expanded.split_once('?') {
+        Some((path, _)) => path.to_string(),
+        None => expanded,
+    }
+}
+
+fn check_try_uri(entry: &str) -> Result<(), String> {
+    if !entry.starts_with('/') && !entry.starts_with('$') {
+        return Err(format!("'{}' must start with '/' or a variable", entry));
+    }
+    let mut rest = entry;
+    while let Some(start) = rest.find('$') {
+        rest = &rest[start..];
+        let known = TRY_FILES_VARIABLES.iter().find(|var| {
+            rest.starts_with(*var)
+                && !rest[var.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        });
+        let Some(var) = known else {
+            return Err(format!(
+                "'{}' uses an unknown variable (supported: {})",
+                entry,
+                TRY_FILES_VARIABLES.join(", ")
+            ));
+        };
+        rest = &rest[var.len()..];
+    }
+    Ok(())
+}
+
+/// A virtual host with all per-request artifacts precompiled
+#[derive(Debug, Clone)]
+pub struct CompiledVhost {
+    /// The source configuration block
+    pub config: VirtualHostConfig,
+    /// Document root
+    pub root: PathBuf,
+    /// Directory index files, in order
+    pub index: Vec<String>,
+    /// Page cache enabled for this vhost
+    pub cache_enabled: bool,
+    /// Page cache TTL override
+    pub cache_ttl: Option<Duration>,
+    /// Stale-while-revalidate window override
+    pub cache_stale_grace: Option<Duration>,
+    /// Paths never served from or stored in the page cache
+    pub cache_exclude: CacheExclude,
+    /// Content types never stored in the page cache
+    pub cache_exclude_types: Arc<Vec<String>>,
+    /// Request values that split page cache entries
+    pub cache_vary: CacheVary,
+    /// Cookies that keep a request out of the page cache
+    pub cache_bypass_cookies: BypassCookies,
+    /// Query parameters a cached page may carry
+    pub cache_query_allow: Arc<Vec<String>>,
+    /// Request timeout override for the whole vhost
+    pub request_timeout: Option<Duration>,
+    /// `[[virtualhost.location]]` blocks in configuration order
+    pub locations: Vec<CompiledLocation>,
+    /// `[[virtualhost.rewrite]]` rules
+    pub rewrites: Rewrites,
+    /// Paths answered with 403
+    pub deny: DenyList,
+    /// Routing for paths that are neither a file nor a directory
+    pub try_files: TryFiles,
+    /// `Cache-Control` overrides for static files
+    pub static_cache_control: Arc<CacheControlRules>,
+    /// Lowercased extensions, without the leading dot, sent as attachments
+    pub force_download: Arc<Vec<String>>,
+}
+
+/// A compiled `[[virtualhost.location]]` block
+#[derive(Debug, Clone)]
+pub struct CompiledLocation {
+    pub matcher: PathMatcher,
+    pub request_timeout: Option<Duration>,
+    pub streaming_timeout: Option<Duration>,
+    pub uploads: Option<UploadPolicy>,
+    pub proxy_pass: Option<Upstream>,
+}
+
+impl CompiledVhost {
+    /// Compile a single virtual host block
+    pub fn compile(config: &VirtualHostConfig) -> Self {
+        let cache = config.cache.as_ref();
+        let mut locations: Vec<CompiledLocation> = config
+            .locations
+            .iter()
+            .map(|location| CompiledLocation {
+                matcher: PathMatcher::new(std::slice::from_ref(&location.path)),
+                request_timeout: location.request_timeout,
+                streaming_timeout: location.streaming_timeout,
+                uploads: location.uploads,
+                // Validated with the rest of the config
+                proxy_pass: location
+                    .proxy_pass
+                    .as_deref()
+                    .and_then(|proxy_pass| Upstream::parse(proxy_pass).ok()),
+            })
+            .collect();
+
+        // Platform presets go last so configured locations override them
+        let wordpress = config
+            .platform
+            .as_deref()
+            .is_some_and(|p| p.to_ascii_lowercase().contains("wordpress"));
+        if wordpress {
+            locations.push(CompiledLocation {
+                matcher: PathMatcher::new(&[WORDPRESS_UPLOADS.to_string()]),
+                request_timeout: None,
+                streaming_timeout: None,
+                uploads: Some(UploadPolicy::Attachment),
+                proxy_pass: None,
+            });
+        }
+
+        Self {
+            root: PathBuf::from(&config.root),
+            index: config.index.clone(),
+            cache_enabled: cache.map(|c| c.enable).unwrap_or(true),
+            cache_ttl: cache.map(|c| Duration::from_secs(c.ttl)),
+            cache_stale_grace: cache.and_then(|c| c.stale_grace).map(Duration::from_secs),
+            cache_exclude: cache
+                .map(|c| CacheExclude::new(&c.exclude))
+                .unwrap_or_default(),
+            cache_exclude_types: Arc::new(
+                cache.map(|c| c.exclude_types.clone()).unwrap_or_default(),
+            ),
+            cache_vary: cache.map(|c| CacheVary::new(&c.vary)).unwrap_or_default(),
+            cache_bypass_cookies: match cache.and_then(|c| c.bypass_cookies.as_ref()) {
+                Some(patterns) => BypassCookies::new(patterns),
+                None if wordpress => BypassCookies::new(WORDPRESS_BYPASS_COOKIES),
+                None => BypassCookies::default(),
+            },
+            cache_query_allow: Arc::new(cache.map(|c| c.query_allow.clone()).unwrap_or_default()),
+            request_timeout: config.request_timeout,
+            locations,
+            rewrites: Rewrites::new(&config.rewrites).unwrap_or_else(|e| {
+                warn!(
+                    "Invalid rewrite for {} ({}), ignoring rewrites",
+                    config.domain, e
+                );
+                Rewrites::default()
+            }),
+            deny: DenyList::new(config.deny_dotfiles, &config.deny_files),
+            try_files: TryFiles::new(&config.try_files).unwrap_or_else(|e| {
+                warn!(
+                    "Invalid try_files for {} ({}), using the default",
+                    config.domain, e
+                );
+                TryFiles::default()
+            }),
+            static_cache_control: Arc::new(
+                CacheControlRules::new(&config.static_cache_control).unwrap_or_else(|e| {
+                    warn!(
+                        "Invalid static_cache_control for {} ({}), using the defaults",
+                        config.domain, e
+                    );
+                    CacheControlRules::default()
+                }),
+            ),
+            force_download: Arc::new(
+                config
+                    .force_download
+                    .iter()
+                    .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                    .filter(|extension| !extension.is_empty())
+                    .collect(),
+            ),
+            config: config.clone(),
+        }
+    }
+
+    /// Upload protections for `path`, if it lies in an upload directory
+    pub fn upload_policy(&self, path: &str) -> Option<UploadPolicy> {
+        self.location_setting(path, |l| l.uploads)
+            .filter(|policy| *policy != UploadPolicy::Off)
+    }
+
+    /// The upstream serving `path`, if it lies in a `proxy_pass` location
+    pub fn upstream(&self, path: &str) -> Option<&Upstream> {
+        self.locations
+            .iter()
+            .filter(|location| location.matcher.matches(path))
+            .find_map(|location| location.proxy_pass.as_ref())
+    }
+
+    /// A setting from the first location matching `path` that sets it
+    fn location_setting<T>(
+        &self,
+        path: &str,
+        setting: impl Fn(&CompiledLocation) -> Option<T>,
+    ) -> Option<T> {
+        self.locations
+            .iter()
+            .filter(|location| location.matcher.matches(path))
+            .find_map(setting)
+    }
+}
+
+/// The timeout that applies to a request and the setting it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeout {
+    pub duration: Duration,
+    pub source: &'static str,
+}
+
+/// Internal endpoints that use `server.api_timeout`
+pub fn is_api_path(path: &str) -> bool {
+    path.starts_with("/api/v1/")
+        || matches!(
+            path,
+            "/health" | "/healthz" | "/ready" | "/readyz" | "/metrics"
+        )
+}
+
+/// A configuration snapshot together with its compiled virtual hosts
+#[derive(Debug)]
+pub struct CompiledConfig {
+    /// Configuration this snapshot was compiled from
+    pub config: Arc<Config>,
+    /// Access log render plan
+    pub log_format: LogFormat,
+    /// Paths that accept writes in read-only mode
+    pub readonly_allow: PathMatcher,
+    /// Built-in content types merged with `[mime_types]`
+    pub mime_types: Arc<MimeTypes>,
+    /// Peers whose `X-Forwarded-For` is believed
+    pub trusted_proxies: TrustedProxies,
+    /// Clients allowed to send `PURGE` (`cache.purge_allow`)
+    pub purge_allow: TrustedProxies,
+    /// Clients `/metrics` is served to (`server.metrics_allow`)
+    pub metrics_allow: TrustedProxies,
+    /// Port named in HTTPS redirects: `listen_ssl`'s, unless it is 443
+    pub https_port: Option<u16>,
+    /// `[server.headers]`, added to every response
+    pub security_headers: SecurityHeaders,
+    vhosts: Vec<CompiledVhost>,
+    /// Lowercased domain -> index of the first vhost declaring it
+    by_domain: HashMap<String, usize>,
+    /// Index of the first `*` vhost
+    wildcard: Option<usize>,
+}
+
+impl CompiledConfig {
+    /// Compile every virtual host of `config`
+    pub fn compile(config: Arc<Config>) -> Self {
+        let vhosts: Vec<CompiledVhost> = config
+            .virtualhost
+            .iter()
+            .map(CompiledVhost::compile)
+            .collect();
+
+        let mut by_domain = HashMap::new();
+        let mut wildcard = None;
+        for (index, vhost) in vhosts.iter().enumerate() {
+            if vhost.config.domain == "*" {
+                wildcard.get_or_insert(index);
+            } else {
+                by_domain
+                    .entry(vhost.config.domain.to_ascii_lowercase())
+                    .or_insert(index);
+            }
+        }
+
+        // Validated configs always compile; fall back for hand-built ones
+        let log_format = LogFormat::compile(&config.logging).unwrap_or_else(|e| {
+            warn!("Invalid logging.format ({}), using combined", e);
+            LogFormat::default()
+        });
+
+        let trusted_proxies =
+            TrustedProxies::new(&config.server.trusted_proxies).unwrap_or_else(|e| {
+                warn!("Invalid server.trusted_proxies ({}), trusting none", e);
+                TrustedProxies::default()
+            });
+
+        let purge_allow = TrustedProxies::new(&config.cache.purge_allow).unwrap_or_else(|e| {
+            warn!("Invalid cache.purge_allow ({}), allowing none", e);
+            TrustedProxies::default()
+        });
+
+        let metrics_allow = TrustedProxies::new(&config.server.metrics_allow).unwrap_or_else(|e| {
+            warn!("Invalid server.metrics_allow ({}), allowing none", e);
+            TrustedProxies::default()
+        });
+
+        let security_headers = SecurityHeaders::new(&config.server.headers).unwrap_or_else(|e| {
+            warn!("Invalid server.headers ({}), adding none", e);
+            SecurityHeaders::default()
+        });
+
+        let https_port = config
+            .server
+            .listen_ssl
+            .as_deref()
+            .and_then(|listen| listen.rsplit_once(':'))
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .filter(|&port| port != 443);
+
+        Self {
+            readonly_allow: PathMatcher::new(&config.server.readonly_allow),
+            trusted_proxies,
+            purge_allow,
+            metrics_allow,
+            https_port,
+            security_headers,
+            mime_types: Arc::new(MimeTypes::from_config(&config)),
+            config,
+            log_format,
+            vhosts,
+            by_domain,
+            wildcard,
+        }
+    }
+
+    /// Find the vhost for a `Host` header value (port is ignored).
+    ///
+    /// Vhosts are considered in configuration order, so a `*` entry declared
+    /// before a specific domain shadows it.
+    pub fn find(&self, host: &str) -> Option<&CompiledVhost> {
+        let host = host.split(':').next().unwrap_or(host);
+        let exact = if host.bytes().any(|b| b.is_ascii_uppercase()) {
+            self.by_domain.get(&host.to_ascii_lowercase())
+        } else {
+            self.by_domain.get(host)
+        };
+        let index = match (exact.copied(), self.wildcard) {
+            (Some(exact), Some(wildcard)) => exact.min(wildcard),
+            (exact, wildcard) => exact.or(wildcard)?,
+        };
+        self.vhosts.get(index)
+    }
+
+    /// Resolve the timeout for a request.
+    ///
+    /// API endpoints use `server.api_timeout`; site traffic uses the first
+    /// matching location, then the vhost, then `server.request_timeout`
+    /// (raised past `php.max_execution_time` when PHP is enabled).
+    pub fn request_timeout(&self, host: &str, path: &str) -> RequestTimeout {
+        let server = &self.config.server;
+        if is_api_path(path) {
+            if let Some(duration) = server.api_timeout {
+                return RequestTimeout {
+                    duration,
+                    source: "server.api_timeout",
+                };
+            }
+        } else if let Some(vhost) = self.find(host) {
+            if let Some(duration) = vhost.location_setting(path, |l| l.request_timeout) {
+                return RequestTimeout {
+                    duration,
+                    source: "location.request_timeout",
+                };
+            }
+            if let Some(duration) = vhost.request_timeout {
+                return RequestTimeout {
+                    duration,
+                    source: "virtualhost.request_timeout",
+                };
+            }
+        }
+        // The server-wide default never cuts a PHP script off before its own
+        // max_execution_time does, so the script's timeout error gets out
+        let php = &self.config.php;
+        if php.enable && !is_api_path(path) && php.max_execution_time >= server.request_timeout {
+            return RequestTimeout {
+                duration: php.max_execution_time + PHP_TIMEOUT_GRACE,
+                source: "php.max_execution_time",
+            };
+        }
+        RequestTimeout {
+            duration: server.request_timeout,
+            source: "server.request_timeout",
+        }
+    }
+
+    /// How long a streamed response may run: the matching location's
+    /// `streaming_timeout`, or the request timeout when none is set
+    pub fn streaming_timeout(&self, host: &str, path: &str) -> Duration {
+        self.find(host)
+            .and_then(|vhost| vhost.location_setting(path, |l| l.streaming_timeout))
+            .unwrap_or_else(|| self.request_timeout(host, path).duration)
+    }
+
+    /// All compiled vhosts in configuration order
+    pub fn vhosts(&self) -> &[CompiledVhost] {
+        &self.vhosts
+    }
+}
+
+/// Shared, atomically swappable configuration snapshot
+#[derive(Debug)]
+pub struct ConfigHandle {
+    current: RwLock<Arc<CompiledConfig>>,
+}
+
+impl ConfigHandle {
+    /// Compile `config` and make it the current snapshot
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(CompiledConfig::compile(config))),
+        }
+    }
+
+    /// Current snapshot; hold it for the whole request
+    pub fn load(&self) -> Arc<CompiledConfig> {
+        self.current.read().clone()
+    }
+
+    /// Compile `config` and swap it in; in-flight requests keep their snapshot
+    pub fn replace(&self, config: Arc<Config>) {
+        // Compile outside the lock so readers are never blocked on compilation
+        let compiled = Arc::new(CompiledConfig::compile(config));
+        *self.current.write() = compiled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vhost(domain: &str, root: &str) -> VirtualHostConfig {
+        toml::from_str(&format!("domain = \"{}\"\nroot = \"{}\"", domain, root)).unwrap()
+    }
+
+    fn config(vhosts: Vec<VirtualHostConfig>) -> Arc<Config> {
+        Arc::new(Config {
+            virtualhost: vhosts,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn test_path_matcher() {
+        let matcher = PathMatcher::new(&["/admin".to_string(), "/tmp*".to_string()]);
+        assert!(matcher.matches("/admin"));
+        assert!(matcher.matches("/admin/users"));
+        assert!(!matcher.matches("/administrator"));
+        assert!(matcher.matches("/tmpfile"));
+        assert!(!matcher.matches("/blog"));
+        assert!(PathMatcher::new(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_query_allowed() {
+        let allowed = ["page".to_string(), "utm_*".to_string()];
+        assert!(query_allowed(&allowed, "page=2"));
+        assert!(query_allowed(&allowed, "utm_source=mail&page=2&"));
+        assert!(!query_allowed(&allowed, "page=2&s=shoes"));
+        assert!(!query_allowed(&[], "page=2"));
+        assert!(query_allowed(&["*".to_string()], "anything=1"));
+    }
+
+    #[test]
+    fn test_cache_exclude_wordpress_defaults() {
+        let platform = crate::apache_compat::converter::platform_cache("wordpress").unwrap();
+        let exclude = CacheExclude::new(&platform.exclude);
+        assert!(exclude.excludes("/wp-admin/", None));
+        assert!(exclude.excludes("/wp-admin/post.php", Some("post=1&action=edit")));
+        assert!(exclude.excludes("/wp-login.php", None));
+        assert!(exclude.excludes("/wp-login.php", Some("action=lostpassword")));
+        assert!(!exclude.excludes("/wp-admin", None));
+        assert!(!exclude.excludes("/wp-login.php.bak", None));
+        assert!(!exclude.excludes("/", None));
+        assert!(!exclude.excludes("/blog/hello-world/", None));
+    }
+
+    #[test]
+    fn test_cache_exclude_globs() {
+        let exclude = CacheExclude::new(&[
+            "/index.php?s=*".to_string(),
+            "/shop/*/cart".to_string(),
+            "/api/v?/*".to_string(),
+            "/account".to_string(),
+            "!/account/login".to_string(),
+        ]);
+        // A rule with a query-string component
+        assert!(exclude.excludes("/index.php", Some("s=shoes")));
+        assert!(!exclude.excludes("/index.php", Some("p=42")));
+        assert!(!exclude.excludes("/index.php", None));
+
+        assert!(exclude.excludes("/shop/eu/cart", None));
+        assert!(!exclude.excludes("/shop/eu/checkout", None));
+        assert!(exclude.excludes("/api/v2/users", None));
+        assert!(!exclude.excludes("/api/v10/users", None));
+
+        assert!(exclude.excludes("/account/orders", None));
+        assert!(!exclude.excludes("/account/login", None));
+        assert!(CacheExclude::default().rules.is_empty());
+
+        // Anchored at the start of the path, and case-sensitive
+        let exclude = CacheExclude::new(&["/cart*".to_string()]);
+        assert!(exclude.excludes("/cart", None));
+        assert!(exclude.excludes("/cart/", None));
+        assert!(exclude.excludes("/cart-totals", None));
+        assert!(!exclude.excludes("/shop/cart", None));
+        assert!(!exclude.excludes("/Cart", None));
+    }
+
+    fn vary_key(vary: &CacheVary, headers: &[(&str, &str)]) -> String {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                hyper::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        crate::cache::build_page_cache_key_scoped(
+            "example.test",
+            None,
+            None,
+            vary.variant(&map, None).as_deref(),
+            "/",
+        )
+    }
+
+    #[test]
+    fn test_cache_vary_keys() {
+        let vary = CacheVary::new(&["Accept-Encoding".to_string(), "Cookie:wp_lang".to_string()]);
+        let gzip = vary_key(&vary, &[("accept-encoding", "gzip")]);
+        assert_ne!(gzip, vary_key(&vary, &[("accept-encoding", "identity")]));
+        assert_eq!(gzip, vary_key(&vary, &[("accept-encoding", "  GZIP ")]));
+
+        // Absent values are an empty token, distinct from any real value
+        let absent = vary_key(&vary, &[]);
+        assert_eq!(absent, vary_key(&vary, &[("x-other", "1")]));
+        assert_ne!(absent, gzip);
+
+        let english = vary_key(&vary, &[("cookie", "session=1; wp_lang=EN")]);
+        assert_eq!(english, vary_key(&vary, &[("cookie", "wp_lang=en")]));
+        assert_ne!(english, vary_key(&vary, &[("cookie", "wp_lang=de")]));
+
+        // Values that only differ in characters keys can't hold stay apart
+        assert_ne!(
+            vary_key(&vary, &[("accept-encoding", "gzip,br")]),
+            vary_key(&vary, &[("accept-encoding", "gzip br")])
+        );
+
+        // The language variant is kept alongside
+        let map: HeaderMap = [(hyper::header::ACCEPT_ENCODING, "gzip".parse().unwrap())]
+            .into_iter()
+            .collect();
+        let variant = vary.variant(&map, Some("de".to_string())).unwrap();
+        assert!(variant.ends_with("-de"));
+        assert_eq!(CacheVary::default().variant(&map, None), None);
+    }
+
+    #[tokio::test]
+    async fn test_cache_vary_entries_stay_apart() {
+        use crate::cache::{CacheLifetime, CacheManager, CachedResponse};
+
+        let cache = CacheManager::new(&crate::config::CacheConfig {
+            enable: true,
+            l1_enabled: true,
+            l2_enabled: false,
+            ..crate::config::CacheConfig::default()
+        });
+        let vary = CacheVary::new(&["Accept-Encoding".to_string()]);
+        let gzip = vary_key(&vary, &[("accept-encoding", "gzip")]);
+        let identity = vary_key(&vary, &[("accept-encoding", "identity")]);
+        cache
+            .set_response(
+                &gzip,
+                CachedResponse::new(200, Vec::new(), "gzip page"),
+                CacheLifetime::from_ttl(Duration::from_secs(60)),
+            )
+            .await;
+
+        assert!(cache.get_response(&identity).await.is_none());
+        assert_eq!(cache.get_response(&gzip).await.unwrap().body, "gzip page");
+    }
+
+    #[test]
+    fn test_deny_list() {
+        let deny = DenyList::default();
+        assert!(deny.matches("/.env"));
+        assert!(deny.matches("/.git/config"));
+        assert!(deny.matches("/app/.htpasswd"));
+        assert!(!deny.matches("/.well-known/acme-challenge/token"));
+        assert!(!deny.matches("/index.html"));
+        assert!(!deny.matches("/"));
+
+        let deny = DenyList::new(
+            true,
+            &[
+                "*.bak".to_string(),
+                "*.SQL".to_string(),
+                "composer.lock".to_string(),
+                "/private/*".to_string(),
+                "backup-?".to_string(),
+            ],
+        );
+        assert!(deny.matches("/wp-config.php.bak"));
+        assert!(deny.matches("/dumps/db.Sql"));
+        assert!(deny.matches("/DB.SQL"));
+        assert!(deny.matches("/Composer.lock"));
+        assert!(deny.matches("/backup-1/index.html"));
+        assert!(!deny.matches("/backup-12/index.html"));
+        assert!(deny.matches("/private/keys.txt"));
+        assert!(!deny.matches("/public/private/keys.txt"));
+        assert!(!deny.matches("/bakery.html"));
+
+        let dotfiles_allowed = DenyList::new(false, &[]);
+        assert!(!dotfiles_allowed.matches("/.env"));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*.tar.gz", "site.tar.gz"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("?.txt", "é.txt"));
+        assert!(!glob_matches("?.txt", "ab.txt"));
+    }
+
+    #[test]
+    fn test_find_respects_declaration_order() {
+        let compiled = CompiledConfig::compile(config(vec![
+            vhost("example.com", "/srv/example"),
+            vhost("*", "/srv/default"),
+            vhost("late.example", "/srv/late"),
+        ]));
+
+        assert_eq!(
+            compiled.find("Example.com:8080").unwrap().root,
+            PathBuf::from("/srv/example")
+        );
+        assert_eq!(
+            compiled.find("other.test").unwrap().root,
+            PathBuf::from("/srv/default")
+        );
+        // The wildcard is declared first, exactly like the linear scan it replaces
+        assert_eq!(
+            compiled.find("late.example").unwrap().root,
+            PathBuf::from("/srv/default")
+        );
+
+        let no_default = CompiledConfig::compile(config(vec![vhost("a.test", "/srv/a")]));
+        assert!(no_default.find("b.test").is_none());
+    }
+
+    #[test]
+    fn test_request_timeout_precedence() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            request_timeout = "60s"
+            api_timeout = "2s"
+
+            [[virtualhost]]
+            domain = "shop.test"
+            root = "/srv/shop"
+            request_timeout = "20s"
+
+            [[virtualhost.location]]
+            path = "/events*"
+            streaming_timeout = "1h"
+
+            [[virtualhost.location]]
+            path = "/admin/export*"
+            request_timeout = "10m"
+
+            [[virtualhost]]
+            domain = "*"
+            root = "/srv/default"
+            "#,
+        )
+        .unwrap();
+        let compiled = CompiledConfig::compile(Arc::new(config));
+
+        let timeout = |host: &str, path: &str| {
+            let resolved = compiled.request_timeout(host, path);
+            (resolved.duration.as_secs(), resolved.source)
+        };
+        assert_eq!(
+            timeout("shop.test", "/api/v1/status"),
+            (2, "server.api_timeout")
+        );
+        assert_eq!(
+            timeout("shop.test", "/admin/export/orders.csv"),
+            (600, "location.request_timeout")
+        );
+        assert_eq!(
+            timeout("shop.test", "/checkout"),
+            (20, "virtualhost.request_timeout")
+        );
+        assert_eq!(
+            timeout("blog.test", "/admin/export"),
+            (60, "server.request_timeout")
+        );
+        // A location without request_timeout doesn't shadow later ones
+        assert_eq!(
+            timeout("shop.test", "/events/chat"),
+            (20, "virtualhost.request_timeout")
+        );
+
+        let streaming = |host: &str, path: &str| compiled.streaming_timeout(host, path).as_secs();
+        assert_eq!(streaming("shop.test", "/events/chat"), 3600);
+        assert_eq!(streaming("shop.test", "/checkout"), 20);
+        assert_eq!(streaming("blog.test", "/events"), 60);
+    }
+
+    #[test]
+    fn test_php_execution_time_outlasts_default_timeout() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            request_timeout = "10s"
+
+            [php]
+            max_execution_time = "2m"
+
+            [[virtualhost]]
+            domain = "shop.test"
+            root = "/srv/shop"
+
+            [[virtualhost.location]]
+            path = "/quick*"
+            request_timeout = "5s"
+            "#,
+        )
+        .unwrap();
+        let compiled = CompiledConfig::compile(Arc::new(config));
+
+        let resolved = compiled.request_timeout("shop.test", "/report.php");
+        assert_eq!(resolved.duration, Duration::from_secs(121));
+        assert_eq!(resolved.source, "php.max_execution_time");
+        // Explicit site timeouts and the API are not raised
+        assert_eq!(
+            compiled.request_timeout("shop.test", "/quick.php").source,
+            "location.request_timeout"
+        );
+        assert_eq!(
+            compiled
+                .request_timeout("shop.test", "/api/v1/status")
+                .source,
+            "server.request_timeout"
+        );
+    }
+
+    #[test]
+    fn test_bypass_cookies() {
+        let config: Config = toml::from_str(
+            r#"
+            [[virtualhost]]
+            domain = "wp.test"
+            root = "/srv/wp"
+            platform = "wordpress"
+
+            [[virtualhost]]
+            domain = "shop.test"
+            root = "/srv/shop"
+            platform = "wordpress"
+
+            [virtualhost.cache]
+            bypass_cookies = ["cart_?", "session"]
+
+            [[virtualhost]]
+            domain = "*"
+            root = "/srv/default"
+            "#,
+        )
+        .unwrap();
+        let compiled = CompiledConfig::compile(Arc::new(config));
+        let bypassed = |host: &str, cookies: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for cookie in cookies {
+                headers.append(COOKIE, cookie.parse().unwrap());
+            }
+            compiled
+                .find(host)
+                .unwrap()
+                .cache_bypass_cookies
+                .matches(&headers)
+        };
+
+        // WordPress defaults, found among other cookies in one header
+        assert!(bypassed(
+            "wp.test",
+            &["_ga=GA1.2; wordpress_logged_in_0a1b=admin%7C1700; theme=dark"]
+        ));
+        assert!(bypassed("wp.test", &["_ga=1; wp-postpass_0a1b=pass"]));
+        assert!(bypassed("wp.test", &["a=1;woocommerce_items_in_cart=1"]));
+        assert!(bypassed(
+            "wp.test",
+            &["theme=dark", "comment_author_0a1b=Ann"]
+        ));
+        assert!(!bypassed(
+            "wp.test",
+            &["_ga=GA1.2; wordpress_test_cookie=WP%20Cookie%20check; theme=dark"]
+        ));
+        // Names only, whole and case-sensitive
+        assert!(!bypassed("wp.test", &["theme=wordpress_logged_in_0a1b"]));
+        assert!(!bypassed("wp.test", &["WordPress_Logged_In_0a1b=admin"]));
+        assert!(!bypassed("wp.test", &[]));
+
+        // Configured patterns replace the defaults
+        assert!(bypassed("shop.test", &["a=1; cart_1=x"]));
+        assert!(bypassed("shop.test", &["session"]));
+        assert!(!bypassed("shop.test", &["wordpress_logged_in_0a1b=admin"]));
+        assert!(!bypassed("shop.test", &["cart_10=x"]));
+
+        // Other platforms have none
+        assert!(!bypassed("other.test", &["wordpress_logged_in_0a1b=admin"]));
+    }
+
+    #[test]
+    fn test_wordpress_uploads_preset() {
+        let config: Config = toml::from_str(
+            r#"
+            [[virtualhost]]
+            domain = "wp.test"
+            root = "/srv/wp"
+            platform = "WordPress"
+
+            [[virtualhost]]
+            domain = "legacy.test"
+            root = "/srv/legacy"
+            platform = "wordpress"
+
+            [[virtualhost.location]]
+            path = "/wp-content/uploads/legacy"
+            uploads = "off"
+
+            [[virtualhost.location]]
+            path = "/media"
+            uploads = "plain"
+
+            [[virtualhost]]
+            domain = "*"
+            root = "/srv/default"
+            "#,
+        )
+        .unwrap();
+        let compiled = CompiledConfig::compile(Arc::new(config));
+        let policy = |host: &str, path: &str| compiled.find(host).unwrap().upload_policy(path);
+
+        assert_eq!(
+            policy("wp.test", "/wp-content/uploads/2024/shell.php"),
+            Some(UploadPolicy::Attachment)
+        );
+        assert_eq!(
+            policy("wp.test", "/wp-content/uploads"),
+            Some(UploadPolicy::Attachment)
+        );
+        assert_eq!(policy("wp.test", "/wp-content/uploads-old/a.php"), None);
+        assert_eq!(policy("wp.test", "/wp-content/plugins/a.php"), None);
+
+        // Configured locations take precedence over the preset
+        assert_eq!(
+            policy("legacy.test", "/wp-content/uploads/legacy/a.php"),
+            None
+        );
+        assert_eq!(
+            policy("legacy.test", "/wp-content/uploads/a.php"),
+            Some(UploadPolicy::Attachment)
+        );
+        assert_eq!(
+            policy("legacy.test", "/media/a.svg"),
+            Some(UploadPolicy::Plain)
+        );
+
+        assert_eq!(policy("other.test", "/wp-content/uploads/a.php"), None);
+    }
+
+    #[test]
+    fn test_try_files() {
+        let parse = |entries: &[&str]| {
+            let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+            TryFiles::new(&entries)
+        };
+
+        assert_eq!(parse(&[]).unwrap(), TryFiles::default());
+        let static_site = parse(&["$uri", "$uri.html", "=404"]).unwrap();
+        assert_eq!(static_site.candidates(), ["$uri", "$uri.html"]);
+        assert_eq!(
+            static_site.fallback(),
+            &TryFile::Status(StatusCode::NOT_FOUND)
+        );
+        let laravel = parse(&["$uri", "/public/index.php$is_args$args"]).unwrap();
+        assert_eq!(
+            laravel.fallback(),
+            &TryFile::Uri("/public/index.php$is_args$args".to_string())
+        );
+
+        for bad in [
+            &["=404", "$uri"][..],
+            &["=99"],
+            &["=abc"],
+            &["index.php"],
+            &["$request_filename"],
+            &["$urix"],
+        ] {
+            assert!(parse(bad).is_err(), "{:?}", bad);
+        }
+
+        assert_eq!(expand_try_uri("$uri/", "/blog", None), "/blog/");
+        assert_eq!(
+            expand_try_uri("/index.php$is_args$args", "/blog", Some("p=1")),
+            "/index.php"
+        );
+        assert_eq!(
+            expand_try_uri("$uri.html", "/about", Some("x")),
+            "/about.html"
+        );
+    }
+
+    /// Readers racing with reloads must always see a vhost from the same
+    /// snapshot as the config they loaded.
+    #[test]
+    fn test_reload_race_never_mixes_snapshots() {
+        let generation_config = |generation: usize| {
+            config(vec![vhost(
+                "example.com",
+                &format!("/srv/gen{}", generation),
+            )])
+        };
+        let handle = Arc::new(ConfigHandle::new(generation_config(0)));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = handle.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20_000 {
+                        let snapshot = handle.load();
+                        let vhost = snapshot.find("example.com").unwrap();
+                        assert_eq!(
+                            vhost.root,
+                            PathBuf::from(&snapshot.config.virtualhost[0].root)
+                        );
+                    }
+                })
+            })
+            .collect();
+
+        for generation in 1..2_000 {
+            handle.replace(generation_config(generation));
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(
+            handle.load().find("example.com").unwrap().root,
+            PathBuf::from("/srv/gen1999")
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! Zero-copy static files (`server.sendfile`)
+//!
+//! hyper owns the connection and writes every body byte itself, so a large
+//! static file normally passes through userspace one chunk at a time. On a
+//! plain HTTP/1.1 connection on Linux, with `server.sendfile` on, the file is
+//! handed to the kernel with sendfile(2) instead:
+//!
+//! - the accept loop wraps the socket in a [`ZeroCopyStream`] and gives every
+//!   request on the connection its [`ZeroCopySlot`]
+//! - a streamed static file answers with a [`PlaceholderBody`]: the open file
+//!   plus chunks of a shared zeroed buffer that is never sent
+//! - the connection is switched to vectored writes, so hyper queues those
+//!   chunks as they are; when its writes reach the stream, slices pointing
+//!   into the placeholder buffer are replaced by sendfile(2) from the file
+//!
+//! Responses go out in request order, and a body registers its file when
+//! hyper first asks it for data, so the slot keeps files in a queue and the
+//! front one always belongs to the placeholder being written. Everything else
+//! (response heads, other bodies) reaches the socket unchanged. TLS and
+//! HTTP/2 connections get no slot and keep the normal body path.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+/// Whether this platform can send files with sendfile(2)
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Bytes covered by one placeholder chunk (and at most one sendfile call)
+const PLACEHOLDER_LEN: usize = 1 << 20;
+
+/// Stand-in for file contents; allocated zeroed, so its pages are never
+/// touched
+static PLACEHOLDER: Lazy<&'static [u8]> =
+    Lazy::new(|| Box::leak(vec![0u8; PLACEHOLDER_LEN].into_boxed_slice()));
+
+/// Whether `buf` lies inside the placeholder buffer
+fn is_placeholder(buf: &[u8]) -> bool {
+    let start = PLACEHOLDER.as_ptr() as usize;
+    let ptr = buf.as_ptr() as usize;
+    !buf.is_empty() && ptr >= start && ptr + buf.len() <= start + PLACEHOLDER_LEN
+}
+
+/// A file waiting to be sent: `remaining` bytes from `offset`
+struct QueuedFile {
+    file: File,
+    offset: i64,
+    remaining: u64,
+}
+
+/// Files whose placeholders a connection is writing, in response order
+#[derive(Clone, Default)]
+pub struct ZeroCopySlot(Arc<Mutex<VecDeque<QueuedFile>>>);
+
+impl ZeroCopySlot {
+    /// Body sending `length` bytes of `file` from `offset` through this slot
+    pub fn body(&self, file: File, offset: u64, length: u64) -> PlaceholderBody {
+        PlaceholderBody {
+            slot: self.clone(),
+            file: Some(QueuedFile {
+                file,
+                offset: offset as i64,
+                remaining: length,
+            }),
+            remaining: length,
+        }
+    }
+}
+
+/// Body of placeholder chunks, sent by the connection's [`ZeroCopyStream`]
+/// straight from its file
+pub struct PlaceholderBody {
+    slot: ZeroCopySlot,
+    /// Queued on the slot with the first chunk
+    file: Option<QueuedFile>,
+    remaining: u64,
+}
+
+impl Body for PlaceholderBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        if let Some(file) = self.file.take() {
+            self.slot.0.lock().push_back(file);
+        }
+
+        let chunk = self.remaining.min(PLACEHOLDER_LEN as u64) as usize;
+        self.remaining -= chunk as u64;
+        Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(
+            &PLACEHOLDER[..chunk],
+        )))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// A TCP connection that sends placeholder chunks from their files
+pub struct ZeroCopyStream {
+    stream: TcpStream,
+    slot: Option<ZeroCopySlot>,
+}
+
+impl ZeroCopyStream {
+    /// Wrap `stream`; until [`enable`](Self::enable) it writes as is
+    pub fn new(stream: TcpStream) -> Self {
+        Self { stream, slot: None }
+    }
+
+    /// Start sending placeholders; `None` where sendfile(2) is unavailable
+    pub fn enable(&mut self) -> Option<ZeroCopySlot> {
+        if !SUPPORTED {
+            return None;
+        }
+        Some(self.slot.get_or_insert_with(ZeroCopySlot::default).clone())
+    }
+
+    /// Send up to `len` bytes of the file at the front of the queue
+    fn poll_send_file(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<io::Result<usize>> {
+        let Some(slot) = &self.slot else {
+            return Poll::Ready(Err(unaccounted()));
+        };
+        let mut queue = slot.0.lock();
+        let Some(front) = queue.front_mut() else {
+            return Poll::Ready(Err(unaccounted()));
+        };
+        let len = len.min(front.remaining as usize);
+
+        loop {
+            ready!(self.stream.poll_write_ready(cx))?;
+            let sent = self.stream.try_io(tokio::io::Interest::WRITABLE, || {
+                sendfile(&self.stream, &front.file, &mut front.offset, len)
+            });
+            match sent {
+                Ok(0) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "file shrank while being sent",
+                    )))
+                }
+                Ok(n) => {
+                    front.remaining -= n as u64;
+                    if front.remaining == 0 {
+                        queue.pop_front();
+                    }
+                    return Poll::Ready(Ok(n));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+/// A placeholder with no file behind it must fail the connection rather
+/// than send zeroes
+fn unaccounted() -> io::Error {
+    io::Error::other("placeholder body without a queued file")
+}
+
+#[cfg(target_os = "linux")]
+fn sendfile(socket: &TcpStream, file: &File, offset: &mut i64, len: usize) -> io::Result<usize> {
+    let mut off = *offset as nix::libc::off_t;
+    let sent = nix::sys::sendfile::sendfile(socket, file, Some(&mut off), len)?;
+    *offset = off as i64;
+    Ok(sent)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sendfile(_: &TcpStream, _: &File, _: &mut i64, _: usize) -> io::Result<usize> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+impl AsyncRead for ZeroCopyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ZeroCopyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if is_placeholder(buf) {
+            return self.poll_send_file(cx, buf.len());
+        }
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    /// Write the slices before the first placeholder, or send the file
+    /// behind a placeholder that comes first
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match bufs.iter().position(|buf| is_placeholder(buf)) {
+            Some(0) => self.poll_send_file(cx, bufs[0].len()),
+            Some(first) => Pin::new(&mut self.stream).poll_write_vectored(cx, &bufs[..first]),
+            None => Pin::new(&mut self.stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use std::io::Write;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_placeholders_are_sent_from_files() {
+        let contents: Vec<u8> = (0..PLACEHOLDER_LEN * 2 + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&contents).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (client, accepted) = tokio::join!(client, listener.accept());
+        let mut client = client.unwrap();
+        let mut stream = ZeroCopyStream::new(accepted.unwrap().0);
+        let slot = stream.enable().unwrap();
+
+        // Two bodies on one connection: 10 bytes in, then the rest
+        let first = slot.body(file.try_clone().unwrap(), 10, 100);
+        let second = slot.body(file, 110, contents.len() as u64 - 110);
+        let mut chunks = vec![Bytes::from_static(b"head|")];
+        for body in [first, second] {
+            let mut body = std::pin::pin!(body);
+            while let Some(frame) = body.frame().await {
+                chunks.push(frame.unwrap().into_data().unwrap());
+            }
+            chunks.push(Bytes::from_static(b"|"));
+        }
+
+        let writer = tokio::spawn(async move {
+            for mut chunk in chunks {
+                while !chunk.is_empty() {
+                    let n = stream
+                        .write_vectored(&[IoSlice::new(&chunk)])
+                        .await
+                        .unwrap();
+                    let _ = chunk.split_to(n);
+                }
+            }
+            stream.shutdown().await.unwrap();
+        });
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+
+        let mut expected = b"head|".to_vec();
+        expected.extend_from_slice(&contents[10..110]);
+        expected.push(b'|');
+        expected.extend_from_slice(&contents[110..]);
+        expected.push(b'|');
+        assert_eq!(received.len(), expected.len());
+        assert!(received == expected);
+    }
+
+    #[tokio::test]
+    async fn test_placeholder_without_file_fails() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (_client, accepted) = tokio::join!(client, listener.accept());
+        let mut stream = ZeroCopyStream::new(accepted.unwrap().0);
+        assert!(stream.write(&PLACEHOLDER[..16]).await.is_err());
+
+        stream.enable().unwrap();
+        assert!(stream.write(&PLACEHOLDER[..16]).await.is_err());
+        assert_eq!(stream.write(b"plain").await.unwrap(), 5);
+    }
+}
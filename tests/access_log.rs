@@ -0,0 +1,193 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    log_path: PathBuf,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(logging: &str) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let log_path = config_dir.path().join("access.log");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\naccess_log = \"{}\"\n\n[php]\nenable = false\n\n[logging]\n{}\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr,
+            log_path.to_string_lossy(),
+            logging,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            log_path,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn requests_are_written_to_the_access_log() -> Result<()> {
+    let server = TestServer::start(
+        r#"format = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\" %D""#,
+    )
+    .await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let status = get(&client, server.addr, "/hello.txt?x=1").await?;
+    assert_eq!(status, StatusCode::OK);
+    let status = get(&client, server.addr, "/missing").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let log = wait_for_line(&server.log_path, "/missing").await?;
+    let hello = log
+        .lines()
+        .find(|line| line.contains("/hello.txt"))
+        .context("no line for /hello.txt")?;
+    assert!(hello.starts_with("127.0.0.1 - - ["), "{}", hello);
+    assert!(
+        hello.contains("\"GET /hello.txt?x=1 HTTP/1.1\" 200 5 \"-\" \"test-agent\" "),
+        "{}",
+        hello
+    );
+    let micros = hello.rsplit(' ').next().unwrap_or_default();
+    assert!(micros.parse::<u64>().is_ok(), "{}", hello);
+
+    let missing = log
+        .lines()
+        .find(|line| line.contains("/missing"))
+        .context("no line for /missing")?;
+    assert!(
+        missing.contains("\"GET /missing HTTP/1.1\" 404 "),
+        "{}",
+        missing
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn json_access_log() -> Result<()> {
+    let server = TestServer::start(r#"format = "json""#).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    get(&client, server.addr, "/hello.txt").await?;
+    let log = wait_for_line(&server.log_path, "/hello.txt").await?;
+    let line = log
+        .lines()
+        .find(|line| line.contains("/hello.txt"))
+        .context("no line for /hello.txt")?;
+    let value: serde_json::Value = serde_json::from_str(line).context("parse log line")?;
+    assert_eq!(value["method"], "GET");
+    assert_eq!(value["uri"], "/hello.txt");
+    assert_eq!(value["status"], 200);
+    assert_eq!(value["body_bytes_sent"], 5);
+    assert_eq!(value["user_agent"], "test-agent");
+
+    Ok(())
+}
+
+/// Wait for the writer to flush a line containing `needle`
+async fn wait_for_line(path: &Path, needle: &str) -> Result<String> {
+    for _ in 0..50 {
+        let log = std::fs::read_to_string(path).unwrap_or_default();
+        if log.contains(needle) {
+            return Ok(log);
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    Err(anyhow::anyhow!(
+        "{} never logged {}",
+        path.display(),
+        needle
+    ))
+}
+
+async fn get(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<StatusCode> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("User-Agent", "test-agent")
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    response.into_body().collect().await.context("read body")?;
+    Ok(status)
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
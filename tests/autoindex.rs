@@ -0,0 +1,219 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderMap, CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir_all(docroot.path().join("files/sub")).context("create files dir")?;
+        std::fs::create_dir_all(docroot.path().join("site")).context("create site dir")?;
+        for (path, contents) in [
+            ("files/report.pdf", "%PDF"),
+            ("files/<script>.txt", "x"),
+            ("files/.htpasswd", "admin:secret"),
+            ("site/index.html", "<h1>site index</h1>"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\n\n[[virtualhost]]\ndomain = \"list.test\"\nroot = \"{}\"\nindex = [\"index.html\"]\nautoindex = true\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr, root, root
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn directories_are_listed_when_enabled() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, body) = get(&client, server.addr, "list.test", "/files/").await?;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(headers[CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(headers[CACHE_CONTROL], "no-cache");
+    assert!(body.contains("<a href=\"/\">../</a>"), "{}", body);
+    assert!(
+        body.contains("<a href=\"/files/sub/\">sub/</a>"),
+        "{}",
+        body
+    );
+    assert!(body.contains("<a href=\"/files/report.pdf\">report.pdf</a>"));
+    assert!(body.contains("&lt;script&gt;.txt"), "{}", body);
+    assert!(!body.contains("<script>"));
+    assert!(!body.contains("htpasswd"));
+
+    // Without the trailing slash the client is sent to the directory URL
+    let (status, headers, _) = get(&client, server.addr, "list.test", "/files").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[LOCATION], "http://list.test/files/");
+
+    // Index files still win
+    let (status, _, body) = get(&client, server.addr, "list.test", "/site/").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("site index"));
+
+    // Listings are never replayed from the page cache
+    std::fs::write(server._docroot.path().join("files/new.txt"), "new")?;
+    let (_, headers, body) = get(&client, server.addr, "list.test", "/files/").await?;
+    assert!(body.contains("new.txt"), "{}", body);
+    assert!(headers.get("x-cache").is_none_or(|value| value != "HIT"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn directories_redirect_to_trailing_slash() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, _) =
+        get(&client, server.addr, "other.test", "/site?page=2&x=%20").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[LOCATION], "http://other.test/site/?page=2&x=%20");
+
+    let (status, headers, _) = get(&client, server.addr, "other.test:8443", "/files/sub").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[LOCATION], "http://other.test:8443/files/sub/");
+
+    // A Host that isn't a plain host[:port] gets a relative Location
+    let (status, headers, _) = get(&client, server.addr, "evil@other.test", "/site").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers[LOCATION], "/site/");
+
+    // Files and internal endpoints are never redirected
+    let (status, headers, body) =
+        get(&client, server.addr, "other.test", "/files/report.pdf").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers.get(LOCATION).is_none());
+    assert_eq!(body, "%PDF");
+    let (status, _, _) = get(&client, server.addr, "other.test", "/health").await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, body) = get(&client, server.addr, "other.test", "/site/").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("site index"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn listing_is_denied_by_default() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, _, body) = get(&client, server.addr, "other.test", "/files/").await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body.contains("Directory listing denied"));
+    assert!(!body.contains("report.pdf"));
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -22,6 +22,11 @@ struct TestServer {
 
 impl TestServer {
     async fn start() -> Result<Self> {
+        Self::start_with_cache("").await
+    }
+
+    /// Start with extra `[cache]` settings
+    async fn start_with_cache(cache: &str) -> Result<Self> {
         let docroot = tempfile::tempdir().context("create temp docroot")?;
         std::fs::create_dir_all(docroot.path().join("catalog")).context("create catalog dir")?;
         std::fs::write(docroot.path().join("catalog").join("a.html"), "<h1>A</h1>")
@@ -33,8 +38,9 @@ impl TestServer {
         let config_dir = tempfile::tempdir().context("create temp config dir")?;
         let config_path = config_dir.path().join("veloserve.toml");
         let config_toml = format!(
-            "[server]\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            "[server]\nallow_root = true\nlisten = \"{}\"\ntrusted_proxies = [\"127.0.0.1\"]\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n{}\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
             addr,
+            cache,
             docroot.path().to_string_lossy()
         );
         std::fs::write(&config_path, config_toml).context("write config file")?;
@@ -212,6 +218,240 @@ async fn cache_warm_endpoint_processes_queue_and_populates_cache() -> Result<()>
     Ok(())
 }
 
+#[tokio::test]
+async fn cached_entries_keep_status_and_headers() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    warm_path(&client, server.addr, "/catalog/a.html").await?;
+
+    let entry = get_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/entry?domain=example.test&path=/catalog/a.html",
+    )
+    .await?;
+    assert_eq!(entry.status, StatusCode::OK);
+    assert_eq!(entry.body["status"], 200);
+    assert_eq!(entry.body["body_bytes"], 10);
+    assert!(entry.body["size_bytes"].as_u64().unwrap_or(0) > 10);
+    let headers = entry.body["headers"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    assert!(headers
+        .iter()
+        .any(|header| header[0] == "content-type" && header[1] == "text/html; charset=utf-8"));
+    assert!(!headers.iter().any(|header| header[0] == "x-cache"));
+
+    let missing = get_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/entry?domain=example.test&path=/catalog/missing.html",
+    )
+    .await?;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_status_headers_can_be_hidden() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let miss = get_path(&client, server.addr, "/catalog/a.html").await?;
+    assert_eq!(miss.cache_header.as_deref(), Some("MISS"));
+    assert_eq!(miss.headers["x-cache-ttl"], "3600");
+    let hit = get_path(&client, server.addr, "/catalog/a.html").await?;
+    assert_eq!(hit.cache_header.as_deref(), Some("HIT"));
+    let age: u64 = hit.headers["age"].to_str()?.parse()?;
+    let ttl: u64 = hit.headers["x-cache-ttl"].to_str()?.parse()?;
+    assert!(age <= 2 && ttl + age == 3600, "age {} ttl {}", age, ttl);
+    let bypass = get_path(&client, server.addr, "/catalog/a.html?preview=1").await?;
+    assert_eq!(bypass.cache_header.as_deref(), Some("BYPASS"));
+
+    let quiet = TestServer::start_with_cache("debug_headers = false").await?;
+    for _ in 0..2 {
+        let page = get_path(&client, quiet.addr, "/catalog/a.html").await?;
+        assert_eq!(page.status, StatusCode::OK);
+        for name in ["x-cache", "x-cache-ttl", "age"] {
+            assert!(!page.headers.contains_key(name), "{}", name);
+        }
+    }
+    // Still cached, just not advertised
+    let entry = get_json(
+        &client,
+        quiet.addr,
+        "/api/v1/cache/entry?domain=example.test&path=/catalog/a.html",
+    )
+    .await?;
+    assert_eq!(entry.status, StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_pages_are_not_cached() -> Result<()> {
+    // Both pages are 10 bytes
+    let server = TestServer::start_with_cache("max_entry_size = \"8\"").await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    for _ in 0..2 {
+        let page = get_path(&client, server.addr, "/catalog/a.html").await?;
+        assert_eq!(page.status, StatusCode::OK);
+        assert_eq!(page.cache_header.as_deref(), Some("BYPASS(size)"));
+    }
+
+    let stats = get_json(&client, server.addr, "/api/v1/cache/stats").await?;
+    assert_eq!(stats.body["cache"]["skipped_too_large"], 2);
+    assert_eq!(stats.body["cache"]["entries"], 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn purge_api_takes_urls_and_prefixes() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    warm_path(&client, server.addr, "/catalog/a.html").await?;
+    warm_path(&client, server.addr, "/catalog/b.html").await?;
+
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/purge?url=http%3A%2F%2Fexample.test%2Fcatalog%2Fa.html",
+        &json!({}),
+        &[],
+    )
+    .await?;
+    assert_eq!(response.status, StatusCode::OK);
+    let a = get_path(&client, server.addr, "/catalog/a.html").await?;
+    assert_eq!(a.cache_header.as_deref(), Some("MISS"));
+    let b = get_path(&client, server.addr, "/catalog/b.html").await?;
+    assert_eq!(b.cache_header.as_deref(), Some("HIT"));
+
+    warm_path(&client, server.addr, "/catalog/a.html").await?;
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/purge?prefix=/catalog/&domain=example.test",
+        &json!({}),
+        &[],
+    )
+    .await?;
+    assert_eq!(response.status, StatusCode::OK);
+    for path in ["/catalog/a.html", "/catalog/b.html"] {
+        let page = get_path(&client, server.addr, path).await?;
+        assert_eq!(page.cache_header.as_deref(), Some("MISS"), "{}", path);
+    }
+
+    // A bare path names no host
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/purge?prefix=/catalog/",
+        &json!({}),
+        &[],
+    )
+    .await?;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn purge_api_clears_a_domain() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    warm_path(&client, server.addr, "/catalog/a.html").await?;
+    warm_path(&client, server.addr, "/catalog/b.html").await?;
+
+    // Any case, with or without a port
+    let response = post_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/purge?domain=Example.TEST:8080",
+        &json!({}),
+        &[],
+    )
+    .await?;
+    assert_eq!(response.status, StatusCode::OK);
+    for path in ["/catalog/a.html", "/catalog/b.html"] {
+        let page = get_path(&client, server.addr, path).await?;
+        assert_eq!(page.cache_header.as_deref(), Some("MISS"), "{}", path);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_stats_break_down_by_host() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    warm_path(&client, server.addr, "/catalog/a.html").await?;
+    warm_path(&client, server.addr, "/catalog/b.html").await?;
+
+    let stats = get_json(&client, server.addr, "/api/v1/cache/stats").await?;
+    assert_eq!(stats.status, StatusCode::OK);
+    let host = &stats.body["cache"]["hosts"]["example.test"];
+    assert_eq!(host["entries"], 2);
+    assert!(host["size_bytes"].as_u64().unwrap_or(0) > 0);
+    assert_eq!(host["hits"], 2);
+    assert_eq!(host["misses"], 2);
+    assert!(stats.body["cache"]["tags"].is_object());
+
+    let stats = get_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/stats?domain=other.test",
+    )
+    .await?;
+    assert_eq!(stats.body["cache"]["hosts"], json!({}));
+    assert_eq!(stats.body["cache"]["entries"], 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn purge_method_drops_one_page() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    warm_path(&client, server.addr, "/catalog/a.html").await?;
+    warm_path(&client, server.addr, "/catalog/b.html").await?;
+
+    // A client outside cache.purge_allow, forwarded by a trusted proxy
+    let refused = purge(&client, server.addr, "/catalog/a.html", Some("203.0.113.9")).await?;
+    assert_eq!(refused.status, StatusCode::METHOD_NOT_ALLOWED);
+    let page = get_path(&client, server.addr, "/catalog/a.html").await?;
+    assert_eq!(page.cache_header.as_deref(), Some("HIT"));
+
+    let response = purge(&client, server.addr, "/catalog/a.html", None).await?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["purged"], true);
+    let page = get_path(&client, server.addr, "/catalog/a.html").await?;
+    assert_eq!(page.cache_header.as_deref(), Some("MISS"));
+    let page = get_path(&client, server.addr, "/catalog/b.html").await?;
+    assert_eq!(page.cache_header.as_deref(), Some("HIT"));
+
+    let response = purge(&client, server.addr, "/catalog/missing.html", None).await?;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["purged"], false);
+
+    Ok(())
+}
+
 struct HttpResult {
     status: StatusCode,
     body: Value,
@@ -220,6 +460,7 @@ struct HttpResult {
 struct PageResult {
     status: StatusCode,
     cache_header: Option<String>,
+    headers: hyper::HeaderMap,
 }
 
 async fn post_json(
@@ -279,6 +520,7 @@ async fn get_path(
         .get("X-Cache")
         .and_then(|v| v.to_str().ok())
         .map(|v| v.to_string());
+    let headers = response.headers().clone();
     let _ = response
         .into_body()
         .collect()
@@ -287,9 +529,41 @@ async fn get_path(
     Ok(PageResult {
         status,
         cache_header,
+        headers,
     })
 }
 
+async fn purge(
+    client: &Client<HttpConnector, Full<Bytes>>,
+    addr: SocketAddr,
+    path: &str,
+    forwarded_for: Option<&str>,
+) -> Result<HttpResult> {
+    let mut builder = Request::builder()
+        .method(Method::from_bytes(b"PURGE")?)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "example.test");
+    if let Some(client) = forwarded_for {
+        builder = builder.header("X-Forwarded-For", client);
+    }
+    let request = builder
+        .body(Full::new(Bytes::new()))
+        .context("build PURGE request")?;
+    let response = client
+        .request(request)
+        .await
+        .context("execute PURGE request")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read PURGE body")?
+        .to_bytes();
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    Ok(HttpResult { status, body })
+}
+
 async fn get_json(
     client: &Client<HttpConnector, Full<Bytes>>,
     addr: SocketAddr,
@@ -0,0 +1,240 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+const SNAPSHOT: &str = "memory-cache.vsstate";
+
+/// A docroot and a cache directory shared by successive server runs
+struct Site {
+    docroot: TempDir,
+    cache_dir: TempDir,
+}
+
+impl Site {
+    fn new(page: &str) -> Result<Self> {
+        let site = Self {
+            docroot: tempfile::tempdir().context("create temp docroot")?,
+            cache_dir: tempfile::tempdir().context("create temp cache dir")?,
+        };
+        site.write_page(page)?;
+        Ok(site)
+    }
+
+    fn write_page(&self, page: &str) -> Result<()> {
+        std::fs::write(self.docroot.path().join("page.html"), page).context("write page.html")
+    }
+
+    fn snapshot(&self) -> PathBuf {
+        self.cache_dir.path().join(SNAPSHOT)
+    }
+}
+
+struct TestServer {
+    addr: SocketAddr,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(site: &Site, persist_interval: u64) -> Result<Self> {
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\n\n[php]\nenable = false\n\n\
+             [cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n\
+             disk_path = \"{cache}\"\npersist = true\npersist_interval = {persist_interval}\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            addr = addr,
+            cache = site.cache_dir.path().to_string_lossy(),
+            persist_interval = persist_interval,
+            root = site.docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    /// Send SIGTERM and wait for the server to exit
+    async fn terminate(mut self) -> Result<()> {
+        kill(Pid::from_raw(self.child.id() as i32), Signal::SIGTERM).context("send SIGTERM")?;
+        for _ in 0..100 {
+            if let Some(status) = self.child.try_wait()? {
+                anyhow::ensure!(status.success(), "server exited with {}", status);
+                return Ok(());
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        Err(anyhow::anyhow!("server did not exit after SIGTERM"))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn cache_is_restored_after_a_graceful_restart() -> Result<()> {
+    let site = Site::new("<h1>before</h1>")?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let server = TestServer::start(&site, 0).await?;
+    let (_, cache, _) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    let (_, cache, _) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    server.terminate().await?;
+    assert!(site.snapshot().exists());
+
+    // Only a restored entry can still answer with the old page
+    site.write_page("<h1>after</h1>")?;
+    let server = TestServer::start(&site, 0).await?;
+    let (status, cache, body) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(body, Bytes::from_static(b"<h1>before</h1>"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn periodic_snapshots_survive_a_crash() -> Result<()> {
+    let site = Site::new("<h1>before</h1>")?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let server = TestServer::start(&site, 1).await?;
+    get(&client, server.addr, "/page.html").await?;
+    wait_for_file(&site.snapshot()).await?;
+    // SIGKILL leaves no chance to save at shutdown
+    drop(server);
+
+    site.write_page("<h1>after</h1>")?;
+    let server = TestServer::start(&site, 1).await?;
+    let (_, cache, body) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(body, Bytes::from_static(b"<h1>before</h1>"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn corrupt_snapshot_starts_an_empty_cache() -> Result<()> {
+    let site = Site::new("<h1>page</h1>")?;
+    std::fs::write(site.snapshot(), b"VSSTATE but not really").context("write snapshot")?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let server = TestServer::start(&site, 0).await?;
+    let (status, cache, _) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cache.as_deref(), Some("MISS"));
+
+    // The next shutdown replaces it with a good one
+    server.terminate().await?;
+    let server = TestServer::start(&site, 0).await?;
+    let (_, cache, _) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+) -> Result<(StatusCode, Option<String>, Bytes)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "example.test")
+        .body(Empty::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let cache = response
+        .headers()
+        .get("x-cache")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, cache, body))
+}
+
+async fn wait_for_file(path: &Path) -> Result<()> {
+    for _ in 0..60 {
+        if path.exists() {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    Err(anyhow::anyhow!("{} was never written", path.display()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Empty::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
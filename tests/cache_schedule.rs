@@ -0,0 +1,182 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::Value;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Full<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("news.html"), "<h1>news</h1>")
+            .context("write news.html")?;
+        std::fs::write(docroot.path().join("shop.html"), "<h1>shop</h1>")
+            .context("write shop.html")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n\n[[cache.schedule]]\nname = \"short-news\"\ncron = \"* * * * * *\"\naction = \"set_ttl:/news*:42\"\nduration = 600\n\n[[cache.schedule]]\nname = \"tag-sweep\"\ncron = \"*/1 * * * * *\"\naction = \"purge_tag:campaign\"\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn schedules_run_every_second_and_override_ttl() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Both schedules fire every second; wait for at least two runs each
+    let mut schedules = Vec::new();
+    for _ in 0..50 {
+        let status = get_json(&client, server.addr, "/api/v1/status").await?;
+        schedules = status["cache_schedule"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        if schedules.len() == 2 && schedules.iter().all(|job| job["runs"].as_u64() >= Some(2)) {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(schedules.len(), 2, "schedules: {:?}", schedules);
+    assert_eq!(schedules[0]["name"], "short-news");
+    assert!(schedules[0]["active_until"].is_string());
+    assert_eq!(schedules[1]["name"], "tag-sweep");
+    assert_eq!(
+        schedules[1]["last_result"],
+        "purged 0 entries tagged 'campaign'"
+    );
+    for job in &schedules {
+        assert!(job["runs"].as_u64() >= Some(2), "job did not run: {}", job);
+    }
+
+    // Pages under the window get the overridden TTL, others the default
+    for path in ["/news.html", "/shop.html"] {
+        let status = get_status(&client, server.addr, path).await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let news = get_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/entry?domain=example.test&path=/news.html",
+    )
+    .await?;
+    assert_eq!(news["ttl"], 42);
+    let shop = get_json(
+        &client,
+        server.addr,
+        "/api/v1/cache/entry?domain=example.test&path=/shop.html",
+    )
+    .await?;
+    assert_eq!(shop["ttl"], 3600);
+
+    Ok(())
+}
+
+async fn get_status(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<StatusCode> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "example.test")
+        .body(Full::new(Bytes::new()))
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    Ok(response.status())
+}
+
+async fn get_json(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<Value> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "example.test")
+        .body(Full::new(Bytes::new()))
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok(serde_json::from_slice(&body)?)
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
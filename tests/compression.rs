@@ -0,0 +1,207 @@
+#![cfg(unix)]
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH, ETAG, VARY};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary printing a page of repeated markup
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\n'\nfor i in $(seq 1 200); do printf '<p>from php</p>'; done\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    /// `compression` is the `[server.compression]` table body
+    async fn start(compression: &str) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for (path, contents) in [
+            ("app.css", "body { color: red; }\n".repeat(100)),
+            ("small.css", "body { color: red; }\n".to_string()),
+            ("photo.png", "\u{89}PNG not really".repeat(200)),
+            ("page.php", "<?php echo 'from php';".to_string()),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[server.compression]\n{}\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr,
+            compression,
+            php_path.to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn text_responses_are_compressed() -> Result<()> {
+    let server = TestServer::start("level = 5").await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Static files: brotli is preferred, and the ETag becomes weak
+    let (status, headers, body) = get(&client, server.addr, "/app.css", "gzip, br").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_ENCODING], "br");
+    assert_eq!(headers[VARY], "Accept-Encoding");
+    assert_eq!(headers[CONTENT_LENGTH], body.len().to_string());
+    let etag = headers[ETAG].to_str()?;
+    assert!(
+        etag.starts_with("W/\"") && etag.ends_with("-br\""),
+        "{}",
+        etag
+    );
+    let mut css = String::new();
+    brotli::Decompressor::new(&body[..], 4096).read_to_string(&mut css)?;
+    assert_eq!(css, "body { color: red; }\n".repeat(100));
+
+    // PHP output
+    let (status, headers, body) = get(&client, server.addr, "/page.php", "gzip").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_ENCODING], "gzip");
+    let mut html = String::new();
+    flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut html)?;
+    assert_eq!(html, "<p>from php</p>".repeat(200));
+
+    // Clients that don't ask get the identity body
+    let (_, headers, body) = get(&client, server.addr, "/app.css", "identity").await?;
+    assert!(!headers.contains_key(CONTENT_ENCODING));
+    assert_eq!(body.len(), 2100);
+
+    // Small bodies and compressed media are left alone
+    for path in ["/small.css", "/photo.png"] {
+        let (status, headers, _) = get(&client, server.addr, path, "gzip, br").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(CONTENT_ENCODING), "{}", path);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn compression_follows_config() -> Result<()> {
+    let server = TestServer::start("algorithms = [\"gzip\"]\nmin_size = 10").await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (_, headers, _) = get(&client, server.addr, "/small.css", "br, gzip").await?;
+    assert_eq!(headers[CONTENT_ENCODING], "gzip");
+    let (_, headers, _) = get(&client, server.addr, "/app.css", "br").await?;
+    assert!(!headers.contains_key(CONTENT_ENCODING));
+    drop(server);
+
+    let server = TestServer::start("enable = false").await?;
+    let (_, headers, _) = get(&client, server.addr, "/page.php", "br, gzip").await?;
+    assert!(!headers.contains_key(CONTENT_ENCODING));
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+    accept_encoding: &str,
+) -> Result<(StatusCode, HeaderMap, Bytes)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Accept-Encoding", accept_encoding)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, body))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
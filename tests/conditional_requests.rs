@@ -0,0 +1,344 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{
+    HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    IF_UNMODIFIED_SINCE, LAST_MODIFIED, RANGE,
+};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir_all(docroot.path().join("docs")).context("create docs dir")?;
+        for (path, contents) in [
+            ("app.css", "body { color: red; }\n".repeat(100)),
+            ("docs/index.html", "<h1>docs</h1>".to_string()),
+            ("bundle.js", "console.log('bundle');\n".repeat(100)),
+            ("bundle.js.br", "not really brotli".to_string()),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\nprecompressed = true\n",
+            addr,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn revalidation_returns_304() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Plain files and index files resolved from a directory
+    for path in ["/app.css", "/docs/"] {
+        let (status, headers, body) = get(&client, server.addr, path, &[]).await?;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert!(!body.is_empty());
+        let etag = headers[ETAG].to_str()?.to_string();
+        let last_modified = headers[LAST_MODIFIED].to_str()?.to_string();
+
+        let (status, headers, body) =
+            get(&client, server.addr, path, &[(IF_NONE_MATCH, &etag)]).await?;
+        assert_eq!(status, StatusCode::NOT_MODIFIED, "{}", path);
+        assert_eq!(headers[ETAG], etag.as_str());
+        assert!(body.is_empty());
+
+        let (status, _, body) = get(
+            &client,
+            server.addr,
+            path,
+            &[(IF_MODIFIED_SINCE, &last_modified)],
+        )
+        .await?;
+        assert_eq!(status, StatusCode::NOT_MODIFIED, "{}", path);
+        assert!(body.is_empty());
+    }
+
+    // Lists, wildcards and stale tags
+    let (_, headers, _) = get(&client, server.addr, "/app.css", &[]).await?;
+    let etag = headers[ETAG].to_str()?.to_string();
+    let list = format!("\"old\", {}", etag);
+    for (if_none_match, expected) in [
+        (list.as_str(), StatusCode::NOT_MODIFIED),
+        ("*", StatusCode::NOT_MODIFIED),
+        ("\"old\"", StatusCode::OK),
+    ] {
+        let (status, _, _) = get(
+            &client,
+            server.addr,
+            "/app.css",
+            &[(IF_NONE_MATCH, if_none_match)],
+        )
+        .await?;
+        assert_eq!(status, expected, "{}", if_none_match);
+    }
+
+    // A conditional range revalidates first
+    let (status, _, body) = get(
+        &client,
+        server.addr,
+        "/app.css",
+        &[(IF_NONE_MATCH, &etag), (RANGE, "bytes=0-9")],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_preconditions_return_412() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (_, headers, _) = get(&client, server.addr, "/app.css", &[]).await?;
+    let etag = headers[ETAG].to_str()?.to_string();
+    let last_modified = headers[LAST_MODIFIED].to_str()?.to_string();
+
+    for (name, value, expected) in [
+        (IF_MATCH, etag.as_str(), StatusCode::OK),
+        (IF_MATCH, "\"old\"", StatusCode::PRECONDITION_FAILED),
+        (IF_UNMODIFIED_SINCE, last_modified.as_str(), StatusCode::OK),
+        (
+            IF_UNMODIFIED_SINCE,
+            "Sat, 01 Jan 2000 00:00:00 GMT",
+            StatusCode::PRECONDITION_FAILED,
+        ),
+    ] {
+        let (status, headers, body) =
+            get(&client, server.addr, "/app.css", &[(name.clone(), value)]).await?;
+        assert_eq!(status, expected, "{}: {}", name, value);
+        if expected == StatusCode::PRECONDITION_FAILED {
+            assert_eq!(headers[ETAG], etag.as_str());
+            assert!(body.is_empty());
+        }
+    }
+
+    // If-Match is checked before If-None-Match and Range
+    let (status, _, _) = get(
+        &client,
+        server.addr,
+        "/app.css",
+        &[
+            (IF_MATCH, "\"old\""),
+            (IF_NONE_MATCH, &etag),
+            (RANGE, "bytes=0-9"),
+        ],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    let (status, _, body) = get(
+        &client,
+        server.addr,
+        "/app.css",
+        &[(IF_MATCH, &etag), (RANGE, "bytes=0-9")],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body.len(), 10);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn compressed_copies_revalidate() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, _) = get(
+        &client,
+        server.addr,
+        "/app.css",
+        &[(ACCEPT_ENCODING, "gzip")],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_ENCODING], "gzip");
+    let etag = headers[ETAG].to_str()?.to_string();
+
+    let (status, headers, body) = get(
+        &client,
+        server.addr,
+        "/app.css",
+        &[(ACCEPT_ENCODING, "gzip"), (IF_NONE_MATCH, &etag)],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers[ETAG], etag.as_str());
+    assert!(body.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn head_and_precompressed_requests_revalidate() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // HEAD gets the same answer as GET
+    let (_, headers, _) = get(&client, server.addr, "/app.css", &[]).await?;
+    let etag = headers[ETAG].to_str()?.to_string();
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::HEAD,
+        "/app.css",
+        &[(IF_NONE_MATCH, &etag)],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers[ETAG], etag.as_str());
+    assert!(body.is_empty());
+
+    // The tag of a precompressed sibling revalidates that sibling
+    let (status, headers, _) = get(
+        &client,
+        server.addr,
+        "/bundle.js",
+        &[(ACCEPT_ENCODING, "br")],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_ENCODING], "br");
+    let etag = headers[ETAG].to_str()?.to_string();
+    assert!(etag.ends_with("-br\""), "{}", etag);
+    let (status, headers, _) = get(
+        &client,
+        server.addr,
+        "/bundle.js",
+        &[(ACCEPT_ENCODING, "br"), (IF_NONE_MATCH, &etag)],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers[ETAG], etag.as_str());
+
+    // ... and a client without the copy still gets the file
+    let (status, _, body) = get(&client, server.addr, "/bundle.js", &[]).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.is_empty());
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+    headers: &[(hyper::header::HeaderName, &str)],
+) -> Result<(StatusCode, HeaderMap, Bytes)> {
+    request(client, addr, Method::GET, path, headers).await
+}
+
+async fn request(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    headers: &[(hyper::header::HeaderName, &str)],
+) -> Result<(StatusCode, HeaderMap, Bytes)> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path));
+    for (name, value) in headers {
+        builder = builder.header(name, *value);
+    }
+    let request = builder
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, body))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,174 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    docroots: [TempDir; 2],
+    config_path: PathBuf,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroots = [
+            tempfile::tempdir().context("create first docroot")?,
+            tempfile::tempdir().context("create second docroot")?,
+        ];
+        for (docroot, body) in docroots.iter().zip(["first", "second"]) {
+            std::fs::write(docroot.path().join("hello.txt"), body).context("write hello.txt")?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+
+        write_config(&config_path, addr, &docroots[0])?;
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            docroots,
+            config_path,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    /// Point the catch-all vhost at the given docroot
+    fn use_docroot(&self, docroot: usize) -> Result<()> {
+        write_config(&self.config_path, self.addr, &self.docroots[docroot])
+    }
+
+    fn hangup(&self) -> Result<()> {
+        kill(Pid::from_raw(self.child.id() as i32), Signal::SIGHUP).context("send SIGHUP")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn sighup_reloads_the_configuration() -> Result<()> {
+    let mut server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, body) = get(&client, server.addr, "/hello.txt").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "first");
+
+    server.use_docroot(1)?;
+    server.hangup()?;
+    let mut body = String::new();
+    for _ in 0..40 {
+        (_, body) = get(&client, server.addr, "/hello.txt").await?;
+        if body == "second" {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(body, "second");
+
+    // A broken file is rejected and the server keeps running on the last one
+    std::fs::write(&server.config_path, "[[virtualhost]]\ndomain = \"*\"\n")
+        .context("write invalid config")?;
+    server.hangup()?;
+    sleep(Duration::from_millis(300)).await;
+    assert!(server.child.try_wait()?.is_none(), "server exited");
+    let (status, body) = get(&client, server.addr, "/hello.txt").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "second");
+
+    Ok(())
+}
+
+fn write_config(path: &Path, addr: SocketAddr, docroot: &TempDir) -> Result<()> {
+    let config_toml = format!(
+        "[server]\nallow_root = true\nlisten = \"{addr}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n\
+         [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+        addr = addr,
+        root = docroot.path().to_string_lossy(),
+    );
+    std::fs::write(path, config_toml).context("write config file")
+}
+
+async fn get(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,166 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::Value;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(max_connections: usize) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.html"), "<h1>ok</h1>")
+            .context("write index.html")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\nmax_connections = {}\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            max_connections,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        // Let the server notice the readiness client went away
+        sleep(Duration::from_millis(200)).await;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_are_closed() -> Result<()> {
+    let server = TestServer::start(2).await?;
+
+    // Two idle clients, slowloris-style, take every slot
+    let mut idle = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(server.addr).await?;
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: loc").await?;
+        idle.push(stream);
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // The next connection is closed without an answer
+    let mut rejected = TcpStream::connect(server.addr).await?;
+    let _ = rejected
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await;
+    let mut buf = [0u8; 64];
+    let read = timeout(Duration::from_secs(2), rejected.read(&mut buf))
+        .await
+        .context("rejected connection was left open")?;
+    assert!(matches!(read, Ok(0) | Err(_)), "got {:?}", read);
+
+    // Freeing a slot lets clients back in
+    idle.pop();
+    sleep(Duration::from_millis(100)).await;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let (status, metrics) = get_json(&client, server.addr, "/api/v1/metrics").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(metrics["connections"]["max"], 2, "{}", metrics);
+    assert_eq!(metrics["connections"]["in_flight"], 2, "{}", metrics);
+    assert!(
+        metrics["connections"]["rejected_total"].as_u64() >= Some(1),
+        "{}",
+        metrics
+    );
+
+    Ok(())
+}
+
+async fn get_json(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+) -> Result<(StatusCode, Value)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, serde_json::from_slice(&body)?))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
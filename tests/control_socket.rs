@@ -0,0 +1,174 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time::sleep;
+
+struct TestServer {
+    addr: SocketAddr,
+    control: PathBuf,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("page.html"), "<h1>Page</h1>")
+            .context("write page.html")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let control = config_dir.path().join("run").join("control.sock");
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\ncontrol_socket = \"{control}\"\n\n[php]\nenable = false\n\n\
+             [cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            addr = addr,
+            control = control.to_string_lossy(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            control,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(&server.control).await?;
+        Ok(server)
+    }
+
+    /// Run `veloserve cache <args> --socket <control>` and return its stdout
+    fn cli(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("cache")
+            .args(args)
+            .arg("--socket")
+            .arg(&self.control)
+            .output()
+            .context("run veloserve cache")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "veloserve cache {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn cli_reads_cache_stats_over_the_control_socket() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let mode = std::fs::metadata(&server.control)?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let stats = server.cli(&["stats"])?;
+    assert!(stats.contains("Entries: 0"), "{}", stats);
+    assert!(stats.contains("L1: 0 hits"), "{}", stats);
+
+    get(server.addr, "/page.html").await?;
+    get(server.addr, "/page.html").await?;
+
+    let stats = server.cli(&["stats"])?;
+    assert!(stats.contains("Entries: 1"), "{}", stats);
+    assert!(
+        stats.contains("L1: 1 hits, 1 misses, 1 writes"),
+        "{}",
+        stats
+    );
+    assert!(stats.contains("Hit Rate: 50.0%"), "{}", stats);
+
+    let purged = server.cli(&["purge", "--all"])?;
+    assert!(purged.contains("Purged 1 cache entries."), "{}", purged);
+
+    let stats = server.cli(&["stats"])?;
+    assert!(stats.contains("Entries: 0"), "{}", stats);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_control_commands_are_refused() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let mut stream = UnixStream::connect(&server.control).await?;
+    stream.write_all(b"shutdown\n").await?;
+    stream.shutdown().await?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await?;
+    let reply: serde_json::Value = serde_json::from_str(reply.trim())?;
+    assert_eq!(reply["ok"], false);
+    assert_eq!(reply["error"], "unknown command 'shutdown'");
+
+    Ok(())
+}
+
+/// A plain HTTP/1.1 GET, read to the end
+async fn get(addr: SocketAddr, path: &str) -> Result<String> {
+    let mut stream = TcpStream::connect(addr).await.context("connect")?;
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .as_bytes(),
+        )
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    anyhow::ensure!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    Ok(response)
+}
+
+async fn wait_until_ready(control: &Path) -> Result<()> {
+    for _ in 0..60 {
+        if UnixStream::connect(control).await.is_ok() {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!(
+        "control socket {} never came up",
+        control.display()
+    ))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,189 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir_all(docroot.path().join(".git"))?;
+        std::fs::create_dir_all(docroot.path().join(".well-known/acme-challenge"))?;
+        for (path, contents) in [
+            ("index.html", "home"),
+            (".env", "SECRET=1"),
+            (".git/config", "[core]"),
+            (".well-known/acme-challenge/token", "challenge"),
+            ("wp-config.php.bak", "<?php $password = 'x';"),
+            ("dump.SQL", "DROP TABLE users;"),
+            ("composer.lock", "{}"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"strict.test\"\nroot = \"{}\"\ndeny_files = [\"*.bak\", \"*.sql\", \"composer.lock\"]\n\n[[virtualhost]]\ndomain = \"open.test\"\nroot = \"{}\"\ndeny_dotfiles = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr, root, root, root
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn dotfiles_are_denied_by_default() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for path in ["/.env", "/.git/config", "/%2Eenv", "/.git/"] {
+        let (status, _) = get(&client, server.addr, "other.test", path).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", path);
+    }
+
+    // ACME challenges stay reachable
+    let (status, body) = get(
+        &client,
+        server.addr,
+        "other.test",
+        "/.well-known/acme-challenge/token",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "challenge");
+
+    // Without deny_files, other names are served
+    let (status, _) = get(&client, server.addr, "other.test", "/wp-config.php.bak").await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // ... and a vhost can turn the dotfile rule off
+    let (status, body) = get(&client, server.addr, "open.test", "/.env").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "SECRET=1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn deny_files_globs_ignore_case() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for path in [
+        "/wp-config.php.bak",
+        "/wp-config.php.BAK",
+        "/dump.SQL",
+        "/composer.lock",
+        "/.env",
+    ] {
+        let (status, _) = get(&client, server.addr, "strict.test", path).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", path);
+    }
+
+    let (status, body) = get(&client, server.addr, "strict.test", "/index.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "home");
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,183 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::HeaderMap;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::Value;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    root: PathBuf,
+    _base: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let base = tempfile::tempdir().context("create temp dir")?;
+        let root = base.path().join("site");
+        std::fs::create_dir(&root).context("create docroot")?;
+        std::fs::write(root.join("page.html"), "<h1>page</h1>").context("write page.html")?;
+        std::fs::write(root.join("other.html"), "<h1>other</h1>").context("write other.html")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\n\n[[virtualhost]]\ndomain = \"site.test\"\nroot = \"{}\"\nindex = [\"index.html\"]\n\n[virtualhost.cache]\nttl = 1\n",
+            addr,
+            root.to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            root,
+            _base: base,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn unmounted_docroot_answers_503_until_it_returns() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, _) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["x-cache"], "MISS");
+    let (status, _, _) = get(&client, server.addr, "/missing.html").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Simulate the mount going away
+    let away = server.root.with_file_name("site.unmounted");
+    std::fs::rename(&server.root, &away).context("unmount docroot")?;
+
+    for path in ["/other.html", "/missing.html", "/"] {
+        let (status, headers, _) = get(&client, server.addr, path).await?;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", path);
+        assert_eq!(headers["retry-after"], "10", "{}", path);
+    }
+
+    // Cached pages keep being served, past their TTL
+    sleep(Duration::from_millis(2100)).await;
+    let (status, headers, body) = get(&client, server.addr, "/page.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["x-cache"], "STALE");
+    assert_eq!(body, "<h1>page</h1>");
+
+    let status = get_json(&client, server.addr, "/api/v1/status").await?;
+    let degraded = status["degraded_docroots"]
+        .as_array()
+        .context("degraded list")?;
+    assert_eq!(degraded.len(), 1, "{}", status);
+    assert_eq!(degraded[0]["domain"], "site.test");
+    assert!(degraded[0]["failures"].as_u64() >= Some(4), "{}", status);
+
+    // Back again: the next request probes, recovers and is served normally
+    std::fs::rename(&away, &server.root).context("remount docroot")?;
+    let (status, _, body) = get(&client, server.addr, "/other.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<h1>other</h1>");
+    let (status, _, _) = get(&client, server.addr, "/missing.html").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let status = get_json(&client, server.addr, "/api/v1/status").await?;
+    assert_eq!(status["degraded_docroots"], serde_json::json!([]));
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "site.test")
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn get_json(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<Value> {
+    let (_, _, body) = get(client, addr, path).await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,208 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary whose scripts all claim success
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Status: 200 OK\\r\\nContent-Type: text/html\\r\\n\\r\\ncustom page from php'\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir_all(docroot.path().join("errors")).context("create errors dir")?;
+        std::fs::create_dir_all(docroot.path().join("private")).context("create private dir")?;
+        for (path, contents) in [
+            ("index.html", "<h1>home</h1>"),
+            ("errors/404.html", "<h1>custom not found</h1>"),
+            ("errors/403.php", "<?php echo 'custom page from php';"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"pages.test\"\nroot = \"{}\"\nindex = [\"index.html\"]\nerror_pages = {{ 404 = \"/errors/404.html\", 403 = \"/errors/403.php\" }}\n\n[[virtualhost]]\ndomain = \"broken.test\"\nroot = \"{}\"\nindex = [\"index.html\"]\nerror_pages = {{ 404 = \"/errors/gone.html\" }}\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            php_path.to_string_lossy(),
+            root,
+            root,
+            root
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn vhost_error_pages_keep_the_error_status() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, body) =
+        request(&client, server.addr, Method::GET, "pages.test", "/nope").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, "<h1>custom not found</h1>");
+    assert_eq!(headers[CONTENT_TYPE], "text/html; charset=utf-8");
+    assert!(!headers.contains_key("etag"));
+
+    let (status, headers, body) =
+        request(&client, server.addr, Method::HEAD, "pages.test", "/nope").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(headers["content-length"], "25");
+    assert!(body.is_empty());
+
+    // PHP error pages run, but can't turn the error into a success
+    let (status, _, body) =
+        request(&client, server.addr, Method::GET, "pages.test", "/private/").await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body, "custom page from php");
+
+    // Existing files are unaffected
+    let (status, _, body) = request(&client, server.addr, Method::GET, "pages.test", "/").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<h1>home</h1>");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_error_pages_fall_back_to_built_in() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, _, body) =
+        request(&client, server.addr, Method::GET, "broken.test", "/nope").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.contains("404 Not Found"), "{}", body);
+
+    // Vhosts without error_pages and the API keep the built-in pages
+    let (status, _, body) =
+        request(&client, server.addr, Method::GET, "other.test", "/nope").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.contains("404 Not Found"), "{}", body);
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "pages.test",
+        "/api/v1/nope",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!body.contains("custom not found"), "{}", body);
+
+    Ok(())
+}
+
+async fn request(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,184 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode, Version};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tempfile::TempDir;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(h2c: bool) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\nh2c_prior_knowledge = {h2c}\n\n[php]\nenable = false\n\n\
+             [cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"localhost\"\nroot = \"{root}\"\n",
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// GET each of `paths` over one HTTP/2 connection with prior knowledge
+async fn h2_get(addr: SocketAddr, paths: &[&str]) -> Result<Vec<(StatusCode, Version, Bytes)>> {
+    let tcp = TcpStream::connect(addr).await.context("connect")?;
+    let (mut sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tcp))
+            .await
+            .context("h2 handshake")?;
+    tokio::spawn(connection);
+
+    let mut responses = Vec::new();
+    for path in paths {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://localhost:{}{}", addr.port(), path))
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = sender.send_request(request).await.context("h2 request")?;
+        let (status, version) = (response.status(), response.version());
+        let body = response.into_body().collect().await?.to_bytes();
+        responses.push((status, version, body));
+    }
+    Ok(responses)
+}
+
+#[tokio::test]
+async fn prior_knowledge_h2_is_served_when_enabled() -> Result<()> {
+    let server = TestServer::start(true).await?;
+
+    // One connection, the vhost found through :authority
+    let responses = h2_get(server.addr, &["/health", "/hello.txt"]).await?;
+    assert_eq!(
+        responses,
+        vec![
+            (StatusCode::OK, Version::HTTP_2, Bytes::from("OK")),
+            (StatusCode::OK, Version::HTTP_2, Bytes::from("hello")),
+        ]
+    );
+
+    // HTTP/1.1 on the same listener is unaffected
+    let (status, version, body) = http1_get(server.addr, "/hello.txt", false).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(version, Version::HTTP_11);
+    assert_eq!(body, "hello");
+
+    // Only prior knowledge is offered: an upgrade request is answered over
+    // HTTP/1.1 rather than with 101
+    let (status, version, body) = http1_get(server.addr, "/hello.txt", true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(version, Version::HTTP_11);
+    assert_eq!(body, "hello");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn prior_knowledge_h2_is_refused_by_default() -> Result<()> {
+    let server = TestServer::start(false).await?;
+    assert!(h2_get(server.addr, &["/health"]).await.is_err());
+    Ok(())
+}
+
+async fn http1_get(
+    addr: SocketAddr,
+    path: &str,
+    upgrade: bool,
+) -> Result<(StatusCode, Version, Bytes)> {
+    let client: Client<_, Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let mut request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "localhost");
+    if upgrade {
+        request = request
+            .header("Connection", "Upgrade, HTTP2-Settings")
+            .header("Upgrade", "h2c")
+            .header("HTTP2-Settings", "AAMAAABkAARAAAAAAAIAAAAA");
+    }
+    let request = request
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let (status, version) = (response.status(), response.version());
+    let body = response.into_body().collect().await?.to_bytes();
+    Ok((status, version, body))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,200 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode, Version};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::ServerName;
+use tempfile::TempDir;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+struct TestServer {
+    tls_addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve HTTP port")?;
+        let tls_addr = reserve_local_addr().context("reserve HTTPS port")?;
+        let fixtures = fixtures_dir();
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\nlisten_ssl = \"{tls_addr}\"\n\n\
+             [ssl]\ncert = \"{cert}\"\nkey = \"{key}\"\n\n\
+             [php]\nenable = false\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"localhost\"\nroot = \"{root}\"\n",
+            cert = fixtures.join("localhost.crt").display(),
+            key = fixtures.join("localhost.key").display(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            tls_addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        // Listeners are all bound before any of them accepts
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn h2_is_negotiated_over_tls() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let tls = connect_tls(server.tls_addr, &[b"h2", b"http/1.1"]).await?;
+    assert_eq!(tls.get_ref().1.alpn_protocol(), Some(b"h2".as_slice()));
+
+    let (mut sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tls))
+            .await
+            .context("h2 handshake")?;
+    tokio::spawn(connection);
+
+    // Two requests on the one connection; the vhost is found via :authority
+    for (path, expected) in [("/health", "OK"), ("/hello.txt", "hello")] {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!(
+                "https://localhost:{}{}",
+                server.tls_addr.port(),
+                path
+            ))
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = sender.send_request(request).await.context("h2 request")?;
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        assert_eq!(response.version(), Version::HTTP_2);
+        let body = response.into_body().collect().await?.to_bytes();
+        assert_eq!(body, expected);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn http1_is_used_without_h2_in_alpn() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    for alpn in [&[b"http/1.1".as_slice()][..], &[]] {
+        let tls = connect_tls(server.tls_addr, alpn).await?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(tls))
+            .await
+            .context("http1 handshake")?;
+        tokio::spawn(connection);
+
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/health")
+            .header("Host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = sender
+            .send_request(request)
+            .await
+            .context("http1 request")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_11);
+    }
+
+    Ok(())
+}
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls")
+}
+
+/// TLS connection trusting the fixture certificate, offering `alpn`
+async fn connect_tls(addr: SocketAddr, alpn: &[&[u8]]) -> Result<TlsStream<TcpStream>> {
+    let pem = std::fs::read(fixtures_dir().join("localhost.crt")).context("read cert")?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots
+            .add(cert.context("parse cert")?)
+            .context("trust cert")?;
+    }
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .context("protocol versions")?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+
+    let tcp = TcpStream::connect(addr).await.context("connect")?;
+    let name = ServerName::try_from("localhost").context("server name")?;
+    TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .context("TLS handshake")
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -27,13 +27,16 @@ impl TestServer {
             "<h1>Hello from VeloServe</h1>",
         )
         .context("write index.html")?;
+        // Over static.stream_threshold, so a GET would stream it
+        std::fs::write(docroot.path().join("video.mp4"), vec![7u8; 3 << 20])
+            .context("write video.mp4")?;
 
         let addr = reserve_local_addr().context("reserve local port")?;
 
         let config_dir = tempfile::tempdir().context("create temp config dir")?;
         let config_path = config_dir.path().join("veloserve.toml");
         let config_toml = format!(
-            "[server]\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
             addr,
             docroot.path().to_string_lossy()
         );
@@ -117,6 +120,46 @@ async fn supports_common_http_methods() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn head_matches_get_without_a_body() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    for (path, size) in [("/index.html", 29), ("/video.mp4", 3 << 20)] {
+        let mut responses = Vec::new();
+        for method in [Method::GET, Method::HEAD] {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(format!("http://{}{}", server.addr, path))
+                .header("Host", "example.test")
+                .body(http_body_util::Empty::<Bytes>::new())
+                .context("build request")?;
+            let response = client
+                .request(request)
+                .await
+                .with_context(|| format!("{} {} failed", method, path))?;
+            let (parts, body) = response.into_parts();
+            let body = body.collect().await.context("read body")?.to_bytes();
+            responses.push((parts, body));
+        }
+        let (get, get_body) = &responses[0];
+        let (head, head_body) = &responses[1];
+
+        assert_eq!(head.status, StatusCode::OK);
+        assert!(head_body.is_empty(), "HEAD {} sent a body", path);
+        assert_eq!(get_body.len(), size);
+        assert_eq!(head.headers["content-length"], size.to_string().as_str());
+        for name in ["content-type", "etag", "last-modified", "cache-control"] {
+            assert_eq!(head.headers[name], get.headers[name], "{} {}", path, name);
+        }
+    }
+
+    Ok(())
+}
+
 async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
     let connector = HttpConnector::new();
     let client: Client<_, http_body_util::Empty<Bytes>> =
@@ -0,0 +1,162 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::LOCATION;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+struct TestServer {
+    addr: SocketAddr,
+    tls_addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+        let challenges = docroot.path().join(".well-known/acme-challenge");
+        std::fs::create_dir_all(&challenges).context("create challenge dir")?;
+        std::fs::write(challenges.join("token123"), "token123.thumbprint")
+            .context("write challenge")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve HTTP port")?;
+        let tls_addr = reserve_local_addr().context("reserve HTTPS port")?;
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\nlisten_ssl = \"{tls_addr}\"\n\
+             redirect_to_https = true\n\n\
+             [ssl]\ncert = \"{cert}\"\nkey = \"{key}\"\n\n\
+             [php]\nenable = false\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"plain.test\"\nroot = \"{root}\"\n\
+             redirect_to_https = false\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            cert = fixtures.join("localhost.crt").display(),
+            key = fixtures.join("localhost.key").display(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            tls_addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    async fn get(&self, host: &str, path: &str) -> Result<(Response<()>, Bytes)> {
+        let client: Client<_, Empty<Bytes>> =
+            Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, path))
+            .header("Host", host)
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = client.request(request).await.context("request failed")?;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+        Ok((Response::from_parts(parts, ()), body))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn plain_http_is_redirected_to_https() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (response, _) = server
+        .get("example.com:8080", "/hello.txt?lang=en&x=1")
+        .await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()[LOCATION],
+        format!(
+            "https://example.com:{}/hello.txt?lang=en&x=1",
+            server.tls_addr.port()
+        )
+    );
+
+    // A vhost can opt out
+    let (response, body) = server.get("plain.test", "/hello.txt").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "hello");
+    Ok(())
+}
+
+#[tokio::test]
+async fn acme_challenges_are_served_over_plain_http() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (response, body) = server
+        .get("example.com", "/.well-known/acme-challenge/token123")
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "token123.thumbprint");
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,288 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::HeaderMap;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary: every script holds the CGI response it answers with
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\ncat \"$SCRIPT_FILENAME\"\n";
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "post.php",
+        "Content-Type: text/html\r\nCache-Control: no-cache\r\n\
+         X-LiteSpeed-Cache-Control: public,max-age=120\r\n\
+         X-LiteSpeed-Tag: post_123, home\r\n\r\n<p>post</p>",
+    ),
+    (
+        "archive.php",
+        "Content-Type: text/html\r\nX-LiteSpeed-Cache-Control: public\r\n\
+         X-LiteSpeed-Tag: archive\r\n\r\n<p>archive</p>",
+    ),
+    (
+        "cart.php",
+        "Content-Type: text/html\r\nCache-Control: public, max-age=600\r\n\
+         X-LiteSpeed-Cache-Control: no-cache\r\n\r\n<p>cart</p>",
+    ),
+    (
+        "save.php",
+        "Content-Type: text/html\r\nX-LiteSpeed-Cache-Control: no-cache\r\n\
+         X-LiteSpeed-Purge: public, tag=post_123\r\n\r\n<p>saved</p>",
+    ),
+    (
+        "flush.php",
+        "Content-Type: text/html\r\nX-LiteSpeed-Cache-Control: no-cache\r\n\
+         X-LiteSpeed-Purge: *\r\n\r\n<p>flushed</p>",
+    ),
+    (
+        "reserved.php",
+        "Content-Type: text/html\r\nX-LiteSpeed-Cache-Control: no-cache\r\n\
+         X-LiteSpeed-Purge: tag=domain:example.test, path:example.test/archive.php\r\n\r\n\
+         <p>reserved</p>",
+    ),
+];
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for (script, response) in SCRIPTS {
+            std::fs::write(docroot.path().join(script), response).context("write php script")?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n\
+             [cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndefault_ttl = 3600\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.php\"]\n",
+            addr,
+            php_path.to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    async fn get(&self, path: &str) -> Result<(HeaderMap, String)> {
+        self.get_from("example.test", path).await
+    }
+
+    async fn get_from(&self, host: &str, path: &str) -> Result<(HeaderMap, String)> {
+        let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, path))
+            .header("Host", host)
+            .body(Empty::new())
+            .context("build request")?;
+        let response = client.request(request).await.context("request failed")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers().clone();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .context("read body")?
+            .to_bytes();
+        Ok((headers, String::from_utf8_lossy(&body).into_owned()))
+    }
+
+    async fn cache_status(&self, path: &str) -> Result<String> {
+        self.cache_status_from("example.test", path).await
+    }
+
+    async fn cache_status_from(&self, host: &str, path: &str) -> Result<String> {
+        let (headers, _) = self.get_from(host, path).await?;
+        Ok(headers
+            .get("x-cache")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn litespeed_headers_drive_the_page_cache() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    // X-LiteSpeed-Cache-Control beats Cache-Control: no-cache
+    let (headers, body) = server.get("/post.php").await?;
+    assert_eq!(body, "<p>post</p>");
+    assert_eq!(headers["x-cache"], "MISS");
+    for name in [
+        "x-litespeed-cache-control",
+        "x-litespeed-tag",
+        "x-litespeed-purge",
+    ] {
+        assert!(!headers.contains_key(name), "{} reached the client", name);
+    }
+    let (headers, _) = server.get("/post.php").await?;
+    assert_eq!(headers["x-cache"], "HIT");
+    assert!(!headers.contains_key("x-litespeed-tag"));
+
+    let (_, entry) = server
+        .get("/api/v1/cache/entry?domain=example.test&path=/post.php")
+        .await?;
+    let entry: serde_json::Value = serde_json::from_str(&entry)?;
+    assert_eq!(entry["ttl"], 120);
+    let tags = entry["tags"].as_array().context("tags array")?;
+    assert!(
+        tags.contains(&"ls:example.test:post_123".into()),
+        "{:?}",
+        tags
+    );
+    assert!(tags.contains(&"ls:example.test:home".into()), "{:?}", tags);
+
+    // ... and keeps out what Cache-Control alone would have stored
+    assert_eq!(server.cache_status("/cart.php").await?, "BYPASS");
+    assert_eq!(server.cache_status("/cart.php").await?, "BYPASS");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn litespeed_purge_drops_tagged_pages() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    for path in ["/post.php", "/archive.php"] {
+        server.get(path).await?;
+        assert_eq!(server.cache_status(path).await?, "HIT");
+    }
+
+    let (headers, body) = server.get("/save.php").await?;
+    assert_eq!(body, "<p>saved</p>");
+    assert!(!headers.contains_key("x-litespeed-purge"));
+    assert_eq!(server.cache_status("/post.php").await?, "MISS");
+    assert_eq!(server.cache_status("/archive.php").await?, "HIT");
+
+    // `*` empties the site
+    server.get("/flush.php").await?;
+    assert_eq!(server.cache_status("/post.php").await?, "MISS");
+    assert_eq!(server.cache_status("/archive.php").await?, "MISS");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn litespeed_purge_stays_within_its_site() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    // Both sites tag /post.php with post_123
+    for host in ["one.test", "two.test"] {
+        server.get_from(host, "/post.php").await?;
+        assert_eq!(server.cache_status_from(host, "/post.php").await?, "HIT");
+    }
+
+    server.get_from("one.test", "/save.php").await?;
+    assert_eq!(
+        server.cache_status_from("one.test", "/post.php").await?,
+        "MISS"
+    );
+    assert_eq!(
+        server.cache_status_from("two.test", "/post.php").await?,
+        "HIT"
+    );
+
+    server.get_from("one.test", "/flush.php").await?;
+    assert_eq!(
+        server.cache_status_from("two.test", "/post.php").await?,
+        "HIT"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn litespeed_purge_of_reserved_tags_is_refused() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    server.get("/archive.php").await?;
+    assert_eq!(server.cache_status("/archive.php").await?, "HIT");
+
+    let (_, body) = server.get("/reserved.php").await?;
+    assert_eq!(body, "<p>reserved</p>");
+    assert_eq!(server.cache_status("/archive.php").await?, "HIT");
+
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Empty::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,288 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\nhello from php'\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+        std::fs::write(docroot.path().join("page.php"), "<?php echo 'hi';")
+            .context("write page.php")?;
+        std::fs::write(docroot.path().join("metrics"), "site page").context("write metrics")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\ntrusted_proxies = [\"127.0.0.1\"]\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{php}\"\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            addr = addr,
+            php = php_path.to_string_lossy(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn prometheus_endpoint_counts_requests() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, _, _) = get(&client, server.addr, "/hello.txt").await?;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = get(&client, server.addr, "/missing.txt").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, body) = get(&client, server.addr, "/page.php").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello from php");
+
+    // Requests are recorded once their body is out; give the last a moment
+    let mut scrape = String::new();
+    for _ in 0..20 {
+        let (status, content_type, body) = get(&client, server.addr, "/metrics").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(content_type.starts_with("text/plain"), "{}", content_type);
+        scrape = body;
+        if scrape.contains("veloserve_php_duration_seconds_count 1") {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    let value = |name: &str| -> Option<u64> {
+        scrape
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|value| value.parse().ok())
+    };
+    // Readiness probes and earlier scrapes count as well
+    let requests = value("veloserve_requests_total").context("veloserve_requests_total")?;
+    assert!(requests >= 3, "{}", scrape);
+    assert!(
+        value("veloserve_responses_total{class=\"4xx\"}") >= Some(1),
+        "{}",
+        scrape
+    );
+    assert!(
+        value("veloserve_response_bytes_total") >= Some(19),
+        "{}",
+        scrape
+    );
+    assert_eq!(
+        value("veloserve_php_executions_total"),
+        Some(1),
+        "{}",
+        scrape
+    );
+    assert_eq!(value("veloserve_php_errors_total"), Some(0), "{}", scrape);
+    assert!(
+        scrape.contains("# TYPE veloserve_request_duration_seconds histogram"),
+        "{}",
+        scrape
+    );
+    assert!(
+        scrape
+            .lines()
+            .any(|line| line.starts_with("veloserve_request_duration_seconds_bucket{le=\"+Inf\"} ")),
+        "{}",
+        scrape
+    );
+    assert_eq!(
+        value("veloserve_php_duration_seconds_bucket{le=\"+Inf\"}"),
+        Some(1),
+        "{}",
+        scrape
+    );
+
+    // The JSON endpoint reports the same request counter
+    let (status, _, body) = get(&client, server.addr, "/api/v1/metrics").await?;
+    assert_eq!(status, StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body).context("parse metrics json")?;
+    assert!(
+        json["requests_total"].as_u64() >= Some(requests),
+        "{}",
+        body
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_are_counted_as_they_arrive() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let requests_total = |body: &str| -> Result<u64> {
+        let json: serde_json::Value = serde_json::from_str(body).context("parse status json")?;
+        json["requests_total"]
+            .as_u64()
+            .context("requests_total missing")
+    };
+
+    // Each status request counts itself
+    let (_, _, body) = get(&client, server.addr, "/api/v1/status").await?;
+    let before = requests_total(&body)?;
+    for path in ["/hello.txt", "/missing.txt"] {
+        get(&client, server.addr, path).await?;
+    }
+    let (_, _, body) = get(&client, server.addr, "/api/v1/status").await?;
+    assert_eq!(requests_total(&body)?, before + 3, "{}", body);
+
+    // Responses are counted once their body is out, which may trail the client
+    let mut json = serde_json::Value::Null;
+    for _ in 0..20 {
+        let (_, _, body) = get(&client, server.addr, "/api/v1/status").await?;
+        json = serde_json::from_str(&body).context("parse status json")?;
+        if json["responses"]["4xx"].as_u64() >= Some(1) {
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert!(json["responses"]["2xx"].as_u64() >= Some(1), "{}", json);
+    assert_eq!(json["responses"]["4xx"].as_u64(), Some(1), "{}", json);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_are_only_served_to_allowed_clients() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // A remote client behind the trusted proxy gets the vhost's own page
+    let request = Request::builder()
+        .uri(format!("http://{}/metrics", server.addr))
+        .header("x-forwarded-for", "203.0.113.9")
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await?.to_bytes();
+    assert_eq!(body, "site page");
+
+    // Loopback is in the default server.metrics_allow
+    let (status, _, body) = get(&client, server.addr, "/metrics").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("veloserve_requests_total"), "{}", body);
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+) -> Result<(StatusCode, String, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((
+        status,
+        content_type,
+        String::from_utf8_lossy(&body).into_owned(),
+    ))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,152 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addrs: [SocketAddr; 2],
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addrs = [
+            reserve_local_addr().context("reserve first port")?,
+            reserve_local_addr().context("reserve second port")?,
+        ];
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = [\"{first}\", \"{second}\"]\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            first = addrs[0],
+            second = addrs[1],
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addrs,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        for addr in addrs {
+            wait_until_ready(addr).await?;
+        }
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn every_listen_address_answers() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for addr in server.addrs {
+        let (status, body) = get(&client, addr, "/health").await?;
+        assert_eq!(status, StatusCode::OK, "{}", addr);
+        assert_eq!(body, "OK");
+
+        let (status, body) = get(&client, addr, "/hello.txt").await?;
+        assert_eq!(status, StatusCode::OK, "{}", addr);
+        assert_eq!(body, "hello");
+    }
+
+    // Both listeners feed the same server
+    let (_, body) = get(&client, server.addrs[0], "/api/v1/status").await?;
+    let before: serde_json::Value = serde_json::from_str(&body).context("parse status")?;
+    get(&client, server.addrs[1], "/hello.txt").await?;
+    let (_, body) = get(&client, server.addrs[0], "/api/v1/status").await?;
+    let after: serde_json::Value = serde_json::from_str(&body).context("parse status")?;
+    assert_eq!(
+        after["requests_total"].as_u64(),
+        before["requests_total"].as_u64().map(|total| total + 2)
+    );
+
+    Ok(())
+}
+
+async fn get(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
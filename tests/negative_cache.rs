@@ -0,0 +1,320 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+const ADMIN_TOKEN: &str = "negative-cache-admin-token";
+
+/// Fake php binary: logs each run to `runs` and answers with the script,
+/// which holds a raw CGI response
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\necho \"$SCRIPT_FILENAME\" >> \"RUNS\"\ncat \"$SCRIPT_FILENAME\"\n";
+
+/// A WordPress-style front controller answering every missing URL
+const FRONT_CONTROLLER: &str = "Status: 404 Not Found\r\nContent-Type: text/html\r\n\
+     Cache-Control: no-cache, must-revalidate, max-age=0\r\n\r\n<p>nothing here</p>";
+
+struct TestServer {
+    addr: SocketAddr,
+    runs: PathBuf,
+    _docroot: TempDir,
+    _static_root: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+struct Page {
+    status: StatusCode,
+    cache: String,
+    body: String,
+}
+
+impl TestServer {
+    async fn start(negative_ttl: u64) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.php"), FRONT_CONTROLLER)
+            .context("write front controller")?;
+        let static_root = tempfile::tempdir().context("create static docroot")?;
+        std::fs::write(static_root.path().join("404.html"), "<p>custom 404</p>")
+            .context("write error page")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let runs = config_dir.path().join("runs");
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP.replace("RUNS", &runs.to_string_lossy()))
+            .context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\nadmin_token = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n\
+             [cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\nnegative_ttl = {}\n\n\
+             [[virtualhost]]\ndomain = \"static.test\"\nroot = \"{}\"\nerror_pages = {{ 404 = \"/404.html\" }}\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.php\"]\n",
+            addr,
+            ADMIN_TOKEN,
+            php_path.to_string_lossy(),
+            negative_ttl,
+            static_root.path().to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            runs,
+            _docroot: docroot,
+            _static_root: static_root,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        host: &str,
+        path: &str,
+        cookie: Option<&str>,
+    ) -> Result<Page> {
+        let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", self.addr, path))
+            .header("Host", host);
+        if let Some(cookie) = cookie {
+            builder = builder.header("Cookie", cookie);
+        }
+        let request = builder.body(Empty::new()).context("build request")?;
+        let response = client.request(request).await.context("request failed")?;
+        let status = response.status();
+        let cache = response
+            .headers()
+            .get("x-cache")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .context("read body")?
+            .to_bytes();
+        Ok(Page {
+            status,
+            cache,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Page> {
+        self.request(Method::GET, "example.test", path, None).await
+    }
+
+    async fn purge(&self, query: &str) -> Result<()> {
+        let path = format!("/api/v1/cache/purge{}", query);
+        let page = self
+            .request(Method::POST, "example.test", &path, None)
+            .await?;
+        assert_eq!(page.status, StatusCode::OK, "{}", page.body);
+        Ok(())
+    }
+
+    async fn enable_readonly(&self) -> Result<()> {
+        let client: Client<HttpConnector, Full<Bytes>> =
+            Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/api/v1/readonly", self.addr))
+            .header("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+            .body(Full::new(Bytes::from_static(b"{\"enabled\": true}")))
+            .context("build request")?;
+        let response = client.request(request).await.context("request failed")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    fn php_runs(&self) -> usize {
+        std::fs::read_to_string(&self.runs)
+            .map(|runs| runs.lines().count())
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn front_controller_404s_are_cached() -> Result<()> {
+    let server = TestServer::start(60).await?;
+
+    let page = server.get("/wp-content/old.jpg").await?;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    assert_eq!(page.cache, "MISS");
+    let page = server.get("/wp-content/old.jpg").await?;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    assert_eq!(page.cache, "HIT");
+    assert_eq!(page.body, "<p>nothing here</p>");
+    assert_eq!(server.php_runs(), 1);
+
+    let entry = server
+        .get("/api/v1/cache/entry?domain=example.test&path=/wp-content/old.jpg")
+        .await?;
+    let entry: serde_json::Value = serde_json::from_str(&entry.body)?;
+    assert_eq!(entry["ttl"], 60);
+    let tags = entry["tags"].as_array().context("tags array")?;
+    assert!(tags.contains(&"negative".into()), "{:?}", tags);
+    assert!(tags.contains(&"negative:example.test".into()), "{:?}", tags);
+
+    // Requests with cookies always reach PHP, and don't store their 404
+    let page = server
+        .request(
+            Method::GET,
+            "example.test",
+            "/other.jpg",
+            Some("theme=dark"),
+        )
+        .await?;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    assert_eq!(page.cache, "BYPASS");
+    let page = server
+        .request(
+            Method::GET,
+            "example.test",
+            "/wp-content/old.jpg",
+            Some("theme=dark"),
+        )
+        .await?;
+    assert_eq!(page.cache, "BYPASS");
+    assert_eq!(server.php_runs(), 3);
+    assert_eq!(server.get("/other.jpg").await?.cache, "MISS");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn readonly_mode_keeps_cookie_requests_off_cached_404s() -> Result<()> {
+    let server = TestServer::start(60).await?;
+
+    server.get("/missing").await?;
+    assert_eq!(server.get("/missing").await?.cache, "HIT");
+    server.enable_readonly().await?;
+
+    let page = server
+        .request(Method::GET, "example.test", "/missing", Some("theme=dark"))
+        .await?;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    assert_eq!(page.cache, "BYPASS");
+    assert_eq!(server.php_runs(), 2);
+    assert_eq!(server.get("/missing").await?.cache, "HIT");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn negative_entries_are_purged() -> Result<()> {
+    let server = TestServer::start(60).await?;
+
+    for query in ["?tag=negative:example.test", "?domain=example.test", ""] {
+        server.get("/missing").await?;
+        assert_eq!(server.get("/missing").await?.cache, "HIT");
+        server.purge(query).await?;
+        assert_eq!(server.get("/missing").await?.cache, "MISS", "{}", query);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cached_builtin_404s_keep_the_error_page() -> Result<()> {
+    let server = TestServer::start(60).await?;
+
+    for _ in 0..2 {
+        let page = server
+            .request(Method::GET, "static.test", "/missing.html", None)
+            .await?;
+        assert_eq!(page.status, StatusCode::NOT_FOUND);
+        assert_eq!(page.body, "<p>custom 404</p>");
+    }
+    let entry = server
+        .get("/api/v1/cache/entry?domain=static.test&path=/missing.html")
+        .await?;
+    assert_eq!(entry.status, StatusCode::OK, "{}", entry.body);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn negative_caching_is_off_by_default() -> Result<()> {
+    let server = TestServer::start(0).await?;
+
+    for _ in 0..2 {
+        let page = server.get("/missing").await?;
+        assert_eq!(page.status, StatusCode::NOT_FOUND);
+        assert_eq!(page.cache, "BYPASS");
+    }
+    assert_eq!(server.php_runs(), 2);
+
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Empty::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
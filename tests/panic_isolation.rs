@@ -0,0 +1,226 @@
+#![cfg(all(unix, feature = "panic-tests"))]
+
+//! Relies on the `VELOSERVE_TEST_PANIC` trigger: `cargo test --features
+//! panic-tests`
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::Value;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary that answers every script
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\nok from php'\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    /// `php` is the `[php]` table body; `trigger` the panic point and needle
+    async fn start(php: &str, trigger: &str) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for (path, contents) in [
+            ("index.html", "<h1>still here</h1>"),
+            ("ok.php", "<?php echo 'ok from php';"),
+            ("boom.php", "<?php echo 'never';"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+        let socket_path = config_dir.path().join("php.sock");
+        std::fs::write(&socket_path, "").context("create socket placeholder")?;
+
+        let php = php
+            .replace("PHP_BINARY", &php_path.to_string_lossy())
+            .replace("SOCKET_PATH", &socket_path.to_string_lossy());
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\n{}\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            php,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .env("VELOSERVE_TEST_PANIC", trigger)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    fn is_running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Panic on `/boom.php` three times, checking normal requests in between
+async fn assert_survives_panics(
+    server: &mut TestServer,
+    ok_path: &str,
+    ok_body: &str,
+) -> Result<()> {
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for round in 0..3 {
+        let (status, body) = get(&client, server.addr, "/boom.php").await?;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "round {}", round);
+        assert!(!body.contains("never"), "{}", body);
+
+        let (status, body) = get(&client, server.addr, ok_path).await?;
+        assert_eq!(status, StatusCode::OK, "round {}: {}", round, body);
+        assert!(body.contains(ok_body), "round {}: {}", round, body);
+
+        let (status, _) = get(&client, server.addr, "/").await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    assert!(server.is_running(), "server exited after a panic");
+    Ok(())
+}
+
+#[tokio::test]
+async fn handler_panic_is_answered_with_500() -> Result<()> {
+    let mut server = TestServer::start("enable = false", "handler:/boom").await?;
+    assert_survives_panics(&mut server, "/", "still here").await?;
+
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let metrics = get_json(&client, server.addr, "/api/v1/metrics").await?;
+    assert_eq!(metrics["panics_total"], 3, "{}", metrics);
+    Ok(())
+}
+
+#[tokio::test]
+async fn php_panic_is_contained_in_cgi_mode() -> Result<()> {
+    let mut server = TestServer::start(
+        "enable = true\nmode = \"cgi\"\nbinary_path = \"PHP_BINARY\"",
+        "php:boom.php",
+    )
+    .await?;
+    assert_survives_panics(&mut server, "/ok.php", "ok from php").await
+}
+
+#[tokio::test]
+async fn php_panic_is_contained_in_socket_mode() -> Result<()> {
+    let mut server = TestServer::start(
+        "enable = true\nmode = \"socket\"\nbinary_path = \"PHP_BINARY\"\nsocket_path = \"SOCKET_PATH\"",
+        "php:boom.php",
+    )
+    .await?;
+    assert_survives_panics(&mut server, "/ok.php", "ok from php").await
+}
+
+/// The embed worker thread panics and is respawned with a fresh runtime
+#[cfg(feature = "php-embed")]
+#[tokio::test]
+async fn embed_worker_is_respawned_after_a_panic() -> Result<()> {
+    let mut server = TestServer::start("enable = true\nmode = \"embed\"", "embed:boom.php").await?;
+    assert_survives_panics(&mut server, "/ok.php", "ok from php").await?;
+
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let workers = get_json(&client, server.addr, "/api/v1/workers").await?;
+    assert_eq!(
+        workers["php_stats"]["embed_worker_restarts"], 3,
+        "{}",
+        workers
+    );
+    Ok(())
+}
+
+async fn get(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn get_json(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<Value> {
+    let (_, body) = get(client, addr, path).await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,182 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+const SECRET: &str = "TOP SECRET";
+
+struct TestServer {
+    addr: SocketAddr,
+    /// The file next to the docroot that must never be served
+    secret: PathBuf,
+    _base: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        // base/secret.txt sits next to the docroot base/root
+        // Not the default `.tmp` prefix, which the dotfile rule would refuse
+        let base = tempfile::Builder::new()
+            .prefix("traversal")
+            .tempdir()
+            .context("create temp base dir")?;
+        let root = base.path().join("root");
+        std::fs::create_dir(&root)?;
+        std::fs::write(root.join("index.html"), "home")?;
+        let secret = base.path().join("secret.txt");
+        std::fs::write(&secret, SECRET)?;
+        symlink(base.path(), root.join("escape"))?;
+        symlink(&secret, root.join("leak.txt"))?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr,
+            root.to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            secret,
+            _base: base,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn encoded_traversal_stays_in_the_docroot() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let absolute = server.secret.to_string_lossy().into_owned();
+    let paths = [
+        "/..%2fsecret.txt".to_string(),
+        "/..%2F..%2F..%2Fsecret.txt".to_string(),
+        "/%2e%2e/secret.txt".to_string(),
+        "/%2e%2e%2fsecret.txt".to_string(),
+        "/../secret.txt".to_string(),
+        // A decoded leading slash must not make the path absolute
+        format!("/%2F{}", absolute.trim_start_matches('/')),
+    ];
+    for path in &paths {
+        let (status, body) = get(&client, server.addr, "example.test", path).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+        assert!(!body.contains(SECRET), "{} leaked the secret", path);
+    }
+
+    let (status, body) = get(&client, server.addr, "example.test", "/index.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "home");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn symlinks_out_of_the_docroot_are_not_followed() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for path in ["/escape/secret.txt", "/leak.txt", "/escape/"] {
+        let (status, body) = get(&client, server.addr, "example.test", path).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+        assert!(!body.contains(SECRET), "{} leaked the secret", path);
+    }
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
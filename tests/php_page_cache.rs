@@ -0,0 +1,384 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary: logs each run to `runs` and answers with a cacheable
+/// page naming the run (`slow.php` takes half a second)
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\necho \"$SCRIPT_FILENAME\" >> \"RUNS\"\ncase \"$SCRIPT_FILENAME\" in *slow.php) sleep 0.5 ;; esac\nn=$(wc -l < \"RUNS\" | tr -d ' ')\nprintf 'Content-Type: text/html\\r\\nCache-Control: public, max-age=600\\r\\n\\r\\n<p>run %s</p>' \"$n\"\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    runs: PathBuf,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir(docroot.path().join("wp-admin")).context("create wp-admin")?;
+        for script in [
+            "index.php",
+            "account.php",
+            "slow.php",
+            "wp-admin/options.php",
+        ] {
+            std::fs::write(docroot.path().join(script), "<?php echo 'page';")
+                .context("write php script")?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let runs = config_dir.path().join("runs");
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP.replace("RUNS", &runs.to_string_lossy()))
+            .context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n\
+             [cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\ndebug_headers = true\n\n\
+             [[virtualhost]]\ndomain = \"nocache.test\"\nroot = \"{}\"\nindex = [\"index.php\"]\n\n\
+             [virtualhost.cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"wp.test\"\nroot = \"{}\"\nindex = [\"index.php\"]\nplatform = \"wordpress\"\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.php\"]\n\n\
+             [virtualhost.cache]\nttl = 600\nexclude = [\"/account.php\", \"/wp-admin/*\"]\nquery_allow = [\"page\", \"utm_*\"]\nvary = [\"X-Device\"]\n",
+            addr,
+            php_path.to_string_lossy(),
+            docroot.path().to_string_lossy(),
+            docroot.path().to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            runs,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    /// How many times PHP ran
+    fn php_runs(&self) -> usize {
+        std::fs::read_to_string(&self.runs)
+            .map(|runs| runs.lines().count())
+            .unwrap_or(0)
+    }
+
+    async fn get(&self, path: &str, device: Option<&str>) -> Result<(Option<String>, String)> {
+        let headers: Vec<_> = device
+            .map(|device| ("X-Device", device))
+            .into_iter()
+            .collect();
+        self.get_from(None, path, &headers).await
+    }
+
+    /// GET with an explicit Host header and extra request headers
+    async fn get_from(
+        &self,
+        host: Option<&str>,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(Option<String>, String)> {
+        let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let mut request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, path));
+        if let Some(host) = host {
+            request = request.header("Host", host);
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = client
+            .request(request.body(Empty::new()).context("build request")?)
+            .await
+            .context("request failed")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cache = response
+            .headers()
+            .get("x-cache")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .context("read body")?
+            .to_bytes();
+        Ok((cache, String::from_utf8_lossy(&body).into_owned()))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn cache_hit_skips_php() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (cache, body) = server.get("/index.php", None).await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    assert_eq!(body, "<p>run 1</p>");
+
+    let (cache, body) = server.get("/index.php", None).await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(body, "<p>run 1</p>");
+    assert_eq!(server.php_runs(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn concurrent_misses_run_php_once() -> Result<()> {
+    let server = std::sync::Arc::new(TestServer::start().await?);
+
+    let requests: Vec<_> = (0..10)
+        .map(|_| {
+            let server = server.clone();
+            tokio::spawn(async move { server.get("/slow.php", None).await })
+        })
+        .collect();
+    let mut statuses = Vec::new();
+    for request in requests {
+        let (cache, body) = request.await??;
+        assert_eq!(body, "<p>run 1</p>");
+        statuses.push(cache.unwrap_or_default());
+    }
+    assert_eq!(statuses.iter().filter(|s| *s == "MISS").count(), 1);
+    assert_eq!(statuses.iter().filter(|s| *s == "HIT").count(), 9);
+    assert_eq!(server.php_runs(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn excluded_path_bypasses_the_cache() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    for run in 1..=2 {
+        let (cache, body) = server.get("/account.php", None).await?;
+        assert_eq!(cache.as_deref(), Some("BYPASS"));
+        assert_eq!(body, format!("<p>run {}</p>", run));
+    }
+    assert_eq!(server.php_runs(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn wordpress_admin_bypasses_the_cache() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    for _ in 0..2 {
+        let (cache, _) = server.get("/wp-admin/options.php", None).await?;
+        assert_eq!(cache.as_deref(), Some("BYPASS"));
+    }
+
+    // Pretty permalinks go through index.php and are cached
+    let (cache, _) = server.get("/blog/post", None).await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    let (cache, _) = server.get("/blog/post", None).await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(server.php_runs(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_allowed_query_strings_are_cached() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (cache, _) = server
+        .get("/index.php?page=2&utm_source=mail", None)
+        .await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    let (cache, _) = server
+        .get("/index.php?page=2&utm_source=mail", None)
+        .await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+
+    // A different allowed query is its own page
+    let (cache, _) = server.get("/index.php?page=3", None).await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+
+    let (cache, _) = server.get("/index.php?page=2&s=shoes", None).await?;
+    assert_eq!(cache.as_deref(), Some("BYPASS"));
+    assert_eq!(server.php_runs(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn vary_headers_get_their_own_entries() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (cache, mobile) = server.get("/", Some("mobile")).await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    let (cache, desktop) = server.get("/", Some("desktop")).await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    assert_ne!(mobile, desktop);
+
+    let (cache, body) = server.get("/", Some("Mobile")).await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(body, mobile);
+    assert_eq!(server.php_runs(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn disabled_vhost_never_uses_the_cache() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    for run in 1..=2 {
+        let (cache, body) = server
+            .get_from(Some("nocache.test"), "/index.php", &[])
+            .await?;
+        assert_eq!(cache.as_deref(), Some("BYPASS"));
+        assert_eq!(body, format!("<p>run {}</p>", run));
+    }
+
+    // The catch-all vhost still caches
+    server.get("/index.php", None).await?;
+    let (cache, _) = server.get("/index.php", None).await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(server.php_runs(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cache_config_reports_effective_vhost_settings() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (_, body) = server.get("/api/v1/cache/config", None).await?;
+    let config: serde_json::Value = serde_json::from_str(&body)?;
+    let vhosts = config["vhosts"].as_array().context("vhosts array")?;
+    assert_eq!(vhosts.len(), 3);
+
+    assert_eq!(vhosts[0]["domain"], "nocache.test");
+    assert_eq!(vhosts[0]["cache_enabled"], false);
+
+    assert_eq!(vhosts[2]["domain"], "*");
+    assert_eq!(vhosts[2]["cache_enabled"], true);
+    assert_eq!(vhosts[2]["ttl"], 600);
+    assert_eq!(vhosts[2]["ttl_source"], "vhost");
+    assert_eq!(
+        vhosts[2]["query_allow"],
+        serde_json::json!(["page", "utm_*"])
+    );
+    assert_eq!(vhosts[2]["vary"], serde_json::json!(["X-Device"]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn wordpress_login_cookies_bypass_the_cache() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let logged_in = [(
+        "Cookie",
+        "_ga=GA1.2.3; wordpress_logged_in_0a1b=admin%7C1700; theme=dark",
+    )];
+    for run in 1..=2 {
+        let (cache, body) = server
+            .get_from(Some("wp.test"), "/index.php", &logged_in)
+            .await?;
+        assert_eq!(cache.as_deref(), Some("BYPASS(cookie)"));
+        assert_eq!(body, format!("<p>run {}</p>", run));
+    }
+
+    // Other cookies don't keep visitors out of the cache
+    let visitor = [("Cookie", "_ga=GA1.2.3; theme=dark")];
+    let (cache, _) = server
+        .get_from(Some("wp.test"), "/index.php", &visitor)
+        .await?;
+    assert_eq!(cache.as_deref(), Some("MISS"));
+    let (cache, body) = server
+        .get_from(Some("wp.test"), "/index.php", &visitor)
+        .await?;
+    assert_eq!(cache.as_deref(), Some("HIT"));
+    assert_eq!(body, "<p>run 3</p>");
+
+    // Logged-in users still skip the copy visitors are served
+    let (cache, _) = server
+        .get_from(Some("wp.test"), "/index.php", &logged_in)
+        .await?;
+    assert_eq!(cache.as_deref(), Some("BYPASS(cookie)"));
+    assert_eq!(server.php_runs(), 4);
+
+    // Requests skipped for another reason aren't put down to the cookie
+    let (cache, _) = server
+        .get_from(Some("wp.test"), "/index.php?s=search", &logged_in)
+        .await?;
+    assert_eq!(cache.as_deref(), Some("BYPASS"));
+
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Empty::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
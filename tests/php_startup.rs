@@ -0,0 +1,193 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary: `-v` takes `probe_secs`, scripts answer immediately
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  sleep PROBE_SECS\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\n<p>hello from php</p>'\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    spawned_at: Instant,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(probe_secs: u64, startup_grace_ms: u64) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.php"), "<?php echo 'hello';")
+            .context("write index.php")?;
+        std::fs::write(docroot.path().join("static.txt"), "static").context("write static.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(
+            &php_path,
+            FAKE_PHP.replace("PROBE_SECS", &probe_secs.to_string()),
+        )
+        .context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\nstartup_grace_ms = {}\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.php\"]\n",
+            addr,
+            php_path.to_string_lossy(),
+            startup_grace_ms,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let spawned_at = Instant::now();
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            spawned_at,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn listeners_bind_before_slow_php_probe() -> Result<()> {
+    let server = TestServer::start(3, 50).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // The listener is up long before the three second probe finishes
+    assert!(
+        server.spawned_at.elapsed() < Duration::from_secs(2),
+        "listener took {:?}",
+        server.spawned_at.elapsed()
+    );
+    let (status, _) = get(&client, server.addr, "/static.txt").await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = get(&client, server.addr, "/ready").await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.contains("\"php\":\"starting\""), "{}", body);
+
+    // Past the short grace period PHP requests are told to retry
+    let (status, _) = get(&client, server.addr, "/index.php").await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let mut ready = false;
+    for _ in 0..100 {
+        let (status, _) = get(&client, server.addr, "/ready").await?;
+        if status == StatusCode::OK {
+            ready = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(ready, "PHP pool never became ready");
+
+    let (status, body) = get(&client, server.addr, "/index.php").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("hello from php"), "{}", body);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn php_requests_wait_out_startup_grace() -> Result<()> {
+    let server = TestServer::start(1, 10_000).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Arrives while the probe is still running and waits for it
+    let (status, body) = get(&client, server.addr, "/index.php").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("hello from php"), "{}", body);
+
+    let (status, body) = get(&client, server.addr, "/ready").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("\"php\":\"ready\""), "{}", body);
+
+    Ok(())
+}
+
+async fn get(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,188 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary: `events.php` emits one event a second, `page.php` is
+/// cacheable HTML that opts out of buffering
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\ncase \"$SCRIPT_FILENAME\" in\n  *events.php)\n    printf 'Content-Type: text/event-stream\\r\\nX-Accel-Buffering: no\\r\\n\\r\\n'\n    for n in 1 2 3 4 5 6 7 8 9 10; do\n      printf 'data: %s\\n\\n' \"$n\"\n      sleep 1\n    done\n    ;;\n  *)\n    printf 'Content-Type: text/html\\r\\nX-Accel-Buffering: no\\r\\n\\r\\n<p>live</p>'\n    ;;\nesac\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for script in ["events.php", "page.php"] {
+            std::fs::write(docroot.path().join(script), "<?php flush();")
+                .context("write php script")?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\nrequest_timeout = \"3s\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"page.php\"]\n\n[[virtualhost.location]]\npath = \"/events.php\"\nstreaming_timeout = \"30s\"\n",
+            addr,
+            php_path.to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn events_arrive_as_php_flushes_them() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let started = Instant::now();
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}/events.php", server.addr))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    assert!(!response.headers().contains_key("x-accel-buffering"));
+
+    let mut body = response.into_body();
+    let mut received = String::new();
+    let mut first_event = None;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.context("read body frame")?;
+        if let Some(data) = frame.data_ref() {
+            received.push_str(&String::from_utf8_lossy(data));
+            first_event.get_or_insert_with(|| started.elapsed());
+        }
+    }
+
+    // The first event shows up long before the script finishes
+    let first_event = first_event.context("no events received")?;
+    assert!(
+        first_event < Duration::from_millis(900),
+        "first event after {:?}",
+        first_event
+    );
+    let expected: String = (1..=10).map(|n| format!("data: {}\n\n", n)).collect();
+    assert_eq!(received, expected);
+
+    // streaming_timeout, not the three second request_timeout, bounds the stream
+    assert!(started.elapsed() >= Duration::from_secs(9));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn streamed_pages_are_not_cached() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for _ in 0..2 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/page.php", server.addr))
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = client.request(request).await.context("request failed")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(
+            response
+                .headers()
+                .get("x-cache")
+                .and_then(|v| v.to_str().ok()),
+            Some("HIT")
+        );
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .context("read body")?
+            .to_bytes();
+        assert_eq!(body, Bytes::from("<p>live</p>"));
+    }
+
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
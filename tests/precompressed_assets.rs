@@ -0,0 +1,193 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    /// `precompressed` is the `[static]` default for vhosts without their own
+    async fn start(precompressed: bool) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for (path, contents) in [
+            ("index.html", "<h1>plain</h1>"),
+            ("index.html.gz", "gzip bytes"),
+            ("app.js", "plain js"),
+            ("app.js.br", "brotli bytes"),
+            ("app.js.gz", "gzip bytes"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\n\n[static]\nprecompressed = {}\n\n[[virtualhost]]\ndomain = \"static.test\"\nroot = \"{}\"\nprecompressed = true\n\n[[virtualhost]]\ndomain = \"plain.test\"\nroot = \"{}\"\nprecompressed = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr, precompressed, root, root, root
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn precompressed_siblings_follow_accept_encoding() -> Result<()> {
+    let server = TestServer::start(false).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, body) =
+        get(&client, server.addr, "static.test", "/app.js", "gzip, br").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "brotli bytes");
+    assert_eq!(headers[CONTENT_ENCODING], "br");
+    assert_eq!(headers["vary"], "Accept-Encoding");
+
+    let (_, headers, body) = get(&client, server.addr, "static.test", "/app.js", "gzip").await?;
+    assert_eq!(body, "gzip bytes");
+    assert_eq!(headers[CONTENT_ENCODING], "gzip");
+
+    // Vhosts without `precompressed` always get the original
+    let (_, headers, body) = get(&client, server.addr, "other.test", "/app.js", "br").await?;
+    assert_eq!(body, "plain js");
+    assert!(!headers.contains_key(CONTENT_ENCODING));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn static_precompressed_is_the_vhost_default() -> Result<()> {
+    let server = TestServer::start(true).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Inherited from [static], still preferring brotli
+    let (_, headers, body) = get(&client, server.addr, "other.test", "/app.js", "gzip, br").await?;
+    assert_eq!(body, "brotli bytes");
+    assert_eq!(headers[CONTENT_ENCODING], "br");
+
+    // A vhost can opt out
+    let (_, headers, body) = get(&client, server.addr, "plain.test", "/app.js", "br").await?;
+    assert_eq!(body, "plain js");
+    assert!(!headers.contains_key(CONTENT_ENCODING));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn encoded_pages_are_not_replayed_from_cache() -> Result<()> {
+    let server = TestServer::start(false).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (_, headers, body) = get(&client, server.addr, "static.test", "/", "gzip").await?;
+    assert_eq!(body, "gzip bytes");
+    assert_eq!(headers[CONTENT_ENCODING], "gzip");
+
+    // A client that can't decode gzip must never see the gzip body
+    let (_, headers, body) = get(&client, server.addr, "static.test", "/", "identity").await?;
+    assert_eq!(body, "<h1>plain</h1>");
+    assert!(!headers.contains_key(CONTENT_ENCODING));
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+    accept_encoding: &str,
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .header(ACCEPT_ENCODING, accept_encoding)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
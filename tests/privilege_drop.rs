@@ -0,0 +1,156 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use nix::unistd::{Uid, User};
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+const TARGET_USER: &str = "nobody";
+
+struct TestServer {
+    addr: SocketAddr,
+    cache_dir: std::path::PathBuf,
+    _docroot: TempDir,
+    _state_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = world_readable_tempdir()?;
+        std::fs::write(docroot.path().join("index.html"), "<h1>unprivileged</h1>")
+            .context("write index.html")?;
+        std::fs::set_permissions(
+            docroot.path().join("index.html"),
+            std::fs::Permissions::from_mode(0o644),
+        )?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+
+        let state_dir = world_readable_tempdir()?;
+        let cache_dir = state_dir.path().join("cache");
+        let config_path = state_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nlisten = \"{}\"\nuser = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl2_enabled = true\nstorage = \"disk\"\ndisk_path = \"{}\"\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            TARGET_USER,
+            cache_dir.to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            cache_dir,
+            _docroot: docroot,
+            _state_dir: state_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Files written after the drop (page cache entries) belong to the target user.
+#[tokio::test]
+async fn files_created_after_drop_belong_to_target_user() -> Result<()> {
+    // Switching users needs root; CI containers run the suite as root
+    if !Uid::effective().is_root() {
+        return Ok(());
+    }
+    let target = User::from_name(TARGET_USER)?.context("target user missing")?;
+
+    let server = TestServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}/", server.addr))
+        .header("Host", "privdrop.test")
+        .body(http_body_util::Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_eq!(
+        std::fs::metadata(&server.cache_dir)?.uid(),
+        target.uid.as_raw()
+    );
+
+    let mut entries = 0;
+    for entry in std::fs::read_dir(&server.cache_dir)? {
+        let metadata = entry?.metadata()?;
+        assert_eq!(metadata.uid(), target.uid.as_raw());
+        entries += 1;
+    }
+    assert!(entries > 0, "expected a page cache entry on disk");
+
+    Ok(())
+}
+
+fn world_readable_tempdir() -> Result<TempDir> {
+    let dir = tempfile::tempdir().context("create temp dir")?;
+    std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755))?;
+    Ok(dir)
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,436 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{
+    HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        let video: Vec<u8> = (0..2000u32).map(|n| (n % 251) as u8).collect();
+        std::fs::write(docroot.path().join("clip.mp4"), &video).context("write clip.mp4")?;
+        std::fs::write(docroot.path().join("empty.bin"), "").context("write empty.bin")?;
+        std::fs::write(docroot.path().join("movie.mp4"), large_file())
+            .context("write movie.mp4")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn ranges_are_served_partially() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let video: Vec<u8> = (0..2000u32).map(|n| (n % 251) as u8).collect();
+
+    let cases: &[(&str, usize, usize)] = &[
+        ("bytes=0-99", 0, 99),
+        ("bytes=1500-", 1500, 1999),
+        ("bytes=-500", 1500, 1999),
+        ("bytes=1990-5000", 1990, 1999),
+    ];
+    for &(range, first, last) in cases {
+        let (status, headers, body) =
+            request(&client, server.addr, Method::GET, "/clip.mp4", Some(range)).await?;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT, "{}", range);
+        assert_eq!(
+            headers[CONTENT_RANGE],
+            format!("bytes {}-{}/2000", first, last).as_str(),
+            "{}",
+            range
+        );
+        assert_eq!(
+            headers[CONTENT_LENGTH],
+            (last - first + 1).to_string().as_str()
+        );
+        assert_eq!(body, &video[first..=last], "{}", range);
+    }
+
+    // HEAD describes the range without sending it
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::HEAD,
+        "/clip.mp4",
+        Some("bytes=0-9"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(headers[CONTENT_RANGE], "bytes 0-9/2000");
+    assert_eq!(headers[CONTENT_LENGTH], "10");
+    assert!(body.is_empty());
+
+    // Several ranges come back as multipart/byteranges
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "/clip.mp4",
+        Some("bytes=0-1,5-9"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    let boundary = headers[CONTENT_TYPE]
+        .to_str()?
+        .strip_prefix("multipart/byteranges; boundary=")
+        .expect("multipart content type")
+        .to_string();
+    let mut expected = Vec::new();
+    for (first, last) in [(0, 1), (5, 9)] {
+        expected.extend_from_slice(
+            format!(
+                "\r\n--{}\r\nContent-Type: video/mp4\r\nContent-Range: bytes {}-{}/2000\r\n\r\n",
+                boundary, first, last
+            )
+            .as_bytes(),
+        );
+        expected.extend_from_slice(&video[first..=last]);
+    }
+    expected.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    assert_eq!(body, expected);
+    assert_eq!(headers[CONTENT_LENGTH], expected.len().to_string().as_str());
+
+    // Without a Range, with one we don't support, or with ranges adding up to
+    // more than the file, the whole file
+    for range in [None, Some("items=0-1"), Some("bytes=0-,0-")] {
+        let (status, headers, body) =
+            request(&client, server.addr, Method::GET, "/clip.mp4", range).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["accept-ranges"], "bytes");
+        assert_eq!(body, video);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unsatisfiable_ranges_answer_416() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for (path, range, size) in [
+        ("/clip.mp4", "bytes=2000-", 2000),
+        ("/clip.mp4", "bytes=-0", 2000),
+        ("/empty.bin", "bytes=0-", 0),
+    ] {
+        let (status, headers, body) =
+            request(&client, server.addr, Method::GET, path, Some(range)).await?;
+        assert_eq!(
+            status,
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            "{} {}",
+            path,
+            range
+        );
+        assert_eq!(headers[CONTENT_RANGE], format!("bytes */{}", size).as_str());
+        assert!(body.is_empty());
+    }
+
+    // A suffix of an empty file is the (empty) file itself
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "/empty.bin",
+        Some("bytes=-500"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn empty_files_get_416_or_the_whole_file() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for (method, range, expected) in [
+        (Method::GET, "bytes=0-0", StatusCode::RANGE_NOT_SATISFIABLE),
+        (
+            Method::GET,
+            "bytes=0-,1-",
+            StatusCode::RANGE_NOT_SATISFIABLE,
+        ),
+        (Method::HEAD, "bytes=0-", StatusCode::RANGE_NOT_SATISFIABLE),
+        (Method::GET, "bytes=-1", StatusCode::OK),
+        (Method::GET, "bytes=0-0,-1", StatusCode::OK),
+        (Method::GET, "bytes=5-2", StatusCode::OK),
+        (Method::HEAD, "bytes=-1", StatusCode::OK),
+    ] {
+        let (status, headers, body) = request(
+            &client,
+            server.addr,
+            method.clone(),
+            "/empty.bin",
+            Some(range),
+        )
+        .await?;
+        assert_eq!(status, expected, "{} {}", method, range);
+        assert_eq!(headers[CONTENT_LENGTH], "0", "{} {}", method, range);
+        assert!(body.is_empty());
+        if status == StatusCode::RANGE_NOT_SATISFIABLE {
+            assert_eq!(headers[CONTENT_RANGE], "bytes */0");
+        } else {
+            assert!(!headers.contains_key(CONTENT_RANGE));
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_if_range_sends_the_whole_file() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let path = server._docroot.path().join("report.pdf");
+    let written = SystemTime::now() - Duration::from_secs(60);
+    write_with_mtime(&path, &[b'a'; 1000], written)?;
+
+    // A download manager fetches the start and remembers the validators
+    let (status, headers, _) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "/report.pdf",
+        Some("bytes=0-499"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    let etag = headers[ETAG].to_str()?.to_string();
+    let last_modified = headers[LAST_MODIFIED].to_str()?.to_string();
+
+    // Unchanged file: both forms of If-Range resume the download
+    for validator in [&etag, &last_modified] {
+        let (status, headers, body) = resume(
+            &client,
+            server.addr,
+            Method::GET,
+            "/report.pdf",
+            Some("bytes=500-"),
+            Some(validator),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT, "{}", validator);
+        assert_eq!(headers[CONTENT_RANGE], "bytes 500-999/1000");
+        assert_eq!(body.len(), 500);
+    }
+
+    // The file is replaced mid-download: the range is ignored and the new
+    // file is sent whole, whichever validator the client kept
+    write_with_mtime(&path, &[b'b'; 1200], written + Duration::from_secs(30))?;
+    for validator in [&etag, &last_modified] {
+        let (status, headers, body) = resume(
+            &client,
+            server.addr,
+            Method::GET,
+            "/report.pdf",
+            Some("bytes=500-"),
+            Some(validator),
+        )
+        .await?;
+        assert_eq!(status, StatusCode::OK, "{}", validator);
+        assert!(!headers.contains_key(CONTENT_RANGE));
+        assert_eq!(body, vec![b'b'; 1200]);
+    }
+
+    Ok(())
+}
+
+fn write_with_mtime(path: &std::path::Path, contents: &[u8], modified: SystemTime) -> Result<()> {
+    std::fs::write(path, contents).context("write file")?;
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(modified))
+        .context("set mtime")
+}
+
+/// Over the default 1 MiB streaming threshold
+fn large_file() -> Vec<u8> {
+    (0..3 * 1024 * 1024u32).map(|n| (n % 253) as u8).collect()
+}
+
+#[tokio::test]
+async fn large_files_are_streamed_with_a_length() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let movie = large_file();
+
+    let (status, headers, body) =
+        request(&client, server.addr, Method::GET, "/movie.mp4", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_LENGTH], movie.len().to_string().as_str());
+    assert!(body == movie, "streamed body differs from the file");
+
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "/movie.mp4",
+        Some("bytes=1000-2099999"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(headers[CONTENT_LENGTH], "2099000");
+    assert!(body == movie[1000..2_100_000], "streamed range differs");
+
+    let (status, headers, body) =
+        request(&client, server.addr, Method::HEAD, "/movie.mp4", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[CONTENT_LENGTH], movie.len().to_string().as_str());
+    assert!(body.is_empty());
+
+    // Walking away mid-download leaves the server serving
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await?;
+    stream
+        .write_all(b"GET /movie.mp4 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await?;
+    let mut partial = [0u8; 4096];
+    stream.read_exact(&mut partial).await?;
+    drop(stream);
+
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "/clip.mp4",
+        Some("bytes=0-9"),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body.len(), 10);
+
+    Ok(())
+}
+
+async fn request(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    range: Option<&str>,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+    resume(client, addr, method, path, range, None).await
+}
+
+/// A range request conditional on `If-Range`
+async fn resume(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    range: Option<&str>,
+    if_range: Option<&str>,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path));
+    if let Some(range) = range {
+        builder = builder.header(RANGE, range);
+    }
+    if let Some(if_range) = if_range {
+        builder = builder.header(IF_RANGE, if_range);
+    }
+    let request = builder
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, body.to_vec()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
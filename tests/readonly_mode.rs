@@ -0,0 +1,296 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Full<Bytes>>;
+
+const ADMIN_TOKEN: &str = "readonly-test-admin-token";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.html"), "<h1>home</h1>")
+            .context("write index.html")?;
+        std::fs::write(docroot.path().join("login.html"), "<h1>login</h1>")
+            .context("write login.html")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\nreadonly_message = \"Writes are paused\"\nreadonly_allow = [\"/login.html\"]\nadmin_token = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            ADMIN_TOKEN,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn readonly_mode_rejects_writes_while_reads_keep_flowing() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Background readers run across every toggle below
+    let stop = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+    let failed_reads = Arc::new(AtomicU64::new(0));
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let (client, stop) = (client.clone(), stop.clone());
+            let (reads, failed_reads) = (reads.clone(), failed_reads.clone());
+            let addr = server.addr;
+            tokio::spawn(async move {
+                while !stop.load(Ordering::Relaxed) {
+                    match send(
+                        &client,
+                        addr,
+                        Method::GET,
+                        "/index.html",
+                        "example.test",
+                        None,
+                    )
+                    .await
+                    {
+                        Ok((StatusCode::OK, _)) => reads.fetch_add(1, Ordering::Relaxed),
+                        _ => failed_reads.fetch_add(1, Ordering::Relaxed),
+                    };
+                }
+            })
+        })
+        .collect();
+
+    let post = |path: &'static str, host: &'static str| {
+        send(&client, server.addr, Method::POST, path, host, Some("a=1"))
+    };
+
+    assert_ne!(
+        post("/index.html", "example.test").await?.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    // Toggling takes the admin token
+    let (status, _) = send(
+        &client,
+        server.addr,
+        Method::POST,
+        "/api/v1/readonly",
+        "example.test",
+        Some(&json!({"enabled": true}).to_string()),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_ne!(
+        post("/index.html", "example.test").await?.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let toggled = api(&client, server.addr, json!({"enabled": true})).await?;
+    assert_eq!(toggled["readonly"]["global"], true);
+
+    let (status, body) = post("/index.html", "example.test").await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, "Writes are paused");
+    assert_eq!(
+        send(
+            &client,
+            server.addr,
+            Method::DELETE,
+            "/index.html",
+            "example.test",
+            None
+        )
+        .await?
+        .0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_ne!(
+        post("/login.html", "example.test").await?.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let (status, body) = send(
+        &client,
+        server.addr,
+        Method::GET,
+        "/api/v1/status",
+        "example.test",
+        None,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    let status_json: Value = serde_json::from_str(&body)?;
+    assert_eq!(status_json["readonly"]["global"], true);
+
+    let (status, _) = send(
+        &client,
+        server.addr,
+        Method::POST,
+        "/api/v1/cache/purge",
+        "example.test",
+        None,
+    )
+    .await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // Scope to a single vhost: other domains accept writes again
+    api(&client, server.addr, json!({"enabled": false})).await?;
+    api(
+        &client,
+        server.addr,
+        json!({"enabled": true, "vhost": "locked.test", "duration_secs": 600}),
+    )
+    .await?;
+    assert_eq!(
+        post("/index.html", "locked.test").await?.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_ne!(
+        post("/index.html", "example.test").await?.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    api(
+        &client,
+        server.addr,
+        json!({"enabled": false, "vhost": "locked.test"}),
+    )
+    .await?;
+    assert_ne!(
+        post("/index.html", "locked.test").await?.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    stop.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.await?;
+    }
+    assert!(reads.load(Ordering::Relaxed) > 0);
+    assert_eq!(failed_reads.load(Ordering::Relaxed), 0);
+
+    Ok(())
+}
+
+async fn api(client: &HttpClient, addr: SocketAddr, payload: Value) -> Result<Value> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/api/v1/readonly", addr))
+        .header("Authorization", format!("Bearer {}", ADMIN_TOKEN))
+        .body(Full::new(Bytes::from(payload.to_string())))
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert_eq!(status, StatusCode::OK, "readonly API failed: {}", body);
+    Ok(serde_json::from_str(&body)?)
+}
+
+async fn send(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    host: &str,
+    body: Option<&str>,
+) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Full::new(Bytes::from(body.unwrap_or("").to_string())))
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "redis-tests")]
+
+//! Needs a Redis server: `cargo test --features redis-tests`, pointed at
+//! VELOSERVE_TEST_REDIS_URL (default redis://127.0.0.1:6379/15)
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use redis::Commands;
+use veloserve::cache::CacheManager;
+use veloserve::config::{CacheConfig, CacheStorage};
+
+fn redis_url() -> String {
+    std::env::var("VELOSERVE_TEST_REDIS_URL")
+        .unwrap_or_else(|_| "redis://127.0.0.1:6379/15".to_string())
+}
+
+fn redis_config(l1_ttl: u64) -> CacheConfig {
+    CacheConfig {
+        storage: CacheStorage::Redis,
+        redis_url: Some(redis_url()),
+        redis_timeout_ms: 500,
+        redis_l1_ttl: l1_ttl,
+        ..CacheConfig::default()
+    }
+}
+
+/// Keys unique to this run, so parallel tests and earlier runs don't collide
+fn unique(name: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0);
+    format!("{}-{}-{}", name, std::process::id(), nanos)
+}
+
+fn connection() -> Result<redis::Connection> {
+    let client = redis::Client::open(redis_url()).context("open redis")?;
+    client.get_connection().context("connect to redis")
+}
+
+#[tokio::test]
+async fn entries_are_shared_and_expire_in_redis() -> Result<()> {
+    let writer = CacheManager::new(&redis_config(5));
+    let reader = CacheManager::new(&redis_config(5));
+    assert_eq!(writer.stats()["l2"]["available"], true);
+
+    let key = format!("page:example.test:/{}", unique("shared"));
+    writer
+        .set_with_ttl(
+            &key,
+            b"from redis".to_vec(),
+            "text/html",
+            vec![],
+            Duration::from_secs(30),
+        )
+        .await;
+
+    let (body, content_type) = reader
+        .get_with_metadata(&key)
+        .await
+        .context("entry not visible to the second instance")?;
+    assert_eq!(body, b"from redis");
+    assert_eq!(content_type, "text/html");
+    assert_eq!(reader.stats()["l2"]["hits"], 1);
+
+    // TTLs are native Redis expiries
+    let ttl: i64 = connection()?.ttl(format!("veloserve:v1:entry:{}", key))?;
+    assert!((1..=30).contains(&ttl), "ttl {}", ttl);
+
+    writer.remove(&key).await;
+    let exists: bool = connection()?.exists(format!("veloserve:v1:entry:{}", key))?;
+    assert!(!exists);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tag_purge_reaches_other_instances_after_l1_ttl() -> Result<()> {
+    let purger = CacheManager::new(&redis_config(1));
+    let reader = CacheManager::new(&redis_config(1));
+
+    let tag = unique("product");
+    let tagged = format!("page:example.test:/{}", unique("tagged"));
+    let untagged = format!("page:example.test:/{}", unique("untagged"));
+    purger
+        .set(&tagged, b"tagged".to_vec(), "text/html", vec![tag.clone()])
+        .await;
+    purger
+        .set(&untagged, b"untagged".to_vec(), "text/html", vec![])
+        .await;
+
+    // Pull both into the reader's L1
+    assert!(reader.get(&tagged).await.is_some());
+    assert!(reader.get(&untagged).await.is_some());
+
+    assert_eq!(purger.purge_by_tag_count(&tag).await, 2);
+    let tag_set_exists: bool = connection()?.exists(format!("veloserve:v1:tag:{}", tag))?;
+    assert!(!tag_set_exists);
+
+    // The reader's L1 copy is only trusted for redis_l1_ttl
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(reader.get(&tagged).await.is_none());
+    assert_eq!(reader.get(&untagged).await, Some(b"untagged".to_vec()));
+
+    purger.remove(&untagged).await;
+    Ok(())
+}
@@ -0,0 +1,191 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir_all(docroot.path().join("errors")).context("create errors")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+        std::fs::write(
+            docroot.path().join("errors/431.html"),
+            "<h1>trim your cookies</h1>",
+        )
+        .context("write error page")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\nmax_uri_length = 256\nmax_header_bytes = 1024\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"custom.test\"\nroot = \"{root}\"\nerror_pages = {{ 431 = \"/errors/431.html\" }}\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            addr = addr,
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn long_uris_get_414() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, _) = get(&client, server.addr, "/hello.txt?q=short", &[]).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let long = format!("/hello.txt?q={}", "a".repeat(300));
+    let (status, body) = get(&client, server.addr, &long, &[]).await?;
+    assert_eq!(status, StatusCode::URI_TOO_LONG);
+    assert!(body.contains("<h1>414 URI Too Long</h1>"), "{}", body);
+    assert!(body.contains("VeloServe"), "{}", body);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn large_header_blocks_get_431() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let cookie = "c".repeat(600);
+    let (status, body) = get(&client, server.addr, "/hello.txt", &[("cookie", &cookie)]).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello");
+
+    // The limit covers the whole block, not each header
+    let headers = [("cookie", cookie.as_str()), ("x-trace", cookie.as_str())];
+    let (status, body) = get(&client, server.addr, "/hello.txt", &headers).await?;
+    assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert!(
+        body.contains("<h1>431 Request Header Fields Too Large</h1>"),
+        "{}",
+        body
+    );
+
+    // The vhost's error page replaces the built-in one
+    let headers = [
+        ("host", "custom.test"),
+        ("cookie", cookie.as_str()),
+        ("x-trace", cookie.as_str()),
+    ];
+    let (status, body) = get(&client, server.addr, "/hello.txt", &headers).await?;
+    assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    assert_eq!(body, "<h1>trim your cookies</h1>");
+
+    // Heads far past the limits are refused without being buffered
+    let huge = "h".repeat(64 * 1024);
+    if let Ok((status, _)) = get(&client, server.addr, "/hello.txt", &[("x-huge", &huge)]).await {
+        assert_eq!(status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+    let (status, _) = get(&client, server.addr, "/hello.txt", &[]).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<(StatusCode, String)> {
+    let mut request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,265 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary whose scripts take a second to answer
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nsleep 1\nprintf 'Content-Type: text/html\\r\\n\\r\\n<p>done</p>'\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+/// Timeouts of the default test server
+const API_TIMEOUTS: &str = "request_timeout = \"30s\"\napi_timeout = \"300ms\"";
+
+impl TestServer {
+    /// `timeouts` is added to the `[server]` table
+    async fn start(timeouts: &str) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for script in ["index.php", "report.php"] {
+            std::fs::write(docroot.path().join(script), "<?php sleep(1);")
+                .context("write php script")?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n{}\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.php\"]\n\n[[virtualhost.location]]\npath = \"/report.php\"\nrequest_timeout = \"300ms\"\n",
+            addr,
+            timeouts,
+            php_path.to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn api_times_out_while_php_keeps_running() -> Result<()> {
+    let server = TestServer::start(API_TIMEOUTS).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let started = Instant::now();
+    let php = tokio::spawn({
+        let client = client.clone();
+        let addr = server.addr;
+        async move { get(&client, addr, "/index.php").await }
+    });
+
+    // An API client that never finishes its body is cut off by api_timeout
+    let (status, api_elapsed) = stalled_api_request(server.addr).await?;
+    assert_eq!(status, 504);
+    assert!(
+        api_elapsed < Duration::from_millis(900),
+        "API request held for {:?}",
+        api_elapsed
+    );
+
+    // The PHP request started at the same time runs past that point
+    let (status, body) = php.await??;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("done"), "{}", body);
+    assert!(started.elapsed() >= Duration::from_millis(900));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn location_timeout_overrides_site_timeout() -> Result<()> {
+    let server = TestServer::start(API_TIMEOUTS).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let started = Instant::now();
+    let (status, _) = get(&client, server.addr, "/report.php").await?;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_millis(900));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn php_scripts_outlast_a_shorter_request_timeout() -> Result<()> {
+    let server = TestServer::start("request_timeout = \"300ms\"").await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Bounded by php.max_execution_time (30s), not the 300ms request timeout
+    let (status, body) = get(&client, server.addr, "/index.php").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("done"), "{}", body);
+
+    // An explicit location timeout still applies
+    let (status, _) = get(&client, server.addr, "/report.php").await?;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stalled_headers_are_disconnected_after_keepalive_timeout() -> Result<()> {
+    let server = TestServer::start("keepalive_timeout = \"500ms\"").await?;
+
+    for partial in [
+        &b"GET / HTTP/1.1\r\nHost: example.test\r\nUser-Agent: sl"[..],
+        b"",
+    ] {
+        let mut stream = TcpStream::connect(server.addr).await.context("connect")?;
+        let started = Instant::now();
+        stream
+            .write_all(partial)
+            .await
+            .context("write partial request")?;
+
+        let mut buf = [0u8; 1024];
+        loop {
+            let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .context("stalled connection was never closed")?;
+            if matches!(read, Ok(0) | Err(_)) {
+                break;
+            }
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(400) && elapsed < Duration::from_secs(3),
+            "closed after {:?}",
+            elapsed
+        );
+    }
+
+    Ok(())
+}
+
+/// Sends the headers of a POST and only part of its body, returning the status and how long it took
+async fn stalled_api_request(addr: SocketAddr) -> Result<(u16, Duration)> {
+    let mut stream = TcpStream::connect(addr).await.context("connect")?;
+    let started = Instant::now();
+    stream
+        .write_all(
+            b"POST /api/v1/cache/invalidate HTTP/1.1\r\nHost: example.test\r\nContent-Type: application/json\r\nContent-Length: 100\r\n\r\n{\"paths\":",
+        )
+        .await
+        .context("write partial request")?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .context("API request was never answered")?
+            .context("read response")?;
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..read]);
+    }
+    let elapsed = started.elapsed();
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .context("parse status line")?;
+    Ok((status, elapsed))
+}
+
+async fn get(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,152 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::LOCATION;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir(docroot.path().join("pages")).context("create pages dir")?;
+        std::fs::write(docroot.path().join("pages/intro.txt"), "intro page")
+            .context("write intro.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\n\n[php]\nenable = false\n\n\
+             [cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n\n\
+             [[virtualhost.rewrite]]\npattern = '^/docs/([a-z]+)\\.html$'\n\
+             replacement = \"/pages/$1.txt\"\n\n\
+             [[virtualhost.rewrite]]\npattern = \"^/old/(.*)$\"\nreplacement = \"/docs/$1\"\n\
+             flags = \"permanent\"\n\n\
+             [[virtualhost.rewrite]]\npattern = \"^/ping$\"\nreplacement = \"/pong\"\nflags = \"last\"\n\n\
+             [[virtualhost.rewrite]]\npattern = \"^/pong$\"\nreplacement = \"/ping\"\nflags = \"last\"\n",
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    async fn get(&self, path: &str) -> Result<(Response<()>, Bytes)> {
+        let client: Client<_, Empty<Bytes>> =
+            Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, path))
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = client.request(request).await.context("request failed")?;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+        Ok((Response::from_parts(parts, ()), body))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn capture_group_rewrite_serves_the_new_path() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (response, body) = server.get("/docs/intro.html").await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, "intro page");
+
+    // Paths no rule matches are resolved as they are
+    let (response, _) = server.get("/docs/intro.txt").await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn permanent_rewrite_redirects_the_client() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (response, _) = server.get("/old/intro.html?ref=mail").await?;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()[LOCATION], "/docs/intro.html?ref=mail");
+    Ok(())
+}
+
+#[tokio::test]
+async fn rewrite_loop_fails_with_500() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let (response, _) = server.get("/ping").await?;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
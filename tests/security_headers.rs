@@ -0,0 +1,204 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::Empty;
+use hyper::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_FRAME_OPTIONS,
+};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::ServerName;
+use tempfile::TempDir;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+struct TestServer {
+    addr: SocketAddr,
+    tls_addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve HTTP port")?;
+        let tls_addr = reserve_local_addr().context("reserve HTTPS port")?;
+        let fixtures = fixtures_dir();
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\nlisten_ssl = \"{tls_addr}\"\n\n\
+             [server.headers]\nx_frame_options = \"SAMEORIGIN\"\n\
+             referrer_policy = \"strict-origin-when-cross-origin\"\n\
+             content_security_policy = \"default-src 'self'\"\n\n\
+             [server.headers.hsts]\nmax_age = 63072000\nincludeSubDomains = true\n\
+             preload = true\n\n\
+             [ssl]\ncert = \"{cert}\"\nkey = \"{key}\"\n\n\
+             [php]\nenable = false\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"localhost\"\nroot = \"{root}\"\n",
+            cert = fixtures.join("localhost.crt").display(),
+            key = fixtures.join("localhost.key").display(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            tls_addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn assert_common_headers(headers: &hyper::HeaderMap) {
+    assert_eq!(headers[X_FRAME_OPTIONS], "SAMEORIGIN");
+    assert_eq!(headers[REFERRER_POLICY], "strict-origin-when-cross-origin");
+    assert_eq!(headers[CONTENT_SECURITY_POLICY], "default-src 'self'");
+}
+
+#[tokio::test]
+async fn tls_responses_carry_hsts_and_security_headers() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let tls = connect_tls(server.tls_addr, &[b"http/1.1"]).await?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(tls))
+        .await
+        .context("http1 handshake")?;
+    tokio::spawn(connection);
+
+    // Found and not found alike
+    for (path, status) in [
+        ("/hello.txt", StatusCode::OK),
+        ("/missing.txt", StatusCode::NOT_FOUND),
+    ] {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(path)
+            .header("Host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .context("build request")?;
+        let response = sender.send_request(request).await.context("request")?;
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            response.headers()[STRICT_TRANSPORT_SECURITY],
+            "max-age=63072000; includeSubDomains; preload"
+        );
+        assert_common_headers(response.headers());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn plain_http_responses_omit_hsts() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let client: Client<_, Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}/hello.txt", server.addr))
+        .header("Host", "localhost")
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(STRICT_TRANSPORT_SECURITY));
+    assert_common_headers(response.headers());
+    Ok(())
+}
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls")
+}
+
+/// TLS connection trusting the fixture certificate, offering `alpn`
+async fn connect_tls(addr: SocketAddr, alpn: &[&[u8]]) -> Result<TlsStream<TcpStream>> {
+    let pem = std::fs::read(fixtures_dir().join("localhost.crt")).context("read cert")?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots
+            .add(cert.context("parse cert")?)
+            .context("trust cert")?;
+    }
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .context("protocol versions")?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+
+    let tcp = TcpStream::connect(addr).await.context("connect")?;
+    let name = ServerName::try_from("localhost").context("server name")?;
+    TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .context("TLS handshake")
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,355 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary whose scripts are their own CGI output
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\ncat \"$SCRIPT_FILENAME\"\n";
+
+const REPORT: &str = "%PDF-1.7 quarterly numbers";
+
+struct TestServer {
+    addr: SocketAddr,
+    _dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let dir = tempfile::tempdir().context("create temp dir")?;
+        let docroot = dir.path().join("public");
+        let private = dir.path().join("private");
+        std::fs::create_dir_all(&docroot).context("create docroot")?;
+        std::fs::create_dir_all(&private).context("create private dir")?;
+        std::fs::write(private.join("report.pdf"), REPORT).context("write report")?;
+        std::fs::write(docroot.join("secret.txt"), "not private").context("write secret")?;
+        std::fs::write(docroot.join("Café menu.zip"), "PK").context("write archive")?;
+
+        let sendfile = format!("X-Sendfile: {}", private.join("report.pdf").display());
+        let scripts = [
+            (
+                "download.php",
+                format!("Content-Type: application/x-report\r\nContent-Disposition: attachment; filename=\"report.pdf\"\r\nCache-Control: private\r\n{}\r\n\r\nscript body", sendfile),
+            ),
+            (
+                "accel.php",
+                "X-Accel-Redirect: /report.pdf?v=1\r\n\r\n".to_string(),
+            ),
+            (
+                "escape.php",
+                "X-Accel-Redirect: /../public/secret.txt\r\n\r\n".to_string(),
+            ),
+            (
+                "absolute.php",
+                format!("X-Sendfile: {}\r\n\r\n", docroot.join("secret.txt").display()),
+            ),
+        ];
+        for (name, contents) in &scripts {
+            std::fs::write(docroot.join(name), contents)
+                .with_context(|| format!("write {}", name))?;
+        }
+
+        let php_path = dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = true\n\n\
+             [[virtualhost]]\ndomain = \"files.test\"\nroot = \"{}\"\nsendfile_root = \"{}\"\nforce_download = [\"pdf\", \"zip\"]\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n",
+            addr,
+            php_path.display(),
+            docroot.display(),
+            private.display(),
+            docroot.display()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _dir: dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn php_hands_off_files() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/download.php",
+        &[],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, REPORT);
+    assert_eq!(headers[CONTENT_TYPE], "application/x-report");
+    assert_eq!(
+        headers[CONTENT_DISPOSITION],
+        "attachment; filename=\"report.pdf\""
+    );
+    assert_eq!(headers["cache-control"], "private");
+    assert!(!headers.contains_key("x-sendfile"));
+
+    // Ranges and conditional requests work as for static files
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/download.php",
+        &[(RANGE.as_str(), "bytes=0-3")],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, "%PDF");
+    assert_eq!(
+        headers["content-range"],
+        format!("bytes 0-3/{}", REPORT.len())
+    );
+    let etag = headers[ETAG].to_str()?.to_string();
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/download.php",
+        &[(IF_NONE_MATCH.as_str(), &etag)],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+
+    // A form POST gets the file too
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::POST,
+        "files.test",
+        "/download.php",
+        &[],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, REPORT);
+
+    // X-Accel-Redirect URIs are relative to sendfile_root; the type comes from the file
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/accel.php",
+        &[],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, REPORT);
+    assert_eq!(headers[CONTENT_TYPE], "application/pdf");
+    assert!(!headers.contains_key("x-accel-redirect"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn force_download_defers_to_php() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Static files with a listed extension download under their own name
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/Caf%C3%A9%20menu.zip",
+        &[],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "PK");
+    assert_eq!(
+        headers[CONTENT_DISPOSITION],
+        "attachment; filename=\"Caf_ menu.zip\"; filename*=UTF-8''Caf%C3%A9%20menu.zip"
+    );
+
+    // So do handed-off files, unless the script named a disposition itself
+    let (_, headers, _) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/accel.php",
+        &[],
+    )
+    .await?;
+    assert_eq!(
+        headers[CONTENT_DISPOSITION],
+        "attachment; filename=\"report.pdf\""
+    );
+    let (_, headers, _) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "files.test",
+        "/download.php",
+        &[],
+    )
+    .await?;
+    assert_eq!(
+        headers.get_all(CONTENT_DISPOSITION).iter().count(),
+        1,
+        "{:?}",
+        headers
+    );
+
+    // Other vhosts are unaffected
+    let (status, headers, _) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "other.test",
+        "/Caf%C3%A9%20menu.zip",
+        &[],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!headers.contains_key(CONTENT_DISPOSITION));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn hand_off_is_confined_to_sendfile_root() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for script in ["/escape.php", "/absolute.php"] {
+        let (status, headers, body) =
+            request(&client, server.addr, Method::GET, "files.test", script, &[]).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", script);
+        assert!(!body.contains("not private"), "{}", body);
+        assert!(!headers.contains_key("x-sendfile"));
+    }
+
+    // Without a sendfile_root the header is refused, not forwarded
+    let (status, headers, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "other.test",
+        "/download.php",
+        &[],
+    )
+    .await?;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!body.contains("PDF"), "{}", body);
+    assert!(!headers.contains_key("x-sendfile"));
+
+    Ok(())
+}
+
+async fn request(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let request = builder
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
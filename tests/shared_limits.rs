@@ -0,0 +1,207 @@
+//! Needs a Redis server: set VELOSERVE_TEST_REDIS_URL (e.g. redis://127.0.0.1:6379/15)
+
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Full<Bytes>>;
+
+/// Mirrors the invalidation API limit in the handler
+const INVALIDATION_RATE_LIMIT: usize = 120;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(redis_url: &str) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.html"), "<h1>ok</h1>")
+            .context("write index.html")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\nredis_url = \"{}\"\n\n[limits]\ninvalidation = \"shared\"\nredis_timeout_ms = 200\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr,
+            redis_url,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn invalidation_limit_is_shared_between_instances() -> Result<()> {
+    let Ok(redis_url) = std::env::var("VELOSERVE_TEST_REDIS_URL") else {
+        eprintln!("VELOSERVE_TEST_REDIS_URL not set, skipping");
+        return Ok(());
+    };
+    flush_limit_keys(&redis_url)?;
+
+    let first = TestServer::start(&redis_url).await?;
+    let second = TestServer::start(&redis_url).await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Counters live in one-minute windows; don't straddle a boundary
+    let into_window = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs()
+        % 60;
+    if into_window > 45 {
+        sleep(Duration::from_secs(61 - into_window)).await;
+    }
+
+    // Spread the budget over both instances, as IP-hash balancing would
+    for n in 0..INVALIDATION_RATE_LIMIT {
+        let addr = if n % 2 == 0 { first.addr } else { second.addr };
+        let (status, body) = invalidate(&client, addr, n).await?;
+        assert_eq!(status, StatusCode::OK, "request {}: {}", n, body);
+    }
+
+    // Neither instance has seen more than half the limit locally
+    for addr in [first.addr, second.addr] {
+        let (status, body) = invalidate(&client, addr, INVALIDATION_RATE_LIMIT).await?;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+    }
+
+    let metrics = get_json(&client, first.addr, "/api/v1/metrics").await?;
+    assert_eq!(metrics["limits"]["invalidation"], "shared");
+    assert_eq!(metrics["limits"]["local_fallbacks"], 0);
+
+    Ok(())
+}
+
+/// Drop counters left over from earlier runs in the same window
+fn flush_limit_keys(redis_url: &str) -> Result<()> {
+    let client = redis::Client::open(redis_url).context("open redis")?;
+    let mut conn = client.get_connection().context("connect to redis")?;
+    let keys: Vec<String> = redis::cmd("KEYS")
+        .arg("veloserve:v1:limit:*")
+        .query(&mut conn)
+        .context("list limit keys")?;
+    if !keys.is_empty() {
+        redis::cmd("DEL")
+            .arg(keys)
+            .query::<()>(&mut conn)
+            .context("delete limit keys")?;
+    }
+    Ok(())
+}
+
+async fn invalidate(
+    client: &HttpClient,
+    addr: SocketAddr,
+    n: usize,
+) -> Result<(StatusCode, Value)> {
+    let payload = json!({
+        "scope": "url",
+        "domain": "example.test",
+        "paths": [format!("/page-{}.html", n)]
+    });
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/api/v1/cache/invalidate", addr))
+        .header("Host", "example.test")
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(payload.to_string())))
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+async fn get_json(client: &HttpClient, addr: SocketAddr, path: &str) -> Result<Value> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Full::new(Bytes::new()))
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok(serde_json::from_slice(&body)?)
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,151 @@
+#![cfg(unix)]
+
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+/// VeloServe started the way systemd does it: the listening socket is passed
+/// as fd 3 with `LISTEN_FDS`/`LISTEN_PID`/`LISTEN_FDNAMES` set.
+struct ActivatedServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    _occupied: TcpListener,
+    child: Child,
+}
+
+impl ActivatedServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("index.html"), "<h1>activated</h1>")
+            .context("write index.html")?;
+
+        let passed = TcpListener::bind("127.0.0.1:0").context("bind activated socket")?;
+        let addr = passed.local_addr().context("read activated addr")?;
+
+        // server.listen points at a port the test holds, so binding it would fail
+        let occupied = TcpListener::bind("127.0.0.1:0").context("bind occupied socket")?;
+        let configured = occupied.local_addr().context("read occupied addr")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            configured,
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let fd = passed.as_raw_fd();
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg("LISTEN_PID=$$; export LISTEN_PID; exec \"$0\" \"$@\"")
+            .arg(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .env("LISTEN_FDS", "1")
+            .env("LISTEN_FDNAMES", "http")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        // SAFETY: only async-signal-safe dup/dup2/close run between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                // dup() clears FD_CLOEXEC; dup2 then places the socket at fd 3
+                let inheritable = nix::unistd::dup(fd)?;
+                nix::unistd::dup2(inheritable, 3)?;
+                nix::unistd::close(inheritable)?;
+                Ok(())
+            });
+        }
+
+        let child = command.spawn().context("start veloserve child process")?;
+        drop(passed);
+
+        wait_until_ready(addr).await?;
+
+        Ok(Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            _occupied: occupied,
+            child,
+        })
+    }
+}
+
+impl Drop for ActivatedServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn serves_requests_on_socket_activated_listener() -> Result<()> {
+    let server = ActivatedServer::start().await?;
+
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}/", server.addr))
+        .header("Host", "example.test")
+        .body(http_body_util::Empty::<Bytes>::new())
+        .context("build request")?;
+
+    let response = client.request(request).await.context("request failed")?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read response body")?
+        .to_bytes();
+    assert_eq!(body.as_ref(), b"<h1>activated</h1>");
+
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
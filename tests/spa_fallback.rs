@@ -0,0 +1,231 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{HeaderMap, Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary that reports the script it ran
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\nran %s' \"$SCRIPT_NAME\"\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::create_dir_all(docroot.path().join("assets")).context("create assets")?;
+        for (path, contents) in [
+            ("index.html", "<div id=\"app\"></div>"),
+            ("index.php", "<?php // front controller"),
+            ("about.html", "<h1>about</h1>"),
+            ("assets/app.js", "render()"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{php}\"\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"dots.test\"\nroot = \"{root}\"\nspa_fallback = \"/index.html\"\nspa_fallback_extensions = true\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\nspa_fallback = \"/index.html\"\n",
+            addr = addr,
+            php = php_path.to_string_lossy(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn routes_get_the_app_shell() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for path in ["/dashboard/settings", "/users/42?tab=posts", "/assets/"] {
+        let (status, headers, body) =
+            request(&client, server.addr, Method::GET, "app.test", path).await?;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_eq!(body, "<div id=\"app\"></div>", "{}", path);
+        assert!(headers[CONTENT_TYPE].to_str()?.starts_with("text/html"));
+        assert_eq!(headers[CACHE_CONTROL], "no-cache");
+    }
+
+    // Real files aren't shadowed
+    let (status, _, body) =
+        request(&client, server.addr, Method::GET, "app.test", "/about.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<h1>about</h1>");
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "app.test",
+        "/assets/app.js",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "render()");
+
+    // Internal routes come first
+    let (status, headers, _) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "app.test",
+        "/api/v1/status",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers[CONTENT_TYPE]
+        .to_str()?
+        .starts_with("application/json"));
+    let (status, _, body) =
+        request(&client, server.addr, Method::GET, "app.test", "/health").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.contains("app"), "{}", body);
+
+    // Other methods still reach the front controller
+    let (status, _, body) =
+        request(&client, server.addr, Method::POST, "app.test", "/dashboard").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "ran /index.php");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn paths_with_extensions_skip_the_fallback() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // A missing asset carries on to try_files (the front controller here)
+    for path in ["/assets/missing.js", "/users/john.doe"] {
+        let (status, _, body) =
+            request(&client, server.addr, Method::GET, "app.test", path).await?;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_eq!(body, "ran /index.php", "{}", path);
+    }
+
+    // unless the vhost opts in
+    let (status, _, body) = request(
+        &client,
+        server.addr,
+        Method::GET,
+        "dots.test",
+        "/users/john.doe",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<div id=\"app\"></div>");
+
+    Ok(())
+}
+
+async fn request(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
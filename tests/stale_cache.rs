@@ -0,0 +1,273 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+/// Fake php binary: prints news.txt from the docroot, or a bare 304 when the
+/// request has a validator, as scripts that handle conditional GETs do
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nif [ -n \"$HTTP_IF_MODIFIED_SINCE$HTTP_IF_NONE_MATCH\" ]; then\n  printf 'Status: 304 Not Modified\\r\\n\\r\\n'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\n'\ncat \"$(dirname \"$SCRIPT_FILENAME\")/news.txt\"\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        Self::start_with("default_ttl = 1\nstale_grace = 30\n", "").await
+    }
+
+    /// Start with the given `[cache]` settings and vhost settings
+    async fn start_with(cache: &str, vhost: &str) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("news.html"), "<h1>old</h1>")
+            .context("write news.html")?;
+        std::fs::write(docroot.path().join("news.txt"), "<h1>old</h1>")
+            .context("write news.txt")?;
+        std::fs::write(
+            docroot.path().join("news.php"),
+            "<?php readfile('news.txt');",
+        )
+        .context("write news.php")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\n{}\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\n{}",
+            addr,
+            php_path.to_string_lossy(),
+            cache,
+            docroot.path().to_string_lossy(),
+            vhost
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        wait_until_ready(addr).await?;
+
+        Ok(Self {
+            addr,
+            docroot,
+            _config_dir: config_dir,
+            child,
+        })
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct Page {
+    status: StatusCode,
+    cache: Option<String>,
+    age: Option<u64>,
+    warning: Option<String>,
+    body: String,
+}
+
+#[tokio::test]
+async fn expired_page_is_served_stale_while_refreshed() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    let first = get_page(&client, server.addr, "/news.html", &[]).await?;
+    assert_eq!(first.status, StatusCode::OK);
+    assert_eq!(first.cache.as_deref(), Some("MISS"));
+    let hit = get_page(&client, server.addr, "/news.html", &[]).await?;
+    assert_eq!(hit.cache.as_deref(), Some("HIT"));
+    assert!(hit.warning.is_none());
+
+    std::fs::write(server.docroot.path().join("news.html"), "<h1>new</h1>")
+        .context("update news.html")?;
+    sleep(Duration::from_millis(2500)).await;
+
+    // Past its TTL the old copy still answers at once, marked as stale
+    let stale = get_page(&client, server.addr, "/news.html", &[]).await?;
+    assert_eq!(stale.status, StatusCode::OK);
+    assert_eq!(stale.cache.as_deref(), Some("STALE"));
+    assert_eq!(stale.body, "<h1>old</h1>");
+    assert!(stale.age.unwrap_or(0) >= 2, "age {:?}", stale.age);
+    assert_eq!(
+        stale.warning.as_deref(),
+        Some("110 - \"Response is Stale\"")
+    );
+
+    // The background refresh replaces it
+    let mut refreshed = None;
+    for _ in 0..40 {
+        let page = get_page(&client, server.addr, "/news.html", &[]).await?;
+        if page.cache.as_deref() == Some("HIT") {
+            refreshed = Some(page);
+            break;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    let refreshed = refreshed.context("stale page was not refreshed")?;
+    assert_eq!(refreshed.body, "<h1>new</h1>");
+    assert!(refreshed.warning.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn vhost_stale_window_overrides_the_global_one() -> Result<()> {
+    let server = TestServer::start_with(
+        "default_ttl = 1\n",
+        "\n[virtualhost.cache]\nttl = 1\nstale_while_revalidate = 30\n",
+    )
+    .await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    get_page(&client, server.addr, "/news.html", &[]).await?;
+    std::fs::write(server.docroot.path().join("news.html"), "<h1>new</h1>")
+        .context("update news.html")?;
+    sleep(Duration::from_millis(2500)).await;
+
+    let stale = get_page(&client, server.addr, "/news.html", &[]).await?;
+    assert_eq!(stale.cache.as_deref(), Some("STALE"));
+    assert_eq!(stale.body, "<h1>old</h1>");
+    Ok(())
+}
+
+#[tokio::test]
+async fn conditional_and_range_requests_refresh_the_whole_page() -> Result<()> {
+    let server = TestServer::start().await?;
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+
+    get_page(&client, server.addr, "/news.php", &[]).await?;
+    std::fs::write(server.docroot.path().join("news.txt"), "<h1>new</h1>")
+        .context("update news.txt")?;
+    sleep(Duration::from_millis(2500)).await;
+
+    // The stale hit that starts the refresh carries validators the script
+    // would answer with a 304, which can't be stored
+    get_page(
+        &client,
+        server.addr,
+        "/news.php",
+        &[
+            ("If-Modified-Since", "Fri, 01 Jan 2100 00:00:00 GMT"),
+            ("If-None-Match", "*"),
+            ("Range", "bytes=0-3"),
+        ],
+    )
+    .await?;
+
+    // Any later stale hit would start a refresh of its own, so the very next
+    // request has to find the page refreshed
+    sleep(Duration::from_millis(1000)).await;
+    let refreshed = get_page(&client, server.addr, "/news.php", &[]).await?;
+    assert_eq!(refreshed.cache.as_deref(), Some("HIT"));
+    assert_eq!(refreshed.body, "<h1>new</h1>");
+    Ok(())
+}
+
+async fn get_page(
+    client: &Client<HttpConnector, Full<Bytes>>,
+    addr: SocketAddr,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<Page> {
+    let mut request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", "example.test");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request
+        .body(Full::new(Bytes::new()))
+        .context("build page request")?;
+    let response = client
+        .request(request)
+        .await
+        .context("execute page request")?;
+    let status = response.status();
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let cache = header("x-cache");
+    let age = header("age").and_then(|v| v.parse().ok());
+    let warning = header("warning");
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read page body")?
+        .to_bytes();
+    Ok(Page {
+        status,
+        cache,
+        age,
+        warning,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build(connector);
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(Full::new(Bytes::new()))
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,178 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::symlink;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        // User content links elsewhere in the docroot, to a file and to a
+        // directory (links out of the docroot are never followed)
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        let store = docroot.path().join("store");
+        std::fs::create_dir_all(store.join("site"))?;
+        std::fs::write(store.join("secret.txt"), "stored")?;
+        std::fs::write(store.join("site/index.html"), "linked index")?;
+        std::fs::write(docroot.path().join("plain.txt"), "plain")?;
+        std::fs::create_dir(docroot.path().join("docs"))?;
+        std::fs::write(docroot.path().join("docs/page.txt"), "page")?;
+        symlink(store.join("secret.txt"), docroot.path().join("secret.txt"))?;
+        symlink(store.join("site"), docroot.path().join("site"))?;
+        symlink(
+            store.join("site/index.html"),
+            docroot.path().join("docs/index.html"),
+        )?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"never.test\"\nroot = \"{}\"\nindex = [\"index.html\"]\nfollow_symlinks = \"never\"\n\n[[virtualhost]]\ndomain = \"owner.test\"\nroot = \"{}\"\nindex = [\"index.html\"]\nfollow_symlinks = \"owner_match\"\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"index.html\"]\n",
+            addr, root, root, root
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn never_refuses_paths_through_symlinks() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, body) = get(&client, server.addr, "never.test", "/plain.txt").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "plain");
+
+    // A linked file, a file below a linked directory, and a linked index
+    for path in ["/secret.txt", "/site/index.html", "/site/", "/docs/"] {
+        let (status, _) = get(&client, server.addr, "never.test", path).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", path);
+    }
+
+    // Missing paths are still 404s
+    let (status, _) = get(&client, server.addr, "never.test", "/nope.txt").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn owner_match_and_always_follow_own_links() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // The test owns both the links and their targets
+    for host in ["owner.test", "other.test"] {
+        let (status, body) = get(&client, server.addr, host, "/secret.txt").await?;
+        assert_eq!(status, StatusCode::OK, "{}", host);
+        assert_eq!(body, "stored");
+
+        let (status, body) = get(&client, server.addr, host, "/docs/").await?;
+        assert_eq!(status, StatusCode::OK, "{}", host);
+        assert_eq!(body, "linked index");
+    }
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/health", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,231 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{HeaderMap, CONTENT_LENGTH};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary: `events.php` streams three events, other scripts print a
+/// page whose headers declare a wrong length
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\ncase \"$SCRIPT_FILENAME\" in\n  *events.php)\n    printf 'Content-Type: text/event-stream\\r\\nX-Accel-Buffering: no\\r\\n\\r\\n'\n    for n in 1 2 3; do\n      printf 'data: %s\\n\\n' \"$n\"\n    done\n    ;;\n  *)\n    printf 'Content-Type: text/html\\r\\nContent-Length: 3\\r\\n\\r\\n<p>rendered by php</p>'\n    ;;\nesac\n";
+
+const PAGE: &str = "<h1>static page with some length to it</h1>";
+
+struct TestServer {
+    addr: SocketAddr,
+    log: Arc<Mutex<Vec<String>>>,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("page.html"), PAGE).context("write page.html")?;
+        for script in ["events.php", "page.php"] {
+            std::fs::write(docroot.path().join(script), "<?php").context("write php script")?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = true\nl1_enabled = true\nl2_enabled = false\n\n[logging]\nformat = \"ACCESS $request_method $request_uri $status $body_bytes_sent\"\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nindex = [\"page.html\"]\n",
+            addr,
+            php_path.to_string_lossy(),
+            docroot.path().to_string_lossy()
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .env("RUST_LOG", "veloserve::access=info")
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let stdout = child.stdout.take().context("capture stdout")?;
+        let lines = log.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lock().unwrap().push(line);
+            }
+        });
+
+        let server = Self {
+            addr,
+            log,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+
+    /// Bytes on the `seen`-th access line for `method uri`, waiting for it to appear
+    async fn logged_bytes(&self, method: &str, uri: &str, seen: usize) -> Result<u64> {
+        let needle = format!("ACCESS {} {} ", method, uri);
+        for _ in 0..100 {
+            let matches: Vec<u64> = self
+                .log
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|line| line.split_once(&needle))
+                .filter_map(|(_, rest)| rest.split_whitespace().nth(1)?.parse().ok())
+                .collect();
+            if matches.len() > seen {
+                return Ok(matches[seen]);
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        Err(anyhow::anyhow!("no access log line for {}", needle))
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn lengths_match_what_the_client_receives() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // Static file, then the same page replayed from the cache
+    for (seen, cache) in ["MISS", "HIT"].into_iter().enumerate() {
+        let (status, headers, body) =
+            request(&client, server.addr, Method::GET, "/page.html").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-cache"], cache);
+        assert_eq!(body.len(), PAGE.len());
+        assert_eq!(declared_length(&headers), Some(PAGE.len()));
+        assert_eq!(
+            server.logged_bytes("GET", "/page.html", seen).await?,
+            PAGE.len() as u64
+        );
+    }
+
+    // HEAD declares the length but sends nothing
+    let (status, headers, body) = request(&client, server.addr, Method::HEAD, "/page.html").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_empty());
+    assert_eq!(declared_length(&headers), Some(PAGE.len()));
+    assert_eq!(server.logged_bytes("HEAD", "/page.html", 0).await?, 0);
+
+    // A wrong length from PHP never reaches the client
+    let (status, headers, body) = request(&client, server.addr, Method::GET, "/page.php").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"<p>rendered by php</p>");
+    assert_eq!(declared_length(&headers), Some(body.len()));
+    assert_eq!(
+        server.logged_bytes("GET", "/page.php", 0).await?,
+        body.len() as u64
+    );
+
+    // Streams go out chunked and log what was actually sent
+    let (status, headers, body) = request(&client, server.addr, Method::GET, "/events.php").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(declared_length(&headers), None);
+    assert_eq!(headers["transfer-encoding"], "chunked");
+    assert_eq!(body, b"data: 1\n\ndata: 2\n\ndata: 3\n\n");
+    assert_eq!(
+        server.logged_bytes("GET", "/events.php", 0).await?,
+        body.len() as u64
+    );
+
+    Ok(())
+}
+
+fn declared_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+async fn request(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, body.to_vec()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
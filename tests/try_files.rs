@@ -0,0 +1,194 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary that reports the script, PATH_INFO and query it ran with
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\nran %s path=%s query=%s' \"$SCRIPT_NAME\" \"$PATH_INFO\" \"$QUERY_STRING\"\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        for dir in ["laravel/public/css", "static"] {
+            std::fs::create_dir_all(docroot.path().join(dir))
+                .with_context(|| format!("create {}", dir))?;
+        }
+        for (path, contents) in [
+            ("laravel/public/index.php", "<?php // front controller"),
+            ("laravel/public/css/app.css", "body{}"),
+            ("static/index.php", "<?php // never run"),
+            ("static/index.html", "<h1>home</h1>"),
+            ("static/about.html", "<h1>about</h1>"),
+        ] {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{php}\"\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"laravel.test\"\nroot = \"{root}/laravel\"\ntry_files = [\"$uri\", \"$uri/\", \"/public/index.php$is_args$args\"]\n\n\
+             [[virtualhost]]\ndomain = \"static.test\"\nroot = \"{root}/static\"\ntry_files = [\"$uri\", \"$uri.html\", \"=404\"]\n\n\
+             [[virtualhost]]\ndomain = \"spa.test\"\nroot = \"{root}/static\"\ntry_files = [\"$uri\", \"/index.html\"]\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}/laravel\"\n",
+            addr = addr,
+            php = php_path.to_string_lossy(),
+            root = root,
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn nested_front_controller() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, body) = get(&client, server.addr, "laravel.test", "/users/42?tab=posts").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "ran /public/index.php path=/users/42 query=tab=posts");
+
+    // Existing files are still served directly
+    let (status, body) = get(&client, server.addr, "laravel.test", "/public/css/app.css").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "body{}");
+
+    // Without try_files only /index.php is a front controller
+    let (status, _) = get(&client, server.addr, "other.test", "/users/42").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn static_site_falls_back_to_404() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    let (status, body) = get(&client, server.addr, "static.test", "/about").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<h1>about</h1>");
+
+    // index.php exists but is never used as a front controller
+    let (status, body) = get(&client, server.addr, "static.test", "/missing").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.contains("404 Not Found"), "{}", body);
+    assert!(!body.contains("ran"), "{}", body);
+
+    // A URI fallback serves the static page for every missing path
+    let (status, body) = get(&client, server.addr, "spa.test", "/app/settings").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<h1>home</h1>");
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,129 @@
+#![cfg(unix)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use tempfile::TempDir;
+use tokio::net::UnixStream;
+use tokio::time::sleep;
+
+struct TestServer {
+    socket: PathBuf,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let socket = config_dir.path().join("veloserve.sock");
+        // Left behind by a server that didn't clean up
+        drop(std::os::unix::net::UnixListener::bind(&socket).context("bind stale socket")?);
+
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"unix:{socket}\"\nsocket_mode = \"0600\"\n\n[php]\nenable = false\n\n[cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            socket = socket.to_string_lossy(),
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            socket,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(&server.socket).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn serves_http_over_a_unix_socket() -> Result<()> {
+    let server = TestServer::start().await?;
+
+    let mode = std::fs::metadata(&server.socket)?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let (status, body) = get(&server.socket, "/health").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "OK");
+
+    let (status, body) = get(&server.socket, "/hello.txt").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello");
+
+    Ok(())
+}
+
+async fn get(socket: &Path, path: &str) -> Result<(StatusCode, String)> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .context("connect to socket")?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .context("handshake")?;
+    tokio::spawn(conn);
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(path)
+        .header("host", "localhost")
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = sender
+        .send_request(request)
+        .await
+        .context("request failed")?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+async fn wait_until_ready(socket: &Path) -> Result<()> {
+    for _ in 0..60 {
+        if let Ok((StatusCode::OK, _)) = get(socket, "/ready").await {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!(
+        "server did not become ready on {}",
+        socket.display()
+    ))
+}
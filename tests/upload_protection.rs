@@ -0,0 +1,310 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::HeaderMap;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Fake php binary that reports which script it ran
+const FAKE_PHP: &str = "#!/bin/sh\nif [ \"$1\" = \"-v\" ]; then\n  echo 'PHP 8.3.0 (cli)'\n  exit 0\nfi\nprintf 'Content-Type: text/html\\r\\n\\r\\nran %s' \"$SCRIPT_FILENAME\"\n";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start() -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        let uploads = docroot.path().join("wp-content/uploads/2024");
+        std::fs::create_dir_all(&uploads).context("create uploads dir")?;
+        std::fs::create_dir_all(docroot.path().join("wp-content/plugins"))
+            .context("create plugins dir")?;
+        std::fs::create_dir_all(docroot.path().join("files")).context("create files dir")?;
+
+        let files: &[(&str, &str)] = &[
+            ("index.php", "<?php // front controller"),
+            ("wp-content/plugins/ok.php", "<?php echo 'ok';"),
+            ("wp-content/uploads/shell.php", "<?php system($_GET['c']);"),
+            ("wp-content/uploads/index.php", "<?php system($_GET['c']);"),
+            (
+                "wp-content/uploads/2024/shell.php",
+                "<?php system($_GET['c']);",
+            ),
+            ("wp-content/uploads/evil.html", "<script>alert(1)</script>"),
+            ("wp-content/uploads/evil.svg", "<svg onload=\"alert(1)\"/>"),
+            ("wp-content/uploads/notes.txt", "just text"),
+            ("files/evil.svg", "<svg onload=\"alert(1)\"/>"),
+        ];
+        for (path, contents) in files {
+            std::fs::write(docroot.path().join(path), contents)
+                .with_context(|| format!("write {}", path))?;
+        }
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let php_path = config_dir.path().join("php");
+        std::fs::write(&php_path, FAKE_PHP).context("write fake php")?;
+        std::fs::set_permissions(&php_path, std::fs::Permissions::from_mode(0o755))
+            .context("make fake php executable")?;
+
+        let addr = reserve_local_addr().context("reserve local port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let root = docroot.path().to_string_lossy();
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{}\"\n\n[php]\nenable = true\nmode = \"cgi\"\nbinary_path = \"{}\"\n\n[cache]\nenable = false\n\n[[virtualhost]]\ndomain = \"plain.test\"\nroot = \"{}\"\n\n[[virtualhost.location]]\npath = \"/files\"\nuploads = \"plain\"\n\n[[virtualhost]]\ndomain = \"*\"\nroot = \"{}\"\nplatform = \"wordpress\"\n",
+            addr,
+            php_path.to_string_lossy(),
+            root,
+            root
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn php_never_runs_from_uploads() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    // PHP elsewhere runs, so a missing "ran" below means it was blocked
+    let (status, _, body) = get(
+        &client,
+        server.addr,
+        "wp.test",
+        "/wp-content/plugins/ok.php",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("ran") && body.ends_with("ok.php"), "{}", body);
+
+    let blocked = [
+        "/wp-content/uploads/shell.php",
+        "/wp-content/uploads/shell.php?c=id",
+        "/wp-content/uploads/shell.php/foo",
+        "/wp-content/uploads/shell.php/foo.jpg",
+        "/wp-content/uploads/2024/shell.php",
+        "/wp-content/uploads/2024/shell.php/",
+        "/wp-content/uploads/",
+        "/wp-content/uploads/shell%2ephp",
+        "/wp-content/%75ploads/shell.php",
+        "/wp-content/%75ploads/shell.php/foo",
+    ];
+    for path in blocked {
+        let (status, headers, body) = get(&client, server.addr, "wp.test", path).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}: {}", path, body);
+        assert_eq!(headers["x-content-type-options"], "nosniff", "{}", path);
+        assert!(!body.contains("ran"), "{} executed: {}", path, body);
+    }
+
+    // The bare directory is only redirected to the blocked URL above
+    let (status, headers, body) =
+        get(&client, server.addr, "wp.test", "/wp-content/uploads").await?;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers["location"], "http://wp.test/wp-content/uploads/");
+    assert!(!body.contains("ran"), "{}", body);
+
+    // Paths the URI parser would otherwise normalise away are sent raw
+    for path in [
+        "//wp-content//uploads/shell.php",
+        "/wp-content/uploads//shell.php/foo",
+        "/wp-content/./uploads/shell.php",
+    ] {
+        let (status, body) = raw_request(server.addr, "POST", path).await?;
+        assert!(!body.contains("shell.php"), "{} executed: {}", path, body);
+        assert_ne!(status, 200, "{}: {}", path, body);
+    }
+
+    // Missing scripts fall through to the root front controller, never to uploads
+    let (status, _, body) = get(
+        &client,
+        server.addr,
+        "wp.test",
+        "/wp-content/uploads/2024/../shell.php",
+    )
+    .await?;
+    assert!(
+        !body.contains("uploads/shell.php") && !body.contains("uploads/2024/shell.php"),
+        "{}: {}",
+        status,
+        body
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn risky_uploads_are_not_rendered() -> Result<()> {
+    let server = TestServer::start().await?;
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+    for path in [
+        "/wp-content/uploads/evil.html",
+        "/wp-content/uploads/evil.svg",
+    ] {
+        let (status, headers, _) = get(&client, server.addr, "wp.test", path).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-disposition"], "attachment", "{}", path);
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+    }
+
+    let (status, headers, body) = get(
+        &client,
+        server.addr,
+        "wp.test",
+        "/wp-content/uploads/notes.txt",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "just text");
+    assert!(!headers.contains_key("content-disposition"));
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+
+    // A configured `plain` location rewrites the type instead
+    let (status, headers, _) = get(&client, server.addr, "plain.test", "/files/evil.svg").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "text/plain; charset=utf-8");
+    assert!(!headers.contains_key("content-disposition"));
+
+    // Without the WordPress platform the preset doesn't apply
+    let (status, headers, body) = get(
+        &client,
+        server.addr,
+        "plain.test",
+        "/wp-content/uploads/shell.php",
+    )
+    .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.ends_with("uploads/shell.php"), "{}", body);
+    assert!(!headers.contains_key("content-disposition"));
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    host: &str,
+    path: &str,
+) -> Result<(StatusCode, HeaderMap, String)> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}{}", addr, path))
+        .header("Host", host)
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Sends a request line exactly as given and returns the status and body
+async fn raw_request(addr: SocketAddr, method: &str, path: &str) -> Result<(u16, String)> {
+    let mut stream = TcpStream::connect(addr).await.context("connect")?;
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: wp.test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .context("write request")?;
+
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .context("response timed out")?
+        .context("read response")?;
+    let response = String::from_utf8_lossy(&response).into_owned();
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .context("parse status line")?;
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+    Ok((status, body))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
@@ -0,0 +1,240 @@
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(upstream: SocketAddr) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("hello.txt"), "hello").context("write hello.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\n\n[php]\nenable = false\n\n\
+             [cache]\nenable = false\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n\n\
+             [[virtualhost.location]]\npath = \"/app*\"\nproxy_pass = \"http://{upstream}\"\n",
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A one-connection WebSocket echo server. It answers the handshake with the
+/// request head it saw (in `X-Seen`), then echoes one text frame unmasked.
+async fn spawn_echo_upstream() -> Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("accept");
+        let head = read_head(&mut stream).await.expect("read handshake");
+        let seen: Vec<&str> = head
+            .lines()
+            .filter(|line| {
+                let line = line.to_ascii_lowercase();
+                line.starts_with("sec-websocket-") || line.starts_with("upgrade:")
+            })
+            .collect();
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             X-Seen: {}\r\n\r\n",
+            seen.join(" | ")
+        );
+        stream
+            .write_all(response.as_bytes())
+            .await
+            .expect("write 101");
+
+        let payload = read_masked_frame(&mut stream).await.expect("read frame");
+        let mut frame = vec![0x81, payload.len() as u8];
+        frame.extend_from_slice(&payload);
+        stream.write_all(&frame).await.expect("write echo");
+    });
+    Ok(addr)
+}
+
+/// Read an HTTP head up to the blank line
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8(head)?)
+}
+
+/// The value of header `name` in an HTTP head
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Read one short client frame and unmask its payload
+async fn read_masked_frame(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut header = [0u8; 6];
+    stream.read_exact(&mut header).await?;
+    let len = (header[1] & 0x7f) as usize;
+    let mask = &header[2..6];
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(payload)
+}
+
+#[tokio::test]
+async fn websocket_frame_is_echoed_through_the_tunnel() -> Result<()> {
+    let upstream = spawn_echo_upstream().await?;
+    let server = TestServer::start(upstream).await?;
+
+    let mut client = TcpStream::connect(server.addr).await?;
+    let handshake = format!(
+        "GET /app/ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: {KEY}\r\n\
+         Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: pusher\r\n\r\n"
+    );
+    client.write_all(handshake.as_bytes()).await?;
+
+    let head = timeout(Duration::from_secs(5), read_head(&mut client)).await??;
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    assert_eq!(
+        header(&head, "sec-websocket-accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+    // The Sec-WebSocket-* headers reached the upstream unchanged
+    let seen: Vec<String> = header(&head, "x-seen")
+        .context("x-seen header")?
+        .split(" | ")
+        .map(|line| {
+            let (name, value) = line.split_once(": ").unwrap_or((line, ""));
+            format!("{}: {}", name.to_ascii_lowercase(), value)
+        })
+        .collect();
+    for expected in [
+        "upgrade: websocket".to_string(),
+        format!("sec-websocket-key: {KEY}"),
+        "sec-websocket-version: 13".to_string(),
+        "sec-websocket-protocol: pusher".to_string(),
+    ] {
+        assert!(seen.contains(&expected), "{:?}", seen);
+    }
+
+    // A masked text frame, "hello"
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![0x81, 0x80 | 5];
+    frame.extend_from_slice(&mask);
+    frame.extend(b"hello".iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    client.write_all(&frame).await?;
+
+    let mut echoed = [0u8; 7];
+    timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await??;
+    assert_eq!(&echoed, b"\x81\x05hello");
+
+    // The upstream closed; the tunnel follows
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut rest)).await??;
+    assert!(rest.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_outside_proxied_locations_is_not_tunnelled() -> Result<()> {
+    let upstream = spawn_echo_upstream().await?;
+    let server = TestServer::start(upstream).await?;
+
+    let mut client = TcpStream::connect(server.addr).await?;
+    let handshake = format!(
+        "GET /hello.txt HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: {KEY}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    client.write_all(handshake.as_bytes()).await?;
+
+    let head = timeout(Duration::from_secs(5), read_head(&mut client)).await??;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    Ok(())
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}
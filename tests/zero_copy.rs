@@ -0,0 +1,196 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{CONTENT_LENGTH, RANGE};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+type HttpClient = Client<HttpConnector, Empty<Bytes>>;
+
+/// Several times the placeholder and stream chunk sizes, and not a multiple
+/// of either
+const BIG_LEN: usize = 5 * 1024 * 1024 + 12345;
+
+struct TestServer {
+    addr: SocketAddr,
+    _docroot: TempDir,
+    _config_dir: TempDir,
+    child: Child,
+}
+
+impl TestServer {
+    async fn start(sendfile: bool, contents: &[u8]) -> Result<Self> {
+        let docroot = tempfile::tempdir().context("create temp docroot")?;
+        std::fs::write(docroot.path().join("big.bin"), contents).context("write big.bin")?;
+        std::fs::write(docroot.path().join("small.txt"), "small").context("write small.txt")?;
+
+        let config_dir = tempfile::tempdir().context("create temp config dir")?;
+        let addr = reserve_local_addr().context("reserve port")?;
+        let config_path = config_dir.path().join("veloserve.toml");
+        let config_toml = format!(
+            "[server]\nallow_root = true\nlisten = \"{addr}\"\nsendfile = {sendfile}\n\n[php]\nenable = false\n\n\
+             [cache]\nenable = false\n\n[static]\nstream_threshold = 65536\nstream_chunk_size = 16384\n\n\
+             [[virtualhost]]\ndomain = \"*\"\nroot = \"{root}\"\n",
+            root = docroot.path().to_string_lossy(),
+        );
+        std::fs::write(&config_path, config_toml).context("write config file")?;
+
+        let child = Command::new(env!("CARGO_BIN_EXE_veloserve"))
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("start veloserve child process")?;
+
+        let server = Self {
+            addr,
+            _docroot: docroot,
+            _config_dir: config_dir,
+            child,
+        };
+        wait_until_ready(addr).await?;
+        Ok(server)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[tokio::test]
+async fn sendfile_sends_the_same_bytes() -> Result<()> {
+    let contents: Vec<u8> = (0..BIG_LEN as u64)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+
+    for sendfile in [true, false] {
+        let server = TestServer::start(sendfile, &contents).await?;
+        // One pooled connection carries every request in turn
+        let client: HttpClient = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+
+        let started = Instant::now();
+        let (status, length, body) =
+            get(&client, server.addr, Method::GET, "/big.bin", None).await?;
+        let elapsed = started.elapsed();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(length, Some(BIG_LEN as u64));
+        assert_eq!(body.len(), BIG_LEN, "sendfile = {}", sendfile);
+        assert!(body == contents, "sendfile = {}: contents differ", sendfile);
+        eprintln!(
+            "sendfile = {}: {} bytes in {:?} ({:.0} MiB/s)",
+            sendfile,
+            body.len(),
+            elapsed,
+            body.len() as f64 / 1048576.0 / elapsed.as_secs_f64()
+        );
+
+        // Responses before and after a sendfile body are untouched
+        let (status, _, body) = get(&client, server.addr, Method::GET, "/small.txt", None).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"small");
+
+        let (first, last) = (1000, 3 * 1024 * 1024 + 7);
+        let range = format!("bytes={}-{}", first, last);
+        let (status, length, body) =
+            get(&client, server.addr, Method::GET, "/big.bin", Some(&range)).await?;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(length, Some((last - first + 1) as u64));
+        assert_eq!(body.len(), last - first + 1);
+        assert!(
+            body == contents[first..=last],
+            "sendfile = {}: range differs",
+            sendfile
+        );
+
+        let (status, length, body) =
+            get(&client, server.addr, Method::HEAD, "/big.bin", None).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(length, Some(BIG_LEN as u64));
+        assert!(body.is_empty());
+
+        let (_, _, body) = get(&client, server.addr, Method::GET, "/small.txt", None).await?;
+        assert_eq!(&body[..], b"small");
+    }
+
+    Ok(())
+}
+
+async fn get(
+    client: &HttpClient,
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    range: Option<&str>,
+) -> Result<(StatusCode, Option<u64>, Bytes)> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path));
+    if let Some(range) = range {
+        request = request.header(RANGE, range);
+    }
+    let request = request
+        .body(Empty::<Bytes>::new())
+        .context("build request")?;
+    let response = client.request(request).await.context("request failed")?;
+    let status = response.status();
+    let length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .context("read body")?
+        .to_bytes();
+    Ok((status, length, body))
+}
+
+async fn wait_until_ready(addr: SocketAddr) -> Result<()> {
+    let connector = HttpConnector::new();
+    let client: Client<_, http_body_util::Empty<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(connector);
+
+    let url = format!("http://{}/ready", addr);
+
+    for _ in 0..60 {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(&url)
+            .body(http_body_util::Empty::<Bytes>::new())
+            .context("build readiness request")?;
+
+        if let Ok(response) = client.request(request).await {
+            if response.status() == StatusCode::OK {
+                return Ok(());
+            }
+        }
+
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    Err(anyhow::anyhow!("server did not become ready on {}", addr))
+}
+
+fn reserve_local_addr() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").context("bind ephemeral socket")?;
+    let addr = listener.local_addr().context("read local addr")?;
+    drop(listener);
+    Ok(addr)
+}